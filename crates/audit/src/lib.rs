@@ -0,0 +1,389 @@
+//! CraftSQL Audit — a tamper-evident record of who moved which root, when.
+//!
+//! [`AuditedStore`] wraps a store so that every root pointer change made
+//! through it (`update_root`, `set_named_root`, and `remove_named_root`)
+//! appends an entry to an audit log: the actor making the change, the ref,
+//! the CID it pointed at before and after, and when. The log is a chain of
+//! pages, each naming the previous entry by CID, with the latest kept in
+//! the [`AUDIT_REF`] named root. Since a page's CID is the hash of its
+//! contents, no entry can be altered or dropped without changing the CID of
+//! every entry after it.
+//!
+//! [`verify`] checks a log offline, from its pages alone: that every entry
+//! hashes to the CID it is named by, that the chain is unbroken, and that
+//! each change starts where the previous change to the same ref left it, so
+//! that a root moved behind the wrapper's back shows up at the next audited
+//! change. A writer who can move [`AUDIT_REF`] can still put back an older
+//! head; check that a head you saw earlier is among the entries [`verify`]
+//! returns where that matters.
+//!
+//! Changes are serialized within one wrapper. Two wrappers appending to the
+//! same store at once can each extend the same head, losing one entry; the
+//! ref it changed then fails [`verify`]'s continuity check at its next
+//! change.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use craftsql_core::{Cid, CommitMeta, Page, PageStore, PageStoreError, Result, RootSignal};
+use serde::{Deserialize, Serialize};
+
+/// Named root pointing at the latest audit entry.
+pub const AUDIT_REF: &str = ".audit";
+
+/// Leading bytes of an audit entry page.
+const MAGIC: &[u8; 8] = b"csqlaud1";
+
+/// A root pointer change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    UpdateRoot,
+    SetNamedRoot(String),
+    RemoveNamedRoot(String),
+}
+
+impl Action {
+    /// The named root changed, or `None` for the current root.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Action::UpdateRoot => None,
+            Action::SetNamedRoot(name) | Action::RemoveNamedRoot(name) => Some(name),
+        }
+    }
+}
+
+/// One entry of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub cid: Cid,
+    /// Position in the log, from 0 for the first entry.
+    pub seq: u64,
+    /// The entry before this one, `None` for the first.
+    pub previous: Option<Cid>,
+    pub actor: String,
+    pub action: Action,
+    /// Where the ref pointed before, `None` if it wasn't set.
+    pub old: Option<Cid>,
+    /// Where it points after, `None` once removed.
+    pub new: Option<Cid>,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+}
+
+/// An audit entry as stored.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    previous: Option<Cid>,
+    seq: u64,
+    actor: String,
+    action: Action,
+    old: Option<Cid>,
+    new: Option<Cid>,
+    time: u64,
+}
+
+impl Record {
+    fn from_page(cid: Cid, page: &Page) -> Option<AuditEntry> {
+        let record: Record = bincode::deserialize(page.data.strip_prefix(MAGIC)?).ok()?;
+        Some(AuditEntry {
+            cid,
+            seq: record.seq,
+            previous: record.previous,
+            actor: record.actor,
+            action: record.action,
+            old: record.old,
+            new: record.new,
+            time: record.time,
+        })
+    }
+}
+
+/// Why an audit log failed [`verify`].
+#[derive(Debug, thiserror::Error)]
+pub enum AuditError {
+    #[error(transparent)]
+    Store(#[from] PageStoreError),
+    #[error("audit entry {0} is missing")]
+    Missing(Cid),
+    #[error("page {0} doesn't match its CID")]
+    Tampered(Cid),
+    #[error("page {0} is not an audit entry")]
+    NotAnEntry(Cid),
+    #[error("audit entry {cid} is number {seq}, expected {expected}")]
+    OutOfSequence { cid: Cid, seq: u64, expected: u64 },
+    #[error("{name} was moved from {} to {} without an audit entry", display_root(.expected), display_root(.found))]
+    Unrecorded { name: String, expected: Option<Cid>, found: Option<Cid> },
+}
+
+fn display_root(root: &Option<Cid>) -> String {
+    root.map_or_else(|| "none".to_string(), |cid| cid.to_string())
+}
+
+/// Display name of the ref an action changes.
+fn ref_name(action: &Action) -> String {
+    action.name().map_or_else(|| "the current root".to_string(), str::to_string)
+}
+
+/// Check the audit log ending at entry `head`, and return its entries,
+/// oldest first.
+pub fn verify(store: &dyn PageStore, head: Cid) -> std::result::Result<Vec<AuditEntry>, AuditError> {
+    let mut entries = Vec::new();
+    let mut next = Some(head);
+    while let Some(cid) = next {
+        let page = match store.get(&cid) {
+            Ok(page) => page,
+            Err(PageStoreError::NotFound(_)) => return Err(AuditError::Missing(cid)),
+            Err(e) => return Err(e.into()),
+        };
+        if Cid::from_bytes(&page.data) != cid {
+            return Err(AuditError::Tampered(cid));
+        }
+        let entry = Record::from_page(cid, &page).ok_or(AuditError::NotAnEntry(cid))?;
+        next = entry.previous;
+        entries.push(entry);
+    }
+    entries.reverse();
+
+    let mut last: HashMap<Option<String>, Option<Cid>> = HashMap::new();
+    for (expected, entry) in (0u64..).zip(&entries) {
+        if entry.seq != expected {
+            return Err(AuditError::OutOfSequence { cid: entry.cid, seq: entry.seq, expected });
+        }
+        let name = entry.action.name().map(str::to_string);
+        if let Some(&before) = last.get(&name) {
+            if before != entry.old {
+                return Err(AuditError::Unrecorded { name: ref_name(&entry.action), expected: before, found: entry.old });
+            }
+        }
+        last.insert(name, entry.new);
+    }
+    Ok(entries)
+}
+
+/// A store that records every root pointer change in an audit log.
+pub struct AuditedStore<S> {
+    inner: S,
+    actor: String,
+    /// Held from reading a ref's old value until its entry is appended.
+    lock: Mutex<()>,
+}
+
+impl<S: PageStore> AuditedStore<S> {
+    /// Record changes to `inner`'s roots as made by `actor`.
+    pub fn new(inner: S, actor: &str) -> Self {
+        Self { inner, actor: actor.to_string(), lock: Mutex::new(()) }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    /// The latest audit entry's CID, or `None` if nothing was recorded yet.
+    pub fn head(&self) -> Result<Option<Cid>> {
+        self.inner.get_named_root(AUDIT_REF)
+    }
+
+    /// The audit log, oldest first, checked with [`verify`].
+    pub fn entries(&self) -> std::result::Result<Vec<AuditEntry>, AuditError> {
+        match self.head()? {
+            Some(head) => verify(&self.inner, head),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Apply `change` to the ref `action` names, then record it. Nothing is
+    /// recorded if `change` fails or reports that it changed nothing.
+    fn audited(&self, action: Action, new: Option<Cid>, change: impl FnOnce() -> Result<bool>) -> Result<bool> {
+        if action.name().is_some_and(|name| name == AUDIT_REF) {
+            return Err(PageStoreError::Unauthorized(format!("{} is kept by the audit log", AUDIT_REF)));
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let old = match action.name() {
+            Some(name) => self.inner.get_named_root(name)?,
+            None => self.inner.current_root()?,
+        };
+        if !change()? {
+            return Ok(false);
+        }
+
+        let previous = self.head()?;
+        let seq = match previous {
+            Some(cid) => self.load(&cid)?.seq + 1,
+            None => 0,
+        };
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let record = Record { previous, seq, actor: self.actor.clone(), action, old, new, time };
+        let mut data = MAGIC.to_vec();
+        data.extend(bincode::serialize(&record).expect("audit entry serialization"));
+        let cid = self.inner.put(&Page { data })?;
+        self.inner.set_named_root(AUDIT_REF, cid)?;
+        Ok(true)
+    }
+
+    fn load(&self, cid: &Cid) -> Result<AuditEntry> {
+        let page = self.inner.get(cid)?;
+        Record::from_page(*cid, &page)
+            .ok_or_else(|| PageStoreError::Storage(format!("{} points at {}, which is not an audit entry", AUDIT_REF, cid)))
+    }
+}
+
+impl<S: PageStore> PageStore for AuditedStore<S> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        self.inner.get(cid)
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        self.inner.put(page)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        self.inner.put_many(pages)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        self.inner.has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        self.audited(Action::UpdateRoot, Some(new_root), || self.inner.update_root(new_root).map(|()| true))?;
+        Ok(())
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        self.audited(Action::UpdateRoot, Some(new_root), || self.inner.update_root_with_meta(new_root, meta).map(|()| true))?;
+        Ok(())
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        self.inner.current_root()
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        let action = Action::SetNamedRoot(name.to_string());
+        self.audited(action, Some(cid), || self.inner.set_named_root(name, cid).map(|()| true))?;
+        Ok(())
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        let action = Action::SetNamedRoot(name.to_string());
+        self.audited(action, Some(cid), || self.inner.set_named_root_with_meta(name, cid, meta).map(|()| true))?;
+        Ok(())
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.inner.get_named_root(name)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.audited(Action::RemoveNamedRoot(name.to_string()), None, || self.inner.remove_named_root(name))
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        self.inner.list_named_roots()
+    }
+
+    /// The inner store's pages, less the audit entries: only the latest is
+    /// reachable from a root, but they must all be kept.
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        let mut entries = HashSet::new();
+        let mut next = self.head()?;
+        while let Some(cid) = next {
+            match self.load(&cid) {
+                Ok(entry) => next = entry.previous,
+                Err(PageStoreError::NotFound(_)) => next = None,
+                Err(e) => return Err(e),
+            }
+            entries.insert(cid);
+        }
+        let mut pages = self.inner.list_pages()?;
+        pages.retain(|(cid, _)| !entries.contains(cid));
+        Ok(pages)
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.inner.delete_page(cid)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        self.inner.delete_many(cids)
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        self.inner.root_signal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+
+    fn put(store: &dyn PageStore, data: &[u8]) -> Cid {
+        store.put(&Page { data: data.to_vec() }).unwrap()
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let inner = MemPageStore::new();
+        let alice = AuditedStore::new(inner.clone(), "alice");
+        let (v1, v2) = (put(&alice, b"v1"), put(&alice, b"v2"));
+        alice.update_root(v1).unwrap();
+        alice.set_named_root("main", v1).unwrap();
+        AuditedStore::new(inner.clone(), "bob").set_named_root("main", v2).unwrap();
+        assert!(alice.remove_named_root("main").unwrap());
+        assert!(!alice.remove_named_root("main").unwrap());
+
+        let entries = alice.entries().unwrap();
+        let summary: Vec<_> = entries.iter().map(|e| (e.seq, e.actor.as_str(), e.action.clone(), e.old, e.new)).collect();
+        let main = || "main".to_string();
+        assert_eq!(summary, vec![
+            (0, "alice", Action::UpdateRoot, None, Some(v1)),
+            (1, "alice", Action::SetNamedRoot(main()), None, Some(v1)),
+            (2, "bob", Action::SetNamedRoot(main()), Some(v1), Some(v2)),
+            (3, "alice", Action::RemoveNamedRoot(main()), Some(v2), None),
+        ]);
+        assert_eq!(entries[3].previous, Some(entries[2].cid));
+        assert_eq!(alice.head().unwrap(), Some(entries[3].cid));
+
+        // The log can't be moved through the wrapper, and its entries are
+        // never offered for collection
+        assert!(matches!(alice.set_named_root(AUDIT_REF, v1), Err(PageStoreError::Unauthorized(_))));
+        assert!(matches!(alice.remove_named_root(AUDIT_REF), Err(PageStoreError::Unauthorized(_))));
+        let pages: Vec<Cid> = alice.list_pages().unwrap().into_iter().map(|(cid, _)| cid).collect();
+        assert!(pages.contains(&v1) && !pages.contains(&entries[0].cid));
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let inner = MemPageStore::new();
+        let store = AuditedStore::new(inner.clone(), "alice");
+        let (v1, v2) = (put(&store, b"v1"), put(&store, b"v2"));
+        store.set_named_root("main", v1).unwrap();
+        store.set_named_root("main", v2).unwrap();
+        let entries = store.entries().unwrap();
+
+        // A root moved behind the wrapper's back is caught at its next change
+        inner.set_named_root("main", v1).unwrap();
+        store.set_named_root("main", v2).unwrap();
+        assert!(matches!(store.entries(), Err(AuditError::Unrecorded { .. })));
+
+        // A rewritten entry no longer hashes to the CID the next one names
+        let forged = Record { previous: None, seq: 0, actor: "mallory".into(), action: Action::UpdateRoot, old: None, new: None, time: 0 };
+        let mut snapshot = inner.snapshot();
+        let mut data = MAGIC.to_vec();
+        data.extend(bincode::serialize(&forged).unwrap());
+        snapshot.pages.insert(entries[0].cid, data);
+        let forged = MemPageStore::from_snapshot(snapshot.clone());
+        assert!(matches!(verify(&forged, entries[1].cid), Err(AuditError::Tampered(cid)) if cid == entries[0].cid));
+        snapshot.pages.remove(&entries[0].cid);
+        let pruned = MemPageStore::from_snapshot(snapshot);
+        assert!(matches!(verify(&pruned, entries[1].cid), Err(AuditError::Missing(_))));
+        assert!(matches!(verify(&inner, v1), Err(AuditError::NotAnEntry(_))));
+        assert_eq!(verify(&inner, entries[1].cid).unwrap(), entries);
+    }
+}
@@ -0,0 +1,327 @@
+//! CraftSQL Backup — a database in one file.
+//!
+//! A `.craftsql` archive holds one or more roots, every commit in their
+//! history, and every page they reach, each page once: an artifact to put
+//! in object storage or attach to a ticket. [`restore`] loads it into any
+//! store.
+//!
+//! The file is [`MAGIC`] followed by a zlib stream, whose checksum catches
+//! corruption, holding a [`Manifest`] and then every page. Each is prefixed
+//! with its length as a little-endian `u32`; the manifest is bincode. Pages
+//! carry no CIDs, since restoring recomputes them from the data.
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use craftsql_core::{reachable, Cid, Page, PageStore, PageStoreError};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+/// Leading bytes of a backup archive.
+pub const MAGIC: &[u8; 8] = b"csqlbak1";
+
+/// Largest page or manifest a well-formed archive holds.
+const MAX_RECORD_LEN: u32 = 1 << 30;
+
+/// Backup errors.
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error(transparent)]
+    Store(#[from] PageStoreError),
+    #[error("backup file: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a CraftSQL backup")]
+    NotABackup,
+    #[error("corrupt backup: {0}")]
+    Corrupt(String),
+    #[error("no named root {0:?}")]
+    NoSuchRef(String),
+    #[error("{0} already points elsewhere; restore with force to overwrite it")]
+    RefExists(String),
+}
+
+pub type Result<T> = std::result::Result<T, BackupError>;
+
+/// Which roots to back up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Roots {
+    /// The current root and every named root except bookkeeping ones
+    /// (starting with `.`).
+    All,
+    /// These named roots only.
+    Named(Vec<String>),
+}
+
+/// What an archive holds, stored at its start.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// When the backup was taken, in seconds since the Unix epoch.
+    pub time: u64,
+    pub root: Option<Cid>,
+    pub refs: Vec<(String, Cid)>,
+    pub pages: u64,
+}
+
+/// What [`backup`] wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupStats {
+    pub manifest: Manifest,
+    /// Page data before compression.
+    pub page_bytes: u64,
+    /// Size of the archive.
+    pub archive_bytes: u64,
+}
+
+/// What [`restore`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreStats {
+    pub pages_restored: u64,
+    /// Pages the store already had.
+    pub pages_present: u64,
+    /// Named roots set, plus one if the current root was.
+    pub roots_set: usize,
+}
+
+/// Counts the bytes passing through to `inner`.
+struct Counting<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_record(out: &mut impl Write, data: &[u8]) -> Result<()> {
+    let len = u32::try_from(data.len()).ok().filter(|len| *len <= MAX_RECORD_LEN).ok_or_else(|| {
+        PageStoreError::Storage(format!("{} bytes is too large for a backup record", data.len()))
+    })?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+fn read_record(input: &mut impl Read) -> Result<Vec<u8>> {
+    let truncated = |e: io::Error| match e.kind() {
+        io::ErrorKind::UnexpectedEof => BackupError::Corrupt("truncated".into()),
+        _ => BackupError::Corrupt(e.to_string()),
+    };
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len).map_err(truncated)?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_RECORD_LEN {
+        return Err(BackupError::Corrupt(format!("record of {} bytes", len)));
+    }
+    let mut data = vec![0; len as usize];
+    input.read_exact(&mut data).map_err(truncated)?;
+    Ok(data)
+}
+
+/// Write `roots` of `store`, their history, and every page they reach to
+/// `out` as an archive.
+pub fn backup(store: &dyn PageStore, roots: &Roots, out: &mut dyn Write) -> Result<BackupStats> {
+    let (root, refs) = match roots {
+        Roots::All => {
+            let refs = store.list_named_roots()?.into_iter().filter(|(name, _)| !name.starts_with('.')).collect();
+            (store.current_root()?, refs)
+        }
+        Roots::Named(names) => {
+            let refs = names.iter()
+                .map(|name| {
+                    let cid = store.get_named_root(name)?.ok_or_else(|| BackupError::NoSuchRef(name.clone()))?;
+                    Ok((name.clone(), cid))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            (None, refs)
+        }
+    };
+    let starts: Vec<Cid> = root.into_iter().chain(refs.iter().map(|(_, cid)| *cid)).collect();
+    let mut pages: Vec<Cid> = reachable(store, &starts)?.into_iter().collect();
+    pages.sort_by_key(|cid| cid.0);
+
+    let manifest = Manifest {
+        time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        root,
+        refs,
+        pages: pages.len() as u64,
+    };
+
+    let mut counting = Counting { inner: out, count: 0 };
+    counting.write_all(MAGIC)?;
+    let mut encoder = ZlibEncoder::new(&mut counting, Compression::default());
+    write_record(&mut encoder, &bincode::serialize(&manifest).expect("manifest serialization"))?;
+    let mut page_bytes = 0;
+    for cid in &pages {
+        let page = store.get(cid)?;
+        page_bytes += page.data.len() as u64;
+        write_record(&mut encoder, &page.data)?;
+    }
+    encoder.finish()?;
+    counting.flush()?;
+    Ok(BackupStats { manifest, page_bytes, archive_bytes: counting.count })
+}
+
+/// Read just the manifest of the archive in `input`.
+pub fn read_manifest(input: &mut dyn Read) -> Result<Manifest> {
+    Ok(open(input)?.1)
+}
+
+fn open(input: &mut dyn Read) -> Result<(ZlibDecoder<&mut dyn Read>, Manifest)> {
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic).map_err(|_| BackupError::NotABackup)?;
+    if &magic != MAGIC {
+        return Err(BackupError::NotABackup);
+    }
+    let mut decoder = ZlibDecoder::new(input);
+    let manifest = bincode::deserialize(&read_record(&mut decoder)?)
+        .map_err(|e| BackupError::Corrupt(format!("manifest: {}", e)))?;
+    Ok((decoder, manifest))
+}
+
+/// Load the archive in `input` into `store`: its pages, then its named
+/// roots and current root.
+///
+/// Refuses, before writing anything, to move a root the store already has
+/// pointing elsewhere, unless `force` is set. Roots are only set once every
+/// page they reach is in the store.
+pub fn restore(input: &mut dyn Read, store: &dyn PageStore, force: bool) -> Result<RestoreStats> {
+    let (mut decoder, manifest) = open(input)?;
+    if !force {
+        for (name, cid) in &manifest.refs {
+            if store.get_named_root(name)?.is_some_and(|current| current != *cid) {
+                return Err(BackupError::RefExists(name.clone()));
+            }
+        }
+        if manifest.root.is_some() && store.current_root()?.is_some_and(|current| Some(current) != manifest.root) {
+            return Err(BackupError::RefExists("the current root".into()));
+        }
+    }
+
+    let mut stats = RestoreStats::default();
+    let mut restored = HashSet::new();
+    for _ in 0..manifest.pages {
+        let data = read_record(&mut decoder)?;
+        let cid = Cid::from_bytes(&data);
+        if store.has(&cid)? {
+            stats.pages_present += 1;
+        } else {
+            store.put(&Page { data })?;
+            stats.pages_restored += 1;
+        }
+        restored.insert(cid);
+    }
+    // Reading to the end checks the stream's checksum
+    if decoder.read(&mut [0u8; 1])? != 0 {
+        return Err(BackupError::Corrupt("data after the last page".into()));
+    }
+
+    let starts: Vec<Cid> = manifest.root.into_iter().chain(manifest.refs.iter().map(|(_, cid)| *cid)).collect();
+    for cid in reachable(store, &starts)?.difference(&restored) {
+        if !store.has(cid)? {
+            return Err(BackupError::Corrupt(format!("page {} is missing", cid)));
+        }
+    }
+    for (name, cid) in &manifest.refs {
+        store.set_named_root(name, *cid)?;
+        stats.roots_set += 1;
+    }
+    if let Some(root) = manifest.root {
+        store.update_root(root)?;
+        stats.roots_set += 1;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::Commit;
+    use craftsql_store_mem::MemPageStore;
+    use craftsql_testing::put_table;
+
+    fn source() -> (MemPageStore, Cid) {
+        let store = MemPageStore::new();
+        let v1 = put_table(&store, &[&[1; 4096], &[2; 4096]]);
+        let first = Commit::new(v1, vec![], "first").put(&store).unwrap();
+        let v2 = put_table(&store, &[&[1; 4096], &[3; 4096]]);
+        let second = Commit::new(v2, vec![first], "second").put(&store).unwrap();
+        store.set_named_root("main", second).unwrap();
+        store.set_named_root(".head", second).unwrap();
+        store.update_root(v2).unwrap();
+        store.put(&Page { data: b"unreachable".to_vec() }).unwrap();
+        (store, second)
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let (src, main) = source();
+        let mut archive = Vec::new();
+        let stats = backup(&src, &Roots::All, &mut archive).unwrap();
+        assert_eq!(stats.manifest.refs, vec![("main".to_string(), main)]);
+        // Two commits, two page tables, three distinct data pages
+        assert_eq!((stats.manifest.pages, stats.page_bytes > 3 * 4096), (7, true));
+        assert_eq!(stats.archive_bytes, archive.len() as u64);
+        assert!(archive.len() < 4096);
+        assert_eq!(read_manifest(&mut archive.as_slice()).unwrap(), stats.manifest);
+
+        let dst = MemPageStore::new();
+        let restored = restore(&mut archive.as_slice(), &dst, false).unwrap();
+        assert_eq!((restored.pages_restored, restored.roots_set), (7, 2));
+        assert_eq!(dst.get_named_root("main").unwrap(), Some(main));
+        assert_eq!(dst.current_root().unwrap(), src.current_root().unwrap());
+        assert_eq!(dst.get_named_root(".head").unwrap(), None);
+        let commit = Commit::load(&dst, &main).unwrap().unwrap();
+        assert!(Commit::load(&dst, &commit.parents[0]).unwrap().is_some());
+
+        // Again: everything is already there
+        let again = restore(&mut archive.as_slice(), &dst, false).unwrap();
+        assert_eq!((again.pages_restored, again.pages_present), (0, 7));
+    }
+
+    #[test]
+    fn test_named_roots_and_conflicts() {
+        let (src, main) = source();
+        let mut archive = Vec::new();
+        let stats = backup(&src, &Roots::Named(vec!["main".into()]), &mut archive).unwrap();
+        assert_eq!(stats.manifest.root, None);
+        assert!(matches!(
+            backup(&src, &Roots::Named(vec!["nope".into()]), &mut Vec::new()),
+            Err(BackupError::NoSuchRef(_))
+        ));
+
+        let dst = MemPageStore::new();
+        dst.set_named_root("main", Cid::from_bytes(b"elsewhere")).unwrap();
+        assert!(matches!(restore(&mut archive.as_slice(), &dst, false), Err(BackupError::RefExists(_))));
+        assert_eq!(dst.page_count(), 0);
+        restore(&mut archive.as_slice(), &dst, true).unwrap();
+        assert_eq!(dst.get_named_root("main").unwrap(), Some(main));
+    }
+
+    #[test]
+    fn test_damaged_archives() {
+        let (src, _) = source();
+        let mut archive = Vec::new();
+        backup(&src, &Roots::All, &mut archive).unwrap();
+
+        assert!(matches!(restore(&mut &b"not a backup"[..], &MemPageStore::new(), false), Err(BackupError::NotABackup)));
+        let truncated = &archive[..archive.len() - 20];
+        assert!(restore(&mut &truncated[..], &MemPageStore::new(), false).is_err());
+        let mut flipped = archive.clone();
+        let middle = flipped.len() / 2;
+        flipped[middle] ^= 0xff;
+        let dst = MemPageStore::new();
+        assert!(restore(&mut flipped.as_slice(), &dst, false).is_err());
+        assert_eq!(dst.current_root().unwrap(), None);
+    }
+}
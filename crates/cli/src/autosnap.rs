@@ -0,0 +1,158 @@
+//! Automatic snapshots: the current root, on a schedule, with the oldest
+//! pruned.
+//!
+//! Each round snapshots the current root as `auto-<time>` (see
+//! [`AUTO_PREFIX`]), named by the time in UTC so that names sort oldest
+//! first, unless the newest automatic snapshot already holds it: a database
+//! left alone doesn't crowd its older versions out. Then all but the newest
+//! `keep` automatic snapshots are deleted. Snapshots made by hand are never
+//! touched; `gc` reclaims the pages only pruned snapshots held.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use craftsql_core::{Cid, PageStore};
+
+use crate::history::{self, format_time};
+use crate::refs::{self, snapshot_ref};
+use crate::Result;
+
+/// Prefix of the names of automatic snapshots.
+pub const AUTO_PREFIX: &str = "auto-";
+
+/// What one round did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Round {
+    /// The snapshot made and the root it holds, unless nothing changed.
+    pub created: Option<(String, Cid)>,
+    /// Automatic snapshots deleted for being past the newest `keep`.
+    pub pruned: Vec<String>,
+}
+
+/// Parse an interval: seconds, or a number with an `s`, `m`, `h`, or `d`
+/// suffix, as in `90`, `10m`, or `1d`.
+pub fn parse_interval(s: &str) -> Option<Duration> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => return None,
+    };
+    let secs = number.parse::<u64>().ok()?.checked_mul(unit)?;
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// The name of an automatic snapshot made at `secs` since the Unix epoch.
+fn auto_name(secs: u64) -> String {
+    let time = format_time(secs);
+    format!("{}{}", AUTO_PREFIX, time.trim_end_matches(" UTC").replace(' ', "_").replace(':', ""))
+}
+
+/// Snapshot the current root unless the newest automatic snapshot already
+/// holds it, then delete all but the newest `keep` automatic snapshots.
+pub fn snapshot_once(store: &dyn PageStore, keep: Option<usize>) -> Result<Round> {
+    let mut round = Round::default();
+    history::catch_up(store)?;
+    // Oldest first: the names sort by time
+    let mut auto: Vec<(String, Cid)> = refs::snapshots(store)?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(AUTO_PREFIX))
+        .collect();
+    auto.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if let Some(root) = store.current_root()? {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let name = auto_name(now);
+        let newest = auto.last().map(|(_, cid)| *cid);
+        if newest != Some(root) && auto.last().is_none_or(|(last, _)| *last < name) {
+            store.set_named_root(&snapshot_ref(&name), root)?;
+            auto.push((name.clone(), root));
+            round.created = Some((name, root));
+        }
+    }
+
+    if let Some(keep) = keep {
+        let excess = auto.len().saturating_sub(keep);
+        for (name, _) in auto.drain(..excess) {
+            store.remove_named_root(&snapshot_ref(&name))?;
+            round.pruned.push(name);
+        }
+    }
+    Ok(round)
+}
+
+/// Run [`snapshot_once`] every `interval` until `stop` is set, reporting
+/// every round, failed ones included, to `on_round`.
+pub fn run(
+    store: &dyn PageStore,
+    interval: Duration,
+    keep: Option<usize>,
+    stop: &AtomicBool,
+    on_round: &mut dyn FnMut(Result<Round>),
+) {
+    while !stop.load(Ordering::Relaxed) {
+        on_round(snapshot_once(store, keep));
+        // Sleep in short steps so a stop is noticed promptly
+        let next = Instant::now() + interval;
+        while !stop.load(Ordering::Relaxed) {
+            let left = next.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            std::thread::sleep(left.min(Duration::from_millis(100)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::Page;
+    use craftsql_store_local::LocalPageStore;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_interval("10m"), Some(Duration::from_secs(600)));
+        assert_eq!(parse_interval("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_interval("1d"), Some(Duration::from_secs(86_400)));
+        for bad in ["", "0", "m", "10x", "1.5h", "-1m"] {
+            assert_eq!(parse_interval(bad), None, "{}", bad);
+        }
+        assert_eq!(auto_name(86_400 + 3723), "auto-1970-01-02_010203");
+    }
+
+    #[test]
+    fn test_snapshot_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        assert_eq!(snapshot_once(&store, Some(2)).unwrap(), Round::default());
+
+        let root = store.put(&Page { data: b"v1".to_vec() }).unwrap();
+        store.update_root(root).unwrap();
+        let round = snapshot_once(&store, Some(2)).unwrap();
+        let (name, cid) = round.created.unwrap();
+        assert!(name.starts_with(AUTO_PREFIX));
+        assert_eq!(cid, root);
+        // Unchanged: nothing new
+        assert_eq!(snapshot_once(&store, Some(2)).unwrap(), Round::default());
+
+        // Past `keep`, the oldest automatic snapshots go; others stay
+        store.set_named_root(&snapshot_ref("manual"), root).unwrap();
+        for (i, old) in ["auto-1970-01-01_000001", "auto-1970-01-01_000002"].iter().enumerate() {
+            let cid = store.put(&Page { data: vec![i as u8] }).unwrap();
+            store.set_named_root(&snapshot_ref(old), cid).unwrap();
+        }
+        let round = snapshot_once(&store, Some(1)).unwrap();
+        assert_eq!(round.created, None);
+        assert_eq!(round.pruned, ["auto-1970-01-01_000001", "auto-1970-01-01_000002"]);
+        let names: Vec<String> = refs::snapshots(&store).unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"manual".to_string()) && names.contains(&name));
+    }
+}
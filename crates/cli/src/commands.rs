@@ -0,0 +1,1153 @@
+//! Command implementations. Each writes its human-readable output to `out`.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use craftsql_backup::Roots;
+use craftsql_core::{
+    changed_objects, force_update, is_ancestor, load_page_table, page_map, page_table_root, remove_branch, set_protected,
+    Cid, Commit, PageOwner, PageStore, RootEvent,
+};
+use craftsql_namespace::{create_database, database_usage, delete_database, list_databases};
+use craftsql_replicator::{Metrics, Replicator, Round};
+use craftsql_sync::shallow::shallow_commits;
+use craftsql_sync::{
+    clone_shallow, clone_store, clone_tables, pull_shallow, pull_tables, BranchUpdate, Remote, TransferStats,
+};
+
+use crate::autosnap;
+use crate::fsck::{self, Problem};
+use crate::gc;
+use crate::history::{self, format_time};
+use crate::refs::{self, resolve, snapshot_ref, validate_name, HEAD_REF, MERGE_HEAD_REF, SNAPSHOT_PREFIX};
+use crate::remotes;
+use crate::{Error, Result};
+
+pub fn snapshot_create(store: &dyn PageStore, name: &str, from: Option<&str>, out: &mut dyn Write) -> Result<()> {
+    validate_name(name)?;
+    let snapshot = snapshot_ref(name);
+    if store.get_named_root(&snapshot)?.is_some() {
+        return Err(Error::Exists(format!("snapshot {}", name)));
+    }
+    history::catch_up(store)?;
+    let cid = match from {
+        Some(rev) => resolve(store, rev)?,
+        None => store.current_root()?.ok_or(Error::NoRoot)?,
+    };
+    store.set_named_root(&snapshot, cid)?;
+    writeln!(out, "created snapshot {} at {}", name, cid)?;
+    Ok(())
+}
+
+pub fn snapshot_list(store: &dyn PageStore, out: &mut dyn Write) -> Result<()> {
+    for (name, cid) in refs::snapshots(store)? {
+        writeln!(out, "{}  {}", cid, name)?;
+    }
+    Ok(())
+}
+
+pub fn snapshot_delete(store: &dyn PageStore, name: &str, out: &mut dyn Write) -> Result<()> {
+    validate_name(name)?;
+    if !store.remove_named_root(&snapshot_ref(name))? {
+        return Err(Error::UnknownRef(name.to_string()));
+    }
+    writeln!(out, "deleted snapshot {}", name)?;
+    Ok(())
+}
+
+/// List branches, marking those at the current root with `*`.
+pub fn branch_list(store: &dyn PageStore, out: &mut dyn Write) -> Result<()> {
+    let root = store.current_root()?;
+    for (name, cid) in refs::branches(store)? {
+        let marker = if Some(page_table_root(store, &cid)?) == root { '*' } else { ' ' };
+        writeln!(out, "{} {}  {}", marker, cid, name)?;
+    }
+    Ok(())
+}
+
+/// Create a branch at `start` (default: the current root), or move an
+/// existing one if `force` is set and it isn't protected against that.
+pub fn branch_create(
+    store: &dyn PageStore,
+    name: &str,
+    start: Option<&str>,
+    force: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    validate_name(name)?;
+    if !force && store.get_named_root(name)?.is_some() {
+        return Err(Error::Exists(format!("branch {}", name)));
+    }
+    history::catch_up(store)?;
+    let cid = match start {
+        Some(rev) => resolve(store, rev)?,
+        None => store.current_root()?.ok_or(Error::NoRoot)?,
+    };
+    force_update(store, name, cid)?;
+    writeln!(out, "branch {} at {}", name, cid)?;
+    Ok(())
+}
+
+pub fn branch_delete(store: &dyn PageStore, name: &str, out: &mut dyn Write) -> Result<()> {
+    validate_name(name)?;
+    if !remove_branch(store, name)? {
+        return Err(Error::UnknownRef(name.to_string()));
+    }
+    writeln!(out, "deleted branch {}", name)?;
+    Ok(())
+}
+
+/// Protect a branch, so that it only ever moves forward and can't be
+/// deleted, or stop protecting it.
+pub fn branch_protect(store: &dyn PageStore, name: &str, protect: bool, out: &mut dyn Write) -> Result<()> {
+    validate_name(name)?;
+    set_protected(store, name, protect)?;
+    writeln!(out, "{} branch {}", if protect { "protected" } else { "unprotected" }, name)?;
+    Ok(())
+}
+
+/// Point the current root at a branch, snapshot, or CID.
+pub fn checkout(store: &dyn PageStore, rev: &str, out: &mut dyn Write) -> Result<()> {
+    let target = resolve(store, rev)?;
+    let commit = Commit::load(store, &target)?;
+    let cid = commit.as_ref().map_or(target, |commit| commit.root);
+    history::catch_up(store)?;
+    store.update_root(cid)?;
+    match commit {
+        Some(_) => store.set_named_root(HEAD_REF, target)?,
+        None => {
+            store.remove_named_root(HEAD_REF)?;
+        }
+    }
+    store.remove_named_root(MERGE_HEAD_REF)?;
+    history::record(store, cid, &format!("checkout {}", rev))?;
+    writeln!(out, "checked out {} at {}", rev, cid)?;
+    Ok(())
+}
+
+/// Commit the current root onto `branch`. The parents are the checked-out
+/// commit (or the branch's, if none is) and, after a merge, the commit
+/// merged in. A branch can only move forward: its commit must be an
+/// ancestor of the checked-out one.
+pub fn commit(store: &dyn PageStore, branch: &str, message: &str, author: &str, out: &mut dyn Write) -> Result<()> {
+    validate_name(branch)?;
+    history::catch_up(store)?;
+    let root = store.current_root()?.ok_or(Error::NoRoot)?;
+    let head = store.get_named_root(HEAD_REF)?;
+    let tip = match store.get_named_root(branch)? {
+        Some(tip) if Commit::load(store, &tip)?.is_some() => Some(tip),
+        _ => None,
+    };
+    if let (Some(head), Some(tip)) = (head, tip) {
+        if !is_ancestor(store, tip, head)? {
+            return Err(Error::Diverged(branch.to_string()));
+        }
+    }
+    let mut parents: Vec<Cid> = head.or(tip).into_iter().collect();
+    if let Some(merged) = store.get_named_root(MERGE_HEAD_REF)? {
+        parents.push(merged);
+    }
+
+    let cid = Commit::new(root, parents, message).with_author(author).put(store)?;
+    store.set_named_root(branch, cid)?;
+    store.set_named_root(HEAD_REF, cid)?;
+    store.remove_named_root(MERGE_HEAD_REF)?;
+    writeln!(out, "committed {} to {}", cid, branch)?;
+    Ok(())
+}
+
+/// Print the full current root CID.
+pub fn root(store: &dyn PageStore, out: &mut dyn Write) -> Result<()> {
+    let cid = store.current_root()?.ok_or(Error::NoRoot)?;
+    writeln!(out, "{}", cid.to_hex())?;
+    Ok(())
+}
+
+/// Print the history of root changes, newest first.
+pub fn reflog(store: &dyn PageStore, max_count: Option<usize>, out: &mut dyn Write) -> Result<()> {
+    let entries = history::entries(store, max_count)?;
+    if let Some(root) = store.current_root()? {
+        if entries.first().map(|entry| entry.root) != Some(root) {
+            writeln!(out, "{}  (current root, not yet recorded)", root)?;
+        }
+    }
+    for entry in entries {
+        writeln!(out, "{}  {}  {}", entry.root, format_time(entry.time), entry.action)?;
+    }
+    Ok(())
+}
+
+/// Print the commits behind `rev` (default: the checked-out commit), newest
+/// first: at most `max_count` of them, and only those made at or after
+/// `since` (see [`history::parse_time`]).
+pub fn log(
+    store: &dyn PageStore,
+    rev: Option<&str>,
+    max_count: Option<usize>,
+    since: Option<&str>,
+    oneline: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let from = match rev {
+        Some(rev) => resolve(store, rev)?,
+        None => store.get_named_root(HEAD_REF)?.ok_or(Error::NoHead)?,
+    };
+    let since = since
+        .map(|time| history::parse_time(time).ok_or_else(|| Error::InvalidTime(time.to_string())))
+        .transpose()?;
+    let entries = craftsql_core::log(store, from)
+        .take(max_count.unwrap_or(usize::MAX))
+        .take_while(|entry| entry.as_ref().map_or(true, |entry| since.is_none_or(|since| entry.time >= since)));
+    for (i, entry) in entries.enumerate() {
+        let entry = entry?;
+        if oneline {
+            writeln!(out, "{}  {}", entry.cid, entry.message.lines().next().unwrap_or(""))?;
+            continue;
+        }
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "commit {}", entry.cid.to_hex())?;
+        if entry.parents.len() > 1 {
+            let parents: Vec<String> = entry.parents.iter().map(|parent| parent.to_string()).collect();
+            writeln!(out, "Merge:  {}", parents.join(" "))?;
+        }
+        if !entry.author.is_empty() {
+            writeln!(out, "Author: {}", entry.author)?;
+        }
+        writeln!(out, "Date:   {}", format_time(entry.time))?;
+        if let Some(pages) = entry.pages_changed {
+            writeln!(out, "Pages:  {} changed", pages)?;
+        }
+        if !entry.message.is_empty() {
+            writeln!(out)?;
+            for line in entry.message.lines() {
+                writeln!(out, "    {}", line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the row changes from `old` to `new`, as text or JSON.
+#[cfg(feature = "sql")]
+pub fn diff(store: Box<dyn PageStore>, old: &str, new: &str, json: bool, out: &mut dyn Write) -> Result<()> {
+    let (old, new) = (resolve(store.as_ref(), old)?, resolve(store.as_ref(), new)?);
+    let (old, new) = (page_table_root(store.as_ref(), &old)?, page_table_root(store.as_ref(), &new)?);
+    let diff = craftsql_diff::diff_roots(store.into(), old, new)?;
+    if json {
+        writeln!(out, "{}", diff.to_json())?;
+    } else {
+        diff.write_text(out)?;
+    }
+    Ok(())
+}
+
+/// Merge the changes `rev` made since `base` into the current root. Without
+/// a `base`, the merge base of the checked-out commit and `rev` is used.
+#[cfg(feature = "sql")]
+pub fn merge(
+    store: Box<dyn PageStore>,
+    rev: &str,
+    base: Option<&str>,
+    policy: craftsql_diff::ConflictPolicy,
+    out: &mut dyn Write,
+) -> Result<()> {
+    use craftsql_diff::{merge_roots, ConflictPolicy, DiffError};
+
+    let target = resolve(store.as_ref(), rev)?;
+    let theirs = page_table_root(store.as_ref(), &target)?;
+    let base = match base {
+        Some(base) => page_table_root(store.as_ref(), &resolve(store.as_ref(), base)?)?,
+        None => {
+            let head = store.get_named_root(HEAD_REF)?.ok_or_else(|| Error::NoMergeBase(rev.to_string()))?;
+            let base = craftsql_core::merge_base(store.as_ref(), head, target)?
+                .ok_or_else(|| Error::NoMergeBase(rev.to_string()))?;
+            page_table_root(store.as_ref(), &base)?
+        }
+    };
+    history::catch_up(store.as_ref())?;
+    let ours = store.current_root()?.ok_or(Error::NoRoot)?;
+    let store: std::sync::Arc<dyn PageStore> = store.into();
+    let (merged, report) = match merge_roots(store.clone(), base, ours, theirs, policy) {
+        Err(DiffError::Conflicts(conflicts)) => {
+            for conflict in &conflicts {
+                writeln!(out, "conflict: {}", conflict)?;
+            }
+            return Err(DiffError::Conflicts(conflicts).into());
+        }
+        result => result?,
+    };
+    let kept = if policy == ConflictPolicy::Theirs { "took theirs" } else { "kept ours" };
+    for conflict in &report.conflicts {
+        writeln!(out, "conflict ({}): {}", kept, conflict)?;
+    }
+    store.update_root(merged)?;
+    if Commit::load(store.as_ref(), &target)?.is_some() {
+        store.set_named_root(MERGE_HEAD_REF, target)?;
+    }
+    history::record(store.as_ref(), merged, &format!("merge {}", rev))?;
+    writeln!(
+        out,
+        "merged {} at {}: {} rows and {} tables taken from it",
+        rev, merged, report.rows_applied, report.tables_applied
+    )?;
+    Ok(())
+}
+
+/// Replay the commits on `branch` that `onto` lacks on top of it, and move
+/// the branch to the result. If `branch` is checked out, the current root
+/// moves with it; uncommitted changes must be committed first.
+#[cfg(feature = "sql")]
+pub fn rebase(
+    store: Box<dyn PageStore>,
+    branch: &str,
+    onto: &str,
+    policy: craftsql_diff::ConflictPolicy,
+    out: &mut dyn Write,
+) -> Result<()> {
+    use craftsql_diff::{ConflictPolicy, DiffError};
+
+    validate_name(branch)?;
+    let tip = store.get_named_root(branch)?.ok_or_else(|| Error::UnknownRef(branch.to_string()))?;
+    let target = resolve(store.as_ref(), onto)?;
+    history::catch_up(store.as_ref())?;
+    let checked_out = store.get_named_root(HEAD_REF)? == Some(tip);
+    if checked_out && store.current_root()? != Some(page_table_root(store.as_ref(), &tip)?) {
+        return Err(Error::Uncommitted);
+    }
+
+    let store: std::sync::Arc<dyn PageStore> = store.into();
+    let report = match craftsql_diff::rebase(store.clone(), tip, target, policy) {
+        Err(DiffError::RebaseConflicts { commit, conflicts }) => {
+            for conflict in &conflicts {
+                writeln!(out, "conflict in {}: {}", commit, conflict)?;
+            }
+            return Err(DiffError::RebaseConflicts { commit, conflicts }.into());
+        }
+        result => result?,
+    };
+    let kept = if policy == ConflictPolicy::Theirs { "took theirs" } else { "kept ours" };
+    for conflict in &report.conflicts {
+        writeln!(out, "conflict ({}): {}", kept, conflict)?;
+    }
+    for (old, new) in &report.replayed {
+        writeln!(out, "replayed {} as {}", old, new)?;
+    }
+    for old in &report.skipped {
+        writeln!(out, "skipped {}: its changes are already in {}", old, onto)?;
+    }
+    force_update(store.as_ref(), branch, report.head)?;
+    if checked_out {
+        let root = page_table_root(store.as_ref(), &report.head)?;
+        store.update_root(root)?;
+        store.set_named_root(HEAD_REF, report.head)?;
+        history::record(store.as_ref(), root, &format!("rebase {} onto {}", branch, onto))?;
+    }
+    writeln!(out, "rebased {} onto {} at {}", branch, onto, report.head)?;
+    Ok(())
+}
+
+/// Serve the branches and snapshots as read-only `.sqlite` files under
+/// `mountpoint` until it is unmounted.
+#[cfg(all(unix, feature = "fuse"))]
+pub fn mount(store: Box<dyn PageStore>, mountpoint: &Path, out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "serving {}; unmount with `fusermount3 -u {}`", mountpoint.display(), mountpoint.display())?;
+    out.flush()?;
+    craftsql_fuse::mount(std::sync::Arc::<dyn PageStore>::from(store), mountpoint).map_err(Error::Mount)
+}
+
+/// Progress callback that overwrites one status line on `progress`.
+fn progress_line(progress: &mut dyn Write) -> impl FnMut(&TransferStats) + '_ {
+    let mut last_report = 0;
+    move |stats| {
+        let pages = stats.pages_copied + stats.pages_skipped;
+        if pages >= last_report + 100 || stats.roots_done == stats.roots_total {
+            last_report = pages;
+            let _ = write!(
+                progress,
+                "\rroots {}/{}, pages copied {}, already present {}",
+                stats.roots_done, stats.roots_total, stats.pages_copied, stats.pages_skipped
+            );
+        }
+    }
+}
+
+/// Copy `src` into `dst`, reporting progress on `progress` as it goes.
+/// Copy `src` into `dst`: everything, only `tables` of each database if any
+/// are given (see [`craftsql_sync::partial`]), or if `shallow`, only the
+/// latest version of each branch (see [`craftsql_sync::shallow`]).
+pub fn clone(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    tables: &[String],
+    shallow: bool,
+    out: &mut dyn Write,
+    progress: &mut dyn Write,
+) -> Result<()> {
+    let stats = if shallow {
+        clone_shallow(src, dst, &mut progress_line(progress))?
+    } else if tables.is_empty() {
+        clone_store(src, dst, &mut progress_line(progress))?
+    } else {
+        clone_tables(src, dst, tables, &mut progress_line(progress))?
+    };
+    if stats.roots_total > 0 {
+        writeln!(progress)?;
+    }
+    writeln!(
+        out,
+        "cloned {} roots: {} pages ({} bytes) copied, {} already present",
+        stats.roots_total, stats.pages_copied, stats.bytes_copied, stats.pages_skipped
+    )?;
+    Ok(())
+}
+
+pub fn push(
+    store: &dyn PageStore,
+    remote: Remote<'_>,
+    branch: &str,
+    force: bool,
+    out: &mut dyn Write,
+    progress: &mut dyn Write,
+) -> Result<()> {
+    let update = craftsql_sync::push(store, remote, branch, force, &mut progress_line(progress))?;
+    report_update(&update, "remote", out, progress)
+}
+
+/// How much of the remote's branch [`pull`] fetches.
+#[derive(Debug, Clone, Copy)]
+pub enum PullMode<'a> {
+    /// All of it, with its history.
+    Full,
+    /// Only these tables and their indexes (see [`craftsql_sync::partial`]).
+    Tables(&'a [String]),
+    /// Only its latest version (see [`craftsql_sync::shallow`]).
+    Shallow,
+}
+
+pub fn pull(
+    store: &dyn PageStore,
+    remote: Remote<'_>,
+    branch: &str,
+    force: bool,
+    mode: PullMode<'_>,
+    out: &mut dyn Write,
+    progress: &mut dyn Write,
+) -> Result<()> {
+    // Partial pulls bring over less than the estimate counts
+    if let (PullMode::Full | PullMode::Shallow, Some(theirs)) = (mode, remote.store.get_named_root(branch)?) {
+        let ours = store.get_named_root(branch)?;
+        let cost = craftsql_sync::estimate_sync_cost(remote.store, store, theirs, ours)?;
+        writeln!(progress, "pulling about {} pages ({} bytes)", cost.pages_to_transfer, cost.bytes)?;
+    }
+    let mut on_progress = progress_line(progress);
+    let update = match mode {
+        PullMode::Full => craftsql_sync::pull(store, remote, branch, force, &mut on_progress)?,
+        PullMode::Tables(tables) => pull_tables(store, remote, branch, tables, force, &mut on_progress)?,
+        PullMode::Shallow => pull_shallow(store, remote, branch, force, &mut on_progress)?,
+    };
+    drop(on_progress);
+    report_update(&update, "local", out, progress)
+}
+
+/// Fetch from `src` the history that shallow clones and pulls left out.
+pub fn unshallow(store: &dyn PageStore, src: &dyn PageStore, out: &mut dyn Write, progress: &mut dyn Write) -> Result<()> {
+    let commits = shallow_commits(store)?.len();
+    let stats = craftsql_sync::unshallow(store, src, &mut progress_line(progress))?;
+    if stats.roots_total > 0 {
+        writeln!(progress)?;
+    }
+    writeln!(
+        out,
+        "fetched the history of {} shallow commits: {} pages ({} bytes) copied, {} already present",
+        commits, stats.pages_copied, stats.bytes_copied, stats.pages_skipped
+    )?;
+    Ok(())
+}
+
+/// Fetch `branches` of `remote` (default: all of them) and any pages we
+/// lack, leaving our own branches alone; each is then `<remote>/<branch>`.
+pub fn fetch(
+    store: &dyn PageStore,
+    remote: Remote<'_>,
+    branches: &[String],
+    out: &mut dyn Write,
+    progress: &mut dyn Write,
+) -> Result<()> {
+    let branches = if branches.is_empty() {
+        refs::branches(remote.store)?.into_iter().map(|(name, _)| name).collect()
+    } else {
+        branches.to_vec()
+    };
+    for branch in &branches {
+        let mut update = craftsql_sync::fetch(store, remote, branch, &mut progress_line(progress))?;
+        update.branch = format!("{}/{}", remote.name, branch);
+        report_update(&update, "remote branch", out, progress)?;
+    }
+    Ok(())
+}
+
+/// List where each remote's branches were last seen.
+pub fn branch_list_remotes(store: &dyn PageStore, out: &mut dyn Write) -> Result<()> {
+    for (remote, branch, cid) in craftsql_sync::remote_branches(store)? {
+        writeln!(out, "  {}  {}/{}", cid, remote, branch)?;
+    }
+    Ok(())
+}
+
+pub fn remote_add(store: &dyn PageStore, name: &str, location: &str, out: &mut dyn Write) -> Result<()> {
+    remotes::add(store, name, location)?;
+    writeln!(out, "added remote {} at {}", name, location)?;
+    Ok(())
+}
+
+pub fn remote_list(store: &dyn PageStore, out: &mut dyn Write) -> Result<()> {
+    for (name, location) in remotes::list(store)? {
+        writeln!(out, "{}  {}", name, location)?;
+    }
+    Ok(())
+}
+
+/// Remove a remote, and forget where its branches were.
+pub fn remote_remove(store: &dyn PageStore, name: &str, out: &mut dyn Write) -> Result<()> {
+    remotes::remove(store, name)?;
+    writeln!(out, "removed remote {}", name)?;
+    Ok(())
+}
+
+/// Write a backup archive of the branches and snapshots `names` (everything
+/// if empty) to `path`.
+pub fn backup(store: &dyn PageStore, path: &Path, names: &[String], out: &mut dyn Write) -> Result<()> {
+    let roots = if names.is_empty() {
+        Roots::All
+    } else {
+        let refs = names.iter()
+            .map(|name| {
+                if store.get_named_root(name)?.is_some() {
+                    return Ok(name.clone());
+                }
+                let snapshot = snapshot_ref(name);
+                match store.get_named_root(&snapshot)? {
+                    Some(_) => Ok(snapshot),
+                    None => Err(Error::UnknownRef(name.clone())),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Roots::Named(refs)
+    };
+    // Write next to the target and rename, so a failed backup leaves no partial archive
+    let tmp = path.with_extension("partial");
+    let mut file = BufWriter::new(File::create(&tmp)?);
+    let stats = craftsql_backup::backup(store, &roots, &mut file).and_then(|stats| {
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(stats)
+    });
+    let stats = match stats {
+        Ok(stats) => stats,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+    };
+    craftsql_core::replace_file(&tmp, path)?;
+    writeln!(
+        out,
+        "backed up {} roots to {}: {} pages ({} bytes, {} compressed)",
+        stats.manifest.refs.len() + usize::from(stats.manifest.root.is_some()),
+        path.display(),
+        stats.manifest.pages,
+        stats.page_bytes,
+        stats.archive_bytes
+    )?;
+    Ok(())
+}
+
+/// Load the backup archive at `path` into `store`.
+pub fn restore(store: &dyn PageStore, path: &Path, force: bool, out: &mut dyn Write) -> Result<()> {
+    let mut file = BufReader::new(File::open(path)?);
+    let stats = craftsql_backup::restore(&mut file, store, force)?;
+    writeln!(
+        out,
+        "restored {} roots from {}: {} pages added, {} already present",
+        stats.roots_set,
+        path.display(),
+        stats.pages_restored,
+        stats.pages_present
+    )?;
+    Ok(())
+}
+
+/// Write the database at `rev` (default: the current root) to `path` as a
+/// standard SQLite file.
+pub fn export(store: &dyn PageStore, rev: Option<&str>, path: &Path, out: &mut dyn Write) -> Result<()> {
+    let cid = match rev {
+        Some(rev) => resolve(store, rev)?,
+        None => store.current_root()?.ok_or(Error::NoRoot)?,
+    };
+    let size = craftsql_core::export_sqlite(store, &cid, path)?;
+    writeln!(out, "exported {} to {} ({} bytes)", cid, path.display(), size)?;
+    Ok(())
+}
+
+/// Import the SQLite database at `path` as a new commit and check it out,
+/// starting `branch` at it if given.
+pub fn import(store: &dyn PageStore, path: &Path, branch: Option<&str>, out: &mut dyn Write) -> Result<()> {
+    if let Some(branch) = branch {
+        validate_name(branch)?;
+        if store.get_named_root(branch)?.is_some() {
+            return Err(Error::Exists(format!("branch {}", branch)));
+        }
+    }
+    history::catch_up(store)?;
+    let imported = craftsql_core::import_sqlite(store, path, &format!("import {}", path.display()))?;
+    if let Some(branch) = branch {
+        store.set_named_root(branch, imported.commit)?;
+    }
+    store.update_root(imported.page_table)?;
+    store.set_named_root(HEAD_REF, imported.commit)?;
+    store.remove_named_root(MERGE_HEAD_REF)?;
+    history::record(store, imported.page_table, &format!("import {}", path.display()))?;
+    writeln!(
+        out,
+        "imported {} as {}: {} pages of {} bytes",
+        path.display(),
+        imported.commit,
+        imported.pages,
+        imported.page_size
+    )?;
+    Ok(())
+}
+
+/// Print a line for every change to the current root, branches, and
+/// snapshots until the process is stopped, or until `max_events` lines.
+pub fn watch(store: &dyn PageStore, max_events: Option<usize>, out: &mut dyn Write) -> Result<()> {
+    if max_events == Some(0) {
+        return Ok(());
+    }
+    let mut printed = 0;
+    for event in craftsql_core::watch(store)? {
+        let line = match event? {
+            RootEvent::Root(cid) => format!("root {}", cid),
+            RootEvent::Named { name, .. } if refs::is_reserved(&name) => continue,
+            RootEvent::Named { name, cid } => {
+                let (kind, name) = match name.strip_prefix(SNAPSHOT_PREFIX) {
+                    Some(snapshot) => ("snapshot", snapshot.to_string()),
+                    None => ("branch", name),
+                };
+                match cid {
+                    Some(cid) => format!("{} {} {}", kind, name, cid),
+                    None => format!("{} {} deleted", kind, name),
+                }
+            }
+        };
+        writeln!(out, "{}", line)?;
+        out.flush()?;
+        printed += 1;
+        if max_events.is_some_and(|max| printed >= max) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Snapshot the current root every `interval` while it changes, keeping
+/// the newest `keep` automatic snapshots, for as long as the process runs.
+/// Failed rounds are reported to `progress` and retried.
+pub fn autosnapshot(
+    store: &dyn PageStore,
+    interval: Duration,
+    keep: Option<usize>,
+    out: &mut dyn Write,
+    progress: &mut dyn Write,
+) -> Result<()> {
+    let mut result = Ok(());
+    let stop = AtomicBool::new(false);
+    autosnap::run(store, interval, keep, &stop, &mut |round| {
+        let reported = (|| {
+            match round {
+                Ok(round) => {
+                    if let Some((name, cid)) = &round.created {
+                        writeln!(out, "created snapshot {} at {}", name, cid)?;
+                    }
+                    for name in &round.pruned {
+                        writeln!(out, "deleted snapshot {}", name)?;
+                    }
+                }
+                Err(e) => writeln!(progress, "snapshot failed: {}; retrying", e)?,
+            }
+            out.flush()
+        })();
+        if let Err(e) = reported {
+            result = Err(e.into());
+            stop.store(true, Ordering::Relaxed);
+        }
+    });
+    result
+}
+
+pub fn db_list(store: &dyn PageStore, out: &mut dyn Write) -> Result<()> {
+    for name in list_databases(store)? {
+        writeln!(out, "{}", name)?;
+    }
+    Ok(())
+}
+
+pub fn db_create(store: &dyn PageStore, name: &str, out: &mut dyn Write) -> Result<()> {
+    create_database(store, name)?;
+    writeln!(out, "created database {}", name)?;
+    Ok(())
+}
+
+/// Delete a database's root and named roots; `gc` reclaims its pages.
+pub fn db_delete(store: &dyn PageStore, name: &str, out: &mut dyn Write) -> Result<()> {
+    delete_database(store, name)?;
+    writeln!(out, "deleted database {}", name)?;
+    Ok(())
+}
+
+/// Show the storage each branch and snapshot, and the current root, takes,
+/// and how much of it no other shares. With `databases`, show each of the
+/// store's databases instead.
+pub fn du(store: &dyn PageStore, databases: bool, out: &mut dyn Write) -> Result<()> {
+    let usage = if databases {
+        database_usage(store)?
+    } else {
+        let mut groups: Vec<(String, Vec<Cid>)> = Vec::new();
+        groups.extend(store.current_root()?.map(|root| (String::new(), vec![root])));
+        for (name, cid) in refs::branches(store)? {
+            groups.push((name, vec![cid]));
+        }
+        for (name, cid) in refs::snapshots(store)? {
+            groups.push((format!("snapshot {}", name), vec![cid]));
+        }
+        craftsql_core::usage(store, &groups)?
+    };
+    writeln!(out, "{:>8} {:>12} {:>12}  name", "pages", "bytes", "unique")?;
+    for usage in usage {
+        let name = match usage.name.as_str() {
+            "" if databases => "(the store's own)",
+            "" => "(current root)",
+            name => name,
+        };
+        writeln!(out, "{:>8} {:>12} {:>12}  {}", usage.pages, usage.bytes, usage.unique_bytes, name)?;
+    }
+    Ok(())
+}
+
+/// Show how many pages each table and index of `rev` (default: the current
+/// root) takes, biggest first. With `since`, also how many of them changed
+/// after that version, to tell which tables a database grew in.
+pub fn tables(store: &dyn PageStore, rev: Option<&str>, since: Option<&str>, out: &mut dyn Write) -> Result<()> {
+    let cid = match rev {
+        Some(rev) => resolve(store, rev)?,
+        None => store.current_root()?.ok_or(Error::NoRoot)?,
+    };
+    let map = page_map(store, &cid)?;
+    let changed = match since {
+        Some(since) => {
+            let old = resolve(store, since)?;
+            let diff = load_page_table(store, &cid)?.diff(&load_page_table(store, &old)?);
+            Some(changed_objects(&diff, &page_map(store, &old)?, &map))
+        }
+        None => None,
+    };
+
+    // (label, pages, pages changed)
+    let mut rows: Vec<(String, usize, usize)> = Vec::new();
+    for (i, object) in map.objects.iter().enumerate() {
+        let label = match object.kind.as_str() {
+            "index" => format!("{} (index on {})", object.name, object.table),
+            _ => object.name.clone(),
+        };
+        let count = changed.as_ref().and_then(|changed| changed.get(&object.name)).copied().unwrap_or(0);
+        rows.push((label, map.pages(i).count(), count));
+    }
+    for (owner, label) in [(PageOwner::Freelist, "(free)"), (PageOwner::Unused, "(unused)")] {
+        let pages = map.iter().filter(|(_, page_owner)| *page_owner == owner).count();
+        if pages > 0 {
+            rows.push((label.to_string(), pages, 0));
+        }
+    }
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    match changed {
+        Some(_) => writeln!(out, "{:>8} {:>12} {:>8}  name", "pages", "bytes", "changed")?,
+        None => writeln!(out, "{:>8} {:>12}  name", "pages", "bytes")?,
+    }
+    for (label, pages, count) in rows {
+        let bytes = pages * map.page_size;
+        match changed {
+            Some(_) => writeln!(out, "{:>8} {:>12} {:>8}  {}", pages, bytes, count, label)?,
+            None => writeln!(out, "{:>8} {:>12}  {}", pages, bytes, label)?,
+        }
+    }
+    Ok(())
+}
+
+/// Delete the pages nothing needs any more (see [`gc::collect`]).
+pub fn gc(store: &dyn PageStore, grace: Duration, dry_run: bool, out: &mut dyn Write) -> Result<()> {
+    let stats = gc::collect(store, grace, dry_run)?;
+    writeln!(
+        out,
+        "{} {} unreachable pages ({} bytes); {} pages kept",
+        if dry_run { "would remove" } else { "removed" },
+        stats.pages_swept,
+        stats.bytes_swept,
+        stats.pages_kept
+    )?;
+    Ok(())
+}
+
+/// Check every page and ref of `store`, and unless `pages_only`, each
+/// version its refs point at with SQLite's integrity check. Fails if
+/// anything is wrong, after reporting it.
+pub fn fsck(store: Box<dyn PageStore>, pages_only: bool, json: bool, out: &mut dyn Write) -> Result<()> {
+    let report = fsck::check(store.into(), !pages_only)?;
+    if json {
+        writeln!(out, "{}", report.to_json())?;
+    } else {
+        for problem in &report.problems {
+            match problem {
+                Problem::MissingPage { cid, needed_by } => writeln!(out, "missing page {:#} (needed by {})", cid, needed_by)?,
+                Problem::CorruptPage { cid } => writeln!(out, "corrupt page {:#}: contents don't match the CID", cid)?,
+                Problem::NotACommit { cid, needed_by } => writeln!(out, "page {:#} is not a commit (parent of {})", cid, needed_by)?,
+                Problem::BadPageTable { cid, needed_by, error } => {
+                    writeln!(out, "page {:#} is not a page table (needed by {}): {}", cid, needed_by, error)?
+                }
+                Problem::Integrity { root, refs, messages } => {
+                    writeln!(out, "database {:#} ({}) fails its integrity check:", root, refs.join(", "))?;
+                    for message in messages {
+                        writeln!(out, "    {}", message)?;
+                    }
+                }
+            }
+        }
+        writeln!(
+            out,
+            "checked {} refs, {} pages, {} databases: {} problems",
+            report.refs_checked,
+            report.pages_checked,
+            report.databases_checked,
+            report.problems.len()
+        )?;
+    }
+    if !report.is_healthy() {
+        return Err(Error::Unhealthy(report.problems.len()));
+    }
+    Ok(())
+}
+
+/// Replicate `source` into `dst`: once, or every `interval` for as long as
+/// the process runs. Rounds that change nothing aren't reported.
+pub fn replicate(
+    source: Remote<'_>,
+    dst: &dyn PageStore,
+    interval: Option<Duration>,
+    out: &mut dyn Write,
+    progress: &mut dyn Write,
+) -> Result<()> {
+    let mut replicator = Replicator::new(source, dst)?;
+    let Some(interval) = interval else {
+        let round = replicator.sync_once(&mut progress_line(progress))?;
+        if round.stats.roots_total > 0 {
+            writeln!(progress)?;
+        }
+        if round.is_empty() {
+            writeln!(out, "up to date")?;
+        } else {
+            report_round(&round, replicator.metrics(), out)?;
+        }
+        return Ok(());
+    };
+
+    let mut result = Ok(());
+    let stop = AtomicBool::new(false);
+    replicator.run(interval, &stop, &mut |round, metrics| {
+        let reported = match round {
+            Ok(round) if round.is_empty() => Ok(()),
+            Ok(round) => report_round(round, metrics, out),
+            Err(e) => writeln!(progress, "replication from {} failed: {}; retrying", source.name, e),
+        };
+        if let Err(e) = reported {
+            result = Err(e.into());
+            stop.store(true, Ordering::Relaxed);
+        }
+    });
+    result
+}
+
+fn report_round(round: &Round, metrics: &Metrics, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{}: {} refs updated, {} removed{}; {} pages ({} bytes) copied in {:.1?} (total {} pages)",
+        format_time(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())),
+        round.refs_set.len(),
+        round.refs_removed.len(),
+        if round.root_moved { ", root moved" } else { "" },
+        round.stats.pages_copied,
+        round.stats.bytes_copied,
+        round.elapsed,
+        metrics.pages_copied
+    )
+}
+
+fn report_update(update: &BranchUpdate, side: &str, out: &mut dyn Write, progress: &mut dyn Write) -> Result<()> {
+    if update.stats.roots_total > 0 {
+        writeln!(progress)?;
+    }
+    if update.up_to_date() {
+        writeln!(out, "{} {} is up to date at {}", side, update.branch, update.new)?;
+        return Ok(());
+    }
+    let old = update.old.map_or_else(|| "(new)".to_string(), |cid| cid.to_string());
+    writeln!(
+        out,
+        "{} {}: {} -> {}{} ({} pages copied, {} already present)",
+        side,
+        update.branch,
+        old,
+        update.new,
+        if update.forced { " (forced)" } else { "" },
+        update.stats.pages_copied,
+        update.stats.pages_skipped
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::{Page, PageStoreError, PageTable};
+    use craftsql_store_local::LocalPageStore;
+
+    fn output(f: impl FnOnce(&mut dyn Write) -> Result<()>) -> String {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        assert!(matches!(snapshot_create(&store, "v1", None, &mut Vec::new()), Err(Error::NoRoot)));
+
+        let v1 = Cid::from_bytes(b"v1");
+        store.update_root(v1).unwrap();
+        output(|out| snapshot_create(&store, "v1", None, out));
+        assert!(matches!(snapshot_create(&store, "v1", None, &mut Vec::new()), Err(Error::Exists(_))));
+        output(|out| snapshot_create(&store, "copy", Some("v1"), out));
+
+        let list = output(|out| snapshot_list(&store, out));
+        assert_eq!(list, format!("{}  copy\n{}  v1\n", v1, v1));
+        // Snapshots aren't branches
+        assert_eq!(output(|out| branch_list(&store, out)), "");
+
+        output(|out| snapshot_delete(&store, "copy", out));
+        assert!(matches!(snapshot_delete(&store, "copy", &mut Vec::new()), Err(Error::UnknownRef(_))));
+        assert_eq!(refs::snapshots(&store).unwrap(), vec![("v1".to_string(), v1)]);
+    }
+
+    #[test]
+    fn test_branch_checkout_and_log() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        let base = store.put(&Page { data: b"base".to_vec() }).unwrap();
+        let feature = store.put(&Page { data: b"feature work".to_vec() }).unwrap();
+
+        store.update_root(base).unwrap();
+        output(|out| branch_create(&store, "main", None, false, out));
+        output(|out| branch_create(&store, "feature", Some("main"), false, out));
+        assert!(matches!(
+            branch_create(&store, "feature", None, false, &mut Vec::new()),
+            Err(Error::Exists(_))
+        ));
+
+        // Work happens on "feature" outside the CLI
+        store.update_root(feature).unwrap();
+        output(|out| branch_create(&store, "feature", None, true, out));
+        assert_eq!(
+            output(|out| branch_list(&store, out)),
+            format!("* {}  feature\n  {}  main\n", feature, base)
+        );
+
+        output(|out| checkout(&store, "main", out));
+        assert_eq!(output(|out| root(&store, out)), format!("{}\n", base.to_hex()));
+
+        let log = output(|out| reflog(&store, None, out));
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(&base.to_string()) && lines[0].ends_with("checkout main"));
+        assert!(lines[1].starts_with(&feature.to_string()) && lines[1].ends_with("update"));
+        assert!(lines[2].starts_with(&base.to_string()) && lines[2].ends_with("update"));
+
+        // A protected branch doesn't move back, or go
+        assert_eq!(output(|out| branch_protect(&store, "feature", true, out)), "protected branch feature\n");
+        let protected = |result: Result<()>| matches!(result, Err(Error::Store(PageStoreError::Protected(_))));
+        assert!(protected(branch_create(&store, "feature", Some("main"), true, &mut Vec::new())));
+        assert!(protected(branch_delete(&store, "feature", &mut Vec::new())));
+        output(|out| branch_protect(&store, "feature", false, out));
+
+        output(|out| branch_delete(&store, "feature", out));
+        assert!(matches!(checkout(&store, "feature", &mut Vec::new()), Err(Error::UnknownRef(_))));
+    }
+
+    #[test]
+    fn test_commits() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        let v1 = store.put(&Page { data: PageTable::new().to_bytes() }).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, store.put(&Page { data: b"page".to_vec() }).unwrap());
+        let v2 = store.put(&Page { data: pt.to_bytes() }).unwrap();
+
+        store.update_root(v1).unwrap();
+        output(|out| commit(&store, "main", "first", "ada", out));
+        let first = store.get_named_root("main").unwrap().unwrap();
+        assert_eq!(Commit::load(&store, &first).unwrap().unwrap().parents, vec![]);
+        assert_eq!(output(|out| branch_list(&store, out)), format!("* {}  main\n", first));
+
+        store.update_root(v2).unwrap();
+        output(|out| commit(&store, "main", "second\n\ndetails", "", out));
+        let second = store.get_named_root("main").unwrap().unwrap();
+        let loaded = Commit::load(&store, &second).unwrap().unwrap();
+        assert_eq!((loaded.root, loaded.parents, loaded.message.as_str()), (v2, vec![first], "second\n\ndetails"));
+
+        assert_eq!(output(|out| log(&store, None, None, None, true, out)), format!("{}  second\n{}  first\n", second, first));
+        let full = output(|out| log(&store, Some("main"), Some(2), None, false, out));
+        assert!(full.starts_with(&format!("commit {}\nDate:   ", second.to_hex())), "{}", full);
+        assert!(full.contains("\n\n    second\n    \n    details\n\ncommit "), "{}", full);
+        assert!(full.contains("Pages:  1 changed\n") && full.contains("Author: ada\n"), "{}", full);
+        assert!(output(|out| log(&store, None, Some(1), None, true, out)).ends_with("second\n"));
+        assert!(output(|out| log(&store, None, None, Some("2999-01-01"), true, out)).is_empty());
+        assert!(matches!(log(&store, None, None, Some("soon"), true, &mut Vec::new()), Err(Error::InvalidTime(_))));
+
+        // Checking out a commit goes to its page table, and the next commit follows it
+        output(|out| checkout(&store, &first.to_hex(), out));
+        assert_eq!(store.current_root().unwrap(), Some(v1));
+        assert_eq!(store.get_named_root(HEAD_REF).unwrap(), Some(first));
+        assert!(matches!(commit(&store, "main", "", "", &mut Vec::new()), Err(Error::Diverged(_))));
+        output(|out| commit(&store, "side", "", "", out));
+        let side = store.get_named_root("side").unwrap().unwrap();
+        assert_eq!(Commit::load(&store, &side).unwrap().unwrap().parents, vec![first]);
+        assert_eq!(craftsql_core::merge_base(&store, side, second).unwrap(), Some(first));
+    }
+
+    #[test]
+    fn test_remotes_and_fetch() {
+        let (tmp_local, tmp_remote) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let store = LocalPageStore::new(tmp_local.path()).unwrap();
+        let location = tmp_remote.path().to_str().unwrap();
+        output(|out| remote_add(&store, "origin", location, out));
+        assert_eq!(output(|out| remote_list(&store, out)), format!("origin  {}\n", location));
+
+        let (name, spec) = remotes::lookup(&store, "origin").unwrap();
+        let remote_store = spec.open(None).unwrap();
+        let remote = Remote { name: &name, store: remote_store.as_ref() };
+        let v1 = store.put(&Page { data: PageTable::new().to_bytes() }).unwrap();
+        store.set_named_root("main", v1).unwrap();
+        output(|out| push(&store, remote, "main", false, out, &mut Vec::new()));
+
+        let mut pt = PageTable::new();
+        pt.set(0, remote_store.put(&Page { data: b"theirs".to_vec() }).unwrap());
+        let v2 = remote_store.put(&Page { data: pt.to_bytes() }).unwrap();
+        remote_store.set_named_root("main", v2).unwrap();
+        let fetched = output(|out| fetch(&store, remote, &[], out, &mut Vec::new()));
+        assert_eq!(fetched, format!("remote branch origin/main: {} -> {} (1 pages copied, 0 already present)\n", v1, v2));
+        assert_eq!(store.get_named_root("main").unwrap(), Some(v1));
+        assert_eq!(output(|out| branch_list_remotes(&store, out)), format!("  {}  origin/main\n", v2));
+        output(|out| checkout(&store, "origin/main", out));
+        assert_eq!(store.current_root().unwrap(), Some(v2));
+
+        output(|out| remote_remove(&store, "origin", out));
+        assert!(output(|out| branch_list_remotes(&store, out)).is_empty());
+        assert!(matches!(resolve(&store, "origin/main"), Err(Error::UnknownRef(_))));
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let (tmp_src, tmp_dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (src, dst) = (LocalPageStore::new(tmp_src.path()).unwrap(), LocalPageStore::new(tmp_dst.path()).unwrap());
+        let root = src.put(&Page { data: PageTable::new().to_bytes() }).unwrap();
+        src.update_root(root).unwrap();
+        output(|out| snapshot_create(&src, "v1", None, out));
+        output(|out| branch_create(&src, "main", None, false, out));
+
+        let archive = tmp_src.path().join("db.craftsql");
+        assert!(matches!(backup(&src, &archive, &["v2".into()], &mut Vec::new()), Err(Error::UnknownRef(_))));
+        assert!(!archive.exists() && !archive.with_extension("partial").exists());
+        output(|out| backup(&src, &archive, &["v1".into()], out));
+        output(|out| restore(&dst, &archive, false, out));
+        assert_eq!(dst.list_named_roots().unwrap(), vec![(snapshot_ref("v1"), root)]);
+        assert_eq!(dst.current_root().unwrap(), None);
+    }
+
+    #[test]
+    fn test_import_and_export() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(&tmp.path().join("store")).unwrap();
+        let mut database = vec![0u8; 2 * 1024];
+        database[..16].copy_from_slice(b"SQLite format 3\0");
+        database[16..18].copy_from_slice(&1024u16.to_be_bytes());
+        database[18..20].copy_from_slice(&[1, 1]);
+        let file = tmp.path().join("app.db");
+        fs::write(&file, &database).unwrap();
+
+        output(|out| import(&store, &file, Some("main"), out));
+        let head = store.get_named_root(HEAD_REF).unwrap().unwrap();
+        assert_eq!(store.get_named_root("main").unwrap(), Some(head));
+        assert_eq!(store.current_root().unwrap(), Some(page_table_root(&store, &head).unwrap()));
+        assert!(matches!(import(&store, &file, Some("main"), &mut Vec::new()), Err(Error::Exists(_))));
+
+        let copy = tmp.path().join("copy.db");
+        output(|out| export(&store, Some("main"), &copy, out));
+        assert_eq!(fs::read(&copy).unwrap(), database);
+    }
+
+    #[test]
+    fn test_tables() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(&tmp.path().join("store")).unwrap();
+        // An empty database: the schema table's leaf, and a free page
+        let mut database = vec![0u8; 2 * 1024];
+        database[..16].copy_from_slice(b"SQLite format 3\0");
+        database[16..18].copy_from_slice(&1024u16.to_be_bytes());
+        database[32..36].copy_from_slice(&2u32.to_be_bytes());
+        database[100] = 13;
+        let file = tmp.path().join("app.db");
+        fs::write(&file, &database).unwrap();
+        output(|out| import(&store, &file, Some("main"), out));
+
+        let printed = output(|out| tables(&store, None, Some("main"), out));
+        let lines: Vec<Vec<&str>> = printed.lines().map(|line| line.split_whitespace().collect()).collect();
+        assert_eq!(lines, [
+            vec!["pages", "bytes", "changed", "name"],
+            vec!["1", "1024", "0", "(free)"],
+            vec!["1", "1024", "0", "sqlite_schema"],
+        ]);
+    }
+
+    #[test]
+    fn test_watch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        let writer = LocalPageStore::new(tmp.path()).unwrap();
+        let root = Cid::from_bytes(b"root");
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            writer.update_root(root).unwrap();
+            history::record(&writer, root, "checkout").unwrap();
+            writer.set_named_root(&snapshot_ref("v1"), root).unwrap();
+        });
+        let printed = output(|out| watch(&store, Some(2), out));
+        thread.join().unwrap();
+        // Changes read together come out in one order; bookkeeping refs are skipped
+        let mut lines: Vec<&str> = printed.lines().collect();
+        lines.sort();
+        assert_eq!(lines, vec![format!("root {}", root), format!("snapshot v1 {}", root)]);
+    }
+}
@@ -0,0 +1,374 @@
+//! Store checks: whether everything a store's refs need is there and sound.
+//!
+//! [`check`] starts from the current root and every named root, across all
+//! of the store's [databases](craftsql_namespace). Every page they reach is
+//! read and hashed against its CID: commits, their parents, page tables,
+//! and the data pages the tables list. A ref to a database version must
+//! point at a commit or a page table that parses. Bookkeeping refs (names
+//! starting with `.`) also hold pages of other formats, such as history
+//! entries, so for those only the page pointed at is checked unless it's a
+//! version. History stops at commits a shallow copy left without parents
+//! (see [`craftsql_sync::shallow`]).
+//!
+//! Then, unless told to skip it and with the `sql` feature, each database
+//! version a branch, snapshot, or current root points at is opened through
+//! the VFS and run through `PRAGMA integrity_check`. Older versions in
+//! their history aren't, since each check reads the whole database again.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use craftsql_core::{Cid, Commit, Page, PageStore, PageStoreError, PageTable};
+use craftsql_sync::shallow::SHALLOW_PREFIX;
+use serde::{Serialize, Serializer};
+
+use crate::Result;
+
+/// Something wrong with the store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// A page something needs isn't in the store.
+    MissingPage {
+        #[serde(serialize_with = "hex")]
+        cid: Cid,
+        /// The ref, or CID of the page, that needs it.
+        needed_by: String,
+    },
+    /// A page whose contents don't hash to its CID.
+    CorruptPage {
+        #[serde(serialize_with = "hex")]
+        cid: Cid,
+    },
+    /// A commit's parent that isn't a commit.
+    NotACommit {
+        #[serde(serialize_with = "hex")]
+        cid: Cid,
+        needed_by: String,
+    },
+    /// A page that should be a page table but doesn't parse as one.
+    BadPageTable {
+        #[serde(serialize_with = "hex")]
+        cid: Cid,
+        needed_by: String,
+        error: String,
+    },
+    /// A database version that SQLite's integrity check finds fault with.
+    Integrity {
+        /// The page table.
+        #[serde(serialize_with = "hex")]
+        root: Cid,
+        /// Refs pointing at it.
+        refs: Vec<String>,
+        /// What the check reported, or why the database didn't open.
+        messages: Vec<String>,
+    },
+}
+
+fn hex<S: Serializer>(cid: &Cid, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&cid.to_hex())
+}
+
+/// What a check found.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FsckReport {
+    pub refs_checked: u64,
+    pub pages_checked: u64,
+    /// Database versions run through `PRAGMA integrity_check`.
+    pub databases_checked: u64,
+    pub problems: Vec<Problem>,
+}
+
+impl FsckReport {
+    pub fn is_healthy(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("fsck report serialization")
+    }
+}
+
+/// The name of named root `name` within its database, if it's one of a
+/// database's refs.
+fn local_name(name: &str) -> &str {
+    name.strip_prefix(craftsql_namespace::PREFIX)
+        .and_then(|rest| rest.split_once(".refs.").map(|(_, name)| name))
+        .unwrap_or(name)
+}
+
+/// Check every page `store`'s refs reach, then (with `sqlite`) each
+/// version they point at with SQLite's integrity check.
+pub fn check(store: Arc<dyn PageStore>, sqlite: bool) -> Result<FsckReport> {
+    let mut refs: Vec<(String, Cid)> = store.current_root()?.map(|cid| ("root".to_string(), cid)).into_iter().collect();
+    refs.extend(store.list_named_roots()?);
+    let shallow: HashSet<Cid> = refs.iter()
+        .filter(|(name, _)| local_name(name).starts_with(SHALLOW_PREFIX))
+        .map(|(_, cid)| *cid)
+        .collect();
+
+    let mut walk = Walk {
+        store: store.as_ref(),
+        shallow,
+        pages: HashMap::new(),
+        versions: HashMap::new(),
+        report: FsckReport::default(),
+    };
+    // Versions to run SQLite's check on, with the refs pointing at them
+    let mut versions: BTreeMap<String, (Cid, Vec<String>)> = BTreeMap::new();
+    for (name, cid) in &refs {
+        walk.report.refs_checked += 1;
+        let bookkeeping = local_name(name).starts_with('.');
+        if let Some(table) = walk.version(*cid, name, !bookkeeping)? {
+            if !bookkeeping {
+                versions.entry(table.to_hex()).or_insert_with(|| (table, Vec::new())).1.push(name.clone());
+            }
+        }
+    }
+    let mut report = walk.report;
+
+    if sqlite {
+        for (root, refs) in versions.into_values() {
+            report.databases_checked += 1;
+            let messages = integrity_check(Arc::clone(&store), root);
+            if !messages.is_empty() {
+                report.problems.push(Problem::Integrity { root, refs, messages });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// What SQLite's integrity check reports wrong with the database at page
+/// table `root`: nothing if it's sound.
+#[cfg(feature = "sql")]
+fn integrity_check(store: Arc<dyn PageStore>, root: Cid) -> Vec<String> {
+    let checked = craftsql_diff::open_root(store, root).and_then(|db| {
+        let mut statement = db.prepare("PRAGMA integrity_check")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        Ok(rows.collect::<std::result::Result<Vec<_>, _>>()?)
+    });
+    match checked {
+        Ok(messages) if messages == ["ok"] => Vec::new(),
+        Ok(messages) => messages,
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+#[cfg(not(feature = "sql"))]
+fn integrity_check(_store: Arc<dyn PageStore>, _root: Cid) -> Vec<String> {
+    Vec::new()
+}
+
+struct Walk<'a> {
+    store: &'a dyn PageStore,
+    /// Commits whose parents a shallow copy left out.
+    shallow: HashSet<Cid>,
+    /// Pages read so far, and whether each and all it reaches are sound.
+    pages: HashMap<Cid, bool>,
+    /// Refs' targets checked so far, and their sound page tables.
+    versions: HashMap<Cid, Option<Cid>>,
+    report: FsckReport,
+}
+
+impl Walk<'_> {
+    /// Read `cid` and check it hashes to its CID, recording what's wrong
+    /// if not.
+    fn fetch(&mut self, cid: Cid, needed_by: &str) -> Result<Option<Page>> {
+        self.report.pages_checked += 1;
+        match self.store.get(&cid) {
+            Ok(page) if Cid::from_bytes(&page.data) == cid => Ok(Some(page)),
+            Ok(_) => {
+                self.report.problems.push(Problem::CorruptPage { cid });
+                Ok(None)
+            }
+            Err(PageStoreError::NotFound(_)) => {
+                self.report.problems.push(Problem::MissingPage { cid, needed_by: needed_by.to_string() });
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Check what ref `name` points at, `cid`, and if it's a commit or page
+    /// table, everything it reaches. Unless `version`, it may be any other
+    /// page. Returns the version's page table if that is all sound.
+    fn version(&mut self, cid: Cid, name: &str, version: bool) -> Result<Option<Cid>> {
+        if let Some(&table) = self.versions.get(&cid) {
+            return Ok(table);
+        }
+        let table = match self.fetch(cid, name)? {
+            None => None,
+            Some(page) => match Commit::from_bytes(&page.data) {
+                Some(commit) => {
+                    let root = commit.root;
+                    self.pages.insert(cid, true);
+                    self.history(cid, commit)?;
+                    self.pages[&root].then_some(root)
+                }
+                None => match PageTable::from_bytes(&page.data) {
+                    Ok(table) => {
+                        let sound = self.table(cid, table)?;
+                        self.pages.insert(cid, sound);
+                        sound.then_some(cid)
+                    }
+                    // Bookkeeping of some other format; another ref may
+                    // still need it to be a version
+                    Err(_) if !version => return Ok(None),
+                    Err(error) => {
+                        let needed_by = name.to_string();
+                        self.report.problems.push(Problem::BadPageTable { cid, needed_by, error: error.to_string() });
+                        None
+                    }
+                },
+            },
+        };
+        self.versions.insert(cid, table);
+        Ok(table)
+    }
+
+    /// Check commit `cid`'s page table and its parents', back to the first
+    /// commit or a shallow one.
+    fn history(&mut self, cid: Cid, commit: Commit) -> Result<()> {
+        let mut queue = vec![(cid, commit)];
+        while let Some((cid, commit)) = queue.pop() {
+            let needed_by = format!("{:#}", cid);
+            self.page_table(commit.root, &needed_by)?;
+            if self.shallow.contains(&cid) {
+                continue;
+            }
+            for parent in commit.parents {
+                if self.pages.contains_key(&parent) {
+                    continue;
+                }
+                let page = self.fetch(parent, &needed_by)?;
+                match page.as_ref().map(|page| Commit::from_bytes(&page.data)) {
+                    Some(Some(commit)) => queue.push((parent, commit)),
+                    Some(None) => {
+                        let needed_by = needed_by.clone();
+                        self.report.problems.push(Problem::NotACommit { cid: parent, needed_by });
+                    }
+                    None => {}
+                }
+                self.pages.insert(parent, page.is_some());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the page table at `cid` and its pages. Returns whether all are
+    /// sound.
+    fn page_table(&mut self, cid: Cid, needed_by: &str) -> Result<bool> {
+        if let Some(&sound) = self.pages.get(&cid) {
+            return Ok(sound);
+        }
+        let sound = match self.fetch(cid, needed_by)? {
+            None => false,
+            Some(page) => match PageTable::from_bytes(&page.data) {
+                Ok(table) => self.table(cid, table)?,
+                Err(error) => {
+                    let needed_by = needed_by.to_string();
+                    self.report.problems.push(Problem::BadPageTable { cid, needed_by, error: error.to_string() });
+                    false
+                }
+            },
+        };
+        self.pages.insert(cid, sound);
+        Ok(sound)
+    }
+
+    /// Check the pages of `table`, stored at `cid`.
+    fn table(&mut self, cid: Cid, table: PageTable) -> Result<bool> {
+        let needed_by = format!("{:#}", cid);
+        let mut sound = true;
+        for entry in table.entries.into_iter().flatten() {
+            sound &= match self.pages.get(&entry) {
+                Some(&page_sound) => page_sound,
+                None => {
+                    let page_sound = self.fetch(entry, &needed_by)?.is_some();
+                    self.pages.insert(entry, page_sound);
+                    page_sound
+                }
+            };
+        }
+        Ok(sound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_local::LocalPageStore;
+    use craftsql_sync::shallow::shallow_ref;
+    use craftsql_testing::put_table;
+
+    #[test]
+    fn test_check_pages() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = Arc::new(LocalPageStore::new(tmp.path()).unwrap());
+        let v1 = put_table(store.as_ref(), &[b"one", b"two"]);
+        let first = Commit::new(v1, vec![], "first").put(store.as_ref()).unwrap();
+        let v2 = put_table(store.as_ref(), &[b"one", b"three"]);
+        let second = Commit::new(v2, vec![first], "second").put(store.as_ref()).unwrap();
+        store.set_named_root("main", second).unwrap();
+        store.update_root(v2).unwrap();
+        store.set_named_root(".remotes", store.put(&Page { data: b"{}".to_vec() }).unwrap()).unwrap();
+        // A shallow copy's commit, whose parent was never copied
+        let lost = Cid::from_bytes(b"never copied");
+        let shallow = Commit::new(v2, vec![lost], "shallow").put(store.as_ref()).unwrap();
+        store.set_named_root("copied", shallow).unwrap();
+        store.set_named_root(&shallow_ref(&shallow), shallow).unwrap();
+
+        let report = check(store.clone(), false).unwrap();
+        assert!(report.is_healthy(), "{:?}", report.problems);
+        assert_eq!(report.refs_checked, 5);
+        assert_eq!(report.databases_checked, 0);
+
+        // Break it: a page gone, one altered, and a branch at something
+        // that's no version
+        store.delete_page(&Cid::from_bytes(b"two")).unwrap();
+        std::fs::write(store.page_path(&Cid::from_bytes(b"three")), b"tampered").unwrap();
+        store.set_named_root("junk", store.put(&Page { data: b"not a table".to_vec() }).unwrap()).unwrap();
+        let report = check(store.clone(), false).unwrap();
+        assert_eq!(report.problems.len(), 3, "{:?}", report.problems);
+        assert!(report.problems.contains(&Problem::MissingPage { cid: Cid::from_bytes(b"two"), needed_by: format!("{:#}", v1) }));
+        assert!(report.problems.contains(&Problem::CorruptPage { cid: Cid::from_bytes(b"three") }));
+        assert!(report.problems.iter().any(|p| matches!(p, Problem::BadPageTable { needed_by, .. } if needed_by == "junk")));
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        let kinds: Vec<&str> = json["problems"].as_array().unwrap().iter().map(|p| p["kind"].as_str().unwrap()).collect();
+        assert!(kinds.contains(&"missing_page") && kinds.contains(&"corrupt_page"));
+    }
+
+    #[cfg(feature = "sql")]
+    #[test]
+    fn test_check_databases() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = Arc::new(LocalPageStore::new(&tmp.path().join("store")).unwrap());
+        let file = tmp.path().join("db.sqlite");
+        let db = rusqlite::Connection::open(&file).unwrap();
+        db.execute_batch("
+            CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 200)
+            INSERT INTO t SELECT i, printf('row %d', i) FROM n;
+        ").unwrap();
+        drop(db);
+        let imported = craftsql_core::import_sqlite(store.as_ref(), &file, "import").unwrap();
+        store.set_named_root("main", imported.commit).unwrap();
+        assert!(check(store.clone(), true).unwrap().is_healthy());
+
+        // Every page is stored intact, but page 2 is no b-tree page
+        let mut table = craftsql_core::load_page_table(store.as_ref(), &imported.page_table).unwrap();
+        table.set(1, store.put(&Page { data: vec![0; imported.page_size] }).unwrap());
+        let broken = store.put(&Page { data: table.to_bytes() }).unwrap();
+        store.set_named_root("broken", broken).unwrap();
+        let report = check(store.clone(), true).unwrap();
+        assert_eq!(report.databases_checked, 2);
+        match &report.problems[..] {
+            [Problem::Integrity { root, refs, messages }] => {
+                assert_eq!((root, &refs[..]), (&broken, &["broken".to_string()][..]));
+                assert!(!messages.is_empty());
+            }
+            problems => panic!("{:?}", problems),
+        }
+    }
+}
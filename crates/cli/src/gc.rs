@@ -0,0 +1,153 @@
+//! Garbage collection: deleting the pages nothing needs any more.
+//!
+//! Marks every page reachable (see [`reachable`]) from the current root,
+//! every named root (branches, snapshots, and bookkeeping such as tracking
+//! refs), and the roots of [history](crate::history) entries newer than a
+//! grace window, so a recent `checkout` can still be undone. The history
+//! entries themselves are always kept, so `log` stays complete even once
+//! older roots are gone. The histories of the store's
+//! [databases](craftsql_namespace) count too. Every other page the store
+//! lists is swept.
+//!
+//! Pages a writer has stored but not yet pointed a root at look unreachable
+//! too: collect while nothing else writes to the store.
+//!
+//! A store only sweeps the pages it lists, and deletes them its own way
+//! (see [`PageStore::delete_page`]): a caching store lists and evicts its
+//! cached pages, leaving its remote as it is.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use craftsql_core::{reachable, Cid, PageStore};
+use craftsql_namespace::{list_databases, NamespacedPageStore};
+
+use crate::{history, Result};
+
+/// How long history keeps old roots alive by default.
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(14 * 86_400);
+
+/// What a collection found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub pages_kept: u64,
+    /// Unreachable pages deleted, or that would be on a dry run.
+    pub pages_swept: u64,
+    pub bytes_swept: u64,
+}
+
+/// Delete every page of `store` not reachable from its roots or from
+/// history entries newer than `grace`. With `dry_run`, only count them.
+pub fn collect(store: &dyn PageStore, grace: Duration, dry_run: bool) -> Result<GcStats> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let cutoff = now.saturating_sub(grace.as_secs());
+
+    let mut roots: Vec<Cid> = store.current_root()?.into_iter().collect();
+    roots.extend(store.list_named_roots()?.into_iter().map(|(_, cid)| cid));
+    mark_history(store, cutoff, &mut roots)?;
+    for name in list_databases(store)? {
+        mark_history(&NamespacedPageStore::new(store, &name)?, cutoff, &mut roots)?;
+    }
+    let marked = reachable(store, &roots)?;
+
+    let mut stats = GcStats::default();
+    let mut unmarked = Vec::new();
+    for (cid, size) in store.list_pages()? {
+        if marked.contains(&cid) {
+            stats.pages_kept += 1;
+        } else {
+            unmarked.push((cid, size));
+        }
+    }
+    let deleted = if dry_run {
+        vec![true; unmarked.len()]
+    } else {
+        store.delete_many(&unmarked.iter().map(|(cid, _)| *cid).collect::<Vec<_>>())?
+    };
+    for ((_, size), deleted) in unmarked.into_iter().zip(deleted) {
+        if deleted {
+            stats.pages_swept += 1;
+            stats.bytes_swept += size;
+        }
+    }
+    Ok(stats)
+}
+
+/// Add every history entry of `store`, and the roots of those made at or
+/// after `cutoff`, to `roots`.
+fn mark_history(store: &dyn PageStore, cutoff: u64, roots: &mut Vec<Cid>) -> Result<()> {
+    for (cid, entry) in history::stored_entries(store, None)? {
+        roots.push(cid);
+        if entry.time >= cutoff {
+            roots.push(entry.root);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::{Commit, Page};
+    use craftsql_store_local::LocalPageStore;
+    use craftsql_testing::put_table;
+
+    #[test]
+    fn test_collect() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        let v1 = put_table(&store, &[b"kept by a commit"]);
+        let first = Commit::new(v1, vec![], "first").put(&store).unwrap();
+        let v2 = put_table(&store, &[b"current"]);
+        let second = Commit::new(v2, vec![first], "second").put(&store).unwrap();
+        store.set_named_root("main", second).unwrap();
+        let recent = put_table(&store, &[b"in recent history"]);
+        history::record(&store, recent, "checkout recent").unwrap();
+        let app = NamespacedPageStore::new(&store, "app").unwrap();
+        let app_recent = put_table(&app, &[b"in a database's history"]);
+        history::record(&app, app_recent, "checkout").unwrap();
+        history::record(&app, v2, "checkout").unwrap();
+        store.update_root(v2).unwrap();
+        store.put(&Page { data: b"garbage".to_vec() }).unwrap();
+        let dropped = put_table(&store, &[b"dropped"]);
+        store.set_named_root("old", dropped).unwrap();
+        store.remove_named_root("old").unwrap();
+
+        let dry = collect(&store, DEFAULT_GRACE, true).unwrap();
+        assert_eq!((dry.pages_swept, dry.bytes_swept), (3, 7 + 7 + store.get(&dropped).unwrap().data.len() as u64));
+        assert_eq!(store.list_pages().unwrap().len() as u64, dry.pages_kept + dry.pages_swept);
+
+        let swept = collect(&store, DEFAULT_GRACE, false).unwrap();
+        assert_eq!(swept, dry);
+        assert!(!store.has(&dropped).unwrap());
+        for cid in [v1, first, recent, app_recent, Cid::from_bytes(b"in recent history"), Cid::from_bytes(b"current")] {
+            assert!(store.has(&cid).unwrap());
+        }
+        assert_eq!(collect(&store, DEFAULT_GRACE, false).unwrap().pages_swept, 0);
+        assert_eq!(history::entries(&store, None).unwrap()[0].root, recent);
+        assert_eq!(history::entries(&app, None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_collect_caching_store() {
+        use craftsql_store_cached::{CacheConfig, CachingPageStore};
+        use craftsql_store_mem::MemPageStore;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let remote = MemPageStore::new();
+        let store = CachingPageStore::new(tmp.path(), &remote, CacheConfig::default()).unwrap();
+        let root = put_table(&store, &[b"current"]);
+        store.update_root(root).unwrap();
+        let garbage = store.put(&Page { data: b"garbage".to_vec() }).unwrap();
+        // Never cached, so never listed
+        let remote_only = remote.put(&Page { data: b"remote only".to_vec() }).unwrap();
+
+        let swept = collect(&store, DEFAULT_GRACE, false).unwrap();
+        assert_eq!((swept.pages_kept, swept.pages_swept, swept.bytes_swept), (2, 1, 7));
+        assert!(!store.is_cached(&garbage));
+        for cid in [garbage, remote_only] {
+            assert!(remote.has(&cid).unwrap());
+        }
+        assert_eq!(store.get(&Cid::from_bytes(b"current")).unwrap().data, b"current");
+        assert_eq!(collect(&store, DEFAULT_GRACE, false).unwrap().pages_swept, 0);
+    }
+}
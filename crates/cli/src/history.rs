@@ -0,0 +1,183 @@
+//! Root history recorded by the CLI.
+//!
+//! Every root change made through the CLI appends an entry, stored as a page
+//! that links to the previous entry; the newest entry's CID is kept in the
+//! [`HISTORY_REF`] named root. Root changes made elsewhere (e.g. SQL writes
+//! through the VFS) aren't seen as they happen: the next CLI command that
+//! changes the root first records the root it found as an `update` entry.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use craftsql_core::{Cid, Page, PageStore, PageStoreError};
+use serde::{Deserialize, Serialize};
+
+use crate::refs::parse_cid;
+use crate::Result;
+
+/// Named root pointing at the newest history entry.
+pub const HISTORY_REF: &str = ".history";
+
+/// One recorded root change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The root after the change.
+    pub root: Cid,
+    /// What changed it, e.g. `checkout v1`.
+    pub action: String,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+}
+
+/// On-disk form of an entry; CIDs as hex.
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    root: String,
+    previous: Option<String>,
+    action: String,
+    time: u64,
+}
+
+/// Append an entry for `root`.
+pub fn record(store: &dyn PageStore, root: Cid, action: &str) -> Result<()> {
+    let stored = StoredEntry {
+        root: root.to_hex(),
+        previous: store.get_named_root(HISTORY_REF)?.map(|cid| cid.to_hex()),
+        action: action.to_string(),
+        time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+    };
+    let data = serde_json::to_vec(&stored).expect("history entry serialization");
+    let cid = store.put(&Page { data })?;
+    store.set_named_root(HISTORY_REF, cid)?;
+    Ok(())
+}
+
+/// Record the current root if it moved since the last entry.
+pub fn catch_up(store: &dyn PageStore) -> Result<()> {
+    let Some(root) = store.current_root()? else {
+        return Ok(());
+    };
+    if entries(store, Some(1))?.first().map(|entry| entry.root) != Some(root) {
+        record(store, root, "update")?;
+    }
+    Ok(())
+}
+
+/// Entries newest first, at most `limit` of them.
+pub fn entries(store: &dyn PageStore, limit: Option<usize>) -> Result<Vec<Entry>> {
+    Ok(stored_entries(store, limit)?.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Entries newest first, each with the CID of the page it is stored in.
+pub fn stored_entries(store: &dyn PageStore, limit: Option<usize>) -> Result<Vec<(Cid, Entry)>> {
+    let mut entries = Vec::new();
+    let mut next = store.get_named_root(HISTORY_REF)?;
+    while let Some(cid) = next {
+        if limit.is_some_and(|limit| entries.len() >= limit) {
+            break;
+        }
+        let page = store.get(&cid)?;
+        let stored: StoredEntry = serde_json::from_slice(&page.data)
+            .map_err(|e| PageStoreError::Storage(format!("history entry {}: {}", cid, e)))?;
+        let root = parse_cid(&stored.root)
+            .ok_or_else(|| PageStoreError::Storage(format!("history entry {}: bad root", cid)))?;
+        next = stored.previous.as_deref().and_then(parse_cid);
+        entries.push((cid, Entry { root, action: stored.action, time: stored.time }));
+    }
+    Ok(entries)
+}
+
+/// Format Unix seconds as `YYYY-MM-DD HH:MM:SS UTC`.
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil date from days since 1970-01-01 (proleptic Gregorian)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, rem / 3600, rem % 3600 / 60, rem % 60
+    )
+}
+
+/// Parse Unix seconds, `YYYY-MM-DD`, or `YYYY-MM-DD HH:MM[:SS]` (UTC; a
+/// `T` may separate date and time).
+pub fn parse_time(s: &str) -> Option<u64> {
+    if let Ok(secs) = s.parse() {
+        return Some(secs);
+    }
+    let (date, time) = s.split_once([' ', 'T']).unwrap_or((s, "00:00"));
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.trim_end_matches(" UTC").trim_end_matches('Z').splitn(3, ':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next().unwrap_or(Some(0))?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // Days since 1970-01-01, the inverse of format_time
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from(days * 86_400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_local::LocalPageStore;
+
+    #[test]
+    fn test_record_and_catch_up() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        let a = Cid::from_bytes(b"root a");
+        let b = Cid::from_bytes(b"root b");
+
+        catch_up(&store).unwrap();
+        assert!(entries(&store, None).unwrap().is_empty());
+
+        store.update_root(a).unwrap();
+        catch_up(&store).unwrap();
+        catch_up(&store).unwrap();
+        record(&store, b, "checkout b").unwrap();
+
+        let log = entries(&store, None).unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!((log[0].root, log[0].action.as_str()), (b, "checkout b"));
+        assert_eq!((log[1].root, log[1].action.as_str()), (a, "update"));
+        assert_eq!(entries(&store, Some(1)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(951_827_696), "2000-02-29 12:34:56 UTC");
+        assert_eq!(format_time(1_790_000_000), "2026-09-21 14:13:20 UTC");
+    }
+
+    #[test]
+    fn test_parse_time() {
+        for secs in [0, 951_827_696, 1_790_000_000] {
+            assert_eq!(parse_time(&format_time(secs)), Some(secs));
+        }
+        assert_eq!(parse_time("1790000000"), Some(1_790_000_000));
+        assert_eq!(parse_time("2026-09-21"), Some(1_789_948_800));
+        assert_eq!(parse_time("2026-09-21T14:13"), Some(1_789_999_980));
+        for bad in ["", "yesterday", "2026-13-01", "2026-09-21 25:00", "1969-12-31"] {
+            assert_eq!(parse_time(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! CraftSQL command implementations, shared by the `craftsql` binary and
+//! other front ends such as the Python bindings.
+//!
+//! Opens a local store directory or a CraftOBJ daemon (see [`store::StoreSpec`])
+//! and manages its snapshots, branches, and root pointer, moves a database
+//! or branch between two stores (optionally named, see [`remotes`]), diffs
+//! and merges the data in two versions, deletes the pages nothing needs any
+//! more, checks that a store is sound (see [`fsck`]), or backs it up to a
+//! single file. With the `shell` feature, [`shell`] queries a version
+//! interactively.
+
+pub mod autosnap;
+pub mod commands;
+pub mod fsck;
+pub mod gc;
+pub mod history;
+pub mod refs;
+pub mod remotes;
+#[cfg(feature = "shell")]
+pub mod shell;
+pub mod store;
+
+use craftsql_core::PageStoreError;
+use craftsql_sync::SyncError;
+
+/// CLI errors.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Store(#[from] PageStoreError),
+    #[error(transparent)]
+    Sync(#[from] SyncError),
+    #[error(transparent)]
+    Backup(#[from] craftsql_backup::BackupError),
+    #[error(transparent)]
+    Namespace(#[from] craftsql_namespace::NamespaceError),
+    #[cfg(feature = "sql")]
+    #[error(transparent)]
+    Diff(#[from] craftsql_diff::DiffError),
+    #[error("invalid name {0:?}: use letters, digits, '-', '_' and '.', not starting with '.'")]
+    InvalidName(String),
+    #[error("invalid remote name {0:?}: use letters, digits, '-' and '_'")]
+    InvalidRemoteName(String),
+    #[error("no remote named {0:?}")]
+    UnknownRemote(String),
+    #[error("no branch, snapshot, or CID named {0:?}")]
+    UnknownRef(String),
+    #[error("invalid time {0:?}: use Unix seconds or YYYY-MM-DD [HH:MM[:SS]] (UTC)")]
+    InvalidTime(String),
+    #[error("{0} already exists")]
+    Exists(String),
+    #[error("the store has no root yet")]
+    NoRoot,
+    #[error("no commit checked out: name a branch, snapshot, or CID")]
+    NoHead,
+    #[error("branch {0} has commits the checked-out commit lacks; check it out first")]
+    Diverged(String),
+    #[error("the current root has uncommitted changes; commit them first")]
+    Uncommitted,
+    #[error("no common history with {0:?}: pass --base")]
+    NoMergeBase(String),
+    #[error("the store has {0} problem(s)")]
+    Unhealthy(usize),
+    #[error("no store given: pass --store or set CRAFTSQL_STORE")]
+    NoStore,
+    #[cfg(feature = "shell")]
+    #[error("read input: {0}")]
+    Readline(#[from] rustyline::error::ReadlineError),
+    #[cfg(all(unix, feature = "fuse"))]
+    #[error("mount: {0}")]
+    Mount(std::io::Error),
+    #[error("write output: {0}")]
+    Output(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,514 @@
+//! `craftsql` — command-line access to CraftSQL stores.
+//!
+//! Argument parsing only; the commands live in the `craftsql_cli` library.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use craftsql_cli::commands::PullMode;
+use craftsql_cli::store::StoreSpec;
+use craftsql_cli::{autosnap, commands, gc, remotes, Error, Result};
+use craftsql_core::PageStore;
+use craftsql_namespace::NamespacedPageStore;
+use craftsql_sync::Remote;
+
+#[derive(Parser)]
+#[command(name = "craftsql", version, about = "Manage CraftSQL stores: snapshots, branches, and roots")]
+struct Cli {
+    /// Store to open: a directory, `unix:<socket>`, `tcp://<host:port>`, or an `http://` URL
+    #[arg(short, long, env = "CRAFTSQL_STORE")]
+    store: Option<String>,
+
+    /// Local cache directory for a daemon-backed --store
+    #[arg(long, env = "CRAFTSQL_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Database within the store to work on (default: the store's own)
+    #[arg(long, env = "CRAFTSQL_DATABASE")]
+    database: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create, list, or delete snapshots
+    #[command(subcommand)]
+    Snapshot(SnapshotCommand),
+    /// List, create, or delete the databases a store holds
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Add, list, or remove remotes: names for other stores
+    #[command(subcommand)]
+    Remote(RemoteCommand),
+    /// List branches, or create, move, or delete one
+    Branch {
+        /// Branch to create; lists branches if omitted
+        name: Option<String>,
+        /// List where each remote's branches were last seen instead
+        #[arg(short, long, conflicts_with = "name")]
+        remotes: bool,
+        /// Branch, snapshot, or CID to start from (default: the current root)
+        start: Option<String>,
+        /// Delete the branch instead
+        #[arg(short, long, requires = "name", conflicts_with_all = ["start", "force"])]
+        delete: bool,
+        /// Move the branch if it already exists, unless it's protected and
+        /// this would drop commits
+        #[arg(short, long, requires = "name")]
+        force: bool,
+        /// Protect the branch instead: it can then only move forward, and
+        /// can't be deleted
+        #[arg(long, requires = "name", conflicts_with_all = ["start", "force", "delete", "unprotect"])]
+        protect: bool,
+        /// Stop protecting the branch instead
+        #[arg(long, requires = "name", conflicts_with_all = ["start", "force", "delete"])]
+        unprotect: bool,
+    },
+    /// Point the current root at a branch, snapshot, or CID; `<rev>@{<time>}`
+    /// picks the version of `rev` as of a time
+    Checkout {
+        rev: String,
+    },
+    /// Record the current root as a commit on a branch and move the branch to it
+    Commit {
+        branch: String,
+        /// Describe the commit
+        #[arg(short, long, default_value = "")]
+        message: String,
+        /// Who is making the commit
+        #[arg(long, env = "CRAFTSQL_AUTHOR", default_value = "")]
+        author: String,
+    },
+    /// Print the current root CID
+    Root,
+    /// Show the commits behind a version, newest first
+    Log {
+        /// Branch, snapshot, or CID to start from (default: the checked-out commit)
+        rev: Option<String>,
+        /// Show at most this many commits
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+        /// Show only commits made at or after this time (Unix seconds or
+        /// YYYY-MM-DD [HH:MM[:SS]], UTC)
+        #[arg(long)]
+        since: Option<String>,
+        /// One line per commit: its CID and the first line of its message
+        #[arg(long)]
+        oneline: bool,
+    },
+    /// Show the history of root changes
+    Reflog {
+        /// Show at most this many entries
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+    },
+    /// Copy a database, with all its pages, branches, and snapshots, to
+    /// another store. Re-run to resume an interrupted clone.
+    Clone {
+        /// Store to copy from (same forms as --store)
+        src: String,
+        /// Store to copy into
+        dst: String,
+        /// Copy only these tables (comma-separated) and their indexes,
+        /// leaving the rest empty
+        #[arg(long, value_delimiter = ',')]
+        tables: Vec<String>,
+        /// Copy only the latest version of each branch, without its history
+        #[arg(long, conflicts_with = "tables")]
+        shallow: bool,
+    },
+    /// Send a branch to another store, copying only the pages it lacks
+    Push {
+        /// Remote, or store to push to (same forms as --store)
+        remote: String,
+        branch: String,
+        /// Overwrite the remote branch even if it moved since the last sync
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Fetch a branch from another store, copying only the pages we lack
+    Pull {
+        /// Remote, or store to pull from (same forms as --store)
+        remote: String,
+        branch: String,
+        /// Overwrite our branch even if it moved since the last sync
+        #[arg(short, long)]
+        force: bool,
+        /// Fetch only these tables (comma-separated) and their indexes,
+        /// leaving the rest empty
+        #[arg(long, value_delimiter = ',')]
+        tables: Vec<String>,
+        /// Fetch only the branch's latest version, without its history
+        #[arg(long, conflicts_with = "tables")]
+        shallow: bool,
+    },
+    /// Fetch the history a shallow clone or pull left out
+    Unshallow {
+        /// Remote, or store to fetch from (same forms as --store)
+        remote: String,
+    },
+    /// Fetch branches from another store without moving ours; each is then
+    /// `<remote>/<branch>`
+    Fetch {
+        /// Remote, or store to fetch from (same forms as --store)
+        remote: String,
+        /// Branches to fetch (default: all)
+        branches: Vec<String>,
+    },
+    /// Keep one store a copy of another: copy new pages and move its
+    /// branches, snapshots, and root whenever the source changes
+    Replicate {
+        /// Store to copy from (same forms as --store)
+        #[arg(long)]
+        from: String,
+        /// Store to copy into
+        #[arg(long)]
+        to: String,
+        /// Seconds between checks of the source
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+        /// Bring the copy up to date once and exit
+        #[arg(long)]
+        once: bool,
+    },
+    /// Write branches and snapshots, their history, and their pages to one
+    /// compressed file
+    Backup {
+        /// File to write, conventionally ending in `.craftsql`
+        file: PathBuf,
+        /// Branches and snapshots to include (default: all, and the current root)
+        refs: Vec<String>,
+    },
+    /// Load a file written by `backup` into the store
+    Restore {
+        file: PathBuf,
+        /// Move branches, snapshots, and the root even if they point elsewhere
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Write a branch, snapshot, or CID out as an ordinary SQLite file
+    Export {
+        /// File to write
+        file: PathBuf,
+        /// Branch, snapshot, or CID to export (default: the current root)
+        #[arg(long)]
+        from: Option<String>,
+    },
+    /// Store an ordinary SQLite file as a new commit and check it out
+    Import {
+        file: PathBuf,
+        /// Start a new branch at the imported commit
+        #[arg(short, long)]
+        branch: Option<String>,
+    },
+    /// Print changes to the current root, branches, and snapshots as they happen
+    Watch {
+        /// Exit after this many changes
+        #[arg(short = 'n', long, conflicts_with = "interval")]
+        max_count: Option<usize>,
+        /// Instead, snapshot the current root this often (seconds, or with
+        /// an s, m, h, or d suffix) while it keeps changing, until stopped
+        #[arg(long, value_parser = interval)]
+        interval: Option<Duration>,
+        /// Keep only this many of the newest automatic snapshots
+        #[arg(long, requires = "interval")]
+        keep: Option<usize>,
+    },
+    /// Show how many pages each table and index takes
+    Tables {
+        /// Branch, snapshot, or CID to look at (default: the current root)
+        #[arg(long)]
+        at: Option<String>,
+        /// Also count the pages of each that changed since this version
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Show how much storage each branch and snapshot takes, and how much
+    /// of it is theirs alone
+    Du {
+        /// Show each of the store's databases instead
+        #[arg(long)]
+        databases: bool,
+    },
+    /// Delete pages no branch, snapshot, root, or recent history entry of
+    /// any database needs
+    Gc {
+        /// Keep the roots of history entries newer than this many days
+        #[arg(long, default_value_t = gc::DEFAULT_GRACE.as_secs() / 86_400)]
+        grace_days: u64,
+        /// Report what would be removed without removing it
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Check that every page the store's refs need is there and intact,
+    /// and that SQLite finds each database they point at sound
+    Fsck {
+        /// Only check pages and refs, not the databases with SQLite
+        #[arg(long)]
+        pages_only: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show rows inserted, deleted, and updated between two versions
+    #[cfg(feature = "sql")]
+    Diff {
+        /// Branch, snapshot, or CID to compare from
+        old: String,
+        /// Branch, snapshot, or CID to compare to
+        new: String,
+        /// Print the diff as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Merge the changes a branch, snapshot, or CID made since it split
+    /// off into the current root
+    #[cfg(feature = "sql")]
+    Merge {
+        rev: String,
+        /// Version both sides started from (default: the last commit both
+        /// the checked-out commit and `rev` descend from)
+        #[arg(long)]
+        base: Option<String>,
+        /// How to settle rows both sides changed: ours, theirs, or fail
+        #[arg(long, default_value = "fail")]
+        conflict: craftsql_diff::ConflictPolicy,
+    },
+    /// Replay a branch's commits on top of a branch, snapshot, or CID, for
+    /// a linear history instead of a merge
+    #[cfg(feature = "sql")]
+    Rebase {
+        branch: String,
+        onto: String,
+        /// How to settle rows both sides changed: ours, theirs, or fail
+        #[arg(long, default_value = "fail")]
+        conflict: craftsql_diff::ConflictPolicy,
+    },
+    /// Query a version interactively with SQL; `.help` lists the shell's
+    /// own commands
+    #[cfg(feature = "shell")]
+    Shell {
+        /// Branch, snapshot, or CID to open (default: the current root)
+        #[arg(short, long)]
+        branch: Option<String>,
+    },
+    /// Serve branches and snapshots as read-only `.sqlite` files under a
+    /// directory, until unmounted
+    #[cfg(all(unix, feature = "fuse"))]
+    Mount {
+        mountpoint: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    /// Snapshot the current root (or another branch, snapshot, or CID)
+    Create {
+        name: String,
+        #[arg(long)]
+        from: Option<String>,
+    },
+    /// List snapshots
+    List,
+    /// Delete a snapshot
+    Delete {
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RemoteCommand {
+    /// Name a store to push to, pull from, and fetch
+    Add {
+        name: String,
+        /// Where the store is (same forms as --store)
+        location: String,
+    },
+    /// List remotes
+    List,
+    /// Remove a remote, and forget where its branches were
+    Remove {
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommand {
+    /// List databases
+    List,
+    /// Create an empty database
+    Create {
+        name: String,
+    },
+    /// Delete a database's root, branches, and snapshots; `gc` then
+    /// removes the pages no other database shares
+    Delete {
+        name: String,
+    },
+}
+
+fn run(cli: Cli, out: &mut dyn Write) -> Result<()> {
+    // The whole store, for commands that span its databases
+    let shared_store = || -> Result<Box<dyn PageStore>> {
+        let spec = StoreSpec::parse(cli.store.as_deref().ok_or(Error::NoStore)?);
+        spec.open(cli.cache_dir.as_deref())
+    };
+    let store = || -> Result<Box<dyn PageStore>> {
+        let store = shared_store()?;
+        match cli.database.as_deref() {
+            Some(name) => Ok(Box::new(NamespacedPageStore::new(Arc::<dyn PageStore>::from(store), name)?)),
+            None => Ok(store),
+        }
+    };
+    match cli.command {
+        Command::Db(DbCommand::List) => commands::db_list(shared_store()?.as_ref(), out),
+        Command::Db(DbCommand::Create { name }) => commands::db_create(shared_store()?.as_ref(), &name, out),
+        Command::Db(DbCommand::Delete { name }) => commands::db_delete(shared_store()?.as_ref(), &name, out),
+        Command::Snapshot(SnapshotCommand::Create { name, from }) => {
+            commands::snapshot_create(store()?.as_ref(), &name, from.as_deref(), out)
+        }
+        Command::Snapshot(SnapshotCommand::List) => commands::snapshot_list(store()?.as_ref(), out),
+        Command::Snapshot(SnapshotCommand::Delete { name }) => {
+            commands::snapshot_delete(store()?.as_ref(), &name, out)
+        }
+        Command::Remote(RemoteCommand::Add { name, location }) => {
+            commands::remote_add(store()?.as_ref(), &name, &location, out)
+        }
+        Command::Remote(RemoteCommand::List) => commands::remote_list(store()?.as_ref(), out),
+        Command::Remote(RemoteCommand::Remove { name }) => commands::remote_remove(store()?.as_ref(), &name, out),
+        Command::Branch { remotes: true, .. } => commands::branch_list_remotes(store()?.as_ref(), out),
+        Command::Branch { name: None, .. } => commands::branch_list(store()?.as_ref(), out),
+        Command::Branch { name: Some(name), delete: true, .. } => {
+            commands::branch_delete(store()?.as_ref(), &name, out)
+        }
+        Command::Branch { name: Some(name), protect, unprotect, .. } if protect || unprotect => {
+            commands::branch_protect(store()?.as_ref(), &name, protect, out)
+        }
+        Command::Branch { name: Some(name), start, force, .. } => {
+            commands::branch_create(store()?.as_ref(), &name, start.as_deref(), force, out)
+        }
+        Command::Checkout { rev } => commands::checkout(store()?.as_ref(), &rev, out),
+        Command::Commit { branch, message, author } => {
+            commands::commit(store()?.as_ref(), &branch, &message, &author, out)
+        }
+        Command::Root => commands::root(store()?.as_ref(), out),
+        Command::Log { rev, max_count, since, oneline } => {
+            commands::log(store()?.as_ref(), rev.as_deref(), max_count, since.as_deref(), oneline, out)
+        }
+        Command::Reflog { max_count } => commands::reflog(store()?.as_ref(), max_count, out),
+        Command::Clone { src, dst, tables, shallow } => {
+            let src = StoreSpec::parse(&src).open(None)?;
+            let dst = StoreSpec::parse(&dst).open(None)?;
+            commands::clone(src.as_ref(), dst.as_ref(), &tables, shallow, out, &mut std::io::stderr())
+        }
+        Command::Push { remote, branch, force } => {
+            let store = store()?;
+            let (name, spec) = remotes::lookup(store.as_ref(), &remote)?;
+            let remote = spec.open(None)?;
+            let remote = Remote { name: &name, store: remote.as_ref() };
+            commands::push(store.as_ref(), remote, &branch, force, out, &mut std::io::stderr())
+        }
+        Command::Pull { remote, branch, force, tables, shallow } => {
+            let store = store()?;
+            let (name, spec) = remotes::lookup(store.as_ref(), &remote)?;
+            let remote = spec.open(None)?;
+            let remote = Remote { name: &name, store: remote.as_ref() };
+            let mode = match (shallow, tables.is_empty()) {
+                (true, _) => PullMode::Shallow,
+                (false, true) => PullMode::Full,
+                (false, false) => PullMode::Tables(&tables),
+            };
+            commands::pull(store.as_ref(), remote, &branch, force, mode, out, &mut std::io::stderr())
+        }
+        Command::Unshallow { remote } => {
+            let store = store()?;
+            let (_, spec) = remotes::lookup(store.as_ref(), &remote)?;
+            let remote = spec.open(None)?;
+            commands::unshallow(store.as_ref(), remote.as_ref(), out, &mut std::io::stderr())
+        }
+        Command::Fetch { remote, branches } => {
+            let store = store()?;
+            let (name, spec) = remotes::lookup(store.as_ref(), &remote)?;
+            let remote = spec.open(None)?;
+            let remote = Remote { name: &name, store: remote.as_ref() };
+            commands::fetch(store.as_ref(), remote, &branches, out, &mut std::io::stderr())
+        }
+        Command::Replicate { from, to, interval, once } => {
+            let spec = StoreSpec::parse(&from);
+            let src = spec.open(None)?;
+            let source = Remote { name: &spec.id(), store: src.as_ref() };
+            let dst = StoreSpec::parse(&to).open(None)?;
+            let interval = (!once).then(|| Duration::from_secs(interval));
+            commands::replicate(source, dst.as_ref(), interval, out, &mut std::io::stderr())
+        }
+        Command::Backup { file, refs } => commands::backup(store()?.as_ref(), &file, &refs, out),
+        Command::Restore { file, force } => commands::restore(store()?.as_ref(), &file, force, out),
+        Command::Export { file, from } => commands::export(store()?.as_ref(), from.as_deref(), &file, out),
+        Command::Import { file, branch } => commands::import(store()?.as_ref(), &file, branch.as_deref(), out),
+        Command::Watch { interval: Some(interval), keep, .. } => {
+            commands::autosnapshot(store()?.as_ref(), interval, keep, out, &mut std::io::stderr())
+        }
+        Command::Watch { max_count, .. } => commands::watch(store()?.as_ref(), max_count, out),
+        Command::Tables { at, since } => commands::tables(store()?.as_ref(), at.as_deref(), since.as_deref(), out),
+        Command::Du { databases: true } => commands::du(shared_store()?.as_ref(), true, out),
+        Command::Du { databases: false } => commands::du(store()?.as_ref(), false, out),
+        Command::Gc { grace_days, dry_run } => {
+            commands::gc(shared_store()?.as_ref(), Duration::from_secs(grace_days * 86_400), dry_run, out)
+        }
+        Command::Fsck { pages_only, json } => commands::fsck(shared_store()?, pages_only, json, out),
+        #[cfg(feature = "sql")]
+        Command::Diff { old, new, json } => commands::diff(store()?, &old, &new, json, out),
+        #[cfg(feature = "sql")]
+        Command::Merge { rev, base, conflict } => commands::merge(store()?, &rev, base.as_deref(), conflict, out),
+        #[cfg(feature = "sql")]
+        Command::Rebase { branch, onto, conflict } => commands::rebase(store()?, &branch, &onto, conflict, out),
+        #[cfg(feature = "shell")]
+        Command::Shell { branch } => craftsql_cli::shell::run(store()?.into(), branch.as_deref(), out),
+        #[cfg(all(unix, feature = "fuse"))]
+        Command::Mount { mountpoint } => commands::mount(store()?, &mountpoint, out),
+    }
+}
+
+fn interval(s: &str) -> std::result::Result<Duration, String> {
+    autosnap::parse_interval(s).ok_or_else(|| format!("invalid interval {:?}: use e.g. 90, 90s, 10m, 2h, or 1d", s))
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse(), &mut std::io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("craftsql: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["craftsql", "-s", "db", "branch", "-d", "old"]).unwrap();
+        assert!(matches!(cli.command, Command::Branch { delete: true, .. }));
+        assert!(Cli::try_parse_from(["craftsql", "-s", "db", "branch", "-d"]).is_err());
+        assert!(Cli::try_parse_from(["craftsql", "-s", "db", "branch", "-r", "new"]).is_err());
+        let cli = Cli::try_parse_from(["craftsql", "branch", "--protect", "main"]).unwrap();
+        assert!(matches!(cli.command, Command::Branch { protect: true, unprotect: false, .. }));
+        assert!(Cli::try_parse_from(["craftsql", "branch", "--protect", "-f", "main"]).is_err());
+
+        let cli = Cli::try_parse_from(["craftsql", "watch", "--interval", "10m", "--keep", "48"]).unwrap();
+        assert!(matches!(cli.command, Command::Watch { interval: Some(i), keep: Some(48), .. } if i == Duration::from_secs(600)));
+        assert!(Cli::try_parse_from(["craftsql", "watch", "--keep", "48"]).is_err());
+        assert!(Cli::try_parse_from(["craftsql", "watch", "--interval", "ten"]).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Branches and snapshots on top of the store's named roots.
+//!
+//! Both are named roots, pointing at a commit or directly at a page table.
+//! Snapshots carry a [`SNAPSHOT_PREFIX`] so the two can be listed apart;
+//! names starting with `.` are reserved for the CLI's own bookkeeping (see
+//! [`crate::history`], [`HEAD_REF`]). Where a remote's branches were last
+//! seen is bookkeeping too, read as `<remote>/<branch>` (see
+//! [`crate::remotes`]).
+
+use craftsql_core::{commit_at, Cid, PageStore};
+use craftsql_store_local::sanitize_ref_name;
+
+use crate::history::parse_time;
+use crate::{Error, Result};
+
+/// Prefix of the named roots that hold snapshots.
+pub const SNAPSHOT_PREFIX: &str = "snapshot.";
+
+/// Optional prefix of a remote branch's name, as in `remotes/origin/main`.
+pub const REMOTES_PREFIX: &str = "remotes/";
+
+/// Named root holding the commit last checked out or made; the parent of
+/// the next commit.
+pub const HEAD_REF: &str = ".head";
+
+/// Named root holding the commit last merged in, until the merge is
+/// committed; the next commit's second parent.
+pub const MERGE_HEAD_REF: &str = ".merge-head";
+
+/// Check that `name` is usable as a branch or snapshot name.
+///
+/// Names must survive [`sanitize_ref_name`] unchanged so that every backend
+/// stores and lists them under the same name.
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || is_reserved(name)
+        || name.starts_with(SNAPSHOT_PREFIX)
+        || sanitize_ref_name(name) != name
+    {
+        return Err(Error::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Named root holding the snapshot `name`.
+pub fn snapshot_ref(name: &str) -> String {
+    format!("{}{}", SNAPSHOT_PREFIX, name)
+}
+
+/// Named roots that are branches: not bookkeeping, snapshots, or the
+/// roots of the store's databases.
+pub fn branches(store: &dyn PageStore) -> Result<Vec<(String, Cid)>> {
+    Ok(store.list_named_roots()?
+        .into_iter()
+        .filter(|(name, _)| !is_reserved(name) && !name.starts_with(SNAPSHOT_PREFIX))
+        .collect())
+}
+
+/// Whether `name` is bookkeeping or belongs to one of the store's databases.
+pub fn is_reserved(name: &str) -> bool {
+    name.starts_with('.') || name.starts_with(craftsql_namespace::PREFIX)
+}
+
+pub fn snapshots(store: &dyn PageStore) -> Result<Vec<(String, Cid)>> {
+    Ok(store.list_named_roots()?
+        .into_iter()
+        .filter_map(|(name, cid)| Some((name.strip_prefix(SNAPSHOT_PREFIX)?.to_string(), cid)))
+        .collect())
+}
+
+/// Parse a full CID: 64 hex digits, or base32 or base58 (see
+/// [`CidEncoding`](craftsql_core::CidEncoding)).
+pub fn parse_cid(s: &str) -> Option<Cid> {
+    s.parse().ok()
+}
+
+/// Resolve a branch name, snapshot name, remote branch (`origin/main` or
+/// `remotes/origin/main`), or full CID, in that order.
+///
+/// `<rev>@{<time>}` is the newest commit reachable from `rev` made at or
+/// before `time` (see [`parse_time`]).
+pub fn resolve(store: &dyn PageStore, rev: &str) -> Result<Cid> {
+    if let Some((from, time)) = rev.strip_suffix('}').and_then(|rev| rev.rsplit_once("@{")) {
+        let time = parse_time(time).ok_or_else(|| Error::InvalidTime(time.to_string()))?;
+        let from = resolve(store, from)?;
+        return commit_at(store, from, time)?.ok_or_else(|| Error::UnknownRef(rev.to_string()));
+    }
+    if validate_name(rev).is_ok() {
+        if let Some(cid) = store.get_named_root(rev)? {
+            return Ok(cid);
+        }
+        if let Some(cid) = store.get_named_root(&snapshot_ref(rev))? {
+            return Ok(cid);
+        }
+    }
+    if let Some((remote, branch)) = rev.strip_prefix(REMOTES_PREFIX).unwrap_or(rev).split_once('/') {
+        if let Some(cid) = craftsql_sync::remote_branch(store, remote, branch)? {
+            return Ok(cid);
+        }
+    }
+    parse_cid(rev).ok_or_else(|| Error::UnknownRef(rev.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::Commit;
+    use craftsql_store_local::LocalPageStore;
+
+    #[test]
+    fn test_validate_name() {
+        for name in ["main", "v1.2", "feature_x", "2024-01-01"] {
+            assert!(validate_name(name).is_ok(), "{}", name);
+        }
+        for name in ["", ".history", "snapshot.v1", "db.app.root", "a/b", "has space"] {
+            assert!(validate_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        let branch = Cid::from_bytes(b"branch");
+        let snapshot = Cid::from_bytes(b"snapshot");
+        store.set_named_root("main", branch).unwrap();
+        store.set_named_root(&snapshot_ref("v1"), snapshot).unwrap();
+        store.set_named_root(&snapshot_ref("main"), snapshot).unwrap();
+        store.set_named_root("db.app.root", snapshot).unwrap();
+
+        assert_eq!(resolve(&store, "main").unwrap(), branch);
+        assert_eq!(resolve(&store, "v1").unwrap(), snapshot);
+        assert_eq!(resolve(&store, &branch.to_hex()).unwrap(), branch);
+        assert_eq!(resolve(&store, &branch.encode(craftsql_core::CidEncoding::Base58)).unwrap(), branch);
+        assert!(matches!(resolve(&store, "v2"), Err(Error::UnknownRef(_))));
+
+        store.set_named_root(&craftsql_sync::tracking_ref("origin", "main"), snapshot).unwrap();
+        assert_eq!(resolve(&store, "origin/main").unwrap(), snapshot);
+        assert_eq!(resolve(&store, "remotes/origin/main").unwrap(), snapshot);
+        assert!(matches!(resolve(&store, "origin/dev"), Err(Error::UnknownRef(_))));
+
+        assert_eq!(branches(&store).unwrap(), vec![("main".to_string(), branch)]);
+        assert_eq!(snapshots(&store).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_at_time() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        let commit = |time, parents| {
+            let root = Cid::from_bytes(&[time as u8]);
+            Commit { time, ..Commit::new(root, parents, "") }.put(&store).unwrap()
+        };
+        let old = commit(86_400, vec![]);
+        let new = commit(2 * 86_400, vec![old]);
+        store.set_named_root("main", new).unwrap();
+
+        assert_eq!(resolve(&store, "main@{1970-01-02 12:00}").unwrap(), old);
+        assert_eq!(resolve(&store, "main@{172800}").unwrap(), new);
+        assert!(matches!(resolve(&store, "main@{1970-01-01}"), Err(Error::UnknownRef(_))));
+        assert!(matches!(resolve(&store, "main@{soon}"), Err(Error::InvalidTime(_))));
+    }
+}
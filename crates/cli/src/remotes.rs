@@ -0,0 +1,114 @@
+//! Configured remotes: short names for other stores to push to, pull from,
+//! and fetch.
+//!
+//! The configuration is a page mapping each name to a store location (any
+//! form `--store` takes), kept in the [`REMOTES_REF`] named root so that it
+//! travels with the store. A remote's branches are tracked under its name
+//! (see [`craftsql_sync::remote_branch`]) and shown as `<remote>/<branch>`.
+//! Stores given by location instead of by name are tracked under
+//! [`StoreSpec::id`].
+
+use std::collections::BTreeMap;
+
+use craftsql_core::{Page, PageStore, PageStoreError};
+use craftsql_store_local::sanitize_ref_name;
+
+use crate::store::StoreSpec;
+use crate::{Error, Result};
+
+/// Named root pointing at the remotes configuration.
+pub const REMOTES_REF: &str = ".remotes";
+
+/// Check that `name` is usable as a remote name. Unlike branches, remotes
+/// can't contain `.`, which separates them from the branch in tracking refs.
+pub fn validate_remote_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('.') || sanitize_ref_name(name) != name {
+        return Err(Error::InvalidRemoteName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// The configured remotes, name to location.
+pub fn list(store: &dyn PageStore) -> Result<BTreeMap<String, String>> {
+    let Some(cid) = store.get_named_root(REMOTES_REF)? else {
+        return Ok(BTreeMap::new());
+    };
+    let page = store.get(&cid)?;
+    serde_json::from_slice(&page.data)
+        .map_err(|e| PageStoreError::Storage(format!("remotes configuration {}: {}", cid, e)).into())
+}
+
+fn save(store: &dyn PageStore, remotes: &BTreeMap<String, String>) -> Result<()> {
+    if remotes.is_empty() {
+        store.remove_named_root(REMOTES_REF)?;
+        return Ok(());
+    }
+    let data = serde_json::to_vec(remotes).expect("remotes serialization");
+    let cid = store.put(&Page { data })?;
+    store.set_named_root(REMOTES_REF, cid)?;
+    Ok(())
+}
+
+/// Add a remote called `name` for the store at `location`.
+pub fn add(store: &dyn PageStore, name: &str, location: &str) -> Result<()> {
+    validate_remote_name(name)?;
+    let mut remotes = list(store)?;
+    if remotes.contains_key(name) {
+        return Err(Error::Exists(format!("remote {}", name)));
+    }
+    remotes.insert(name.to_string(), location.to_string());
+    save(store, &remotes)
+}
+
+/// Remove the remote called `name` and forget its branches.
+pub fn remove(store: &dyn PageStore, name: &str) -> Result<()> {
+    let mut remotes = list(store)?;
+    if remotes.remove(name).is_none() {
+        return Err(Error::UnknownRemote(name.to_string()));
+    }
+    save(store, &remotes)?;
+    craftsql_sync::remove_remote_branches(store, name)?;
+    Ok(())
+}
+
+/// The name to track `remote` under and where it lives: a configured
+/// remote's, or if there is none by that name, `remote` taken as a location.
+pub fn lookup(store: &dyn PageStore, remote: &str) -> Result<(String, StoreSpec)> {
+    if let Some(location) = list(store)?.get(remote) {
+        return Ok((remote.to_string(), StoreSpec::parse(location)));
+    }
+    let spec = StoreSpec::parse(remote);
+    Ok((spec.id(), spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_local::LocalPageStore;
+
+    #[test]
+    fn test_remotes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = LocalPageStore::new(tmp.path()).unwrap();
+        assert!(list(&store).unwrap().is_empty());
+
+        add(&store, "origin", "tcp://example:4001").unwrap();
+        add(&store, "backup", "/srv/backup").unwrap();
+        assert!(matches!(add(&store, "origin", "/elsewhere"), Err(Error::Exists(_))));
+        for name in ["", "a.b", "a/b", ".hidden"] {
+            assert!(matches!(add(&store, name, "/x"), Err(Error::InvalidRemoteName(_))), "{:?}", name);
+        }
+        let names: Vec<_> = list(&store).unwrap().into_keys().collect();
+        assert_eq!(names, ["backup", "origin"]);
+
+        let (name, spec) = lookup(&store, "origin").unwrap();
+        assert_eq!((name.as_str(), spec.id()), ("origin", StoreSpec::parse("tcp://example:4001").id()));
+        let (name, _) = lookup(&store, "/srv/other").unwrap();
+        assert_eq!(name, StoreSpec::parse("/srv/other").id());
+
+        remove(&store, "origin").unwrap();
+        remove(&store, "backup").unwrap();
+        assert!(matches!(remove(&store, "origin"), Err(Error::UnknownRemote(_))));
+        assert_eq!(store.get_named_root(REMOTES_REF).unwrap(), None);
+    }
+}
@@ -0,0 +1,227 @@
+//! `craftsql shell`: an interactive SQL prompt over one version of a
+//! database.
+//!
+//! The version (a branch, snapshot, or CID, or else the current root) is
+//! opened read-only through the VFS, so exploring it changes nothing. Input
+//! is gathered until a line ends with `;`, then run; lines starting with `.`
+//! are the shell's own commands (see [`HELP`]). Output is in the `sqlite3`
+//! shell's default list mode: one row per line, columns separated by `|`.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use craftsql_core::{page_table_root, Cid, PageStore};
+use rusqlite::fallible_iterator::FallibleIterator;
+use rusqlite::types::ValueRef;
+use rusqlite::{Batch, Connection};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::commands;
+use crate::refs::resolve;
+use crate::{Error, Result};
+
+/// The shell's own commands.
+pub const HELP: &str = "\
+.branch NAME     start a branch at the open version
+.diff REV        show the rows changed from the open version to REV
+.help            show this
+.open REV        open another branch, snapshot, or CID
+.quit            leave the shell
+.schema [TABLE]  show the statements that created tables and indexes
+.snapshot NAME   snapshot the open version
+.tables          list the tables
+";
+
+/// An open version, and the statement being typed into it.
+pub struct Shell {
+    store: Arc<dyn PageStore>,
+    /// The version as it was named, for the prompt.
+    name: String,
+    /// What the name resolved to: a commit or a page table.
+    target: Cid,
+    /// The page table opened.
+    root: Cid,
+    db: Connection,
+    /// Lines of a statement that doesn't end with `;` yet.
+    pending: String,
+}
+
+impl Shell {
+    /// Open `rev`, or the current root if there is none.
+    pub fn open(store: Arc<dyn PageStore>, rev: Option<&str>) -> Result<Self> {
+        let (name, target, root, db) = open(&store, rev)?;
+        Ok(Self { store, name, target, root, db, pending: String::new() })
+    }
+
+    /// The prompt for the next line: the open version's name, or a
+    /// continuation marker in the middle of a statement.
+    pub fn prompt(&self) -> String {
+        if self.pending.is_empty() {
+            format!("{}> ", self.name)
+        } else {
+            format!("{}...> ", " ".repeat(self.name.len().saturating_sub(3)))
+        }
+    }
+
+    /// Drop a statement typed so far.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Handle one line of input; `false` once the shell should exit. SQL
+    /// errors and failed commands are reported on `out`, not returned.
+    pub fn line(&mut self, line: &str, out: &mut dyn Write) -> Result<bool> {
+        let result = if self.pending.is_empty() && line.trim_start().starts_with('.') {
+            self.command(line.trim(), out)
+        } else {
+            self.pending.push_str(line);
+            self.pending.push('\n');
+            if !self.pending.trim_end().ends_with(';') {
+                return Ok(true);
+            }
+            let sql = std::mem::take(&mut self.pending);
+            self.run_sql(&sql, out).map(|()| true)
+        };
+        match result {
+            Err(Error::Output(e)) => Err(Error::Output(e)),
+            Err(e) => {
+                writeln!(out, "error: {}", e)?;
+                Ok(true)
+            }
+            Ok(go_on) => Ok(go_on),
+        }
+    }
+
+    fn command(&mut self, line: &str, out: &mut dyn Write) -> Result<bool> {
+        let mut words = line.split_whitespace();
+        let store = self.store.as_ref();
+        match (words.next().unwrap_or_default(), words.next()) {
+            (".quit" | ".exit", _) => return Ok(false),
+            (".help", _) => write!(out, "{}", HELP)?,
+            (".tables", _) => self.run_sql(
+                "SELECT name FROM sqlite_schema WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;",
+                out,
+            )?,
+            (".schema", table) => {
+                let filter = table.map_or(String::new(), |table| format!("AND tbl_name = '{}'", table.replace('\'', "''")));
+                self.run_sql(
+                    &format!("SELECT sql || ';' FROM sqlite_schema WHERE sql IS NOT NULL {} ORDER BY rowid;", filter),
+                    out,
+                )?
+            }
+            (".open", Some(rev)) => {
+                (self.name, self.target, self.root, self.db) = open(&self.store, Some(rev))?;
+            }
+            (".snapshot", Some(name)) => commands::snapshot_create(store, name, Some(&self.target.to_hex()), out)?,
+            (".branch", Some(name)) => commands::branch_create(store, name, Some(&self.target.to_hex()), false, out)?,
+            (".diff", Some(rev)) => {
+                let new = page_table_root(store, &resolve(store, rev)?)?;
+                craftsql_diff::diff_roots(Arc::clone(&self.store), self.root, new)?.write_text(out)?;
+            }
+            _ => writeln!(out, "unknown command or missing argument: {} (see .help)", line)?,
+        }
+        Ok(true)
+    }
+
+    /// Run every statement in `sql`, printing any rows they return.
+    fn run_sql(&self, sql: &str, out: &mut dyn Write) -> Result<()> {
+        let mut batch = Batch::new(&self.db, sql);
+        while let Some(mut statement) = batch.next().map_err(sql_error)? {
+            let columns = statement.column_count();
+            let mut rows = statement.raw_query();
+            while let Some(row) = rows.next().map_err(sql_error)? {
+                let mut values = Vec::with_capacity(columns);
+                for i in 0..columns {
+                    values.push(format_value(row.get_ref(i).map_err(sql_error)?));
+                }
+                writeln!(out, "{}", values.join("|"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resolve and open `rev`, or the current root: its name, what it resolved
+/// to, its page table, and the connection.
+fn open(store: &Arc<dyn PageStore>, rev: Option<&str>) -> Result<(String, Cid, Cid, Connection)> {
+    let (name, target) = match rev {
+        Some(rev) => (rev.to_string(), resolve(store.as_ref(), rev)?),
+        None => ("root".to_string(), store.current_root()?.ok_or(Error::NoRoot)?),
+    };
+    let root = page_table_root(store.as_ref(), &target)?;
+    let db = craftsql_diff::open_root(Arc::clone(store), root)?;
+    Ok((name, target, root, db))
+}
+
+fn sql_error(e: rusqlite::Error) -> Error {
+    craftsql_diff::DiffError::from(e).into()
+}
+
+fn format_value(value: ValueRef<'_>) -> String {
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned(),
+        ValueRef::Blob(blob) => format!("x'{}'", hex::encode(blob)),
+    }
+}
+
+/// Run the shell on the terminal until `.quit` or end of input.
+pub fn run(store: Arc<dyn PageStore>, rev: Option<&str>, out: &mut dyn Write) -> Result<()> {
+    let mut shell = Shell::open(store, rev)?;
+    let mut editor = DefaultEditor::new()?;
+    loop {
+        match editor.readline(&shell.prompt()) {
+            Ok(line) => {
+                editor.add_history_entry(line.as_str())?;
+                if !shell.line(&line, out)? {
+                    return Ok(());
+                }
+            }
+            // Ctrl-C abandons the statement being typed, as in sqlite3
+            Err(ReadlineError::Interrupted) => shell.clear(),
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::import_sqlite;
+    use craftsql_store_local::LocalPageStore;
+
+    fn run_lines(shell: &mut Shell, lines: &[&str]) -> String {
+        let mut out = Vec::new();
+        for line in lines {
+            assert!(shell.line(line, &mut out).unwrap());
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_shell() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store: Arc<dyn PageStore> = Arc::new(LocalPageStore::new(&tmp.path().join("store")).unwrap());
+        let path = tmp.path().join("db.sqlite");
+        let db = Connection::open(&path).unwrap();
+        db.execute_batch("CREATE TABLE t (x, y); INSERT INTO t VALUES (1, 'one'), (2, NULL);").unwrap();
+        drop(db);
+        let imported = import_sqlite(store.as_ref(), &path, "import").unwrap();
+        store.set_named_root("main", imported.commit).unwrap();
+
+        let mut shell = Shell::open(Arc::clone(&store), Some("main")).unwrap();
+        assert_eq!(shell.prompt(), "main> ");
+        assert_eq!(run_lines(&mut shell, &["SELECT x, y", "FROM t ORDER BY x;"]), "1|one\n2|\n");
+        assert_eq!(run_lines(&mut shell, &[".tables"]), "t\n");
+        assert!(run_lines(&mut shell, &["DELETE FROM t;"]).starts_with("error: "));
+        assert!(run_lines(&mut shell, &[".open nope"]).starts_with("error: "));
+
+        run_lines(&mut shell, &[".snapshot v1"]);
+        assert_eq!(store.get_named_root("snapshot.v1").unwrap(), Some(imported.commit));
+        assert!(!shell.line(".quit", &mut Vec::new()).unwrap());
+    }
+}
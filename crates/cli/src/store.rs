@@ -0,0 +1,90 @@
+//! Opening a store from a command-line location.
+
+use std::path::{Path, PathBuf};
+
+use craftsql_core::{Cid, PageStore, ProtectedStore};
+use craftsql_objbridge::DaemonBackend;
+use craftsql_objstore::CraftObjPageStore;
+use craftsql_store_local::LocalPageStore;
+
+use crate::Result;
+
+/// Where a store lives, parsed from a `--store` argument.
+///
+/// - `unix:<path>` — CraftOBJ daemon on a Unix socket
+/// - `tcp://<host:port>` — CraftOBJ daemon over TCP
+/// - `http://<host:port>/<path>` — CraftOBJ daemon over HTTP
+/// - anything else — a local store directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreSpec {
+    Local(PathBuf),
+    Unix(String),
+    Tcp(String),
+    Http(String),
+}
+
+impl StoreSpec {
+    pub fn parse(spec: &str) -> Self {
+        if let Some(path) = spec.strip_prefix("unix:") {
+            StoreSpec::Unix(path.to_string())
+        } else if let Some(addr) = spec.strip_prefix("tcp://") {
+            StoreSpec::Tcp(addr.to_string())
+        } else if spec.starts_with("http://") {
+            StoreSpec::Http(spec.to_string())
+        } else {
+            StoreSpec::Local(PathBuf::from(spec))
+        }
+    }
+
+    /// Open the store, holding every ref update to its branch protection
+    /// (see [`ProtectedStore`]). Daemon-backed stores keep their local
+    /// cache in `cache_dir`, or in a per-location directory under the
+    /// system temp dir if none is given.
+    pub fn open(&self, cache_dir: Option<&Path>) -> Result<Box<dyn PageStore>> {
+        let backend = match self {
+            StoreSpec::Local(dir) => return Ok(Box::new(ProtectedStore::new(LocalPageStore::new(dir)?))),
+            StoreSpec::Unix(path) => DaemonBackend::new(path),
+            StoreSpec::Tcp(addr) => DaemonBackend::tcp(addr),
+            StoreSpec::Http(url) => DaemonBackend::http(url),
+        };
+        let cache_dir = match cache_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => self.default_cache_dir(),
+        };
+        Ok(Box::new(ProtectedStore::new(CraftObjPageStore::new(&cache_dir, backend)?)))
+    }
+
+    /// Short stable identifier for this location, usable in ref names.
+    pub fn id(&self) -> String {
+        let location = match self {
+            StoreSpec::Local(dir) => format!("{:?}", std::fs::canonicalize(dir).unwrap_or_else(|_| dir.clone())),
+            other => format!("{:?}", other),
+        };
+        let location = Cid::from_bytes(location.as_bytes());
+        location.to_hex()[..16].to_string()
+    }
+
+    fn default_cache_dir(&self) -> PathBuf {
+        std::env::temp_dir().join("craftsql-cache").join(self.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_store_spec() {
+        assert_eq!(StoreSpec::parse("./db"), StoreSpec::Local(PathBuf::from("./db")));
+        assert_eq!(StoreSpec::parse("unix:/tmp/craftobj.sock"), StoreSpec::Unix("/tmp/craftobj.sock".into()));
+        assert_eq!(StoreSpec::parse("tcp://10.0.0.2:7000"), StoreSpec::Tcp("10.0.0.2:7000".into()));
+        assert_eq!(
+            StoreSpec::parse("http://node:8080/rpc"),
+            StoreSpec::Http("http://node:8080/rpc".into())
+        );
+        assert_ne!(
+            StoreSpec::parse("unix:/a.sock").default_cache_dir(),
+            StoreSpec::parse("unix:/b.sock").default_cache_dir()
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Branch update rules, the same over every backend.
+//!
+//! [`fast_forward`] moves a branch only along its history: to a commit its
+//! current one is an ancestor of. [`force_update`] moves it anywhere,
+//! unless the branch is protected, when only fast-forwards are allowed and
+//! [`remove_branch`] refuses too. Which branches are protected is stored in
+//! the store itself, in the page [`PROTECTED_REF`] names, so every client
+//! of the store goes by the same list.
+//!
+//! Those functions only hold callers that go through them. A
+//! [`ProtectedStore`] holds every ref update to the protection rules, so
+//! code setting and removing named roots directly can't get around them.
+
+use std::collections::BTreeSet;
+
+use crate::{is_ancestor, Cid, CommitMeta, Page, PageStore, PageStoreError, Result, RootSignal};
+
+/// Named root of the page listing the protected branches, one per line.
+pub const PROTECTED_REF: &str = ".protected";
+
+/// The protected branches.
+pub fn protected_branches(store: &dyn PageStore) -> Result<BTreeSet<String>> {
+    let Some(cid) = store.get_named_root(PROTECTED_REF)? else {
+        return Ok(BTreeSet::new());
+    };
+    let page = store.get(&cid)?;
+    let list = std::str::from_utf8(&page.data)
+        .map_err(|_| PageStoreError::Storage(format!("protected branch list {} is not text", cid)))?;
+    Ok(list.lines().map(str::to_string).collect())
+}
+
+/// Protect `branch`, or stop protecting it. Returns whether that changed
+/// anything.
+pub fn set_protected(store: &dyn PageStore, branch: &str, protected: bool) -> Result<bool> {
+    let mut branches = protected_branches(store)?;
+    let changed = if protected { branches.insert(branch.to_string()) } else { branches.remove(branch) };
+    if !changed {
+        return Ok(false);
+    }
+    if branches.is_empty() {
+        store.remove_named_root(PROTECTED_REF)?;
+        return Ok(true);
+    }
+    let data = branches.into_iter().collect::<Vec<_>>().join("\n").into_bytes();
+    let cid = store.put(&Page { data })?;
+    store.set_named_root(PROTECTED_REF, cid)?;
+    Ok(true)
+}
+
+/// Whether moving a branch from `old` to `new` is a fast-forward: it's
+/// new, or `old` is `new` or one of its ancestors.
+pub fn is_fast_forward(store: &dyn PageStore, old: Option<Cid>, new: Cid) -> Result<bool> {
+    match old {
+        Some(old) => is_ancestor(store, old, new),
+        None => Ok(true),
+    }
+}
+
+/// Move `branch` to `new` if that's a fast-forward.
+pub fn fast_forward(store: &dyn PageStore, branch: &str, new: Cid) -> Result<()> {
+    if let Some(old) = store.get_named_root(branch)? {
+        if !is_ancestor(store, old, new)? {
+            return Err(PageStoreError::NonFastForward { branch: branch.to_string(), old, new });
+        }
+    }
+    store.set_named_root(branch, new)
+}
+
+/// Move `branch` to `new`, fast-forward or not, unless it's protected and
+/// this isn't a fast-forward. Returns whether it was one.
+pub fn force_update(store: &dyn PageStore, branch: &str, new: Cid) -> Result<bool> {
+    let old = store.get_named_root(branch)?;
+    let fast_forward = is_fast_forward(store, old, new)?;
+    if !fast_forward && protected_branches(store)?.contains(branch) {
+        return Err(PageStoreError::Protected(branch.to_string()));
+    }
+    store.set_named_root(branch, new)?;
+    Ok(fast_forward)
+}
+
+/// Delete `branch` unless it's protected. Returns whether it existed.
+pub fn remove_branch(store: &dyn PageStore, branch: &str) -> Result<bool> {
+    if protected_branches(store)?.contains(branch) {
+        return Err(PageStoreError::Protected(branch.to_string()));
+    }
+    store.remove_named_root(branch)
+}
+
+/// A store refusing any move of a protected branch that isn't a
+/// fast-forward, and its removal, with [`PageStoreError::Protected`], however
+/// the caller goes about it.
+pub struct ProtectedStore<S> {
+    inner: S,
+}
+
+impl<S: PageStore> ProtectedStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Refuse moving `name` to `new` if it's protected and that isn't a
+    /// fast-forward.
+    fn check_move(&self, name: &str, new: Cid) -> Result<()> {
+        if !protected_branches(&self.inner)?.contains(name) {
+            return Ok(());
+        }
+        let old = self.inner.get_named_root(name)?;
+        if old != Some(new) && !is_fast_forward(&self.inner, old, new)? {
+            return Err(PageStoreError::Protected(name.to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl<S: PageStore> PageStore for ProtectedStore<S> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        self.inner.get(cid)
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        self.inner.put(page)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        self.inner.put_many(pages)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        self.inner.has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        self.inner.update_root(new_root)
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        self.inner.update_root_with_meta(new_root, meta)
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        self.inner.current_root()
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.check_move(name, cid)?;
+        self.inner.set_named_root(name, cid)
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        self.check_move(name, cid)?;
+        self.inner.set_named_root_with_meta(name, cid, meta)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.inner.get_named_root(name)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        if protected_branches(&self.inner)?.contains(name) {
+            return Err(PageStoreError::Protected(name.to_string()));
+        }
+        self.inner.remove_named_root(name)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        self.inner.list_named_roots()
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        self.inner.list_pages()
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.inner.delete_page(cid)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        self.inner.delete_many(cids)
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        self.inner.root_signal()
+    }
+}
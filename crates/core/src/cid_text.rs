@@ -0,0 +1,202 @@
+//! CIDs as text: hex, base32, and base58btc, told apart by length.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use crate::Cid;
+
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+const BASE58: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Characters in a hex or base32 CID. Base58 ones vary: each leading zero
+/// byte is a `1`, so they run from 32 to 44 characters.
+const HEX_LEN: usize = 64;
+const BASE32_LEN: usize = 52;
+
+/// A text encoding of a [`Cid`]; see [`Cid::encode`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CidEncoding {
+    /// 64 lowercase hex digits, as in store file names and pointer files.
+    #[default]
+    Hex,
+    /// 52 characters of unpadded lowercase RFC 4648 base32.
+    Base32,
+    /// Bitcoin's base58 alphabet, 32 to 44 characters.
+    Base58,
+}
+
+/// Why text isn't a [`Cid`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseCidError {
+    #[error("a CID is 64 hex, 52 base32, or 32 to 44 base58 characters, not {0}")]
+    Length(usize),
+    #[error("invalid {encoding:?} character {character:?} in CID")]
+    Character { encoding: CidEncoding, character: char },
+    /// Valid characters that aren't how the encoding writes any 32 bytes:
+    /// too large a base58 number, or stray low bits in base32.
+    #[error("not a canonical {0:?} CID")]
+    NotCanonical(CidEncoding),
+}
+
+impl Cid {
+    /// This CID in full, in `encoding`. [`Display`](std::fmt::Display)
+    /// shows a short hex prefix, or all of it with `{:#}`.
+    pub fn encode(&self, encoding: CidEncoding) -> String {
+        match encoding {
+            CidEncoding::Hex => self.to_hex(),
+            CidEncoding::Base32 => encode_base32(&self.0),
+            CidEncoding::Base58 => encode_base58(&self.0),
+        }
+    }
+}
+
+/// Parses any [`CidEncoding`], telling them apart by length. Base32 may be
+/// upper case; hex may be either.
+impl FromStr for Cid {
+    type Err = ParseCidError;
+
+    fn from_str(s: &str) -> Result<Self, ParseCidError> {
+        let cid = match s.len() {
+            HEX_LEN => {
+                let mut bytes = [0u8; 32];
+                hex::decode_to_slice(s, &mut bytes).map_err(|_| ParseCidError::Character {
+                    encoding: CidEncoding::Hex,
+                    character: s.chars().find(|c| !c.is_ascii_hexdigit()).unwrap_or_default(),
+                })?;
+                return Ok(Cid(bytes));
+            }
+            BASE32_LEN => Cid(decode_base32(s)?),
+            32..=44 => Cid(decode_base58(s)?),
+            len => return Err(ParseCidError::Length(len)),
+        };
+        // Decoding ignores base32's padding bits and base58's leading ones;
+        // re-encoding catches text that sets or miscounts them
+        let (encoding, canonical) = if s.len() == BASE32_LEN {
+            (CidEncoding::Base32, cid.encode(CidEncoding::Base32).eq_ignore_ascii_case(s))
+        } else {
+            (CidEncoding::Base58, cid.encode(CidEncoding::Base58) == s)
+        };
+        if !canonical {
+            return Err(ParseCidError::NotCanonical(encoding));
+        }
+        Ok(cid)
+    }
+}
+
+impl TryFrom<&str> for Cid {
+    type Error = ParseCidError;
+
+    fn try_from(s: &str) -> Result<Self, ParseCidError> {
+        s.parse()
+    }
+}
+
+fn digit(alphabet: &[u8], encoding: CidEncoding, c: char) -> Result<u32, ParseCidError> {
+    alphabet.iter()
+        .position(|&a| a as char == c)
+        .map(|i| i as u32)
+        .ok_or(ParseCidError::Character { encoding, character: c })
+}
+
+fn encode_base32(bytes: &[u8; 32]) -> String {
+    let mut out = String::with_capacity(BASE32_LEN);
+    let (mut acc, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        acc = (acc << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(acc >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(acc << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+fn decode_base32(s: &str) -> Result<[u8; 32], ParseCidError> {
+    let mut bytes = [0u8; 32];
+    let (mut acc, mut bits, mut i) = (0u32, 0, 0);
+    for c in s.chars() {
+        acc = (acc << 5) | digit(BASE32, CidEncoding::Base32, c.to_ascii_lowercase())?;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes[i] = (acc >> bits) as u8;
+            i += 1;
+        }
+    }
+    Ok(bytes)
+}
+
+fn encode_base58(bytes: &[u8; 32]) -> String {
+    // Base-58 digits of the number, least significant first
+    let mut digits: Vec<u8> = Vec::with_capacity(44);
+    for &byte in bytes {
+        let mut carry = byte as u32;
+        for digit in &mut digits {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+    let mut out = "1".repeat(zeros);
+    out.extend(digits.iter().rev().map(|&d| BASE58[d as usize] as char));
+    out
+}
+
+fn decode_base58(s: &str) -> Result<[u8; 32], ParseCidError> {
+    let mut bytes = [0u8; 32];
+    for c in s.chars() {
+        let mut carry = digit(BASE58, CidEncoding::Base58, c)?;
+        for byte in bytes.iter_mut().rev() {
+            carry += (*byte as u32) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        if carry > 0 {
+            return Err(ParseCidError::NotCanonical(CidEncoding::Base58));
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cid_text() {
+        let mut zeros = [0u8; 32];
+        zeros[31] = 1;
+        for cid in [Cid::from_bytes(b"page"), Cid([0xff; 32]), Cid([0; 32]), Cid(zeros)] {
+            for encoding in [CidEncoding::Hex, CidEncoding::Base32, CidEncoding::Base58] {
+                let text = cid.encode(encoding);
+                assert_eq!(text.parse::<Cid>(), Ok(cid), "{:?} {}", encoding, text);
+            }
+            assert_eq!(Cid::try_from(cid.encode(CidEncoding::Base32).to_uppercase().as_str()), Ok(cid));
+            assert_eq!(format!("{:#}", cid), cid.to_hex());
+        }
+        assert_eq!(Cid([0; 32]).encode(CidEncoding::Base58), "1".repeat(32));
+        assert_eq!(Cid([0xff; 32]).encode(CidEncoding::Base32).len(), BASE32_LEN);
+
+        // Known encodings of the same bytes
+        let cid: Cid = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".parse().unwrap();
+        assert_eq!(cid, Cid::from_bytes(b""));
+        assert_eq!(cid.encode(CidEncoding::Base32), "4oymiquy7qobjgx36tejs35zeqt24qpemsnzgtfeswmrw6csxbkq");
+        assert_eq!(cid.encode(CidEncoding::Base58), "GKot5hBsd81kMupNCXHaqbhv3huEbxAFMLnpcX2hniwn");
+
+        assert_eq!("abc".parse::<Cid>(), Err(ParseCidError::Length(3)));
+        let bad = format!("{}0", &"1".repeat(43));
+        assert_eq!(bad.parse::<Cid>(), Err(ParseCidError::Character { encoding: CidEncoding::Base58, character: '0' }));
+        assert_eq!("z".repeat(44).parse::<Cid>(), Err(ParseCidError::NotCanonical(CidEncoding::Base58)));
+        assert_eq!("7".repeat(52).parse::<Cid>(), Err(ParseCidError::NotCanonical(CidEncoding::Base32)));
+    }
+}
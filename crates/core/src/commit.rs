@@ -0,0 +1,260 @@
+//! Commits — versions of a database linked to the versions they came from.
+//!
+//! A commit is a page naming a page table and its parent commits, so the
+//! commits reachable from a branch form a DAG. Named roots may point at a
+//! commit or directly at a page table; anything that isn't a commit is
+//! treated as a commit with no parents.
+//!
+//! History can be cut short, as by a shallow copy that leaves out a
+//! commit's parents: walks through the DAG end at commits missing from the
+//! store, as they do at first commits.
+
+use std::collections::{HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Cid, Page, PageStore, PageStoreError, PageTable, Result};
+
+/// Leading bytes of a commit page, so it can't be mistaken for a page table.
+const MAGIC: &[u8; 8] = b"csqlcmt2";
+
+/// Leading bytes of a commit written before commits had authors.
+const MAGIC_V1: &[u8; 8] = b"csqlcmt1";
+
+/// A recorded version of the database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commit {
+    /// Page table of the database at this commit.
+    pub root: Cid,
+    /// Commits this one follows: none for the first, two or more for a merge.
+    pub parents: Vec<Cid>,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub message: String,
+    /// Who made the commit, free-form; empty if not recorded.
+    pub author: String,
+}
+
+/// Who made a root update, why, and the root it was based on: provenance
+/// a store can record with
+/// [`update_root_with_meta`](PageStore::update_root_with_meta) without the
+/// root being a commit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommitMeta {
+    /// Who made the change, free-form.
+    pub author: String,
+    pub message: String,
+    /// The root the change was based on, `None` for a first one.
+    pub parent: Option<Cid>,
+}
+
+impl CommitMeta {
+    pub fn new(author: &str, message: &str) -> Self {
+        Self { author: author.to_string(), message: message.to_string(), parent: None }
+    }
+
+    pub fn with_parent(mut self, parent: Option<Cid>) -> Self {
+        self.parent = parent;
+        self
+    }
+
+    /// The commit of `root` this describes, made now.
+    pub fn to_commit(&self, root: Cid) -> Commit {
+        Commit::new(root, self.parent.into_iter().collect(), &self.message).with_author(&self.author)
+    }
+}
+
+/// A commit as first written, without an author.
+#[derive(Deserialize)]
+struct CommitV1 {
+    root: Cid,
+    parents: Vec<Cid>,
+    time: u64,
+    message: String,
+}
+
+impl Commit {
+    /// A commit of `root` made now.
+    pub fn new(root: Cid, parents: Vec<Cid>, message: &str) -> Self {
+        Self { root, parents, time: now(), message: message.to_string(), author: String::new() }
+    }
+
+    /// Record who made the commit.
+    pub fn with_author(mut self, author: &str) -> Self {
+        self.author = author.to_string();
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(bincode::serialize(self).expect("commit serialization"));
+        data
+    }
+
+    /// Parse a commit page; `None` if the page is something else.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if let Some(v1) = data.strip_prefix(MAGIC_V1) {
+            let CommitV1 { root, parents, time, message } = bincode::deserialize(v1).ok()?;
+            return Some(Self { root, parents, time, message, author: String::new() });
+        }
+        bincode::deserialize(data.strip_prefix(MAGIC)?).ok()
+    }
+
+    /// Store the commit, returning its CID.
+    pub fn put(&self, store: &dyn PageStore) -> Result<Cid> {
+        store.put(&Page { data: self.to_bytes() })
+    }
+
+    /// Load the commit at `cid`; `None` if that page isn't a commit.
+    pub fn load(store: &dyn PageStore, cid: &Cid) -> Result<Option<Self>> {
+        Ok(Self::from_bytes(&store.get(cid)?.data))
+    }
+}
+
+/// Seconds since the Unix epoch. Browsers have no system clock for
+/// `SystemTime` to read, so there it comes from JavaScript.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) fn now() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) fn now() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+/// The page table a named root points at: the commit's root if `cid` is a
+/// commit, otherwise `cid` itself.
+pub fn page_table_root(store: &dyn PageStore, cid: &Cid) -> Result<Cid> {
+    Ok(Commit::load(store, cid)?.map_or(*cid, |commit| commit.root))
+}
+
+/// Read the page table at `cid`, a page table or a commit.
+pub fn load_page_table(store: &dyn PageStore, cid: &Cid) -> Result<PageTable> {
+    let table_cid = page_table_root(store, cid)?;
+    PageTable::from_bytes(&store.get(&table_cid)?.data)
+        .map_err(|e| PageStoreError::Storage(format!("{} is not a page table: {}", table_cid, e)))
+}
+
+/// The commit at `cid`, or `None` if it isn't one or is missing from the store.
+pub(crate) fn load_present(store: &dyn PageStore, cid: &Cid) -> Result<Option<Commit>> {
+    match Commit::load(store, cid) {
+        Err(PageStoreError::NotFound(_)) => Ok(None),
+        result => result,
+    }
+}
+
+fn parents(store: &dyn PageStore, cid: &Cid) -> Result<Vec<Cid>> {
+    Ok(load_present(store, cid)?.map(|commit| commit.parents).unwrap_or_default())
+}
+
+/// `cid` and every commit reachable from it through parents.
+fn ancestors(store: &dyn PageStore, cid: Cid) -> Result<HashSet<Cid>> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([cid]);
+    while let Some(cid) = queue.pop_front() {
+        if seen.insert(cid) {
+            queue.extend(parents(store, &cid)?);
+        }
+    }
+    Ok(seen)
+}
+
+/// The newest commit reachable from `from` (itself included) made at or
+/// before `time`, in seconds since the Unix epoch.
+pub fn commit_at(store: &dyn PageStore, from: Cid, time: u64) -> Result<Option<Cid>> {
+    let mut best: Option<(u64, [u8; 32])> = None;
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([from]);
+    while let Some(cid) = queue.pop_front() {
+        if !seen.insert(cid) {
+            continue;
+        }
+        let Some(commit) = load_present(store, &cid)? else {
+            continue;
+        };
+        if commit.time <= time && best.is_none_or(|best| (commit.time, cid.0) > best) {
+            best = Some((commit.time, cid.0));
+        }
+        queue.extend(commit.parents);
+    }
+    Ok(best.map(|(_, bytes)| Cid(bytes)))
+}
+
+/// Whether `ancestor` is `descendant` or reachable from it through parents.
+pub fn is_ancestor(store: &dyn PageStore, ancestor: Cid, descendant: Cid) -> Result<bool> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([descendant]);
+    while let Some(cid) = queue.pop_front() {
+        if cid == ancestor {
+            return Ok(true);
+        }
+        if seen.insert(cid) {
+            queue.extend(parents(store, &cid)?);
+        }
+    }
+    Ok(false)
+}
+
+/// The best common ancestor of commits `a` and `b`: one reachable from
+/// both that no other common ancestor descends from. `None` if their
+/// histories never meet.
+///
+/// After criss-cross merges there can be several such commits; the newest
+/// is returned.
+pub fn merge_base(store: &dyn PageStore, a: Cid, b: Cid) -> Result<Option<Cid>> {
+    let of_a = ancestors(store, a)?;
+
+    // Walk back from b, stopping at the first common commits on each path
+    let mut common = Vec::new();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([b]);
+    while let Some(cid) = queue.pop_front() {
+        if !seen.insert(cid) {
+            continue;
+        }
+        if of_a.contains(&cid) {
+            common.push(cid);
+        } else {
+            queue.extend(parents(store, &cid)?);
+        }
+    }
+
+    // A common commit reached on one path may be an ancestor of one reached on another
+    let mut best = Vec::new();
+    for &candidate in &common {
+        let mut redundant = false;
+        for &other in &common {
+            if other != candidate && is_ancestor(store, candidate, other)? {
+                redundant = true;
+                break;
+            }
+        }
+        if !redundant {
+            let time = load_present(store, &candidate)?.map_or(0, |commit| commit.time);
+            best.push((time, candidate.0));
+        }
+    }
+    Ok(best.into_iter().max().map(|(_, bytes)| Cid(bytes)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_bytes() {
+        let root = Cid::from_bytes(b"page table");
+        let parent = Cid::from_bytes(b"parent");
+        let authored = Commit::new(root, vec![parent], "second").with_author("ada");
+        assert_eq!(Commit::from_bytes(&authored.to_bytes()), Some(authored));
+
+        // Commits from before authors were recorded still load
+        let mut v1 = MAGIC_V1.to_vec();
+        v1.extend(bincode::serialize(&(root, vec![parent], 7u64, "old")).unwrap());
+        let old = Commit::from_bytes(&v1).unwrap();
+        assert_eq!((old.parents, old.time, old.message.as_str(), old.author.as_str()), (vec![parent], 7, "old", ""));
+    }
+}
@@ -0,0 +1,101 @@
+//! Everyday root and page operations, for every [`PageStore`].
+
+use std::collections::HashSet;
+
+use crate::{Cid, Commit, Page, PageStore, PageStoreError, PageTable, Result};
+
+/// Helpers over the [`PageStore`] methods, implemented for every store.
+pub trait PageStoreExt: PageStore {
+    /// Name the current root `name`, returning it. Fails if there is no
+    /// current root.
+    fn snapshot(&self, name: &str) -> Result<Cid> {
+        let root = self.current_root()?
+            .ok_or_else(|| PageStoreError::Storage(format!("no current root to snapshot as {}", name)))?;
+        self.set_named_root(name, root)?;
+        Ok(root)
+    }
+
+    /// Make the root named `name` current, returning it.
+    fn checkout(&self, name: &str) -> Result<Cid> {
+        let root = named_root(self, name)?;
+        self.update_root(root)?;
+        Ok(root)
+    }
+
+    /// Name `to` whatever `from` names, returning it. The current root
+    /// doesn't move.
+    fn fork(&self, from: &str, to: &str) -> Result<Cid> {
+        let root = named_root(self, from)?;
+        self.set_named_root(to, root)?;
+        Ok(root)
+    }
+
+    /// Call `visitor` once with every page reachable from `root` (see
+    /// [`reachable`](crate::reachable)), `root` first. Unlike `reachable`,
+    /// a missing page is an error.
+    fn walk_root(&self, root: Cid, mut visitor: impl FnMut(&Cid, &Page) -> Result<()>) -> Result<()> {
+        walk(self, root, &mut HashSet::new(), &mut visitor)
+    }
+
+    /// Copy the current root and every named root to `other`, with the
+    /// pages they reach that `other` lacks. Pages are stored before any
+    /// root is set. Returns how many pages were copied.
+    fn copy_to(&self, other: &dyn PageStore) -> Result<usize> {
+        let current = self.current_root()?;
+        let named = self.list_named_roots()?;
+        let mut seen = HashSet::new();
+        let mut copied = 0;
+        for root in current.iter().chain(named.iter().map(|(_, cid)| cid)) {
+            walk(self, *root, &mut seen, &mut |cid, page| {
+                if !other.has(cid)? {
+                    other.put(page)?;
+                    copied += 1;
+                }
+                Ok(())
+            })?;
+        }
+        for (name, cid) in named {
+            other.set_named_root(&name, cid)?;
+        }
+        if let Some(root) = current {
+            other.update_root(root)?;
+        }
+        Ok(copied)
+    }
+}
+
+impl<S: PageStore + ?Sized> PageStoreExt for S {}
+
+fn named_root<S: PageStore + ?Sized>(store: &S, name: &str) -> Result<Cid> {
+    store.get_named_root(name)?.ok_or_else(|| PageStoreError::Storage(format!("no root named {}", name)))
+}
+
+/// Visit the pages reachable from `root` not yet in `seen`.
+fn walk<S: PageStore + ?Sized>(
+    store: &S,
+    root: Cid,
+    seen: &mut HashSet<Cid>,
+    visitor: &mut dyn FnMut(&Cid, &Page) -> Result<()>,
+) -> Result<()> {
+    let mut queue = vec![root];
+    while let Some(cid) = queue.pop() {
+        if !seen.insert(cid) {
+            continue;
+        }
+        let page = store.get(&cid)?;
+        visitor(&cid, &page)?;
+        if let Some(commit) = Commit::from_bytes(&page.data) {
+            queue.extend(commit.parents.into_iter().rev());
+            queue.push(commit.root);
+        } else if let Ok(page_table) = PageTable::from_bytes(&page.data) {
+            // Data pages are leaves: visit them without parsing them
+            for data in page_table.entries.into_iter().flatten() {
+                if seen.insert(data) {
+                    visitor(&data, &store.get(&data)?)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
@@ -1,4 +1,73 @@
 //! CraftSQL Core — PageStore trait and CID types
+//!
+//! [`Cid`], [`Page`], [`PageTable`], and their serialization need only
+//! `alloc`: without the default `std` feature the crate is `no_std`, so
+//! embedded and browser backends can share them. Stores and everything
+//! built on them need `std`. Either way the crate builds for
+//! `wasm32-unknown-unknown`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod branch;
+mod cid_text;
+#[cfg(feature = "std")]
+mod commit;
+#[cfg(feature = "std")]
+mod ext;
+#[cfg(feature = "std")]
+mod log;
+#[cfg(feature = "std")]
+mod page_map;
+#[cfg(feature = "std")]
+mod reach;
+#[cfg(feature = "std")]
+mod replace;
+#[cfg(feature = "std")]
+mod sqlite_file;
+#[cfg(feature = "std")]
+mod store;
+mod table_diff;
+mod table_format;
+#[cfg(feature = "std")]
+mod usage;
+#[cfg(feature = "std")]
+mod watch;
+
+#[cfg(feature = "std")]
+pub use branch::{
+    fast_forward, force_update, is_fast_forward, protected_branches, remove_branch, set_protected, ProtectedStore,
+    PROTECTED_REF,
+};
+pub use cid_text::{CidEncoding, ParseCidError};
+#[cfg(feature = "std")]
+pub use commit::{commit_at, is_ancestor, load_page_table, merge_base, page_table_root, Commit, CommitMeta};
+#[cfg(feature = "std")]
+pub use ext::PageStoreExt;
+#[cfg(feature = "std")]
+pub use log::{log, Log, LogEntry};
+#[cfg(feature = "std")]
+pub use page_map::{changed_objects, page_map, page_map_of, PageMap, PageOwner, SchemaObject};
+#[cfg(feature = "std")]
+pub use reach::reachable;
+#[cfg(feature = "std")]
+pub use replace::replace_file;
+#[cfg(feature = "std")]
+pub use sqlite_file::{export_sqlite, import_sqlite, Imported};
+#[cfg(feature = "std")]
+pub use store::{PageStore, PageStoreError, Result};
+pub use table_diff::{ApplyError, DiffStats, PageTableDiff};
+pub use table_format::PageTableError;
+#[cfg(feature = "std")]
+pub use usage::{usage, Usage};
+#[cfg(feature = "std")]
+pub use watch::{watch, RootEvent, RootSignal, Watch, DEFAULT_POLL_INTERVAL};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
 
 use sha2::{Digest, Sha256};
 use serde::{Serialize, Deserialize};
@@ -16,13 +85,28 @@ impl Cid {
         Self(out)
     }
 
+    /// Compute CID by streaming bytes from a reader
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: std::io::Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut reader, &mut hasher)?;
+        let mut out = [0u8; 32];
+        out.copy_from_slice(&hasher.finalize());
+        Ok(Self(out))
+    }
+
     pub fn to_hex(&self) -> String {
         hex::encode(self.0)
     }
 }
 
-impl std::fmt::Display for Cid {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// The first 16 hex digits, enough to tell CIDs apart by eye; `{:#}` gives
+/// all 64, which [`parse`](str::parse) reads back.
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return write!(f, "{}", self.to_hex());
+        }
         write!(f, "{}", &self.to_hex()[..16])
     }
 }
@@ -33,82 +117,43 @@ pub struct Page {
     pub data: Vec<u8>,
 }
 
-/// Result type for PageStore operations
-pub type Result<T> = std::result::Result<T, PageStoreError>;
-
-/// PageStore errors
-#[derive(Debug, thiserror::Error)]
-pub enum PageStoreError {
-    #[error("page not found: {0}")]
-    NotFound(Cid),
-    #[error("storage error: {0}")]
-    Storage(String),
-    #[error("io error: {0}")]
-    Io(#[from] std::io::Error),
-}
-
-/// Swappable storage backend for CraftSQL
-pub trait PageStore: Send + Sync {
-    /// Fetch a page by its content identifier
-    fn get(&self, cid: &Cid) -> Result<Page>;
-
-    /// Store a page, returns its content identifier
-    fn put(&self, page: &Page) -> Result<Cid>;
-
-    /// Update the default root pointer to a new page table CID
-    fn update_root(&self, new_root: Cid) -> Result<()>;
-
-    /// Get the current default root pointer
-    fn current_root(&self) -> Result<Option<Cid>>;
-
-    /// Save a named root pointer (snapshot/branch)
-    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()>;
-
-    /// Get a named root pointer
-    fn get_named_root(&self, name: &str) -> Result<Option<Cid>>;
-
-    /// Remove a named root pointer
-    fn remove_named_root(&self, name: &str) -> Result<bool>;
-
-    /// List all named root pointers
-    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>>;
-}
-
-/// Diff between two PageTables — which pages changed
-#[derive(Debug, Clone)]
-pub struct PageTableDiff {
-    /// Pages added or modified: (page_num, old_cid, new_cid)
-    pub changed: Vec<(usize, Option<Cid>, Option<Cid>)>,
-}
-
-impl PageTable {
-    /// Compute diff from `old` to `self` (new).
-    /// Returns entries where CIDs differ.
-    pub fn diff(&self, old: &PageTable) -> PageTableDiff {
-        let max_len = self.entries.len().max(old.entries.len());
-        let mut changed = Vec::new();
-
-        for i in 0..max_len {
-            let old_cid = old.get(i).copied();
-            let new_cid = self.get(i).copied();
-            if old_cid != new_cid {
-                changed.push((i, old_cid, new_cid));
-            }
-        }
-
-        PageTableDiff { changed }
-    }
-}
-
 /// Page table — maps page numbers to CIDs
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PageTable {
     pub entries: Vec<Option<Cid>>,
+    /// Bytes per page, or 0 if not recorded (as in tables written before
+    /// the format had a header).
+    #[serde(default)]
+    pub page_size: u32,
+    #[serde(default)]
+    pub meta: DbMeta,
+}
+
+/// What a page table records about its database besides the pages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbMeta {
+    /// The application's schema version: SQLite's `user_version`.
+    pub schema_version: u32,
+    /// When the database was created, in seconds since the Unix epoch; 0
+    /// if unknown.
+    pub created: u64,
 }
 
 impl PageTable {
+    /// Leading bytes of a serialized table that
+    /// [`max_serialized_len`](Self::max_serialized_len) reads.
+    pub const PREFIX_LEN: usize = table_format::PREFIX_LEN;
+
     pub fn new() -> Self {
-        Self { entries: Vec::new() }
+        Self { entries: Vec::new(), page_size: 0, meta: DbMeta::default() }
+    }
+
+    /// An empty table for a database created now.
+    #[cfg(feature = "std")]
+    pub fn created_now() -> Self {
+        let mut table = Self::new();
+        table.meta.created = commit::now();
+        table
     }
 
     /// Get CID for a page number
@@ -124,6 +169,32 @@ impl PageTable {
         self.entries[page_num] = Some(cid);
     }
 
+    /// Record the page size and schema version from the database header at
+    /// the start of page 1. Does nothing if `header` is shorter than the
+    /// 100-byte header or doesn't give a valid page size.
+    pub fn record_header(&mut self, header: &[u8]) {
+        if header.len() < 100 {
+            return;
+        }
+        if let Some(page_size) = sqlite_page_size(header) {
+            self.page_size = page_size as u32;
+            self.meta.schema_version = u32::from_be_bytes(header[60..64].try_into().unwrap());
+        }
+    }
+
+    /// Bytes per page: as recorded, or for a table written before page
+    /// sizes were, the length of page 1. `None` if neither is known.
+    #[cfg(feature = "std")]
+    pub fn page_size_in<S: PageStore + ?Sized>(&self, store: &S) -> Result<Option<usize>> {
+        if self.page_size != 0 {
+            return Ok(Some(self.page_size as usize));
+        }
+        match self.get(0) {
+            Some(cid) => Ok(Some(store.get(cid)?.data.len())),
+            None => Ok(None),
+        }
+    }
+
     /// Number of pages
     pub fn len(&self) -> usize {
         self.entries.len()
@@ -133,14 +204,31 @@ impl PageTable {
         self.entries.is_empty()
     }
 
-    /// Serialize to bytes
+    /// Serialize to bytes, in the current versioned format
     pub fn to_bytes(&self) -> Vec<u8> {
-        bincode::serialize(self).expect("page table serialization")
+        table_format::encode(self)
+    }
+
+    /// Deserialize from bytes in the current format or the legacy bincode one.
+    ///
+    /// A legacy table re-serializes in the current format, so under a new
+    /// CID: to store a table exactly as read, keep the bytes it came from.
+    pub fn from_bytes(data: &[u8]) -> core::result::Result<Self, PageTableError> {
+        table_format::decode(data)
+    }
+
+    /// An upper bound on the length of the serialized table that starts
+    /// with `prefix`, for reading it out of a larger blob. `None` if
+    /// `prefix` is too short to tell; [`PREFIX_LEN`](Self::PREFIX_LEN)
+    /// bytes always suffice.
+    pub fn max_serialized_len(prefix: &[u8]) -> Option<u64> {
+        table_format::max_len(prefix)
     }
 
-    /// Deserialize from bytes
-    pub fn from_bytes(data: &[u8]) -> std::result::Result<Self, bincode::Error> {
-        bincode::deserialize(data)
+    /// Deserialize from a reader, consuming only the serialized bytes
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: std::io::Read>(reader: R) -> std::result::Result<Self, PageTableError> {
+        table_format::read(reader)
     }
 }
 
@@ -150,6 +238,15 @@ impl Default for PageTable {
     }
 }
 
+/// Page size recorded in a SQLite database header.
+pub(crate) fn sqlite_page_size(header: &[u8]) -> Option<usize> {
+    let size = match u16::from_be_bytes([header[16], header[17]]) {
+        1 => 65536,
+        size => size as usize,
+    };
+    (size.is_power_of_two() && (512..=65536).contains(&size)).then_some(size)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -161,6 +258,7 @@ mod tests {
         let cid3 = Cid::from_bytes(b"world");
         assert_eq!(cid1, cid2);
         assert_ne!(cid1, cid3);
+        assert_eq!(Cid::from_reader(&b"hello"[..]).unwrap(), cid1);
     }
 
     #[test]
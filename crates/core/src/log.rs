@@ -0,0 +1,108 @@
+//! Commit logs — the history behind a version, newest first.
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::commit::load_present;
+use crate::{load_page_table, Cid, Commit, PageStore, PageStoreError, PageTable, Result};
+
+/// One commit in a [`log`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub cid: Cid,
+    pub parents: Vec<Cid>,
+    pub author: String,
+    pub message: String,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    /// Pages that differ from the first parent's version, or all of them
+    /// for a first commit; `None` if either version is missing from the
+    /// store, as past the end of a shallow copy.
+    pub pages_changed: Option<usize>,
+}
+
+/// Iterator over the commits reachable from a version; see [`log`].
+pub struct Log<'a> {
+    store: &'a dyn PageStore,
+    /// Commits waiting to be listed, newest on top.
+    queue: BinaryHeap<(u64, [u8; 32])>,
+    waiting: HashMap<Cid, Commit>,
+    seen: HashSet<Cid>,
+    /// Failure to load the starting commit, reported by the first `next`.
+    error: Option<PageStoreError>,
+}
+
+/// The commits reachable from `from` through their parents: `from` first,
+/// then newest first by commit time, each once.
+///
+/// A walk ends at a root that isn't a commit and at commits missing from
+/// the store, as it does at first commits; the log of a bare page table is
+/// empty.
+pub fn log(store: &dyn PageStore, from: Cid) -> Log<'_> {
+    let mut log = Log {
+        store,
+        queue: BinaryHeap::new(),
+        waiting: HashMap::new(),
+        seen: HashSet::new(),
+        error: None,
+    };
+    log.error = log.enqueue(from).err();
+    log
+}
+
+impl Log<'_> {
+    fn enqueue(&mut self, cid: Cid) -> Result<()> {
+        if !self.seen.insert(cid) {
+            return Ok(());
+        }
+        if let Some(commit) = load_present(self.store, &cid)? {
+            self.queue.push((commit.time, cid.0));
+            self.waiting.insert(cid, commit);
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, cid: Cid, commit: Commit) -> Result<LogEntry> {
+        for parent in &commit.parents {
+            self.enqueue(*parent)?;
+        }
+        let before = match commit.parents.first() {
+            Some(parent) => load_present_page_table(self.store, parent)?,
+            None => Some(PageTable::new()),
+        };
+        let pages_changed = match (load_present_page_table(self.store, &commit.root)?, before) {
+            (Some(pages), Some(before)) => Some(pages.diff(&before).changed.len()),
+            _ => None,
+        };
+        Ok(LogEntry {
+            cid,
+            parents: commit.parents,
+            author: commit.author,
+            message: commit.message,
+            time: commit.time,
+            pages_changed,
+        })
+    }
+}
+
+/// The page table at `cid`, or `None` if it is missing from the store.
+fn load_present_page_table(store: &dyn PageStore, cid: &Cid) -> Result<Option<PageTable>> {
+    match load_page_table(store, cid) {
+        Err(PageStoreError::NotFound(_)) => Ok(None),
+        result => result.map(Some),
+    }
+}
+
+impl Iterator for Log<'_> {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        let (_, bytes) = self.queue.pop()?;
+        let cid = Cid(bytes);
+        let commit = self.waiting.remove(&cid)?;
+        Some(self.entry(cid, commit))
+    }
+}
+
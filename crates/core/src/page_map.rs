@@ -0,0 +1,390 @@
+//! Which table or index each page of a database belongs to.
+//!
+//! Reads the SQLite file format directly: the schema table on page 1 names
+//! every b-tree and its root page, and walking each b-tree down to its
+//! leaves and overflow chains finds the rest of its pages. Page numbers are
+//! SQLite's, starting at 1, so page `n` is page table entry `n - 1`.
+
+use std::collections::BTreeMap;
+
+use crate::{load_page_table, Cid, PageStore, PageStoreError, PageTable, PageTableDiff, Result};
+
+/// A b-tree named in the schema table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaObject {
+    /// `table` or `index`.
+    pub kind: String,
+    pub name: String,
+    /// The table an index is on; a table's own name for a table.
+    pub table: String,
+    pub root_page: u32,
+}
+
+/// What a page is used for (see [`PageMap`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageOwner {
+    /// A b-tree or overflow page of [`PageMap::objects`]`[i]`.
+    Object(usize),
+    Freelist,
+    /// An auto-vacuum pointer map page.
+    PointerMap,
+    /// The page holding the byte range SQLite uses for file locks.
+    LockByte,
+    /// Reached from nothing.
+    Unused,
+}
+
+/// The owner of every page of one version of a database (see [`page_map`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageMap {
+    pub page_size: usize,
+    /// The schema table itself first, as `sqlite_schema` on page 1.
+    pub objects: Vec<SchemaObject>,
+    owners: Vec<PageOwner>,
+}
+
+impl PageMap {
+    /// Number of pages in the database.
+    pub fn len(&self) -> usize {
+        self.owners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
+
+    /// Owner of page `page`, or `None` past the end of the database.
+    pub fn owner(&self, page: u32) -> Option<PageOwner> {
+        page.checked_sub(1).and_then(|i| self.owners.get(i as usize)).copied()
+    }
+
+    /// The table or index page `page` belongs to, if any.
+    pub fn object(&self, page: u32) -> Option<&SchemaObject> {
+        match self.owner(page)? {
+            PageOwner::Object(i) => Some(&self.objects[i]),
+            _ => None,
+        }
+    }
+
+    /// Index into [`objects`](Self::objects) of the table or index `name`.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.objects.iter().position(|object| object.name == name)
+    }
+
+    /// Every page with its owner, in page order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, PageOwner)> + '_ {
+        self.owners.iter().enumerate().map(|(i, owner)| (i as u32 + 1, *owner))
+    }
+
+    /// The pages of [`objects`](Self::objects)`[object]`, in page order.
+    pub fn pages(&self, object: usize) -> impl Iterator<Item = u32> + '_ {
+        self.iter().filter(move |(_, owner)| *owner == PageOwner::Object(object)).map(|(page, _)| page)
+    }
+}
+
+fn corrupt(reason: String) -> PageStoreError {
+    PageStoreError::Storage(format!("malformed database: {}", reason))
+}
+
+fn be16(data: &[u8], at: usize) -> Result<usize> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+        .ok_or_else(|| corrupt(format!("read past the end of a page at {}", at)))
+}
+
+fn be32(data: &[u8], at: usize) -> Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| corrupt(format!("read past the end of a page at {}", at)))
+}
+
+/// A SQLite varint at `at`: its value and length.
+fn varint(data: &[u8], at: usize) -> Result<(u64, usize)> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *data.get(at + i).ok_or_else(|| corrupt(format!("truncated varint at {}", at)))?;
+        if i == 8 {
+            return Ok(((value << 8) | byte as u64, 9));
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    unreachable!()
+}
+
+/// Pages of one database, read through its page table.
+struct Pages<'a> {
+    store: &'a dyn PageStore,
+    table: PageTable,
+    page_size: usize,
+    /// Page size less the bytes reserved at the end of each page.
+    usable: usize,
+    owners: Vec<PageOwner>,
+}
+
+impl Pages<'_> {
+    fn read(&self, page: u32) -> Result<Vec<u8>> {
+        if page == 0 || page as usize > self.owners.len() {
+            return Err(corrupt(format!("page {} is out of range", page)));
+        }
+        match self.table.get(page as usize - 1) {
+            Some(cid) => Ok(self.store.get(cid)?.data),
+            None => Ok(vec![0; self.page_size]),
+        }
+    }
+
+    fn claim(&mut self, page: u32, owner: PageOwner) -> Result<()> {
+        let slot = page
+            .checked_sub(1)
+            .and_then(|i| self.owners.get_mut(i as usize))
+            .ok_or_else(|| corrupt(format!("page {} is out of range", page)))?;
+        if *slot != PageOwner::Unused {
+            return Err(corrupt(format!("page {} is used twice", page)));
+        }
+        *slot = owner;
+        Ok(())
+    }
+
+    /// Bytes of a cell payload of `size` kept on its b-tree page; the rest
+    /// spills to overflow pages.
+    fn local_size(&self, size: usize, table_leaf: bool) -> usize {
+        let usable = self.usable;
+        let max = if table_leaf { usable - 35 } else { (usable - 12) * 64 / 255 - 23 };
+        if size <= max {
+            return size;
+        }
+        let min = (usable - 12) * 32 / 255 - 23;
+        let local = min + (size - min) % (usable - 4);
+        if local <= max {
+            local
+        } else {
+            min
+        }
+    }
+
+    /// Claim the overflow chain starting at `first` for `owner`, and
+    /// return the `len` bytes of payload it holds when `collect` is set.
+    fn overflow(&mut self, mut first: u32, mut len: usize, owner: PageOwner, collect: bool) -> Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        while len > 0 {
+            if first == 0 {
+                return Err(corrupt("overflow chain ends early".into()));
+            }
+            self.claim(first, owner)?;
+            let data = self.read(first)?;
+            let take = len.min(self.usable - 4);
+            if collect {
+                let bytes = data.get(4..4 + take).ok_or_else(|| corrupt(format!("overflow page {} is short", first)))?;
+                payload.extend_from_slice(bytes);
+            }
+            len -= take;
+            first = be32(&data, 0)?;
+        }
+        Ok(payload)
+    }
+
+    /// Claim the b-tree rooted at `root` for `owner`. Returns the payloads
+    /// of its leaf cells when `collect` is set.
+    fn btree(&mut self, root: u32, owner: PageOwner, collect: bool) -> Result<Vec<Vec<u8>>> {
+        let mut payloads = Vec::new();
+        let mut stack = vec![root];
+        while let Some(page) = stack.pop() {
+            self.claim(page, owner)?;
+            let data = self.read(page)?;
+            let start = if page == 1 { 100 } else { 0 };
+            let kind = *data.get(start).ok_or_else(|| corrupt(format!("page {} is empty", page)))?;
+            let interior = match kind {
+                2 | 5 => true,
+                10 | 13 => false,
+                _ => return Err(corrupt(format!("page {} is not a b-tree page (type {})", page, kind))),
+            };
+            let cells = be16(&data, start + 3)?;
+            let header = if interior { 12 } else { 8 };
+            if interior {
+                stack.push(be32(&data, start + 8)?);
+            }
+            for i in 0..cells {
+                let mut at = be16(&data, start + header + 2 * i)?;
+                if interior {
+                    stack.push(be32(&data, at)?);
+                    at += 4;
+                    if kind == 5 {
+                        // Table interior cells hold only a key
+                        continue;
+                    }
+                }
+                let (size, n) = varint(&data, at)?;
+                at += n;
+                if kind == 13 {
+                    at += varint(&data, at)?.1;
+                }
+                let size = size as usize;
+                let local = self.local_size(size, kind == 13);
+                let mut payload = Vec::new();
+                if collect && !interior {
+                    let bytes = data.get(at..at + local).ok_or_else(|| corrupt(format!("cell {} of page {} overruns it", i, page)))?;
+                    payload.extend_from_slice(bytes);
+                }
+                if local < size {
+                    let rest = self.overflow(be32(&data, at + local)?, size - local, owner, collect && !interior)?;
+                    payload.extend(rest);
+                }
+                if collect && !interior {
+                    payloads.push(payload);
+                }
+            }
+        }
+        Ok(payloads)
+    }
+
+    fn freelist(&mut self, mut trunk: u32) -> Result<()> {
+        while trunk != 0 {
+            self.claim(trunk, PageOwner::Freelist)?;
+            let data = self.read(trunk)?;
+            let leaves = be32(&data, 4)? as usize;
+            for i in 0..leaves {
+                self.claim(be32(&data, 8 + 4 * i)?, PageOwner::Freelist)?;
+            }
+            trunk = be32(&data, 0)?;
+        }
+        Ok(())
+    }
+}
+
+/// A column of a schema table record: text, or an integer.
+enum Value {
+    Int(i64),
+    Text(String),
+    Other,
+}
+
+/// The columns of a SQLite record.
+fn record(payload: &[u8]) -> Result<Vec<Value>> {
+    let (header_len, mut at) = varint(payload, 0)?;
+    let mut body = header_len as usize;
+    let mut values = Vec::new();
+    while at < header_len as usize {
+        let (serial, n) = varint(payload, at)?;
+        at += n;
+        let len = match serial {
+            0 | 8 | 9 => 0,
+            1..=4 => serial as usize,
+            5 => 6,
+            6 | 7 => 8,
+            10 | 11 => return Err(corrupt(format!("reserved serial type {}", serial))),
+            _ => (serial as usize - 12) / 2,
+        };
+        let bytes = payload.get(body..body + len).ok_or_else(|| corrupt("record overruns its payload".into()))?;
+        body += len;
+        values.push(match serial {
+            1..=6 => {
+                let mut int = if bytes[0] & 0x80 != 0 { -1i64 } else { 0 };
+                for byte in bytes {
+                    int = (int << 8) | *byte as i64;
+                }
+                Value::Int(int)
+            }
+            8 => Value::Int(0),
+            9 => Value::Int(1),
+            serial if serial >= 13 && serial % 2 == 1 => Value::Text(String::from_utf8_lossy(bytes).into_owned()),
+            _ => Value::Other,
+        });
+    }
+    Ok(values)
+}
+
+/// Map every page of the database at `root`, a page table or a commit, to
+/// the table or index it belongs to. Tables and indexes without pages of
+/// their own (views, triggers, virtual tables) aren't listed.
+///
+/// Only SQLite's own structures are read, so this works on any store
+/// without opening the database. A page with two owners, or a b-tree page
+/// that isn't one, makes it fail as malformed.
+pub fn page_map(store: &dyn PageStore, root: &Cid) -> Result<PageMap> {
+    page_map_of(store, root, &|_| true)
+}
+
+/// [`page_map`], walking only the tables and indexes `walk` accepts. The
+/// rest are still listed, but their pages other than the root are left
+/// [`Unused`](PageOwner::Unused) and never read.
+pub fn page_map_of(store: &dyn PageStore, root: &Cid, walk: &dyn Fn(&SchemaObject) -> bool) -> Result<PageMap> {
+    let table = load_page_table(store, root)?;
+    let Some(first) = table.get(0) else {
+        return Ok(PageMap { page_size: 0, objects: Vec::new(), owners: Vec::new() });
+    };
+    let header = store.get(first)?.data;
+    if header.len() < 100 || !header.starts_with(b"SQLite format 3\0") {
+        return Err(corrupt("page 1 has no SQLite header".into()));
+    }
+    let page_size = header.len();
+    let usable = page_size - header[20] as usize;
+    // The size in the header is only trusted if written by a version that
+    // maintains it, as the change counter and version-valid-for agree
+    let mut count = be32(&header, 28)? as usize;
+    if count == 0 || header[24..28] != header[92..96] {
+        count = table.len();
+    }
+
+    let mut pages = Pages { store, table, page_size, usable, owners: vec![PageOwner::Unused; count] };
+    let lock_byte = (1usize << 30) / page_size + 1;
+    if lock_byte <= count {
+        pages.owners[lock_byte - 1] = PageOwner::LockByte;
+    }
+    if be32(&header, 52)? != 0 {
+        // Auto-vacuum: a pointer map page every usable / 5 pages, from page 2
+        let mut page = 2;
+        while page <= count {
+            if pages.owners[page - 1] == PageOwner::Unused {
+                pages.owners[page - 1] = PageOwner::PointerMap;
+            }
+            page += usable / 5 + 1;
+        }
+    }
+
+    let mut objects = vec![SchemaObject {
+        kind: "table".into(),
+        name: "sqlite_schema".into(),
+        table: "sqlite_schema".into(),
+        root_page: 1,
+    }];
+    for payload in pages.btree(1, PageOwner::Object(0), true)? {
+        let mut values = record(&payload)?.into_iter();
+        let mut text = || match values.next() {
+            Some(Value::Text(text)) => text,
+            _ => String::new(),
+        };
+        let (kind, name, table) = (text(), text(), text());
+        let root_page = match values.next() {
+            Some(Value::Int(page)) if page > 0 => page as u32,
+            _ => continue,
+        };
+        objects.push(SchemaObject { kind, name, table, root_page });
+    }
+    for (i, object) in objects.iter().enumerate().skip(1) {
+        if walk(object) {
+            pages.btree(object.root_page, PageOwner::Object(i), false)?;
+        }
+    }
+    pages.freelist(be32(&header, 32)?)?;
+
+    Ok(PageMap { page_size, objects, owners: pages.owners })
+}
+
+/// How many of the pages `diff` changed (see [`PageTable::diff`]) belong
+/// to each table or index, by name. A page counts for its owner in both the
+/// old version, mapped by `old`, and the new, mapped by `new`.
+pub fn changed_objects(diff: &PageTableDiff, old: &PageMap, new: &PageMap) -> BTreeMap<String, usize> {
+    let mut changed = BTreeMap::new();
+    for (i, _, _) in &diff.changed {
+        let page = *i as u32 + 1;
+        let before = old.object(page).map(|object| &object.name);
+        let after = new.object(page).map(|object| &object.name);
+        for name in before.into_iter().chain(after.filter(|after| Some(*after) != before)) {
+            *changed.entry(name.clone()).or_default() += 1;
+        }
+    }
+    changed
+}
+
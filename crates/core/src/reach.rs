@@ -0,0 +1,35 @@
+//! Reachability — which pages a set of roots still needs.
+
+use std::collections::HashSet;
+
+use crate::{Cid, Commit, PageStore, PageStoreError, PageTable, Result};
+
+/// Every page reachable from `roots`: the roots themselves, the pages of
+/// any page table among them, and for any commit its page table and parent
+/// commits, all the way down. Other pages are leaves.
+///
+/// Pages that are missing from the store are included but not followed, so
+/// a store with part of its history pruned still yields what it does have.
+pub fn reachable(store: &dyn PageStore, roots: &[Cid]) -> Result<HashSet<Cid>> {
+    let mut marked = HashSet::new();
+    let mut queue: Vec<Cid> = roots.to_vec();
+    while let Some(cid) = queue.pop() {
+        if !marked.insert(cid) {
+            continue;
+        }
+        let page = match store.get(&cid) {
+            Ok(page) => page,
+            Err(PageStoreError::NotFound(_)) => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(commit) = Commit::from_bytes(&page.data) {
+            queue.push(commit.root);
+            queue.extend(commit.parents);
+        } else if let Ok(page_table) = PageTable::from_bytes(&page.data) {
+            // Data pages are leaves: mark them without reading them
+            marked.extend(page_table.entries.into_iter().flatten());
+        }
+    }
+    Ok(marked)
+}
+
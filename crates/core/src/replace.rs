@@ -0,0 +1,42 @@
+//! Moving a file over another, the way every store commits a write.
+
+use std::io;
+use std::path::Path;
+
+/// Move `from` to `to`, replacing any file there, in one step.
+///
+/// On Unix this is a plain rename. Windows refuses to replace a file that
+/// another process has open without sharing deletes — a reader, a virus
+/// scanner, the search indexer — so there the rename is retried for a
+/// little while before the error is returned.
+pub fn replace_file(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(windows)]
+    {
+        let mut backoff = std::time::Duration::from_millis(5);
+        for _ in 0..8 {
+            match std::fs::rename(from, to) {
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => std::thread::sleep(backoff),
+                result => return result,
+            }
+            backoff *= 2;
+        }
+    }
+    std::fs::rename(from, to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("new"), dir.path().join("target"));
+        std::fs::write(&to, b"old").unwrap();
+        std::fs::write(&from, b"new").unwrap();
+        replace_file(&from, &to).unwrap();
+        assert_eq!(std::fs::read(&to).unwrap(), b"new");
+        assert!(!from.exists());
+        assert!(replace_file(&from, &to).is_err());
+    }
+}
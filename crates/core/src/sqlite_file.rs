@@ -0,0 +1,147 @@
+//! Moving databases between stores and ordinary SQLite files.
+//!
+//! A page table is a SQLite file cut into pages: entry `i` holds the bytes
+//! at offset `i * page_size`, and a missing entry reads as zeros. Nothing
+//! here needs SQLite itself.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{load_page_table, replace_file, sqlite_page_size, Cid, Commit, Page, PageStore, PageStoreError, PageTable, Result};
+
+const HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Bytes of pages [`import_sqlite`] hands the store at a time.
+const IMPORT_BATCH_BYTES: usize = 8 << 20;
+
+/// What [`import_sqlite`] stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Imported {
+    /// The commit of the imported database, with no parents.
+    pub commit: Cid,
+    pub page_table: Cid,
+    pub pages: usize,
+    pub page_size: usize,
+}
+
+fn invalid(path: &Path, reason: &str) -> PageStoreError {
+    PageStoreError::Storage(format!("{}: {}", path.display(), reason))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Write the database at `root`, a page table or a commit, to `path` as a
+/// standard SQLite file. Returns its size in bytes.
+///
+/// The file is written next to `path` and renamed into place, so readers
+/// never see half of it.
+pub fn export_sqlite(store: &dyn PageStore, root: &Cid, path: &Path) -> Result<u64> {
+    let table = load_page_table(store, root)?;
+
+    let tmp = with_suffix(path, ".partial");
+    let written = (|| {
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        let mut page_size = (table.page_size != 0).then_some(table.page_size as usize);
+        for (i, entry) in table.entries.iter().enumerate() {
+            let data = match entry {
+                Some(cid) => store.get(cid)?.data,
+                None => vec![0; *page_size.get_or_insert(4096)],
+            };
+            if *page_size.get_or_insert(data.len()) != data.len() {
+                return Err(PageStoreError::Storage(format!(
+                    "page {} is {} bytes, not {}",
+                    i,
+                    data.len(),
+                    page_size.unwrap_or_default()
+                )));
+            }
+            file.write_all(&data)?;
+        }
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(table.len() as u64 * page_size.unwrap_or(0) as u64)
+    })();
+    match written {
+        Ok(size) => {
+            replace_file(&tmp, path)?;
+            Ok(size)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// Store the SQLite database at `path`: every page, a page table over them,
+/// and a commit of it with `message`. Moves no roots; the caller points
+/// whichever it likes at the result.
+///
+/// This is the fast way to load a large database: build it with plain
+/// SQLite, then import it. Pages are read straight from the file and
+/// stored in batches (see [`PageStore::put_many`]), with none of the VFS's
+/// per-page bookkeeping and no intermediate roots.
+///
+/// Refuses a database with a non-empty rollback journal or write-ahead log
+/// next to it, since its file alone may be missing committed changes or
+/// hold half-made ones. The imported header is switched out of WAL mode,
+/// which stores don't support.
+pub fn import_sqlite(store: &dyn PageStore, path: &Path, message: &str) -> Result<Imported> {
+    for suffix in ["-journal", "-wal"] {
+        let sidecar = with_suffix(path, suffix);
+        match fs::metadata(&sidecar) {
+            Ok(meta) if meta.len() > 0 => {
+                return Err(invalid(path, &format!("{} is not empty; open the database with sqlite3 to settle it first", sidecar.display())))
+            }
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut header = [0u8; 100];
+    if file.read_exact(&mut header).is_err() || &header[..16] != HEADER {
+        return Err(invalid(path, "not a SQLite database"));
+    }
+    let page_size = sqlite_page_size(&header).ok_or_else(|| invalid(path, "invalid page size"))?;
+    if len % page_size as u64 != 0 {
+        return Err(invalid(path, &format!("size {} is not a multiple of the page size {}", len, page_size)));
+    }
+
+    // Pages go to the store in batches, and the page table once at the end
+    let pages = (len / page_size as u64) as usize;
+    let batch_pages = (IMPORT_BATCH_BYTES / page_size).max(1);
+    let mut table = PageTable { entries: Vec::with_capacity(pages), ..PageTable::new() };
+    table.record_header(&header);
+    let mut batch = Vec::with_capacity(batch_pages.min(pages));
+    for i in 0..pages {
+        let mut data = vec![0; page_size];
+        if i == 0 {
+            data[..100].copy_from_slice(&header);
+            file.read_exact(&mut data[100..])?;
+            // File format read and write versions: 2 is WAL, 1 a rollback journal
+            for version in &mut data[18..20] {
+                if *version == 2 {
+                    *version = 1;
+                }
+            }
+        } else {
+            file.read_exact(&mut data)?;
+        }
+        batch.push(Page { data });
+        if batch.len() == batch_pages || i + 1 == pages {
+            table.entries.extend(store.put_many(&batch)?.into_iter().map(Some));
+            batch.clear();
+        }
+    }
+
+    let page_table = store.put(&Page { data: table.to_bytes() })?;
+    let commit = Commit::new(page_table, vec![], message).put(store)?;
+    Ok(Imported { commit, page_table, pages: table.len(), page_size })
+}
+
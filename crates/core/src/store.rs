@@ -0,0 +1,444 @@
+//! The [`PageStore`] trait, and the errors stores return.
+
+use std::sync::Arc;
+
+use crate::{Cid, CommitMeta, Page, RootSignal};
+
+/// Result type for PageStore operations
+pub type Result<T> = std::result::Result<T, PageStoreError>;
+
+/// PageStore errors
+#[derive(Debug, thiserror::Error)]
+pub enum PageStoreError {
+    #[error("page not found: {0}")]
+    NotFound(Cid),
+    #[error("storage error: {0}")]
+    Storage(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("root conflict: expected {}, found {}", display_root(.expected), display_root(.actual))]
+    RootConflict {
+        expected: Option<Cid>,
+        actual: Option<Cid>,
+    },
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("read-only store: {0}")]
+    ReadOnly(String),
+    /// The backend couldn't be reached, or the connection was lost.
+    #[error("network error: {0}")]
+    Network(String),
+    #[error("timed out: {0}")]
+    Timeout(String),
+    /// Moving `branch` to `new` would drop commits: `old`, where it is, is
+    /// not among `new`'s ancestors.
+    #[error("non-fast-forward update of {branch}: {old} is not an ancestor of {new}")]
+    NonFastForward { branch: String, old: Cid, new: Cid },
+    #[error("branch {0} is protected: only fast-forward updates are allowed")]
+    Protected(String),
+    /// A concurrent change got in the way; unlike [`RootConflict`](Self::RootConflict),
+    /// not of a root pointer.
+    #[error("conflict: {0}")]
+    Conflict(String),
+    /// Bytes fetched for `expected` hash to `actual`.
+    #[error("corrupt page: expected {expected}, got {actual}")]
+    Corruption { expected: Cid, actual: Cid },
+    /// `source`, raised by the store method `op`, about `cid` if there is one.
+    #[error("{op}{}: {source}", cid.map(|cid| format!(" {}", cid)).unwrap_or_default())]
+    Context {
+        op: &'static str,
+        cid: Option<Cid>,
+        source: Box<PageStoreError>,
+    },
+}
+
+impl PageStoreError {
+    /// A failed network call: [`Timeout`](Self::Timeout) if `error` is a
+    /// timeout, [`Network`](Self::Network) otherwise.
+    pub fn network(what: impl std::fmt::Display, error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => Self::Timeout(format!("{}: {}", what, error)),
+            _ => Self::Network(format!("{}: {}", what, error)),
+        }
+    }
+
+    /// Whether the same call may succeed if simply made again: a network
+    /// failure, a timeout, or a transient I/O error. Conflicts need the
+    /// caller to look again first, so they don't count.
+    pub fn is_retryable(&self) -> bool {
+        use std::io::ErrorKind;
+        match self {
+            Self::Network(_) | Self::Timeout(_) => true,
+            Self::Io(e) => matches!(
+                e.kind(),
+                ErrorKind::Interrupted
+                    | ErrorKind::TimedOut
+                    | ErrorKind::WouldBlock
+                    | ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+            ),
+            Self::Context { source, .. } => source.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// Wrap the error with the store method that raised it and the CID it
+    /// was about. [`NotFound`](Self::NotFound) and
+    /// [`RootConflict`](Self::RootConflict), which callers match on and which
+    /// already name their CIDs, are returned as they are, as is an error
+    /// that already has context.
+    pub fn with_context(self, op: &'static str, cid: Option<Cid>) -> Self {
+        match self {
+            Self::NotFound(_) | Self::RootConflict { .. } | Self::Context { .. } => self,
+            source => Self::Context { op, cid, source: Box::new(source) },
+        }
+    }
+
+    /// The error without any [`Context`](Self::Context) around it.
+    pub fn without_context(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.without_context(),
+            error => error,
+        }
+    }
+}
+
+fn display_root(root: &Option<Cid>) -> String {
+    root.map_or_else(|| "none".to_string(), |cid| cid.to_string())
+}
+
+/// Swappable storage backend for CraftSQL
+///
+/// The trait is object safe: a backend chosen at runtime works as a
+/// `Box<dyn PageStore>` or `Arc<dyn PageStore>`, both of which are stores
+/// themselves.
+pub trait PageStore: Send + Sync {
+    /// Fetch a page by its content identifier
+    fn get(&self, cid: &Cid) -> Result<Page>;
+
+    /// Store a page, returns its content identifier
+    fn put(&self, page: &Page) -> Result<Cid>;
+
+    /// Store several pages, returning their CIDs in order.
+    ///
+    /// The default stores them one at a time; backends that can write a
+    /// batch at once (one transaction, one round trip) override this.
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        pages.iter().map(|page| self.put(page)).collect()
+    }
+
+    /// Check whether a page is stored.
+    ///
+    /// The default fetches the page; backends that can answer without reading
+    /// it override this.
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        match self.get(cid) {
+            Ok(_) => Ok(true),
+            Err(PageStoreError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Update the default root pointer to a new page table CID
+    fn update_root(&self, new_root: Cid) -> Result<()>;
+
+    /// [`update_root`](Self::update_root), recording who made the change
+    /// and why where the store keeps such provenance.
+    ///
+    /// The default drops `meta` and calls `update_root`.
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        let _ = meta;
+        self.update_root(new_root)
+    }
+
+    /// Get the current default root pointer
+    fn current_root(&self) -> Result<Option<Cid>>;
+
+    /// Save a named root pointer (snapshot/branch)
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()>;
+
+    /// [`set_named_root`](Self::set_named_root), recording who made the
+    /// change and why as [`update_root_with_meta`](Self::update_root_with_meta)
+    /// does.
+    ///
+    /// The default drops `meta` and calls `set_named_root`.
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        let _ = meta;
+        self.set_named_root(name, cid)
+    }
+
+    /// Get a named root pointer
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>>;
+
+    /// Remove a named root pointer
+    fn remove_named_root(&self, name: &str) -> Result<bool>;
+
+    /// List all named root pointers
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>>;
+
+    /// Every stored page's CID and size in bytes, for garbage collection.
+    ///
+    /// The default fails: not every backend can enumerate its pages.
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        Err(PageStoreError::Storage("this store can't list its pages".into()))
+    }
+
+    /// Delete a page no root needs any more. Returns whether it was stored.
+    ///
+    /// What deleting means is up to the backend: a store holding its own
+    /// pages removes the page, a cache evicts its copy and leaves the one in
+    /// the store behind it, and a store on a content network unpins it so
+    /// the network may collect it. This is what garbage collection, cache
+    /// eviction, and pruning go through.
+    ///
+    /// The default fails, like [`list_pages`](Self::list_pages).
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        Err(PageStoreError::Storage(format!("this store can't delete pages ({})", cid)))
+    }
+
+    /// Delete several pages, returning for each whether it was stored.
+    ///
+    /// The default deletes them one at a time; backends that can batch
+    /// deletes override this.
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        cids.iter().map(|cid| self.delete_page(cid)).collect()
+    }
+
+    /// Something that wakes when a root may have changed, for [`watch`](crate::watch).
+    ///
+    /// The default is `None`: watchers poll.
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        Ok(None)
+    }
+}
+
+/// A shared store, so one store can be registered with the VFS and still
+/// used directly.
+impl<S: PageStore + ?Sized> PageStore for Arc<S> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        (**self).get(cid)
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        (**self).put(page)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        (**self).put_many(pages)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        (**self).has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        (**self).update_root(new_root)
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        (**self).update_root_with_meta(new_root, meta)
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        (**self).current_root()
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        (**self).set_named_root(name, cid)
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        (**self).set_named_root_with_meta(name, cid, meta)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        (**self).get_named_root(name)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        (**self).remove_named_root(name)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        (**self).list_named_roots()
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        (**self).list_pages()
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        (**self).delete_page(cid)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        (**self).delete_many(cids)
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        (**self).root_signal()
+    }
+}
+
+/// A borrowed store, so a wrapper can be layered over a store for a while
+/// without owning it.
+impl<S: PageStore + ?Sized> PageStore for &S {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        (**self).get(cid)
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        (**self).put(page)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        (**self).put_many(pages)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        (**self).has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        (**self).update_root(new_root)
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        (**self).update_root_with_meta(new_root, meta)
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        (**self).current_root()
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        (**self).set_named_root(name, cid)
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        (**self).set_named_root_with_meta(name, cid, meta)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        (**self).get_named_root(name)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        (**self).remove_named_root(name)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        (**self).list_named_roots()
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        (**self).list_pages()
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        (**self).delete_page(cid)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        (**self).delete_many(cids)
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        (**self).root_signal()
+    }
+}
+
+/// An owned store, typically a `Box<dyn PageStore>` picked at runtime, so it
+/// can go wherever a store type is expected: in a wrapper, or to the VFS.
+impl<S: PageStore + ?Sized> PageStore for Box<S> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        (**self).get(cid)
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        (**self).put(page)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        (**self).put_many(pages)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        (**self).has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        (**self).update_root(new_root)
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        (**self).update_root_with_meta(new_root, meta)
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        (**self).current_root()
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        (**self).set_named_root(name, cid)
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        (**self).set_named_root_with_meta(name, cid, meta)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        (**self).get_named_root(name)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        (**self).remove_named_root(name)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        (**self).list_named_roots()
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        (**self).list_pages()
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        (**self).delete_page(cid)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        (**self).delete_many(cids)
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        (**self).root_signal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_taxonomy() {
+        let cid = Cid::from_bytes(b"page");
+        let timeout = PageStoreError::network("GET /pages", std::io::ErrorKind::TimedOut.into());
+        assert!(matches!(timeout, PageStoreError::Timeout(_)));
+        assert!(matches!(PageStoreError::network("connect", std::io::ErrorKind::ConnectionRefused.into()), PageStoreError::Network(_)));
+
+        let error = timeout.with_context("get", Some(cid));
+        assert!(error.is_retryable());
+        assert!(matches!(error.without_context(), PageStoreError::Timeout(_)));
+        assert!(error.to_string().starts_with(&format!("get {}: timed out: GET /pages", cid)));
+        assert!(matches!(PageStoreError::NotFound(cid).with_context("get", Some(cid)), PageStoreError::NotFound(_)));
+
+        let corrupt = PageStoreError::Corruption { expected: cid, actual: Cid::from_bytes(b"other") };
+        assert!(!corrupt.is_retryable());
+        assert!(!PageStoreError::Conflict("busy".into()).is_retryable());
+        assert!(PageStoreError::Io(std::io::ErrorKind::Interrupted.into()).is_retryable());
+        assert!(!PageStoreError::Io(std::io::ErrorKind::NotFound.into()).is_retryable());
+    }
+}
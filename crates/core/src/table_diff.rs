@@ -0,0 +1,219 @@
+//! Diffs between page tables, and applying them: the unit of incremental
+//! sync and replay.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Cid, PageTable};
+
+/// Leading bytes of a serialized diff.
+#[cfg(feature = "std")]
+const MAGIC: &[u8; 8] = b"csqldif1";
+
+/// Diff between two PageTables — which pages changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageTableDiff {
+    /// Pages added or modified: (page_num, old_cid, new_cid)
+    pub changed: Vec<(usize, Option<Cid>, Option<Cid>)>,
+    /// Entries in the old table, which a table must have for the diff to
+    /// apply to it.
+    pub old_len: usize,
+    /// Entries in the new table, which applying the diff leaves.
+    pub new_len: usize,
+}
+
+/// Why a [`PageTableDiff`] doesn't apply to a table. Nothing is changed
+/// when it doesn't.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ApplyError {
+    #[error("page {page} is {}, not {} as the diff expects", display_entry(.found), display_entry(.expected))]
+    Conflict {
+        page: usize,
+        expected: Option<Cid>,
+        found: Option<Cid>,
+    },
+    #[error("table has {found} pages, not {expected} as the diff expects")]
+    Length { expected: usize, found: usize },
+}
+
+/// What a [`PageTableDiff`] changes, and what shipping it costs (see
+/// [`PageTableDiff::stats`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffStats {
+    /// Pages empty or past the end in the old table and set in the new one.
+    pub pages_added: u64,
+    /// Pages set in both tables, to different contents.
+    pub pages_changed: u64,
+    /// Pages set in the old table and empty or gone in the new one.
+    pub pages_removed: u64,
+    /// Distinct pages the new table needs that the old one didn't have.
+    pub pages_to_transfer: u64,
+    /// Their size.
+    pub bytes: u64,
+}
+
+fn display_entry(entry: &Option<Cid>) -> String {
+    entry.map_or_else(|| "empty".into(), |cid| alloc::format!("{}", cid))
+}
+
+impl PageTable {
+    /// Compute diff from `old` to `self` (new).
+    /// Returns entries where CIDs differ.
+    pub fn diff(&self, old: &PageTable) -> PageTableDiff {
+        let max_len = self.entries.len().max(old.entries.len());
+        let mut changed = Vec::new();
+
+        for i in 0..max_len {
+            let old_cid = old.get(i).copied();
+            let new_cid = self.get(i).copied();
+            if old_cid != new_cid {
+                changed.push((i, old_cid, new_cid));
+            }
+        }
+
+        PageTableDiff { changed, old_len: old.len(), new_len: self.len() }
+    }
+
+    /// Turn this table, the diff's old table, into its new one.
+    ///
+    /// Fails, changing nothing, unless the table is as the diff expects:
+    /// as long as the old table, with every changed page as it was there.
+    pub fn apply(&mut self, diff: &PageTableDiff) -> Result<(), ApplyError> {
+        if self.len() != diff.old_len {
+            return Err(ApplyError::Length { expected: diff.old_len, found: self.len() });
+        }
+        for &(page, expected, _) in &diff.changed {
+            let found = self.get(page).copied();
+            if found != expected {
+                return Err(ApplyError::Conflict { page, expected, found });
+            }
+        }
+        self.entries.resize(self.entries.len().max(diff.new_len), None);
+        for &(page, _, new) in &diff.changed {
+            if let Some(entry) = self.entries.get_mut(page) {
+                *entry = new;
+            }
+        }
+        self.entries.truncate(diff.new_len);
+        Ok(())
+    }
+}
+
+impl PageTableDiff {
+    /// Whether the tables had the same pages.
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.old_len == self.new_len
+    }
+
+    /// Count the pages this diff adds, changes, and removes, and the
+    /// pages of `page_size` bytes (every page of a SQLite database is one
+    /// size) it brings in: each distinct new page once, leaving out any
+    /// it only moves from another changed place. Pages the receiving store
+    /// has anyway still count; `craftsql_sync::estimate_sync_cost` asks it.
+    pub fn stats(&self, page_size: usize) -> DiffStats {
+        let mut stats = DiffStats::default();
+        let (mut old, mut new) = (Vec::new(), Vec::new());
+        for &(_, old_cid, new_cid) in &self.changed {
+            match (old_cid, new_cid) {
+                (None, Some(_)) => stats.pages_added += 1,
+                (Some(_), Some(_)) => stats.pages_changed += 1,
+                (Some(_), None) => stats.pages_removed += 1,
+                (None, None) => {}
+            }
+            old.extend(old_cid.map(|cid| cid.0));
+            new.extend(new_cid.map(|cid| cid.0));
+        }
+        old.sort_unstable();
+        new.sort_unstable();
+        new.dedup();
+        stats.pages_to_transfer = new.iter().filter(|cid| old.binary_search(cid).is_err()).count() as u64;
+        stats.bytes = stats.pages_to_transfer * page_size as u64;
+        stats
+    }
+
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(bincode::serialize(self).expect("page table diff serialization"));
+        data
+    }
+
+    /// Parse a serialized diff; `None` if the bytes are something else.
+    #[cfg(feature = "std")]
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let diff: Self = bincode::deserialize(data.strip_prefix(MAGIC)?).ok()?;
+        // Every change must be to a page one of the tables had
+        let pages = diff.old_len.max(diff.new_len);
+        diff.changed.iter().all(|&(page, _, _)| page < pages).then_some(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(pages: &[Option<&[u8]>]) -> PageTable {
+        let mut table = PageTable::new();
+        table.entries = pages.iter().map(|page| page.map(Cid::from_bytes)).collect();
+        table
+    }
+
+    #[test]
+    fn test_page_table_diff_apply() {
+        let old = table(&[Some(b"a"), Some(b"b"), None, Some(b"d")]);
+        for new in [
+            table(&[Some(b"a"), Some(b"B"), Some(b"c"), Some(b"d"), Some(b"e")]),
+            table(&[None, Some(b"b")]),
+            table(&[]),
+            old.clone(),
+        ] {
+            let diff = new.diff(&old);
+            assert_eq!(diff.is_empty(), new.entries == old.entries);
+            let shipped = PageTableDiff::from_bytes(&diff.to_bytes()).unwrap();
+            assert_eq!(shipped, diff);
+
+            let mut applied = old.clone();
+            applied.apply(&shipped).unwrap();
+            assert_eq!(applied.entries, new.entries);
+        }
+        assert!(PageTableDiff::from_bytes(&old.to_bytes()).is_none());
+
+        // A table that moved on since the old one doesn't take the diff
+        let diff = table(&[Some(b"a"), Some(b"B"), None, Some(b"d")]).diff(&old);
+        let mut moved = table(&[Some(b"a"), Some(b"X"), None, Some(b"d")]);
+        let conflict = moved.apply(&diff).unwrap_err();
+        assert_eq!(conflict, ApplyError::Conflict {
+            page: 1,
+            expected: Some(Cid::from_bytes(b"b")),
+            found: Some(Cid::from_bytes(b"X")),
+        });
+        assert_eq!(moved.get(1), Some(&Cid::from_bytes(b"X")));
+        let mut longer = table(&[Some(b"a"), Some(b"b"), None, Some(b"d"), Some(b"e")]);
+        assert_eq!(longer.apply(&diff), Err(ApplyError::Length { expected: 4, found: 5 }));
+
+        // Nor does a diff naming pages outside both tables
+        let mut bogus = diff.clone();
+        bogus.changed.push((9, None, Some(Cid::from_bytes(b"z"))));
+        assert!(PageTableDiff::from_bytes(&bogus.to_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_page_table_diff_stats() {
+        let old = table(&[Some(b"a"), Some(b"b"), None, Some(b"d")]);
+        // Page 1 changes, and pages 2 and 4 are added, both as "c"
+        let new = table(&[Some(b"a"), Some(b"B"), Some(b"c"), Some(b"d"), Some(b"c")]);
+        assert_eq!(new.diff(&old).stats(4096), DiffStats {
+            pages_added: 2,
+            pages_changed: 1,
+            pages_removed: 0,
+            pages_to_transfer: 2,
+            bytes: 8192,
+        });
+        // Moving "b" to page 0 brings nothing in
+        let new = table(&[Some(b"b"), None, None, Some(b"d")]);
+        assert_eq!(new.diff(&old).stats(4096), DiffStats { pages_changed: 1, pages_removed: 1, ..DiffStats::default() });
+        assert_eq!(old.diff(&old).stats(4096), DiffStats::default());
+    }
+}
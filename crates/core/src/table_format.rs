@@ -0,0 +1,314 @@
+//! The page table wire format.
+//!
+//! A stored page table starts with a header: magic, format version, entry
+//! encoding, page size, and the database size in pages. Then comes the
+//! database's [`DbMeta`], length-prefixed so fields can be added without a
+//! new version, and the entries. Version 1 tables have no metadata, and
+//! tables written before the header existed are bare bincode; both are
+//! still read, and never written.
+//!
+//! The legacy layout is read by hand rather than with bincode, so that
+//! decoding needs no `std`: a `u64` entry count, then a tag byte per entry,
+//! followed by the CID if the tag is 1.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{Cid, DbMeta, PageTable};
+
+/// Leading bytes of a page table. A legacy table starts with its entry
+/// count as a `u64`, which is never this large.
+const MAGIC: &[u8; 8] = b"csqlptbl";
+const VERSION: u8 = 2;
+/// The first header version, without metadata.
+const VERSION_1: u8 = 1;
+
+/// Version, encoding, page size, and page count, after the magic.
+const HEADER_LEN: usize = 1 + 1 + 4 + 8;
+
+/// Schema version and creation time, as this version writes them.
+const META_LEN: usize = 4 + 8;
+
+/// Every page is present: the CIDs, in page order.
+const ENCODING_DENSE: u8 = 0;
+/// A bitmap of the pages present, low bit first, then their CIDs in order.
+const ENCODING_BITMAP: u8 = 1;
+
+/// Most entries reserved before reading them, since the header can claim
+/// any number.
+const MAX_RESERVE: usize = 1 << 16;
+
+/// Why bytes aren't a page table.
+#[derive(Debug, thiserror::Error)]
+pub enum PageTableError {
+    #[error("unsupported page table version {0}")]
+    Version(u8),
+    #[error("unknown page table entry encoding {0}")]
+    Encoding(u8),
+    #[error("malformed page table: {0}")]
+    Malformed(&'static str),
+    #[cfg(feature = "std")]
+    #[error("read page table: {0}")]
+    Io(#[source] std::io::Error),
+}
+
+/// Where a table's bytes come from: a slice, or with `std`, a reader.
+trait Input {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), PageTableError>;
+}
+
+impl Input for &[u8] {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), PageTableError> {
+        if self.len() < buf.len() {
+            return Err(PageTableError::Malformed("truncated"));
+        }
+        let (head, rest) = self.split_at(buf.len());
+        buf.copy_from_slice(head);
+        *self = rest;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+struct Reader<R>(R);
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> Input for Reader<R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), PageTableError> {
+        self.0.read_exact(buf).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => PageTableError::Malformed("truncated"),
+            _ => PageTableError::Io(e),
+        })
+    }
+}
+
+pub(crate) fn encode(table: &PageTable) -> Vec<u8> {
+    let present = table.entries.iter().flatten().count();
+    let dense = present == table.entries.len();
+    let bitmap_len = if dense { 0 } else { table.entries.len().div_ceil(8) };
+
+    let mut data = Vec::with_capacity(MAGIC.len() + HEADER_LEN + 2 + META_LEN + bitmap_len + 32 * present);
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+    data.push(if dense { ENCODING_DENSE } else { ENCODING_BITMAP });
+    data.extend_from_slice(&table.page_size.to_le_bytes());
+    data.extend_from_slice(&(table.entries.len() as u64).to_le_bytes());
+    data.extend_from_slice(&(META_LEN as u16).to_le_bytes());
+    data.extend_from_slice(&table.meta.schema_version.to_le_bytes());
+    data.extend_from_slice(&table.meta.created.to_le_bytes());
+    if !dense {
+        let mut bitmap = vec![0u8; bitmap_len];
+        for (i, entry) in table.entries.iter().enumerate() {
+            if entry.is_some() {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+        }
+        data.extend_from_slice(&bitmap);
+    }
+    for cid in table.entries.iter().flatten() {
+        data.extend_from_slice(&cid.0);
+    }
+    data
+}
+
+/// Bytes [`max_len`] needs from the start of a table.
+pub(crate) const PREFIX_LEN: usize = MAGIC.len() + HEADER_LEN;
+
+/// An upper bound on the length of the table starting with `prefix`, from
+/// the page count in its first bytes; `None` if there are too few of them.
+pub(crate) fn max_len(prefix: &[u8]) -> Option<u64> {
+    let Some(header) = prefix.strip_prefix(MAGIC) else {
+        // A legacy entry is at most a tag byte and a CID
+        let pages = u64::from_le_bytes(prefix.get(..8)?.try_into().unwrap());
+        return Some(pages.saturating_mul(33).saturating_add(8));
+    };
+    let pages = u64::from_le_bytes(header.get(6..14)?.try_into().unwrap());
+    let meta = 2 + u16::MAX as u64;
+    Some(pages.saturating_mul(32).saturating_add(pages.div_ceil(8)).saturating_add(PREFIX_LEN as u64 + meta))
+}
+
+/// Decode a whole page table; bytes past its end are an error.
+pub(crate) fn decode(mut data: &[u8]) -> Result<PageTable, PageTableError> {
+    let table = read_table(&mut data)?;
+    if !data.is_empty() {
+        return Err(PageTableError::Malformed("trailing bytes"));
+    }
+    Ok(table)
+}
+
+/// Read a page table, consuming only its bytes.
+#[cfg(feature = "std")]
+pub(crate) fn read<R: std::io::Read>(reader: R) -> Result<PageTable, PageTableError> {
+    read_table(&mut Reader(reader))
+}
+
+fn read_table<I: Input>(input: &mut I) -> Result<PageTable, PageTableError> {
+    let mut start = [0u8; 8];
+    input.read_exact(&mut start)?;
+    if &start == MAGIC {
+        read_body(input)
+    } else {
+        read_legacy(input, u64::from_le_bytes(start))
+    }
+}
+
+/// Read the entries of a legacy table with `count` of them.
+fn read_legacy<I: Input>(input: &mut I, count: u64) -> Result<PageTable, PageTableError> {
+    let count = usize::try_from(count).map_err(|_| PageTableError::Malformed("too many pages"))?;
+    let mut entries = Vec::with_capacity(count.min(MAX_RESERVE));
+    let mut tag = [0u8];
+    for _ in 0..count {
+        input.read_exact(&mut tag)?;
+        entries.push(match tag[0] {
+            0 => None,
+            1 => {
+                let mut cid = [0u8; 32];
+                input.read_exact(&mut cid)?;
+                Some(Cid(cid))
+            }
+            _ => return Err(PageTableError::Malformed("invalid legacy entry tag")),
+        });
+    }
+    Ok(PageTable { entries, page_size: 0, meta: DbMeta::default() })
+}
+
+/// Read what follows the magic.
+fn read_body<I: Input>(reader: &mut I) -> Result<PageTable, PageTableError> {
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    if header[0] != VERSION && header[0] != VERSION_1 {
+        return Err(PageTableError::Version(header[0]));
+    }
+    let page_size = u32::from_le_bytes(header[2..6].try_into().unwrap());
+    let pages = usize::try_from(u64::from_le_bytes(header[6..14].try_into().unwrap()))
+        .map_err(|_| PageTableError::Malformed("too many pages"))?;
+
+    let mut meta = DbMeta::default();
+    if header[0] == VERSION {
+        let mut len = [0u8; 2];
+        reader.read_exact(&mut len)?;
+        let mut bytes = vec![0u8; u16::from_le_bytes(len) as usize];
+        reader.read_exact(&mut bytes)?;
+        // Fields a later writer added past these are skipped
+        if bytes.len() < META_LEN {
+            return Err(PageTableError::Malformed("metadata too short"));
+        }
+        meta.schema_version = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        meta.created = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
+    }
+
+    let bitmap = match header[1] {
+        ENCODING_DENSE => None,
+        ENCODING_BITMAP => {
+            // A byte at a time, so a lying page count runs out of input
+            // before it runs out of memory
+            let len = pages.div_ceil(8);
+            let mut bitmap = Vec::with_capacity(len.min(MAX_RESERVE));
+            let mut byte = [0u8];
+            for _ in 0..len {
+                reader.read_exact(&mut byte)?;
+                bitmap.push(byte[0]);
+            }
+            if pages % 8 != 0 && bitmap[len - 1] >> (pages % 8) != 0 {
+                return Err(PageTableError::Malformed("bitmap marks pages past the end"));
+            }
+            Some(bitmap)
+        }
+        encoding => return Err(PageTableError::Encoding(encoding)),
+    };
+
+    let mut entries = Vec::with_capacity(pages.min(MAX_RESERVE));
+    for i in 0..pages {
+        if bitmap.as_ref().is_some_and(|bitmap| bitmap[i / 8] & (1 << (i % 8)) == 0) {
+            entries.push(None);
+            continue;
+        }
+        let mut cid = [0u8; 32];
+        reader.read_exact(&mut cid)?;
+        entries.push(Some(Cid(cid)));
+    }
+    Ok(PageTable { entries, page_size, meta })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Legacy<'a> {
+        entries: &'a [Option<Cid>],
+    }
+
+    #[test]
+    fn test_page_table_format() {
+        let (a, b) = (Cid::from_bytes(b"a"), Cid::from_bytes(b"b"));
+        for entries in [vec![], vec![Some(a), Some(b)], vec![Some(a), None, None, Some(b), None, None, None, None, None]] {
+            let meta = DbMeta { schema_version: 3, created: 1_700_000_000 };
+            let table = PageTable { entries: entries.clone(), page_size: 4096, meta };
+            let bytes = table.to_bytes();
+            assert!(bytes.starts_with(MAGIC));
+            for decoded in [PageTable::from_bytes(&bytes).unwrap(), PageTable::from_reader(&bytes[..]).unwrap()] {
+                assert_eq!(decoded.entries, entries);
+                assert_eq!(decoded.page_size, 4096);
+                assert_eq!(decoded.meta, meta);
+            }
+
+            // Legacy bincode still reads, with the page size unknown
+            let legacy = bincode::serialize(&Legacy { entries: &entries }).unwrap();
+            for decoded in [PageTable::from_bytes(&legacy).unwrap(), PageTable::from_reader(&legacy[..]).unwrap()] {
+                assert_eq!(decoded.entries, entries);
+                assert_eq!(decoded.page_size, 0);
+            }
+        }
+
+        // The reader stops at the end of the table, in either format
+        let table = PageTable { entries: vec![Some(a), None], page_size: 512, meta: DbMeta::default() };
+        for mut bytes in [table.to_bytes(), bincode::serialize(&Legacy { entries: &table.entries }).unwrap()] {
+            let len = bytes.len();
+            bytes.extend_from_slice(b"rest");
+            let mut reader = &bytes[..];
+            PageTable::from_reader(&mut reader).unwrap();
+            assert_eq!(reader, b"rest");
+            assert!(PageTable::from_bytes(&bytes[..len]).is_ok());
+        }
+        assert!(matches!(PageTable::from_bytes(&[table.to_bytes(), vec![0]].concat()), Err(PageTableError::Malformed(_))));
+
+        let bytes = table.to_bytes();
+        let mut future = bytes.clone();
+        future[8] = 3;
+        assert!(matches!(PageTable::from_bytes(&future), Err(PageTableError::Version(3))));
+        let mut unknown = bytes.clone();
+        unknown[9] = 9;
+        assert!(matches!(PageTable::from_bytes(&unknown), Err(PageTableError::Encoding(9))));
+        assert!(matches!(PageTable::from_bytes(&bytes[..bytes.len() - 1]), Err(PageTableError::Malformed(_))));
+        let entries_at = MAGIC.len() + HEADER_LEN + 2 + META_LEN;
+        let mut padded = bytes.clone();
+        padded[entries_at] |= 0x80;
+        assert!(matches!(PageTable::from_bytes(&padded), Err(PageTableError::Malformed(_))));
+
+        // Version 1 has no metadata
+        let mut v1 = bytes[..MAGIC.len() + HEADER_LEN].to_vec();
+        v1[8] = VERSION_1;
+        v1.extend_from_slice(&bytes[entries_at..]);
+        let decoded = PageTable::from_bytes(&v1).unwrap();
+        assert_eq!((decoded.entries, decoded.page_size, decoded.meta), (table.entries.clone(), 512, DbMeta::default()));
+
+        // Legacy tables are strict about their tags and length too
+        let legacy = bincode::serialize(&Legacy { entries: &table.entries }).unwrap();
+        assert!(matches!(PageTable::from_bytes(&[&legacy[..8], &[2]].concat()), Err(PageTableError::Malformed(_))));
+        assert!(matches!(PageTable::from_bytes(&[&legacy[..], &[0]].concat()), Err(PageTableError::Malformed(_))));
+
+        // Metadata fields from a later writer are skipped
+        let mut longer = bytes[..entries_at].to_vec();
+        longer[MAGIC.len() + HEADER_LEN] += 4;
+        longer.extend_from_slice(b"more");
+        longer.extend_from_slice(&bytes[entries_at..]);
+        assert_eq!(PageTable::from_bytes(&longer).unwrap().entries, table.entries);
+
+        // A page count the input can't back fails without allocating it
+        let mut huge = bytes[..entries_at].to_vec();
+        huge[14..22].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(PageTable::from_reader(&huge[..]).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+//! Storage accounting — how many bytes each root really takes.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{reachable, Cid, PageStore, PageStoreError, Result};
+
+/// Storage reachable from one group of roots (see [`usage`]).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub name: String,
+    pub pages: u64,
+    pub bytes: u64,
+    /// Pages no other group reaches: what dropping this group would free.
+    pub unique_pages: u64,
+    pub unique_bytes: u64,
+}
+
+impl Usage {
+    /// Bytes this group shares with at least one other.
+    pub fn shared_bytes(&self) -> u64 {
+        self.bytes - self.unique_bytes
+    }
+}
+
+/// Storage used by each named group of roots: everything reachable from
+/// them (see [`reachable`]), and how much of it no other group reaches.
+///
+/// Sizes come from [`PageStore::list_pages`] where the store supports it,
+/// and otherwise from reading each page. Pages missing from the store count
+/// as empty.
+pub fn usage(store: &dyn PageStore, groups: &[(String, Vec<Cid>)]) -> Result<Vec<Usage>> {
+    let marked = groups.iter().map(|(_, roots)| reachable(store, roots)).collect::<Result<Vec<HashSet<Cid>>>>()?;
+    let mut reached_by: HashMap<Cid, u32> = HashMap::new();
+    for cid in marked.iter().flatten() {
+        *reached_by.entry(*cid).or_default() += 1;
+    }
+
+    let mut sizes: HashMap<Cid, u64> = match store.list_pages() {
+        Ok(pages) => pages.into_iter().filter(|(cid, _)| reached_by.contains_key(cid)).collect(),
+        Err(_) => HashMap::new(),
+    };
+    for cid in reached_by.keys() {
+        if !sizes.contains_key(cid) {
+            let size = match store.get(cid) {
+                Ok(page) => page.data.len() as u64,
+                Err(PageStoreError::NotFound(_)) => 0,
+                Err(e) => return Err(e),
+            };
+            sizes.insert(*cid, size);
+        }
+    }
+
+    Ok(groups
+        .iter()
+        .zip(marked)
+        .map(|((name, _), cids)| {
+            let mut usage = Usage { name: name.clone(), ..Usage::default() };
+            for cid in cids {
+                let size = sizes[&cid];
+                usage.pages += 1;
+                usage.bytes += size;
+                if reached_by[&cid] == 1 {
+                    usage.unique_pages += 1;
+                    usage.unique_bytes += size;
+                }
+            }
+            usage
+        })
+        .collect())
+}
+
@@ -0,0 +1,158 @@
+//! Watching a store's roots for changes.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::{Cid, PageStore, Result};
+
+/// How often a [`Watch`] reads the roots of a store that can't signal.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A change to one of a store's roots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RootEvent {
+    /// The default root moved.
+    Root(Cid),
+    /// A named root was set (`Some`) or removed (`None`).
+    Named { name: String, cid: Option<Cid> },
+}
+
+/// Wakes a [`Watch`] when a store's roots may have changed, so it needn't
+/// poll. See [`PageStore::root_signal`].
+pub trait RootSignal: Send {
+    /// Block until a root may have changed or `timeout` passes. Waking
+    /// when nothing changed is harmless.
+    fn wait(&mut self, timeout: Duration) -> Result<()>;
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Roots {
+    root: Option<Cid>,
+    named: BTreeMap<String, Cid>,
+}
+
+impl Roots {
+    fn read(store: &dyn PageStore) -> Result<Self> {
+        Ok(Self { root: store.current_root()?, named: store.list_named_roots()?.into_iter().collect() })
+    }
+
+    /// Events taking `self` to `new`.
+    fn changes(&self, new: &Roots) -> Vec<RootEvent> {
+        let mut events = Vec::new();
+        if let Some(root) = new.root.filter(|root| self.root != Some(*root)) {
+            events.push(RootEvent::Root(root));
+        }
+        for (name, cid) in &new.named {
+            if self.named.get(name) != Some(cid) {
+                events.push(RootEvent::Named { name: name.clone(), cid: Some(*cid) });
+            }
+        }
+        for name in self.named.keys().filter(|name| !new.named.contains_key(*name)) {
+            events.push(RootEvent::Named { name: name.clone(), cid: None });
+        }
+        events
+    }
+}
+
+/// Changes to a store's roots from the moment [`watch`] was called, e.g.
+/// to reopen read connections when a writer commits.
+///
+/// Roots are compared whenever the store signals (see
+/// [`PageStore::root_signal`]) and otherwise every poll interval, so a root
+/// that moves and moves back in between goes unseen. As an iterator it
+/// blocks until the next event.
+pub struct Watch<'a> {
+    store: &'a dyn PageStore,
+    signal: Option<Box<dyn RootSignal>>,
+    poll_interval: Duration,
+    last: Roots,
+    pending: VecDeque<RootEvent>,
+}
+
+/// Start watching the roots of `store`.
+pub fn watch(store: &dyn PageStore) -> Result<Watch<'_>> {
+    // Subscribe before reading, so no change falls in between
+    let signal = store.root_signal()?;
+    Ok(Watch {
+        store,
+        signal,
+        poll_interval: DEFAULT_POLL_INTERVAL,
+        last: Roots::read(store)?,
+        pending: VecDeque::new(),
+    })
+}
+
+impl Watch<'_> {
+    /// Read the roots this often, even if the store can signal.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Whether the store signals changes rather than being polled.
+    pub fn is_signaled(&self) -> bool {
+        self.signal.is_some()
+    }
+
+    /// The next change, waiting up to `timeout` for one (forever if `None`).
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<RootEvent>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Some(event));
+            }
+            let roots = Roots::read(self.store)?;
+            self.pending.extend(self.last.changes(&roots));
+            self.last = roots;
+            if !self.pending.is_empty() {
+                continue;
+            }
+
+            let mut wait = self.poll_interval;
+            if let Some(deadline) = deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Ok(None);
+                }
+                wait = wait.min(left);
+            }
+            match &mut self.signal {
+                Some(signal) => signal.wait(wait)?,
+                None => std::thread::sleep(wait),
+            }
+        }
+    }
+}
+
+impl Iterator for Watch<'_> {
+    type Item = Result<RootEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event(None).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cid(n: u8) -> Cid {
+        Cid([n; 32])
+    }
+
+    #[test]
+    fn test_changes() {
+        let old = Roots { root: Some(cid(1)), named: [("a".to_string(), cid(1)), ("b".to_string(), cid(2))].into() };
+        let new = Roots { root: Some(cid(2)), named: [("a".to_string(), cid(3)), ("c".to_string(), cid(2))].into() };
+        assert_eq!(
+            old.changes(&new),
+            vec![
+                RootEvent::Root(cid(2)),
+                RootEvent::Named { name: "a".into(), cid: Some(cid(3)) },
+                RootEvent::Named { name: "c".into(), cid: Some(cid(2)) },
+                RootEvent::Named { name: "b".into(), cid: None },
+            ]
+        );
+        assert!(new.changes(&new).is_empty());
+    }
+}
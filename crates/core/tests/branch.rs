@@ -0,0 +1,62 @@
+//! Branch rules over a real store.
+
+use craftsql_core::{
+    fast_forward, force_update, protected_branches, remove_branch, set_protected, Cid, Commit, PageStore,
+    PageStoreError, ProtectedStore, PROTECTED_REF,
+};
+use craftsql_store_mem::MemPageStore;
+
+#[test]
+fn test_branch_rules() {
+    let store = MemPageStore::new();
+    let first = Commit::new(Cid::from_bytes(b"v1"), vec![], "first").put(&store).unwrap();
+    let second = Commit::new(Cid::from_bytes(b"v2"), vec![first], "second").put(&store).unwrap();
+    let other = Commit::new(Cid::from_bytes(b"v3"), vec![first], "other").put(&store).unwrap();
+
+    // Forward only
+    fast_forward(&store, "main", first).unwrap();
+    fast_forward(&store, "main", second).unwrap();
+    fast_forward(&store, "main", second).unwrap();
+    let err = fast_forward(&store, "main", other).unwrap_err();
+    assert!(matches!(err, PageStoreError::NonFastForward { old, new, .. } if old == second && new == other));
+    assert_eq!(store.get_named_root("main").unwrap(), Some(second));
+
+    // Forced, until protected
+    assert!(!force_update(&store, "main", other).unwrap());
+    assert!(set_protected(&store, "main", true).unwrap());
+    assert!(!set_protected(&store, "main", true).unwrap());
+    assert_eq!(protected_branches(&store).unwrap().into_iter().collect::<Vec<_>>(), ["main"]);
+    assert!(matches!(force_update(&store, "main", first), Err(PageStoreError::Protected(_))));
+    assert!(matches!(remove_branch(&store, "main"), Err(PageStoreError::Protected(_))));
+    store.set_named_root("main", first).unwrap();
+    assert!(force_update(&store, "main", second).unwrap());
+
+    assert!(set_protected(&store, "main", false).unwrap());
+    assert_eq!(store.get_named_root(PROTECTED_REF).unwrap(), None);
+    assert!(remove_branch(&store, "main").unwrap());
+}
+
+#[test]
+fn test_protected_store() {
+    let store = ProtectedStore::new(MemPageStore::new());
+    let first = Commit::new(Cid::from_bytes(b"v1"), vec![], "first").put(&store).unwrap();
+    let second = Commit::new(Cid::from_bytes(b"v2"), vec![first], "second").put(&store).unwrap();
+    let other = Commit::new(Cid::from_bytes(b"v3"), vec![first], "other").put(&store).unwrap();
+    store.set_named_root("main", first).unwrap();
+    set_protected(&store, "main", true).unwrap();
+
+    // Setting and removing refs directly goes by the same rules
+    store.set_named_root("main", second).unwrap();
+    store.set_named_root("main", second).unwrap();
+    assert!(matches!(store.set_named_root("main", other), Err(PageStoreError::Protected(_))));
+    assert!(matches!(store.remove_named_root("main"), Err(PageStoreError::Protected(_))));
+    assert_eq!(store.get_named_root("main").unwrap(), Some(second));
+
+    // Other refs move freely
+    store.set_named_root("topic", second).unwrap();
+    store.set_named_root("topic", other).unwrap();
+    assert!(store.remove_named_root("topic").unwrap());
+
+    set_protected(&store, "main", false).unwrap();
+    store.set_named_root("main", other).unwrap();
+}
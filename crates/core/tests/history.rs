@@ -0,0 +1,157 @@
+//! Commit history over a real store: commits, ancestry, logs and
+//! reachability.
+
+use craftsql_core::{
+    commit_at, is_ancestor, log, merge_base, page_table_root, reachable, Cid, Commit, LogEntry, Page, PageStore,
+    PageTable,
+};
+use craftsql_store_mem::MemPageStore;
+
+fn commit(store: &MemPageStore, message: &str, time: u64, parents: &[Cid]) -> Cid {
+    let root = store.put(&Page { data: message.as_bytes().to_vec() }).unwrap();
+    Commit { time, ..Commit::new(root, parents.to_vec(), message) }.put(store).unwrap()
+}
+
+#[test]
+fn test_commit_roundtrip() {
+    let store = MemPageStore::new();
+    let c = commit(&store, "first", 1, &[]);
+    let loaded = Commit::load(&store, &c).unwrap().unwrap();
+    assert_eq!(loaded.message, "first");
+    assert_eq!(page_table_root(&store, &c).unwrap(), loaded.root);
+
+    // Page tables aren't commits, and commits aren't page tables
+    let pt = store.put(&Page { data: PageTable::new().to_bytes() }).unwrap();
+    assert_eq!(Commit::load(&store, &pt).unwrap(), None);
+    assert_eq!(page_table_root(&store, &pt).unwrap(), pt);
+    assert!(PageTable::from_bytes(&store.get(&c).unwrap().data).is_err());
+}
+
+#[test]
+fn test_merge_base() {
+    let store = MemPageStore::new();
+    //   base - a1 - a2 ----- m
+    //      \           /
+    //       b1 ------ b2
+    let base = commit(&store, "base", 1, &[]);
+    let a1 = commit(&store, "a1", 2, &[base]);
+    let a2 = commit(&store, "a2", 3, &[a1]);
+    let b1 = commit(&store, "b1", 2, &[base]);
+    let b2 = commit(&store, "b2", 4, &[b1]);
+    let m = commit(&store, "m", 5, &[a2, b2]);
+    let c = commit(&store, "c", 6, &[b2]);
+
+    assert_eq!(merge_base(&store, a2, b2).unwrap(), Some(base));
+    assert_eq!(merge_base(&store, m, c).unwrap(), Some(b2));
+    assert_eq!(merge_base(&store, a1, m).unwrap(), Some(a1));
+    assert_eq!(merge_base(&store, m, m).unwrap(), Some(m));
+    assert_eq!(merge_base(&store, a2, commit(&store, "unrelated", 7, &[])).unwrap(), None);
+
+    assert!(is_ancestor(&store, base, m).unwrap());
+    assert!(is_ancestor(&store, b1, c).unwrap());
+    assert!(!is_ancestor(&store, a1, c).unwrap());
+    assert!(!is_ancestor(&store, m, a2).unwrap());
+}
+
+#[test]
+fn test_commit_at() {
+    let store = MemPageStore::new();
+    let first = commit(&store, "first", 100, &[]);
+    let side = commit(&store, "side", 250, &[first]);
+    let second = commit(&store, "second", 200, &[first]);
+    let merge = commit(&store, "merge", 300, &[second, side]);
+
+    assert_eq!(commit_at(&store, merge, 99).unwrap(), None);
+    assert_eq!(commit_at(&store, merge, 100).unwrap(), Some(first));
+    assert_eq!(commit_at(&store, merge, 249).unwrap(), Some(second));
+    assert_eq!(commit_at(&store, merge, 299).unwrap(), Some(side));
+    assert_eq!(commit_at(&store, merge, u64::MAX).unwrap(), Some(merge));
+    assert_eq!(commit_at(&store, second, 299).unwrap(), Some(second));
+}
+
+#[test]
+fn test_criss_cross_merge_base() {
+    let store = MemPageStore::new();
+    // Two merges of the same pair of commits, in opposite directions
+    let base = commit(&store, "base", 1, &[]);
+    let a = commit(&store, "a", 2, &[base]);
+    let b = commit(&store, "b", 3, &[base]);
+    let x = commit(&store, "x", 4, &[a, b]);
+    let y = commit(&store, "y", 5, &[b, a]);
+    assert_eq!(merge_base(&store, x, y).unwrap(), Some(b));
+}
+
+#[test]
+fn test_missing_history() {
+    let store = MemPageStore::new();
+    // A shallow copy: the first commit never made it into the store
+    let missing = Cid::from_bytes(b"first commit");
+    let second = commit(&store, "second", 200, &[missing]);
+    let third = commit(&store, "third", 300, &[second]);
+
+    assert!(is_ancestor(&store, second, third).unwrap());
+    assert!(!is_ancestor(&store, third, second).unwrap());
+    assert_eq!(commit_at(&store, third, 250).unwrap(), Some(second));
+    assert_eq!(commit_at(&store, third, 100).unwrap(), None);
+    assert_eq!(merge_base(&store, third, second).unwrap(), Some(second));
+}
+
+#[test]
+fn test_log() {
+    let store = MemPageStore::new();
+    let commit = |pages: &[&[u8]], parents: &[Cid], time: u64| {
+        let mut pt = PageTable::new();
+        for (i, data) in pages.iter().enumerate() {
+            pt.set(i, store.put(&Page { data: data.to_vec() }).unwrap());
+        }
+        let root = store.put(&Page { data: pt.to_bytes() }).unwrap();
+        let message = format!("at {}", time);
+        Commit { time, ..Commit::new(root, parents.to_vec(), &message).with_author("ada") }.put(&store).unwrap()
+    };
+    //   first - a ------ merge
+    //       \          /
+    //        b (newer)
+    let first = commit(&[b"one", b"two"], &[], 100);
+    let a = commit(&[b"one", b"TWO"], &[first], 200);
+    let b = commit(&[b"ONE", b"two", b"three"], &[first], 300);
+    let merge = commit(&[b"ONE", b"TWO", b"three"], &[a, b], 150);
+
+    let entries: Vec<LogEntry> = log(&store, merge).map(|entry| entry.unwrap()).collect();
+    let cids: Vec<Cid> = entries.iter().map(|entry| entry.cid).collect();
+    assert_eq!(cids, vec![merge, b, a, first]);
+    assert_eq!(entries[0].parents, vec![a, b]);
+    assert_eq!((entries[0].author.as_str(), entries[0].message.as_str(), entries[0].time), ("ada", "at 150", 150));
+    let changed: Vec<Option<usize>> = entries.iter().map(|entry| entry.pages_changed).collect();
+    assert_eq!(changed, vec![Some(2), Some(2), Some(1), Some(2)]);
+
+    // Bare page tables have no log; a missing parent ends it
+    assert_eq!(log(&store, Commit::load(&store, &first).unwrap().unwrap().root).count(), 0);
+    let shallow = commit(&[b"x"], &[Cid::from_bytes(b"not stored")], 400);
+    let entries: Vec<LogEntry> = log(&store, shallow).map(|entry| entry.unwrap()).collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].pages_changed, None);
+}
+
+#[test]
+fn test_reachable() {
+    let store = MemPageStore::new();
+    let put = |data: &[u8]| store.put(&Page { data: data.to_vec() }).unwrap();
+    let mut table = PageTable::new();
+    table.set(0, put(b"page 0"));
+    table.set(2, put(b"page 2"));
+    let v1 = put(&table.to_bytes());
+    let first = Commit::new(v1, vec![], "first").put(&store).unwrap();
+    table.set(2, put(b"page 2 changed"));
+    let v2 = put(&table.to_bytes());
+    let second = Commit::new(v2, vec![first], "second").put(&store).unwrap();
+    let stray = put(b"stray");
+    let missing = Cid::from_bytes(b"missing");
+
+    let marked = reachable(&store, &[second, missing]).unwrap();
+    assert_eq!(marked.len(), 8);
+    assert!(marked.contains(&Cid::from_bytes(b"page 2")) && marked.contains(&missing));
+    assert!(!marked.contains(&stray));
+
+    // A page table root reaches only its own pages
+    assert_eq!(reachable(&store, &[v1]).unwrap().len(), 3);
+}
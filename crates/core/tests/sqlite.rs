@@ -0,0 +1,177 @@
+//! Importing and exporting SQLite files, and mapping their pages, over a
+//! real store.
+
+use craftsql_core::{
+    changed_objects, export_sqlite, import_sqlite, page_map, Commit, Page, PageMap, PageOwner, PageStore, PageTable,
+};
+use craftsql_store_mem::MemPageStore;
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A made-up three-page database in WAL mode.
+fn database() -> Vec<u8> {
+    let mut data = vec![0u8; 3 * 512];
+    data[..16].copy_from_slice(b"SQLite format 3\0");
+    data[16..18].copy_from_slice(&512u16.to_be_bytes());
+    data[18..20].copy_from_slice(&[2, 2]);
+    data[60..64].copy_from_slice(&7u32.to_be_bytes());
+    data[600] = 1;
+    data[1100] = 2;
+    data
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[test]
+fn test_import_and_export() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = MemPageStore::new();
+    let original = dir.path().join("original.db");
+    fs::write(&original, database()).unwrap();
+
+    let imported = import_sqlite(&store, &original, "import").unwrap();
+    assert_eq!((imported.pages, imported.page_size), (3, 512));
+    let commit = Commit::load(&store, &imported.commit).unwrap().unwrap();
+    assert_eq!((commit.root, commit.message.as_str()), (imported.page_table, "import"));
+
+    let exported = dir.path().join("exported.db");
+    assert_eq!(export_sqlite(&store, &imported.commit, &exported).unwrap(), 3 * 512);
+    let mut expected = database();
+    expected[18..20].copy_from_slice(&[1, 1]);
+    assert_eq!(fs::read(&exported).unwrap(), expected);
+    assert!(!with_suffix(&exported, ".partial").exists());
+
+    // The page table records the header's page size and user_version
+    let mut table = PageTable::from_bytes(&store.get(&imported.page_table).unwrap().data).unwrap();
+    assert_eq!((table.page_size, table.meta.schema_version), (512, 7));
+
+    // Missing entries are zero pages
+    table.entries[1] = None;
+    let sparse = store.put(&Page { data: table.to_bytes() }).unwrap();
+    export_sqlite(&store, &sparse, &exported).unwrap();
+    assert!(fs::read(&exported).unwrap()[512..1024].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn test_import_refuses() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = MemPageStore::new();
+    let path = dir.path().join("db");
+    fs::write(&path, b"not a database").unwrap();
+    assert!(import_sqlite(&store, &path, "").is_err());
+
+    fs::write(&path, &database()[..1000]).unwrap();
+    assert!(import_sqlite(&store, &path, "").is_err());
+
+    fs::write(&path, database()).unwrap();
+    fs::write(with_suffix(&path, "-wal"), b"frames").unwrap();
+    assert!(import_sqlite(&store, &path, "").is_err());
+    fs::write(with_suffix(&path, "-wal"), b"").unwrap();
+    import_sqlite(&store, &path, "").unwrap();
+}
+
+/// Page counts by b-tree name, as SQLite itself reports them.
+fn dbstat(db: &Connection) -> BTreeMap<String, usize> {
+    let mut stmt = db.prepare("SELECT name, count(*) FROM dbstat GROUP BY name").unwrap();
+    let rows = stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)? as usize))).unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+fn map(path: &Path) -> PageMap {
+    let store = MemPageStore::new();
+    let imported = import_sqlite(&store, path, "").unwrap();
+    page_map(&store, &imported.commit).unwrap()
+}
+
+fn counts(map: &PageMap) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for (page, _) in map.iter() {
+        if let Some(object) = map.object(page) {
+            *counts.entry(object.name.clone()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+#[test]
+fn test_page_map() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db");
+    let db = Connection::open(&path).unwrap();
+    db.execute_batch("
+        PRAGMA page_size = 1024;
+        CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, body BLOB);
+        CREATE INDEX t_name ON t (name);
+        CREATE TABLE gone (x);
+        CREATE VIEW v AS SELECT id FROM t;
+        WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300)
+            INSERT INTO t SELECT i, 'name ' || i, zeroblob(i * 10) FROM n;
+        INSERT INTO gone SELECT body FROM t;
+        DROP TABLE gone;
+    ").unwrap();
+
+    let map = map(&path);
+    assert_eq!(map.page_size, 1024);
+    let freelist: usize = db.query_row("PRAGMA freelist_count", [], |r| r.get(0)).unwrap();
+    assert!(freelist > 0);
+    assert_eq!(map.iter().filter(|(_, owner)| *owner == PageOwner::Freelist).count(), freelist);
+    assert_eq!(counts(&map), dbstat(&db));
+    // Views have no pages, and dropped tables are gone
+    assert_eq!(map.objects.iter().map(|o| o.name.as_str()).collect::<Vec<_>>(), ["sqlite_schema", "t", "t_name"]);
+
+    let t = map.find("t").unwrap();
+    assert_eq!(map.pages(t).next(), Some(map.objects[t].root_page));
+    assert_eq!(map.object(map.objects[t].root_page).unwrap().name, "t");
+    let index = &map.objects[map.find("t_name").unwrap()];
+    assert_eq!((index.kind.as_str(), index.table.as_str()), ("index", "t"));
+    assert_eq!(map.owner(map.len() as u32 + 1), None);
+}
+
+#[test]
+fn test_auto_vacuum() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db");
+    let db = Connection::open(&path).unwrap();
+    db.execute_batch("
+        PRAGMA page_size = 512;
+        PRAGMA auto_vacuum = FULL;
+        CREATE TABLE t (body BLOB);
+        WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 200)
+            INSERT INTO t SELECT zeroblob(600) FROM n;
+    ").unwrap();
+
+    let map = map(&path);
+    // One pointer map page for every 102 pages
+    let pointer_maps: Vec<u32> = map.iter().filter(|(_, owner)| *owner == PageOwner::PointerMap).map(|(page, _)| page).collect();
+    assert_eq!(pointer_maps[..2], [2, 105]);
+    assert_eq!(counts(&map), dbstat(&db));
+    assert!(!map.iter().any(|(_, owner)| owner == PageOwner::Unused));
+}
+
+#[test]
+fn test_changed_objects() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db");
+    let db = Connection::open(&path).unwrap();
+    db.execute_batch("
+        CREATE TABLE a (x);
+        CREATE TABLE b (x);
+        INSERT INTO a VALUES (1);
+    ").unwrap();
+    let store = MemPageStore::new();
+    let old = import_sqlite(&store, &path, "").unwrap().page_table;
+    db.execute("INSERT INTO b VALUES (2)", []).unwrap();
+    let new = import_sqlite(&store, &path, "").unwrap().page_table;
+
+    let table = |cid| PageTable::from_bytes(&store.get(&cid).unwrap().data).unwrap();
+    let diff = table(new).diff(&table(old));
+    let changed = changed_objects(&diff, &page_map(&store, &old).unwrap(), &page_map(&store, &new).unwrap());
+    // Page 1 changes with every write, for its change counter
+    assert_eq!(changed, [("b".to_string(), 1), ("sqlite_schema".to_string(), 1)].into());
+}
@@ -0,0 +1,89 @@
+//! Store-generic helpers over a real store.
+
+use craftsql_core::{usage, Cid, Commit, Page, PageStore, PageStoreError, PageStoreExt, PageTable};
+use craftsql_store_mem::MemPageStore;
+use std::sync::Arc;
+
+#[test]
+fn test_dyn_stores() {
+    // Generic code takes boxed and shared trait objects as stores
+    fn has<S: PageStore>(store: S, cid: &Cid) -> bool {
+        store.has(cid).unwrap()
+    }
+    let store = MemPageStore::new();
+    let cid = store.put(&Page { data: b"page".to_vec() }).unwrap();
+    let boxed: Box<dyn PageStore> = Box::new(store);
+    let shared: Arc<dyn PageStore> = boxed.into();
+    assert!(has(Arc::clone(&shared), &cid));
+    assert!(has(Box::new(shared) as Box<dyn PageStore>, &cid));
+}
+
+#[test]
+fn test_page_store_ext() {
+    let store = MemPageStore::new();
+    let put = |data: &[u8]| store.put(&Page { data: data.to_vec() }).unwrap();
+    assert!(PageStoreExt::snapshot(&store, "v0").is_err());
+
+    let mut table = PageTable::new();
+    table.set(0, put(b"page 0"));
+    table.set(1, put(b"page 1"));
+    let v1 = put(&table.to_bytes());
+    let first = Commit::new(v1, vec![], "first").put(&store).unwrap();
+    table.set(1, put(b"page 1 changed"));
+    let v2 = put(&table.to_bytes());
+    let second = Commit::new(v2, vec![first], "second").put(&store).unwrap();
+    put(b"stray");
+
+    store.update_root(first).unwrap();
+    assert_eq!(PageStoreExt::snapshot(&store, "v1").unwrap(), first);
+    store.set_named_root("main", second).unwrap();
+    assert_eq!(store.checkout("main").unwrap(), second);
+    assert_eq!(store.current_root().unwrap(), Some(second));
+    assert_eq!(store.fork("v1", "old").unwrap(), first);
+    assert!(store.fork("nope", "x").is_err());
+
+    let mut visited = Vec::new();
+    store.walk_root(second, |cid, _| {
+        visited.push(*cid);
+        Ok(())
+    }).unwrap();
+    assert_eq!(visited.len(), 7);
+    assert_eq!(visited[0], second);
+    assert!(!visited.contains(&Cid::from_bytes(b"stray")));
+
+    let other = MemPageStore::new();
+    assert_eq!(store.copy_to(&other).unwrap(), 7);
+    assert_eq!(other.current_root().unwrap(), Some(second));
+    assert_eq!(other.list_named_roots().unwrap(), store.list_named_roots().unwrap());
+    assert_eq!(store.copy_to(&other).unwrap(), 0);
+
+    // A missing page stops the walk
+    table.set(2, Cid::from_bytes(b"missing"));
+    let broken = put(&table.to_bytes());
+    assert!(matches!(store.walk_root(broken, |_, _| Ok(())), Err(PageStoreError::NotFound(_))));
+}
+
+#[test]
+fn test_usage() {
+    let store = MemPageStore::new();
+    let put = |data: &[u8]| store.put(&Page { data: data.to_vec() }).unwrap();
+    let table = |cids: &[Cid]| {
+        let mut table = PageTable::new();
+        for (i, cid) in cids.iter().enumerate() {
+            table.set(i, *cid);
+        }
+        put(&table.to_bytes())
+    };
+    let shared = put(&[0; 100]);
+    let a = table(&[shared, put(&[1; 10])]);
+    let b = table(&[shared, put(&[2; 20])]);
+
+    let groups = vec![("a".to_string(), vec![a]), ("b".to_string(), vec![b, a])];
+    let usage = usage(&store, &groups).unwrap();
+    let table_bytes = store.get(&a).unwrap().data.len() as u64;
+    assert_eq!((usage[0].pages, usage[0].bytes), (3, table_bytes + 110));
+    // Everything `a` reaches, `b` does too
+    assert_eq!((usage[0].unique_pages, usage[0].unique_bytes), (0, 0));
+    assert_eq!((usage[1].unique_pages, usage[1].unique_bytes), (2, table_bytes + 20));
+    assert_eq!(usage[1].shared_bytes(), table_bytes + 110);
+}
@@ -0,0 +1,304 @@
+//! CraftSQL — a versioned SQLite database behind one handle.
+//!
+//! [`Database::open`] opens the store a [`Config`] names (the same
+//! locations as the `craftsql` command), registers the CraftSQL VFS over
+//! it, and opens a SQLite connection through that at the current root.
+//! Committed transactions move the root; the handle's methods version it:
+//!
+//! ```no_run
+//! use craftsql::{Config, Database};
+//!
+//! let mut db = Database::open(Config::new("./db"))?;
+//! db.connection().execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);")?;
+//! db.snapshot("v1")?;
+//! db.connection().execute("INSERT INTO t VALUES (2)", [])?;
+//! let diff = db.diff("v1", None)?;
+//! assert_eq!(diff.tables[0].inserted.len(), 1);
+//! db.checkout("v1")?;
+//! # Ok::<(), craftsql::Error>(())
+//! ```
+//!
+//! Anything the handle doesn't cover is one step away: [`Database::store`]
+//! is the store itself, for the other crates' functions.
+
+use std::io::sink;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use craftsql_cli::commands;
+use craftsql_cli::refs::{self, resolve, snapshot_ref, HEAD_REF};
+use craftsql_cli::store::StoreSpec;
+use craftsql_core::{page_table_root, Cid, CommitMeta, LogEntry, PageStore, PageStoreError};
+use craftsql_diff::{DatabaseDiff, DiffError};
+use craftsql_vfs::{ConnectionMeta, RootControl, DEFAULT_EXCLUSIVE_WAIT};
+use rusqlite::{Connection, OpenFlags};
+
+/// Errors from a [`Database`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Store(#[from] PageStoreError),
+    #[error(transparent)]
+    Command(#[from] craftsql_cli::Error),
+    #[error(transparent)]
+    Diff(#[from] DiffError),
+    #[error("sqlite: {0}")]
+    Sql(#[from] rusqlite::Error),
+    #[error("register VFS: {0}")]
+    Register(String),
+    #[error("the connection has a transaction open; commit or roll it back first")]
+    InTransaction,
+    #[error("another connection kept its transaction open")]
+    Busy,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+static VFS_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Where a [`Database`] lives.
+#[derive(Debug, Clone)]
+pub struct Config {
+    store: StoreSpec,
+    cache_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// The store at `store`: a local directory, `unix:<path>`,
+    /// `tcp://<host:port>`, or `http://...` (see [`StoreSpec`]).
+    pub fn new(store: &str) -> Self {
+        Self { store: StoreSpec::parse(store), cache_dir: None }
+    }
+
+    /// Cache a daemon-backed store's pages in `dir` instead of a
+    /// per-location temp directory.
+    pub fn with_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+}
+
+/// A database: its store, and a SQLite connection to its current root.
+pub struct Database {
+    store: Arc<dyn PageStore>,
+    vfs: String,
+    control: RootControl<dyn PageStore>,
+    /// The connection's commit meta, kept across reconnects.
+    meta: ConnectionMeta,
+    db: Connection,
+}
+
+impl Database {
+    /// Open the database `config` names, creating it if the store is empty.
+    pub fn open(config: Config) -> Result<Self> {
+        let store = config.store.open(config.cache_dir.as_deref())?;
+        Self::open_store(store.into())
+    }
+
+    /// Open the database in `store`, such as one built in code.
+    ///
+    /// Each call registers its own VFS with SQLite; registrations last for
+    /// the life of the process.
+    pub fn open_store(store: Arc<dyn PageStore>) -> Result<Self> {
+        let vfs = format!("craftsql_{}_{}", std::process::id(), VFS_COUNTER.fetch_add(1, Ordering::SeqCst));
+        let control = craftsql_vfs::register_with_control(&vfs, Arc::clone(&store))
+            .map_err(|e| Error::Register(format!("{:?}", e)))?;
+        let meta = ConnectionMeta::default();
+        let db = Self::connect(&vfs, &control, &meta)?;
+        Ok(Self { store, vfs, control, meta, db })
+    }
+
+    fn connect(vfs: &str, control: &RootControl<dyn PageStore>, meta: &ConnectionMeta) -> Result<Connection> {
+        let db = Connection::open_with_flags_and_vfs(control.connection_path(meta), OpenFlags::default(), vfs)?;
+        db.execute_batch("PRAGMA journal_mode=DELETE;")?;
+        Ok(db)
+    }
+
+    /// The connection to the current root.
+    pub fn connection(&self) -> &Connection {
+        &self.db
+    }
+
+    pub fn store(&self) -> &Arc<dyn PageStore> {
+        &self.store
+    }
+
+    /// The current root, or `None` before anything is written.
+    pub fn root(&self) -> Result<Option<Cid>> {
+        Ok(self.store.current_root()?)
+    }
+
+    /// Snapshot the current root as `name`; returns the root. See
+    /// [`snapshot_consistent`](Self::snapshot_consistent) for a snapshot
+    /// that can't race a commit.
+    pub fn snapshot(&self, name: &str) -> Result<Cid> {
+        commands::snapshot_create(self.store.as_ref(), name, None, &mut sink())?;
+        self.named_root(&snapshot_ref(name))
+    }
+
+    /// Snapshot as `name` the root the last committed transaction left,
+    /// read under a read transaction on the connection; returns the root.
+    ///
+    /// While the read lock is held no connection through this database's
+    /// VFS can commit, so the root can't move between being read and being
+    /// recorded, and commits write their pages out before they let go of
+    /// their locks: the snapshot is always a whole, committed state.
+    /// Refused while the connection has a transaction of its own open,
+    /// whose changes it would leave out.
+    pub fn snapshot_consistent(&self, name: &str) -> Result<Cid> {
+        if !self.db.is_autocommit() {
+            return Err(Error::InTransaction);
+        }
+        let tx = self.db.unchecked_transaction()?;
+        // A deferred transaction takes its lock at its first read
+        self.db.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))?;
+        let root = self.store.current_root()?.ok_or(craftsql_cli::Error::NoRoot)?;
+        commands::snapshot_create(self.store.as_ref(), name, Some(&root.to_hex()), &mut sink())?;
+        tx.commit()?;
+        Ok(root)
+    }
+
+    /// Create branch `name` at the current root; returns the root.
+    pub fn branch(&self, name: &str) -> Result<Cid> {
+        commands::branch_create(self.store.as_ref(), name, None, false, &mut sink())?;
+        self.named_root(name)
+    }
+
+    /// Record `meta`'s author and message with every transaction the
+    /// connection commits from now on, where the store keeps such
+    /// provenance (see [`PageStore::update_root_with_meta`]); `None` stops
+    /// recording.
+    pub fn set_commit_meta(&self, meta: Option<CommitMeta>) {
+        self.meta.set(meta);
+    }
+
+    /// Commit the current root onto `branch`; returns the commit.
+    pub fn commit(&self, branch: &str, message: &str) -> Result<Cid> {
+        commands::commit(self.store.as_ref(), branch, message, "", &mut sink())?;
+        self.named_root(branch)
+    }
+
+    /// Point the current root at a branch, snapshot, or CID, and reopen
+    /// the connection there; returns the new root.
+    ///
+    /// Refused while the connection has a transaction open. The root moves
+    /// under the VFS's exclusive lock, so no connection through it commits
+    /// its buffers of the old root over the new one.
+    pub fn checkout(&mut self, rev: &str) -> Result<Cid> {
+        if !self.db.is_autocommit() {
+            return Err(Error::InTransaction);
+        }
+        self.control
+            .exclusive(DEFAULT_EXCLUSIVE_WAIT, |store| commands::checkout(store, rev, &mut sink()))
+            .ok_or(Error::Busy)??;
+        // SQLite keeps its page cache while the header's change counter
+        // matches, which two versions can share
+        self.db = Self::connect(&self.vfs, &self.control, &self.meta)?;
+        Ok(self.store.current_root()?.expect("checked out a root"))
+    }
+
+    /// The row changes from `old` to `new` (default: the current root),
+    /// each a branch, snapshot, or CID.
+    pub fn diff(&self, old: &str, new: Option<&str>) -> Result<DatabaseDiff> {
+        let old = page_table_root(self.store.as_ref(), &resolve(self.store.as_ref(), old)?)?;
+        let new = match new {
+            Some(new) => page_table_root(self.store.as_ref(), &resolve(self.store.as_ref(), new)?)?,
+            None => self.store.current_root()?.ok_or(craftsql_cli::Error::NoRoot)?,
+        };
+        Ok(craftsql_diff::diff_roots(Arc::clone(&self.store), old, new)?)
+    }
+
+    /// The commits behind `rev` (default: the one last checked out or
+    /// made), newest first.
+    pub fn history(&self, rev: Option<&str>) -> Result<Vec<LogEntry>> {
+        let from = match rev {
+            Some(rev) => resolve(self.store.as_ref(), rev)?,
+            None => self.store.get_named_root(HEAD_REF)?.ok_or(craftsql_cli::Error::NoHead)?,
+        };
+        Ok(craftsql_core::log(self.store.as_ref(), from).collect::<craftsql_core::Result<_>>()?)
+    }
+
+    /// Snapshot names and roots.
+    pub fn snapshots(&self) -> Result<Vec<(String, Cid)>> {
+        Ok(refs::snapshots(self.store.as_ref())?)
+    }
+
+    /// Branch names and what they point at.
+    pub fn branches(&self) -> Result<Vec<(String, Cid)>> {
+        Ok(refs::branches(self.store.as_ref())?)
+    }
+
+    fn named_root(&self, name: &str) -> Result<Cid> {
+        Ok(self.store.get_named_root(name)?.ok_or_else(|| craftsql_cli::Error::UnknownRef(name.to_string()))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+
+    fn count(db: &Database) -> i64 {
+        db.connection().query_row("SELECT count(*) FROM t", [], |r| r.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_database() {
+        let mut db = Database::open_store(Arc::new(MemPageStore::new())).unwrap();
+        assert_eq!(db.root().unwrap(), None);
+        db.connection().execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY, x); INSERT INTO t VALUES (1, 'a');").unwrap();
+        let v1 = db.snapshot("v1").unwrap();
+        assert_eq!(Some(v1), db.root().unwrap());
+        db.branch("main").unwrap();
+        let first = db.commit("main", "one row").unwrap();
+
+        db.connection().execute("INSERT INTO t VALUES (2, 'b')", []).unwrap();
+        let diff = db.diff("v1", None).unwrap();
+        assert_eq!(diff.tables[0].inserted.len(), 1);
+        let second = db.commit("main", "two rows").unwrap();
+        let history: Vec<Cid> = db.history(None).unwrap().into_iter().map(|entry| entry.cid).collect();
+        assert_eq!(history, [second, first]);
+
+        // The connection follows the checkout
+        assert_eq!(db.checkout("v1").unwrap(), v1);
+        assert_eq!(count(&db), 1);
+        db.checkout("main").unwrap();
+        assert_eq!(count(&db), 2);
+        db.connection().execute_batch("BEGIN; INSERT INTO t VALUES (3, 'c');").unwrap();
+        assert!(matches!(db.checkout("v1"), Err(Error::InTransaction)));
+        db.connection().execute_batch("ROLLBACK").unwrap();
+        assert!(db.diff("v1", Some("main")).unwrap().tables[0].deleted.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_consistent() {
+        let db = Database::open_store(Arc::new(MemPageStore::new())).unwrap();
+        db.connection().execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);").unwrap();
+        let committed = db.root().unwrap().unwrap();
+
+        db.connection().execute_batch("BEGIN; INSERT INTO t VALUES (2);").unwrap();
+        assert!(matches!(db.snapshot_consistent("v1"), Err(Error::InTransaction)));
+        db.connection().execute_batch("COMMIT").unwrap();
+        let root = db.snapshot_consistent("v1").unwrap();
+        assert_ne!(root, committed);
+        assert_eq!(db.snapshots().unwrap(), [("v1".to_string(), root)]);
+        // The lock is released: writes go on
+        db.connection().execute("INSERT INTO t VALUES (3)", []).unwrap();
+        assert_eq!(count(&db), 3);
+    }
+
+    #[test]
+    fn test_open_config() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = Config::new(tmp.path().to_str().unwrap());
+        let db = Database::open(config.clone()).unwrap();
+        db.connection().execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);").unwrap();
+        drop(db);
+        assert_eq!(count(&Database::open(config).unwrap()), 1);
+        assert!(matches!(
+            Database::open(Config::new(tmp.path().to_str().unwrap())).unwrap().snapshot("bad name"),
+            Err(Error::Command(craftsql_cli::Error::InvalidName(_)))
+        ));
+    }
+}
@@ -0,0 +1,379 @@
+//! CraftSQL Diff — what data changed between two database versions.
+//!
+//! Compares two SQLite databases table by table: schema changes, and rows
+//! inserted, deleted, or updated, matched by primary key (or rowid for
+//! tables without one). [`diff_roots`] opens two roots of a PageStore
+//! read-only through the VFS and diffs them; [`diff_connections`] works on
+//! any pair of open connections. [`open_root`] and [`open_at`] open one
+//! version by page table or by time, and [`export_via_backup`] copies one
+//! to a plain SQLite file with SQLite's backup API.
+//!
+//! [`merge_roots`] and [`merge_connections`] do the reverse: apply the
+//! changes one side made since a common base onto the other, and
+//! [`rebase`] replays a line of commits that way on top of another.
+
+mod merge;
+mod open;
+mod rebase;
+
+pub use merge::{merge_connections, Conflict, ConflictPolicy, MergeReport};
+pub use open::{diff_roots, export_via_backup, merge_roots, open_at, open_root};
+pub use rebase::{rebase, RebaseReport};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use craftsql_core::{Cid, PageStoreError};
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::ToSql;
+use rusqlite::Connection;
+use serde::{Serialize, Serializer};
+
+/// Diff errors.
+#[derive(Debug, thiserror::Error)]
+pub enum DiffError {
+    #[error(transparent)]
+    Store(#[from] PageStoreError),
+    #[error("sqlite: {0}")]
+    Sql(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("register VFS: {0}")]
+    Register(String),
+    #[error("no commit at or before {0}")]
+    NoCommitAt(u64),
+    #[error("{} merge conflict(s)", .0.len())]
+    Conflicts(Vec<Conflict>),
+    #[error("{} conflict(s) replaying commit {commit}", .conflicts.len())]
+    RebaseConflicts { commit: Cid, conflicts: Vec<Conflict> },
+    #[error("{0} is not a commit")]
+    NotACommit(Cid),
+    #[error("{0} and {1} have no common history")]
+    NoCommonHistory(Cid, Cid),
+}
+
+pub type Result<T> = std::result::Result<T, DiffError>;
+
+/// A column value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl From<ValueRef<'_>> for SqlValue {
+    fn from(value: ValueRef<'_>) -> Self {
+        match value {
+            ValueRef::Null => SqlValue::Null,
+            ValueRef::Integer(i) => SqlValue::Integer(i),
+            ValueRef::Real(f) => SqlValue::Real(f),
+            ValueRef::Text(t) => SqlValue::Text(String::from_utf8_lossy(t).into_owned()),
+            ValueRef::Blob(b) => SqlValue::Blob(b.to_vec()),
+        }
+    }
+}
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Borrowed(match self {
+            SqlValue::Null => ValueRef::Null,
+            SqlValue::Integer(i) => ValueRef::Integer(*i),
+            SqlValue::Real(f) => ValueRef::Real(*f),
+            SqlValue::Text(t) => ValueRef::Text(t.as_bytes()),
+            SqlValue::Blob(b) => ValueRef::Blob(b),
+        }))
+    }
+}
+
+/// JSON scalars, with blobs as `{"blob": "<hex>"}`.
+impl Serialize for SqlValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            SqlValue::Null => serializer.serialize_none(),
+            SqlValue::Integer(i) => serializer.serialize_i64(*i),
+            SqlValue::Real(f) => serializer.serialize_f64(*f),
+            SqlValue::Text(t) => serializer.serialize_str(t),
+            SqlValue::Blob(b) => {
+                use serde::ser::SerializeMap;
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("blob", &hex::encode(b))?;
+                map.end()
+            }
+        }
+    }
+}
+
+/// A row (or part of one) by column name.
+pub type Row = BTreeMap<String, SqlValue>;
+
+/// A table's `CREATE` statement on each side; `None` where it doesn't exist.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaChange {
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// A row present on both sides with different values.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RowUpdate {
+    /// Primary key columns (or `rowid`).
+    pub key: Row,
+    pub old: Row,
+    pub new: Row,
+}
+
+impl RowUpdate {
+    /// Columns whose value differs, with old and new values.
+    pub fn changed_columns(&self) -> Vec<(&str, Option<&SqlValue>, Option<&SqlValue>)> {
+        let columns: BTreeSet<&String> = self.old.keys().chain(self.new.keys()).collect();
+        columns.into_iter()
+            .map(|col| (col.as_str(), self.old.get(col), self.new.get(col)))
+            .filter(|(_, old, new)| old != new)
+            .collect()
+    }
+}
+
+/// Changes to one table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TableDiff {
+    pub table: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<SchemaChange>,
+    pub inserted: Vec<Row>,
+    pub deleted: Vec<Row>,
+    pub updated: Vec<RowUpdate>,
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.schema.is_none() && self.inserted.is_empty() && self.deleted.is_empty() && self.updated.is_empty()
+    }
+}
+
+/// Changes between two databases, one entry per changed table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DatabaseDiff {
+    pub tables: Vec<TableDiff>,
+}
+
+impl DatabaseDiff {
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("diff serialization")
+    }
+
+    /// Human-readable rendering: `+` inserted, `-` deleted, `~` updated rows.
+    pub fn write_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        for table in &self.tables {
+            writeln!(out, "table {}", table.table)?;
+            if let Some(schema) = &table.schema {
+                let change = match (&schema.old, &schema.new) {
+                    (None, _) => "created",
+                    (_, None) => "dropped",
+                    _ => "changed",
+                };
+                writeln!(out, "  schema {}", change)?;
+            }
+            for row in &table.inserted {
+                writeln!(out, "  + {}", json(row))?;
+            }
+            for row in &table.deleted {
+                writeln!(out, "  - {}", json(row))?;
+            }
+            for update in &table.updated {
+                let changes: Vec<String> = update.changed_columns().into_iter()
+                    .map(|(col, old, new)| format!("{}: {} -> {}", col, json(&old), json(&new)))
+                    .collect();
+                writeln!(out, "  ~ {} {}", json(&update.key), changes.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compact JSON for text output.
+fn json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).expect("value serialization")
+}
+
+/// Diff two open databases, from `old` to `new`.
+pub fn diff_connections(old: &Connection, new: &Connection) -> Result<DatabaseDiff> {
+    let old_tables = tables(old)?;
+    let new_tables = tables(new)?;
+    let names: BTreeSet<&String> = old_tables.keys().chain(new_tables.keys()).collect();
+
+    let mut diff = DatabaseDiff::default();
+    for name in names {
+        let (old_sql, new_sql) = (old_tables.get(name), new_tables.get(name));
+        let mut table = TableDiff { table: name.clone(), ..Default::default() };
+        if old_sql != new_sql {
+            table.schema = Some(SchemaChange { old: old_sql.cloned(), new: new_sql.cloned() });
+        }
+
+        let old_rows = match old_sql {
+            Some(_) => read_rows(old, name)?,
+            None => BTreeMap::new(),
+        };
+        let new_rows = match new_sql {
+            Some(_) => read_rows(new, name)?,
+            None => BTreeMap::new(),
+        };
+        for (key, (key_row, row)) in &new_rows {
+            match old_rows.get(key) {
+                None => table.inserted.push(row.clone()),
+                Some((_, old_row)) if old_row != row => table.updated.push(RowUpdate {
+                    key: key_row.clone(),
+                    old: old_row.clone(),
+                    new: row.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        for (key, (_, row)) in &old_rows {
+            if !new_rows.contains_key(key) {
+                table.deleted.push(row.clone());
+            }
+        }
+
+        if !table.is_empty() {
+            diff.tables.push(table);
+        }
+    }
+    Ok(diff)
+}
+
+/// User tables and their `CREATE` statements.
+fn tables(db: &Connection) -> Result<BTreeMap<String, String>> {
+    let mut stmt = db.prepare(
+        "SELECT name, sql FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+    )?;
+    let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+fn quote(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// All rows of `table`, keyed by a canonical encoding of their key columns.
+///
+/// Rows are keyed by primary key; tables without one are keyed by `rowid`,
+/// which then also appears in the row.
+fn read_rows(db: &Connection, table: &str) -> Result<BTreeMap<String, (Row, Row)>> {
+    let mut stmt = db.prepare(&format!("PRAGMA table_info({})", quote(table)))?;
+    let mut pk: Vec<(i64, String)> = stmt.query_map([], |r| Ok((r.get::<_, i64>(5)?, r.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|(pk, _)| *pk > 0)
+        .collect();
+    pk.sort();
+    let key_columns: Vec<String> = if pk.is_empty() {
+        vec!["rowid".to_string()]
+    } else {
+        pk.into_iter().map(|(_, name)| name).collect()
+    };
+
+    let select = if key_columns == ["rowid"] { "rowid AS rowid, *" } else { "*" };
+    let mut stmt = db.prepare(&format!("SELECT {} FROM {}", select, quote(table)))?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+
+    let mut rows = BTreeMap::new();
+    let mut query = stmt.query([])?;
+    while let Some(r) = query.next()? {
+        let mut row = Row::new();
+        for (i, column) in columns.iter().enumerate() {
+            row.insert(column.clone(), SqlValue::from(r.get_ref(i)?));
+        }
+        let key_row: Row = key_columns.iter()
+            .map(|col| (col.clone(), row.get(col).cloned().unwrap_or(SqlValue::Null)))
+            .collect();
+        let key_values: Vec<&SqlValue> = key_row.values().collect();
+        let key = serde_json::to_string(&key_values).expect("key serialization");
+        rows.insert(key, (key_row, row));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db(sql: &str) -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(sql).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_row_changes() {
+        let old = db("
+            CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, price REAL);
+            INSERT INTO items VALUES (1, 'alpha', 1.5), (2, 'beta', 2.5), (3, 'gamma', 3.5);
+        ");
+        let new = db("
+            CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, price REAL);
+            INSERT INTO items VALUES (1, 'alpha', 1.5), (2, 'beta', 9.0), (4, 'delta', 4.5);
+        ");
+
+        let diff = diff_connections(&old, &new).unwrap();
+        assert_eq!(diff.tables.len(), 1);
+        let items = &diff.tables[0];
+        assert_eq!(items.schema, None);
+        assert_eq!(items.inserted.len(), 1);
+        assert_eq!(items.inserted[0]["name"], SqlValue::Text("delta".into()));
+        assert_eq!(items.deleted[0]["id"], SqlValue::Integer(3));
+        assert_eq!(items.updated.len(), 1);
+        assert_eq!(
+            items.updated[0].changed_columns(),
+            vec![("price", Some(&SqlValue::Real(2.5)), Some(&SqlValue::Real(9.0)))]
+        );
+
+        let mut text = Vec::new();
+        diff.write_text(&mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "\
+table items
+  + {\"id\":4,\"name\":\"delta\",\"price\":4.5}
+  - {\"id\":3,\"name\":\"gamma\",\"price\":3.5}
+  ~ {\"id\":2} price: 2.5 -> 9.0
+");
+
+        let json: serde_json::Value = serde_json::from_str(&diff.to_json()).unwrap();
+        assert_eq!(json["tables"][0]["updated"][0]["new"]["price"], 9.0);
+    }
+
+    #[test]
+    fn test_schema_changes_and_rowid_tables() {
+        let old = db("
+            CREATE TABLE log (msg TEXT);
+            INSERT INTO log VALUES ('a'), ('b');
+            CREATE TABLE gone (x);
+            CREATE TABLE pairs (a TEXT, b TEXT, v BLOB, PRIMARY KEY (a, b)) WITHOUT ROWID;
+            INSERT INTO pairs VALUES ('x', 'y', x'00ff');
+        ");
+        let new = db("
+            CREATE TABLE log (msg TEXT);
+            INSERT INTO log VALUES ('a'), ('B');
+            CREATE TABLE pairs (a TEXT, b TEXT, v BLOB, PRIMARY KEY (a, b)) WITHOUT ROWID;
+            INSERT INTO pairs VALUES ('x', 'y', x'00ff');
+            CREATE TABLE fresh (x);
+        ");
+
+        let diff = diff_connections(&old, &new).unwrap();
+        let names: Vec<&str> = diff.tables.iter().map(|t| t.table.as_str()).collect();
+        assert_eq!(names, ["fresh", "gone", "log"]);
+
+        assert_eq!(diff.tables[0].schema.as_ref().unwrap().old, None);
+        assert_eq!(diff.tables[1].schema.as_ref().unwrap().new, None);
+        let log = &diff.tables[2];
+        assert_eq!(log.updated[0].key["rowid"], SqlValue::Integer(2));
+        assert_eq!(log.updated[0].new["msg"], SqlValue::Text("B".into()));
+
+        assert!(diff_connections(&new, &new).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+//! Three-way merge at the row level.
+//!
+//! Each side's changes are judged against the base: a row (or table) only
+//! one side changed takes that side's version, and one both sides changed
+//! the same way is left alone. Anything else is a [`Conflict`], settled by
+//! the [`ConflictPolicy`]. Only tables are merged; indexes, views, and
+//! triggers stay as they are on our side.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::{json, quote, read_rows, tables, DiffError, Result, Row};
+
+/// How to settle a conflict.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep our version.
+    Ours,
+    /// Take their version.
+    Theirs,
+    /// Make no changes and return [`DiffError::Conflicts`].
+    #[default]
+    Fail,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "ours" => Ok(ConflictPolicy::Ours),
+            "theirs" => Ok(ConflictPolicy::Theirs),
+            "fail" => Ok(ConflictPolicy::Fail),
+            other => Err(format!("unknown conflict policy {:?}: use ours, theirs, or fail", other)),
+        }
+    }
+}
+
+/// A change both sides made differently. `None` where the row or table
+/// doesn't exist on that side.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Conflict {
+    Row {
+        table: String,
+        key: Row,
+        base: Option<Row>,
+        ours: Option<Row>,
+        theirs: Option<Row>,
+    },
+    /// A table whose definition both sides changed, or which one side
+    /// changed and the other dropped or altered.
+    Table {
+        table: String,
+        base: Option<String>,
+        ours: Option<String>,
+        theirs: Option<String>,
+    },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Row { table, key, ours, theirs, .. } => write!(
+                f,
+                "row {} in {}: ours {}, theirs {}",
+                json(key), table, json(ours), json(theirs)
+            ),
+            Conflict::Table { table, .. } => write!(f, "table {}: changed differently on both sides", table),
+        }
+    }
+}
+
+/// What a merge changed on our side.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MergeReport {
+    /// Rows inserted, updated, or deleted to take their side.
+    pub rows_applied: usize,
+    /// Tables created, dropped, or replaced to take their side.
+    pub tables_applied: usize,
+    /// Conflicts settled by the policy.
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A change to make on our side.
+enum Action {
+    Put { table: String, row: Row },
+    Delete { table: String, key: Row },
+    /// Drop the table if it exists, then recreate it from `sql` (if any)
+    /// holding `rows`.
+    Replace { table: String, sql: Option<String>, rows: Vec<Row> },
+}
+
+/// Merge the changes from `base` to `theirs` into `ours`, writing to `ours`
+/// in a single transaction.
+pub fn merge_connections(
+    base: &Connection,
+    ours: &Connection,
+    theirs: &Connection,
+    policy: ConflictPolicy,
+) -> Result<MergeReport> {
+    let (base_tables, our_tables, their_tables) = (tables(base)?, tables(ours)?, tables(theirs)?);
+    let names: BTreeSet<&String> = base_tables.keys()
+        .chain(our_tables.keys())
+        .chain(their_tables.keys())
+        .collect();
+
+    let mut plan = Vec::new();
+    let mut conflicts = Vec::new();
+    for name in names {
+        let (b, o, t) = (base_tables.get(name), our_tables.get(name), their_tables.get(name));
+        if t == b && o != b {
+            // We dropped or altered it; their row changes can't be replayed on that
+            if rows(theirs, t, name)? == rows(base, b, name)? {
+                continue;
+            }
+        } else if t == b || o == t {
+            if t.is_some() {
+                let base_rows = if b == o { rows(base, b, name)? } else { BTreeMap::new() };
+                let (our_rows, their_rows) = (rows(ours, o, name)?, rows(theirs, t, name)?);
+                merge_rows(name, &base_rows, &our_rows, &their_rows, policy, &mut plan, &mut conflicts);
+            }
+            continue;
+        } else if o == b && rows(ours, o, name)? == rows(base, b, name)? {
+            // Only they changed it
+            plan.push(replace(theirs, name, t)?);
+            continue;
+        }
+
+        conflicts.push(Conflict::Table {
+            table: name.clone(),
+            base: b.cloned(),
+            ours: o.cloned(),
+            theirs: t.cloned(),
+        });
+        if policy == ConflictPolicy::Theirs {
+            plan.push(replace(theirs, name, t)?);
+        }
+    }
+
+    if policy == ConflictPolicy::Fail && !conflicts.is_empty() {
+        return Err(DiffError::Conflicts(conflicts));
+    }
+
+    let mut report = MergeReport { conflicts, ..Default::default() };
+    let tx = ours.unchecked_transaction()?;
+    for action in plan {
+        match action {
+            Action::Put { table, row } => {
+                put_row(&tx, &table, &row)?;
+                report.rows_applied += 1;
+            }
+            Action::Delete { table, key } => {
+                let condition: Vec<String> = key.keys()
+                    .enumerate()
+                    .map(|(i, col)| format!("{} IS ?{}", quote(col), i + 1))
+                    .collect();
+                let sql = format!("DELETE FROM {} WHERE {}", quote(&table), condition.join(" AND "));
+                tx.execute(&sql, rusqlite::params_from_iter(key.values()))?;
+                report.rows_applied += 1;
+            }
+            Action::Replace { table, sql, rows } => {
+                tx.execute_batch(&format!("DROP TABLE IF EXISTS {}", quote(&table)))?;
+                if let Some(sql) = sql {
+                    tx.execute_batch(&sql)?;
+                    for row in &rows {
+                        put_row(&tx, &table, row)?;
+                    }
+                }
+                report.tables_applied += 1;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(report)
+}
+
+/// Rows of `table` on one side; none if the side doesn't have it.
+fn rows(db: &Connection, sql: Option<&String>, table: &str) -> Result<BTreeMap<String, (Row, Row)>> {
+    match sql {
+        Some(_) => read_rows(db, table),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Take their version of `table`, definition and contents.
+fn replace(theirs: &Connection, table: &str, sql: Option<&String>) -> Result<Action> {
+    Ok(Action::Replace {
+        table: table.to_string(),
+        sql: sql.cloned(),
+        rows: rows(theirs, sql, table)?.into_values().map(|(_, row)| row).collect(),
+    })
+}
+
+fn merge_rows(
+    table: &str,
+    base: &BTreeMap<String, (Row, Row)>,
+    ours: &BTreeMap<String, (Row, Row)>,
+    theirs: &BTreeMap<String, (Row, Row)>,
+    policy: ConflictPolicy,
+    plan: &mut Vec<Action>,
+    conflicts: &mut Vec<Conflict>,
+) {
+    let keys: BTreeSet<&String> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+    for key in keys {
+        let [b, o, t] = [base, ours, theirs].map(|side| side.get(key).map(|(_, row)| row));
+        if t == b || o == t {
+            continue;
+        }
+        let key_row = [base, ours, theirs].into_iter()
+            .find_map(|side| side.get(key))
+            .map(|(key_row, _)| key_row.clone())
+            .unwrap_or_default();
+        if o != b {
+            conflicts.push(Conflict::Row {
+                table: table.to_string(),
+                key: key_row.clone(),
+                base: b.cloned(),
+                ours: o.cloned(),
+                theirs: t.cloned(),
+            });
+            if policy != ConflictPolicy::Theirs {
+                continue;
+            }
+        }
+        plan.push(match t {
+            Some(row) => Action::Put { table: table.to_string(), row: row.clone() },
+            None => Action::Delete { table: table.to_string(), key: key_row },
+        });
+    }
+}
+
+fn put_row(db: &Connection, table: &str, row: &Row) -> Result<()> {
+    let columns: Vec<String> = row.keys().map(|col| quote(col)).collect();
+    let params: Vec<String> = (1..=row.len()).map(|i| format!("?{}", i)).collect();
+    let sql = format!(
+        "INSERT OR REPLACE INTO {} ({}) VALUES ({})",
+        quote(table), columns.join(", "), params.join(", ")
+    );
+    db.execute(&sql, rusqlite::params_from_iter(row.values()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SqlValue;
+
+    const SCHEMA: &str = "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT);";
+
+    fn db(sql: &str) -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(SCHEMA).unwrap();
+        db.execute_batch(sql).unwrap();
+        db
+    }
+
+    fn values(db: &Connection) -> Vec<(i64, String)> {
+        let mut stmt = db.prepare("SELECT id, v FROM t ORDER BY id").unwrap();
+        stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?))).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_merge_rows() {
+        let base = db("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c');");
+        let ours = db("INSERT INTO t VALUES (1, 'A'), (2, 'b'), (3, 'c'), (4, 'ours');");
+        let theirs = db("INSERT INTO t VALUES (1, 'a'), (2, 'B'), (5, 'theirs');");
+
+        let report = merge_connections(&base, &ours, &theirs, ConflictPolicy::Fail).unwrap();
+        assert_eq!((report.rows_applied, report.conflicts.len()), (3, 0));
+        assert_eq!(values(&ours), vec![
+            (1, "A".to_string()), (2, "B".to_string()), (4, "ours".to_string()), (5, "theirs".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_row_conflicts() {
+        let base = db("INSERT INTO t VALUES (1, 'a'), (2, 'b');");
+        let theirs = db("INSERT INTO t VALUES (1, 'theirs');");
+        let ours = || db("INSERT INTO t VALUES (1, 'ours'), (2, 'ours');");
+
+        let target = ours();
+        let Err(DiffError::Conflicts(conflicts)) = merge_connections(&base, &target, &theirs, ConflictPolicy::Fail) else {
+            panic!("expected conflicts");
+        };
+        assert_eq!(conflicts.len(), 2);
+        assert!(matches!(&conflicts[1], Conflict::Row { key, theirs: None, .. } if key["id"] == SqlValue::Integer(2)));
+        assert_eq!(values(&target), vec![(1, "ours".to_string()), (2, "ours".to_string())]);
+
+        let target = ours();
+        let report = merge_connections(&base, &target, &theirs, ConflictPolicy::Ours).unwrap();
+        assert_eq!((report.rows_applied, report.conflicts.len()), (0, 2));
+        assert_eq!(values(&target), vec![(1, "ours".to_string()), (2, "ours".to_string())]);
+
+        let target = ours();
+        merge_connections(&base, &target, &theirs, ConflictPolicy::Theirs).unwrap();
+        assert_eq!(values(&target), vec![(1, "theirs".to_string())]);
+    }
+
+    #[test]
+    fn test_merge_tables() {
+        let base = db("CREATE TABLE gone (x); CREATE TABLE kept (x); INSERT INTO kept VALUES (1);");
+        let ours = db("CREATE TABLE gone (x); CREATE TABLE kept (x); INSERT INTO kept VALUES (2);");
+        let theirs = db("CREATE TABLE kept (x); INSERT INTO kept VALUES (1); CREATE TABLE fresh (y); INSERT INTO fresh VALUES ('new');");
+
+        let report = merge_connections(&base, &ours, &theirs, ConflictPolicy::Fail).unwrap();
+        assert_eq!(report.tables_applied, 2);
+        let names: Vec<String> = tables(&ours).unwrap().into_keys().collect();
+        assert_eq!(names, ["fresh", "kept", "t"]);
+        let fresh: String = ours.query_row("SELECT y FROM fresh", [], |r| r.get(0)).unwrap();
+        assert_eq!(fresh, "new");
+
+        // They altered a table we changed rows in
+        let base = db("");
+        let ours = db("INSERT INTO t VALUES (1, 'ours');");
+        let theirs = db("ALTER TABLE t ADD COLUMN extra;");
+        let Err(DiffError::Conflicts(conflicts)) = merge_connections(&base, &ours, &theirs, ConflictPolicy::Fail) else {
+            panic!("expected conflicts");
+        };
+        assert!(matches!(&conflicts[..], [Conflict::Table { table, .. }] if table == "t"));
+    }
+}
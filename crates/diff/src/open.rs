@@ -0,0 +1,278 @@
+//! Opening stored roots as SQLite databases.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use craftsql_core::{commit_at, page_table_root, Cid, Page, PageStore, PageStoreError, Result as StoreResult};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+
+use crate::{diff_connections, merge_connections, ConflictPolicy, DatabaseDiff, DiffError, MergeReport, Result};
+
+static VFS_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A view of a store with its own current root, so the VFS opens that
+/// version without touching the store's root pointer or named roots.
+/// Unless `writable`, all writes are refused.
+struct RootView {
+    store: Arc<dyn PageStore>,
+    root: Arc<Mutex<Cid>>,
+    writable: bool,
+}
+
+impl RootView {
+    fn read_only<T>(&self) -> StoreResult<T> {
+        Err(PageStoreError::ReadOnly(format!("root {} is opened read-only", self.root.lock().unwrap())))
+    }
+}
+
+impl PageStore for RootView {
+    fn get(&self, cid: &Cid) -> StoreResult<Page> {
+        self.store.get(cid)
+    }
+
+    fn put(&self, page: &Page) -> StoreResult<Cid> {
+        if !self.writable {
+            return self.read_only();
+        }
+        self.store.put(page)
+    }
+
+    fn has(&self, cid: &Cid) -> StoreResult<bool> {
+        self.store.has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> StoreResult<()> {
+        if !self.writable {
+            return self.read_only();
+        }
+        *self.root.lock().unwrap() = new_root;
+        Ok(())
+    }
+
+    fn current_root(&self) -> StoreResult<Option<Cid>> {
+        Ok(Some(*self.root.lock().unwrap()))
+    }
+
+    fn set_named_root(&self, _name: &str, _cid: Cid) -> StoreResult<()> {
+        self.read_only()
+    }
+
+    fn get_named_root(&self, name: &str) -> StoreResult<Option<Cid>> {
+        self.store.get_named_root(name)
+    }
+
+    fn remove_named_root(&self, _name: &str) -> StoreResult<bool> {
+        self.read_only()
+    }
+
+    fn list_named_roots(&self) -> StoreResult<Vec<(String, Cid)>> {
+        self.store.list_named_roots()
+    }
+}
+
+/// Open the database at page table `root` read-only.
+///
+/// Each call registers its own VFS with SQLite; registrations last for the
+/// life of the process.
+pub fn open_root(store: Arc<dyn PageStore>, root: Cid) -> Result<Connection> {
+    let (db, _) = open_view(store, root, false)?;
+    Ok(db)
+}
+
+/// Open the database as it was at `time` (seconds since the Unix epoch),
+/// read-only: the newest commit reachable from commit `from` made at or
+/// before then.
+pub fn open_at(store: Arc<dyn PageStore>, from: Cid, time: u64) -> Result<Connection> {
+    let commit = commit_at(store.as_ref(), from, time)?.ok_or(DiffError::NoCommitAt(time))?;
+    let root = page_table_root(store.as_ref(), &commit)?;
+    open_root(store, root)
+}
+
+/// Copy the database at page table `root` to a standard SQLite file at
+/// `path` with SQLite's online backup API, reading it through the VFS.
+/// Returns the file's size in bytes.
+///
+/// Unlike [`export_sqlite`](craftsql_core::export_sqlite), which copies
+/// the stored pages as they are, SQLite itself writes the copy, so it's a
+/// check that the VFS serves a consistent database. The file is written
+/// next to `path` and renamed into place.
+pub fn export_via_backup(store: Arc<dyn PageStore>, root: Cid, path: &Path) -> Result<u64> {
+    let db = open_root(store, root)?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".partial");
+    let tmp = PathBuf::from(tmp);
+    let copied = db.backup(DatabaseName::Main, &tmp, None);
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    fs::rename(&tmp, path)?;
+    Ok(fs::metadata(path)?.len())
+}
+
+/// Open the database at `root`; with `writable`, committed writes store
+/// their pages in `store` and move the returned root, not the store's.
+pub(crate) fn open_view(store: Arc<dyn PageStore>, root: Cid, writable: bool) -> Result<(Connection, Arc<Mutex<Cid>>)> {
+    let name = format!(
+        "craftsql_diff_{}_{}",
+        std::process::id(),
+        VFS_COUNTER.fetch_add(1, Ordering::SeqCst)
+    );
+    let root = Arc::new(Mutex::new(root));
+    let view = RootView { store, root: Arc::clone(&root), writable };
+    craftsql_vfs::register(&name, view).map_err(|e| DiffError::Register(format!("{:?}", e)))?;
+
+    let path = format!("/craftsql/{}/db", name);
+    let flags = if writable { OpenFlags::SQLITE_OPEN_READ_WRITE } else { OpenFlags::SQLITE_OPEN_READ_ONLY };
+    let db = Connection::open_with_flags_and_vfs(&path, flags, name.as_str())?;
+    if writable {
+        db.execute_batch("PRAGMA journal_mode=DELETE;")?;
+    }
+    Ok((db, root))
+}
+
+/// Diff the databases at page tables `old` and `new` in `store`.
+pub fn diff_roots(store: Arc<dyn PageStore>, old: Cid, new: Cid) -> Result<DatabaseDiff> {
+    let old = open_root(Arc::clone(&store), old)?;
+    let new = open_root(store, new)?;
+    diff_connections(&old, &new)
+}
+
+/// Merge the changes from `base` to `theirs` into `ours`, returning the
+/// merged page table. Nothing in `store` but its pages changes: no root
+/// pointer or named root is moved.
+pub fn merge_roots(
+    store: Arc<dyn PageStore>,
+    base: Cid,
+    ours: Cid,
+    theirs: Cid,
+    policy: ConflictPolicy,
+) -> Result<(Cid, MergeReport)> {
+    if theirs == base || theirs == ours {
+        return Ok((ours, MergeReport::default()));
+    }
+    if ours == base {
+        return Ok((theirs, MergeReport::default()));
+    }
+    let base = open_root(Arc::clone(&store), base)?;
+    let theirs = open_root(Arc::clone(&store), theirs)?;
+    let (db, root) = open_view(store, ours, true)?;
+    let report = merge_connections(&base, &db, &theirs, policy)?;
+    drop(db);
+    let merged = *root.lock().unwrap();
+    Ok((merged, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::Commit;
+    use craftsql_store_local::LocalPageStore;
+
+    #[test]
+    fn test_diff_roots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = Arc::new(LocalPageStore::new(tmp.path()).unwrap());
+
+        let name = "craftsql_diff_test_writer";
+        craftsql_vfs::register(name, LocalPageStore::new(tmp.path()).unwrap()).unwrap();
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE;
+        let db = Connection::open_with_flags_and_vfs(format!("/craftsql/{}/db", name), flags, name).unwrap();
+        db.execute_batch("
+            PRAGMA journal_mode=DELETE;
+            CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT);
+            INSERT INTO t VALUES (1, 'one'), (2, 'two');
+        ").unwrap();
+        let v1 = store.current_root().unwrap().unwrap();
+        db.execute_batch("UPDATE t SET v = 'TWO' WHERE id = 2; INSERT INTO t VALUES (3, 'three');").unwrap();
+        let v2 = store.current_root().unwrap().unwrap();
+
+        let diff = diff_roots(store.clone(), v1, v2).unwrap();
+        assert_eq!(diff.tables.len(), 1);
+        assert_eq!(diff.tables[0].inserted[0]["v"], crate::SqlValue::Text("three".into()));
+        assert_eq!(diff.tables[0].updated[0].new["v"], crate::SqlValue::Text("TWO".into()));
+
+        // The pinned view can't be written through
+        let old = open_root(store.clone(), v1).unwrap();
+        assert!(old.execute("DELETE FROM t", []).is_err());
+        assert_eq!(store.current_root().unwrap(), Some(v2));
+
+    }
+
+    #[test]
+    fn test_merge_roots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store: Arc<dyn PageStore> = Arc::new(LocalPageStore::new(tmp.path()).unwrap());
+        let name = "craftsql_diff_test_merge";
+        craftsql_vfs::register(name, LocalPageStore::new(tmp.path()).unwrap()).unwrap();
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE;
+        let db = Connection::open_with_flags_and_vfs(format!("/craftsql/{}/db", name), flags, name).unwrap();
+        db.execute_batch("
+            PRAGMA journal_mode=DELETE;
+            CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT);
+            INSERT INTO t VALUES (1, 'one'), (2, 'two');
+        ").unwrap();
+        let base = store.current_root().unwrap().unwrap();
+
+        // Two lines of work off the base, neither touching the store's root
+        let commit = |sql: &str| {
+            let (db, root) = open_view(Arc::clone(&store), base, true).unwrap();
+            db.execute_batch(sql).unwrap();
+            drop(db);
+            let root = *root.lock().unwrap();
+            root
+        };
+        let ours = commit("UPDATE t SET v = 'TWO' WHERE id = 2;");
+        let theirs = commit("INSERT INTO t VALUES (3, 'three');");
+        assert_eq!(store.current_root().unwrap(), Some(base));
+
+        let (merged, report) = merge_roots(Arc::clone(&store), base, ours, theirs, ConflictPolicy::Fail).unwrap();
+        assert_eq!(report.rows_applied, 1);
+        let db = open_root(Arc::clone(&store), merged).unwrap();
+        let values: Vec<String> = db.prepare("SELECT v FROM t ORDER BY id").unwrap()
+            .query_map([], |r| r.get(0)).unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(values, ["one", "TWO", "three"]);
+        assert_eq!(store.current_root().unwrap(), Some(base));
+
+        // Time travel through commits of the versions
+        let first = Commit { time: 100, ..Commit::new(base, vec![], "") }.put(store.as_ref()).unwrap();
+        let second = Commit { time: 200, ..Commit::new(merged, vec![first], "") }.put(store.as_ref()).unwrap();
+        let count = |db: Connection| db.query_row("SELECT count(*) FROM t", [], |r| r.get::<_, i64>(0)).unwrap();
+        assert_eq!(count(open_at(Arc::clone(&store), second, 199).unwrap()), 2);
+        assert_eq!(count(open_at(Arc::clone(&store), second, 200).unwrap()), 3);
+        assert!(matches!(open_at(Arc::clone(&store), second, 99), Err(DiffError::NoCommitAt(99))));
+
+        // Fast-forward: nothing to open
+        assert_eq!(merge_roots(Arc::clone(&store), base, base, theirs, ConflictPolicy::Fail).unwrap().0, theirs);
+    }
+
+    #[test]
+    fn test_export_via_backup() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store: Arc<dyn PageStore> = Arc::new(LocalPageStore::new(&tmp.path().join("store")).unwrap());
+        let name = "craftsql_diff_test_backup";
+        craftsql_vfs::register_shared(name, Arc::clone(&store)).unwrap();
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE;
+        let db = Connection::open_with_flags_and_vfs(format!("/craftsql/{}/db", name), flags, name).unwrap();
+        db.execute_batch("
+            PRAGMA journal_mode=DELETE;
+            CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT);
+            INSERT INTO t VALUES (1, 'one'), (2, 'two');
+        ").unwrap();
+        let root = store.current_root().unwrap().unwrap();
+        db.execute_batch("BEGIN; INSERT INTO t VALUES (3, 'three');").unwrap();
+
+        let path = tmp.path().join("copy.db");
+        let size = export_via_backup(Arc::clone(&store), root, &path).unwrap();
+        assert_eq!(size, std::fs::metadata(&path).unwrap().len());
+        let copy = Connection::open(&path).unwrap();
+        let count: i64 = copy.query_row("SELECT count(*) FROM t", [], |r| r.get(0)).unwrap();
+        assert_eq!(count, 2);
+        let page_size: i64 = copy.query_row("PRAGMA page_size", [], |r| r.get(0)).unwrap();
+        assert_eq!(size % page_size as u64, 0);
+    }
+}
@@ -0,0 +1,162 @@
+//! Rebasing: replaying a line of commits on top of another version.
+
+use std::sync::Arc;
+
+use craftsql_core::{is_ancestor, page_table_root, Cid, Commit, PageStore};
+
+use crate::{merge_roots, Conflict, ConflictPolicy, DiffError, Result};
+
+/// What a [`rebase`] made.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebaseReport {
+    /// The rebased tip: the last new commit, or `onto` if none was made.
+    pub head: Cid,
+    /// Each commit replayed and the new commit it became, oldest first.
+    pub replayed: Vec<(Cid, Cid)>,
+    /// Commits left out because `onto` already had their changes.
+    pub skipped: Vec<Cid>,
+    /// Rows and tables changed, over all the commits replayed.
+    pub rows_applied: usize,
+    pub tables_applied: usize,
+    /// Conflicts settled by the policy.
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Replay the commits of `tip` that `onto` lacks on top of `onto`, oldest
+/// first, each as the row changes it made from its first parent. The new
+/// commits keep their message and author; the rebased tip is returned in
+/// the report. Nothing in `store` but its pages changes: no root pointer or
+/// named root is moved.
+///
+/// The commits replayed are those on `tip`'s first-parent line down to the
+/// first that `onto` descends from; a merge among them is replayed like any
+/// other commit, so the result is linear. If `tip` already descends from
+/// `onto`, there is nothing to do and the report's head is `tip`; if it is
+/// behind `onto`, the head is `onto`.
+///
+/// With [`ConflictPolicy::Fail`], a conflicting commit stops the rebase with
+/// [`DiffError::RebaseConflicts`] and no commit is made.
+pub fn rebase(store: Arc<dyn PageStore>, tip: Cid, onto: Cid, policy: ConflictPolicy) -> Result<RebaseReport> {
+    let mut root = Commit::load(store.as_ref(), &onto)?.ok_or(DiffError::NotACommit(onto))?.root;
+    let mut report = RebaseReport {
+        head: onto,
+        replayed: Vec::new(),
+        skipped: Vec::new(),
+        rows_applied: 0,
+        tables_applied: 0,
+        conflicts: Vec::new(),
+    };
+    if is_ancestor(store.as_ref(), onto, tip)? {
+        report.head = tip;
+        return Ok(report);
+    }
+
+    let mut todo = Vec::new();
+    let mut cid = tip;
+    while !is_ancestor(store.as_ref(), cid, onto)? {
+        let commit = Commit::load(store.as_ref(), &cid)?.ok_or(DiffError::NotACommit(cid))?;
+        let Some(&parent) = commit.parents.first() else {
+            return Err(DiffError::NoCommonHistory(tip, onto));
+        };
+        todo.push((cid, parent, commit));
+        cid = parent;
+    }
+
+    for (cid, parent, commit) in todo.into_iter().rev() {
+        let base = page_table_root(store.as_ref(), &parent)?;
+        let (merged, merge) = match merge_roots(Arc::clone(&store), base, root, commit.root, policy) {
+            Err(DiffError::Conflicts(conflicts)) => return Err(DiffError::RebaseConflicts { commit: cid, conflicts }),
+            result => result?,
+        };
+        if merged == root {
+            report.skipped.push(cid);
+            report.conflicts.extend(merge.conflicts);
+            continue;
+        }
+        let new = Commit::new(merged, vec![report.head], &commit.message)
+            .with_author(&commit.author)
+            .put(store.as_ref())?;
+        report.replayed.push((cid, new));
+        report.head = new;
+        report.rows_applied += merge.rows_applied;
+        report.tables_applied += merge.tables_applied;
+        report.conflicts.extend(merge.conflicts);
+        root = merged;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open::open_view;
+    use crate::open_root;
+    use craftsql_core::Page;
+    use craftsql_store_local::LocalPageStore;
+    use rusqlite::{Connection, OpenFlags};
+
+    /// Commit the result of running `sql` on `parent`'s version.
+    fn commit(store: &Arc<dyn PageStore>, parent: Cid, sql: &str, message: &str) -> Cid {
+        let (db, root) = open_view(Arc::clone(store), page_table_root(store.as_ref(), &parent).unwrap(), true).unwrap();
+        db.execute_batch(sql).unwrap();
+        drop(db);
+        let root = *root.lock().unwrap();
+        Commit::new(root, vec![parent], message).with_author("ada").put(store.as_ref()).unwrap()
+    }
+
+    fn values(store: &Arc<dyn PageStore>, cid: Cid) -> Vec<String> {
+        let db = open_root(Arc::clone(store), page_table_root(store.as_ref(), &cid).unwrap()).unwrap();
+        let mut stmt = db.prepare("SELECT v FROM t ORDER BY id").unwrap();
+        stmt.query_map([], |r| r.get(0)).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_rebase() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store: Arc<dyn PageStore> = Arc::new(LocalPageStore::new(tmp.path()).unwrap());
+        let name = "craftsql_diff_test_rebase";
+        craftsql_vfs::register(name, LocalPageStore::new(tmp.path()).unwrap()).unwrap();
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE;
+        let db = Connection::open_with_flags_and_vfs(format!("/craftsql/{}/db", name), flags, name).unwrap();
+        db.execute_batch("
+            PRAGMA journal_mode=DELETE;
+            CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT);
+            INSERT INTO t VALUES (1, 'one');
+        ").unwrap();
+        let base = Commit::new(store.current_root().unwrap().unwrap(), vec![], "base").put(store.as_ref()).unwrap();
+
+        //   base - main
+        //      \
+        //       a - b
+        let main = commit(&store, base, "INSERT INTO t VALUES (2, 'two');", "main");
+        let a = commit(&store, base, "INSERT INTO t VALUES (3, 'three');", "a");
+        let b = commit(&store, a, "UPDATE t SET v = 'ONE' WHERE id = 1;", "b");
+
+        let report = rebase(Arc::clone(&store), b, main, ConflictPolicy::Fail).unwrap();
+        assert_eq!(report.replayed.iter().map(|(old, _)| *old).collect::<Vec<_>>(), vec![a, b]);
+        assert_eq!((report.rows_applied, report.conflicts.len()), (2, 0));
+        assert_eq!(values(&store, report.head), ["ONE", "two", "three"]);
+        let rebased = Commit::load(store.as_ref(), &report.head).unwrap().unwrap();
+        assert_eq!((rebased.parents, rebased.message.as_str(), rebased.author.as_str()), (vec![report.replayed[0].1], "b", "ada"));
+
+        // Rebasing again does nothing; a tip behind onto just moves up
+        let again = rebase(Arc::clone(&store), report.head, main, ConflictPolicy::Fail).unwrap();
+        assert_eq!((again.head, again.replayed), (report.head, vec![]));
+        assert_eq!(rebase(Arc::clone(&store), base, main, ConflictPolicy::Fail).unwrap().head, main);
+
+        // A commit changing a row onto changed too
+        let other = commit(&store, base, "UPDATE t SET v = 'uno' WHERE id = 1;", "other");
+        let Err(DiffError::RebaseConflicts { commit: stopped, conflicts }) = rebase(Arc::clone(&store), b, other, ConflictPolicy::Fail) else {
+            panic!("expected conflicts");
+        };
+        assert_eq!((stopped, conflicts.len()), (b, 1));
+        let report = rebase(Arc::clone(&store), b, other, ConflictPolicy::Ours).unwrap();
+        assert_eq!((report.replayed.len(), report.skipped.clone(), report.conflicts.len()), (1, vec![b], 1));
+        assert_eq!(values(&store, report.head), ["uno", "three"]);
+
+        // Unrelated histories can't be rebased onto each other
+        let page = store.put(&Page { data: b"unrelated".to_vec() }).unwrap();
+        let lone = Commit::new(page, vec![], "lone").put(store.as_ref()).unwrap();
+        assert!(matches!(rebase(Arc::clone(&store), lone, main, ConflictPolicy::Fail), Err(DiffError::NoCommonHistory(..))));
+    }
+}
@@ -0,0 +1,40 @@
+/*
+ * CraftSQL C API, from libcraftsql (crates/ext).
+ *
+ * Loading libcraftsql as a SQLite extension registers the stores named by
+ * CRAFTSQL_STORE and CRAFTSQL_STORES; these calls register stores directly.
+ */
+#ifndef CRAFTSQL_H
+#define CRAFTSQL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Register the store at `store` (a directory, "unix:<path>",
+ * "tcp://<host:port>", or "http://...") as SQLite VFS `vfs`; open its
+ * database with "file:db?vfs=<vfs>". Daemon-backed stores cache pages in
+ * `cache_dir`, or a temp directory if it is NULL. Registering the same
+ * store under the same name again does nothing.
+ *
+ * Returns SQLITE_OK, or SQLITE_ERROR with the reason in craftsql_errmsg().
+ */
+int craftsql_register(const char *vfs, const char *store, const char *cache_dir);
+
+/*
+ * Why the calling thread's last craftsql_register() failed. Valid until
+ * its next call.
+ */
+const char *craftsql_errmsg(void);
+
+/* SQLite extension entry point, for sqlite3_load_extension(). */
+struct sqlite3;
+struct sqlite3_api_routines;
+int sqlite3_craftsql_init(struct sqlite3 *db, char **err, const struct sqlite3_api_routines *api);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CRAFTSQL_H */
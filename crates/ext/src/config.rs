@@ -0,0 +1,55 @@
+//! Which stores to register, read from the environment.
+
+use craftsql_cli::store::StoreSpec;
+
+/// VFS name for the store given by `CRAFTSQL_STORE`.
+pub const DEFAULT_VFS: &str = "craftsql";
+
+/// Stores to register as VFSes, by VFS name: `CRAFTSQL_STORE` as
+/// [`DEFAULT_VFS`], then each `name=spec` of the `;`-separated
+/// `CRAFTSQL_STORES`.
+pub fn stores(store: Option<&str>, stores: Option<&str>) -> Result<Vec<(String, StoreSpec)>, String> {
+    let mut found: Vec<(String, StoreSpec)> = Vec::new();
+    if let Some(spec) = store.filter(|spec| !spec.is_empty()) {
+        found.push((DEFAULT_VFS.to_string(), StoreSpec::parse(spec)));
+    }
+    for entry in stores.unwrap_or_default().split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (name, spec) = entry
+            .split_once('=')
+            .map(|(name, spec)| (name.trim(), spec.trim()))
+            .filter(|(name, spec)| !name.is_empty() && !spec.is_empty())
+            .ok_or_else(|| format!("invalid CRAFTSQL_STORES entry {:?}: use name=store", entry))?;
+        if found.iter().any(|(existing, _)| existing == name) {
+            return Err(format!("VFS {:?} is configured twice", name));
+        }
+        found.push((name.to_string(), StoreSpec::parse(spec)));
+    }
+    if found.is_empty() {
+        return Err("no store configured: set CRAFTSQL_STORE or CRAFTSQL_STORES".to_string());
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_stores_from_env() {
+        assert_eq!(
+            stores(Some("./db"), Some(" shared = unix:/run/craftobj.sock ; ;remote=tcp://10.0.0.2:7000")).unwrap(),
+            vec![
+                ("craftsql".to_string(), StoreSpec::Local(PathBuf::from("./db"))),
+                ("shared".to_string(), StoreSpec::Unix("/run/craftobj.sock".into())),
+                ("remote".to_string(), StoreSpec::Tcp("10.0.0.2:7000".into())),
+            ]
+        );
+        assert_eq!(stores(None, Some("a=x=y")).unwrap(), vec![("a".to_string(), StoreSpec::Local("x=y".into()))]);
+
+        assert!(stores(None, None).is_err());
+        assert!(stores(Some(""), Some(" ; ")).is_err());
+        assert!(stores(None, Some("./db")).is_err());
+        assert!(stores(Some("./a"), Some("craftsql=./b")).is_err());
+    }
+}
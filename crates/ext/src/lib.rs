@@ -0,0 +1,183 @@
+//! CraftSQL as a loadable SQLite extension, for any application that can
+//! load one, plus a small C API (`include/craftsql.h`).
+//!
+//! Build with `cargo build --release -p craftsql-ext`, which produces
+//! `libcraftsql.so` (`.dylib`, `.dll`). Loading it registers the CraftSQL
+//! VFS for each configured store:
+//!
+//! - `CRAFTSQL_STORE` — the store for the VFS named `craftsql`, in the
+//!   forms the `craftsql` command takes (a directory, `unix:<path>`,
+//!   `tcp://<host:port>`, or `http://...`);
+//! - `CRAFTSQL_STORES` — more stores as `name=store` pairs separated by
+//!   `;`, each registered as a VFS of that name;
+//! - `CRAFTSQL_CACHE_DIR` — the page cache for a daemon-backed
+//!   `CRAFTSQL_STORE`, shared with the `craftsql` command; the stores of
+//!   `CRAFTSQL_STORES` cache in a subdirectory per name.
+//!
+//! A database then picks its store with the `vfs` URI parameter:
+//!
+//! ```text
+//! sqlite> .load ./libcraftsql
+//! sqlite> .open file:app.db?vfs=craftsql
+//! sqlite> PRAGMA journal_mode=DELETE;
+//! ```
+//!
+//! The file name is ignored: each VFS holds one database, the one at its
+//! store's current root. As with `craftsql-vfs`, never use WAL.
+//!
+//! SQLite resolves `vfs=` when a connection opens, before any extension is
+//! loaded into it, so load the extension first, for instance into an
+//! in-memory connection, then open the database. The registrations and the
+//! library stay for the life of the process. C programs can instead call
+//! `craftsql_register` to register a store under any VFS name.
+//!
+//! The extension calls SQLite through the symbols the host process exports,
+//! so the host must use a shared `libsqlite3` (as the `sqlite3` shell,
+//! Python, and Go's `mattn/go-sqlite3` built with `-tags libsqlite3` do),
+//! not a private copy compiled into it.
+//!
+//! libsql exports the same `sqlite3_` API, so programs using libsql's
+//! shared library or shell load the extension the same way. Rust programs
+//! on libsql's crate use `craftsql-vfs`'s `libsql` feature instead.
+
+mod config;
+
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use craftsql_cli::store::StoreSpec;
+use craftsql_core::PageStore;
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ERROR: c_int = 1;
+/// Keeps the library loaded after the connection that loaded it closes.
+const SQLITE_OK_LOAD_PERMANENTLY: c_int = 256;
+
+extern "C" {
+    fn sqlite3_mprintf(format: *const c_char, ...) -> *mut c_char;
+}
+
+/// Stores registered so far, by VFS name.
+static REGISTERED: Mutex<BTreeMap<String, StoreSpec>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Register `store` as VFS `vfs`. Registering the same store under the same
+/// name again does nothing, so the extension can be loaded more than once.
+fn register(vfs: &str, store: &StoreSpec, cache_dir: Option<&Path>) -> Result<(), String> {
+    let mut registered = REGISTERED.lock().unwrap();
+    match registered.get(vfs) {
+        Some(existing) if existing == store => return Ok(()),
+        Some(existing) => return Err(format!("VFS {} is already registered for {:?}", vfs, existing)),
+        None => {}
+    }
+    let opened: Arc<dyn PageStore> = store.open(cache_dir).map_err(|e| format!("open {:?}: {}", store, e))?.into();
+    craftsql_vfs::register(vfs, opened).map_err(|e| format!("register VFS {}: {:?}", vfs, e))?;
+    registered.insert(vfs.to_string(), store.clone());
+    Ok(())
+}
+
+/// Register every store configured in the environment.
+fn register_from_env() -> Result<(), String> {
+    let var = |name| std::env::var(name).ok();
+    let cache_dir = var("CRAFTSQL_CACHE_DIR").map(PathBuf::from);
+    for (vfs, store) in config::stores(var("CRAFTSQL_STORE").as_deref(), var("CRAFTSQL_STORES").as_deref())? {
+        let cache_dir = match &cache_dir {
+            Some(dir) if vfs != config::DEFAULT_VFS => Some(dir.join(&vfs)),
+            other => other.clone(),
+        };
+        register(&vfs, &store, cache_dir.as_deref())?;
+    }
+    Ok(())
+}
+
+fn c_message(message: &str) -> CString {
+    CString::new(message.replace('\0', "")).unwrap_or_default()
+}
+
+/// SQLite's entry point for `.load craftsql`.
+///
+/// # Safety
+///
+/// Called by SQLite with a valid error message pointer.
+#[no_mangle]
+pub unsafe extern "C" fn sqlite3_craftsql_init(_db: *mut c_void, err: *mut *mut c_char, _api: *const c_void) -> c_int {
+    match register_from_env() {
+        Ok(()) => SQLITE_OK_LOAD_PERMANENTLY,
+        Err(message) => {
+            if !err.is_null() {
+                let message = c_message(&message);
+                *err = sqlite3_mprintf(c"%s".as_ptr(), message.as_ptr());
+            }
+            SQLITE_ERROR
+        }
+    }
+}
+
+/// Register the store at `store` as VFS `vfs`, with daemon-backed stores
+/// caching pages in `cache_dir` (or a per-location temp directory if null).
+/// Returns `SQLITE_OK`, or `SQLITE_ERROR` with the reason in
+/// [`craftsql_errmsg`].
+///
+/// # Safety
+///
+/// `vfs` and `store` must be valid C strings; `cache_dir` one or null.
+#[no_mangle]
+pub unsafe extern "C" fn craftsql_register(vfs: *const c_char, store: *const c_char, cache_dir: *const c_char) -> c_int {
+    let arg = |s: *const c_char| (!s.is_null()).then(|| CStr::from_ptr(s).to_string_lossy().into_owned());
+    let result = match (arg(vfs), arg(store)) {
+        (Some(vfs), Some(store)) => {
+            let cache_dir = arg(cache_dir).map(PathBuf::from);
+            register(&vfs, &StoreSpec::parse(&store), cache_dir.as_deref())
+        }
+        _ => Err("craftsql_register: vfs and store are required".to_string()),
+    };
+    match result {
+        Ok(()) => SQLITE_OK,
+        Err(message) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = c_message(&message));
+            SQLITE_ERROR
+        }
+    }
+}
+
+/// The reason the calling thread's last `craftsql_register` failed, valid
+/// until its next call.
+#[no_mangle]
+pub extern "C" fn craftsql_errmsg() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_local::LocalPageStore;
+    use rusqlite::{Connection, OpenFlags};
+
+    #[test]
+    fn test_register_and_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let path = |dir: &tempfile::TempDir| CString::new(dir.path().to_str().unwrap()).unwrap();
+        let vfs = c"craftsql_ext_test";
+        unsafe {
+            assert_eq!(craftsql_register(vfs.as_ptr(), path(&dir).as_ptr(), std::ptr::null()), SQLITE_OK);
+            // The same store again is fine, another under the same name isn't
+            assert_eq!(craftsql_register(vfs.as_ptr(), path(&dir).as_ptr(), std::ptr::null()), SQLITE_OK);
+            assert_eq!(craftsql_register(vfs.as_ptr(), path(&other).as_ptr(), std::ptr::null()), SQLITE_ERROR);
+            assert!(CStr::from_ptr(craftsql_errmsg()).to_str().unwrap().contains("already registered"));
+            assert_eq!(craftsql_register(std::ptr::null(), path(&dir).as_ptr(), std::ptr::null()), SQLITE_ERROR);
+        }
+
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE;
+        let db = Connection::open_with_flags_and_vfs("/craftsql/ext/db", flags, "craftsql_ext_test").unwrap();
+        db.execute_batch("PRAGMA journal_mode=DELETE; CREATE TABLE t (x); INSERT INTO t VALUES (42);").unwrap();
+        drop(db);
+        assert!(LocalPageStore::new(dir.path()).unwrap().current_root().unwrap().is_some());
+    }
+}
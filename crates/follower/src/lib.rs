@@ -0,0 +1,352 @@
+//! CraftSQL Follower — read replicas that keep up with a writer.
+//!
+//! A [`Follower`] holds a read-only SQLite connection to the database at a
+//! store's current root, or at a named root, and opens a new one whenever
+//! that root moves (see [`craftsql_core::watch`]). The new connection is
+//! swapped in whole: [`Follower::current`] hands out a [`Replica`], and a
+//! reader keeps the version it got for as long as it holds it, so a query
+//! never sees half of one commit and half of another. This is the read
+//! side for dashboards and API servers in front of a single writer.
+//!
+//! Each follower registers one VFS with SQLite, which lasts for the life of
+//! the process.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use craftsql_core::{page_table_root, watch, Cid, Page, PageStore, PageStoreError};
+use rusqlite::{Connection, OpenFlags};
+
+static VFS_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// How often the background thread checks whether to stop.
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+/// Follower errors.
+#[derive(Debug, thiserror::Error)]
+pub enum FollowerError {
+    #[error(transparent)]
+    Store(#[from] PageStoreError),
+    #[error(transparent)]
+    Sql(#[from] rusqlite::Error),
+    #[error("register VFS: {0}")]
+    Register(String),
+    #[error("{0} has no database yet")]
+    NoRoot(String),
+}
+
+pub type Result<T> = std::result::Result<T, FollowerError>;
+
+/// The root a [`Follower`] follows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Follow {
+    /// The store's current root.
+    Root,
+    /// A named root, such as a branch; a commit is followed to its page table.
+    Named(String),
+}
+
+impl std::fmt::Display for Follow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Follow::Root => write!(f, "the current root"),
+            Follow::Named(name) => write!(f, "{:?}", name),
+        }
+    }
+}
+
+/// A read-only connection to one version of the database.
+pub struct Replica {
+    root: Cid,
+    db: Mutex<Connection>,
+}
+
+impl Replica {
+    /// Page table of the version this connection reads.
+    pub fn root(&self) -> Cid {
+        self.root
+    }
+
+    /// The connection, held by one reader at a time.
+    pub fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.db.lock().unwrap()
+    }
+}
+
+/// The store as the follower's VFS sees it: read-only, with the root of
+/// the replica being opened as its current root.
+struct FollowerView {
+    store: Arc<dyn PageStore>,
+    root: Arc<Mutex<Cid>>,
+}
+
+impl FollowerView {
+    fn read_only<T>(&self) -> craftsql_core::Result<T> {
+        Err(PageStoreError::ReadOnly("followers are read-only".into()))
+    }
+}
+
+impl PageStore for FollowerView {
+    fn get(&self, cid: &Cid) -> craftsql_core::Result<Page> {
+        self.store.get(cid)
+    }
+
+    fn put(&self, _page: &Page) -> craftsql_core::Result<Cid> {
+        self.read_only()
+    }
+
+    fn has(&self, cid: &Cid) -> craftsql_core::Result<bool> {
+        self.store.has(cid)
+    }
+
+    fn update_root(&self, _new_root: Cid) -> craftsql_core::Result<()> {
+        self.read_only()
+    }
+
+    fn current_root(&self) -> craftsql_core::Result<Option<Cid>> {
+        Ok(Some(*self.root.lock().unwrap()))
+    }
+
+    fn set_named_root(&self, _name: &str, _cid: Cid) -> craftsql_core::Result<()> {
+        self.read_only()
+    }
+
+    fn get_named_root(&self, name: &str) -> craftsql_core::Result<Option<Cid>> {
+        self.store.get_named_root(name)
+    }
+
+    fn remove_named_root(&self, _name: &str) -> craftsql_core::Result<bool> {
+        self.read_only()
+    }
+
+    fn list_named_roots(&self) -> craftsql_core::Result<Vec<(String, Cid)>> {
+        self.store.list_named_roots()
+    }
+}
+
+/// Page table `follow` points at in `store`.
+fn target(store: &dyn PageStore, follow: &Follow) -> Result<Cid> {
+    let cid = match follow {
+        Follow::Root => store.current_root()?,
+        Follow::Named(name) => store.get_named_root(name)?,
+    };
+    let cid = cid.ok_or_else(|| FollowerError::NoRoot(follow.to_string()))?;
+    Ok(page_table_root(store, &cid)?)
+}
+
+/// Open the database at `root` through the follower VFS `vfs`, which opens
+/// whatever `opening` holds.
+fn open_replica(vfs: &str, opening: &Mutex<Cid>, root: Cid) -> Result<Replica> {
+    let mut opening = opening.lock().unwrap();
+    *opening = root;
+    let path = format!("/craftsql/{}/db", vfs);
+    let db = Connection::open_with_flags_and_vfs(&path, OpenFlags::SQLITE_OPEN_READ_ONLY, vfs)?;
+    // Read the schema now, while the VFS still has this root to give out
+    db.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))?;
+    Ok(Replica { root, db: Mutex::new(db) })
+}
+
+struct Shared {
+    store: Arc<dyn PageStore>,
+    follow: Follow,
+    vfs: String,
+    /// Root the VFS opens next; locked for the whole of an open.
+    opening: Arc<Mutex<Cid>>,
+    current: Mutex<Arc<Replica>>,
+    last_error: Mutex<Option<String>>,
+    stop: AtomicBool,
+}
+
+impl Shared {
+    fn target(&self) -> Result<Cid> {
+        target(self.store.as_ref(), &self.follow)
+    }
+
+    fn open(&self, root: Cid) -> Result<Replica> {
+        open_replica(&self.vfs, &self.opening, root)
+    }
+
+    fn refresh(&self) -> Result<bool> {
+        let root = self.target()?;
+        if self.current.lock().unwrap().root == root {
+            return Ok(false);
+        }
+        let replica = Arc::new(self.open(root)?);
+        *self.current.lock().unwrap() = replica;
+        Ok(true)
+    }
+
+    /// Refresh whenever a root changes, until told to stop.
+    fn run(&self, poll_interval: Duration) {
+        let mut watch = match watch(self.store.as_ref()) {
+            Ok(watch) => watch.with_poll_interval(poll_interval),
+            Err(e) => {
+                *self.last_error.lock().unwrap() = Some(e.to_string());
+                return;
+            }
+        };
+        // Set until a refresh after a root change succeeds
+        let mut behind = false;
+        while !self.stop.load(Ordering::SeqCst) {
+            let result = match watch.next_event(Some(STOP_CHECK)) {
+                Ok(event) => {
+                    behind |= event.is_some();
+                    if behind {
+                        self.refresh().map(|_| behind = false)
+                    } else {
+                        Ok(())
+                    }
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                *self.last_error.lock().unwrap() = Some(e.to_string());
+                // Back off before trying a failing store again
+                std::thread::sleep(poll_interval.min(Duration::from_secs(1)));
+            }
+        }
+    }
+}
+
+/// A read-only database that moves to each new version of a root.
+pub struct Follower {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Follower {
+    /// Open the database at `follow` in `store`. It stays at that version
+    /// until [`refresh`](Self::refresh)ed, or [`start`](Self::start)ed.
+    pub fn open(store: Arc<dyn PageStore>, follow: Follow) -> Result<Self> {
+        let vfs = format!(
+            "craftsql_follower_{}_{}",
+            std::process::id(),
+            VFS_COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        let opening = Arc::new(Mutex::new(Cid([0; 32])));
+        let view = FollowerView { store: Arc::clone(&store), root: Arc::clone(&opening) };
+        craftsql_vfs::register(&vfs, view).map_err(|e| FollowerError::Register(format!("{:?}", e)))?;
+
+        let replica = open_replica(&vfs, &opening, target(store.as_ref(), &follow)?)?;
+        let shared = Shared {
+            store,
+            follow,
+            vfs,
+            opening,
+            current: Mutex::new(Arc::new(replica)),
+            last_error: Mutex::new(None),
+            stop: AtomicBool::new(false),
+        };
+        Ok(Self { shared: Arc::new(shared), thread: None })
+    }
+
+    /// Follow the root from a background thread, which reads it whenever
+    /// the store signals a change, and at least every `poll_interval`.
+    pub fn start(mut self, poll_interval: Duration) -> Self {
+        if self.thread.is_none() {
+            let shared = Arc::clone(&self.shared);
+            self.thread = Some(std::thread::spawn(move || shared.run(poll_interval)));
+        }
+        self
+    }
+
+    /// The newest version opened. Hold it for as long as a consistent view
+    /// is needed; later versions don't affect it.
+    pub fn current(&self) -> Arc<Replica> {
+        Arc::clone(&self.shared.current.lock().unwrap())
+    }
+
+    /// Page table of the newest version opened.
+    pub fn root(&self) -> Cid {
+        self.current().root
+    }
+
+    /// Move to the root's latest version now. Returns whether it had moved.
+    pub fn refresh(&self) -> Result<bool> {
+        self.shared.refresh()
+    }
+
+    /// The last error the background thread hit, if any. It keeps retrying,
+    /// serving the version it has meanwhile.
+    pub fn last_error(&self) -> Option<String> {
+        self.shared.last_error.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Follower {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+    use std::time::Instant;
+
+    fn writer(store: &MemPageStore, name: &str) -> Connection {
+        craftsql_vfs::register(name, store.clone()).unwrap();
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE;
+        let db = Connection::open_with_flags_and_vfs(format!("/craftsql/{}/db", name), flags, name).unwrap();
+        db.execute_batch("
+            PRAGMA journal_mode=DELETE;
+            CREATE TABLE t (id INTEGER PRIMARY KEY);
+            INSERT INTO t VALUES (1);
+        ").unwrap();
+        db
+    }
+
+    fn count(replica: &Replica) -> i64 {
+        replica.connection().query_row("SELECT count(*) FROM t", [], |r| r.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_refresh() {
+        let store = MemPageStore::new();
+        assert!(matches!(
+            Follower::open(Arc::new(store.clone()), Follow::Root),
+            Err(FollowerError::NoRoot(_))
+        ));
+        let db = writer(&store, "craftsql_follower_test_refresh");
+        let follower = Follower::open(Arc::new(store.clone()), Follow::Root).unwrap();
+        let first = follower.current();
+        assert_eq!(count(&first), 1);
+        assert!(!follower.refresh().unwrap());
+
+        db.execute("INSERT INTO t VALUES (2)", []).unwrap();
+        assert!(follower.refresh().unwrap());
+        assert_eq!(count(&follower.current()), 2);
+        // A reader holding the old version still sees it
+        assert_eq!(count(&first), 1);
+        assert!(follower.current().connection().execute("DELETE FROM t", []).is_err());
+    }
+
+    #[test]
+    fn test_follow_in_background() {
+        let store = MemPageStore::new();
+        let db = writer(&store, "craftsql_follower_test_background");
+        store.set_named_root("main", store.current_root().unwrap().unwrap()).unwrap();
+        let follower = Follower::open(Arc::new(store.clone()), Follow::Named("main".into()))
+            .unwrap()
+            .start(Duration::from_secs(60));
+
+        // Only the named root is followed
+        db.execute("INSERT INTO t VALUES (2)", []).unwrap();
+        let moved = store.current_root().unwrap().unwrap();
+        assert_eq!(count(&follower.current()), 1);
+        store.set_named_root("main", moved).unwrap();
+        let started = Instant::now();
+        while follower.root() != moved {
+            assert!(started.elapsed() < Duration::from_secs(10), "follower never moved");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(count(&follower.current()), 2);
+        assert_eq!(follower.last_error(), None);
+    }
+}
@@ -0,0 +1,227 @@
+//! Mount a store as a directory of read-only SQLite files.
+//!
+//! ```text
+//! <mountpoint>/branches/<name>.sqlite
+//! <mountpoint>/snapshots/<name>.sqlite
+//! ```
+//!
+//! Any tool that opens SQLite files (the `sqlite3` shell, Datasette, DB
+//! Browser) can then read any version of a database without knowing about
+//! CraftSQL. Files are materialized lazily: a read fetches just the pages it
+//! covers from the store, so opening a large database over a remote store
+//! costs only what the query touches.
+//!
+//! Listings follow the store's named roots as they change. An open file
+//! keeps reading the version it was opened at, so a branch moving underneath
+//! a reader never hands it a torn database; reopen it to see the new one.
+//!
+//! Mounting needs `fusermount3` (from fuse3) on the `PATH`, and so Unix:
+//! elsewhere the crate is empty.
+#![cfg(unix)]
+
+mod tree;
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use craftsql_core::PageStore;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, Request,
+};
+
+use tree::{Dir, Snapshot, Tree, EXTENSION, ROOT_INODE};
+
+/// How long the kernel may cache attributes and lookups. Short, so moved
+/// branches show up promptly.
+const TTL: Duration = Duration::from_secs(1);
+
+/// The FUSE filesystem over a store; see the [crate docs](crate).
+pub struct SnapshotFs<S> {
+    tree: Tree<S>,
+    /// Open files, each pinned to the version it was opened at.
+    handles: HashMap<u64, Snapshot>,
+    next_handle: u64,
+    uid: u32,
+    gid: u32,
+    mounted_at: SystemTime,
+}
+
+impl<S: PageStore> SnapshotFs<S> {
+    pub fn new(store: S) -> Self {
+        // Files belong to whoever mounted them
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Self {
+            tree: Tree::new(store),
+            handles: HashMap::new(),
+            next_handle: 1,
+            uid,
+            gid,
+            mounted_at: SystemTime::now(),
+        }
+    }
+
+    fn attr(&self, inode: u64, kind: FileType, size: u64) -> FileAttr {
+        FileAttr {
+            ino: inode,
+            size,
+            blocks: size.div_ceil(512),
+            atime: self.mounted_at,
+            mtime: self.mounted_at,
+            ctime: self.mounted_at,
+            crtime: self.mounted_at,
+            kind,
+            perm: if kind == FileType::Directory { 0o555 } else { 0o444 },
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    fn dir_attr(&self, inode: u64) -> FileAttr {
+        self.attr(inode, FileType::Directory, 0)
+    }
+
+    /// Attributes of the file at `inode` as of its root now.
+    fn file_attr(&mut self, inode: u64) -> Result<FileAttr, i32> {
+        let root = self.tree.root(inode).map_err(|_| libc::EIO)?.ok_or(libc::ENOENT)?;
+        let snapshot = Snapshot::open(self.tree.store(), &root).map_err(|_| libc::EIO)?;
+        Ok(self.attr(inode, FileType::RegularFile, snapshot.len()))
+    }
+}
+
+impl<S: PageStore> Filesystem for SnapshotFs<S> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let Some(name) = name.to_str() else {
+            return reply.error(libc::ENOENT);
+        };
+        if parent == ROOT_INODE {
+            return match Dir::ALL.into_iter().find(|dir| dir.name() == name) {
+                Some(dir) => reply.entry(&TTL, &self.dir_attr(dir.inode()), 0),
+                None => reply.error(libc::ENOENT),
+            };
+        }
+        let Some(dir) = Dir::from_inode(parent) else {
+            return reply.error(libc::ENOENT);
+        };
+        let inode = match self.tree.lookup(dir, name) {
+            Ok(Some((inode, _))) => inode,
+            Ok(None) => return reply.error(libc::ENOENT),
+            Err(_) => return reply.error(libc::EIO),
+        };
+        match self.file_attr(inode) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, inode: u64, reply: ReplyAttr) {
+        if inode == ROOT_INODE || Dir::from_inode(inode).is_some() {
+            return reply.attr(&TTL, &self.dir_attr(inode));
+        }
+        match self.file_attr(inode) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, inode: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return reply.error(libc::EROFS);
+        }
+        let root = match self.tree.root(inode) {
+            Ok(Some(root)) => root,
+            Ok(None) => return reply.error(libc::ENOENT),
+            Err(_) => return reply.error(libc::EIO),
+        };
+        match Snapshot::open(self.tree.store(), &root) {
+            Ok(snapshot) => {
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.handles.insert(handle, snapshot);
+                reply.opened(handle, 0);
+            }
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _inode: u64,
+        handle: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(snapshot) = self.handles.get(&handle) else {
+            return reply.error(libc::EBADF);
+        };
+        match snapshot.read(self.tree.store(), offset.max(0) as u64, size as u64) {
+            Ok(data) => reply.data(&data),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _inode: u64,
+        handle: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.handles.remove(&handle);
+        reply.ok();
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, inode: u64, _handle: u64, offset: i64, mut reply: ReplyDirectory) {
+        let mut entries = vec![(inode, FileType::Directory, ".".to_string()), (ROOT_INODE, FileType::Directory, "..".to_string())];
+        if inode == ROOT_INODE {
+            entries.extend(Dir::ALL.map(|dir| (dir.inode(), FileType::Directory, dir.name().to_string())));
+        } else if let Some(dir) = Dir::from_inode(inode) {
+            match self.tree.list(dir) {
+                Ok(files) => entries.extend(files.into_iter().map(|(inode, name, _)| {
+                    (inode, FileType::RegularFile, format!("{}{}", name, EXTENSION))
+                })),
+                Err(_) => return reply.error(libc::EIO),
+            }
+        } else {
+            return reply.error(libc::ENOTDIR);
+        }
+
+        for (i, (inode, kind, name)) in entries.into_iter().enumerate().skip(offset.max(0) as usize) {
+            // The offset given back is where the next call resumes
+            if reply.add(inode, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+fn options() -> Vec<MountOption> {
+    vec![MountOption::RO, MountOption::FSName("craftsql".into()), MountOption::Subtype("craftsql".into())]
+}
+
+/// Mount `store` at `mountpoint` read-only and serve it until unmounted
+/// (e.g. with `fusermount3 -u`).
+pub fn mount<S: PageStore + 'static>(store: S, mountpoint: &Path) -> io::Result<()> {
+    fuser::mount2(SnapshotFs::new(store), mountpoint, &options())
+}
+
+/// Mount `store` at `mountpoint` read-only, served from a background
+/// thread; dropping the session unmounts it.
+pub fn spawn_mount<S: PageStore + 'static>(store: S, mountpoint: &Path) -> io::Result<fuser::BackgroundSession> {
+    fuser::spawn_mount2(SnapshotFs::new(store), mountpoint, &options())
+}
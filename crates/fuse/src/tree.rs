@@ -0,0 +1,237 @@
+//! What the mount shows, independent of FUSE: which files exist, their
+//! inodes, and their bytes.
+
+use std::collections::HashMap;
+
+use craftsql_core::{load_page_table, Cid, PageStore, PageTable, Result};
+
+/// Prefix of the named roots that hold snapshots, as the CLI names them.
+const SNAPSHOT_PREFIX: &str = "snapshot.";
+
+/// Extension of every file in the mount.
+pub(crate) const EXTENSION: &str = ".sqlite";
+
+/// Inodes of the fixed directories; files are numbered after them.
+pub(crate) const ROOT_INODE: u64 = 1;
+pub(crate) const BRANCHES_INODE: u64 = 2;
+pub(crate) const SNAPSHOTS_INODE: u64 = 3;
+const FIRST_FILE_INODE: u64 = 4;
+
+/// A directory of databases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Dir {
+    Branches,
+    Snapshots,
+}
+
+impl Dir {
+    pub(crate) const ALL: [Dir; 2] = [Dir::Branches, Dir::Snapshots];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Dir::Branches => "branches",
+            Dir::Snapshots => "snapshots",
+        }
+    }
+
+    pub(crate) fn inode(self) -> u64 {
+        match self {
+            Dir::Branches => BRANCHES_INODE,
+            Dir::Snapshots => SNAPSHOTS_INODE,
+        }
+    }
+
+    pub(crate) fn from_inode(inode: u64) -> Option<Dir> {
+        Dir::ALL.into_iter().find(|dir| dir.inode() == inode)
+    }
+
+    /// The branch or snapshot a named root holds, if any.
+    fn entry(name: &str) -> Option<(Dir, &str)> {
+        if let Some(snapshot) = name.strip_prefix(SNAPSHOT_PREFIX) {
+            return Some((Dir::Snapshots, snapshot));
+        }
+        // Bookkeeping and the roots of a namespace's databases aren't branches
+        if name.starts_with('.') || name.starts_with(craftsql_namespace::PREFIX) {
+            return None;
+        }
+        Some((Dir::Branches, name))
+    }
+}
+
+/// Branches and snapshots of a store, each with a stable inode.
+pub(crate) struct Tree<S> {
+    store: S,
+    inodes: HashMap<(Dir, String), u64>,
+    names: HashMap<u64, (Dir, String)>,
+}
+
+impl<S: PageStore> Tree<S> {
+    pub(crate) fn new(store: S) -> Self {
+        Self { store, inodes: HashMap::new(), names: HashMap::new() }
+    }
+
+    pub(crate) fn store(&self) -> &S {
+        &self.store
+    }
+
+    fn inode(&mut self, dir: Dir, name: &str) -> u64 {
+        let next = FIRST_FILE_INODE + self.inodes.len() as u64;
+        let inode = *self.inodes.entry((dir, name.to_string())).or_insert(next);
+        self.names.entry(inode).or_insert_with(|| (dir, name.to_string()));
+        inode
+    }
+
+    /// The databases in `dir` as `(inode, name, root)`, names without the extension.
+    pub(crate) fn list(&mut self, dir: Dir) -> Result<Vec<(u64, String, Cid)>> {
+        let mut entries = Vec::new();
+        for (name, root) in self.store.list_named_roots()? {
+            if let Some((_, name)) = Dir::entry(&name).filter(|(found, _)| *found == dir) {
+                entries.push((self.inode(dir, name), name.to_string(), root));
+            }
+        }
+        entries.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(entries)
+    }
+
+    /// The inode and current root of `file` (a name with the extension) in `dir`.
+    pub(crate) fn lookup(&mut self, dir: Dir, file: &str) -> Result<Option<(u64, Cid)>> {
+        let Some(name) = file.strip_suffix(EXTENSION) else {
+            return Ok(None);
+        };
+        let named = match dir {
+            Dir::Branches => name.to_string(),
+            Dir::Snapshots => format!("{}{}", SNAPSHOT_PREFIX, name),
+        };
+        if Dir::entry(&named) != Some((dir, name)) {
+            return Ok(None);
+        }
+        Ok(self.store.get_named_root(&named)?.map(|root| (self.inode(dir, name), root)))
+    }
+
+    /// The current root of the file at `inode`, if it still exists.
+    pub(crate) fn root(&mut self, inode: u64) -> Result<Option<Cid>> {
+        let Some((dir, name)) = self.names.get(&inode).cloned() else {
+            return Ok(None);
+        };
+        Ok(self.lookup(dir, &format!("{}{}", name, EXTENSION))?.map(|(_, root)| root))
+    }
+}
+
+/// One version of a database, read as a SQLite file a page at a time.
+pub(crate) struct Snapshot {
+    page_table: PageTable,
+    page_size: u64,
+}
+
+impl Snapshot {
+    /// The database at `root`, a commit or a page table.
+    pub(crate) fn open(store: &dyn PageStore, root: &Cid) -> Result<Self> {
+        let page_table = load_page_table(store, root)?;
+        let page_size = page_table.page_size_in(store)?.unwrap_or(4096) as u64;
+        Ok(Self { page_table, page_size })
+    }
+
+    /// Size of the file in bytes.
+    pub(crate) fn len(&self) -> u64 {
+        self.page_table.len() as u64 * self.page_size
+    }
+
+    /// Up to `size` bytes at `offset`; fewer at the end of the file.
+    pub(crate) fn read(&self, store: &dyn PageStore, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let end = offset.saturating_add(size).min(self.len());
+        let mut out = Vec::with_capacity(end.saturating_sub(offset) as usize);
+        let mut pos = offset;
+        while pos < end {
+            let index = pos / self.page_size;
+            let mut page = match self.page_table.get(index as usize) {
+                Some(cid) => store.get(cid)?.data,
+                None => Vec::new(),
+            };
+            page.resize(self.page_size as usize, 0);
+            if index == 0 {
+                // File format read and write versions: a WAL-mode header would
+                // have readers look for a -wal file they can't create here
+                for version in &mut page[18..20] {
+                    if *version == 2 {
+                        *version = 1;
+                    }
+                }
+            }
+            let start = (pos - index * self.page_size) as usize;
+            let stop = ((end - index * self.page_size) as usize).min(page.len());
+            out.extend_from_slice(&page[start..stop]);
+            pos = (index + 1) * self.page_size;
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::{export_sqlite, import_sqlite, Commit};
+    use craftsql_store_mem::MemPageStore;
+
+    /// Import a real database with a few pages.
+    fn database(store: &MemPageStore, dir: &std::path::Path, rows: usize) -> Cid {
+        let path = dir.join(format!("{}.db", rows));
+        let db = rusqlite::Connection::open(&path).unwrap();
+        db.execute_batch("PRAGMA page_size = 1024; CREATE TABLE t (x TEXT);").unwrap();
+        for i in 0..rows {
+            db.execute("INSERT INTO t VALUES (?1)", [format!("row {:0>100}", i)]).unwrap();
+        }
+        drop(db);
+        import_sqlite(store, &path, "import").unwrap().commit
+    }
+
+    #[test]
+    fn test_snapshot_reads_the_sqlite_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MemPageStore::new();
+        let commit = database(&store, dir.path(), 50);
+        let exported = dir.path().join("exported.db");
+        export_sqlite(&store, &commit, &exported).unwrap();
+        let expected = std::fs::read(&exported).unwrap();
+
+        let snapshot = Snapshot::open(&store, &commit).unwrap();
+        assert_eq!(snapshot.len(), expected.len() as u64);
+        assert_eq!(snapshot.read(&store, 0, u64::MAX).unwrap(), expected);
+        // Reads across page boundaries, and past the end
+        assert_eq!(snapshot.read(&store, 1000, 100).unwrap(), expected[1000..1100]);
+        assert_eq!(snapshot.read(&store, snapshot.len() - 10, 100).unwrap(), expected[expected.len() - 10..]);
+        assert!(snapshot.read(&store, snapshot.len() + 1, 100).unwrap().is_empty());
+
+        // The page table alone works as well
+        let page_table = Commit::load(&store, &commit).unwrap().unwrap().root;
+        assert_eq!(Snapshot::open(&store, &page_table).unwrap().len(), snapshot.len());
+    }
+
+    #[test]
+    fn test_tree_lists_branches_and_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MemPageStore::new();
+        let (old, new) = (database(&store, dir.path(), 1), database(&store, dir.path(), 2));
+        for (name, root) in [("main", new), ("snapshot.v1", old), (".head", new), ("db.app.root", new)] {
+            store.set_named_root(name, root).unwrap();
+        }
+
+        let mut tree = Tree::new(store);
+        let branches = tree.list(Dir::Branches).unwrap();
+        assert_eq!(branches.iter().map(|e| (e.1.as_str(), e.2)).collect::<Vec<_>>(), vec![("main", new)]);
+        let snapshots = tree.list(Dir::Snapshots).unwrap();
+        assert_eq!(snapshots.iter().map(|e| (e.1.as_str(), e.2)).collect::<Vec<_>>(), vec![("v1", old)]);
+
+        // Inodes are stable and distinct, and follow the root as it moves
+        let (inode, root) = tree.lookup(Dir::Branches, "main.sqlite").unwrap().unwrap();
+        assert_eq!((inode, root), (branches[0].0, new));
+        assert_ne!(inode, snapshots[0].0);
+        tree.store().set_named_root("main", old).unwrap();
+        assert_eq!(tree.root(inode).unwrap(), Some(old));
+
+        for (dir, file) in [(Dir::Branches, "main"), (Dir::Branches, ".head.sqlite"), (Dir::Snapshots, "main.sqlite")] {
+            assert!(tree.lookup(dir, file).unwrap().is_none(), "{}", file);
+        }
+        tree.store().remove_named_root("main").unwrap();
+        assert_eq!(tree.root(inode).unwrap(), None);
+    }
+}
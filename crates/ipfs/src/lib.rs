@@ -0,0 +1,607 @@
+//! IPFS network backend — stores CraftSQL content on an IPFS node.
+//!
+//! Implements [`NetworkBackend`] against the HTTP RPC API of an IPFS node
+//! (Kubo's `/api/v0`), as an alternative to the CraftOBJ daemon:
+//!
+//! ```text
+//! SQLite ←→ CraftVFS ←→ CraftObjPageStore<IpfsBackend> ←→ IPFS node (HTTP API)
+//! ```
+//!
+//! Content is added with `add` as a single raw block (CIDv1, `raw` codec,
+//! SHA-256), so its IPFS CID is derived from the CraftSQL [`Cid`] and back
+//! again; see [`ipfs_cid`]. That limits each published object to
+//! [`MAX_BLOCK_SIZE`] bytes — use
+//! `CraftObjPageStore::with_segment_size(MAX_BLOCK_SIZE as u64)` so bundles
+//! are split to fit. Content is read back with `cat`, which serves byte
+//! ranges natively. It's added pinned, so the node's garbage collection
+//! leaves it until it's unpinned, as pruned bundles are.
+//!
+//! Root pointers are IPNS names: each root is published under its own key
+//! on the node, `<prefix>` for the default root and `<prefix>.<hex name>`
+//! for named roots. Other nodes can follow a root by resolving the key's
+//! IPNS name, but only the node holding the keys can move it.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use craftsql_core::{Cid, PageStoreError, Result};
+use craftsql_objstore::NetworkBackend;
+
+/// Largest object that fits in one raw block.
+pub const MAX_BLOCK_SIZE: usize = 1 << 20;
+
+/// Default API address of a local node.
+pub const DEFAULT_API_URL: &str = "http://127.0.0.1:5001";
+
+/// Default prefix of the IPNS key names holding root pointers.
+const DEFAULT_KEY_PREFIX: &str = "craftsql";
+
+/// CIDv1 header: version 1, `raw` codec, SHA-256 multihash of 32 bytes.
+const CID_PREFIX: [u8; 4] = [0x01, 0x55, 0x12, 0x20];
+
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// The IPFS CID (base32 CIDv1, raw codec) of the content with CraftSQL CID `cid`.
+pub fn ipfs_cid(cid: &Cid) -> String {
+    let mut bytes = CID_PREFIX.to_vec();
+    bytes.extend_from_slice(&cid.0);
+
+    // Multibase prefix `b`: lowercase RFC 4648 base32 without padding
+    let mut out = String::from("b");
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+/// The CraftSQL CID of an IPFS CID made by [`ipfs_cid`]; `None` for any
+/// other kind of CID.
+pub fn parse_ipfs_cid(text: &str) -> Option<Cid> {
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.strip_prefix('b')?.bytes() {
+        let value = BASE32.iter().position(|&b| b == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    let digest = bytes.strip_prefix(&CID_PREFIX[..])?;
+    Some(Cid(digest.try_into().ok()?))
+}
+
+/// NetworkBackend that talks to an IPFS node's HTTP API.
+///
+/// Each call is one HTTP request on a fresh connection.
+pub struct IpfsBackend {
+    api_url: String,
+    timeout: Duration,
+    auth_token: Option<String>,
+    key_prefix: String,
+    /// IPNS names of keys looked up so far, by key name.
+    key_ids: Mutex<HashMap<String, String>>,
+}
+
+impl IpfsBackend {
+    /// Connect to the node whose API is at `api_url` (`http://host:port`).
+    pub fn new(api_url: &str) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            timeout: Duration::from_secs(60),
+            auth_token: None,
+            key_prefix: DEFAULT_KEY_PREFIX.to_string(),
+            key_ids: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Connect to a node on this host at [`DEFAULT_API_URL`].
+    pub fn local() -> Self {
+        Self::new(DEFAULT_API_URL)
+    }
+
+    /// Set the request timeout. IPNS publishes can take a while.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send `token` as a bearer token, for APIs behind authorization.
+    pub fn with_auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// Name root keys `<prefix>` and `<prefix>.<hex name>`, so several
+    /// databases can share a node.
+    pub fn with_key_prefix(mut self, prefix: &str) -> Self {
+        self.key_prefix = prefix.to_string();
+        self
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    /// The IPNS name other nodes resolve to follow the default root, once
+    /// it has been set.
+    pub fn root_ipns_name(&self) -> Result<Option<String>> {
+        self.key_id(&self.key_prefix)
+    }
+
+    fn key_name(&self, name: &str) -> String {
+        format!("{}.{}", self.key_prefix, hex::encode(name))
+    }
+
+    /// POST to API command `command` and return the response body.
+    fn call(&self, command: &str, args: &[(&str, &str)], file: Option<&[u8]>) -> Result<Vec<u8>> {
+        let rest = self.api_url.strip_prefix("http://").ok_or_else(|| {
+            PageStoreError::Storage(format!("unsupported IPFS API URL {} (only http:// is supported)", self.api_url))
+        })?;
+        let (host, base) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        let addr = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+
+        let query: Vec<String> = args.iter().map(|(k, v)| format!("{}={}", k, encode_query(v))).collect();
+        let path = format!("{}/api/v0/{}?{}", base, command, query.join("&"));
+
+        // Files go as multipart form data; a boundary naming the content's
+        // hash can't occur inside it
+        let (content_type, body) = match file {
+            Some(data) => {
+                let boundary = format!("craftsql-{}", Cid::from_bytes(data).to_hex());
+                let mut body = format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n",
+                    boundary
+                ).into_bytes();
+                body.extend_from_slice(data);
+                body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+                (format!("multipart/form-data; boundary={}", boundary), body)
+            }
+            None => ("application/x-www-form-urlencoded".to_string(), Vec::new()),
+        };
+
+        let failed = |e: std::io::Error| PageStoreError::network(format_args!("IPFS API at {}", self.api_url), e);
+        let mut stream = TcpStream::connect(&addr).map_err(failed)?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(failed)?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(failed)?;
+
+        let auth = self.auth_token.as_ref()
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        let head = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\n{}Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            path, host, auth, content_type, body.len()
+        );
+        stream.write_all(head.as_bytes()).map_err(failed)?;
+        stream.write_all(&body).map_err(failed)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map_err(failed)?;
+
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n")
+            .ok_or_else(|| PageStoreError::Storage(format!("malformed HTTP response to {}", command)))?;
+        let status = String::from_utf8_lossy(&response[..split]).lines().next().unwrap_or("").to_string();
+        let body = response[split + 4..].to_vec();
+        match status.split_whitespace().nth(1) {
+            Some("200") => Ok(body),
+            Some("401" | "403") => Err(PageStoreError::Unauthorized(format!("IPFS {}: {}", command, status))),
+            _ => Err(PageStoreError::Storage(format!("IPFS {}: {}", command, api_message(&body, &status)))),
+        }
+    }
+
+    fn call_json(&self, command: &str, args: &[(&str, &str)]) -> Result<serde_json::Value> {
+        let body = self.call(command, args, None)?;
+        serde_json::from_slice(&body)
+            .map_err(|e| PageStoreError::Storage(format!("parse IPFS {} response: {}", command, e)))
+    }
+
+    /// Names and IPNS names of every key on the node.
+    fn keys(&self) -> Result<Vec<(String, String)>> {
+        let result = self.call_json("key/list", &[])?;
+        let keys = result.get("Keys").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+        let keys: Vec<(String, String)> = keys.iter()
+            .filter_map(|key| Some((key.get("Name")?.as_str()?.to_string(), key.get("Id")?.as_str()?.to_string())))
+            .collect();
+        self.key_ids.lock().unwrap().extend(keys.iter().cloned());
+        Ok(keys)
+    }
+
+    /// The IPNS name of key `key`, if the node has it.
+    fn key_id(&self, key: &str) -> Result<Option<String>> {
+        if let Some(id) = self.key_ids.lock().unwrap().get(key) {
+            return Ok(Some(id.clone()));
+        }
+        Ok(self.keys()?.into_iter().find(|(name, _)| name == key).map(|(_, id)| id))
+    }
+
+    /// What IPNS name `id` points at; `None` if nothing was published.
+    fn resolve(&self, id: &str) -> Result<Option<Cid>> {
+        let result = match self.call_json("name/resolve", &[("arg", id), ("nocache", "true")]) {
+            Ok(result) => result,
+            // The node reports a name with no record as a failure to resolve
+            Err(PageStoreError::Storage(msg)) if msg.contains("resolve") => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let path = result.get("Path").and_then(|v| v.as_str()).unwrap_or("");
+        path.strip_prefix("/ipfs/")
+            .and_then(parse_ipfs_cid)
+            .map(Some)
+            .ok_or_else(|| PageStoreError::Storage(format!("IPNS name {} points at {}, not a CraftSQL root", id, path)))
+    }
+
+    fn get_key_root(&self, key: &str) -> Result<Option<Cid>> {
+        match self.key_id(key)? {
+            Some(id) => self.resolve(&id),
+            None => Ok(None),
+        }
+    }
+
+    fn set_key_root(&self, key: &str, cid: Cid) -> Result<()> {
+        if self.key_id(key)?.is_none() {
+            let result = self.call_json("key/gen", &[("arg", key), ("type", "ed25519")])?;
+            if let Some(id) = result.get("Id").and_then(|v| v.as_str()) {
+                self.key_ids.lock().unwrap().insert(key.to_string(), id.to_string());
+            }
+        }
+        let path = format!("/ipfs/{}", ipfs_cid(&cid));
+        self.call_json("name/publish", &[("arg", &path), ("key", key), ("allow-offline", "true")])?;
+        Ok(())
+    }
+}
+
+/// The `Message` of an API error body, or the status line.
+fn api_message(body: &[u8], status: &str) -> String {
+    serde_json::from_slice::<serde_json::Value>(body).ok()
+        .and_then(|v| v.get("Message")?.as_str().map(String::from))
+        .unwrap_or_else(|| status.to_string())
+}
+
+/// Percent-encode everything but unreserved characters and `/`.
+fn encode_query(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl NetworkBackend for IpfsBackend {
+    fn publish_page(&self, data: &[u8]) -> Result<Cid> {
+        if data.len() > MAX_BLOCK_SIZE {
+            return Err(PageStoreError::Storage(format!(
+                "{} bytes won't fit one IPFS block of {} (lower the segment size)", data.len(), MAX_BLOCK_SIZE
+            )));
+        }
+        let cid = Cid::from_bytes(data);
+        let chunker = format!("size-{}", MAX_BLOCK_SIZE);
+        let body = self.call("add", &[
+            ("cid-version", "1"),
+            ("raw-leaves", "true"),
+            ("hash", "sha2-256"),
+            ("chunker", &chunker),
+            ("pin", "true"),
+        ], Some(data))?;
+
+        let expected = ipfs_cid(&cid);
+        let added = serde_json::from_slice::<serde_json::Value>(&body).ok()
+            .and_then(|v| v.get("Hash")?.as_str().map(String::from));
+        if added.as_deref() != Some(expected.as_str()) {
+            return Err(PageStoreError::Storage(format!(
+                "IPFS node added {} as {:?}, expected {}", cid, added, expected
+            )));
+        }
+        Ok(cid)
+    }
+
+    fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
+        let data = self.call("cat", &[("arg", &ipfs_cid(cid))], None)?;
+        let actual = Cid::from_bytes(&data);
+        if actual != *cid {
+            return Err(PageStoreError::Corruption { expected: *cid, actual });
+        }
+        Ok(data)
+    }
+
+    fn supports_range(&self) -> bool {
+        true
+    }
+
+    fn fetch_range(&self, cid: &Cid, offset: u64, len: u64) -> Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let (offset, length) = (offset.to_string(), len.to_string());
+        self.call("cat", &[("arg", &ipfs_cid(cid)), ("offset", &offset), ("length", &length)], None)
+    }
+
+    fn pin(&self, cid: &Cid) -> Result<()> {
+        self.call_json("pin/add", &[("arg", &ipfs_cid(cid))])?;
+        Ok(())
+    }
+
+    fn unpin(&self, cid: &Cid) -> Result<()> {
+        match self.call_json("pin/rm", &[("arg", &ipfs_cid(cid))]) {
+            Err(PageStoreError::Storage(msg)) if msg.contains("not pinned") => Ok(()),
+            result => result.map(drop),
+        }
+    }
+
+    fn get_root(&self) -> Result<Option<Cid>> {
+        self.get_key_root(&self.key_prefix)
+    }
+
+    fn set_root(&self, cid: Cid) -> Result<()> {
+        self.set_key_root(&self.key_prefix, cid)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.get_key_root(&self.key_name(name))
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.set_key_root(&self.key_name(name), cid)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        let key = self.key_name(name);
+        if self.key_id(&key)?.is_none() {
+            return Ok(false);
+        }
+        self.call_json("key/rm", &[("arg", &key)])?;
+        self.key_ids.lock().unwrap().remove(&key);
+        Ok(true)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        let prefix = format!("{}.", self.key_prefix);
+        let mut roots = Vec::new();
+        for (key, id) in self.keys()? {
+            let Some(name) = key.strip_prefix(&prefix)
+                .and_then(|name| hex::decode(name).ok())
+                .and_then(|name| String::from_utf8(name).ok())
+            else {
+                continue;
+            };
+            if let Some(cid) = self.resolve(&id)? {
+                roots.push((name, cid));
+            }
+        }
+        roots.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(roots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::PageStore;
+    use craftsql_objstore::CraftObjPageStore;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    /// Enough of a node's API for the backend: blocks, keys, and IPNS records.
+    #[derive(Default)]
+    struct Node {
+        blocks: Mutex<HashMap<String, Vec<u8>>>,
+        pins: Mutex<std::collections::HashSet<String>>,
+        /// Key name → (IPNS name, published path).
+        keys: Mutex<HashMap<String, (String, Option<String>)>>,
+        keys_made: std::sync::atomic::AtomicUsize,
+    }
+
+    fn serve() -> (String, Arc<Node>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let node = Arc::new(Node::default());
+        let state = Arc::clone(&node);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = Arc::clone(&state);
+                std::thread::spawn(move || handle(stream, &state));
+            }
+        });
+        (url, node)
+    }
+
+    fn handle(mut stream: TcpStream, node: &Node) {
+        let mut reader = BufReader::new(&stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let target = line.split_whitespace().nth(1).unwrap().to_string();
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some(value) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        let (command, query) = target.strip_prefix("/api/v0/").unwrap().split_once('?').unwrap();
+        let args: HashMap<&str, String> = query.split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| (k, v.replace("%2F", "/")))
+            .collect();
+
+        let ok = |json: serde_json::Value| (200, json.to_string().into_bytes());
+        let fail = |msg: &str| (500, serde_json::json!({ "Message": msg, "Code": 0 }).to_string().into_bytes());
+        let (status, content) = match command {
+            "add" => {
+                let start = body.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+                let end = body.windows(4).rposition(|w| w == b"\r\n--").unwrap();
+                let data = body[start..end].to_vec();
+                let hash = ipfs_cid(&Cid::from_bytes(&data));
+                node.blocks.lock().unwrap().insert(hash.clone(), data);
+                if args.get("pin").is_some_and(|pin| pin == "true") {
+                    node.pins.lock().unwrap().insert(hash.clone());
+                }
+                ok(serde_json::json!({ "Name": "file", "Hash": hash }))
+            }
+            "cat" => match node.blocks.lock().unwrap().get(&args["arg"]) {
+                Some(data) => {
+                    let offset = args.get("offset").map_or(0, |o| o.parse().unwrap());
+                    let length = args.get("length").map_or(usize::MAX, |l| l.parse().unwrap());
+                    let start = offset.min(data.len());
+                    (200, data[start..start.saturating_add(length).min(data.len())].to_vec())
+                }
+                None => fail("block not found"),
+            },
+            "pin/add" if node.blocks.lock().unwrap().contains_key(&args["arg"]) => {
+                node.pins.lock().unwrap().insert(args["arg"].clone());
+                ok(serde_json::json!({ "Pins": [args["arg"]] }))
+            }
+            "pin/add" => fail("block not found"),
+            "pin/rm" => match node.pins.lock().unwrap().remove(&args["arg"]) {
+                true => ok(serde_json::json!({ "Pins": [args["arg"]] })),
+                false => fail("not pinned or pinned indirectly"),
+            },
+            "key/gen" => {
+                let id = format!("k51{}", node.keys_made.fetch_add(1, std::sync::atomic::Ordering::SeqCst));
+                node.keys.lock().unwrap().insert(args["arg"].clone(), (id.clone(), None));
+                ok(serde_json::json!({ "Name": args["arg"], "Id": id }))
+            }
+            "key/list" => {
+                let keys: Vec<_> = node.keys.lock().unwrap().iter()
+                    .map(|(name, (id, _))| serde_json::json!({ "Name": name, "Id": id }))
+                    .collect();
+                ok(serde_json::json!({ "Keys": keys }))
+            }
+            "key/rm" => {
+                node.keys.lock().unwrap().remove(&args["arg"]);
+                ok(serde_json::json!({ "Keys": [] }))
+            }
+            "name/publish" => match node.keys.lock().unwrap().get_mut(&args["key"]) {
+                Some((id, path)) => {
+                    *path = Some(args["arg"].clone());
+                    ok(serde_json::json!({ "Name": id, "Value": args["arg"] }))
+                }
+                None => fail("no key by the given name was found"),
+            },
+            "name/resolve" => {
+                let keys = node.keys.lock().unwrap();
+                match keys.values().find(|(id, _)| *id == args["arg"]).and_then(|(_, path)| path.clone()) {
+                    Some(path) => ok(serde_json::json!({ "Path": path })),
+                    None => fail("could not resolve name"),
+                }
+            }
+            _ => (404, b"404 page not found".to_vec()),
+        };
+        let head = format!("HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, content.len());
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(&content).unwrap();
+    }
+
+    #[test]
+    fn test_ipfs_cid() {
+        // The well-known CID of an empty raw block
+        let empty = Cid::from_bytes(b"");
+        assert_eq!(ipfs_cid(&empty), "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku");
+        assert_eq!(parse_ipfs_cid(&ipfs_cid(&empty)), Some(empty));
+
+        let cid = Cid::from_bytes(b"page");
+        assert_eq!(parse_ipfs_cid(&ipfs_cid(&cid)), Some(cid));
+        // CIDv0 and DAG CIDs aren't CraftSQL content
+        assert_eq!(parse_ipfs_cid("QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR"), None);
+        assert_eq!(parse_ipfs_cid("bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"), None);
+    }
+
+    #[test]
+    fn test_content_and_ranges() {
+        let (url, _node) = serve();
+        let backend = IpfsBackend::new(&url);
+
+        let data = b"content on ipfs";
+        let cid = backend.publish_page(data).unwrap();
+        assert_eq!(cid, Cid::from_bytes(data));
+        assert_eq!(backend.fetch_page(&cid).unwrap(), data);
+        assert_eq!(backend.fetch_range(&cid, 11, 100).unwrap(), b"ipfs");
+        assert!(backend.fetch_page(&Cid::from_bytes(b"missing")).is_err());
+        assert!(backend.publish_page(&vec![0; MAX_BLOCK_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_pins() {
+        let (url, node) = serve();
+        let backend = IpfsBackend::new(&url);
+        let cid = backend.publish_page(b"pinned when added").unwrap();
+        let pinned = || node.pins.lock().unwrap().contains(&ipfs_cid(&cid));
+        assert!(pinned());
+
+        backend.unpin(&cid).unwrap();
+        backend.unpin(&cid).unwrap();
+        assert!(!pinned());
+        backend.pin(&cid).unwrap();
+        assert!(pinned());
+        assert!(backend.pin(&Cid::from_bytes(b"missing")).is_err());
+    }
+
+    #[test]
+    fn test_roots_are_ipns_names() {
+        let (url, node) = serve();
+        let backend = IpfsBackend::new(&url).with_key_prefix("db");
+        let (a, b) = (Cid::from_bytes(b"a"), Cid::from_bytes(b"b"));
+
+        assert_eq!(backend.get_root().unwrap(), None);
+        assert_eq!(backend.root_ipns_name().unwrap(), None);
+        backend.set_root(a).unwrap();
+        backend.set_root(b).unwrap();
+        assert_eq!(backend.get_root().unwrap(), Some(b));
+        assert!(backend.root_ipns_name().unwrap().is_some());
+
+        backend.set_named_root("main", a).unwrap();
+        backend.set_named_root("dev/x", b).unwrap();
+        assert_eq!(backend.get_named_root("main").unwrap(), Some(a));
+        assert_eq!(backend.list_named_roots().unwrap(), vec![("dev/x".into(), b), ("main".into(), a)]);
+        assert!(backend.remove_named_root("main").unwrap());
+        assert!(!backend.remove_named_root("main").unwrap());
+        assert_eq!(backend.get_named_root("main").unwrap(), None);
+
+        // Keys from other prefixes are left alone
+        let other = IpfsBackend::new(&url).with_key_prefix("other");
+        other.set_named_root("main", a).unwrap();
+        assert_eq!(backend.list_named_roots().unwrap(), vec![("dev/x".into(), b)]);
+        assert_eq!(node.keys.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_page_store_over_ipfs() {
+        let (url, _node) = serve();
+        let writer_dir = tempfile::tempdir().unwrap();
+        let writer = CraftObjPageStore::new(writer_dir.path(), IpfsBackend::new(&url)).unwrap()
+            .with_segment_size(MAX_BLOCK_SIZE as u64);
+        let page = craftsql_core::Page { data: vec![7; 4096] };
+        let cid = writer.put(&page).unwrap();
+        let mut table = craftsql_core::PageTable::new();
+        table.set(0, cid);
+        let root = writer.put(&craftsql_core::Page { data: table.to_bytes() }).unwrap();
+        writer.update_root(root).unwrap();
+
+        // A fresh cache reads everything back through the node
+        let reader_dir = tempfile::tempdir().unwrap();
+        let reader = CraftObjPageStore::new(reader_dir.path(), IpfsBackend::new(&url)).unwrap();
+        assert_eq!(reader.get(&cid).unwrap().data, page.data);
+    }
+}
@@ -0,0 +1,138 @@
+//! CraftSQL Keys — encrypted pages, the keys for them, and key rotation.
+//!
+//! [`EncryptedPageStore`] wraps any store and encrypts every page with
+//! XChaCha20-Poly1305 before it reaches the inner store, so CIDs, roots,
+//! and everything the inner store sees are of ciphertext. Each encrypted
+//! page names the key it was encrypted under, so keys can change without
+//! rewriting old pages first.
+//!
+//! Keys come from a [`KeyProvider`]:
+//!
+//! - [`Keyring`] — keys held in memory, loaded from `CRAFTSQL_KEYS` or a
+//!   keyring file;
+//! - [`CallbackKeys`] — keys fetched by ID through a callback, e.g. from
+//!   the OS keychain.
+//!
+//! To rotate, make a new key current ([`Keyring::rotate`]); new pages are
+//! encrypted under it straight away. [`rekey`] then re-encrypts every page
+//! reachable from the store's roots under it. Old ciphertext stays in the
+//! inner store until garbage-collected, so keep retired keys until then.
+//!
+//! Move encrypted databases between stores through the inner stores: a
+//! copy made through an `EncryptedPageStore` would store plaintext.
+
+mod provider;
+mod rekey;
+mod store;
+
+pub use provider::{CallbackKeys, Keyring, KEYS_ENV};
+pub use rekey::{rekey, RekeyStats};
+pub use store::{key_id, EncryptedPageStore};
+
+use craftsql_core::PageStoreError;
+
+/// Longest key ID; IDs are stored in every page's header.
+pub const MAX_KEY_ID_LEN: usize = 64;
+
+/// Key errors.
+#[derive(Debug, thiserror::Error)]
+pub enum KeyError {
+    #[error("no key {0:?}")]
+    UnknownKey(String),
+    #[error("no current key")]
+    NoCurrentKey,
+    #[error("invalid key ID {0:?}: use 1 to 64 letters, digits, '-', '_' and '.'")]
+    InvalidId(String),
+    #[error("invalid key {0:?}: expected 64 hex digits")]
+    InvalidSecret(String),
+    #[error("{0} is the current key")]
+    InUse(String),
+    #[error("key provider: {0}")]
+    Provider(String),
+    #[error("keyring file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<KeyError> for PageStoreError {
+    fn from(e: KeyError) -> Self {
+        match e {
+            KeyError::Io(e) => PageStoreError::Io(e),
+            e => PageStoreError::Storage(e.to_string()),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, KeyError>;
+
+/// A 256-bit page encryption key and its ID.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    id: String,
+    secret: [u8; 32],
+}
+
+impl Key {
+    pub fn new(id: &str, secret: [u8; 32]) -> Result<Self> {
+        validate_id(id)?;
+        Ok(Self { id: id.to_string(), secret })
+    }
+
+    /// A key with secret given as 64 hex digits.
+    pub fn from_hex(id: &str, secret: &str) -> Result<Self> {
+        let secret = hex::decode(secret.trim()).ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| KeyError::InvalidSecret(id.to_string()))?;
+        Self::new(id, secret)
+    }
+
+    /// A new random key.
+    pub fn generate(id: &str) -> Result<Self> {
+        let mut secret = [0u8; 32];
+        getrandom::fill(&mut secret).map_err(|e| KeyError::Provider(format!("random key: {}", e)))?;
+        Self::new(id, secret)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn secret(&self) -> &[u8; 32] {
+        &self.secret
+    }
+
+    pub fn secret_hex(&self) -> String {
+        hex::encode(self.secret)
+    }
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Key").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+fn validate_id(id: &str) -> Result<()> {
+    let valid = !id.is_empty()
+        && id.len() <= MAX_KEY_ID_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid { Ok(()) } else { Err(KeyError::InvalidId(id.to_string())) }
+}
+
+/// Where keys come from.
+pub trait KeyProvider: Send + Sync {
+    /// The key to encrypt new pages under.
+    fn current(&self) -> Result<Key>;
+
+    /// The key named `id`, to decrypt pages encrypted under it.
+    fn get(&self, id: &str) -> Result<Key>;
+}
+
+impl<P: KeyProvider + ?Sized> KeyProvider for std::sync::Arc<P> {
+    fn current(&self) -> Result<Key> {
+        (**self).current()
+    }
+
+    fn get(&self, id: &str) -> Result<Key> {
+        (**self).get(id)
+    }
+}
@@ -0,0 +1,247 @@
+//! Key providers: an in-memory keyring (from the environment or a file) and
+//! one backed by a callback.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{validate_id, Key, KeyError, KeyProvider, Result};
+
+/// Environment variable [`Keyring::from_env`] reads.
+pub const KEYS_ENV: &str = "CRAFTSQL_KEYS";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct KeyringFile {
+    current: Option<String>,
+    /// Secrets as hex, by key ID.
+    keys: BTreeMap<String, String>,
+}
+
+/// Keys held in memory, one of them current.
+#[derive(Debug, Default)]
+pub struct Keyring {
+    state: RwLock<(Option<String>, BTreeMap<String, Key>)>,
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A keyring of `id=hex` pairs separated by `;`, the first current.
+    pub fn parse(keys: &str) -> Result<Self> {
+        let keyring = Self::new();
+        for entry in keys.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (id, secret) = entry.split_once('=').ok_or_else(|| KeyError::InvalidSecret(entry.to_string()))?;
+            let key = Key::from_hex(id.trim(), secret)?;
+            let first = keyring.current().is_err();
+            keyring.add(key);
+            if first {
+                keyring.set_current(id.trim())?;
+            }
+        }
+        Ok(keyring)
+    }
+
+    /// The keyring in [`KEYS_ENV`], or an empty one if it isn't set.
+    pub fn from_env() -> Result<Self> {
+        Self::parse(&std::env::var(KEYS_ENV).unwrap_or_default())
+    }
+
+    /// Read a keyring file written by [`save`](Self::save).
+    pub fn load(path: &Path) -> Result<Self> {
+        let file: KeyringFile = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| KeyError::Provider(format!("{}: {}", path.display(), e)))?;
+        let keyring = Self::new();
+        for (id, secret) in &file.keys {
+            keyring.add(Key::from_hex(id, secret)?);
+        }
+        if let Some(current) = &file.current {
+            keyring.set_current(current)?;
+        }
+        Ok(keyring)
+    }
+
+    /// Write the keyring to `path`, readable only by its owner on Unix,
+    /// replacing any file there only once the new one is complete.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = {
+            let state = self.state.read().unwrap();
+            KeyringFile {
+                current: state.0.clone(),
+                keys: state.1.iter().map(|(id, key)| (id.clone(), key.secret_hex())).collect(),
+            }
+        };
+        let data = serde_json::to_vec_pretty(&file).expect("keyring serialization");
+        let tmp = path.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut out = options.open(&tmp)?;
+        out.write_all(&data)?;
+        out.sync_all()?;
+        craftsql_core::replace_file(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Add `key`, replacing any key with its ID. Doesn't make it current.
+    pub fn add(&self, key: Key) {
+        self.state.write().unwrap().1.insert(key.id().to_string(), key);
+    }
+
+    pub fn set_current(&self, id: &str) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if !state.1.contains_key(id) {
+            return Err(KeyError::UnknownKey(id.to_string()));
+        }
+        state.0 = Some(id.to_string());
+        Ok(())
+    }
+
+    /// Generate a key named `id` and make it current.
+    pub fn rotate(&self, id: &str) -> Result<Key> {
+        if self.state.read().unwrap().1.contains_key(id) {
+            return Err(KeyError::InvalidId(format!("{} (already in the keyring)", id)));
+        }
+        let key = Key::generate(id)?;
+        self.add(key.clone());
+        self.set_current(id)?;
+        Ok(key)
+    }
+
+    /// Remove a key no page needs any more. The current key can't be removed.
+    pub fn remove(&self, id: &str) -> Result<bool> {
+        let mut state = self.state.write().unwrap();
+        if state.0.as_deref() == Some(id) {
+            return Err(KeyError::InUse(id.to_string()));
+        }
+        Ok(state.1.remove(id).is_some())
+    }
+
+    /// IDs of all keys, sorted.
+    pub fn ids(&self) -> Vec<String> {
+        self.state.read().unwrap().1.keys().cloned().collect()
+    }
+}
+
+impl KeyProvider for Keyring {
+    fn current(&self) -> Result<Key> {
+        let state = self.state.read().unwrap();
+        let id = state.0.as_ref().ok_or(KeyError::NoCurrentKey)?;
+        Ok(state.1[id].clone())
+    }
+
+    fn get(&self, id: &str) -> Result<Key> {
+        self.state.read().unwrap().1.get(id).cloned().ok_or_else(|| KeyError::UnknownKey(id.to_string()))
+    }
+}
+
+/// Keys fetched by ID through a callback, such as a lookup in the OS
+/// keychain, and cached after the first fetch.
+pub struct CallbackKeys<F> {
+    current: RwLock<String>,
+    fetch: F,
+    cache: Mutex<HashMap<String, Key>>,
+}
+
+impl<F> CallbackKeys<F>
+where
+    F: Fn(&str) -> Result<[u8; 32]> + Send + Sync,
+{
+    /// Keys from `fetch`, with `current` the one to encrypt under.
+    pub fn new(current: &str, fetch: F) -> Result<Self> {
+        validate_id(current)?;
+        Ok(Self { current: RwLock::new(current.to_string()), fetch, cache: Mutex::new(HashMap::new()) })
+    }
+
+    /// Encrypt under `id` from now on.
+    pub fn set_current(&self, id: &str) -> Result<()> {
+        validate_id(id)?;
+        *self.current.write().unwrap() = id.to_string();
+        Ok(())
+    }
+}
+
+impl<F> KeyProvider for CallbackKeys<F>
+where
+    F: Fn(&str) -> Result<[u8; 32]> + Send + Sync,
+{
+    fn current(&self) -> Result<Key> {
+        let id = self.current.read().unwrap().clone();
+        self.get(&id)
+    }
+
+    fn get(&self, id: &str) -> Result<Key> {
+        if let Some(key) = self.cache.lock().unwrap().get(id) {
+            return Ok(key.clone());
+        }
+        let key = Key::new(id, (self.fetch)(id)?)?;
+        self.cache.lock().unwrap().insert(id.to_string(), key.clone());
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const A: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+    const B: &str = "0202020202020202020202020202020202020202020202020202020202020202";
+
+    #[test]
+    fn test_parse_keyring() {
+        let keyring = Keyring::parse(&format!(" new={} ; old={};", B, A)).unwrap();
+        assert_eq!(keyring.current().unwrap().id(), "new");
+        assert_eq!(keyring.get("old").unwrap().secret(), &[1; 32]);
+        assert_eq!(keyring.ids(), vec!["new", "old"]);
+        assert!(matches!(keyring.get("gone"), Err(KeyError::UnknownKey(_))));
+
+        assert!(matches!(Keyring::parse("").unwrap().current(), Err(KeyError::NoCurrentKey)));
+        assert!(matches!(Keyring::parse("k=abc"), Err(KeyError::InvalidSecret(_))));
+        assert!(matches!(Keyring::parse(&format!("a/b={}", A)), Err(KeyError::InvalidId(_))));
+    }
+
+    #[test]
+    fn test_rotate_save_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys.json");
+        let keyring = Keyring::parse(&format!("k1={}", A)).unwrap();
+        let k2 = keyring.rotate("k2").unwrap();
+        assert!(keyring.rotate("k2").is_err());
+        assert!(matches!(keyring.remove("k2"), Err(KeyError::InUse(_))));
+        keyring.save(&path).unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let loaded = Keyring::load(&path).unwrap();
+        assert_eq!(loaded.current().unwrap(), k2);
+        assert!(loaded.remove("k1").unwrap());
+        assert_eq!(loaded.ids(), vec!["k2"]);
+    }
+
+    #[test]
+    fn test_callback_keys_are_cached() {
+        let fetches = AtomicUsize::new(0);
+        let keys = CallbackKeys::new("k1", |id| {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            match id {
+                "k1" => Ok([1; 32]),
+                other => Err(KeyError::UnknownKey(other.to_string())),
+            }
+        })
+        .unwrap();
+        assert_eq!(keys.current().unwrap().secret(), &[1; 32]);
+        assert_eq!(keys.get("k1").unwrap().id(), "k1");
+        assert!(keys.get("k2").is_err());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+}
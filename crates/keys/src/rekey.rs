@@ -0,0 +1,209 @@
+//! Re-encrypting a store's reachable pages under the current key.
+
+use std::collections::HashMap;
+
+use craftsql_core::{Cid, Commit, Page, PageStore, PageStoreError, PageTable, Result};
+
+use crate::{EncryptedPageStore, KeyProvider};
+
+/// What [`rekey`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RekeyStats {
+    /// Pages re-encrypted under the current key, page tables and commits
+    /// included.
+    pub pages_rewritten: u64,
+    /// Pages already under the current key.
+    pub pages_kept: u64,
+    /// Root pointers moved to re-encrypted roots.
+    pub roots_moved: usize,
+}
+
+struct Rekey<'a, S, P> {
+    store: &'a EncryptedPageStore<S, P>,
+    current: String,
+    /// Old CID to new, for everything visited.
+    rewritten: HashMap<Cid, Cid>,
+    stats: RekeyStats,
+}
+
+impl<S: PageStore, P: KeyProvider> Rekey<'_, S, P> {
+    /// Store `data` as the new version of `cid`, unless `cid` is already
+    /// `data` under the current key.
+    fn replace(&mut self, cid: Cid, key_id: &str, unchanged: bool, data: Vec<u8>) -> Result<Cid> {
+        let new = if unchanged && key_id == self.current {
+            self.stats.pages_kept += 1;
+            cid
+        } else {
+            self.stats.pages_rewritten += 1;
+            self.store.put(&Page { data })?
+        };
+        self.rewritten.insert(cid, new);
+        Ok(new)
+    }
+
+    fn page(&mut self, cid: Cid) -> Result<Cid> {
+        if let Some(&new) = self.rewritten.get(&cid) {
+            return Ok(new);
+        }
+        let (key_id, data) = self.store.open(&cid)?;
+        self.replace(cid, &key_id, true, data)
+    }
+
+    fn page_table(&mut self, cid: Cid) -> Result<Cid> {
+        if let Some(&new) = self.rewritten.get(&cid) {
+            return Ok(new);
+        }
+        let (key_id, data) = self.store.open(&cid)?;
+        let mut table = PageTable::from_bytes(&data)
+            .map_err(|e| PageStoreError::Storage(format!("root {} is not a page table or commit: {}", cid, e)))?;
+        let mut unchanged = true;
+        for entry in table.entries.iter_mut().flatten() {
+            let new = self.page(*entry)?;
+            unchanged &= new == *entry;
+            *entry = new;
+        }
+        self.replace(cid, &key_id, unchanged, table.to_bytes())
+    }
+
+    /// Rewrite a root: a commit, with its page table and every ancestor, or
+    /// a page table. Ancestors go first, without recursing down long histories.
+    fn root(&mut self, root: Cid) -> Result<Cid> {
+        let mut stack = vec![(root, false)];
+        while let Some((cid, parents_done)) = stack.pop() {
+            if self.rewritten.contains_key(&cid) {
+                continue;
+            }
+            let (key_id, data) = self.store.open(&cid)?;
+            let Some(commit) = Commit::from_bytes(&data) else {
+                self.page_table(cid)?;
+                continue;
+            };
+            if !parents_done {
+                stack.push((cid, true));
+                stack.extend(commit.parents.iter().map(|&parent| (parent, false)));
+                continue;
+            }
+            let new = Commit {
+                root: self.page_table(commit.root)?,
+                parents: commit.parents.iter().map(|parent| self.rewritten[parent]).collect(),
+                ..commit.clone()
+            };
+            self.replace(cid, &key_id, new == commit, new.to_bytes())?;
+        }
+        Ok(self.rewritten[&root])
+    }
+}
+
+/// Re-encrypt every page reachable from `store`'s roots under the
+/// provider's current key, then move the roots to the re-encrypted copies.
+///
+/// The current root and every named root are followed, through commits'
+/// page tables and parents. Names starting with `.` (bookkeeping such as
+/// the checked-out commit) are moved only if they point at something
+/// rewritten; anything else they lead to stays under its old key.
+///
+/// Pages already under the current key are kept, so running it again after
+/// an interruption only redoes what's left. Nothing may write to the store
+/// meanwhile. The old ciphertext is left in the inner store.
+pub fn rekey<S: PageStore, P: KeyProvider>(store: &EncryptedPageStore<S, P>) -> Result<RekeyStats> {
+    let current = store.keys().current()?.id().to_string();
+    let mut rekey = Rekey { store, current, rewritten: HashMap::new(), stats: RekeyStats::default() };
+
+    let root = store.current_root()?;
+    let named = store.list_named_roots()?;
+    let new_root = root.map(|root| rekey.root(root)).transpose()?;
+    let mut moves = Vec::new();
+    for (name, cid) in &named {
+        if !name.starts_with('.') {
+            moves.push((name, *cid, rekey.root(*cid)?));
+        }
+    }
+    for (name, cid) in &named {
+        if let (true, Some(&new)) = (name.starts_with('.'), rekey.rewritten.get(cid)) {
+            moves.push((name, *cid, new));
+        }
+    }
+
+    for (name, old, new) in moves {
+        if new != old {
+            store.set_named_root(name, new)?;
+            rekey.stats.roots_moved += 1;
+        }
+    }
+    if let (Some(old), Some(new)) = (root, new_root) {
+        if new != old {
+            store.update_root(new)?;
+            rekey.stats.roots_moved += 1;
+        }
+    }
+    Ok(rekey.stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_id, KeyProvider, Keyring};
+    use craftsql_store_mem::MemPageStore;
+    use craftsql_testing::put_table;
+    use std::sync::Arc;
+
+    /// Key IDs of every page reachable from `root` in the inner store.
+    fn reachable_key_ids(store: &EncryptedPageStore<MemPageStore, Arc<Keyring>>, root: Cid) -> Vec<String> {
+        let mut ids = Vec::new();
+        let mut queue = vec![root];
+        while let Some(cid) = queue.pop() {
+            ids.push(key_id(&store.inner().get(&cid).unwrap().data).unwrap().to_string());
+            let data = store.get(&cid).unwrap().data;
+            match Commit::from_bytes(&data) {
+                Some(commit) => queue.extend(std::iter::once(commit.root).chain(commit.parents)),
+                None => {
+                    let table = PageTable::from_bytes(&data).unwrap();
+                    for cid in table.entries.iter().flatten() {
+                        ids.push(key_id(&store.inner().get(cid).unwrap().data).unwrap().to_string());
+                    }
+                }
+            }
+        }
+        ids
+    }
+
+    #[test]
+    fn test_rekey() {
+        let keyring = Arc::new(Keyring::new());
+        keyring.rotate("k1").unwrap();
+        let store = EncryptedPageStore::new(MemPageStore::new(), Arc::clone(&keyring));
+
+        let v1 = put_table(&store, &[b"page 0", b"page 1"]);
+        let first = Commit::new(v1, vec![], "first").put(&store).unwrap();
+        let v2 = put_table(&store, &[b"page 0", b"page 1 changed"]);
+        let second = Commit::new(v2, vec![first], "second").put(&store).unwrap();
+        store.set_named_root("main", second).unwrap();
+        store.set_named_root(".head", second).unwrap();
+        store.set_named_root("v1", v1).unwrap();
+        store.update_root(v2).unwrap();
+
+        keyring.rotate("k2").unwrap();
+        let stats = rekey(&store).unwrap();
+        assert_eq!(stats.pages_kept, 0);
+        assert_eq!(stats.roots_moved, 4);
+
+        // Everything reachable is under k2 and reads back the same
+        keyring.remove("k1").unwrap();
+        let main = store.get_named_root("main").unwrap().unwrap();
+        assert_eq!(store.get_named_root(".head").unwrap(), Some(main));
+        assert!(reachable_key_ids(&store, main).iter().all(|id| id == "k2"));
+        let commit = Commit::load(&store, &main).unwrap().unwrap();
+        assert_eq!((commit.message.as_str(), commit.parents.len()), ("second", 1));
+        assert_eq!(store.current_root().unwrap(), Some(commit.root));
+        let table = PageTable::from_bytes(&store.get(&commit.root).unwrap().data).unwrap();
+        assert_eq!(store.get(table.get(1).unwrap()).unwrap().data, b"page 1 changed");
+        let parent = Commit::load(&store, &commit.parents[0]).unwrap().unwrap();
+        assert_eq!(store.get_named_root("v1").unwrap(), Some(parent.root));
+
+        // Nothing left to do
+        let again = rekey(&store).unwrap();
+        assert_eq!((again.pages_rewritten, again.roots_moved), (0, 0));
+        assert_eq!(again.pages_kept, stats.pages_rewritten);
+        assert_eq!(keyring.current().unwrap().id(), "k2");
+    }
+}
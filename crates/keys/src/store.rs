@@ -0,0 +1,213 @@
+//! A PageStore that encrypts pages on their way into another.
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use craftsql_core::{Cid, CommitMeta, Page, PageStore, PageStoreError, Result, RootSignal};
+
+use crate::{Key, KeyProvider};
+
+/// Leading bytes of an encrypted page.
+const MAGIC: &[u8; 8] = b"csqlenc1";
+
+const NONCE_LEN: usize = 24;
+
+/// The ID of the key an encrypted page was encrypted under, or `None` if
+/// `data` isn't an encrypted page.
+///
+/// An encrypted page is `MAGIC`, the key ID's length (one byte) and bytes,
+/// a random 24-byte nonce, and the ciphertext with its tag. Everything
+/// before the nonce is authenticated along with the ciphertext.
+pub fn key_id(data: &[u8]) -> Option<&str> {
+    let rest = data.strip_prefix(MAGIC)?;
+    let (&len, rest) = rest.split_first()?;
+    std::str::from_utf8(rest.get(..len as usize)?).ok()
+}
+
+/// Pages encrypted under keys from `P`, stored in `S`.
+///
+/// Root pointers pass through unchanged: they name encrypted pages.
+pub struct EncryptedPageStore<S, P> {
+    inner: S,
+    keys: P,
+}
+
+impl<S: PageStore, P: KeyProvider> EncryptedPageStore<S, P> {
+    pub fn new(inner: S, keys: P) -> Self {
+        Self { inner, keys }
+    }
+
+    /// The store holding the encrypted pages.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn keys(&self) -> &P {
+        &self.keys
+    }
+
+    /// Encrypt `data` under `key`.
+    pub(crate) fn seal(key: &Key, data: &[u8]) -> Result<Vec<u8>> {
+        let mut sealed = MAGIC.to_vec();
+        sealed.push(key.id().len() as u8);
+        sealed.extend_from_slice(key.id().as_bytes());
+        let header_len = sealed.len();
+
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::fill(&mut nonce).map_err(|e| PageStoreError::Storage(format!("random nonce: {}", e)))?;
+        let cipher = XChaCha20Poly1305::new(key.secret().into());
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: data, aad: &sealed[..header_len] })
+            .map_err(|_| PageStoreError::Storage("page encryption failed".into()))?;
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt the stored page `cid`, returning the ID of its key too.
+    pub(crate) fn open(&self, cid: &Cid) -> Result<(String, Vec<u8>)> {
+        let sealed = self.inner.get(cid)?.data;
+        let id = key_id(&sealed)
+            .ok_or_else(|| PageStoreError::Storage(format!("page {} is not encrypted", cid)))?
+            .to_string();
+        let header_len = MAGIC.len() + 1 + id.len();
+        let (header, rest) = sealed.split_at(header_len);
+        if rest.len() < NONCE_LEN {
+            return Err(PageStoreError::Storage(format!("encrypted page {} is truncated", cid)));
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let key = self.keys.get(&id)?;
+        let cipher = XChaCha20Poly1305::new(key.secret().into());
+        let data = cipher
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| PageStoreError::Storage(format!("page {} failed to decrypt under key {}", cid, id)))?;
+        Ok((id, data))
+    }
+}
+
+impl<S: PageStore, P: KeyProvider> PageStore for EncryptedPageStore<S, P> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        Ok(Page { data: self.open(cid)?.1 })
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        let key = self.keys.current()?;
+        self.inner.put(&Page { data: Self::seal(&key, &page.data)? })
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        let key = self.keys.current()?;
+        let sealed = pages.iter()
+            .map(|page| Ok(Page { data: Self::seal(&key, &page.data)? }))
+            .collect::<Result<Vec<_>>>()?;
+        self.inner.put_many(&sealed)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        self.inner.has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        self.inner.update_root(new_root)
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        self.inner.update_root_with_meta(new_root, meta)
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        self.inner.current_root()
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.inner.set_named_root(name, cid)
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        self.inner.set_named_root_with_meta(name, cid, meta)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.inner.get_named_root(name)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.inner.remove_named_root(name)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        self.inner.list_named_roots()
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        self.inner.list_pages()
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.inner.delete_page(cid)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        self.inner.delete_many(cids)
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        self.inner.root_signal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Keyring;
+    use craftsql_store_mem::MemPageStore;
+
+    fn keyring() -> Keyring {
+        let keyring = Keyring::new();
+        keyring.rotate("k1").unwrap();
+        keyring
+    }
+
+    #[test]
+    fn test_pages_are_encrypted() {
+        let store = EncryptedPageStore::new(MemPageStore::new(), keyring());
+        let page = Page { data: b"secret row data".to_vec() };
+        let cid = store.put(&page).unwrap();
+        assert_ne!(cid, Cid::from_bytes(&page.data));
+        assert_eq!(store.get(&cid).unwrap().data, page.data);
+
+        let sealed = store.inner().get(&cid).unwrap().data;
+        assert_eq!(key_id(&sealed), Some("k1"));
+        assert!(!sealed.windows(page.data.len()).any(|window| window == page.data));
+        // Random nonces: the same page encrypts differently each time
+        assert_ne!(store.put(&page).unwrap(), cid);
+
+        let pages = [Page { data: b"one".to_vec() }, Page { data: b"two".to_vec() }];
+        let cids = store.put_many(&pages).unwrap();
+        assert_eq!(store.get(&cids[1]).unwrap().data, b"two");
+        assert_eq!(key_id(&store.inner().get(&cids[0]).unwrap().data), Some("k1"));
+    }
+
+    #[test]
+    fn test_unreadable_pages() {
+        let store = EncryptedPageStore::new(MemPageStore::new(), keyring());
+        let plain = store.inner().put(&Page { data: b"plain".to_vec() }).unwrap();
+        assert!(store.get(&plain).is_err());
+
+        // Tampering with the ciphertext or the key ID is detected
+        let cid = store.put(&Page { data: vec![7; 100] }).unwrap();
+        let sealed = store.inner().get(&cid).unwrap().data;
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let tampered = store.inner().put(&Page { data: tampered }).unwrap();
+        assert!(store.get(&tampered).is_err());
+        store.keys().add(Key::new("k2", *store.keys().get("k1").unwrap().secret()).unwrap());
+        let mut renamed = sealed;
+        renamed[MAGIC.len() + 2] = b'2';
+        let renamed = store.inner().put(&Page { data: renamed }).unwrap();
+        assert!(store.get(&renamed).is_err());
+
+        // A provider whose k1 is a different key
+        let other = EncryptedPageStore::new(store.inner().clone(), keyring());
+        assert!(other.get(&cid).is_err());
+    }
+}
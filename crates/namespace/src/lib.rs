@@ -0,0 +1,339 @@
+//! CraftSQL Namespace — many databases in one store.
+//!
+//! A [`NamespacedPageStore`] is one database of a shared store: its root
+//! and named roots live under names prefixed with the database's name,
+//! while pages are shared, so databases with common content store it once.
+//! Database `app` keeps its root in `db.app.root` and its named root `main`
+//! in `db.app.refs.main`. The separator is a dot because some stores (the
+//! local one, for instance) can't keep `/` in a name.
+//!
+//! Pages are shared, so a namespaced store lists and deletes only those no
+//! other database, nor the shared store's own roots, reach: collecting one
+//! database's garbage leaves the others' pages be.
+
+use std::collections::{BTreeMap, HashSet};
+
+use craftsql_core::{reachable, usage, Cid, CommitMeta, Page, PageStore, PageStoreError, PageTable, RootSignal, Usage};
+
+/// Prefix of every name a database keeps in the shared store.
+pub const PREFIX: &str = "db.";
+
+/// Namespace errors.
+#[derive(Debug, thiserror::Error)]
+pub enum NamespaceError {
+    #[error(transparent)]
+    Store(#[from] PageStoreError),
+    #[error("invalid database name {0:?}: use letters, digits, '-' and '_'")]
+    InvalidName(String),
+    #[error("database {0} already exists")]
+    Exists(String),
+    #[error("no database named {0}")]
+    NotFound(String),
+}
+
+pub type Result<T> = std::result::Result<T, NamespaceError>;
+
+/// Check that `name` can name a database.
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(NamespaceError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+fn root_ref(name: &str) -> String {
+    format!("{}{}.root", PREFIX, name)
+}
+
+fn refs_prefix(name: &str) -> String {
+    format!("{}{}.refs.", PREFIX, name)
+}
+
+/// One database of a shared store.
+pub struct NamespacedPageStore<S> {
+    inner: S,
+    name: String,
+    root_ref: String,
+    refs_prefix: String,
+}
+
+impl<S: PageStore> NamespacedPageStore<S> {
+    /// The database `name` in `inner`. It needn't exist yet: setting its
+    /// root creates it.
+    pub fn new(inner: S, name: &str) -> Result<Self> {
+        validate_name(name)?;
+        Ok(Self { inner, name: name.to_string(), root_ref: root_ref(name), refs_prefix: refs_prefix(name) })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Pages the other databases, or the store's own roots, reach: this
+    /// database's garbage collection must leave them be.
+    fn shared_pages(&self) -> craftsql_core::Result<HashSet<Cid>> {
+        let own = format!("{}{}.", PREFIX, self.name);
+        let roots: Vec<Cid> = self.inner.current_root()?
+            .into_iter()
+            .chain(self.inner.list_named_roots()?
+                .into_iter()
+                .filter(|(name, _)| !name.starts_with(&own))
+                .map(|(_, cid)| cid))
+            .collect();
+        reachable(&self.inner, &roots)
+    }
+}
+
+impl<S: PageStore> PageStore for NamespacedPageStore<S> {
+    fn get(&self, cid: &Cid) -> craftsql_core::Result<Page> {
+        self.inner.get(cid)
+    }
+
+    fn put(&self, page: &Page) -> craftsql_core::Result<Cid> {
+        self.inner.put(page)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> craftsql_core::Result<Vec<Cid>> {
+        self.inner.put_many(pages)
+    }
+
+    fn has(&self, cid: &Cid) -> craftsql_core::Result<bool> {
+        self.inner.has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> craftsql_core::Result<()> {
+        self.inner.set_named_root(&self.root_ref, new_root)
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> craftsql_core::Result<()> {
+        self.inner.set_named_root_with_meta(&self.root_ref, new_root, meta)
+    }
+
+    fn current_root(&self) -> craftsql_core::Result<Option<Cid>> {
+        self.inner.get_named_root(&self.root_ref)
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> craftsql_core::Result<()> {
+        self.inner.set_named_root(&format!("{}{}", self.refs_prefix, name), cid)
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> craftsql_core::Result<()> {
+        self.inner.set_named_root_with_meta(&format!("{}{}", self.refs_prefix, name), cid, meta)
+    }
+
+    fn get_named_root(&self, name: &str) -> craftsql_core::Result<Option<Cid>> {
+        self.inner.get_named_root(&format!("{}{}", self.refs_prefix, name))
+    }
+
+    fn remove_named_root(&self, name: &str) -> craftsql_core::Result<bool> {
+        self.inner.remove_named_root(&format!("{}{}", self.refs_prefix, name))
+    }
+
+    fn list_named_roots(&self) -> craftsql_core::Result<Vec<(String, Cid)>> {
+        Ok(self.inner.list_named_roots()?
+            .into_iter()
+            .filter_map(|(name, cid)| Some((name.strip_prefix(&self.refs_prefix)?.to_string(), cid)))
+            .collect())
+    }
+
+    /// The shared store's pages, less those other databases reach.
+    fn list_pages(&self) -> craftsql_core::Result<Vec<(Cid, u64)>> {
+        let shared = self.shared_pages()?;
+        let mut pages = self.inner.list_pages()?;
+        pages.retain(|(cid, _)| !shared.contains(cid));
+        Ok(pages)
+    }
+
+    /// Keeps a page other databases reach, reporting it not deleted.
+    fn delete_page(&self, cid: &Cid) -> craftsql_core::Result<bool> {
+        if self.shared_pages()?.contains(cid) {
+            return Ok(false);
+        }
+        self.inner.delete_page(cid)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> craftsql_core::Result<Vec<bool>> {
+        let shared = self.shared_pages()?;
+        let own: Vec<Cid> = cids.iter().filter(|cid| !shared.contains(cid)).copied().collect();
+        let mut deleted = self.inner.delete_many(&own)?.into_iter();
+        Ok(cids.iter().map(|cid| !shared.contains(cid) && deleted.next().unwrap_or(false)).collect())
+    }
+
+    /// Wakes on any database's changes, as well as this one's.
+    fn root_signal(&self) -> craftsql_core::Result<Option<Box<dyn RootSignal>>> {
+        self.inner.root_signal()
+    }
+}
+
+/// Names of the databases in `store`, sorted.
+pub fn list_databases(store: &dyn PageStore) -> Result<Vec<String>> {
+    let mut names: Vec<String> = store.list_named_roots()?
+        .into_iter()
+        .filter_map(|(name, _)| {
+            let rest = name.strip_prefix(PREFIX)?;
+            let (db, _) = rest.split_once('.')?;
+            validate_name(db).is_ok().then(|| db.to_string())
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Create the empty database `name`, whose root is an empty page table
+/// (which SQLite opens as a new database).
+pub fn create_database(store: &dyn PageStore, name: &str) -> Result<()> {
+    let db = NamespacedPageStore::new(store, name)?;
+    if list_databases(store)?.iter().any(|existing| existing == name) {
+        return Err(NamespaceError::Exists(name.to_string()));
+    }
+    let empty = db.put(&Page { data: PageTable::new().to_bytes() })?;
+    db.update_root(empty)?;
+    Ok(())
+}
+
+/// Delete the database `name`: its root and every named root. Its pages
+/// stay until garbage collection finds nothing else uses them.
+pub fn delete_database(store: &dyn PageStore, name: &str) -> Result<()> {
+    validate_name(name)?;
+    let prefix = format!("{}{}.", PREFIX, name);
+    let mut found = false;
+    for (full, _) in store.list_named_roots()? {
+        if full.starts_with(&prefix) {
+            store.remove_named_root(&full)?;
+            found = true;
+        }
+    }
+    if !found {
+        return Err(NamespaceError::NotFound(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Storage used by each database in `store` (see [`usage`]): everything
+/// its roots reach, and how much no other database shares. The store's own
+/// database, if it has any roots, comes first, named `""`.
+pub fn database_usage(store: &dyn PageStore) -> Result<Vec<Usage>> {
+    let mut own: Vec<Cid> = store.current_root()?.into_iter().collect();
+    let mut databases: BTreeMap<String, Vec<Cid>> = BTreeMap::new();
+    for (name, cid) in store.list_named_roots()? {
+        let database = name.strip_prefix(PREFIX).and_then(|rest| rest.split_once('.')).map(|(db, _)| db);
+        match database.filter(|db| validate_name(db).is_ok()) {
+            Some(db) => databases.entry(db.to_string()).or_default().push(cid),
+            None => own.push(cid),
+        }
+    }
+    let mut groups: Vec<(String, Vec<Cid>)> = Vec::new();
+    if !own.is_empty() {
+        groups.push((String::new(), own));
+    }
+    groups.extend(databases);
+    Ok(usage(store, &groups)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+
+    #[test]
+    fn test_databases_are_separate() {
+        let store = MemPageStore::new();
+        let app = NamespacedPageStore::new(&store, "app").unwrap();
+        let logs = NamespacedPageStore::new(&store, "logs").unwrap();
+        let shared = app.put(&Page { data: b"shared".to_vec() }).unwrap();
+        assert!(logs.has(&shared).unwrap());
+
+        app.update_root(shared).unwrap();
+        app.set_named_root("main", shared).unwrap();
+        assert_eq!(logs.current_root().unwrap(), None);
+        assert_eq!(logs.list_named_roots().unwrap(), vec![]);
+        assert_eq!(app.list_named_roots().unwrap(), vec![("main".to_string(), shared)]);
+        // The shared store's own root is a database of its own
+        assert_eq!(store.current_root().unwrap(), None);
+        assert_eq!(store.get_named_root("db.app.refs.main").unwrap(), Some(shared));
+    }
+
+    #[test]
+    fn test_pages_scoped_to_database() {
+        let store = MemPageStore::new();
+        let app = NamespacedPageStore::new(&store, "app").unwrap();
+        let logs = NamespacedPageStore::new(&store, "logs").unwrap();
+        let table = |pages: &[Cid]| {
+            let mut pt = PageTable::new();
+            for (i, cid) in pages.iter().enumerate() {
+                pt.set(i, *cid);
+            }
+            store.put(&Page { data: pt.to_bytes() }).unwrap()
+        };
+        let shared = store.put(&Page { data: b"shared".to_vec() }).unwrap();
+        let mine = store.put(&Page { data: b"mine".to_vec() }).unwrap();
+        let orphan = store.put(&Page { data: b"orphan".to_vec() }).unwrap();
+        let app_root = table(&[shared, mine]);
+        let logs_root = table(&[shared]);
+        app.update_root(app_root).unwrap();
+        logs.update_root(logs_root).unwrap();
+
+        // What logs uses is none of app's to list or delete
+        let listed: HashSet<Cid> = app.list_pages().unwrap().into_iter().map(|(cid, _)| cid).collect();
+        assert_eq!(listed, HashSet::from([app_root, mine, orphan]));
+        assert_eq!(app.delete_many(&[shared, orphan]).unwrap(), vec![false, true]);
+        assert!(!app.delete_page(&logs_root).unwrap());
+        assert!(store.has(&shared).unwrap());
+        assert!(!store.has(&orphan).unwrap());
+    }
+
+    #[test]
+    fn test_commit_meta() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = craftsql_store_local::LocalPageStore::new(tmp.path()).unwrap();
+        let app = NamespacedPageStore::new(&store, "app").unwrap();
+        let root = app.put(&Page { data: b"root".to_vec() }).unwrap();
+        app.update_root_with_meta(root, &CommitMeta::new("alice", "seed")).unwrap();
+        assert_eq!(app.current_root().unwrap(), Some(root));
+        let commit = store.root_commit(&root).unwrap().unwrap();
+        assert_eq!((commit.author.as_str(), commit.message.as_str()), ("alice", "seed"));
+    }
+
+    #[test]
+    fn test_create_list_delete() {
+        let store = MemPageStore::new();
+        create_database(&store, "b").unwrap();
+        create_database(&store, "a").unwrap();
+        assert!(matches!(create_database(&store, "a"), Err(NamespaceError::Exists(_))));
+        assert!(matches!(create_database(&store, "a.b"), Err(NamespaceError::InvalidName(_))));
+        assert_eq!(list_databases(&store).unwrap(), ["a", "b"]);
+        let a = NamespacedPageStore::new(&store, "a").unwrap();
+        let root = a.current_root().unwrap().unwrap();
+        assert!(PageTable::from_bytes(&a.get(&root).unwrap().data).unwrap().is_empty());
+
+        a.set_named_root("main", root).unwrap();
+        delete_database(&store, "a").unwrap();
+        assert_eq!(list_databases(&store).unwrap(), ["b"]);
+        assert_eq!(a.get_named_root("main").unwrap(), None);
+        assert!(matches!(delete_database(&store, "a"), Err(NamespaceError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_database_usage() {
+        let store = MemPageStore::new();
+        let app = NamespacedPageStore::new(&store, "app").unwrap();
+        let logs = NamespacedPageStore::new(&store, "logs").unwrap();
+        let shared = store.put(&Page { data: vec![0; 100] }).unwrap();
+        app.update_root(shared).unwrap();
+        app.set_named_root("main", app.put(&Page { data: vec![1; 10] }).unwrap()).unwrap();
+        logs.update_root(shared).unwrap();
+
+        let usage = database_usage(&store).unwrap();
+        let summary: Vec<_> = usage.iter().map(|u| (u.name.as_str(), u.bytes, u.unique_bytes)).collect();
+        assert_eq!(summary, [("app", 110, 10), ("logs", 100, 0)]);
+
+        store.update_root(shared).unwrap();
+        assert_eq!(database_usage(&store).unwrap()[0].name, "");
+    }
+}
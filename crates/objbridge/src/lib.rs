@@ -1,25 +1,47 @@
 //! CraftOBJ Network Bridge — connects CraftSQL to the CraftOBJ daemon via JSON-RPC IPC.
 //!
 //! Implements [`NetworkBackend`] for `CraftObjPageStore` by communicating with
-//! the CraftOBJ daemon over a Unix socket using JSON-RPC 2.0.
+//! the CraftOBJ daemon using JSON-RPC 2.0, over a Unix socket or, for a daemon
+//! on another host, over TCP or HTTP (see [`Transport`]).
 //!
 //! # Architecture
 //!
 //! ```text
 //! SQLite ←→ CraftVFS ←→ CraftObjPageStore<DaemonBackend> ←→ craftobj daemon
-//!                                                              (Unix socket)
+//!                                                    (Unix socket / TCP / HTTP)
 //! ```
 //!
-//! Pages are published as raw content via the daemon's `publish` RPC.
-//! Root pointers are managed locally (craftsql-specific, not stored in CraftOBJ DHT).
+//! Pages are published as raw content via the daemon's `publish` RPC, with
+//! any [`PublishOptions`] passed along as `replication` and `segment_size`
+//! parameters; the `placement` RPC reports where content is stored, and
+//! `pin` / `unpin` keep it from the network's garbage collection or release
+//! it.
+//! Root pointers live in the daemon's key-value store (`kv.*` RPCs) under
+//! `craftsql:root:<name>`, so every client of the network sees the latest root.
+//!
+//! Payloads normally travel through temp files whose paths are passed in the
+//! RPC, which requires the daemon to share the client's filesystem. When it
+//! doesn't (containers, separate mount namespaces), use [`Transfer::InBand`]
+//! to send the bytes base64-encoded over the socket instead.
+
+mod metrics;
+
+pub use metrics::{MethodStats, RpcStatsSnapshot, LATENCY_BUCKETS};
+
+use metrics::RpcMetrics;
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use craftsql_core::{Cid, PageStoreError, Result};
-use craftsql_objstore::NetworkBackend;
+use craftsql_objstore::{NetworkBackend, Placement, PublishOptions, RateLimit, SegmentHealth};
 use serde::{Deserialize, Serialize};
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
 use std::os::unix::net::UnixStream;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// JSON-RPC 2.0 request.
 #[derive(Serialize)]
@@ -28,6 +50,9 @@ struct RpcRequest<'a> {
     method: &'a str,
     #[serde(skip_serializing_if = "Option::is_none")]
     params: Option<serde_json::Value>,
+    /// Client auth token, for daemons that require one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<&'a str>,
     id: u64,
 }
 
@@ -36,6 +61,22 @@ struct RpcRequest<'a> {
 struct RpcResponse {
     result: Option<serde_json::Value>,
     error: Option<RpcError>,
+    #[serde(default)]
+    id: Option<u64>,
+}
+
+impl RpcResponse {
+    fn into_result(self) -> Result<serde_json::Value> {
+        if let Some(err) = self.error {
+            if err.code == UNAUTHORIZED_CODE {
+                return Err(PageStoreError::Unauthorized(err.message));
+            }
+            return Err(PageStoreError::Storage(format!(
+                "daemon error {}: {}", err.code, err.message
+            )));
+        }
+        self.result.ok_or_else(|| PageStoreError::Storage("empty daemon response".into()))
+    }
 }
 
 #[derive(Deserialize)]
@@ -44,23 +85,226 @@ struct RpcError {
     message: String,
 }
 
-/// NetworkBackend that talks to the CraftOBJ daemon over Unix socket IPC.
+/// JSON-RPC error code the daemon returns for a missing or rejected token.
+pub const UNAUTHORIZED_CODE: i32 = -32001;
+
+/// Environment variable holding the auth token, if none is set explicitly.
+pub const AUTH_TOKEN_ENV: &str = "CRAFTOBJ_AUTH_TOKEN";
+
+/// Key prefix for root pointers in the daemon's key-value store.
+const ROOT_KEY_PREFIX: &str = "craftsql:root:";
+
+/// Name under which the default (unnamed) root is stored.
+const DEFAULT_ROOT_NAME: &str = "__default__";
+
+fn root_key(name: &str) -> String {
+    format!("{}{}", ROOT_KEY_PREFIX, name)
+}
+
+/// `params` plus whichever of `options` are set; the daemon defaults the rest.
+fn with_options(mut params: serde_json::Value, options: &PublishOptions) -> serde_json::Value {
+    if let Some(replication) = options.replication {
+        params["replication"] = replication.into();
+    }
+    if let Some(segment_size) = options.segment_size {
+        params["segment_size"] = segment_size.into();
+    }
+    params
+}
+
+/// Result of the `placement` RPC.
+#[derive(Deserialize)]
+struct PlacementResult {
+    #[serde(default)]
+    providers: Vec<String>,
+    #[serde(default)]
+    segments: Vec<SegmentResult>,
+}
+
+#[derive(Deserialize)]
+struct SegmentResult {
+    pieces: u32,
+    needed: u32,
+}
+
+/// How page and bundle bytes travel between client and daemon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transfer {
+    /// Via temp files named in the `publish` / `fetch` RPCs.
+    /// The daemon must be able to open the client's paths.
+    #[default]
+    TempFile,
+    /// Base64-encoded inside the `publish_data` / `fetch_data` RPCs.
+    /// Each payload is held in memory while it is transferred.
+    InBand,
+}
+
+/// How often and how patiently failed RPCs are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total attempts per call, including the first (1 = never retry).
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled after each further failure.
+    pub initial_backoff: Duration,
+    /// Upper bound on the delay between attempts.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Fail on the first error.
+    pub fn none() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// How far a failed RPC attempt got, which decides whether it may be retried.
+enum Stage {
+    /// Never reached the daemon (refused, socket missing, ...).
+    Connect,
+    /// Lost after sending: the daemon may or may not have acted on it.
+    Exchange,
+    /// The reply came back and is not worth retrying.
+    Rejected,
+}
+
+struct Failure {
+    stage: Stage,
+    error: PageStoreError,
+}
+
+impl Failure {
+    fn connect(msg: String) -> Self {
+        Self { stage: Stage::Connect, error: PageStoreError::Network(msg) }
+    }
+
+    fn exchange(msg: String) -> Self {
+        Self { stage: Stage::Exchange, error: PageStoreError::Network(msg) }
+    }
+
+    /// Lost mid-exchange to `error`, which may be a timeout.
+    fn lost(what: &str, error: std::io::Error) -> Self {
+        Self { stage: Stage::Exchange, error: PageStoreError::network(what, error) }
+    }
+
+    fn rejected(msg: String) -> Self {
+        Self { stage: Stage::Rejected, error: PageStoreError::Storage(msg) }
+    }
+
+    fn unauthorized(msg: String) -> Self {
+        Self { stage: Stage::Rejected, error: PageStoreError::Unauthorized(msg) }
+    }
+}
+
+/// Whether replaying `method` after an unknown outcome is harmless.
+///
+/// Publishes are content-addressed, but kv writes move roots: a replayed
+/// put could overwrite a root another writer has moved on since, a
+/// replayed compare-and-swap would report its own swap as a conflict, and
+/// a replayed delete would misreport whether the key existed.
+fn is_idempotent(method: &str) -> bool {
+    !matches!(method, "kv.put" | "kv.cas" | "kv.delete")
+}
+
+/// The root a kv value names, if it holds one.
+fn parse_root(value: Option<&str>) -> Result<Option<Cid>> {
+    let Some(hex_str) = value else {
+        return Ok(None);
+    };
+    let bytes = hex::decode(hex_str)
+        .map_err(|e| PageStoreError::Storage(format!("invalid root hex: {}", e)))?;
+    if bytes.len() != 32 {
+        return Err(PageStoreError::Storage("invalid root CID length".into()));
+    }
+    let mut cid = [0u8; 32];
+    cid.copy_from_slice(&bytes);
+    Ok(Some(Cid(cid)))
+}
+
+/// Where the daemon listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Unix socket path; one newline-terminated JSON-RPC message each way.
+    UnixSocket(String),
+    /// `host:port`; same framing as the Unix socket.
+    Tcp(String),
+    /// `http://host[:port][/path]`; each request is a POST of the JSON-RPC body.
+    Http(String),
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::UnixSocket(path) => write!(f, "{}", path),
+            Transport::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Transport::Http(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// NetworkBackend that talks to the CraftOBJ daemon over JSON-RPC.
 ///
-/// Each RPC call opens a fresh connection (the daemon uses one-shot connections).
-/// Page data is transferred via temp files (daemon's publish/fetch API is file-based).
+/// Each RPC call opens a fresh connection (the daemon uses one-shot connections),
+/// so a restarted daemon is picked up by the next attempt.
+/// Page data is transferred via temp files unless [`Transfer::InBand`] is selected.
 pub struct DaemonBackend {
-    socket_path: String,
+    transport: Transport,
     next_id: AtomicU64,
     timeout: Duration,
+    transfer: Transfer,
+    retry: RetryPolicy,
+    auth_token: Option<String>,
+    /// Where temp-file transfers are staged; the daemon must be able to read it.
+    temp_dir: PathBuf,
+    metrics: RpcMetrics,
+    upload: RateLimit,
+    download: RateLimit,
 }
 
 impl DaemonBackend {
     /// Create a new backend connecting to the given Unix socket path.
     pub fn new(socket_path: &str) -> Self {
+        Self::with_transport(Transport::UnixSocket(socket_path.to_string()))
+    }
+
+    /// Connect to a daemon listening on TCP at `addr` (`host:port`).
+    ///
+    /// Payloads go in-band, since a remote daemon can't open local temp files.
+    pub fn tcp(addr: &str) -> Self {
+        Self::with_transport(Transport::Tcp(addr.to_string())).with_transfer(Transfer::InBand)
+    }
+
+    /// Connect to a daemon serving JSON-RPC over HTTP at `url`.
+    ///
+    /// Payloads go in-band, since a remote daemon can't open local temp files.
+    pub fn http(url: &str) -> Self {
+        Self::with_transport(Transport::Http(url.to_string())).with_transfer(Transfer::InBand)
+    }
+
+    /// Create a backend for an explicit transport, with temp-file transfer.
+    ///
+    /// Picks up an auth token from [`AUTH_TOKEN_ENV`] if it is set.
+    pub fn with_transport(transport: Transport) -> Self {
         Self {
-            socket_path: socket_path.to_string(),
+            transport,
             next_id: AtomicU64::new(1),
             timeout: Duration::from_secs(30),
+            transfer: Transfer::default(),
+            retry: RetryPolicy::default(),
+            auth_token: std::env::var(AUTH_TOKEN_ENV).ok().filter(|t| !t.is_empty()),
+            temp_dir: std::env::temp_dir(),
+            metrics: RpcMetrics::default(),
+            upload: RateLimit::unlimited(),
+            download: RateLimit::unlimited(),
         }
     }
 
@@ -75,54 +319,351 @@ impl DaemonBackend {
         self
     }
 
+    /// Set how payloads are transferred to and from the daemon.
+    pub fn with_transfer(mut self, transfer: Transfer) -> Self {
+        self.transfer = transfer;
+        self
+    }
+
+    /// Attach `token` to every request, overriding [`AUTH_TOKEN_ENV`].
+    ///
+    /// A daemon that rejects it fails calls with [`PageStoreError::Unauthorized`].
+    pub fn with_auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// Stage temp-file transfers in `dir` instead of the system temp dir.
+    ///
+    /// Files are uniquely named and removed as soon as each call finishes.
+    pub fn with_temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    /// Set how failed RPCs are retried.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Cap the payload bytes handed to the daemon for publishing.
+    ///
+    /// The daemon uploads on demand, so this paces its use of the link too.
+    /// Keep a clone of `limit` to change the rate later.
+    pub fn with_upload_limit(mut self, limit: RateLimit) -> Self {
+        self.upload = limit;
+        self
+    }
+
+    /// Cap the payload bytes fetched through the daemon.
+    pub fn with_download_limit(mut self, limit: RateLimit) -> Self {
+        self.download = limit;
+        self
+    }
+
+    /// Per-method call counts, errors, retries, and latency so far.
+    ///
+    /// Latency is measured around each whole call, so comparing it with the
+    /// store's own timings shows whether time goes to the daemon.
+    pub fn rpc_stats(&self) -> RpcStatsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// The daemon endpoint.
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
     /// Send a JSON-RPC request and return the result.
+    ///
+    /// Connection failures are retried per the [`RetryPolicy`], so a daemon
+    /// restart is ridden out. A call whose connection drops after the request
+    /// was sent is replayed only if the method is idempotent.
     fn rpc_call(&self, method: &str, params: Option<serde_json::Value>) -> Result<serde_json::Value> {
-        let stream = UnixStream::connect(&self.socket_path)
-            .map_err(|e| PageStoreError::Storage(format!("daemon not running at {}: {}", self.socket_path, e)))?;
-        stream.set_read_timeout(Some(self.timeout))
-            .map_err(|e| PageStoreError::Storage(e.to_string()))?;
-        stream.set_write_timeout(Some(self.timeout))
-            .map_err(|e| PageStoreError::Storage(e.to_string()))?;
-
         let id = self.next_id.fetch_add(1, Ordering::Relaxed);
         let request = RpcRequest {
             jsonrpc: "2.0",
             method,
             params,
+            auth: self.auth_token.as_deref(),
             id,
         };
 
         let json = serde_json::to_string(&request)
             .map_err(|e| PageStoreError::Storage(e.to_string()))?;
 
-        let mut writer = stream.try_clone()
+        let started = Instant::now();
+        let mut retries = 0;
+        let result = self.send_with_retry(method, &json, is_idempotent(method), &mut retries)
+            .and_then(|line| {
+                let response: RpcResponse = serde_json::from_str(line.trim())
+                    .map_err(|e| PageStoreError::Storage(format!("parse daemon response: {}", e)))?;
+                response.into_result()
+            });
+        self.metrics.record(method, started.elapsed(), retries, result.is_ok());
+        result
+    }
+
+    /// Send several calls as one JSON-RPC batch, returning results in call order.
+    ///
+    /// Fails if any call fails; the batch is one round trip either way.
+    fn rpc_batch(&self, calls: Vec<(&str, serde_json::Value)>) -> Result<Vec<serde_json::Value>> {
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+        let idempotent = calls.iter().all(|(method, _)| is_idempotent(method));
+        let requests: Vec<RpcRequest> = calls.into_iter()
+            .map(|(method, params)| RpcRequest {
+                jsonrpc: "2.0",
+                method,
+                params: Some(params),
+                auth: self.auth_token.as_deref(),
+                id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            })
+            .collect();
+
+        let json = serde_json::to_string(&requests)
             .map_err(|e| PageStoreError::Storage(e.to_string()))?;
-        writer.write_all(format!("{}\n", json).as_bytes())
-            .map_err(|e| PageStoreError::Storage(format!("write to daemon: {}", e)))?;
+
+        // Every call in the batch is charged the batch's round trip
+        let started = Instant::now();
+        let mut retries = 0;
+        let sent = self.send_with_retry("batch", &json, idempotent, &mut retries)
+            .and_then(|line| {
+                serde_json::from_str::<Vec<RpcResponse>>(line.trim())
+                    .map_err(|e| PageStoreError::Storage(format!("parse daemon response: {}", e)))
+            });
+        let elapsed = started.elapsed();
+        let mut responses = match sent {
+            Ok(responses) => responses,
+            Err(e) => {
+                for request in &requests {
+                    self.metrics.record(request.method, elapsed, retries, false);
+                }
+                return Err(e);
+            }
+        };
+
+        // Batch responses may arrive in any order; match them up by id
+        let results: Vec<Result<serde_json::Value>> = requests.iter()
+            .map(|request| {
+                let result = match responses.iter().position(|r| r.id == Some(request.id)) {
+                    Some(pos) => responses.swap_remove(pos).into_result(),
+                    None => Err(PageStoreError::Storage(format!(
+                        "no response to batched {} call", request.method
+                    ))),
+                };
+                self.metrics.record(request.method, elapsed, retries, result.is_ok());
+                result
+            })
+            .collect();
+        results.into_iter().collect()
+    }
+
+    /// Deliver `json`, retrying per the [`RetryPolicy`], and return the raw reply.
+    ///
+    /// Adds the number of retries made to `retries`.
+    fn send_with_retry(&self, method: &str, json: &str, idempotent: bool, retries: &mut u64) -> Result<String> {
+        let mut attempt = 1;
+        let mut backoff = self.retry.initial_backoff;
+        loop {
+            let failure = match self.send(json) {
+                Ok(line) => return Ok(line),
+                Err(failure) => failure,
+            };
+            let retryable = match failure.stage {
+                Stage::Connect => true,
+                Stage::Exchange => idempotent,
+                Stage::Rejected => false,
+            };
+            if !retryable || attempt >= self.retry.max_attempts {
+                return Err(failure.error);
+            }
+            tracing::warn!(method, attempt, error = %failure.error, "daemon RPC failed, retrying");
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(self.retry.max_backoff);
+            attempt += 1;
+            *retries += 1;
+        }
+    }
+
+    /// One attempt at delivering `json` and reading the raw response.
+    fn send(&self, json: &str) -> std::result::Result<String, Failure> {
+        let not_running = |e: std::io::Error| {
+            Failure::connect(format!("daemon not running at {}: {}", self.transport, e))
+        };
+        match &self.transport {
+            #[cfg(unix)]
+            Transport::UnixSocket(path) => {
+                let stream = UnixStream::connect(path).map_err(not_running)?;
+                stream.set_read_timeout(Some(self.timeout)).map_err(not_running)?;
+                stream.set_write_timeout(Some(self.timeout)).map_err(not_running)?;
+                Self::exchange_line(stream, json)
+            }
+            #[cfg(not(unix))]
+            Transport::UnixSocket(path) => Err(Failure::rejected(format!(
+                "daemon at {}: Unix sockets aren't supported on this platform; use TCP or HTTP",
+                path
+            ))),
+            Transport::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).map_err(not_running)?;
+                stream.set_read_timeout(Some(self.timeout)).map_err(not_running)?;
+                stream.set_write_timeout(Some(self.timeout)).map_err(not_running)?;
+                Self::exchange_line(stream, json)
+            }
+            Transport::Http(url) => self.http_post(url, json),
+        }
+    }
+
+    /// Write one newline-terminated message and read one line back.
+    fn exchange_line<S: Read + Write>(mut stream: S, json: &str) -> std::result::Result<String, Failure> {
+        stream.write_all(format!("{}\n", json).as_bytes())
+            .map_err(|e| Failure::lost("write to daemon", e))?;
 
         let mut reader = BufReader::new(stream);
         let mut line = String::new();
         reader.read_line(&mut line)
-            .map_err(|e| PageStoreError::Storage(format!("read from daemon: {}", e)))?;
+            .map_err(|e| Failure::lost("read from daemon", e))?;
+        if line.is_empty() {
+            return Err(Failure::exchange("daemon closed the connection".into()));
+        }
+        Ok(line)
+    }
 
-        let response: RpcResponse = serde_json::from_str(line.trim())
-            .map_err(|e| PageStoreError::Storage(format!("parse daemon response: {}", e)))?;
+    /// POST `json` to a plain `http://` URL and return the response body.
+    ///
+    /// Speaks HTTP/1.0 so the reply is never chunked; TLS is left to a proxy.
+    fn http_post(&self, url: &str, json: &str) -> std::result::Result<String, Failure> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            Failure::rejected(format!("unsupported daemon URL {} (only http:// is supported)", url))
+        })?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let addr = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
 
-        if let Some(err) = response.error {
-            return Err(PageStoreError::Storage(format!(
-                "daemon error {}: {}", err.code, err.message
-            )));
+        let not_running = |e: std::io::Error| {
+            Failure::connect(format!("daemon not running at {}: {}", url, e))
+        };
+        let mut stream = TcpStream::connect(&addr).map_err(not_running)?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(not_running)?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(not_running)?;
+
+        let auth = self.auth_token.as_ref()
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        let request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            path, host, auth, json.len(), json
+        );
+        stream.write_all(request.as_bytes())
+            .map_err(|e| Failure::lost("write to daemon", e))?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)
+            .map_err(|e| Failure::lost("read from daemon", e))?;
+        let response = String::from_utf8(response)
+            .map_err(|e| Failure::rejected(format!("parse daemon response: {}", e)))?;
+
+        let (head, body) = response.split_once("\r\n\r\n")
+            .ok_or_else(|| Failure::exchange("malformed HTTP response from daemon".into()))?;
+        let status = head.lines().next().unwrap_or("");
+        match status.split_whitespace().nth(1) {
+            Some("200") => Ok(body.to_string()),
+            Some("401" | "403") => Err(Failure::unauthorized(status.to_string())),
+            // Gateway errors while the daemon behind a proxy restarts
+            Some("502" | "503" | "504") => {
+                Err(Failure::exchange(format!("daemon HTTP error: {}", status)))
+            }
+            _ => Err(Failure::rejected(format!("daemon HTTP error: {}", status))),
+        }
+    }
+
+    /// Publish `data` in-band via the `publish_data` RPC.
+    fn publish_data(&self, data: &[u8], options: &PublishOptions) -> Result<()> {
+        self.rpc_call("publish_data", Some(with_options(serde_json::json!({
+            "data": BASE64.encode(data),
+        }), options)))?
+            .get("cid")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| PageStoreError::Storage("missing cid in publish response".into()))?;
+        Ok(())
+    }
+
+    /// A uniquely named scratch file in the temp dir, deleted when dropped.
+    fn temp_file(&self, prefix: &str) -> Result<tempfile::NamedTempFile> {
+        tempfile::Builder::new()
+            .prefix(prefix)
+            .tempfile_in(&self.temp_dir)
+            .map_err(|e| PageStoreError::Storage(format!(
+                "create temp file in {}: {}", self.temp_dir.display(), e
+            )))
+    }
+
+    /// Have the daemon write `cid` to a fresh temp file and return its path.
+    ///
+    /// The file is deleted when the returned path is dropped, and on every
+    /// error path. A daemon reporting a different output path has its file
+    /// adopted, and deleted in turn, only if it lies inside the temp dir;
+    /// anywhere else it is copied, and left for the daemon.
+    fn fetch_to_temp(&self, cid: &Cid) -> Result<tempfile::TempPath> {
+        let output = self.temp_file("craftsql-fetch-")?.into_temp_path();
+        let result = self.rpc_call("fetch", Some(serde_json::json!({
+            "cid": hex::encode(cid.0),
+            "output": output.to_string_lossy(),
+        })))?;
+
+        match result.get("path").and_then(|v| v.as_str()).map(Path::new) {
+            Some(path) if path != &*output && self.in_temp_dir(path) => Ok(tempfile::TempPath::from_path(path)),
+            Some(path) if path != &*output => {
+                std::fs::copy(path, &output).map_err(|e| PageStoreError::Storage(format!(
+                    "copy fetched page from {}: {}", path.display(), e
+                )))?;
+                Ok(output)
+            }
+            _ => Ok(output),
+        }
+    }
+
+    /// Whether `path` is a file inside the temp dir, which is ours to delete.
+    fn in_temp_dir(&self, path: &Path) -> bool {
+        match (path.canonicalize(), self.temp_dir.canonicalize()) {
+            (Ok(path), Ok(dir)) => path != dir && path.starts_with(dir),
+            _ => false,
         }
+    }
+
+    /// Fetch content in-band via the `fetch_data` RPC, verifying its CID.
+    fn fetch_data(&self, cid: &Cid) -> Result<Vec<u8>> {
+        let result = self.rpc_call("fetch_data", Some(serde_json::json!({
+            "cid": hex::encode(cid.0),
+        })))?;
+        let encoded = result.get("data")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| PageStoreError::Storage("missing data in fetch response".into()))?;
+        let data = BASE64.decode(encoded)
+            .map_err(|e| PageStoreError::Storage(format!("invalid data in fetch response: {}", e)))?;
 
-        response.result.ok_or_else(|| PageStoreError::Storage("empty daemon response".into()))
+        let actual = Cid::from_bytes(&data);
+        if actual != *cid {
+            return Err(PageStoreError::Corruption { expected: *cid, actual });
+        }
+        Ok(data)
     }
 }
 
 impl NetworkBackend for DaemonBackend {
     fn publish_page(&self, data: &[u8]) -> Result<Cid> {
+        self.upload.take(data.len() as u64);
+        if self.transfer == Transfer::InBand {
+            self.publish_data(data, &PublishOptions::default())?;
+            return Ok(Cid::from_bytes(data));
+        }
+
         // Write data to temp file, call daemon's publish RPC
-        let mut tmp = tempfile::NamedTempFile::new()
-            .map_err(|e| PageStoreError::Storage(e.to_string()))?;
+        let mut tmp = self.temp_file("craftsql-publish-")?;
         tmp.write_all(data)
             .map_err(|e| PageStoreError::Storage(e.to_string()))?;
         tmp.flush()
@@ -143,80 +684,144 @@ impl NetworkBackend for DaemonBackend {
     }
 
     fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
-        let cid_hex = hex::encode(cid.0);
-        let output_path = std::env::temp_dir().join(format!("craftsql-fetch-{}", &cid_hex[..16]));
-
-        let result = self.rpc_call("fetch", Some(serde_json::json!({
-            "cid": cid_hex,
-            "output": output_path.to_string_lossy(),
-        })))?;
-
-        let path = result.get("path")
-            .and_then(|v| v.as_str())
-            .map(|s| std::path::PathBuf::from(s))
-            .unwrap_or(output_path);
+        if self.transfer == Transfer::InBand {
+            let data = self.fetch_data(cid)?;
+            self.download.take(data.len() as u64);
+            return Ok(data);
+        }
 
+        let path = self.fetch_to_temp(cid)?;
         let data = std::fs::read(&path)
             .map_err(|e| PageStoreError::Storage(format!("read fetched page: {}", e)))?;
-
-        // Clean up temp file
-        let _ = std::fs::remove_file(&path);
+        self.download.take(data.len() as u64);
 
         // Verify CID
         let actual = Cid::from_bytes(&data);
         if actual != *cid {
-            return Err(PageStoreError::Storage(format!(
-                "CID mismatch after fetch: expected {}, got {}", cid, actual
-            )));
+            return Err(PageStoreError::Corruption { expected: *cid, actual });
         }
 
         Ok(data)
     }
 
+    fn publish_stream(&self, reader: &mut dyn Read) -> Result<Cid> {
+        self.publish_stream_with(reader, &PublishOptions::default())
+    }
+
+    fn publish_stream_with(&self, reader: &mut dyn Read, options: &PublishOptions) -> Result<Cid> {
+        if self.transfer == Transfer::InBand {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            self.upload.take(data.len() as u64);
+            self.publish_data(&data, options)?;
+            return Ok(Cid::from_bytes(&data));
+        }
+
+        // Spool to a temp file (the daemon's publish API is file-based), hashing
+        // from disk so the content is never held in memory.
+        let mut tmp = self.temp_file("craftsql-publish-")?;
+        std::io::copy(&mut self.upload.wrap(reader), &mut tmp)?;
+        tmp.flush()?;
+
+        self.rpc_call("publish", Some(with_options(serde_json::json!({
+            "path": tmp.path().to_string_lossy(),
+        }), options)))?
+            .get("cid")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| PageStoreError::Storage("missing cid in publish response".into()))?;
+
+        Ok(Cid::from_reader(std::fs::File::open(tmp.path())?)?)
+    }
+
+    fn fetch_stream(&self, cid: &Cid, writer: &mut dyn Write) -> Result<()> {
+        let mut writer = self.download.wrap(writer);
+        if self.transfer == Transfer::InBand {
+            writer.write_all(&self.fetch_data(cid)?)?;
+            return Ok(());
+        }
+
+        let path = self.fetch_to_temp(cid)?;
+
+        // Verify CID before handing out any bytes
+        match std::fs::File::open(&path).and_then(Cid::from_reader) {
+            Ok(actual) if actual != *cid => Err(PageStoreError::Corruption { expected: *cid, actual }),
+            Ok(_) => std::fs::File::open(&path)
+                .and_then(|mut file| std::io::copy(&mut file, &mut writer))
+                .map(|_| ())
+                .map_err(|e| PageStoreError::Storage(format!("read fetched page: {}", e))),
+            Err(e) => Err(PageStoreError::Storage(format!("read fetched page: {}", e))),
+        }
+    }
+
+    fn publish_many(&self, items: &[&[u8]]) -> Result<Vec<Cid>> {
+        self.upload.take(items.iter().map(|data| data.len() as u64).sum());
+        // One batched RPC; temp files (if used) must outlive the call
+        let mut tmps = Vec::new();
+        let mut calls = Vec::with_capacity(items.len());
+        for data in items {
+            if self.transfer == Transfer::InBand {
+                calls.push(("publish_data", serde_json::json!({ "data": BASE64.encode(data) })));
+                continue;
+            }
+            let mut tmp = self.temp_file("craftsql-publish-")?;
+            tmp.write_all(data)?;
+            tmp.flush()?;
+            calls.push(("publish", serde_json::json!({ "path": tmp.path().to_string_lossy() })));
+            tmps.push(tmp);
+        }
+
+        for result in self.rpc_batch(calls)? {
+            result.get("cid")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| PageStoreError::Storage("missing cid in publish response".into()))?;
+        }
+        Ok(items.iter().map(|data| Cid::from_bytes(data)).collect())
+    }
+
     fn get_root(&self) -> Result<Option<Cid>> {
-        self.get_named_root("__default__")
+        self.get_named_root(DEFAULT_ROOT_NAME)
     }
 
     fn set_root(&self, cid: Cid) -> Result<()> {
-        self.set_named_root("__default__", cid)
+        self.set_named_root(DEFAULT_ROOT_NAME, cid)
     }
 
-    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
-        let key = format!("craftsql:root:{}", name);
-        let result = self.rpc_call("kv.get", Some(serde_json::json!({"key": key})))?;
-        match result.get("value").and_then(|v| v.as_str()) {
-            Some(hex_str) => {
-                let bytes = hex::decode(hex_str)
-                    .map_err(|e| PageStoreError::Storage(format!("invalid root hex: {}", e)))?;
-                if bytes.len() != 32 {
-                    return Err(PageStoreError::Storage("invalid root CID length".into()));
-                }
-                let mut cid = [0u8; 32];
-                cid.copy_from_slice(&bytes);
-                Ok(Some(Cid(cid)))
-            }
-            None => Ok(None),
+    /// One `kv.cas` call: the daemon swaps the root only if it still holds
+    /// `expected`, and answers with what it holds either way.
+    fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        let result = self.rpc_call("kv.cas", Some(serde_json::json!({
+            "key": root_key(DEFAULT_ROOT_NAME),
+            "expected": expected.map(|cid| hex::encode(cid.0)),
+            "value": hex::encode(new.0),
+        })))?;
+        if result.get("swapped").and_then(|v| v.as_bool()) == Some(true) {
+            return Ok(());
         }
+        let actual = parse_root(result.get("value").and_then(|v| v.as_str()))?;
+        Err(PageStoreError::RootConflict { expected, actual })
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        let result = self.rpc_call("kv.get", Some(serde_json::json!({"key": root_key(name)})))?;
+        parse_root(result.get("value").and_then(|v| v.as_str()))
     }
 
     fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
-        let key = format!("craftsql:root:{}", name);
         self.rpc_call("kv.put", Some(serde_json::json!({
-            "key": key,
+            "key": root_key(name),
             "value": hex::encode(cid.0),
         })))?;
         Ok(())
     }
 
     fn remove_named_root(&self, name: &str) -> Result<bool> {
-        let key = format!("craftsql:root:{}", name);
-        let result = self.rpc_call("kv.delete", Some(serde_json::json!({"key": key})))?;
+        let result = self.rpc_call("kv.delete", Some(serde_json::json!({"key": root_key(name)})))?;
         Ok(result.get("deleted").and_then(|v| v.as_bool()).unwrap_or(false))
     }
 
     fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
         let result = self.rpc_call("kv.list", Some(serde_json::json!({
-            "prefix": "craftsql:root:",
+            "prefix": ROOT_KEY_PREFIX,
         })))?;
         let keys = result.get("keys")
             .and_then(|v| v.as_array())
@@ -224,11 +829,10 @@ impl NetworkBackend for DaemonBackend {
             .unwrap_or_default();
 
         let mut roots = Vec::new();
-        let prefix = "craftsql:root:";
         for key_val in keys {
             if let Some(key) = key_val.as_str() {
-                let name = key.strip_prefix(prefix).unwrap_or(key);
-                if name == "__default__" { continue; }
+                let name = key.strip_prefix(ROOT_KEY_PREFIX).unwrap_or(key);
+                if name == DEFAULT_ROOT_NAME { continue; }
                 if let Ok(Some(cid)) = self.get_named_root(name) {
                     roots.push((name.to_string(), cid));
                 }
@@ -237,6 +841,28 @@ impl NetworkBackend for DaemonBackend {
         roots.sort_by(|a, b| a.0.cmp(&b.0));
         Ok(roots)
     }
+
+    fn pin(&self, cid: &Cid) -> Result<()> {
+        self.rpc_call("pin", Some(serde_json::json!({"cid": hex::encode(cid.0)})))?;
+        Ok(())
+    }
+
+    fn unpin(&self, cid: &Cid) -> Result<()> {
+        self.rpc_call("unpin", Some(serde_json::json!({"cid": hex::encode(cid.0)})))?;
+        Ok(())
+    }
+
+    fn placement(&self, cid: &Cid) -> Result<Option<Placement>> {
+        let result = self.rpc_call("placement", Some(serde_json::json!({"cid": hex::encode(cid.0)})))?;
+        let result: PlacementResult = serde_json::from_value(result)
+            .map_err(|e| PageStoreError::Storage(format!("parse placement response: {}", e)))?;
+        Ok(Some(Placement {
+            providers: result.providers,
+            segments: result.segments.into_iter()
+                .map(|segment| SegmentHealth { pieces: segment.pieces, needed: segment.needed })
+                .collect(),
+        }))
+    }
 }
 
 #[cfg(test)]
@@ -249,23 +875,115 @@ mod tests {
             jsonrpc: "2.0",
             method: "publish",
             params: Some(serde_json::json!({"path": "/tmp/test"})),
+            auth: None,
             id: 1,
         };
         let json = serde_json::to_string(&req).unwrap();
         assert!(json.contains("\"jsonrpc\":\"2.0\""));
         assert!(json.contains("\"method\":\"publish\""));
+        assert!(!json.contains("auth"));
+    }
+
+    #[test]
+    fn test_unauthorized_error_code_maps_to_variant() {
+        let response: RpcResponse = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","error":{"code":-32001,"message":"bad token"},"id":1}"#,
+        ).unwrap();
+        assert!(matches!(response.into_result(), Err(PageStoreError::Unauthorized(m)) if m == "bad token"));
     }
 
     #[test]
     fn test_daemon_backend_creation() {
         let backend = DaemonBackend::new("/tmp/test.sock");
-        assert_eq!(backend.socket_path, "/tmp/test.sock");
+        assert_eq!(backend.transport(), &Transport::UnixSocket("/tmp/test.sock".into()));
+    }
+
+    #[test]
+    fn test_transfer_defaults_to_temp_file() {
+        let backend = DaemonBackend::new("/tmp/test.sock");
+        assert_eq!(backend.transfer, Transfer::TempFile);
+        let backend = backend.with_transfer(Transfer::InBand);
+        assert_eq!(backend.transfer, Transfer::InBand);
+    }
+
+    #[test]
+    fn test_kv_writes_are_not_replayed() {
+        assert!(is_idempotent("publish"));
+        assert!(is_idempotent("fetch_data"));
+        assert!(is_idempotent("kv.get"));
+        assert!(!is_idempotent("kv.put"));
+        assert!(!is_idempotent("kv.cas"));
+        assert!(!is_idempotent("kv.delete"));
+    }
+
+    #[test]
+    fn test_retries_until_attempts_exhausted() {
+        let backend = DaemonBackend::new("/tmp/nonexistent-craftsql-test-retry.sock")
+            .with_retry(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(20),
+                max_backoff: Duration::from_millis(30),
+            });
+        let started = std::time::Instant::now();
+        assert!(backend.get_root().is_err());
+        // Two backoffs: 20ms then 30ms (capped)
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_failed_fetch_leaves_no_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = DaemonBackend::new("/tmp/nonexistent-craftsql-test-tmp.sock")
+            .with_retry(RetryPolicy::none())
+            .with_temp_dir(dir.path());
+        assert!(backend.fetch_page(&Cid([7; 32])).is_err());
+        assert!(backend.fetch_stream(&Cid([7; 32]), &mut Vec::new()).is_err());
+        assert!(backend.publish_page(b"data").is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_rpc_stats_count_failed_calls() {
+        let backend = DaemonBackend::new("/tmp/nonexistent-craftsql-test-stats.sock")
+            .with_retry(RetryPolicy {
+                max_attempts: 2,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+            });
+        assert!(backend.get_root().is_err());
+        assert!(backend.get_named_root("main").is_err());
+
+        let stats = backend.rpc_stats();
+        let kv_get = &stats.methods["kv.get"];
+        assert_eq!(kv_get.calls, 2);
+        assert_eq!(kv_get.errors, 2);
+        assert_eq!(kv_get.retries, 2);
+        assert_eq!(kv_get.latency_histogram.iter().sum::<u64>(), 2);
+        assert_eq!(stats.total_calls(), 2);
     }
 
     #[test]
     fn test_default_socket() {
         let backend = DaemonBackend::default_socket();
-        assert_eq!(backend.socket_path, "/tmp/craftobj.sock");
+        assert_eq!(backend.transport(), &Transport::UnixSocket("/tmp/craftobj.sock".into()));
+    }
+
+    #[test]
+    fn test_remote_transports_default_to_in_band() {
+        let backend = DaemonBackend::tcp("10.0.0.5:7070");
+        assert_eq!(backend.transport().to_string(), "tcp://10.0.0.5:7070");
+        assert_eq!(backend.transfer, Transfer::InBand);
+
+        let backend = DaemonBackend::http("http://daemon.internal:8080/rpc");
+        assert_eq!(backend.transport(), &Transport::Http("http://daemon.internal:8080/rpc".into()));
+        assert_eq!(backend.transfer, Transfer::InBand);
+    }
+
+    #[test]
+    fn test_http_rejects_unsupported_scheme() {
+        let backend = DaemonBackend::http("https://daemon.internal/rpc");
+        let err = backend.get_root().unwrap_err().to_string();
+        assert!(err.contains("only http://"));
     }
 
     #[test]
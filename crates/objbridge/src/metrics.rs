@@ -0,0 +1,110 @@
+//! Per-method RPC call counts, errors, retries, and latency histograms.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets. Calls slower than the last
+/// bound land in one extra overflow bucket.
+pub const LATENCY_BUCKETS: [Duration; 7] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+];
+
+/// Counters for one RPC method.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MethodStats {
+    pub calls: u64,
+    /// Calls that ultimately failed, after any retries.
+    pub errors: u64,
+    /// Extra attempts made because of transient failures.
+    pub retries: u64,
+    /// Wall-clock time across all calls, retries and backoff included.
+    pub total_time: Duration,
+    /// Calls per [`LATENCY_BUCKETS`] bucket; the last entry counts slower calls.
+    pub latency_histogram: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+impl MethodStats {
+    /// Mean time per call, or `None` before the first call.
+    pub fn avg_latency(&self) -> Option<Duration> {
+        (self.calls > 0).then(|| self.total_time / self.calls as u32)
+    }
+}
+
+/// Point-in-time copy of a backend's RPC statistics, keyed by method.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RpcStatsSnapshot {
+    pub methods: BTreeMap<String, MethodStats>,
+}
+
+impl RpcStatsSnapshot {
+    pub fn total_calls(&self) -> u64 {
+        self.methods.values().map(|m| m.calls).sum()
+    }
+
+    pub fn total_errors(&self) -> u64 {
+        self.methods.values().map(|m| m.errors).sum()
+    }
+
+    /// Time spent waiting on the daemon across all methods.
+    pub fn total_time(&self) -> Duration {
+        self.methods.values().map(|m| m.total_time).sum()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RpcMetrics {
+    methods: Mutex<BTreeMap<String, MethodStats>>,
+}
+
+impl RpcMetrics {
+    pub(crate) fn record(&self, method: &str, elapsed: Duration, retries: u64, ok: bool) {
+        let mut methods = self.methods.lock().unwrap();
+        let stats = methods.entry(method.to_string()).or_default();
+        stats.calls += 1;
+        stats.retries += retries;
+        if !ok {
+            stats.errors += 1;
+        }
+        stats.total_time += elapsed;
+        let bucket = LATENCY_BUCKETS.iter()
+            .position(|bound| elapsed <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        stats.latency_histogram[bucket] += 1;
+    }
+
+    pub(crate) fn snapshot(&self) -> RpcStatsSnapshot {
+        RpcStatsSnapshot { methods: self.methods.lock().unwrap().clone() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_buckets_and_totals() {
+        let metrics = RpcMetrics::default();
+        metrics.record("fetch", Duration::from_micros(500), 0, true);
+        metrics.record("fetch", Duration::from_millis(30), 2, false);
+        metrics.record("kv.get", Duration::from_secs(3), 0, true);
+
+        let snapshot = metrics.snapshot();
+        let fetch = &snapshot.methods["fetch"];
+        assert_eq!(fetch.calls, 2);
+        assert_eq!(fetch.errors, 1);
+        assert_eq!(fetch.retries, 2);
+        assert_eq!(fetch.latency_histogram[0], 1);
+        assert_eq!(fetch.latency_histogram[3], 1);
+        assert_eq!(snapshot.methods["kv.get"].latency_histogram[LATENCY_BUCKETS.len()], 1);
+        assert_eq!(snapshot.total_calls(), 3);
+        assert_eq!(snapshot.total_errors(), 1);
+        assert_eq!(fetch.avg_latency(), Some(Duration::from_micros(15_250)));
+    }
+}
@@ -1,191 +1,332 @@
 //! Integration test for the CraftOBJ bridge.
 //!
-//! Uses a mock daemon (Unix socket server) to test the full pipeline:
+//! Uses the `craftsql-testing` mock daemon (Unix socket, TCP, or HTTP server) to
+//! test the full pipeline:
 //! SQLite → CraftVFS → CraftObjPageStore<DaemonBackend> → mock daemon
-
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Write};
-use std::os::unix::net::UnixListener;
-use std::sync::{Arc, Mutex};
+//!
+//! Most cases talk to the daemon over a Unix socket, so these run on Unix.
+#![cfg(unix)]
 
 use craftsql_core::Cid;
+use craftsql_testing::MockDaemon;
+
+#[test]
+fn test_publish_and_fetch_via_mock_daemon() {
+    use craftsql_objbridge::DaemonBackend;
+    use craftsql_objstore::NetworkBackend;
+
+    let socket_path = format!("/tmp/craftsql-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let _handle = daemon.start();
+
+    // Give the listener a moment to bind
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    let backend = DaemonBackend::new(&socket_path);
+
+    // Publish a page
+    let data = b"hello craftsql bridge";
+    let cid = backend.publish_page(data).unwrap();
+    assert_eq!(cid, Cid::from_bytes(data));
 
-/// Mock CraftOBJ daemon that handles publish/fetch over a Unix socket.
-struct MockDaemon {
-    socket_path: String,
-    /// Stored content: CID hex → file data
-    store: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    // Fetch it back
+    let fetched = backend.fetch_page(&cid).unwrap();
+    assert_eq!(fetched, data);
 }
 
-impl MockDaemon {
-    fn new(socket_path: &str) -> Self {
-        Self {
-            socket_path: socket_path.to_string(),
-            store: Arc::new(Mutex::new(HashMap::new())),
+#[test]
+fn test_publish_many_in_one_batch() {
+    use craftsql_objbridge::{DaemonBackend, Transfer};
+    use craftsql_objstore::NetworkBackend;
+
+    let socket_path = format!("/tmp/craftsql-batch-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let _handle = daemon.start();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    let items: [&[u8]; 3] = [b"first", b"second", b"third"];
+    for transfer in [Transfer::TempFile, Transfer::InBand] {
+        let backend = DaemonBackend::new(&socket_path).with_transfer(transfer);
+        let cids = backend.publish_many(&items).unwrap();
+        assert_eq!(cids, items.iter().map(|d| Cid::from_bytes(d)).collect::<Vec<_>>());
+        for (cid, data) in cids.iter().zip(items) {
+            assert_eq!(backend.fetch_page(cid).unwrap(), data);
         }
-    }
 
-    /// Start listening in a background thread. Returns a handle to stop it.
-    fn start(&self) -> std::thread::JoinHandle<()> {
-        let path = self.socket_path.clone();
-        let store = self.store.clone();
-
-        // Remove stale socket
-        let _ = std::fs::remove_file(&path);
-
-        let listener = UnixListener::bind(&path).expect("bind mock daemon socket");
-        // Set non-blocking so we can check for shutdown, but we'll use accept timeout
-        listener.set_nonblocking(false).ok();
-
-        std::thread::spawn(move || {
-            // Accept connections until the socket file is removed
-            for stream in listener.incoming() {
-                let stream = match stream {
-                    Ok(s) => s,
-                    Err(_) => break,
-                };
-
-                let store = store.clone();
-                std::thread::spawn(move || {
-                    let mut reader = BufReader::new(stream.try_clone().unwrap());
-                    let mut writer = stream;
-                    let mut line = String::new();
-
-                    if reader.read_line(&mut line).is_err() {
-                        return;
-                    }
-
-                    let request: serde_json::Value = match serde_json::from_str(line.trim()) {
-                        Ok(v) => v,
-                        Err(_) => return,
-                    };
-
-                    let method = request["method"].as_str().unwrap_or("");
-                    let params = request.get("params");
-                    let id = request["id"].as_u64().unwrap_or(0);
-
-                    let result = match method {
-                        "publish" => {
-                            let path = params
-                                .and_then(|p| p.get("path"))
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("");
-                            match std::fs::read(path) {
-                                Ok(data) => {
-                                    let cid = Cid::from_bytes(&data);
-                                    let cid_hex = hex::encode(cid.0);
-                                    store.lock().unwrap().insert(cid_hex.clone(), data.clone());
-                                    Ok(serde_json::json!({
-                                        "cid": cid_hex,
-                                        "size": data.len(),
-                                        "segments": 1,
-                                    }))
-                                }
-                                Err(e) => Err(format!("read file: {}", e)),
-                            }
-                        }
-                        "fetch" => {
-                            let cid_hex = params
-                                .and_then(|p| p.get("cid"))
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("");
-                            let output = params
-                                .and_then(|p| p.get("output"))
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("/tmp/mock-fetch-out");
-
-                            match store.lock().unwrap().get(cid_hex) {
-                                Some(data) => {
-                                    std::fs::write(output, data).ok();
-                                    Ok(serde_json::json!({ "path": output }))
-                                }
-                                None => Err(format!("content not found: {}", cid_hex)),
-                            }
-                        }
-                        "kv.put" => {
-                            let key = params.and_then(|p| p.get("key")).and_then(|v| v.as_str()).unwrap_or("").to_string();
-                            let value = params.and_then(|p| p.get("value")).and_then(|v| v.as_str()).unwrap_or("").to_string();
-                            store.lock().unwrap().insert(format!("__kv__{}", key), value.into_bytes());
-                            Ok(serde_json::json!({"ok": true}))
-                        }
-                        "kv.get" => {
-                            let key = params.and_then(|p| p.get("key")).and_then(|v| v.as_str()).unwrap_or("").to_string();
-                            let s = store.lock().unwrap();
-                            match s.get(&format!("__kv__{}", key)) {
-                                Some(data) => {
-                                    let val = String::from_utf8_lossy(data).to_string();
-                                    Ok(serde_json::json!({"key": key, "value": val}))
-                                }
-                                None => Ok(serde_json::json!({"key": key, "value": null})),
-                            }
-                        }
-                        "kv.delete" => {
-                            let key = params.and_then(|p| p.get("key")).and_then(|v| v.as_str()).unwrap_or("").to_string();
-                            let existed = store.lock().unwrap().remove(&format!("__kv__{}", key)).is_some();
-                            Ok(serde_json::json!({"deleted": existed}))
-                        }
-                        "kv.list" => {
-                            let prefix = params.and_then(|p| p.get("prefix")).and_then(|v| v.as_str()).unwrap_or("").to_string();
-                            let s = store.lock().unwrap();
-                            let keys: Vec<String> = s.keys()
-                                .filter(|k| k.starts_with("__kv__"))
-                                .map(|k| k.strip_prefix("__kv__").unwrap().to_string())
-                                .filter(|k| k.starts_with(&prefix))
-                                .collect();
-                            Ok(serde_json::json!({"keys": keys}))
-                        }
-                        _ => Err(format!("unknown method: {}", method)),
-                    };
-
-                    let response = match result {
-                        Ok(val) => serde_json::json!({
-                            "jsonrpc": "2.0",
-                            "result": val,
-                            "id": id,
-                        }),
-                        Err(msg) => serde_json::json!({
-                            "jsonrpc": "2.0",
-                            "error": { "code": -32000, "message": msg },
-                            "id": id,
-                        }),
-                    };
-
-                    let resp_str = serde_json::to_string(&response).unwrap();
-                    let _ = writer.write_all(format!("{}\n", resp_str).as_bytes());
-                });
-            }
-        })
+        let stats = backend.rpc_stats();
+        let publish = if transfer == Transfer::InBand { "publish_data" } else { "publish" };
+        assert_eq!(stats.methods[publish].calls, 3);
+        assert_eq!(stats.total_errors(), 0);
     }
 }
 
-impl Drop for MockDaemon {
-    fn drop(&mut self) {
-        let _ = std::fs::remove_file(&self.socket_path);
+#[test]
+fn test_auth_token_required_by_daemon() {
+    use craftsql_core::PageStoreError;
+    use craftsql_objbridge::DaemonBackend;
+    use craftsql_objstore::NetworkBackend;
+
+    let socket_path = format!("/tmp/craftsql-auth-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path).with_token("s3cret");
+    let _handle = daemon.start();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    assert_eq!(craftsql_testing::UNAUTHORIZED_CODE, craftsql_objbridge::UNAUTHORIZED_CODE);
+    let rejected = DaemonBackend::new(&socket_path).with_auth_token("wrong");
+    assert!(matches!(rejected.publish_page(b"data"), Err(PageStoreError::Unauthorized(_))));
+    assert!(matches!(rejected.get_root(), Err(PageStoreError::Unauthorized(_))));
+
+    let client = DaemonBackend::new(&socket_path).with_auth_token("s3cret");
+    let cid = client.publish_page(b"data").unwrap();
+    assert_eq!(client.fetch_page(&cid).unwrap(), b"data");
+    assert_eq!(client.publish_many(&[b"a", b"b"]).unwrap().len(), 2);
+
+    let http = DaemonBackend::http(&daemon.start_http()).with_auth_token("s3cret");
+    assert_eq!(http.fetch_page(&cid).unwrap(), b"data");
+}
+
+#[test]
+fn test_in_band_transfer_via_mock_daemon() {
+    use craftsql_objbridge::{DaemonBackend, Transfer};
+    use craftsql_objstore::NetworkBackend;
+
+    let socket_path = format!("/tmp/craftsql-inband-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let _handle = daemon.start();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    let backend = DaemonBackend::new(&socket_path).with_transfer(Transfer::InBand);
+
+    let data = vec![0xC3; 4096];
+    let cid = backend.publish_page(&data).unwrap();
+    assert_eq!(cid, Cid::from_bytes(&data));
+    assert_eq!(backend.fetch_page(&cid).unwrap(), data);
+
+    let blob = b"bundle bytes over the socket".to_vec();
+    let blob_cid = backend.publish_stream(&mut &blob[..]).unwrap();
+    let mut out = Vec::new();
+    backend.fetch_stream(&blob_cid, &mut out).unwrap();
+    assert_eq!(out, blob);
+}
+
+#[test]
+fn test_publish_options_and_placement_via_mock_daemon() {
+    use craftsql_objbridge::{DaemonBackend, Transfer};
+    use craftsql_objstore::{NetworkBackend, PublishOptions};
+    use craftsql_testing::PublishedWith;
+
+    let socket_path = format!("/tmp/craftsql-placement-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let _handle = daemon.start();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    let options = PublishOptions::default().with_replication(3).with_segment_size(1024);
+    for transfer in [Transfer::TempFile, Transfer::InBand] {
+        let backend = DaemonBackend::new(&socket_path).with_transfer(transfer);
+        let blob = vec![transfer as u8; 2500];
+        let cid = backend.publish_stream_with(&mut &blob[..], &options).unwrap();
+        assert_eq!(daemon.published_with(&cid), Some(PublishedWith { replication: Some(3), segment_size: Some(1024) }));
+
+        let placement = backend.placement(&cid).unwrap().unwrap();
+        assert_eq!(placement.providers.len(), 3);
+        assert_eq!(placement.segments.len(), 3);
+        assert!(placement.is_recoverable());
     }
+
+    // Plain publishes leave the daemon's defaults
+    let backend = DaemonBackend::new(&socket_path);
+    let cid = backend.publish_stream(&mut &b"defaults"[..]).unwrap();
+    assert_eq!(daemon.published_with(&cid), Some(PublishedWith::default()));
+    assert!(backend.placement(&Cid::from_bytes(b"missing")).is_err());
 }
 
 #[test]
-fn test_publish_and_fetch_via_mock_daemon() {
+fn test_pins_via_mock_daemon() {
     use craftsql_objbridge::DaemonBackend;
     use craftsql_objstore::NetworkBackend;
 
-    let socket_path = format!("/tmp/craftsql-test-{}.sock", std::process::id());
+    let socket_path = format!("/tmp/craftsql-pin-test-{}.sock", std::process::id());
     let daemon = MockDaemon::new(&socket_path);
     let _handle = daemon.start();
-
-    // Give the listener a moment to bind
     std::thread::sleep(std::time::Duration::from_millis(50));
 
     let backend = DaemonBackend::new(&socket_path);
+    let cid = backend.publish_page(b"a snapshot's bundle").unwrap();
+    backend.pin(&cid).unwrap();
+    assert!(daemon.is_pinned(&cid));
+    backend.unpin(&cid).unwrap();
+    backend.unpin(&cid).unwrap();
+    assert!(!daemon.is_pinned(&cid));
+    assert!(backend.pin(&Cid::from_bytes(b"missing")).is_err());
+}
 
-    // Publish a page
-    let data = b"hello craftsql bridge";
+#[test]
+fn test_retries_while_daemon_restarts() {
+    use craftsql_objbridge::{DaemonBackend, RetryPolicy};
+    use craftsql_objstore::NetworkBackend;
+    use std::time::Duration;
+
+    let socket_path = format!("/tmp/craftsql-restart-test-{}.sock", std::process::id());
+    let _ = std::fs::remove_file(&socket_path);
+    let backend = DaemonBackend::new(&socket_path).with_retry(RetryPolicy {
+        max_attempts: 20,
+        initial_backoff: Duration::from_millis(20),
+        max_backoff: Duration::from_millis(50),
+    });
+
+    // The daemon comes (back) up while the client is already calling
+    let path = socket_path.clone();
+    let starter = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(150));
+        let daemon = MockDaemon::new(&path);
+        daemon.start();
+        daemon
+    });
+
+    let data = b"written across a restart";
     let cid = backend.publish_page(data).unwrap();
-    assert_eq!(cid, Cid::from_bytes(data));
+    let _daemon = starter.join().unwrap();
+    assert_eq!(backend.fetch_page(&cid).unwrap(), data);
+}
 
-    // Fetch it back
-    let fetched = backend.fetch_page(&cid).unwrap();
+#[test]
+fn test_injected_daemon_faults() {
+    use craftsql_objbridge::{DaemonBackend, RetryPolicy};
+    use craftsql_objstore::NetworkBackend;
+    use std::time::Duration;
+
+    let socket_path = format!("/tmp/craftsql-faults-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let _handle = daemon.start();
+    let backend = DaemonBackend::new(&socket_path).with_retry(RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(10),
+    });
+
+    // Dropped connections are transient and retried
+    daemon.drop_next(2);
+    let cid = backend.publish_page(b"survives two hangups").unwrap();
+    assert!(daemon.contains(&cid));
+    assert_eq!(backend.rpc_stats().methods["publish"].retries, 2);
+
+    // An error response is the daemon's answer and is not retried
+    daemon.fail_method("fetch", 1);
+    assert!(backend.fetch_page(&cid).is_err());
+    assert_eq!(backend.fetch_page(&cid).unwrap(), b"survives two hangups");
+    assert_eq!(daemon.requests("fetch"), 2);
+
+    backend.set_named_root("main", cid).unwrap();
+    assert!(daemon.kv_get("craftsql:root:main").is_some());
+
+    // A root write whose outcome is unknown is not replayed: it could
+    // overwrite a root another writer moved since
+    daemon.drop_next(1);
+    assert!(backend.set_named_root("main", Cid::from_bytes(b"lost")).is_err());
+    assert_eq!(backend.get_named_root("main").unwrap(), Some(cid));
+    assert_eq!(backend.rpc_stats().methods["kv.put"].retries, 0);
+}
+
+#[test]
+fn test_root_compare_and_swap_via_mock_daemon() {
+    use craftsql_core::PageStoreError;
+    use craftsql_objbridge::DaemonBackend;
+    use craftsql_objstore::NetworkBackend;
+
+    let socket_path = format!("/tmp/craftsql-cas-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let _handle = daemon.start();
+    let backend = DaemonBackend::new(&socket_path);
+    let (v1, v2) = (Cid::from_bytes(b"v1"), Cid::from_bytes(b"v2"));
+
+    backend.set_root_if(None, v1).unwrap();
+    let err = backend.set_root_if(None, v2).unwrap_err();
+    assert!(matches!(err, PageStoreError::RootConflict { expected: None, actual: Some(actual) } if actual == v1));
+    backend.set_root_if(Some(v1), v2).unwrap();
+    assert_eq!(backend.get_root().unwrap(), Some(v2));
+
+    // One round trip each, never a separate read
+    assert_eq!(daemon.requests("kv.cas"), 3);
+    assert_eq!(daemon.requests("kv.get"), 1);
+}
+
+#[test]
+fn test_tcp_and_http_transports_via_mock_daemon() {
+    use craftsql_objbridge::DaemonBackend;
+    use craftsql_objstore::NetworkBackend;
+
+    let socket_path = format!("/tmp/craftsql-remote-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let tcp = DaemonBackend::tcp(&daemon.start_tcp());
+    let http = DaemonBackend::http(&daemon.start_http());
+
+    // Published over TCP, visible over HTTP: both reach the same daemon
+    let data = b"page from another host";
+    let cid = tcp.publish_page(data).unwrap();
+    assert_eq!(http.fetch_page(&cid).unwrap(), data);
+
+    http.set_named_root("main", cid).unwrap();
+    assert_eq!(tcp.get_named_root("main").unwrap(), Some(cid));
+    assert_eq!(http.list_named_roots().unwrap(), vec![("main".to_string(), cid)]);
+}
+
+#[test]
+fn test_temp_files_cleaned_up_in_custom_dir() {
+    use craftsql_objbridge::DaemonBackend;
+    use craftsql_objstore::NetworkBackend;
+
+    let socket_path = format!("/tmp/craftsql-tmpdir-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let _handle = daemon.start();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    let dir = tempfile::tempdir().unwrap();
+    let backend = DaemonBackend::new(&socket_path).with_temp_dir(dir.path());
+
+    let cid = backend.publish_page(b"staged in a custom dir").unwrap();
+    assert_eq!(backend.fetch_page(&cid).unwrap(), b"staged in a custom dir");
+    assert!(backend.fetch_page(&Cid::from_bytes(b"missing")).is_err());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    // A file the daemon wrote elsewhere is copied and left to it
+    let elsewhere = tempfile::tempdir().unwrap();
+    daemon.set_fetch_dir(Some(elsewhere.path()));
+    assert_eq!(backend.fetch_page(&cid).unwrap(), b"staged in a custom dir");
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    assert_eq!(std::fs::read_dir(elsewhere.path()).unwrap().count(), 1);
+
+    // One inside the temp dir is taken over and cleaned up
+    daemon.set_fetch_dir(Some(dir.path()));
+    assert_eq!(backend.fetch_page(&cid).unwrap(), b"staged in a custom dir");
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_bandwidth_limits_via_mock_daemon() {
+    use craftsql_objbridge::DaemonBackend;
+    use craftsql_objstore::{NetworkBackend, RateLimit};
+    use std::time::{Duration, Instant};
+
+    let socket_path = format!("/tmp/craftsql-throttle-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let _handle = daemon.start();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    let backend = DaemonBackend::new(&socket_path)
+        .with_upload_limit(RateLimit::new(100_000))
+        .with_download_limit(RateLimit::new(100_000));
+
+    // A second's worth goes at once; the next 50 KB wait half a second
+    let data = vec![0x42; 150_000];
+    let started = Instant::now();
+    let cid = backend.publish_page(&data).unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+
+    let started = Instant::now();
+    let mut fetched = Vec::new();
+    backend.fetch_stream(&cid, &mut fetched).unwrap();
     assert_eq!(fetched, data);
+    assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
 }
 
 #[test]
@@ -225,6 +366,40 @@ fn test_page_store_with_mock_daemon() {
     assert!(root.is_some());
 }
 
+#[test]
+fn test_second_client_discovers_root_via_daemon() {
+    use craftsql_core::{Page, PageStore, PageTable};
+    use craftsql_objbridge::DaemonBackend;
+    use craftsql_objstore::{CraftObjPageStore, NetworkBackend};
+
+    let socket_path = format!("/tmp/craftsql-roots-test-{}.sock", std::process::id());
+    let daemon = MockDaemon::new(&socket_path);
+    let _handle = daemon.start();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    // Two "machines": separate caches, same daemon
+    let tmp_a = tempfile::tempdir().unwrap();
+    let tmp_b = tempfile::tempdir().unwrap();
+    let store_a = CraftObjPageStore::new(tmp_a.path(), DaemonBackend::new(&socket_path)).unwrap();
+    let store_b = CraftObjPageStore::new(tmp_b.path(), DaemonBackend::new(&socket_path)).unwrap();
+
+    let page = Page { data: vec![0x42; 4096] };
+    let cid = store_a.put(&page).unwrap();
+    let mut pt = PageTable::new();
+    pt.set(0, cid);
+    let pt_cid = store_a.put(&Page { data: pt.to_bytes() }).unwrap();
+    store_a.update_root(pt_cid).unwrap();
+    store_a.set_named_root("release", pt_cid).unwrap();
+
+    assert_eq!(store_b.current_root().unwrap(), store_a.current_root().unwrap());
+    assert_eq!(store_b.get_named_root("release").unwrap(), Some(pt_cid));
+    assert_eq!(store_b.list_named_roots().unwrap(), vec![("release".to_string(), pt_cid)]);
+    assert_eq!(store_b.get(&cid).unwrap().data, page.data);
+
+    assert!(store_b.remove_named_root("release").unwrap());
+    assert_eq!(store_a.network().get_named_root("release").unwrap(), None);
+}
+
 #[test]
 fn test_full_vfs_sql_with_mock_daemon() {
     use craftsql_objbridge::DaemonBackend;
@@ -0,0 +1,725 @@
+//! Async variant of [`NetworkBackend`] for tokio-based CraftOBJ clients.
+//!
+//! The VFS path is synchronous, so an [`AsyncNetworkBackend`] is wrapped in a
+//! [`BlockingAdapter`] that drives its futures on a tokio runtime. Async callers
+//! can still reach the backend directly through the store, e.g. to overlap
+//! several bundle fetches with [`CraftObjPageStore::fetch_bundles`], or a
+//! fetch with a publish through [`CraftObjPageStore::update_root_async`].
+
+use crate::segment::SegmentManifest;
+use crate::{bundle_base, CraftObjPageStore, NetworkBackend, Placement, PublishOptions};
+use craftsql_core::{Cid, PageStore, PageStoreError, Result};
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, TryStreamExt};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::runtime::{Handle, Runtime};
+
+/// Bytes [`BlockingAdapter`] reads from a stream being published at a time.
+const STREAM_CHUNK_LEN: usize = 256 << 10;
+
+/// Chunks read ahead of the publish.
+const STREAM_CHUNKS_QUEUED: usize = 4;
+
+/// Async counterpart of [`NetworkBackend`].
+pub trait AsyncNetworkBackend: Send + Sync {
+    /// Publish page data to the network, returns its CID.
+    fn publish_page(&self, data: &[u8]) -> impl Future<Output = Result<Cid>> + Send;
+
+    /// Fetch page data by CID from the network.
+    fn fetch_page(&self, cid: &Cid) -> impl Future<Output = Result<Vec<u8>>> + Send;
+
+    /// Whether [`fetch_range`](Self::fetch_range) is served natively.
+    fn supports_range(&self) -> bool {
+        false
+    }
+
+    /// Fetch up to `len` bytes of content starting at `offset`, clamped to the
+    /// end of the content. The default fetches the whole content and slices it.
+    fn fetch_range(&self, cid: &Cid, offset: u64, len: u64) -> impl Future<Output = Result<Vec<u8>>> + Send {
+        async move {
+            let data = self.fetch_page(cid).await?;
+            let start = (offset as usize).min(data.len());
+            let end = start.saturating_add(len as usize).min(data.len());
+            Ok(data[start..end].to_vec())
+        }
+    }
+
+    /// Get the current root pointer CID from the DHT.
+    fn get_root(&self) -> impl Future<Output = Result<Option<Cid>>> + Send;
+
+    /// Set the root pointer CID in the DHT.
+    fn set_root(&self, cid: Cid) -> impl Future<Output = Result<()>> + Send;
+
+    /// Set the root pointer only if it currently equals `expected`, failing
+    /// with [`PageStoreError::RootConflict`] otherwise. The default is a
+    /// non-atomic read-compare-write.
+    fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> impl Future<Output = Result<()>> + Send {
+        async move {
+            let actual = self.get_root().await?;
+            if actual != expected {
+                return Err(PageStoreError::RootConflict { expected, actual });
+            }
+            self.set_root(new).await
+        }
+    }
+
+    /// Get a named root pointer from the DHT.
+    fn get_named_root(&self, name: &str) -> impl Future<Output = Result<Option<Cid>>> + Send;
+
+    /// Set a named root pointer in the DHT.
+    fn set_named_root(&self, name: &str, cid: Cid) -> impl Future<Output = Result<()>> + Send;
+
+    /// Remove a named root pointer from the DHT.
+    fn remove_named_root(&self, name: &str) -> impl Future<Output = Result<bool>> + Send;
+
+    /// List all named root pointers from the DHT.
+    fn list_named_roots(&self) -> impl Future<Output = Result<Vec<(String, Cid)>>> + Send;
+
+    /// Publish several blobs, returning their CIDs in order. The default
+    /// publishes them one at a time.
+    fn publish_many(&self, items: &[&[u8]]) -> impl Future<Output = Result<Vec<Cid>>> + Send {
+        async move {
+            let mut cids = Vec::with_capacity(items.len());
+            for data in items {
+                cids.push(self.publish_page(data).await?);
+            }
+            Ok(cids)
+        }
+    }
+
+    /// [`publish_page`](Self::publish_page), encoded and spread as `options`
+    /// ask. The default ignores the options.
+    fn publish_with(&self, data: &[u8], options: &PublishOptions) -> impl Future<Output = Result<Cid>> + Send {
+        let _ = options;
+        self.publish_page(data)
+    }
+
+    /// [`publish_with`](Self::publish_with) for content arriving as
+    /// `chunks`, as bundles are published. The default gathers the chunks
+    /// first; backends that can upload as they arrive should override it,
+    /// so the content is never held whole.
+    fn publish_stream_with(
+        &self,
+        chunks: impl Stream<Item = std::io::Result<Vec<u8>>> + Send,
+        options: &PublishOptions,
+    ) -> impl Future<Output = Result<Cid>> + Send {
+        async move {
+            let data: Vec<u8> = chunks.try_concat().await?;
+            self.publish_with(&data, options).await
+        }
+    }
+
+    /// Where the content `cid` is stored, or `None` if the backend can't tell.
+    fn placement(&self, cid: &Cid) -> impl Future<Output = Result<Option<Placement>>> + Send {
+        let _ = cid;
+        async { Ok(None) }
+    }
+
+    /// Keep the content `cid` from being garbage-collected by the network.
+    /// The default does nothing.
+    fn pin(&self, cid: &Cid) -> impl Future<Output = Result<()>> + Send {
+        let _ = cid;
+        async { Ok(()) }
+    }
+
+    /// Let the network collect the content `cid` again. The default does
+    /// nothing.
+    fn unpin(&self, cid: &Cid) -> impl Future<Output = Result<()>> + Send {
+        let _ = cid;
+        async { Ok(()) }
+    }
+}
+
+/// Sync [`NetworkBackend`] over an [`AsyncNetworkBackend`].
+///
+/// Each call blocks the calling thread until the future completes. Called
+/// from inside a runtime, where blocking on it would panic, the future is
+/// driven from a thread of its own instead; a current-thread runtime
+/// blocked on such a call can't drive it too, so hand
+/// [`with_handle`](Self::with_handle) a multi-thread runtime.
+pub struct BlockingAdapter<A: AsyncNetworkBackend> {
+    inner: A,
+    handle: Handle,
+    /// Owned runtime when created with [`BlockingAdapter::new`].
+    _runtime: Option<Runtime>,
+}
+
+impl<A: AsyncNetworkBackend> BlockingAdapter<A> {
+    /// Wrap `inner`, driving its futures on a dedicated runtime.
+    pub fn new(inner: A) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            inner,
+            handle: runtime.handle().clone(),
+            _runtime: Some(runtime),
+        })
+    }
+
+    /// Wrap `inner`, driving its futures on an existing runtime.
+    pub fn with_handle(inner: A, handle: Handle) -> Self {
+        Self { inner, handle, _runtime: None }
+    }
+
+    /// Access the wrapped async backend.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Run `future` on the runtime until it completes.
+    fn block_on<T: Send>(&self, future: impl Future<Output = T> + Send) -> T {
+        if Handle::try_current().is_err() {
+            return self.handle.block_on(future);
+        }
+        std::thread::scope(|scope| {
+            scope.spawn(move || self.handle.block_on(future))
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+}
+
+/// Read `reader` to the end into `chunks`, stopping early if the other end
+/// hangs up. A read error is passed on as well as returned.
+fn send_chunks(reader: &mut dyn Read, chunks: &mut mpsc::Sender<std::io::Result<Vec<u8>>>) -> Result<()> {
+    loop {
+        let mut chunk = Vec::with_capacity(STREAM_CHUNK_LEN);
+        match (&mut *reader).take(STREAM_CHUNK_LEN as u64).read_to_end(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {
+                if futures::executor::block_on(chunks.send(Ok(chunk))).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => {
+                let passed_on = std::io::Error::new(e.kind(), e.to_string());
+                let _ = futures::executor::block_on(chunks.send(Err(passed_on)));
+                return Err(e.into());
+            }
+        }
+    }
+}
+
+impl<A: AsyncNetworkBackend> NetworkBackend for BlockingAdapter<A> {
+    fn publish_page(&self, data: &[u8]) -> Result<Cid> {
+        self.block_on(self.inner.publish_page(data))
+    }
+
+    fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
+        self.block_on(self.inner.fetch_page(cid))
+    }
+
+    fn supports_range(&self) -> bool {
+        self.inner.supports_range()
+    }
+
+    fn fetch_range(&self, cid: &Cid, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.block_on(self.inner.fetch_range(cid, offset, len))
+    }
+
+    fn get_root(&self) -> Result<Option<Cid>> {
+        self.block_on(self.inner.get_root())
+    }
+
+    fn set_root(&self, cid: Cid) -> Result<()> {
+        self.block_on(self.inner.set_root(cid))
+    }
+
+    fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        self.block_on(self.inner.set_root_if(expected, new))
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.block_on(self.inner.get_named_root(name))
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.block_on(self.inner.set_named_root(name, cid))
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.block_on(self.inner.remove_named_root(name))
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        self.block_on(self.inner.list_named_roots())
+    }
+
+    fn publish_many(&self, items: &[&[u8]]) -> Result<Vec<Cid>> {
+        self.block_on(self.inner.publish_many(items))
+    }
+
+    /// Reads `reader` on the calling thread while the runtime publishes
+    /// what's been read, so only a few chunks are held at once.
+    fn publish_stream_with(&self, reader: &mut dyn Read, options: &PublishOptions) -> Result<Cid> {
+        let (mut chunks, received) = mpsc::channel(STREAM_CHUNKS_QUEUED);
+        std::thread::scope(|scope| {
+            let publish = scope.spawn(move || self.handle.block_on(self.inner.publish_stream_with(received, options)));
+            let read = send_chunks(reader, &mut chunks);
+            drop(chunks);
+            let published = publish.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            read.and(published)
+        })
+    }
+
+    fn placement(&self, cid: &Cid) -> Result<Option<Placement>> {
+        self.block_on(self.inner.placement(cid))
+    }
+
+    fn pin(&self, cid: &Cid) -> Result<()> {
+        self.block_on(self.inner.pin(cid))
+    }
+
+    fn unpin(&self, cid: &Cid) -> Result<()> {
+        self.block_on(self.inner.unpin(cid))
+    }
+}
+
+/// Run the disk-bound `work` on the runtime's blocking threads.
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => result,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(e) => Err(PageStoreError::Storage(format!("blocking task: {}", e))),
+        },
+    }
+}
+
+impl<A: AsyncNetworkBackend + 'static> CraftObjPageStore<BlockingAdapter<A>> {
+    /// [`update_root`](PageStore::update_root) for async callers. Bundling
+    /// and publishing run on the runtime's blocking threads, so fetches
+    /// and other tasks carry on meanwhile.
+    pub async fn update_root_async(self: &Arc<Self>, new_root: Cid) -> Result<()> {
+        let store = Arc::clone(self);
+        blocking(move || store.update_root(new_root)).await
+    }
+
+    /// Fetch several bundles concurrently and unpack them into the local cache.
+    ///
+    /// Bundles already cached are skipped. The base of a delta bundle is
+    /// fetched first if its pages aren't all cached, as the blocking path
+    /// would. Returns the number of `bundle_cids` fetched.
+    pub async fn fetch_bundles(self: &Arc<Self>, bundle_cids: &[Cid]) -> Result<usize> {
+        let mut seen = HashSet::new();
+        let pending: Vec<Cid> = bundle_cids.iter()
+            .copied()
+            .filter(|cid| seen.insert(*cid) && !self.is_cached(cid))
+            .collect();
+
+        let fetches = pending.iter().map(|cid| self.fetch_bundle_once_async(cid, |store| store.is_cached(cid)));
+        futures::future::try_join_all(fetches).await?;
+        Ok(pending.len())
+    }
+
+    /// Fetch and unpack `cid` unless `cached` says another fetch, blocking
+    /// or async, already did: fetches of the same bundle at once wait for
+    /// the first.
+    async fn fetch_bundle_once_async(self: &Arc<Self>, cid: &Cid, cached: impl Fn(&Self) -> bool) -> Result<()> {
+        let flight = self.flight(cid);
+        let result = async {
+            let _fetching = flight.acquire().await;
+            // Another bundle's base, or another caller, may have been this one
+            if cached(self) {
+                return Ok(());
+            }
+            let data = self.fetch_root_blob(cid).await?;
+            self.cache_fetched(cid, data).await
+        }
+        .await;
+        self.land(cid, &flight);
+        result
+    }
+
+    /// Fetch the blob a root points at: a bundle, or a segment manifest.
+    async fn fetch_root_blob(&self, cid: &Cid) -> Result<Vec<u8>> {
+        let started = Instant::now();
+        let data = self.fetch_async(cid).await?;
+        self.stats.record_bundle_fetch(data.len() as u64, started.elapsed());
+        Ok(data)
+    }
+
+    /// Fetch `cid` once the request limit allows, then charge it to the
+    /// download limit. The runtime needs its timer to sleep off a limit.
+    async fn fetch_async(&self, cid: &Cid) -> Result<Vec<u8>> {
+        let data = {
+            let _permit = self.limiter.acquire().await;
+            self.network.inner().fetch_page(cid).await?
+        };
+        if let Some(wait) = self.download.charge(data.len() as u64) {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(data)
+    }
+
+    /// Unpack the fetched root blob `root` of `cid` into the cache, fetching
+    /// its segments and, for a delta bundle, any base pages missing from the
+    /// cache first, so unpacking never reaches for the network.
+    async fn cache_fetched(self: &Arc<Self>, cid: &Cid, root: Vec<u8>) -> Result<()> {
+        let bundle = match SegmentManifest::parse(&root)? {
+            Some(manifest) => Some(self.fetch_segments_async(&manifest).await?),
+            None => None,
+        };
+        if let Some((base, base_table)) = bundle_base(bundle.as_deref().unwrap_or(&root))? {
+            let fetch = self.fetch_bundle_once_async(&base, |store| store.pages_cached(&base_table));
+            Box::pin(fetch).await?;
+        }
+        let store = Arc::clone(self);
+        let cid = *cid;
+        blocking(move || store.cache_bundle(&cid, &root, bundle.as_deref().unwrap_or(&root))).await?;
+        Ok(())
+    }
+
+    /// Fetch and concatenate the segments of a segmented bundle concurrently.
+    async fn fetch_segments_async(&self, manifest: &SegmentManifest) -> Result<Vec<u8>> {
+        let started = Instant::now();
+        let fetches = manifest.segments.iter().map(|(cid, _)| async move {
+            let data = self.fetch_async(cid).await?;
+            let actual = Cid::from_bytes(&data);
+            if actual != *cid {
+                return Err(PageStoreError::Corruption { expected: *cid, actual });
+            }
+            Ok(data)
+        });
+        let bundle = futures::future::try_join_all(fetches).await?.concat();
+        self.stats.record_fetch_bytes(bundle.len() as u64, started.elapsed());
+        Ok(bundle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockNetworkBackend;
+    use craftsql_core::{Page, PageTable};
+    use crate::RateLimit;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    /// Async wrapper sharing a [`MockNetworkBackend`] between stores, with
+    /// the lengths of the chunks streamed to it.
+    #[derive(Clone)]
+    struct MockAsyncBackend(Arc<MockNetworkBackend>, Arc<InFlight>, Arc<Mutex<Vec<usize>>>);
+
+    /// Fetches running at once, and the most seen.
+    #[derive(Default)]
+    struct InFlight {
+        now: AtomicU64,
+        max: AtomicU64,
+    }
+
+    fn mock_async(mock: &Arc<MockNetworkBackend>) -> MockAsyncBackend {
+        MockAsyncBackend(mock.clone(), Arc::default(), Arc::default())
+    }
+
+    impl AsyncNetworkBackend for MockAsyncBackend {
+        async fn publish_page(&self, data: &[u8]) -> Result<Cid> {
+            self.0.publish_page(data)
+        }
+
+        async fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
+            let now = self.1.now.fetch_add(1, Ordering::SeqCst) + 1;
+            self.1.max.fetch_max(now, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            self.1.now.fetch_sub(1, Ordering::SeqCst);
+            self.0.fetch_page(cid)
+        }
+
+        async fn get_root(&self) -> Result<Option<Cid>> {
+            self.0.get_root()
+        }
+
+        async fn set_root(&self, cid: Cid) -> Result<()> {
+            self.0.set_root(cid)
+        }
+
+        async fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+            self.0.set_root_if(expected, new)
+        }
+
+        async fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+            self.0.get_named_root(name)
+        }
+
+        async fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+            self.0.set_named_root(name, cid)
+        }
+
+        async fn remove_named_root(&self, name: &str) -> Result<bool> {
+            self.0.remove_named_root(name)
+        }
+
+        async fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+            self.0.list_named_roots()
+        }
+
+        async fn publish_many(&self, items: &[&[u8]]) -> Result<Vec<Cid>> {
+            self.0.publish_many(items)
+        }
+
+        async fn publish_with(&self, data: &[u8], options: &PublishOptions) -> Result<Cid> {
+            self.0.publish_stream_with(&mut &data[..], options)
+        }
+
+        async fn publish_stream_with(
+            &self,
+            chunks: impl Stream<Item = std::io::Result<Vec<u8>>> + Send,
+            options: &PublishOptions,
+        ) -> Result<Cid> {
+            let chunks: Vec<Vec<u8>> = chunks.try_collect().await?;
+            self.2.lock().unwrap().extend(chunks.iter().map(Vec::len));
+            self.0.publish_stream_with(&mut &chunks.concat()[..], options)
+        }
+
+        async fn placement(&self, cid: &Cid) -> Result<Option<Placement>> {
+            self.0.placement(cid)
+        }
+
+        async fn pin(&self, cid: &Cid) -> Result<()> {
+            self.0.pin(cid)
+        }
+
+        async fn unpin(&self, cid: &Cid) -> Result<()> {
+            self.0.unpin(cid)
+        }
+    }
+
+    /// Commit a one-page database containing `byte`, returns the data page CID.
+    fn commit_page<N: NetworkBackend>(store: &CraftObjPageStore<N>, byte: u8) -> Cid {
+        let cid = store.put(&Page { data: vec![byte; 4096] }).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, cid);
+        let pt_data = pt.to_bytes();
+        let pt_cid = Cid::from_bytes(&pt_data);
+        store.put(&Page { data: pt_data }).unwrap();
+        store.update_root(pt_cid).unwrap();
+        cid
+    }
+
+    #[test]
+    fn test_blocking_adapter_publishes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mock = Arc::new(MockNetworkBackend::new());
+        let adapter = BlockingAdapter::new(mock_async(&mock)).unwrap();
+        let store = CraftObjPageStore::new(tmp.path(), adapter).unwrap();
+
+        commit_page(&store, 1);
+        assert_eq!(mock.publish_count.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert!(store.current_root().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_blocking_adapter_forwards_publishing_and_pins() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mock = Arc::new(MockNetworkBackend::new());
+        let adapter = BlockingAdapter::new(mock_async(&mock)).unwrap();
+        let options = PublishOptions::default().with_replication(2);
+        let store = CraftObjPageStore::new(tmp.path(), adapter).unwrap().with_publish_options(options);
+
+        // Bundles go out with the options, and report where they went
+        commit_page(&store, 1);
+        let bundle = store.current_root().unwrap().unwrap();
+        assert_eq!(mock.publish_options(&bundle), Some(options));
+        assert_eq!(store.bundle_placement(&bundle).unwrap().unwrap().providers, ["mock-0", "mock-1"]);
+
+        // A ref pins its bundle, and pruning it once unreferenced unpins it
+        store.set_named_root("snapshot", bundle).unwrap();
+        assert!(mock.is_pinned(&bundle));
+        commit_page(&store, 2);
+        store.remove_named_root("snapshot").unwrap();
+        store.prune_bundles(1).unwrap();
+        assert!(!mock.is_pinned(&bundle));
+
+        // Page-by-page publishes are batched
+        let store = store.with_page_publish_threshold(1 << 20);
+        commit_page(&store, 3);
+        assert_eq!(mock.batch_count.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_fetch_bundles_concurrently() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mock = Arc::new(MockNetworkBackend::new());
+
+        // Writer publishes two versions
+        let tmp = tempfile::tempdir().unwrap();
+        let writer = CraftObjPageStore::new(
+            tmp.path(),
+            BlockingAdapter::with_handle(mock_async(&mock), runtime.handle().clone()),
+        ).unwrap();
+        let page1 = commit_page(&writer, 1);
+        let page2 = commit_page(&writer, 2);
+        let bundles = writer.bundles().unwrap();
+
+        // Reader with an empty cache pulls both bundles at once
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = Arc::new(CraftObjPageStore::new(
+            tmp2.path(),
+            BlockingAdapter::with_handle(mock_async(&mock), runtime.handle().clone()),
+        ).unwrap());
+        let fetched = runtime.block_on(reader.fetch_bundles(&bundles)).unwrap();
+        assert_eq!(fetched, 2);
+        assert!(reader.is_cached(&page1));
+        assert!(reader.is_cached(&page2));
+
+        // Already cached — nothing to do
+        assert_eq!(runtime.block_on(reader.fetch_bundles(&bundles)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_fetch_delta_bundles() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mock = Arc::new(MockNetworkBackend::new());
+        let adapter = || BlockingAdapter::with_handle(mock_async(&mock), runtime.handle().clone());
+
+        // Two versions sharing a page, the second a delta against the first
+        let tmp = tempfile::tempdir().unwrap();
+        let writer = CraftObjPageStore::new(tmp.path(), adapter()).unwrap().with_delta_bundles(2);
+        let shared = writer.put(&Page { data: vec![7; 4096] }).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, shared);
+        for byte in [1, 2] {
+            pt.set(1, writer.put(&Page { data: vec![byte; 4096] }).unwrap());
+            let pt_cid = writer.put(&Page { data: pt.to_bytes() }).unwrap();
+            writer.update_root(pt_cid).unwrap();
+        }
+        let delta = writer.current_root().unwrap().unwrap();
+        assert!(bundle_base(&mock.fetch_page(&delta).unwrap()).unwrap().is_some());
+
+        // Asked for the delta alone, a cold reader fetches its base too
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = Arc::new(CraftObjPageStore::new(tmp2.path(), adapter()).unwrap());
+        assert_eq!(runtime.block_on(reader.fetch_bundles(&[delta])).unwrap(), 1);
+        assert_eq!(reader.stats.snapshot().bundles_fetched, 2);
+        for i in 0..2 {
+            assert!(reader.is_cached(pt.get(i).unwrap()));
+        }
+
+        // With a base page gone from the cache, the base is fetched again
+        // rather than unpacking reaching for it, even inside a task
+        reader.local().remove(&shared).unwrap();
+        reader.local().remove(&delta).unwrap();
+        let task = Arc::clone(&reader);
+        let fetched = runtime.block_on(runtime.spawn(async move { task.fetch_bundles(&[delta]).await }));
+        assert_eq!(fetched.unwrap().unwrap(), 1);
+        assert_eq!(reader.stats.snapshot().bundles_fetched, 4);
+        assert!(reader.is_cached(&shared));
+    }
+
+    #[test]
+    fn test_fetch_bundles_limited() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mock = Arc::new(MockNetworkBackend::new());
+        let tmp = tempfile::tempdir().unwrap();
+        let writer = CraftObjPageStore::new(
+            tmp.path(),
+            BlockingAdapter::with_handle(mock_async(&mock), runtime.handle().clone()),
+        ).unwrap();
+        for i in 0..3 {
+            commit_page(&writer, i);
+        }
+        let bundles = writer.bundles().unwrap();
+        let bytes: usize = bundles.iter().map(|cid| mock.fetch_page(cid).unwrap().len()).sum();
+
+        // One request at a time, and a download limit owing half a second
+        let backend = mock_async(&mock);
+        let in_flight = backend.1.clone();
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = Arc::new(CraftObjPageStore::new(
+            tmp2.path(),
+            BlockingAdapter::with_handle(backend, runtime.handle().clone()),
+        ).unwrap()
+            .with_max_concurrent_requests(1)
+            .with_download_limit(RateLimit::new(bytes as u64 * 2 / 3)));
+
+        // Two callers after the same bundles fetch each once
+        let fetches = mock.fetch_count.load(Ordering::SeqCst);
+        let started = Instant::now();
+        let (a, b) = runtime.block_on(async {
+            futures::join!(reader.fetch_bundles(&bundles), reader.fetch_bundles(&bundles))
+        });
+        a.unwrap();
+        b.unwrap();
+        assert!(started.elapsed() >= std::time::Duration::from_millis(400));
+        assert_eq!(in_flight.max.load(Ordering::SeqCst), 1);
+        assert_eq!(mock.fetch_count.load(Ordering::SeqCst) - fetches, bundles.len() as u64);
+        assert_eq!(reader.stats.snapshot().bundles_fetched, bundles.len() as u64);
+    }
+
+    #[test]
+    fn test_publish_streams_in_chunks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mock = Arc::new(MockNetworkBackend::new());
+        let backend = mock_async(&mock);
+        let chunks = backend.2.clone();
+        let store = CraftObjPageStore::new(tmp.path(), BlockingAdapter::new(backend).unwrap()).unwrap();
+
+        let mut pt = PageTable::new();
+        for i in 0..100 {
+            pt.set(i, store.put(&Page { data: vec![i as u8; 4096] }).unwrap());
+        }
+        let pt_cid = store.put(&Page { data: pt.to_bytes() }).unwrap();
+        store.update_root(pt_cid).unwrap();
+
+        // The bundle went out a chunk at a time, never whole
+        let bundle = mock.fetch_page(&store.current_root().unwrap().unwrap()).unwrap();
+        let chunks = chunks.lock().unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|&len| len <= STREAM_CHUNK_LEN));
+        assert_eq!(chunks.iter().sum::<usize>(), bundle.len());
+    }
+
+    #[test]
+    fn test_sync_calls_inside_runtime() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mock = Arc::new(MockNetworkBackend::new());
+        let tmp = tempfile::tempdir().unwrap();
+        let writer = CraftObjPageStore::new(
+            tmp.path(),
+            BlockingAdapter::with_handle(mock_async(&mock), runtime.handle().clone()),
+        ).unwrap();
+        let page = commit_page(&writer, 1);
+
+        // A task using the blocking API misses and fetches without panicking
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = CraftObjPageStore::new(
+            tmp2.path(),
+            BlockingAdapter::with_handle(mock_async(&mock), runtime.handle().clone()),
+        ).unwrap();
+        let data = runtime.block_on(runtime.spawn(async move { reader.get(&page).map(|page| page.data) }));
+        assert_eq!(data.unwrap().unwrap(), vec![1; 4096]);
+    }
+
+    #[test]
+    fn test_publish_overlaps_fetch() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mock = Arc::new(MockNetworkBackend::new());
+        let adapter = || BlockingAdapter::with_handle(mock_async(&mock), runtime.handle().clone());
+        let tmp = tempfile::tempdir().unwrap();
+        let writer = Arc::new(CraftObjPageStore::new(tmp.path(), adapter()).unwrap());
+        let old = commit_page(&writer, 1);
+        let bundles = writer.bundles().unwrap();
+
+        let page = writer.put(&Page { data: vec![2; 4096] }).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, page);
+        let pt_cid = writer.put(&Page { data: pt.to_bytes() }).unwrap();
+
+        // One task publishes while another fetches
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = Arc::new(CraftObjPageStore::new(tmp2.path(), adapter()).unwrap());
+        let (published, fetched) = runtime.block_on(async {
+            futures::join!(writer.update_root_async(pt_cid), reader.fetch_bundles(&bundles))
+        });
+        published.unwrap();
+        assert_eq!(fetched.unwrap(), 1);
+        assert!(reader.is_cached(&old));
+        assert_eq!(writer.bundles().unwrap().len(), 2);
+        assert_eq!(mock.get_root().unwrap(), writer.current_root().unwrap());
+    }
+}
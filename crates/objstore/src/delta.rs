@@ -0,0 +1,63 @@
+//! Page deltas: a new version of a page compressed against its old version.
+//!
+//! SQLite rewrites whole pages to change a few bytes of them, so consecutive
+//! versions of a page number are nearly identical. Compressing the new
+//! version with zstd, using the old one as a raw-content prefix, leaves
+//! little more than the changed bytes.
+
+use craftsql_core::{PageStoreError, Result};
+use zstd::zstd_safe::{self, CCtx, DCtx};
+
+fn zstd_error(context: &str, code: usize) -> PageStoreError {
+    PageStoreError::Storage(format!("{}: {}", context, zstd_safe::get_error_name(code)))
+}
+
+/// Compress `page` against `base`.
+pub(crate) fn encode(base: &[u8], page: &[u8]) -> Result<Vec<u8>> {
+    let mut cctx = CCtx::create();
+    // A prefix is always taken as raw content, unlike a dictionary, which
+    // a page that happens to start with the dictionary magic would confuse
+    cctx.ref_prefix(base).map_err(|code| zstd_error("encode page delta", code))?;
+    let mut out = Vec::with_capacity(zstd_safe::compress_bound(page.len()));
+    cctx.compress2(&mut out, page).map_err(|code| zstd_error("encode page delta", code))?;
+    Ok(out)
+}
+
+/// Rebuild a `page_size` page from `delta` and the `base` it was encoded against.
+pub(crate) fn decode(base: &[u8], delta: &[u8], page_size: usize) -> Result<Vec<u8>> {
+    let mut dctx = DCtx::create();
+    dctx.ref_prefix(base).map_err(|code| zstd_error("decode page delta", code))?;
+    let mut page = Vec::with_capacity(page_size);
+    dctx.decompress(&mut page, delta).map_err(|code| zstd_error("decode page delta", code))?;
+    if page.len() != page_size {
+        return Err(PageStoreError::Storage(format!(
+            "page delta decodes to {} bytes, not {}", page.len(), page_size
+        )));
+    }
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let base: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut page = base.clone();
+        page[100..108].copy_from_slice(b"changed!");
+        page[4000] ^= 0xFF;
+
+        let delta = encode(&base, &page).unwrap();
+        assert!(delta.len() < 100, "{} byte delta", delta.len());
+        assert_eq!(decode(&base, &delta, 4096).unwrap(), page);
+
+        // A page starting with the zstd dictionary magic is still plain content
+        let mut magic = base.clone();
+        magic[..4].copy_from_slice(&0xEC30A437u32.to_le_bytes());
+        assert_eq!(decode(&magic, &encode(&magic, &page).unwrap(), 4096).unwrap(), page);
+
+        assert!(decode(&base, &delta, 512).is_err());
+        assert!(decode(&base, b"not a delta", 4096).is_err());
+    }
+}
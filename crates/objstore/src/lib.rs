@@ -1,7 +1,10 @@
 //! CraftOBJ-backed PageStore — bridges CraftSQL to the CraftOBJ P2P network.
 //!
-//! Local-first: disk cache under a configurable directory. Pages are immutable
-//! CIDs cached forever once fetched. Root pointer is fetched/published via DHT.
+//! Local-first: disk cache under a configurable directory, laid out as a
+//! [`LocalPageStore`]. Pages are immutable CIDs cached forever once fetched.
+//! Root pointer is fetched/published via DHT. The cache keeps a
+//! [`CidFilter`](craftsql_store_local::CidFilter), so `has()` answers for
+//! most pages it lacks without a filesystem stat.
 //!
 //! ## Page Bundling
 //!
@@ -12,7 +15,17 @@
 //! - `update_root()` **bundles ALL pages** into a single blob, publishes as one
 //!   CraftOBJ content, and stores the CraftOBJ CID as the root pointer
 //! - `get()` on cache miss fetches the **entire bundle** from the root CID,
-//!   unpacks all pages into local cache, then serves from cache
+//!   unpacks all pages into local cache, then serves from cache. Backends that
+//!   [support byte ranges](NetworkBackend::supports_range) instead read just
+//!   the bundle header and the requested page.
+//!
+//! Small databases can opt out of bundling with
+//! [`CraftObjPageStore::with_page_publish_threshold`]: pages and the page table
+//! are then published as individual objects, and the root is the page table CID.
+//!
+//! Bundles larger than [`CraftObjPageStore::with_segment_size`] are published
+//! as several segments plus a manifest listing them; the root is then the
+//! manifest CID, and readers fetch the segments in parallel and reassemble.
 //!
 //! Bundle format:
 //! ```text
@@ -20,19 +33,81 @@
 //! [version: u16 LE]
 //! [page_size: u32 LE]
 //! [page_count: u32 LE]
-//! [page_table: bincode-serialized PageTable]
+//! [page_table: serialized PageTable]
 //! [page_table_len: u32 LE]  (length of serialized page table)
 //! [page data: page_count × page_size bytes]
 //! ```
 //!
+//! With [`CraftObjPageStore::with_delta_bundles`], bundles after the first
+//! are version 2: each page is stored against the page of the same number in
+//! the previously published bundle, which readers fetch too if they need it.
+//! The page table and its length are followed by
+//! ```text
+//! [base_bundle: 32 bytes CID]
+//! [base_page_table: 32 bytes CID]
+//! [depth: u16 LE]  (delta bundles since the last full one, this one included)
+//! [page_count × (tag: u8, then for tag 1 page_size bytes, for tag 3 len: u32 LE + len bytes)]
+//! ```
+//! where tag 0 is an empty slot, 1 a whole page, 2 the base's page unchanged,
+//! and 3 a zstd delta against the base's page. Pages land in the cache whole.
+//!
 //! Network operations are abstracted behind [`NetworkBackend`] so the real
-//! CraftOBJ client can be wired in later, while tests use a mock.
+//! CraftOBJ client can be wired in later, while tests use a mock. With the
+//! `async` feature, async clients implement `AsyncNetworkBackend` and plug in
+//! through `BlockingAdapter`; [`MirroredBackend`] adds mirrors and failover
+//! behind a single backend.
+//!
+//! [`CraftObjPageStore::with_publish_options`] sets how CraftOBJ erasure-codes
+//! and replicates published bundles, and
+//! [`CraftObjPageStore::bundle_placement`] reports where each one ended up and
+//! whether it can still be rebuilt (see [`placement`]).
+//!
+//! A bundle a named root points at, such as a snapshot, is
+//! [pinned](NetworkBackend::pin) on the network, with its segments and the
+//! bundles it's a delta against, so the network doesn't collect what the
+//! snapshot needs. [`CraftObjPageStore::prune_bundles`] unpins pruned
+//! bundles no root needs any more.
+//!
+//! [`CraftObjPageStore::begin_root_update`] groups a publish with the named
+//! roots that go with it, such as a commit and the branch pointing at it:
+//! the refs only follow if the root moves, and any that then fail to are
+//! reported by name.
+//!
+//! Uploads and downloads can be capped with [`CraftObjPageStore::with_upload_limit`]
+//! and [`CraftObjPageStore::with_download_limit`], so a background sync leaves
+//! room on the link for everything else.
+
+#[cfg(feature = "async")]
+mod async_backend;
+mod delta;
+mod limit;
+mod mirror;
+pub mod placement;
+mod progress;
+mod segment;
+mod throttle;
+mod update;
+
+#[cfg(feature = "async")]
+pub use async_backend::{AsyncNetworkBackend, BlockingAdapter};
+pub use mirror::MirroredBackend;
+pub use placement::{Placement, PublishOptions, SegmentHealth};
+pub use progress::{Progress, ProgressObserver, TransferStage};
+pub use throttle::{RateLimit, Throttled};
+pub use update::{RootUpdate, RootUpdateError};
+
+use limit::Limiter;
+use progress::{ProgressReader, ProgressWriter};
+use segment::{fetch_segment, SegmentManifest};
 
 use craftsql_core::{Cid, Page, PageStore, PageStoreError, PageTable, Result};
-use std::collections::HashMap;
+use craftsql_store_local::{sanitize_ref_name, LocalPageStore, PageData};
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{atomic::{AtomicU64, Ordering}, Mutex};
+use std::sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 // ---------------------------------------------------------------------------
 // NetworkBackend trait
@@ -53,6 +128,19 @@ pub trait NetworkBackend: Send + Sync {
     /// Set the root pointer CID in the DHT.
     fn set_root(&self, cid: Cid) -> Result<()>;
 
+    /// Set the root pointer only if it currently equals `expected`, failing
+    /// with [`PageStoreError::RootConflict`] otherwise.
+    ///
+    /// The default is a non-atomic read-compare-write; backends with a native
+    /// compare-and-swap should override it.
+    fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        let actual = self.get_root()?;
+        if actual != expected {
+            return Err(PageStoreError::RootConflict { expected, actual });
+        }
+        self.set_root(new)
+    }
+
     /// Get a named root pointer from the DHT.
     fn get_named_root(&self, name: &str) -> Result<Option<Cid>>;
 
@@ -64,6 +152,154 @@ pub trait NetworkBackend: Send + Sync {
 
     /// List all named root pointers from the DHT.
     fn list_named_roots(&self) -> Result<Vec<(String, Cid)>>;
+
+    /// Whether [`fetch_range`](Self::fetch_range) is served natively rather than
+    /// by fetching the whole content.
+    fn supports_range(&self) -> bool {
+        false
+    }
+
+    /// Fetch up to `len` bytes of content starting at `offset`.
+    ///
+    /// The range is clamped to the end of the content. The default fetches the
+    /// whole content and slices it.
+    fn fetch_range(&self, cid: &Cid, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let data = self.fetch_page(cid)?;
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(len as usize).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    /// Publish content streamed from `reader`, returns its CID.
+    ///
+    /// The default buffers the stream and calls [`publish_page`](Self::publish_page);
+    /// backends that can stream should override it.
+    fn publish_stream(&self, reader: &mut dyn Read) -> Result<Cid> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        self.publish_page(&data)
+    }
+
+    /// Fetch content by CID, streaming it into `writer`.
+    ///
+    /// The default buffers via [`fetch_page`](Self::fetch_page).
+    fn fetch_stream(&self, cid: &Cid, writer: &mut dyn Write) -> Result<()> {
+        let data = self.fetch_page(cid)?;
+        writer.write_all(&data)?;
+        Ok(())
+    }
+
+    /// Publish several blobs, returning their CIDs in order.
+    ///
+    /// The default publishes them one at a time; backends with a batch call
+    /// should override it to save round trips.
+    fn publish_many(&self, items: &[&[u8]]) -> Result<Vec<Cid>> {
+        items.iter().map(|data| self.publish_page(data)).collect()
+    }
+
+    /// [`publish_stream`](Self::publish_stream), encoded and spread as
+    /// `options` ask.
+    ///
+    /// The default ignores the options, for backends without erasure coding.
+    fn publish_stream_with(&self, reader: &mut dyn Read, options: &PublishOptions) -> Result<Cid> {
+        let _ = options;
+        self.publish_stream(reader)
+    }
+
+    /// Where the content `cid` is stored, or `None` if the backend can't tell.
+    fn placement(&self, cid: &Cid) -> Result<Option<Placement>> {
+        let _ = cid;
+        Ok(None)
+    }
+
+    /// Keep the content `cid` from being garbage-collected by the network.
+    ///
+    /// The default does nothing, for backends that never collect content.
+    fn pin(&self, cid: &Cid) -> Result<()> {
+        let _ = cid;
+        Ok(())
+    }
+
+    /// Let the network collect the content `cid` again. Unpinning content
+    /// that isn't pinned is not an error.
+    fn unpin(&self, cid: &Cid) -> Result<()> {
+        let _ = cid;
+        Ok(())
+    }
+}
+
+impl<T: NetworkBackend + ?Sized> NetworkBackend for Arc<T> {
+    fn publish_page(&self, data: &[u8]) -> Result<Cid> {
+        (**self).publish_page(data)
+    }
+
+    fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
+        (**self).fetch_page(cid)
+    }
+
+    fn get_root(&self) -> Result<Option<Cid>> {
+        (**self).get_root()
+    }
+
+    fn set_root(&self, cid: Cid) -> Result<()> {
+        (**self).set_root(cid)
+    }
+
+    fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        (**self).set_root_if(expected, new)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        (**self).get_named_root(name)
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        (**self).set_named_root(name, cid)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        (**self).remove_named_root(name)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        (**self).list_named_roots()
+    }
+
+    fn supports_range(&self) -> bool {
+        (**self).supports_range()
+    }
+
+    fn fetch_range(&self, cid: &Cid, offset: u64, len: u64) -> Result<Vec<u8>> {
+        (**self).fetch_range(cid, offset, len)
+    }
+
+    fn publish_stream(&self, reader: &mut dyn Read) -> Result<Cid> {
+        (**self).publish_stream(reader)
+    }
+
+    fn fetch_stream(&self, cid: &Cid, writer: &mut dyn Write) -> Result<()> {
+        (**self).fetch_stream(cid, writer)
+    }
+
+    fn publish_many(&self, items: &[&[u8]]) -> Result<Vec<Cid>> {
+        (**self).publish_many(items)
+    }
+
+    fn publish_stream_with(&self, reader: &mut dyn Read, options: &PublishOptions) -> Result<Cid> {
+        (**self).publish_stream_with(reader, options)
+    }
+
+    fn placement(&self, cid: &Cid) -> Result<Option<Placement>> {
+        (**self).placement(cid)
+    }
+
+    fn pin(&self, cid: &Cid) -> Result<()> {
+        (**self).pin(cid)
+    }
+
+    fn unpin(&self, cid: &Cid) -> Result<()> {
+        (**self).unpin(cid)
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -74,6 +310,49 @@ pub trait NetworkBackend: Send + Sync {
 pub struct CacheStats {
     pub hits: AtomicU64,
     pub misses: AtomicU64,
+    /// Bytes sent to the network by publishes.
+    pub bytes_published: AtomicU64,
+    /// Bytes received from the network, bundles and single pages alike.
+    pub bytes_fetched: AtomicU64,
+    pub bundles_published: AtomicU64,
+    pub bundles_fetched: AtomicU64,
+    /// Individual pages published in page-by-page mode.
+    pub pages_published: AtomicU64,
+    /// Individual pages fetched outside a full bundle (range or direct reads).
+    pub pages_fetched: AtomicU64,
+    /// Total time spent in publish calls, in microseconds.
+    pub publish_micros: AtomicU64,
+    /// Total time spent in fetch calls, in microseconds.
+    pub fetch_micros: AtomicU64,
+}
+
+/// Point-in-time copy of [`CacheStats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStatsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub bytes_published: u64,
+    pub bytes_fetched: u64,
+    pub bundles_published: u64,
+    pub bundles_fetched: u64,
+    pub pages_published: u64,
+    pub pages_fetched: u64,
+    pub publish_time: Duration,
+    pub fetch_time: Duration,
+}
+
+impl CacheStatsSnapshot {
+    /// Mean time per publish (bundle or page), if any happened.
+    pub fn avg_publish_latency(&self) -> Option<Duration> {
+        let publishes = self.bundles_published + self.pages_published;
+        (publishes > 0).then(|| self.publish_time / publishes as u32)
+    }
+
+    /// Mean time per fetch (bundle or page), if any happened.
+    pub fn avg_fetch_latency(&self) -> Option<Duration> {
+        let fetches = self.bundles_fetched + self.pages_fetched;
+        (fetches > 0).then(|| self.fetch_time / fetches as u32)
+    }
 }
 
 impl CacheStats {
@@ -81,637 +360,2656 @@ impl CacheStats {
         Self {
             hits: AtomicU64::new(0),
             misses: AtomicU64::new(0),
+            bytes_published: AtomicU64::new(0),
+            bytes_fetched: AtomicU64::new(0),
+            bundles_published: AtomicU64::new(0),
+            bundles_fetched: AtomicU64::new(0),
+            pages_published: AtomicU64::new(0),
+            pages_fetched: AtomicU64::new(0),
+            publish_micros: AtomicU64::new(0),
+            fetch_micros: AtomicU64::new(0),
         }
     }
+
+    /// Copy the current counters.
+    pub fn snapshot(&self) -> CacheStatsSnapshot {
+        CacheStatsSnapshot {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            bytes_published: self.bytes_published.load(Ordering::Relaxed),
+            bytes_fetched: self.bytes_fetched.load(Ordering::Relaxed),
+            bundles_published: self.bundles_published.load(Ordering::Relaxed),
+            bundles_fetched: self.bundles_fetched.load(Ordering::Relaxed),
+            pages_published: self.pages_published.load(Ordering::Relaxed),
+            pages_fetched: self.pages_fetched.load(Ordering::Relaxed),
+            publish_time: Duration::from_micros(self.publish_micros.load(Ordering::Relaxed)),
+            fetch_time: Duration::from_micros(self.fetch_micros.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn record_publish(&self, bytes: u64, elapsed: Duration) {
+        self.bundles_published.fetch_add(1, Ordering::Relaxed);
+        self.record_publish_bytes(bytes, elapsed);
+    }
+
+    fn record_pages_publish(&self, pages: u64, bytes: u64, elapsed: Duration) {
+        self.pages_published.fetch_add(pages, Ordering::Relaxed);
+        self.record_publish_bytes(bytes, elapsed);
+    }
+
+    fn record_publish_bytes(&self, bytes: u64, elapsed: Duration) {
+        self.bytes_published.fetch_add(bytes, Ordering::Relaxed);
+        self.publish_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn record_bundle_fetch(&self, bytes: u64, elapsed: Duration) {
+        self.bundles_fetched.fetch_add(1, Ordering::Relaxed);
+        self.record_fetch_bytes(bytes, elapsed);
+    }
+
+    fn record_page_fetch(&self, bytes: u64, elapsed: Duration) {
+        self.pages_fetched.fetch_add(1, Ordering::Relaxed);
+        self.record_fetch_bytes(bytes, elapsed);
+    }
+
+    /// Bytes and time of a fetch that isn't counted as a bundle or page itself
+    /// (e.g. reading a bundle header).
+    fn record_fetch_bytes(&self, bytes: u64, elapsed: Duration) {
+        self.bytes_fetched.fetch_add(bytes, Ordering::Relaxed);
+        self.fetch_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
 }
 
 /// Bundle magic bytes.
 const BUNDLE_MAGIC: &[u8; 4] = b"CSQL";
 /// Bundle format version.
 const BUNDLE_VERSION: u16 = 1;
+/// Format version of bundles holding pages as deltas against a base bundle.
+const DELTA_BUNDLE_VERSION: u16 = 2;
+
+/// Page slot tags in a delta bundle.
+const SLOT_EMPTY: u8 = 0;
+const SLOT_FULL: u8 = 1;
+const SLOT_SAME: u8 = 2;
+const SLOT_DELTA: u8 = 3;
+
+/// Disambiguates scratch files created by concurrent operations.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Size of the fixed bundle header: magic + version + page_size + page_count.
+const BUNDLE_HEADER_LEN: u64 = 14;
+
+/// Pages per [`NetworkBackend::publish_many`] call when publishing page-by-page.
+const PUBLISH_BATCH_PAGES: usize = 64;
+
+/// Segments fetched at once when reassembling a segmented bundle.
+const SEGMENT_FETCH_PARALLELISM: usize = 4;
+
+/// Network calls a [`CraftObjPageStore`] makes at once unless configured
+/// with [`CraftObjPageStore::with_max_concurrent_requests`].
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// The bundle a delta bundle is encoded against.
+#[derive(Debug, Clone, Copy)]
+struct DeltaBase {
+    bundle: Cid,
+    page_table: Cid,
+    /// Delta bundles since the last full one; 0 for a full bundle.
+    depth: u16,
+}
+
+/// The base of a delta bundle being unbundled, fetched only once a page
+/// is missing from the cache.
+struct BaseBundle {
+    bundle: Cid,
+    page_table_cid: Cid,
+    page_table: Option<PageTable>,
+    fetched: bool,
+}
+
+impl BaseBundle {
+    fn new(bundle: Cid, page_table_cid: Cid) -> Self {
+        Self { bundle, page_table_cid, page_table: None, fetched: false }
+    }
+
+    /// Page `i` of the base, fetching the base bundle if it isn't cached.
+    fn page<N: NetworkBackend>(&mut self, store: &CraftObjPageStore<N>, i: usize) -> Result<Vec<u8>> {
+        loop {
+            if self.page_table.is_none() {
+                if let Ok(page) = store.local.get(&self.page_table_cid) {
+                    self.page_table = Some(PageTable::from_bytes(&page.data)
+                        .map_err(|e| PageStoreError::Storage(format!("parse page table: {}", e)))?);
+                }
+            }
+            let page = self.page_table.as_ref()
+                .and_then(|table| table.get(i))
+                .and_then(|cid| store.local.get(cid).ok());
+            match page {
+                Some(page) => return Ok(page.data),
+                None if !self.fetched => {
+                    self.fetched = true;
+                    store.fetch_and_unbundle(&self.bundle)?;
+                }
+                None => {
+                    return Err(PageStoreError::Storage(format!(
+                        "page {} missing from base bundle {}", i, self.bundle
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Where pages live inside a remote bundle, for byte-range reads.
+struct BundleLayout {
+    page_table: PageTable,
+    page_size: u64,
+    /// Offset of page 0's data within the bundle.
+    data_offset: u64,
+}
+
+/// Reader that keeps a copy of everything read through it.
+struct Recorder<R> {
+    inner: R,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// The base bundle of `bundle` and its page table, if it's a delta bundle,
+/// from its header.
+#[cfg(feature = "async")]
+pub(crate) fn bundle_base(bundle: &[u8]) -> Result<Option<(Cid, Cid)>> {
+    let version = bundle.get(4..6).map(|v| u16::from_le_bytes([v[0], v[1]]));
+    if bundle.get(0..4) != Some(&BUNDLE_MAGIC[..]) || version != Some(DELTA_BUNDLE_VERSION) {
+        return Ok(None);
+    }
+    let truncated = || PageStoreError::Storage("bundle truncated".into());
+    let mut reader = bundle.get(BUNDLE_HEADER_LEN as usize..).ok_or_else(truncated)?;
+    PageTable::from_reader(&mut reader)
+        .map_err(|e| PageStoreError::Storage(format!("parse page table: {}", e)))?;
+    let base = reader.get(4..68).ok_or_else(truncated)?;
+    let cid = |bytes: &[u8]| Cid(bytes.try_into().unwrap());
+    Ok(Some((cid(&base[..32]), cid(&base[32..]))))
+}
 
 /// CraftOBJ-backed PageStore with local disk cache.
 ///
 /// Pages are cached locally and only published as a bundle on `update_root()`.
+/// The cache is a [`LocalPageStore`]; bundle bookkeeping lives alongside it.
 pub struct CraftObjPageStore<N: NetworkBackend> {
-    cache_dir: PathBuf,
+    local: LocalPageStore,
     network: N,
     pub stats: CacheStats,
+    /// CID of the last bundle published (or found as the local root), used to
+    /// skip re-uploading an identical bundle.
+    last_published: Mutex<Option<Cid>>,
+    /// Serializes read-modify-write cycles on the bundle index file.
+    bundle_index: Mutex<()>,
+    /// Layout of the last bundle read by range, so its header is fetched once.
+    /// Shared out rather than read under the lock, which no fetch holds.
+    range_layout: Mutex<Option<(Cid, Option<Arc<BundleLayout>>)>>,
+    progress: Option<Box<dyn ProgressObserver>>,
+    /// Databases up to this many bytes are published page-by-page instead of
+    /// as a bundle (None = always bundle).
+    page_publish_threshold: Option<u64>,
+    /// Bundles larger than this are published in segments (None = never split).
+    segment_size: Option<u64>,
+    /// How the network should encode and spread bundles.
+    publish_options: PublishOptions,
+    /// Pages already published individually, so page mode only uploads new ones.
+    published_pages: Mutex<HashSet<Cid>>,
+    /// Caps the network calls in flight across all threads.
+    limiter: Limiter,
+    /// Bundles being fetched; a thread missing on one waits on its lock
+    /// rather than fetching it again.
+    fetching: Mutex<HashMap<Cid, Arc<Limiter>>>,
+    upload: RateLimit,
+    download: RateLimit,
+    /// Delta bundles published in a row before a full one (0 = never delta).
+    max_delta_chain: u16,
+    /// The last bundle this store published, which the next one may be
+    /// encoded against.
+    delta_base: Mutex<Option<DeltaBase>>,
 }
 
 impl<N: NetworkBackend> CraftObjPageStore<N> {
     /// Create a new store. `cache_dir` is the local disk cache directory.
     pub fn new(cache_dir: &Path, network: N) -> Result<Self> {
-        fs::create_dir_all(cache_dir.join("pages"))?;
+        let local = LocalPageStore::new(cache_dir)?.with_cid_filter()?;
+        let last_published = local.current_root().unwrap_or(None);
+        let published_pages = fs::read_to_string(cache_dir.join("published"))
+            .map(|list| list.lines().filter_map(|l| Self::parse_cid_hex(l).ok()).collect())
+            .unwrap_or_default();
         Ok(Self {
-            cache_dir: cache_dir.to_path_buf(),
+            local,
             network,
             stats: CacheStats::new(),
+            last_published: Mutex::new(last_published),
+            bundle_index: Mutex::new(()),
+            range_layout: Mutex::new(None),
+            progress: None,
+            page_publish_threshold: None,
+            segment_size: None,
+            publish_options: PublishOptions::default(),
+            published_pages: Mutex::new(published_pages),
+            limiter: Limiter::new(DEFAULT_MAX_CONCURRENT_REQUESTS),
+            fetching: Mutex::new(HashMap::new()),
+            upload: RateLimit::unlimited(),
+            download: RateLimit::unlimited(),
+            max_delta_chain: 0,
+            delta_base: Mutex::new(None),
         })
     }
 
-    fn page_path(&self, cid: &Cid) -> PathBuf {
-        self.cache_dir.join("pages").join(hex::encode(cid.0))
+    /// Publish databases of at most `bytes` page-by-page (each page plus the
+    /// page table as separate objects) rather than as one bundle.
+    ///
+    /// Suits small state databases where a commit touches a handful of pages:
+    /// only pages not published before are uploaded. Larger databases keep
+    /// using bundles. The mode is chosen per commit.
+    pub fn with_page_publish_threshold(mut self, bytes: u64) -> Self {
+        self.page_publish_threshold = Some(bytes);
+        self
     }
 
-    fn root_path(&self) -> PathBuf {
-        self.cache_dir.join("root")
+    /// Split bundles larger than `bytes` into segments of at most `bytes`,
+    /// for networks that cap the size of a single published object.
+    pub fn with_segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = Some(bytes.max(1));
+        self
     }
 
-    fn refs_dir(&self) -> PathBuf {
-        self.cache_dir.join("refs")
+    /// Have the network encode and spread bundles as `options` ask, to tune
+    /// how many peers can be lost before a bundle is. Applies to segments
+    /// and their manifests too, but not to page-by-page publishing.
+    pub fn with_publish_options(mut self, options: PublishOptions) -> Self {
+        self.publish_options = options;
+        self
     }
 
-    fn ref_path(&self, name: &str) -> PathBuf {
-        let safe: String = name.chars()
-            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
-            .collect();
-        self.refs_dir().join(safe)
+    /// Publish bundles as deltas against the previous one: each page is
+    /// stored as unchanged, as a zstd delta against the same page number in
+    /// the previous bundle, or whole, whichever is smallest.
+    ///
+    /// A cold reader fetches the chain back to the last full bundle, so a
+    /// full one is published again after `max_chain` deltas in a row, as is
+    /// the first bundle after the store is opened.
+    pub fn with_delta_bundles(mut self, max_chain: u16) -> Self {
+        self.max_delta_chain = max_chain;
+        self
     }
 
-    fn read_cid_file(path: &Path) -> Result<Option<Cid>> {
-        if !path.exists() {
-            return Ok(None);
-        }
-        let hex_str = fs::read_to_string(path)?;
-        let bytes = hex::decode(hex_str.trim())
-            .map_err(|e| PageStoreError::Storage(e.to_string()))?;
-        if bytes.len() != 32 {
-            return Err(PageStoreError::Storage("invalid CID length".into()));
-        }
-        let mut cid = [0u8; 32];
-        cid.copy_from_slice(&bytes);
-        Ok(Some(Cid(cid)))
+    /// Make at most `max` network calls at once, across all threads; the
+    /// rest wait. Defaults to [`DEFAULT_MAX_CONCURRENT_REQUESTS`].
+    pub fn with_max_concurrent_requests(mut self, max: usize) -> Self {
+        self.limiter = Limiter::new(max);
+        self
     }
 
-    /// Check if a page is cached locally.
-    pub fn is_cached(&self, cid: &Cid) -> bool {
-        self.page_path(cid).exists()
+    /// Cap publishing at `limit`. Keep a clone to change the rate later.
+    pub fn with_upload_limit(mut self, limit: RateLimit) -> Self {
+        self.upload = limit;
+        self
     }
 
-    /// Access the network backend.
-    pub fn network(&self) -> &N {
-        &self.network
+    /// Cap fetching at `limit`. Keep a clone to change the rate later.
+    pub fn with_download_limit(mut self, limit: RateLimit) -> Self {
+        self.download = limit;
+        self
     }
 
-    /// Bundle all pages referenced by the given page table into a single blob.
-    ///
-    /// All pages must be in the local cache. Returns the bundle bytes.
-    fn bundle_pages(&self, page_table: &PageTable, page_size: u32) -> Result<Vec<u8>> {
-        let page_count = page_table.len() as u32;
-        let pt_bytes = page_table.to_bytes();
-        let pt_len = pt_bytes.len() as u32;
+    /// Report bundling, publish, fetch, and unbundle progress to `observer`.
+    pub fn with_progress(mut self, observer: impl ProgressObserver + 'static) -> Self {
+        self.progress = Some(Box::new(observer));
+        self
+    }
 
-        // Header: magic(4) + version(2) + page_size(4) + page_count(4) + pt_bytes + pt_len(4)
-        let header_size = 4 + 2 + 4 + 4 + pt_bytes.len() + 4;
-        let total_size = header_size + (page_count as usize) * (page_size as usize);
-        let mut buf = Vec::with_capacity(total_size);
+    /// Make a network call, once the concurrency limit allows.
+    fn net<T>(&self, call: impl FnOnce(&N) -> T) -> T {
+        self.limiter.run(|| call(&self.network))
+    }
 
-        buf.extend_from_slice(BUNDLE_MAGIC);
-        buf.extend_from_slice(&BUNDLE_VERSION.to_le_bytes());
-        buf.extend_from_slice(&page_size.to_le_bytes());
-        buf.extend_from_slice(&page_count.to_le_bytes());
-        buf.extend_from_slice(&pt_bytes);
-        buf.extend_from_slice(&pt_len.to_le_bytes());
+    fn report(&self, progress: Progress) {
+        if let Some(observer) = &self.progress {
+            observer.on_progress(&progress);
+        }
+    }
 
-        // Append page data in order
-        for i in 0..page_count as usize {
-            if let Some(cid) = page_table.get(i) {
-                let path = self.page_path(cid);
-                let data = fs::read(&path).map_err(|e| {
-                    PageStoreError::Storage(format!("read cached page {}: {}", cid, e))
-                })?;
-                buf.extend_from_slice(&data);
-                // Pad to page_size if shorter
-                if data.len() < page_size as usize {
-                    buf.resize(buf.len() + page_size as usize - data.len(), 0);
-                }
-            } else {
-                // Empty page slot — write zeros
-                buf.extend(std::iter::repeat(0u8).take(page_size as usize));
+    /// The local cache, shared in layout with [`LocalPageStore`].
+    pub fn local(&self) -> &LocalPageStore {
+        &self.local
+    }
+
+    fn tombstones_dir(&self) -> PathBuf {
+        self.local.dir().join("tombstones")
+    }
+
+    /// Marker for a named root deleted locally but not yet on the network.
+    /// Holds the unsanitized name so the deletion can be replayed.
+    fn tombstone_path(&self, name: &str) -> PathBuf {
+        self.tombstones_dir().join(sanitize_ref_name(name))
+    }
+
+    /// Replay pending named-root deletions to the network, clearing each
+    /// tombstone once the network confirms. Failures leave it for next time.
+    /// Called after a named-root write reaches the network, so reads never
+    /// pay for it.
+    fn flush_tombstones(&self) {
+        let Ok(entries) = fs::read_dir(self.tombstones_dir()) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let Ok(name) = fs::read_to_string(entry.path()) else {
+                continue;
+            };
+            if self.net(|network| network.remove_named_root(&name)).is_ok() {
+                let _ = fs::remove_file(entry.path());
             }
         }
+    }
 
-        Ok(buf)
+    fn bundles_path(&self) -> PathBuf {
+        self.local.dir().join("bundles")
     }
 
-    /// Unbundle a blob into individual pages, caching them locally.
-    /// Returns the PageTable from the bundle.
-    fn unbundle_pages(&self, data: &[u8]) -> Result<PageTable> {
-        if data.len() < 18 {
-            return Err(PageStoreError::Storage("bundle too small".into()));
-        }
-        if &data[0..4] != BUNDLE_MAGIC {
-            return Err(PageStoreError::Storage("invalid bundle magic".into()));
-        }
-        let version = u16::from_le_bytes([data[4], data[5]]);
-        if version != BUNDLE_VERSION {
-            return Err(PageStoreError::Storage(format!("unsupported bundle version {}", version)));
-        }
-        let page_size = u32::from_le_bytes([data[6], data[7], data[8], data[9]]) as usize;
-        let page_count = u32::from_le_bytes([data[10], data[11], data[12], data[13]]) as usize;
-
-        // Read page table: it's right after the fixed header (offset 14), and its length
-        // is stored as u32 LE right after the page table bytes.
-        // We need to figure out where the page table ends. The pt_len is stored at
-        // offset 14 + pt_len. We can find it by computing: total = header + pages
-        // header = 14 + pt_len + 4, so pt_len = data.len() - 14 - 4 - page_count * page_size
-        let pages_total = page_count * page_size;
-        if data.len() < 18 + pages_total {
-            return Err(PageStoreError::Storage("bundle data too short".into()));
-        }
-        let pt_len_offset = data.len() - pages_total - 4;
-        let pt_len = u32::from_le_bytes([
-            data[pt_len_offset], data[pt_len_offset + 1],
-            data[pt_len_offset + 2], data[pt_len_offset + 3],
-        ]) as usize;
-
-        let pt_start = 14;
-        let pt_end = pt_start + pt_len;
-        if pt_end > pt_len_offset {
-            return Err(PageStoreError::Storage("invalid page table length".into()));
+    fn parse_cid_hex(hex_str: &str) -> Result<Cid> {
+        let bytes = hex::decode(hex_str.trim())
+            .map_err(|e| PageStoreError::Storage(e.to_string()))?;
+        if bytes.len() != 32 {
+            return Err(PageStoreError::Storage("invalid CID length".into()));
         }
+        let mut cid = [0u8; 32];
+        cid.copy_from_slice(&bytes);
+        Ok(Cid(cid))
+    }
 
-        let page_table = PageTable::from_bytes(&data[pt_start..pt_end])
-            .map_err(|e| PageStoreError::Storage(format!("parse page table: {}", e)))?;
-
-        // Extract and cache each page
-        let pages_start = pt_len_offset + 4;
-        for i in 0..page_count {
-            let offset = pages_start + i * page_size;
-            let end = offset + page_size;
-            if end > data.len() {
-                return Err(PageStoreError::Storage("bundle truncated".into()));
-            }
-            let page_data = &data[offset..end];
-            let cid = Cid::from_bytes(page_data);
-            let path = self.page_path(&cid);
-            if !path.exists() {
-                fs::write(&path, page_data)?;
+    /// Read the bundle index: `(bundle_cid, page_table_cid)`, oldest first.
+    fn read_bundle_index(&self) -> Result<Vec<(Cid, Cid)>> {
+        let path = self.bundles_path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for line in fs::read_to_string(&path)?.lines() {
+            if let Some((bundle, pt)) = line.split_once(' ') {
+                entries.push((Self::parse_cid_hex(bundle)?, Self::parse_cid_hex(pt)?));
             }
         }
+        Ok(entries)
+    }
 
-        // Also cache the page table itself as a page (for VFS compatibility)
-        let pt_data = page_table.to_bytes();
-        let pt_cid = Cid::from_bytes(&pt_data);
-        let pt_path = self.page_path(&pt_cid);
-        if !pt_path.exists() {
-            fs::write(&pt_path, &pt_data)?;
+    fn write_bundle_index(&self, entries: &[(Cid, Cid)]) -> Result<()> {
+        let mut out = String::new();
+        for (bundle, pt) in entries {
+            out.push_str(&format!("{} {}\n", hex::encode(bundle.0), hex::encode(pt.0)));
         }
-
-        Ok(page_table)
+        fs::write(self.bundles_path(), out)?;
+        Ok(())
     }
 
-    /// Fetch the bundle from the network using the root CID, unbundle into cache.
-    /// The root CID points to the bundle content in CraftOBJ.
-    fn fetch_and_unbundle(&self, bundle_cid: &Cid) -> Result<PageTable> {
-        let data = self.network.fetch_page(bundle_cid)?;
-        self.unbundle_pages(&data)
+    /// Record a bundle as the most recently seen one.
+    fn record_bundle(&self, bundle_cid: Cid, page_table_cid: Cid) -> Result<()> {
+        let _guard = self.bundle_index.lock().unwrap();
+        let mut entries = self.read_bundle_index()?;
+        entries.retain(|(b, _)| *b != bundle_cid);
+        entries.push((bundle_cid, page_table_cid));
+        self.write_bundle_index(&entries)
     }
-}
 
-impl<N: NetworkBackend> PageStore for CraftObjPageStore<N> {
-    fn get(&self, cid: &Cid) -> Result<Page> {
-        let path = self.page_path(cid);
+    fn delta_bases_path(&self) -> PathBuf {
+        self.local.dir().join("delta-bases")
+    }
 
-        // Local cache hit
-        if let Ok(data) = fs::read(&path) {
-            self.stats.hits.fetch_add(1, Ordering::Relaxed);
-            return Ok(Page { data });
+    /// Record that the bundle with page table `page_table` is a delta
+    /// against bundle `base`, which it can't be rebuilt without.
+    fn record_delta_base(&self, page_table: Cid, base: Cid) -> Result<()> {
+        if self.read_delta_bases()?.get(&page_table) == Some(&base) {
+            return Ok(());
         }
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(self.delta_bases_path())?;
+        writeln!(file, "{} {}", hex::encode(page_table.0), hex::encode(base.0))?;
+        Ok(())
+    }
 
-        // Cache miss → try fetching the whole bundle from root CID.
-        // Individual pages aren't published to CraftOBJ; only bundles are.
-        self.stats.misses.fetch_add(1, Ordering::Relaxed);
-
-        // Try to get the root (bundle CID) and unbundle everything
-        if let Ok(Some(root_cid)) = self.current_root() {
-            // Check if we already have the bundle cached as a page
-            if !self.page_path(&root_cid).exists() {
-                // Fetch bundle from network and unpack all pages into cache
-                let _ = self.fetch_and_unbundle(&root_cid);
+    /// The base bundle of each delta bundle seen, by page table.
+    fn read_delta_bases(&self) -> Result<HashMap<Cid, Cid>> {
+        let path = self.delta_bases_path();
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+        let mut bases = HashMap::new();
+        for line in fs::read_to_string(&path)?.lines() {
+            if let Some((pt, base)) = line.split_once(' ') {
+                bases.insert(Self::parse_cid_hex(pt)?, Self::parse_cid_hex(base)?);
             }
         }
+        Ok(bases)
+    }
 
-        // Retry from cache after unbundling
-        let path = self.page_path(cid);
-        if let Ok(data) = fs::read(&path) {
-            return Ok(Page { data });
+    /// The manifest of `bundle`, if it's a segmented bundle and cached.
+    fn cached_manifest(&self, bundle: &Cid) -> Result<Option<SegmentManifest>> {
+        let Ok(mut file) = self.local.open_page(bundle) else {
+            return Ok(None);
+        };
+        let mut magic = [0u8; 4];
+        if file.read_exact(&mut magic).is_err() || &magic == BUNDLE_MAGIC {
+            return Ok(None);
         }
+        let mut data = magic.to_vec();
+        file.read_to_end(&mut data)?;
+        SegmentManifest::parse(&data)
+    }
 
-        // Last resort: try direct network fetch (for backwards compat / non-bundled pages)
-        match self.network.fetch_page(cid) {
-            Ok(data) => {
-                let actual = Cid::from_bytes(&data);
-                if actual != *cid {
-                    return Err(PageStoreError::Storage(format!(
-                        "CID mismatch: expected {}, got {}", cid, actual
-                    )));
-                }
-                fs::write(&path, &data)?;
-                Ok(Page { data })
+    /// The network objects it takes to rebuild `bundle`: the bundle, or its
+    /// manifest and segments, and the same for each bundle it's a delta
+    /// against, back to a full one.
+    fn bundle_objects(&self, bundle: Cid) -> Result<Vec<Cid>> {
+        let index = self.read_bundle_index()?;
+        let bases = self.read_delta_bases()?;
+        let mut objects = Vec::new();
+        let mut next = Some(bundle);
+        while let Some(bundle) = next.filter(|bundle| !objects.contains(bundle)) {
+            objects.push(bundle);
+            if let Some(manifest) = self.cached_manifest(&bundle)? {
+                objects.extend(manifest.segments.into_iter().map(|(segment, _)| segment));
             }
-            Err(e) => Err(e),
+            next = index.iter()
+                .find(|(b, _)| *b == bundle)
+                .and_then(|(_, pt)| bases.get(pt))
+                .copied();
         }
+        Ok(objects)
     }
 
-    fn put(&self, page: &Page) -> Result<Cid> {
-        let cid = Cid::from_bytes(&page.data);
-        let path = self.page_path(&cid);
+    /// Bundle CIDs this store has published or fetched, oldest first.
+    pub fn bundles(&self) -> Result<Vec<Cid>> {
+        Ok(self.read_bundle_index()?.into_iter().map(|(b, _)| b).collect())
+    }
 
-        // Write to local cache only — no network publish.
-        // Pages are bundled and published as a single CraftOBJ content on update_root().
-        if !path.exists() {
-            fs::write(&path, &page.data)?;
+    /// Drop all but the `keep_recent` most recent bundles from the cache.
+    ///
+    /// Removes the cached blobs of pruned bundles along with their page tables
+    /// and pages, unless still reachable from a retained bundle, the current
+    /// root, or a local named root. Returns the number of files removed.
+    ///
+    /// Pruned bundles none of those need to be rebuilt are also unpinned on
+    /// the network, as far as it's reachable; failures are only logged.
+    pub fn prune_bundles(&self, keep_recent: usize) -> Result<usize> {
+        let _guard = self.bundle_index.lock().unwrap();
+        let entries = self.read_bundle_index()?;
+        let split = entries.len().saturating_sub(keep_recent);
+        let (dropped, retained) = entries.split_at(split);
+
+        let mut roots: Vec<Cid> = retained.iter().map(|(b, _)| *b).collect();
+        roots.extend(self.local.current_root()?);
+        roots.extend(self.local.list_named_roots()?.into_iter().map(|(_, cid)| cid));
+
+        // A root is either a bundle CID from the index or a page table CID.
+        let mut reachable = HashSet::new();
+        for &root in &roots {
+            let pt_cid = entries.iter()
+                .find(|(b, _)| *b == root)
+                .map(|(_, pt)| *pt)
+                .unwrap_or(root);
+            reachable.insert(root);
+            reachable.extend(self.page_table_closure(&pt_cid));
         }
 
-        Ok(cid)
-    }
-
-    fn current_root(&self) -> Result<Option<Cid>> {
-        // Try network first for freshness
-        match self.network.get_root() {
-            Ok(Some(cid)) => {
-                // Cache locally
-                let _ = fs::write(self.root_path(), hex::encode(cid.0));
-                Ok(Some(cid))
+        // Unpin before the manifests listing segments are removed
+        let mut needed = HashSet::new();
+        for root in &roots {
+            if entries.iter().any(|(b, _)| b == root) {
+                needed.extend(self.bundle_objects(*root)?);
             }
-            Ok(None) => {
-                // Fall back to local cache
-                Self::read_cid_file(&self.root_path())
+        }
+        for (bundle, _) in dropped {
+            for object in self.bundle_objects(*bundle)? {
+                if needed.contains(&object) {
+                    continue;
+                }
+                if let Err(e) = self.net(|network| network.unpin(&object)) {
+                    tracing::warn!(cid = %object, error = %e, "failed to unpin pruned bundle");
+                }
             }
-            Err(_) => {
-                // Network error, fall back to local
-                Self::read_cid_file(&self.root_path())
+        }
+
+        let mut removed = 0;
+        for (bundle, pt) in dropped {
+            let mut candidates = self.page_table_closure(pt);
+            candidates.insert(*bundle);
+            for cid in candidates.difference(&reachable) {
+                if self.local.remove(cid)? {
+                    removed += 1;
+                }
             }
         }
+
+        self.write_bundle_index(retained)?;
+        Ok(removed)
     }
 
-    fn update_root(&self, new_root: Cid) -> Result<()> {
-        // The new_root CID points to the page table (from VFS sync).
-        // We need to:
-        // 1. Load the page table from local cache
-        // 2. Bundle all pages into a single blob
-        // 3. Publish the bundle as one CraftOBJ content
-        // 4. Store the bundle CID as the root
+    /// A cached page table's CID plus every page CID it references.
+    /// Empty if the page table is not cached or does not parse.
+    fn page_table_closure(&self, pt_cid: &Cid) -> HashSet<Cid> {
+        let mut cids = HashSet::new();
+        let Ok(page) = self.local.get(pt_cid) else {
+            return cids;
+        };
+        if let Ok(page_table) = PageTable::from_bytes(&page.data) {
+            cids.insert(*pt_cid);
+            cids.extend(page_table.entries.iter().flatten().copied());
+        }
+        cids
+    }
 
-        // Read the page table from local cache
-        let pt_path = self.page_path(&new_root);
-        let pt_data = fs::read(&pt_path).map_err(|e| {
-            PageStoreError::Storage(format!("read page table for bundling: {}", e))
+    /// Where bundle `bundle` (as listed by [`bundles`](Self::bundles)) is
+    /// stored and whether it can be rebuilt, or `None` if the network can't
+    /// tell. The placement covers every object rebuilding it takes: a
+    /// segmented bundle's manifest and segments, and the bundles a delta
+    /// bundle is encoded against. For a page-by-page root, only the page
+    /// table's is known.
+    pub fn bundle_placement(&self, bundle: &Cid) -> Result<Option<Placement>> {
+        let mut placement: Option<Placement> = None;
+        for object in self.bundle_objects(*bundle)? {
+            let Some(object) = self.net(|network| network.placement(&object))? else {
+                return Ok(None);
+            };
+            match &mut placement {
+                Some(placement) => placement.merge(object),
+                None => placement = Some(object),
+            }
+        }
+        Ok(placement)
+    }
+
+    /// [`bundle_placement`](Self::bundle_placement) of every bundle, oldest
+    /// first.
+    pub fn bundle_placements(&self) -> Result<Vec<(Cid, Option<Placement>)>> {
+        self.bundles()?.into_iter()
+            .map(|bundle| Ok((bundle, self.bundle_placement(&bundle)?)))
+            .collect()
+    }
+
+    /// Check if a page is cached locally.
+    pub fn is_cached(&self, cid: &Cid) -> bool {
+        self.local.contains(cid)
+    }
+
+    /// Access the network backend.
+    pub fn network(&self) -> &N {
+        &self.network
+    }
+
+    /// Bundle `page_table` into `tmp` and publish it, returning the bundle CID.
+    ///
+    /// Returns `None` without touching the network if the bundle is identical
+    /// to the last one published (e.g. a sync with no page changes).
+    fn publish_bundle(
+        &self,
+        page_table: &PageTable,
+        pt_bytes: &[u8],
+        page_size: u32,
+        base: Option<&(DeltaBase, PageTable)>,
+        tmp: &Path,
+        last_published: &mut Option<Cid>,
+    ) -> Result<Option<Cid>> {
+        let mut out = BufWriter::new(fs::File::create(tmp)?);
+        self.bundle_pages(page_table, pt_bytes, page_size, base, &mut out)?;
+        out.flush()?;
+        drop(out);
+
+        let size = fs::metadata(tmp)?.len();
+        if let Some(segment_size) = self.segment_size.filter(|&max| size > max) {
+            return self.publish_segmented(tmp, segment_size, last_published);
+        }
+
+        let bundle_cid = Cid::from_reader(fs::File::open(tmp)?)?;
+        if *last_published == Some(bundle_cid) {
+            return Ok(None);
+        }
+
+        // Publish bundle as single CraftOBJ content
+        let file = fs::File::open(tmp)?;
+        let started = Instant::now();
+        let mut reader = ProgressReader::new(BufReader::new(file), |bytes| {
+            self.report(Progress::bytes(TransferStage::Publishing, bytes, Some(size)));
+        });
+        let bundle_cid = self.net(|network| {
+            network.publish_stream_with(&mut self.upload.wrap(&mut reader), &self.publish_options)
         })?;
-        let page_table = PageTable::from_bytes(&pt_data)
-            .map_err(|e| PageStoreError::Storage(format!("parse page table: {}", e)))?;
+        self.stats.record_publish(size, started.elapsed());
+        Ok(Some(bundle_cid))
+    }
 
-        // Detect page size from first page
-        let page_size = if page_table.len() > 0 {
-            if let Some(cid) = page_table.get(0) {
-                let p = self.page_path(cid);
-                fs::read(&p).map(|d| d.len() as u32).unwrap_or(4096)
-            } else {
-                4096
+    /// Publish the bundle in `tmp` as segments of at most `segment_size`, then
+    /// a manifest listing them, returning the manifest CID.
+    ///
+    /// The manifest is derived from the bundle bytes alone, so an unchanged
+    /// bundle is recognized and skipped just like an unsegmented one.
+    fn publish_segmented(
+        &self,
+        tmp: &Path,
+        segment_size: u64,
+        last_published: &Option<Cid>,
+    ) -> Result<Option<Cid>> {
+        let expected = SegmentManifest::from_file(tmp, segment_size)?;
+        if *last_published == Some(Cid::from_bytes(&expected.to_bytes())) {
+            return Ok(None);
+        }
+
+        let size = expected.total_len();
+        let started = Instant::now();
+        let mut file = fs::File::open(tmp)?;
+        let mut segments = Vec::with_capacity(expected.segments.len());
+        let mut done = 0;
+        for &(_, len) in &expected.segments {
+            let mut reader = ProgressReader::new(BufReader::new((&mut file).take(len)), move |bytes| {
+                self.report(Progress::bytes(TransferStage::Publishing, done + bytes, Some(size)));
+            });
+            let segment = self.net(|network| {
+                network.publish_stream_with(&mut self.upload.wrap(&mut reader), &self.publish_options)
+            })?;
+            segments.push((segment, len));
+            done += len;
+        }
+
+        let manifest = SegmentManifest { segments }.to_bytes();
+        self.upload.take(manifest.len() as u64);
+        let manifest_cid = self.net(|network| network.publish_stream_with(&mut &manifest[..], &self.publish_options))?;
+        self.stats.record_publish(size + manifest.len() as u64, started.elapsed());
+        // Cached like a fetched manifest, so its segments can be looked up
+        self.local.put(&Page { data: manifest })?;
+        Ok(Some(manifest_cid))
+    }
+
+    /// Publish every page of `page_table` not yet on the network, then the
+    /// page table itself, returning the page table CID as the new root.
+    ///
+    /// Returns `None` if that page table is already the last published root.
+    fn publish_pages(
+        &self,
+        page_table: &PageTable,
+        pt_cid: Cid,
+        last_published: &Option<Cid>,
+    ) -> Result<Option<Cid>> {
+        if *last_published == Some(pt_cid) {
+            return Ok(None);
+        }
+
+        let mut published = self.published_pages.lock().unwrap();
+        let mut pending: Vec<Cid> = Vec::new();
+        for cid in page_table.entries.iter().flatten().chain(std::iter::once(&pt_cid)) {
+            if !published.contains(cid) && !pending.contains(cid) {
+                pending.push(*cid);
             }
-        } else {
-            4096
-        };
+        }
 
-        // Bundle all pages
-        let bundle = self.bundle_pages(&page_table, page_size)?;
+        let mut log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.local.dir().join("published"))?;
+        let mut done = 0;
+        for batch in pending.chunks(PUBLISH_BATCH_PAGES) {
+            let pages = batch.iter().map(|cid| self.read_cached(cid)).collect::<Result<Vec<_>>>()?;
+            let items: Vec<&[u8]> = pages.iter().map(|p| &p[..]).collect();
+            let bytes = items.iter().map(|p| p.len() as u64).sum();
+            self.upload.take(bytes);
+            let started = Instant::now();
+            self.net(|network| network.publish_many(&items))?;
+            self.stats.record_pages_publish(batch.len() as u64, bytes, started.elapsed());
+
+            for cid in batch {
+                published.insert(*cid);
+                writeln!(log, "{}", hex::encode(cid.0))?;
+            }
+            done += batch.len() as u64;
+            self.report(Progress {
+                stage: TransferStage::Publishing,
+                bytes: 0,
+                total_bytes: None,
+                pages: done,
+                total_pages: Some(pending.len() as u64),
+            });
+        }
 
-        // Publish bundle as single CraftOBJ content
-        let bundle_cid = self.network.publish_page(&bundle)?;
+        Ok(Some(pt_cid))
+    }
 
-        // Store bundle CID as root (both local and network)
-        fs::write(self.root_path(), hex::encode(bundle_cid.0))?;
-        self.network.set_root(bundle_cid)?;
+    /// Unique scratch file path inside the cache directory.
+    fn temp_path(&self, tag: &str) -> PathBuf {
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        self.local.dir().join(format!("{}-{}-{}.tmp", tag, std::process::id(), n))
+    }
 
-        Ok(())
+    /// Read a page that must already be in the local cache, mapped rather
+    /// than copied where [`LocalPageStore::read_page`] can.
+    fn read_cached(&self, cid: &Cid) -> Result<PageData> {
+        self.local.read_page(cid)
+            .map_err(|e| PageStoreError::Storage(format!("read cached page {}: {}", cid, e)))
     }
 
-    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
-        fs::create_dir_all(self.refs_dir())?;
-        fs::write(self.ref_path(name), hex::encode(cid.0))?;
-        self.network.set_named_root(name, cid)?;
-        Ok(())
+    /// The last bundle this store published, with its page table, if the
+    /// next bundle should be a delta against it.
+    fn delta_base(&self, last_published: &Option<Cid>) -> Option<(DeltaBase, PageTable)> {
+        let base = (*self.delta_base.lock().unwrap())?;
+        if *last_published != Some(base.bundle) || base.depth >= self.max_delta_chain {
+            return None;
+        }
+        let page_table = PageTable::from_bytes(&self.local.get(&base.page_table).ok()?.data).ok()?;
+        Some((base, page_table))
     }
 
-    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
-        // Try network, fall back to local
-        match self.network.get_named_root(name) {
-            Ok(Some(cid)) => {
-                let _ = fs::create_dir_all(self.refs_dir());
-                let _ = fs::write(self.ref_path(name), hex::encode(cid.0));
-                Ok(Some(cid))
+    /// Stream a bundle of all pages referenced by the given page table into `out`,
+    /// as deltas against `base` if given. `pt_bytes` is the page table as
+    /// stored, so the bundle carries it under the same CID.
+    ///
+    /// All pages must be in the local cache. Only one page is held in memory at a time.
+    fn bundle_pages<W: Write>(
+        &self,
+        page_table: &PageTable,
+        pt_bytes: &[u8],
+        page_size: u32,
+        base: Option<&(DeltaBase, PageTable)>,
+        out: &mut W,
+    ) -> Result<()> {
+        let page_count = page_table.len() as u32;
+        let pt_len = pt_bytes.len() as u32;
+
+        // Header: magic(4) + version(2) + page_size(4) + page_count(4) + pt_bytes + pt_len(4)
+        out.write_all(BUNDLE_MAGIC)?;
+        out.write_all(&base.map_or(BUNDLE_VERSION, |_| DELTA_BUNDLE_VERSION).to_le_bytes())?;
+        out.write_all(&page_size.to_le_bytes())?;
+        out.write_all(&page_count.to_le_bytes())?;
+        out.write_all(pt_bytes)?;
+        out.write_all(&pt_len.to_le_bytes())?;
+        let mut header_len = BUNDLE_HEADER_LEN + pt_bytes.len() as u64 + 4;
+        if let Some((base, _)) = base {
+            out.write_all(&base.bundle.0)?;
+            out.write_all(&base.page_table.0)?;
+            out.write_all(&(base.depth + 1).to_le_bytes())?;
+            header_len += 66;
+        }
+
+        // Append page data in order
+        let total_bytes = header_len + page_count as u64 * page_size as u64;
+        let mut written = header_len;
+        let zeros = vec![0u8; page_size as usize];
+        for i in 0..page_count as usize {
+            let page = page_table.get(i).map(|cid| self.read_cached(cid)).transpose()?;
+            let data = page.as_deref().unwrap_or_default();
+            // Pad to page_size if shorter; an empty page slot is all zeros
+            let padding = &zeros[..zeros.len().saturating_sub(data.len())];
+
+            match base {
+                None => {
+                    out.write_all(data)?;
+                    out.write_all(padding)?;
+                    written += (data.len() + padding.len()) as u64;
+                }
+                Some((_, base_table)) => {
+                    let slot = self.delta_slot(page_table.get(i), base_table.get(i), &[data, padding].concat())?;
+                    out.write_all(&slot)?;
+                    written += slot.len() as u64;
+                }
             }
-            Ok(None) => Self::read_cid_file(&self.ref_path(name)),
-            Err(_) => Self::read_cid_file(&self.ref_path(name)),
+            self.report(Progress {
+                stage: TransferStage::Bundling,
+                bytes: written,
+                total_bytes: base.is_none().then_some(total_bytes),
+                pages: i as u64 + 1,
+                total_pages: Some(page_count as u64),
+            });
         }
+
+        Ok(())
     }
 
-    fn remove_named_root(&self, name: &str) -> Result<bool> {
-        let local_removed = self.ref_path(name).exists() && fs::remove_file(self.ref_path(name)).is_ok();
-        let net_removed = self.network.remove_named_root(name).unwrap_or(false);
-        Ok(local_removed || net_removed)
+    /// Encode one page slot of a delta bundle: `data` is the (padded) page
+    /// at `cid`, and `base` the page of the same number in the base bundle.
+    fn delta_slot(&self, cid: Option<&Cid>, base: Option<&Cid>, data: &[u8]) -> Result<Vec<u8>> {
+        let Some(cid) = cid else {
+            return Ok(vec![SLOT_EMPTY]);
+        };
+        if base == Some(cid) {
+            return Ok(vec![SLOT_SAME]);
+        }
+        // A base page pruned from the cache just means this one goes whole
+        if let Some(base) = base.and_then(|base| self.local.get(base).ok()) {
+            let delta = delta::encode(&base.data, data)?;
+            if delta.len() + 4 < data.len() {
+                let mut slot = Vec::with_capacity(5 + delta.len());
+                slot.push(SLOT_DELTA);
+                slot.extend_from_slice(&(delta.len() as u32).to_le_bytes());
+                slot.extend_from_slice(&delta);
+                return Ok(slot);
+            }
+        }
+        let mut slot = Vec::with_capacity(1 + data.len());
+        slot.push(SLOT_FULL);
+        slot.extend_from_slice(data);
+        Ok(slot)
     }
 
-    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
-        // Prefer network list, fall back to local
-        match self.network.list_named_roots() {
-            Ok(roots) => Ok(roots),
-            Err(_) => {
-                let mut result = Vec::new();
-                if let Ok(entries) = fs::read_dir(self.refs_dir()) {
-                    for entry in entries.flatten() {
-                        if let Some(name) = entry.file_name().to_str() {
-                            if let Ok(Some(cid)) = Self::read_cid_file(&entry.path()) {
-                                result.push((name.to_string(), cid));
-                            }
-                        }
-                    }
+    /// Unbundle a stream into individual pages, caching them locally.
+    /// Returns the PageTable from the bundle and its CID.
+    fn unbundle_pages<R: Read>(&self, reader: &mut R) -> Result<(PageTable, Cid)> {
+        let truncated = |e: std::io::Error| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                PageStoreError::Storage("bundle truncated".into())
+            } else {
+                PageStoreError::Io(e)
+            }
+        };
+
+        let mut header = [0u8; 14];
+        reader.read_exact(&mut header).map_err(truncated)?;
+        if &header[0..4] != BUNDLE_MAGIC {
+            return Err(PageStoreError::Storage("invalid bundle magic".into()));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != BUNDLE_VERSION && version != DELTA_BUNDLE_VERSION {
+            return Err(PageStoreError::Storage(format!("unsupported bundle version {}", version)));
+        }
+        let page_size = u32::from_le_bytes([header[6], header[7], header[8], header[9]]) as usize;
+        let page_count = u32::from_le_bytes([header[10], header[11], header[12], header[13]]) as usize;
+
+        // The page table follows the fixed header and is read exactly, and the
+        // trailing length must agree with what was read. Its bytes are kept as
+        // read: a legacy table would re-serialize under another CID.
+        let mut recorder = Recorder { inner: &mut *reader, bytes: Vec::new() };
+        let page_table = PageTable::from_reader(&mut recorder)
+            .map_err(|e| PageStoreError::Storage(format!("parse page table: {}", e)))?;
+        let pt_data = recorder.bytes;
+        let mut pt_len = [0u8; 4];
+        reader.read_exact(&mut pt_len).map_err(truncated)?;
+        if u32::from_le_bytes(pt_len) as usize != pt_data.len() {
+            return Err(PageStoreError::Storage("invalid page table length".into()));
+        }
+
+        let mut base = None;
+        if version == DELTA_BUNDLE_VERSION {
+            let mut cids = [0u8; 66];
+            reader.read_exact(&mut cids).map_err(truncated)?;
+            let cid = |bytes: &[u8]| Cid(bytes.try_into().unwrap());
+            self.record_delta_base(Cid::from_bytes(&pt_data), cid(&cids[..32]))?;
+            base = Some(BaseBundle::new(cid(&cids[..32]), cid(&cids[32..64])));
+        }
+
+        // Extract and cache each page
+        let mut page_data = vec![0u8; page_size];
+        for i in 0..page_count {
+            match &mut base {
+                None => {
+                    reader.read_exact(&mut page_data).map_err(truncated)?;
+                    self.local.put(&Page { data: page_data.clone() })?;
                 }
-                result.sort_by(|a, b| a.0.cmp(&b.0));
-                Ok(result)
+                Some(base) => self.unbundle_slot(reader, page_size, page_table.get(i), || base.page(self, i))
+                    .map_err(|e| match e {
+                        PageStoreError::Io(e) => truncated(e),
+                        e => e,
+                    })?,
             }
+            self.report(Progress {
+                stage: TransferStage::Unbundling,
+                bytes: (i as u64 + 1) * page_size as u64,
+                total_bytes: Some(page_count as u64 * page_size as u64),
+                pages: i as u64 + 1,
+                total_pages: Some(page_count as u64),
+            });
         }
-    }
-}
 
-// ---------------------------------------------------------------------------
-// Mock NetworkBackend for tests
-// ---------------------------------------------------------------------------
+        // Also cache the page table itself as a page (for VFS compatibility)
+        let pt_cid = self.local.put(&Page { data: pt_data })?;
 
-/// In-memory mock backend for testing.
-pub struct MockNetworkBackend {
-    pages: Mutex<HashMap<Cid, Vec<u8>>>,
-    root: Mutex<Option<Cid>>,
-    named_roots: Mutex<HashMap<String, Cid>>,
-    pub fetch_count: AtomicU64,
-    pub publish_count: AtomicU64,
-}
+        Ok((page_table, pt_cid))
+    }
 
-impl MockNetworkBackend {
-    pub fn new() -> Self {
-        Self {
-            pages: Mutex::new(HashMap::new()),
-            root: Mutex::new(None),
-            named_roots: Mutex::new(HashMap::new()),
-            fetch_count: AtomicU64::new(0),
-            publish_count: AtomicU64::new(0),
+    /// Read one page slot of a delta bundle and cache the page it holds.
+    /// `cid` is the page the slot is for, and `base` produces the base page.
+    fn unbundle_slot<R: Read>(
+        &self,
+        reader: &mut R,
+        page_size: usize,
+        cid: Option<&Cid>,
+        base: impl FnOnce() -> Result<Vec<u8>>,
+    ) -> Result<()> {
+        let mut tag = [0u8];
+        reader.read_exact(&mut tag)?;
+        let data = match tag[0] {
+            SLOT_EMPTY => vec![0; page_size],
+            SLOT_FULL => {
+                let mut data = vec![0; page_size];
+                reader.read_exact(&mut data)?;
+                data
+            }
+            // Nothing to decode if the page is already here
+            SLOT_SAME if cid.is_some_and(|cid| self.local.contains(cid)) => return Ok(()),
+            SLOT_SAME => base()?,
+            SLOT_DELTA => {
+                let mut len = [0u8; 4];
+                reader.read_exact(&mut len)?;
+                let mut delta = vec![0; u32::from_le_bytes(len) as usize];
+                reader.read_exact(&mut delta)?;
+                if cid.is_some_and(|cid| self.local.contains(cid)) {
+                    return Ok(());
+                }
+                delta::decode(&base()?, &delta, page_size)?
+            }
+            tag => return Err(PageStoreError::Storage(format!("invalid bundle page tag {}", tag))),
+        };
+        self.local.put(&Page { data })?;
+        Ok(())
+    }
+
+    /// Fetch the bundle from the network using the root CID, unbundle into cache.
+    /// The root CID points to the bundle content in CraftOBJ.
+    ///
+    /// The bundle is streamed to disk first, so it is never held in memory.
+    fn fetch_and_unbundle(&self, bundle_cid: &Cid) -> Result<PageTable> {
+        let tmp = self.temp_path("fetch");
+        let result = self.fetch_bundle_into(bundle_cid, &tmp);
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
         }
+        result
     }
-}
 
-impl Default for MockNetworkBackend {
-    fn default() -> Self {
-        Self::new()
+    /// [`Self::fetch_and_unbundle`] unless another thread already did:
+    /// threads missing on the same bundle at once wait for the first one's
+    /// fetch instead of each making their own.
+    fn fetch_bundle_once(&self, bundle_cid: &Cid) {
+        let flight = self.flight(bundle_cid);
+        flight.run(|| {
+            if !self.local.contains(bundle_cid) {
+                let _ = self.fetch_and_unbundle(bundle_cid);
+            }
+        });
+        self.land(bundle_cid, &flight);
     }
-}
 
-impl NetworkBackend for MockNetworkBackend {
-    fn publish_page(&self, data: &[u8]) -> Result<Cid> {
-        let cid = Cid::from_bytes(data);
-        self.pages.lock().unwrap().insert(cid, data.to_vec());
-        self.publish_count.fetch_add(1, Ordering::Relaxed);
-        Ok(cid)
+    /// The lock fetches of `bundle_cid` take turns on, shared by threads and
+    /// async tasks alike. Hand it back to [`Self::land`] when done.
+    fn flight(&self, bundle_cid: &Cid) -> Arc<Limiter> {
+        let mut fetching = self.fetching.lock().unwrap();
+        Arc::clone(fetching.entry(*bundle_cid).or_insert_with(|| Arc::new(Limiter::new(1))))
     }
 
-    fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
-        self.fetch_count.fetch_add(1, Ordering::Relaxed);
-        self.pages.lock().unwrap()
-            .get(cid)
-            .cloned()
-            .ok_or(PageStoreError::NotFound(*cid))
+    fn land(&self, bundle_cid: &Cid, flight: &Arc<Limiter>) {
+        // The last one out (the map holds the other reference) clears the entry
+        let mut fetching = self.fetching.lock().unwrap();
+        if Arc::strong_count(flight) == 2 {
+            fetching.remove(bundle_cid);
+        }
     }
 
-    fn get_root(&self) -> Result<Option<Cid>> {
-        Ok(*self.root.lock().unwrap())
+    fn fetch_bundle_into(&self, bundle_cid: &Cid, tmp: &Path) -> Result<PageTable> {
+        let file = fs::File::create(tmp)?;
+        let started = Instant::now();
+        let mut writer = ProgressWriter::new(file, |bytes| {
+            self.report(Progress::bytes(TransferStage::Fetching, bytes, None));
+        });
+        self.net(|network| network.fetch_stream(bundle_cid, &mut self.download.wrap(&mut writer)))?;
+        self.stats.record_bundle_fetch(fs::metadata(tmp)?.len(), started.elapsed());
+        drop(writer);
+
+        // Segmented roots point at a manifest; reassemble the bundle first
+        let mut magic = [0u8; 4];
+        let has_magic = fs::File::open(tmp)?.read_exact(&mut magic).is_ok();
+        if has_magic && &magic != BUNDLE_MAGIC {
+            if let Some(manifest) = SegmentManifest::parse(&fs::read(tmp)?)? {
+                let assembled = self.temp_path("assemble");
+                let result = self.fetch_segments(&manifest, &assembled)
+                    .and_then(|()| self.unbundle_pages(&mut BufReader::new(fs::File::open(&assembled)?)));
+                let _ = fs::remove_file(&assembled);
+                let (page_table, pt_cid) = result?;
+                self.local.insert_file(bundle_cid, tmp)?;
+                self.record_bundle(*bundle_cid, pt_cid)?;
+                return Ok(page_table);
+            }
+        }
+
+        // Page-by-page roots point at a bare page table rather than a bundle
+        if !has_magic || &magic != BUNDLE_MAGIC {
+            let page_table = PageTable::from_bytes(&fs::read(tmp)?)
+                .map_err(|e| PageStoreError::Storage(format!("parse page table: {}", e)))?;
+            self.local.insert_file(bundle_cid, tmp)?;
+            return Ok(page_table);
+        }
+
+        let (page_table, pt_cid) = self.unbundle_pages(&mut BufReader::new(fs::File::open(tmp)?))?;
+
+        // Keep the blob so later misses know this bundle is already unpacked
+        self.local.insert_file(bundle_cid, tmp)?;
+        self.record_bundle(*bundle_cid, pt_cid)?;
+
+        Ok(page_table)
     }
 
-    fn set_root(&self, cid: Cid) -> Result<()> {
-        *self.root.lock().unwrap() = Some(cid);
+    /// Fetch all segments of `manifest` into `out`, a few in parallel.
+    fn fetch_segments(&self, manifest: &SegmentManifest, out: &Path) -> Result<()> {
+        let total = manifest.total_len();
+        fs::File::create(out)?.set_len(total)?;
+
+        let mut jobs = Vec::with_capacity(manifest.segments.len());
+        let mut offset = 0;
+        for &(cid, len) in &manifest.segments {
+            jobs.push((cid, offset, len));
+            offset += len;
+        }
+
+        let started = Instant::now();
+        let (network, limiter, download) = (&self.network, &self.limiter, &self.download);
+        let mut done = 0;
+        for batch in jobs.chunks(SEGMENT_FETCH_PARALLELISM) {
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = batch.iter()
+                    .map(|&(cid, offset, len)| {
+                        scope.spawn(move || limiter.run(|| fetch_segment(network, download, &cid, out, offset, len)))
+                    })
+                    .collect();
+                handles.into_iter()
+                    .map(|handle| handle.join().expect("segment fetch panicked"))
+                    .collect::<Result<Vec<()>>>()
+            })?;
+            done += batch.iter().map(|&(_, _, len)| len).sum::<u64>();
+            self.report(Progress::bytes(TransferStage::Fetching, done, Some(total)));
+        }
+        self.stats.record_fetch_bytes(total, started.elapsed());
         Ok(())
     }
 
-    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
-        Ok(self.named_roots.lock().unwrap().get(name).copied())
+    /// Unbundle an in-memory bundle into the cache and keep the root blob
+    /// (the bundle itself, or the manifest it was assembled from).
+    #[cfg(feature = "async")]
+    fn cache_bundle(&self, root_cid: &Cid, root: &[u8], bundle: &[u8]) -> Result<PageTable> {
+        let (page_table, pt_cid) = self.unbundle_pages(&mut &bundle[..])?;
+        self.local.put(&Page { data: root.to_vec() })?;
+        self.record_bundle(*root_cid, pt_cid)?;
+        Ok(page_table)
     }
 
-    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
-        self.named_roots.lock().unwrap().insert(name.to_string(), cid);
-        Ok(())
+    /// Whether the page table `page_table` and all its pages are cached.
+    #[cfg(feature = "async")]
+    fn pages_cached(&self, page_table: &Cid) -> bool {
+        let Ok(page) = self.local.get(page_table) else {
+            return false;
+        };
+        PageTable::from_bytes(&page.data)
+            .is_ok_and(|table| table.entries.iter().flatten().all(|cid| self.local.contains(cid)))
     }
 
-    fn remove_named_root(&self, name: &str) -> Result<bool> {
-        Ok(self.named_roots.lock().unwrap().remove(name).is_some())
+    /// Read the header and page table of a remote bundle using range fetches.
+    /// `None` for a delta bundle, whose pages are at no fixed offsets.
+    fn fetch_bundle_layout(&self, bundle_cid: &Cid) -> Result<Option<BundleLayout>> {
+        let started = Instant::now();
+        let header = self.net(|network| {
+            network.fetch_range(bundle_cid, 0, BUNDLE_HEADER_LEN + PageTable::PREFIX_LEN as u64)
+        })?;
+        if header.len() < BUNDLE_HEADER_LEN as usize + 8 {
+            return Err(PageStoreError::Storage("bundle too small".into()));
+        }
+        if &header[0..4] != BUNDLE_MAGIC {
+            return Err(PageStoreError::Storage("invalid bundle magic".into()));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version == DELTA_BUNDLE_VERSION {
+            self.stats.record_fetch_bytes(header.len() as u64, started.elapsed());
+            self.download.take(header.len() as u64);
+            return Ok(None);
+        }
+        if version != BUNDLE_VERSION {
+            return Err(PageStoreError::Storage(format!("unsupported bundle version {}", version)));
+        }
+        let page_size = u32::from_le_bytes([header[6], header[7], header[8], header[9]]) as u64;
+
+        // The start of the page table bounds its length
+        let max_pt_len = PageTable::max_serialized_len(&header[BUNDLE_HEADER_LEN as usize..])
+            .ok_or_else(|| PageStoreError::Storage("bundle too small".into()))?
+            .saturating_add(4);
+        let pt_bytes = self.net(|network| network.fetch_range(bundle_cid, BUNDLE_HEADER_LEN, max_pt_len))?;
+        self.stats.record_fetch_bytes((header.len() + pt_bytes.len()) as u64, started.elapsed());
+        self.download.take((header.len() + pt_bytes.len()) as u64);
+
+        let mut reader = &pt_bytes[..];
+        let page_table = PageTable::from_reader(&mut reader)
+            .map_err(|e| PageStoreError::Storage(format!("parse page table: {}", e)))?;
+        let pt_len = pt_bytes.len() - reader.len();
+        let stored_len = pt_bytes.get(pt_len..pt_len + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+        if stored_len != Some(pt_len) {
+            return Err(PageStoreError::Storage("invalid page table length".into()));
+        }
+
+        Ok(Some(BundleLayout {
+            page_table,
+            page_size,
+            data_offset: BUNDLE_HEADER_LEN + pt_len as u64 + 4,
+        }))
     }
 
-    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
-        let roots = self.named_roots.lock().unwrap();
-        let mut result: Vec<_> = roots.iter().map(|(k, &v)| (k.clone(), v)).collect();
-        result.sort_by(|a, b| a.0.cmp(&b.0));
-        Ok(result)
+    /// Fetch a single page out of a remote bundle with a range read.
+    ///
+    /// Returns `None` if the bundle doesn't contain the page, or is a delta
+    /// bundle, which must be fetched whole.
+    fn fetch_page_range(&self, bundle_cid: &Cid, cid: &Cid) -> Result<Option<Vec<u8>>> {
+        let cached = match &*self.range_layout.lock().unwrap() {
+            Some((bundle, layout)) if bundle == bundle_cid => Some(layout.clone()),
+            _ => None,
+        };
+        let layout = match cached {
+            Some(layout) => layout,
+            None => {
+                let layout = self.fetch_bundle_layout(bundle_cid)?.map(Arc::new);
+                *self.range_layout.lock().unwrap() = Some((*bundle_cid, layout.clone()));
+                layout
+            }
+        };
+        let Some(layout) = layout else {
+            return Ok(None);
+        };
+
+        let Some(index) = layout.page_table.entries.iter().position(|e| e.as_ref() == Some(cid)) else {
+            return Ok(None);
+        };
+        let offset = layout.data_offset + index as u64 * layout.page_size;
+        let started = Instant::now();
+        let data = self.net(|network| network.fetch_range(bundle_cid, offset, layout.page_size))?;
+        self.stats.record_page_fetch(data.len() as u64, started.elapsed());
+        self.download.take(data.len() as u64);
+
+        if Cid::from_bytes(&data) != *cid {
+            return Ok(None);
+        }
+        self.local.put(&Page { data: data.clone() })?;
+        Ok(Some(data))
+    }
+
+    /// Begin an update of the root and named roots, applied together when
+    /// the returned [`RootUpdate`] is committed or dropped. Until then other
+    /// root updates through this store wait, and the network root only
+    /// moves if it's still [`base`](RootUpdate::base) by then.
+    pub fn begin_root_update(&self) -> Result<RootUpdate<'_, N>> {
+        RootUpdate::new(self)
+    }
+
+    /// Publish the page table `new_root` points at and move the network
+    /// root from `base` to it, as [`update_root`](PageStore::update_root)
+    /// and [`RootUpdate`] do while holding `last_published`. Returns the
+    /// root: the bundle (or page table) published, or the last one if it
+    /// was already.
+    fn commit_root(&self, new_root: Cid, base: Option<Cid>, last_published: &mut Option<Cid>) -> Result<Option<Cid>> {
+        // The new_root CID points to the page table (from VFS sync).
+        // We need to:
+        // 1. Load the page table from local cache
+        // 2. Bundle all pages into a single blob (or, below the page publish
+        //    threshold, publish new pages and the page table individually)
+        // 3. Publish the bundle as one CraftOBJ content
+        // 4. Store the bundle CID (or page table CID) as the root
+
+        // Read the page table from local cache
+        let pt_data = self.local.get(&new_root).map_err(|e| {
+            PageStoreError::Storage(format!("read page table for bundling: {}", e))
+        })?;
+        let page_table = PageTable::from_bytes(&pt_data.data)
+            .map_err(|e| PageStoreError::Storage(format!("parse page table: {}", e)))?;
+
+        let page_size = page_table.page_size_in(&self.local)?.unwrap_or(4096) as u32;
+
+        // Small databases go page-by-page; otherwise the bundle is streamed
+        // to a scratch file and published as one object.
+        let db_size = page_table.len() as u64 * page_size as u64;
+        let mut depth = None;
+        let mut delta_of = None;
+        let published = if self.page_publish_threshold.is_some_and(|max| db_size <= max) {
+            self.publish_pages(&page_table, new_root, last_published)
+        } else {
+            let base = self.delta_base(last_published);
+            if base.as_ref().is_some_and(|(base, _)| base.page_table == new_root) {
+                return Ok(*last_published);
+            }
+            depth = Some(base.as_ref().map_or(0, |(base, _)| base.depth + 1));
+            delta_of = base.as_ref().map(|(base, _)| base.bundle);
+            let tmp = self.temp_path("bundle");
+            let result = self.publish_bundle(&page_table, &pt_data.data, page_size, base.as_ref(), &tmp, last_published);
+            let _ = fs::remove_file(&tmp);
+            result
+        };
+        let Some(bundle_cid) = published.map_err(|e| e.with_context("update_root", Some(new_root)))? else {
+            return Ok(*last_published);
+        };
+
+        // Advance the network root only from the root this store last saw, so a
+        // concurrent publisher elsewhere surfaces as a conflict rather than
+        // being silently overwritten. Then record it locally.
+        self.net(|network| network.set_root_if(base, bundle_cid))
+            .map_err(|e| e.with_context("update_root", Some(bundle_cid)))?;
+        self.local.update_root(bundle_cid)?;
+        *last_published = Some(bundle_cid);
+        self.record_bundle(bundle_cid, new_root)?;
+        if let Some(base) = delta_of {
+            self.record_delta_base(new_root, base)?;
+        }
+        if let Some(depth) = depth.filter(|_| self.max_delta_chain > 0) {
+            *self.delta_base.lock().unwrap() = Some(DeltaBase { bundle: bundle_cid, page_table: new_root, depth });
+        }
+
+        Ok(Some(bundle_cid))
     }
 }
 
-// ---------------------------------------------------------------------------
-// Tests
-// ---------------------------------------------------------------------------
+impl<N: NetworkBackend> PageStore for CraftObjPageStore<N> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        // Local cache hit
+        if let Ok(page) = self.local.get(cid) {
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(page);
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::Arc;
+        // Cache miss → try fetching the whole bundle from root CID.
+        // Individual pages aren't published to CraftOBJ; only bundles are.
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
 
-    fn make_store(dir: &Path) -> CraftObjPageStore<MockNetworkBackend> {
-        CraftObjPageStore::new(dir, MockNetworkBackend::new()).unwrap()
+        // Try to get the root (bundle CID) and unbundle everything
+        if let Ok(Some(root_cid)) = self.current_root() {
+            // Backends with range support only pull the page that was asked for
+            if self.network.supports_range() && !self.local.contains(&root_cid) {
+                if let Ok(Some(data)) = self.fetch_page_range(&root_cid, cid) {
+                    return Ok(Page { data });
+                }
+            }
+
+            // Fetch the bundle from network and unpack all pages into cache,
+            // unless we already have it cached as a page
+            if !self.local.contains(&root_cid) {
+                self.fetch_bundle_once(&root_cid);
+            }
+        }
+
+        // Retry from cache after unbundling
+        if let Ok(page) = self.local.get(cid) {
+            return Ok(page);
+        }
+
+        // Last resort: try direct network fetch (for backwards compat / non-bundled pages)
+        let started = Instant::now();
+        match self.net(|network| network.fetch_page(cid)) {
+            Ok(data) => {
+                self.stats.record_page_fetch(data.len() as u64, started.elapsed());
+                self.download.take(data.len() as u64);
+                let actual = Cid::from_bytes(&data);
+                if actual != *cid {
+                    return Err(PageStoreError::Corruption { expected: *cid, actual });
+                }
+                let page = Page { data };
+                self.local.put(&page)?;
+                Ok(page)
+            }
+            Err(e) => Err(e.with_context("get", Some(*cid))),
+        }
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        // Write to local cache only — no network publish.
+        // Pages are bundled and published as a single CraftOBJ content on update_root().
+        self.local.put(page)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        // Only the local cache holds individual pages; the network holds bundles
+        Ok(self.local.contains(cid))
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        // Try network first for freshness
+        match self.net(|network| network.get_root()) {
+            Ok(Some(cid)) => {
+                // Cache locally
+                let _ = self.local.update_root(cid);
+                Ok(Some(cid))
+            }
+            Ok(None) => {
+                // Fall back to local cache
+                self.local.current_root()
+            }
+            Err(_) => {
+                // Network error, fall back to local
+                self.local.current_root()
+            }
+        }
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        // Publish under the lock so concurrent commits can't interleave
+        let mut last_published = self.last_published.lock().unwrap();
+        let base = self.local.current_root()?;
+        self.commit_root(new_root, base, &mut last_published).map(drop)
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        // Pin a bundle before a ref points at it, so the network keeps it
+        if self.read_bundle_index()?.iter().any(|(bundle, _)| *bundle == cid) {
+            for object in self.bundle_objects(cid)? {
+                self.net(|network| network.pin(&object))
+                    .map_err(|e| e.with_context("set_named_root", Some(object)))?;
+            }
+        }
+        self.local.set_named_root(name, cid)?;
+        let _ = fs::remove_file(self.tombstone_path(name));
+        self.net(|network| network.set_named_root(name, cid))
+            .map_err(|e| e.with_context("set_named_root", Some(cid)))?;
+        self.flush_tombstones();
+        Ok(())
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        if self.tombstone_path(name).exists() {
+            return Ok(None);
+        }
+
+        // Try network, fall back to local
+        match self.net(|network| network.get_named_root(name)) {
+            Ok(Some(cid)) => {
+                let _ = self.local.set_named_root(name, cid);
+                Ok(Some(cid))
+            }
+            Ok(None) => self.local.get_named_root(name),
+            Err(_) => self.local.get_named_root(name),
+        }
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        let local_removed = self.local.remove_named_root(name)?;
+
+        // Tombstone first so the deletion survives a network failure and is
+        // replayed later, rather than the network copy resurrecting the name.
+        fs::create_dir_all(self.tombstones_dir())?;
+        fs::write(self.tombstone_path(name), name)?;
+        match self.net(|network| network.remove_named_root(name)) {
+            Ok(net_removed) => {
+                let _ = fs::remove_file(self.tombstone_path(name));
+                self.flush_tombstones();
+                Ok(local_removed || net_removed)
+            }
+            Err(_) => Ok(local_removed),
+        }
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        // Union of local and network refs; the network wins on value
+        let mut merged: std::collections::BTreeMap<String, Cid> =
+            self.local.list_named_roots().unwrap_or_default().into_iter().collect();
+        if let Ok(roots) = self.net(|network| network.list_named_roots()) {
+            for (name, cid) in roots {
+                merged.remove(&sanitize_ref_name(&name));
+                merged.insert(name, cid);
+            }
+        }
+
+        Ok(merged.into_iter()
+            .filter(|(name, _)| !self.tombstone_path(name).exists())
+            .collect())
+    }
+
+    /// Pages in the local cache, less those of the bundles in the bundle
+    /// index: roots name bundles, which lead to their pages only through
+    /// the index, and [`prune_bundles`](Self::prune_bundles) drops them.
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        let _guard = self.bundle_index.lock().unwrap();
+        let mut bundled = HashSet::new();
+        for (bundle, pt) in self.read_bundle_index()? {
+            bundled.insert(bundle);
+            bundled.extend(self.page_table_closure(&pt));
+        }
+        let mut pages = self.local.list_pages()?;
+        pages.retain(|(cid, _)| !bundled.contains(cid));
+        Ok(pages)
+    }
+
+    /// Drops a page from the local cache and unpins it on the network, as
+    /// far as it's reachable, so the network may collect it; failures to
+    /// unpin are only logged.
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        let cached = self.local.remove(cid)?;
+        if let Err(e) = self.net(|network| network.unpin(cid)) {
+            tracing::warn!(cid = %cid, error = %e, "failed to unpin deleted page");
+        }
+        Ok(cached)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Mock NetworkBackend for tests
+// ---------------------------------------------------------------------------
+
+/// In-memory mock backend for testing.
+pub struct MockNetworkBackend {
+    pages: Mutex<HashMap<Cid, Vec<u8>>>,
+    root: Mutex<Option<Cid>>,
+    named_roots: Mutex<HashMap<String, Cid>>,
+    range_support: bool,
+    offline: AtomicBool,
+    pub fetch_count: AtomicU64,
+    pub range_fetch_count: AtomicU64,
+    pub publish_count: AtomicU64,
+    /// Number of [`NetworkBackend::publish_many`] calls.
+    pub batch_count: AtomicU64,
+    /// Options each object was last published with.
+    options: Mutex<HashMap<Cid, PublishOptions>>,
+    pinned: Mutex<HashSet<Cid>>,
+}
+
+impl MockNetworkBackend {
+    pub fn new() -> Self {
+        Self {
+            pages: Mutex::new(HashMap::new()),
+            root: Mutex::new(None),
+            named_roots: Mutex::new(HashMap::new()),
+            range_support: false,
+            offline: AtomicBool::new(false),
+            fetch_count: AtomicU64::new(0),
+            range_fetch_count: AtomicU64::new(0),
+            publish_count: AtomicU64::new(0),
+            batch_count: AtomicU64::new(0),
+            options: Mutex::new(HashMap::new()),
+            pinned: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Serve [`NetworkBackend::fetch_range`] natively.
+    pub fn with_range_support(mut self) -> Self {
+        self.range_support = true;
+        self
+    }
+
+    /// Simulate losing (or regaining) the network: while offline every call fails.
+    pub fn set_offline(&self, offline: bool) {
+        self.offline.store(offline, Ordering::Relaxed);
+    }
+
+    /// The options `cid` was last published with.
+    pub fn publish_options(&self, cid: &Cid) -> Option<PublishOptions> {
+        self.options.lock().unwrap().get(cid).copied()
+    }
+
+    pub fn is_pinned(&self, cid: &Cid) -> bool {
+        self.pinned.lock().unwrap().contains(cid)
+    }
+
+    fn check_online(&self) -> Result<()> {
+        if self.offline.load(Ordering::Relaxed) {
+            return Err(PageStoreError::Storage("network unreachable".into()));
+        }
+        Ok(())
+    }
+}
+
+impl Default for MockNetworkBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkBackend for MockNetworkBackend {
+    fn publish_page(&self, data: &[u8]) -> Result<Cid> {
+        self.check_online()?;
+        let cid = Cid::from_bytes(data);
+        self.pages.lock().unwrap().insert(cid, data.to_vec());
+        self.publish_count.fetch_add(1, Ordering::Relaxed);
+        Ok(cid)
+    }
+
+    fn publish_many(&self, items: &[&[u8]]) -> Result<Vec<Cid>> {
+        self.check_online()?;
+        self.batch_count.fetch_add(1, Ordering::Relaxed);
+        items.iter().map(|data| self.publish_page(data)).collect()
+    }
+
+    fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
+        self.check_online()?;
+        self.fetch_count.fetch_add(1, Ordering::Relaxed);
+        self.pages.lock().unwrap()
+            .get(cid)
+            .cloned()
+            .ok_or(PageStoreError::NotFound(*cid))
+    }
+
+    fn supports_range(&self) -> bool {
+        self.range_support
+    }
+
+    fn fetch_range(&self, cid: &Cid, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.check_online()?;
+        self.range_fetch_count.fetch_add(1, Ordering::Relaxed);
+        let pages = self.pages.lock().unwrap();
+        let data = pages.get(cid).ok_or(PageStoreError::NotFound(*cid))?;
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(len as usize).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    fn get_root(&self) -> Result<Option<Cid>> {
+        self.check_online()?;
+        Ok(*self.root.lock().unwrap())
+    }
+
+    fn set_root(&self, cid: Cid) -> Result<()> {
+        self.check_online()?;
+        *self.root.lock().unwrap() = Some(cid);
+        Ok(())
+    }
+
+    fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        self.check_online()?;
+        let mut root = self.root.lock().unwrap();
+        if *root != expected {
+            return Err(PageStoreError::RootConflict { expected, actual: *root });
+        }
+        *root = Some(new);
+        Ok(())
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.check_online()?;
+        Ok(self.named_roots.lock().unwrap().get(name).copied())
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.check_online()?;
+        self.named_roots.lock().unwrap().insert(name.to_string(), cid);
+        Ok(())
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.check_online()?;
+        Ok(self.named_roots.lock().unwrap().remove(name).is_some())
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        self.check_online()?;
+        let roots = self.named_roots.lock().unwrap();
+        let mut result: Vec<_> = roots.iter().map(|(k, &v)| (k.clone(), v)).collect();
+        result.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(result)
+    }
+
+    fn publish_stream_with(&self, reader: &mut dyn Read, options: &PublishOptions) -> Result<Cid> {
+        let cid = self.publish_stream(reader)?;
+        self.options.lock().unwrap().insert(cid, *options);
+        Ok(cid)
+    }
+
+    /// One provider per replica, with each segment rebuilt from any one.
+    fn placement(&self, cid: &Cid) -> Result<Option<Placement>> {
+        self.check_online()?;
+        let len = self.pages.lock().unwrap().get(cid).ok_or(PageStoreError::NotFound(*cid))?.len() as u64;
+        let options = self.publish_options(cid).unwrap_or_default();
+        let replication = options.replication.unwrap_or(1);
+        let segments = len.div_ceil(options.segment_size.unwrap_or(10 << 20)).max(1);
+        Ok(Some(Placement {
+            providers: (0..replication).map(|i| format!("mock-{}", i)).collect(),
+            segments: vec![SegmentHealth { pieces: replication, needed: 1 }; segments as usize],
+        }))
+    }
+
+    fn pin(&self, cid: &Cid) -> Result<()> {
+        self.check_online()?;
+        if !self.pages.lock().unwrap().contains_key(cid) {
+            return Err(PageStoreError::NotFound(*cid));
+        }
+        self.pinned.lock().unwrap().insert(*cid);
+        Ok(())
+    }
+
+    fn unpin(&self, cid: &Cid) -> Result<()> {
+        self.check_online()?;
+        self.pinned.lock().unwrap().remove(cid);
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn make_store(dir: &Path) -> CraftObjPageStore<MockNetworkBackend> {
+        CraftObjPageStore::new(dir, MockNetworkBackend::new()).unwrap()
+    }
+
+    #[test]
+    fn test_read_write_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        let page = Page { data: b"hello craftsql".to_vec() };
+        let cid = store.put(&page).unwrap();
+        let got = store.get(&cid).unwrap();
+        assert_eq!(got.data, page.data);
+    }
+
+    #[test]
+    fn test_cache_hit_no_network_call() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        let page = Page { data: b"cache me".to_vec() };
+        let cid = store.put(&page).unwrap();
+
+        // First read — should be cache hit (put already cached)
+        let _ = store.get(&cid).unwrap();
+        assert_eq!(store.stats.hits.load(Ordering::Relaxed), 1);
+        assert_eq!(store.stats.misses.load(Ordering::Relaxed), 0);
+
+        // Second read — still cache hit
+        let _ = store.get(&cid).unwrap();
+        assert_eq!(store.stats.hits.load(Ordering::Relaxed), 2);
+
+        // Network fetch should not have been called
+        assert_eq!(store.network.fetch_count.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_cache_miss_fetches_bundle_from_network() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        // Put a page locally, update root to create a bundle on network
+        let page = Page { data: vec![0xAB; 4096] };
+        let cid = store.put(&page).unwrap();
+        // Manually create page table and trigger bundling via update_root
+        let mut pt = PageTable::new();
+        pt.set(0, cid);
+        let pt_data = pt.to_bytes();
+        let pt_cid = Cid::from_bytes(&pt_data);
+        let pt_page = Page { data: pt_data };
+        store.put(&pt_page).unwrap();
+        store.update_root(pt_cid).unwrap();
+
+        // Now create a new store pointing at the same network but empty cache
+        let tmp2 = tempfile::tempdir().unwrap();
+        let store2 = make_store(tmp2.path());
+
+        // Copy network state from store to store2
+        let net_pages = store.network.pages.lock().unwrap().clone();
+        *store2.network.pages.lock().unwrap() = net_pages;
+        let root = *store.network.root.lock().unwrap();
+        *store2.network.root.lock().unwrap() = root;
+
+        // First read — cache miss, should fetch bundle and unpack
+        let got = store2.get(&cid).unwrap();
+        assert_eq!(got.data, page.data);
+        assert_eq!(store2.stats.misses.load(Ordering::Relaxed), 1);
+
+        // Second read — cache hit
+        let _ = store2.get(&cid).unwrap();
+        assert_eq!(store2.stats.hits.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_legacy_page_table_keeps_its_cid() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        // A page table as bincode wrote it before the versioned format
+        let cid = store.put(&Page { data: vec![0xCD; 4096] }).unwrap();
+        let mut legacy = 1u64.to_le_bytes().to_vec();
+        legacy.push(1);
+        legacy.extend_from_slice(&cid.0);
+        let pt_cid = store.put(&Page { data: legacy.clone() }).unwrap();
+        store.update_root(pt_cid).unwrap();
+
+        let tmp2 = tempfile::tempdir().unwrap();
+        let store2 = make_store(tmp2.path());
+        let net_pages = store.network.pages.lock().unwrap().clone();
+        *store2.network.pages.lock().unwrap() = net_pages;
+        let root = *store.network.root.lock().unwrap();
+        *store2.network.root.lock().unwrap() = root;
+
+        // Unbundled under the CID it was published as, not a re-serialized one
+        assert_eq!(store2.get(&cid).unwrap().data, vec![0xCD; 4096]);
+        assert_eq!(store2.get(&pt_cid).unwrap().data, legacy);
+    }
+
+    #[test]
+    fn test_root_get_set() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        assert_eq!(store.current_root().unwrap(), None);
+
+        // Create a valid page table and pages so update_root can bundle
+        let page = Page { data: vec![0u8; 4096] };
+        let page_cid = store.put(&page).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, page_cid);
+        let pt_data = pt.to_bytes();
+        let pt_cid = Cid::from_bytes(&pt_data);
+        store.put(&Page { data: pt_data }).unwrap();
+
+        store.update_root(pt_cid).unwrap();
+
+        // Root should now be the bundle CID (not the page table CID)
+        let root = store.current_root().unwrap();
+        assert!(root.is_some());
+    }
+
+    #[test]
+    fn test_named_roots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        let cid1 = Cid::from_bytes(b"snap1");
+        let cid2 = Cid::from_bytes(b"snap2");
+
+        store.set_named_root("v1", cid1).unwrap();
+        store.set_named_root("v2", cid2).unwrap();
+
+        assert_eq!(store.get_named_root("v1").unwrap(), Some(cid1));
+        assert_eq!(store.get_named_root("v2").unwrap(), Some(cid2));
+
+        let roots = store.list_named_roots().unwrap();
+        assert_eq!(roots.len(), 2);
+
+        assert!(store.remove_named_root("v1").unwrap());
+        assert_eq!(store.get_named_root("v1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = Arc::new(make_store(tmp.path()));
+
+        // Put a page (locally cached)
+        let page = Page { data: b"concurrent data".to_vec() };
+        let cid = store.put(&page).unwrap();
+
+        // Spawn multiple readers
+        let mut handles = Vec::new();
+        for _ in 0..8 {
+            let s = Arc::clone(&store);
+            let c = cid;
+            handles.push(std::thread::spawn(move || {
+                let got = s.get(&c).unwrap();
+                assert_eq!(got.data, b"concurrent data");
+            }));
+        }
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        // All should be cache hits (page was cached by put)
+        assert_eq!(store.stats.hits.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn test_put_does_not_publish_to_network() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        let page = Page { data: b"publish me".to_vec() };
+        let _cid = store.put(&page).unwrap();
+
+        // put() only caches locally — no network publish
+        assert_eq!(store.network.publish_count.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_update_root_bundles_and_publishes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        // Create some pages
+        let page1 = Page { data: vec![1u8; 4096] };
+        let page2 = Page { data: vec![2u8; 4096] };
+        let cid1 = store.put(&page1).unwrap();
+        let cid2 = store.put(&page2).unwrap();
+
+        // Build page table and store it
+        let mut pt = PageTable::new();
+        pt.set(0, cid1);
+        pt.set(1, cid2);
+        let pt_data = pt.to_bytes();
+        let pt_cid = Cid::from_bytes(&pt_data);
+        let pt_page = Page { data: pt_data };
+        store.put(&pt_page).unwrap();
+
+        // update_root should bundle and publish
+        store.update_root(pt_cid).unwrap();
+
+        // Exactly one network publish (the bundle)
+        assert_eq!(store.network.publish_count.load(Ordering::Relaxed), 1);
+
+        // Root should be set on network
+        assert!(store.network.root.lock().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_unchanged_bundle_not_republished() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        let cid = store.put(&Page { data: vec![7u8; 4096] }).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, cid);
+        let pt_data = pt.to_bytes();
+        let pt_cid = Cid::from_bytes(&pt_data);
+        store.put(&Page { data: pt_data }).unwrap();
+
+        store.update_root(pt_cid).unwrap();
+        let root = store.current_root().unwrap();
+
+        // Same page table again — nothing to publish
+        store.update_root(pt_cid).unwrap();
+        assert_eq!(store.network.publish_count.load(Ordering::Relaxed), 1);
+        assert_eq!(store.current_root().unwrap(), root);
+
+        // A changed page table publishes a new bundle
+        let cid2 = store.put(&Page { data: vec![8u8; 4096] }).unwrap();
+        pt.set(1, cid2);
+        let pt_data = pt.to_bytes();
+        let pt_cid = Cid::from_bytes(&pt_data);
+        store.put(&Page { data: pt_data }).unwrap();
+        store.update_root(pt_cid).unwrap();
+        assert_eq!(store.network.publish_count.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_prune_bundles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        // Three versions of a one-page database
+        let mut versions = Vec::new();
+        for byte in [1u8, 2, 3] {
+            let cid = store.put(&Page { data: vec![byte; 4096] }).unwrap();
+            let mut pt = PageTable::new();
+            pt.set(0, cid);
+            let pt_data = pt.to_bytes();
+            let pt_cid = Cid::from_bytes(&pt_data);
+            store.put(&Page { data: pt_data }).unwrap();
+            store.update_root(pt_cid).unwrap();
+            versions.push((cid, pt_cid));
+        }
+        assert_eq!(store.bundles().unwrap().len(), 3);
+
+        // Old pages and page tables go, the latest version stays
+        let removed = store.prune_bundles(1).unwrap();
+        assert_eq!(removed, 4);
+        assert_eq!(store.bundles().unwrap().len(), 1);
+        assert!(!store.is_cached(&versions[0].0));
+        assert!(!store.is_cached(&versions[1].1));
+        assert!(store.is_cached(&versions[2].0));
+        assert!(store.is_cached(&versions[2].1));
+    }
+
+    #[test]
+    fn test_prune_keeps_named_roots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        let mut versions = Vec::new();
+        for byte in [1u8, 2] {
+            let cid = store.put(&Page { data: vec![byte; 4096] }).unwrap();
+            let mut pt = PageTable::new();
+            pt.set(0, cid);
+            let pt_data = pt.to_bytes();
+            let pt_cid = Cid::from_bytes(&pt_data);
+            store.put(&Page { data: pt_data }).unwrap();
+            store.update_root(pt_cid).unwrap();
+            versions.push((cid, pt_cid));
+        }
+
+        // Snapshot the first version by its page table CID
+        store.set_named_root("v1", versions[0].1).unwrap();
+
+        assert_eq!(store.prune_bundles(0).unwrap(), 0);
+        assert!(store.is_cached(&versions[0].0));
+        assert!(store.is_cached(&versions[1].0));
+    }
+
+    #[test]
+    fn test_named_roots_pin_bundles() {
+        let commit = |store: &CraftObjPageStore<Arc<MockNetworkBackend>>, byte: u8| {
+            let mut pt = PageTable::new();
+            pt.set(0, store.put(&Page { data: vec![byte; 4096] }).unwrap());
+            pt.set(1, store.put(&Page { data: vec![0; 4096] }).unwrap());
+            let pt_cid = store.put(&Page { data: pt.to_bytes() }).unwrap();
+            store.update_root(pt_cid).unwrap();
+            store.current_root().unwrap().unwrap()
+        };
+
+        // A ref pins its bundle and the bundles it's a delta against
+        let network = Arc::new(MockNetworkBackend::new());
+        let tmp = tempfile::tempdir().unwrap();
+        let store = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap().with_delta_bundles(4);
+        let chain: Vec<Cid> = [1, 2, 3].map(|byte| commit(&store, byte)).to_vec();
+        assert!(chain.iter().all(|bundle| !network.is_pinned(bundle)));
+        store.set_named_root("snapshot", chain[1]).unwrap();
+        assert!(network.is_pinned(&chain[0]) && network.is_pinned(&chain[1]));
+        assert!(!network.is_pinned(&chain[2]));
+
+        // Pruning unpins the bundles no ref needs
+        let network = Arc::new(MockNetworkBackend::new());
+        let tmp = tempfile::tempdir().unwrap();
+        let store = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap();
+        let bundles: Vec<Cid> = [4, 5, 6].map(|byte| commit(&store, byte)).to_vec();
+        store.set_named_root("old", bundles[0]).unwrap();
+        store.set_named_root("kept", bundles[1]).unwrap();
+        store.remove_named_root("old").unwrap();
+        store.prune_bundles(1).unwrap();
+        assert!(!network.is_pinned(&bundles[0]));
+        assert!(network.is_pinned(&bundles[1]));
+
+        // Deleting a page unpins it too
+        store.delete_page(&bundles[1]).unwrap();
+        assert!(!network.is_pinned(&bundles[1]));
     }
 
     #[test]
-    fn test_read_write_roundtrip() {
+    fn test_unbundle_rejects_truncated_bundle() {
         let tmp = tempfile::tempdir().unwrap();
         let store = make_store(tmp.path());
 
-        let page = Page { data: b"hello craftsql".to_vec() };
-        let cid = store.put(&page).unwrap();
-        let got = store.get(&cid).unwrap();
-        assert_eq!(got.data, page.data);
+        let cid = store.put(&Page { data: vec![9u8; 4096] }).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, cid);
+        let mut bundle = Vec::new();
+        store.bundle_pages(&pt, &pt.to_bytes(), 4096, None, &mut bundle).unwrap();
+
+        let (parsed, pt_cid) = store.unbundle_pages(&mut &bundle[..]).unwrap();
+        assert_eq!(parsed.get(0), Some(&cid));
+        assert_eq!(pt_cid, Cid::from_bytes(&pt.to_bytes()));
+
+        bundle.truncate(bundle.len() - 1);
+        let err = store.unbundle_pages(&mut &bundle[..]).unwrap_err();
+        assert!(err.to_string().contains("bundle truncated"));
     }
 
     #[test]
-    fn test_cache_hit_no_network_call() {
+    fn test_cache_miss_fetches_single_page_by_range() {
         let tmp = tempfile::tempdir().unwrap();
         let store = make_store(tmp.path());
 
-        let page = Page { data: b"cache me".to_vec() };
-        let cid = store.put(&page).unwrap();
+        let cid1 = store.put(&Page { data: vec![1u8; 4096] }).unwrap();
+        let cid2 = store.put(&Page { data: vec![2u8; 4096] }).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, cid1);
+        pt.set(1, cid2);
+        let pt_data = pt.to_bytes();
+        let pt_cid = Cid::from_bytes(&pt_data);
+        store.put(&Page { data: pt_data }).unwrap();
+        store.update_root(pt_cid).unwrap();
 
-        // First read — should be cache hit (put already cached)
-        let _ = store.get(&cid).unwrap();
-        assert_eq!(store.stats.hits.load(Ordering::Relaxed), 1);
-        assert_eq!(store.stats.misses.load(Ordering::Relaxed), 0);
+        // Reader with an empty cache and a range-capable backend
+        let tmp2 = tempfile::tempdir().unwrap();
+        let store2 = CraftObjPageStore::new(
+            tmp2.path(),
+            MockNetworkBackend::new().with_range_support(),
+        ).unwrap();
+        let net_pages = store.network.pages.lock().unwrap().clone();
+        *store2.network.pages.lock().unwrap() = net_pages;
+        let root = *store.network.root.lock().unwrap();
+        *store2.network.root.lock().unwrap() = root;
 
-        // Second read — still cache hit
-        let _ = store.get(&cid).unwrap();
-        assert_eq!(store.stats.hits.load(Ordering::Relaxed), 2);
+        let got = store2.get(&cid2).unwrap();
+        assert_eq!(got.data, vec![2u8; 4096]);
 
-        // Network fetch should not have been called
-        assert_eq!(store.network.fetch_count.load(Ordering::Relaxed), 0);
+        // Only the requested page was pulled, never the whole bundle
+        assert_eq!(store2.network.fetch_count.load(Ordering::Relaxed), 0);
+        assert!(store2.is_cached(&cid2));
+        assert!(!store2.is_cached(&cid1));
+
+        // Header is remembered: the next page costs a single range read
+        let before = store2.network.range_fetch_count.load(Ordering::Relaxed);
+        store2.get(&cid1).unwrap();
+        assert_eq!(store2.network.range_fetch_count.load(Ordering::Relaxed), before + 1);
     }
 
     #[test]
-    fn test_cache_miss_fetches_bundle_from_network() {
+    fn test_transfer_stats() {
         let tmp = tempfile::tempdir().unwrap();
         let store = make_store(tmp.path());
 
-        // Put a page locally, update root to create a bundle on network
-        let page = Page { data: vec![0xAB; 4096] };
-        let cid = store.put(&page).unwrap();
-        // Manually create page table and trigger bundling via update_root
+        let cid = store.put(&Page { data: vec![3u8; 4096] }).unwrap();
         let mut pt = PageTable::new();
         pt.set(0, cid);
         let pt_data = pt.to_bytes();
         let pt_cid = Cid::from_bytes(&pt_data);
-        let pt_page = Page { data: pt_data };
-        store.put(&pt_page).unwrap();
+        store.put(&Page { data: pt_data }).unwrap();
         store.update_root(pt_cid).unwrap();
 
-        // Now create a new store pointing at the same network but empty cache
-        let tmp2 = tempfile::tempdir().unwrap();
-        let store2 = CraftObjPageStore {
-            cache_dir: tmp2.path().to_path_buf(),
-            network: MockNetworkBackend::new(),
-            stats: CacheStats::new(),
-        };
-        fs::create_dir_all(tmp2.path().join("pages")).unwrap();
+        let stats = store.stats.snapshot();
+        assert_eq!(stats.bundles_published, 1);
+        assert!(stats.bytes_published > 4096);
+        assert!(stats.avg_publish_latency().is_some());
+        assert_eq!(stats.avg_fetch_latency(), None);
 
-        // Copy network state from store to store2
+        // A reader with an empty cache pulls the same bundle back
+        let tmp2 = tempfile::tempdir().unwrap();
+        let store2 = make_store(tmp2.path());
         let net_pages = store.network.pages.lock().unwrap().clone();
         *store2.network.pages.lock().unwrap() = net_pages;
-        let root = store.network.root.lock().unwrap().clone();
+        let root = *store.network.root.lock().unwrap();
         *store2.network.root.lock().unwrap() = root;
+        store2.get(&cid).unwrap();
 
-        // First read — cache miss, should fetch bundle and unpack
-        let got = store2.get(&cid).unwrap();
-        assert_eq!(got.data, page.data);
-        assert_eq!(store2.stats.misses.load(Ordering::Relaxed), 1);
+        let stats2 = store2.stats.snapshot();
+        assert_eq!(stats2.bundles_fetched, 1);
+        assert_eq!(stats2.bytes_fetched, stats.bytes_published);
+        assert_eq!(stats2.misses, 1);
+    }
 
-        // Second read — cache hit
-        let _ = store2.get(&cid).unwrap();
-        assert_eq!(store2.stats.hits.load(Ordering::Relaxed), 1);
+    #[test]
+    fn test_removed_named_root_not_resurrected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
+
+        let cid = Cid::from_bytes(b"feature branch");
+        store.set_named_root("feature", cid).unwrap();
+
+        // Delete during a network blip
+        store.network.set_offline(true);
+        assert!(store.remove_named_root("feature").unwrap());
+        assert_eq!(store.get_named_root("feature").unwrap(), None);
+        store.network.set_offline(false);
+
+        // Network still had it, but the tombstone wins; reads leave it be
+        assert!(store.list_named_roots().unwrap().is_empty());
+        assert_eq!(store.get_named_root("feature").unwrap(), None);
+        assert_eq!(store.network.get_named_root("feature").unwrap(), Some(cid));
+
+        // The next write that reaches the network replays it
+        store.set_named_root("other", cid).unwrap();
+        assert_eq!(store.network.get_named_root("feature").unwrap(), None);
+
+        // Re-creating the name clears the tombstone
+        store.set_named_root("feature", cid).unwrap();
+        assert_eq!(store.get_named_root("feature").unwrap(), Some(cid));
     }
 
     #[test]
-    fn test_root_get_set() {
+    fn test_list_named_roots_merges_local_and_network() {
         let tmp = tempfile::tempdir().unwrap();
         let store = make_store(tmp.path());
 
-        assert_eq!(store.current_root().unwrap(), None);
+        let local = Cid::from_bytes(b"local");
+        let remote = Cid::from_bytes(b"remote");
+        let newer = Cid::from_bytes(b"newer");
 
-        // Create a valid page table and pages so update_root can bundle
-        let page = Page { data: vec![0u8; 4096] };
-        let page_cid = store.put(&page).unwrap();
+        // Only local: written while the network was down
+        store.network.set_offline(true);
+        assert!(store.set_named_root("offline", local).is_err());
+        store.network.set_offline(false);
+
+        // Only on the network, and a name whose value moved on the network
+        store.network.set_named_root("remote", remote).unwrap();
+        store.set_named_root("shared", local).unwrap();
+        store.network.set_named_root("shared", newer).unwrap();
+
+        let roots = store.list_named_roots().unwrap();
+        assert_eq!(roots, vec![
+            ("offline".to_string(), local),
+            ("remote".to_string(), remote),
+            ("shared".to_string(), newer),
+        ]);
+    }
+
+    #[test]
+    fn test_concurrent_publishers_conflict() {
+        let network = Arc::new(MockNetworkBackend::new());
+        let tmp_a = tempfile::tempdir().unwrap();
+        let tmp_b = tempfile::tempdir().unwrap();
+        let a = CraftObjPageStore::new(tmp_a.path(), network.clone()).unwrap();
+        let b = CraftObjPageStore::new(tmp_b.path(), network.clone()).unwrap();
+
+        let commit = |store: &CraftObjPageStore<Arc<MockNetworkBackend>>, byte: u8| {
+            let cid = store.put(&Page { data: vec![byte; 4096] }).unwrap();
+            let mut pt = PageTable::new();
+            pt.set(0, cid);
+            let pt_data = pt.to_bytes();
+            let pt_cid = Cid::from_bytes(&pt_data);
+            store.put(&Page { data: pt_data }).unwrap();
+            store.update_root(pt_cid)
+        };
+
+        // Both start from the same root
+        commit(&a, 1).unwrap();
+        let base = b.current_root().unwrap();
+
+        // A advances; B's commit was based on the old root
+        commit(&a, 2).unwrap();
+        let err = commit(&b, 3).unwrap_err();
+        assert!(matches!(
+            err,
+            PageStoreError::RootConflict { expected, .. } if expected == base
+        ));
+        assert_eq!(b.current_root().unwrap(), a.current_root().unwrap());
+
+        // After observing the new root, B can advance it
+        commit(&b, 3).unwrap();
+        assert_eq!(network.get_root().unwrap(), b.current_root().unwrap());
+    }
+
+    #[test]
+    fn test_root_update_guard() {
+        let network = Arc::new(MockNetworkBackend::new());
+        let tmp_a = tempfile::tempdir().unwrap();
+        let tmp_b = tempfile::tempdir().unwrap();
+        let a = CraftObjPageStore::new(tmp_a.path(), network.clone()).unwrap();
+        let b = CraftObjPageStore::new(tmp_b.path(), network.clone()).unwrap();
+        let page_table = |store: &CraftObjPageStore<Arc<MockNetworkBackend>>, byte: u8| {
+            let mut pt = PageTable::new();
+            pt.set(0, store.put(&Page { data: vec![byte; 4096] }).unwrap());
+            store.put(&Page { data: pt.to_bytes() }).unwrap()
+        };
+
+        // The root moves and the refs follow
+        let mut update = a.begin_root_update().unwrap();
+        assert_eq!(update.base(), None);
+        update.set_root(page_table(&a, 1));
+        update.name_root("main");
+        let first = update.commit().unwrap().unwrap();
+        assert_eq!(network.get_root().unwrap(), Some(first));
+        assert_eq!(network.get_named_root("main").unwrap(), Some(first));
+
+        // Dropped, an update commits; aborted, it doesn't
+        let mut update = a.begin_root_update().unwrap();
+        update.set_root(page_table(&a, 2));
+        update.name_root("main");
+        drop(update);
+        let second = a.current_root().unwrap().unwrap();
+        assert_ne!(second, first);
+        assert_eq!(network.get_named_root("main").unwrap(), Some(second));
+        let mut update = a.begin_root_update().unwrap();
+        update.set_root(page_table(&a, 3));
+        update.abort();
+        assert_eq!(network.get_root().unwrap(), Some(second));
+
+        // A conflict sets no refs either
+        let mut update = b.begin_root_update().unwrap();
+        assert_eq!(update.base(), None);
+        update.set_root(page_table(&b, 4));
+        update.name_root("main");
+        update.set_named_root("other", first);
+        assert!(matches!(update.commit(), Err(RootUpdateError::Root(PageStoreError::RootConflict { expected: None, .. }))));
+        assert_eq!(network.get_named_root("main").unwrap(), Some(second));
+        assert_eq!(network.get_named_root("other").unwrap(), None);
+
+        // A ref that can't be set is reported, the rest still follow
+        fs::create_dir_all(tmp_a.path().join("refs").join("broken").join("in-the-way")).unwrap();
+        let mut update = a.begin_root_update().unwrap();
+        update.set_root(page_table(&a, 5));
+        update.name_root("broken");
+        update.name_root("main");
+        let Err(RootUpdateError::NamedRoots { root, unset, .. }) = update.commit() else {
+            panic!("expected the broken ref to fail");
+        };
+        let third = root.unwrap();
+        assert_eq!(network.get_root().unwrap(), Some(third));
+        assert_eq!(unset, vec![("broken".to_string(), third)]);
+        assert_eq!(network.get_named_root("main").unwrap(), Some(third));
+
+        // Unwinding, a dropped update is abandoned
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut update = a.begin_root_update().unwrap();
+            update.set_root(page_table(&a, 6));
+            update.name_root("main");
+            panic!("mid-update");
+        }));
+        assert!(result.is_err());
+        assert_eq!(network.get_root().unwrap(), Some(third));
+        assert_eq!(network.get_named_root("main").unwrap(), Some(third));
+    }
+
+    #[test]
+    fn test_progress_reports_every_stage() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let network = Arc::new(MockNetworkBackend::new());
+
+        let tmp = tempfile::tempdir().unwrap();
+        let sink = events.clone();
+        let writer = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap()
+            .with_progress(move |p: &Progress| sink.lock().unwrap().push(*p));
+
+        let cid1 = writer.put(&Page { data: vec![1u8; 4096] }).unwrap();
+        let cid2 = writer.put(&Page { data: vec![2u8; 4096] }).unwrap();
         let mut pt = PageTable::new();
-        pt.set(0, page_cid);
+        pt.set(0, cid1);
+        pt.set(1, cid2);
         let pt_data = pt.to_bytes();
         let pt_cid = Cid::from_bytes(&pt_data);
-        store.put(&Page { data: pt_data }).unwrap();
+        writer.put(&Page { data: pt_data }).unwrap();
+        writer.update_root(pt_cid).unwrap();
 
-        store.update_root(pt_cid).unwrap();
+        let tmp2 = tempfile::tempdir().unwrap();
+        let sink = events.clone();
+        let reader = CraftObjPageStore::new(tmp2.path(), network).unwrap()
+            .with_progress(move |p: &Progress| sink.lock().unwrap().push(*p));
+        reader.get(&cid2).unwrap();
 
-        // Root should now be the bundle CID (not the page table CID)
-        let root = store.current_root().unwrap();
-        assert!(root.is_some());
+        let events = events.lock().unwrap();
+        let last = |stage| events.iter().rev().find(|p| p.stage == stage).copied().unwrap();
+
+        let bundled = last(TransferStage::Bundling);
+        assert_eq!((bundled.pages, bundled.total_pages), (2, Some(2)));
+        assert_eq!(Some(bundled.bytes), bundled.total_bytes);
+
+        let published = last(TransferStage::Publishing);
+        assert_eq!(Some(published.bytes), published.total_bytes);
+        assert_eq!(last(TransferStage::Fetching).bytes, published.bytes);
+
+        let unbundled = last(TransferStage::Unbundling);
+        assert_eq!((unbundled.pages, unbundled.total_pages), (2, Some(2)));
     }
 
     #[test]
-    fn test_named_roots() {
+    fn test_small_database_published_page_by_page() {
+        let network = Arc::new(MockNetworkBackend::new());
         let tmp = tempfile::tempdir().unwrap();
-        let store = make_store(tmp.path());
+        let store = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap()
+            .with_page_publish_threshold(64 * 1024);
 
-        let cid1 = Cid::from_bytes(b"snap1");
-        let cid2 = Cid::from_bytes(b"snap2");
+        let commit = |pages: &[u8]| {
+            let mut pt = PageTable::new();
+            for (i, &byte) in pages.iter().enumerate() {
+                pt.set(i, store.put(&Page { data: vec![byte; 4096] }).unwrap());
+            }
+            let pt_data = pt.to_bytes();
+            let pt_cid = Cid::from_bytes(&pt_data);
+            store.put(&Page { data: pt_data }).unwrap();
+            store.update_root(pt_cid).unwrap();
+            pt_cid
+        };
 
-        store.set_named_root("v1", cid1).unwrap();
-        store.set_named_root("v2", cid2).unwrap();
+        // Two pages + page table, root is the page table itself
+        let pt_cid = commit(&[1, 2]);
+        assert_eq!(network.publish_count.load(Ordering::Relaxed), 3);
+        assert_eq!(network.batch_count.load(Ordering::Relaxed), 1);
+        assert_eq!(network.get_root().unwrap(), Some(pt_cid));
 
-        assert_eq!(store.get_named_root("v1").unwrap(), Some(cid1));
-        assert_eq!(store.get_named_root("v2").unwrap(), Some(cid2));
+        // Changing one page uploads just that page and the new table
+        commit(&[1, 3]);
+        assert_eq!(network.publish_count.load(Ordering::Relaxed), 5);
+        assert_eq!(store.stats.snapshot().pages_published, 5);
 
-        let roots = store.list_named_roots().unwrap();
-        assert_eq!(roots.len(), 2);
+        // A cold reader resolves pages without any bundle
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = CraftObjPageStore::new(tmp2.path(), network.clone()).unwrap();
+        let page = reader.get(&Cid::from_bytes(&[3u8; 4096])).unwrap();
+        assert_eq!(page.data, vec![3u8; 4096]);
 
-        assert!(store.remove_named_root("v1").unwrap());
-        assert_eq!(store.get_named_root("v1").unwrap(), None);
+        // Past the threshold the store falls back to a single bundle
+        commit(&[4; 20]);
+        assert_eq!(network.publish_count.load(Ordering::Relaxed), 6);
     }
 
     #[test]
-    fn test_concurrent_reads() {
+    fn test_page_by_page_publish_is_batched() {
+        let network = Arc::new(MockNetworkBackend::new());
         let tmp = tempfile::tempdir().unwrap();
-        let store = Arc::new(make_store(tmp.path()));
+        let store = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap()
+            .with_page_publish_threshold(1024 * 1024);
 
-        // Put a page (locally cached)
-        let page = Page { data: b"concurrent data".to_vec() };
-        let cid = store.put(&page).unwrap();
+        let mut pt = PageTable::new();
+        for i in 0..100u32 {
+            let mut data = vec![0u8; 4096];
+            data[..4].copy_from_slice(&i.to_le_bytes());
+            pt.set(i as usize, store.put(&Page { data }).unwrap());
+        }
+        let pt_cid = store.put(&Page { data: pt.to_bytes() }).unwrap();
+        store.update_root(pt_cid).unwrap();
 
-        // Spawn multiple readers
-        let mut handles = Vec::new();
-        for _ in 0..8 {
-            let s = Arc::clone(&store);
-            let c = cid;
-            handles.push(std::thread::spawn(move || {
-                let got = s.get(&c).unwrap();
-                assert_eq!(got.data, b"concurrent data");
-            }));
+        // 100 pages + page table in batches of PUBLISH_BATCH_PAGES
+        assert_eq!(network.publish_count.load(Ordering::Relaxed), 101);
+        assert_eq!(network.batch_count.load(Ordering::Relaxed), 2);
+        assert_eq!(store.stats.snapshot().pages_published, 101);
+    }
+
+    #[test]
+    fn test_large_bundle_published_in_segments() {
+        let network = Arc::new(MockNetworkBackend::new());
+        let tmp = tempfile::tempdir().unwrap();
+        let store = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap()
+            .with_segment_size(8192);
+
+        let mut pt = PageTable::new();
+        for i in 0..5u8 {
+            pt.set(i as usize, store.put(&Page { data: vec![i; 4096] }).unwrap());
         }
+        let pt_cid = store.put(&Page { data: pt.to_bytes() }).unwrap();
+        store.update_root(pt_cid).unwrap();
 
-        for h in handles {
-            h.join().unwrap();
+        // ~20KB bundle → 3 segments + manifest, none over the limit
+        assert_eq!(network.publish_count.load(Ordering::Relaxed), 4);
+        assert!(network.pages.lock().unwrap().values().all(|blob| blob.len() <= 8192));
+
+        // Unchanged bundle is not republished
+        store.update_root(pt_cid).unwrap();
+        assert_eq!(network.publish_count.load(Ordering::Relaxed), 4);
+
+        // A cold reader reassembles the bundle from its segments
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = CraftObjPageStore::new(tmp2.path(), network.clone()).unwrap();
+        for i in 0..5u8 {
+            assert_eq!(reader.get(pt.get(i as usize).unwrap()).unwrap().data, vec![i; 4096]);
         }
+        assert_eq!(reader.stats.snapshot().bundles_fetched, 1);
+    }
 
-        // All should be cache hits (page was cached by put)
-        assert_eq!(store.stats.hits.load(Ordering::Relaxed), 8);
+    #[test]
+    fn test_publish_options_and_placement() {
+        let network = Arc::new(MockNetworkBackend::new());
+        let tmp = tempfile::tempdir().unwrap();
+        let options = PublishOptions::default().with_replication(3).with_segment_size(1 << 20);
+        let store = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap()
+            .with_segment_size(8192)
+            .with_publish_options(options);
+
+        let mut pt = PageTable::new();
+        for i in 0..5u8 {
+            pt.set(i as usize, store.put(&Page { data: vec![i; 4096] }).unwrap());
+        }
+        let pt_cid = store.put(&Page { data: pt.to_bytes() }).unwrap();
+        store.update_root(pt_cid).unwrap();
+
+        // The manifest and every segment went out with the options
+        let manifest = store.bundles().unwrap()[0];
+        let segments = SegmentManifest::parse(&network.fetch_page(&manifest).unwrap()).unwrap().unwrap().segments;
+        for cid in std::iter::once(manifest).chain(segments.iter().map(|(cid, _)| *cid)) {
+            assert_eq!(network.publish_options(&cid), Some(options));
+        }
+
+        // The bundle's placement covers all of them
+        let placement = store.bundle_placement(&manifest).unwrap().unwrap();
+        assert_eq!(placement.providers, ["mock-0", "mock-1", "mock-2"]);
+        assert_eq!(placement.segments.len(), 1 + segments.len());
+        assert!(placement.is_recoverable());
+        assert_eq!(store.bundle_placements().unwrap(), [(manifest, Some(placement))]);
+
+        let lost = Placement { providers: vec![], segments: vec![SegmentHealth { pieces: 2, needed: 3 }] };
+        assert!(!lost.is_recoverable());
+    }
+
+    /// A mock network whose fetches take a while, counting how many overlap.
+    #[derive(Default)]
+    struct SlowNetwork {
+        inner: MockNetworkBackend,
+        in_flight: AtomicU64,
+        max_in_flight: AtomicU64,
+    }
+
+    impl NetworkBackend for SlowNetwork {
+        fn publish_page(&self, data: &[u8]) -> Result<Cid> {
+            self.inner.publish_page(data)
+        }
+
+        fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.inner.fetch_page(cid)
+        }
+
+        fn supports_range(&self) -> bool {
+            self.inner.supports_range()
+        }
+
+        fn fetch_range(&self, cid: &Cid, offset: u64, len: u64) -> Result<Vec<u8>> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.inner.fetch_range(cid, offset, len)
+        }
+
+        fn get_root(&self) -> Result<Option<Cid>> {
+            self.inner.get_root()
+        }
+
+        fn set_root(&self, cid: Cid) -> Result<()> {
+            self.inner.set_root(cid)
+        }
+
+        fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+            self.inner.get_named_root(name)
+        }
+
+        fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+            self.inner.set_named_root(name, cid)
+        }
+
+        fn remove_named_root(&self, name: &str) -> Result<bool> {
+            self.inner.remove_named_root(name)
+        }
+
+        fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+            self.inner.list_named_roots()
+        }
+    }
+
+    /// Publish a database of `pages` distinct pages through `store`.
+    fn publish_pages<N: NetworkBackend>(store: &CraftObjPageStore<N>, pages: u8) -> PageTable {
+        let mut pt = PageTable::new();
+        for i in 0..pages {
+            pt.set(i as usize, store.put(&Page { data: vec![i; 4096] }).unwrap());
+        }
+        let pt_cid = store.put(&Page { data: pt.to_bytes() }).unwrap();
+        store.update_root(pt_cid).unwrap();
+        pt
     }
 
     #[test]
-    fn test_put_does_not_publish_to_network() {
+    fn test_concurrent_misses_fetch_bundle_once() {
+        let network = Arc::new(SlowNetwork::default());
         let tmp = tempfile::tempdir().unwrap();
-        let store = make_store(tmp.path());
+        let writer = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap();
+        let pt = publish_pages(&writer, 8);
 
-        let page = Page { data: b"publish me".to_vec() };
-        let _cid = store.put(&page).unwrap();
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = CraftObjPageStore::new(tmp2.path(), network.clone()).unwrap();
+        std::thread::scope(|scope| {
+            for i in 0..8u8 {
+                let (reader, pt) = (&reader, &pt);
+                scope.spawn(move || {
+                    assert_eq!(reader.get(pt.get(i as usize).unwrap()).unwrap().data, vec![i; 4096]);
+                });
+            }
+        });
 
-        // put() only caches locally — no network publish
-        assert_eq!(store.network.publish_count.load(Ordering::Relaxed), 0);
+        // One thread fetched the bundle; the rest waited for it
+        assert_eq!(reader.stats.snapshot().bundles_fetched, 1);
+        assert_eq!(network.inner.fetch_count.load(Ordering::Relaxed), 1);
+        assert!(reader.fetching.lock().unwrap().is_empty());
     }
 
     #[test]
-    fn test_update_root_bundles_and_publishes() {
+    fn test_range_reads_overlap() {
+        let network = Arc::new(SlowNetwork {
+            inner: MockNetworkBackend::new().with_range_support(),
+            ..Default::default()
+        });
         let tmp = tempfile::tempdir().unwrap();
-        let store = make_store(tmp.path());
+        let writer = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap();
+        let pt = publish_pages(&writer, 8);
 
-        // Create some pages
-        let page1 = Page { data: vec![1u8; 4096] };
-        let page2 = Page { data: vec![2u8; 4096] };
-        let cid1 = store.put(&page1).unwrap();
-        let cid2 = store.put(&page2).unwrap();
+        // Once the layout is known, pages of the same bundle are read at once
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = CraftObjPageStore::new(tmp2.path(), network.clone()).unwrap();
+        reader.get(pt.get(0).unwrap()).unwrap();
+        network.max_in_flight.store(0, Ordering::SeqCst);
+        std::thread::scope(|scope| {
+            for i in 1..8u8 {
+                let (reader, pt) = (&reader, &pt);
+                scope.spawn(move || {
+                    assert_eq!(reader.get(pt.get(i as usize).unwrap()).unwrap().data, vec![i; 4096]);
+                });
+            }
+        });
+        assert_eq!(network.inner.fetch_count.load(Ordering::Relaxed), 0);
+        assert!(network.max_in_flight.load(Ordering::SeqCst) > 1);
+    }
 
-        // Build page table and store it
+    #[test]
+    fn test_max_concurrent_requests() {
+        let network = Arc::new(SlowNetwork::default());
+        let tmp = tempfile::tempdir().unwrap();
+        let writer = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap()
+            .with_segment_size(4096);
+        let pt = publish_pages(&writer, 10);
+
+        // Segments are fetched in parallel, but never more than the limit at once
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = CraftObjPageStore::new(tmp2.path(), network.clone()).unwrap()
+            .with_max_concurrent_requests(2);
+        assert_eq!(reader.get(pt.get(9).unwrap()).unwrap().data, vec![9; 4096]);
+        assert!(network.inner.fetch_count.load(Ordering::Relaxed) > 10);
+        assert_eq!(network.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_bandwidth_limits() {
+        let network = Arc::new(MockNetworkBackend::new());
+        let tmp = tempfile::tempdir().unwrap();
+        let upload = RateLimit::new(200_000);
+        let writer = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap()
+            .with_upload_limit(upload.clone());
+
+        // ~300 KB bundle: a second's worth goes at once, the rest at 200 KB/s
+        let started = Instant::now();
+        let pt = publish_pages(&writer, 75);
+        assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+
+        let tmp2 = tempfile::tempdir().unwrap();
+        let download = RateLimit::new(200_000);
+        let reader = CraftObjPageStore::new(tmp2.path(), network.clone()).unwrap()
+            .with_download_limit(download.clone());
+        let started = Instant::now();
+        assert_eq!(reader.get(pt.get(74).unwrap()).unwrap().data, vec![74; 4096]);
+        assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+
+        // Lifting the limit through the kept clone applies to the store
+        upload.set_rate(0);
+        let started = Instant::now();
+        writer.put(&Page { data: vec![0xEE; 4096] }).unwrap();
+        let mut pt = pt;
+        pt.set(75, Cid::from_bytes(&[0xEE; 4096]));
+        let pt_cid = writer.put(&Page { data: pt.to_bytes() }).unwrap();
+        writer.update_root(pt_cid).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(300), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_delta_bundles() {
+        let network = Arc::new(MockNetworkBackend::new());
+        let tmp = tempfile::tempdir().unwrap();
+        let writer = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap()
+            .with_delta_bundles(2);
+        let root_blob = || {
+            let root = network.get_root().unwrap().unwrap();
+            network.pages.lock().unwrap()[&root].clone()
+        };
+        let commit = |pages: &[Vec<u8>]| {
+            let mut pt = PageTable::new();
+            for (i, data) in pages.iter().enumerate() {
+                pt.set(i, writer.put(&Page { data: data.clone() }).unwrap());
+            }
+            let pt_cid = writer.put(&Page { data: pt.to_bytes() }).unwrap();
+            writer.update_root(pt_cid).unwrap();
+            pt
+        };
+
+        let mut pages: Vec<Vec<u8>> = (0..20u32)
+            .map(|i| (0..4096u32).map(|j| (i * 31 + j * 7 % 253) as u8).collect())
+            .collect();
+        commit(&pages);
+        let full = root_blob();
+        assert_eq!(full[4..6], BUNDLE_VERSION.to_le_bytes());
+
+        // A few bytes changed on two pages, and a page added
+        pages[3][10..14].copy_from_slice(b"edit");
+        pages[17][4000] ^= 0xFF;
+        pages.push(vec![0xAB; 4096]);
+        let pt = commit(&pages);
+        let delta = root_blob();
+        assert_eq!(delta[4..6], DELTA_BUNDLE_VERSION.to_le_bytes());
+        assert!(delta.len() < full.len() / 4, "{} vs {} bytes", delta.len(), full.len());
+
+        // Unchanged: nothing published
+        let published = network.publish_count.load(Ordering::Relaxed);
+        writer.update_root(Cid::from_bytes(&pt.to_bytes())).unwrap();
+        assert_eq!(network.publish_count.load(Ordering::Relaxed), published);
+
+        // A cold reader fetches the base bundle too and rebuilds every page
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = CraftObjPageStore::new(tmp2.path(), network.clone()).unwrap();
+        for (i, data) in pages.iter().enumerate() {
+            assert_eq!(&reader.get(pt.get(i).unwrap()).unwrap().data, data);
+        }
+        assert_eq!(reader.stats.snapshot().bundles_fetched, 2);
+        assert_eq!(reader.bundles().unwrap().len(), 2);
+
+        // The chain is capped: after two deltas, a full bundle again
+        pages[0][0] ^= 1;
+        commit(&pages);
+        assert_eq!(root_blob()[4..6], DELTA_BUNDLE_VERSION.to_le_bytes());
+        pages[0][0] ^= 1;
+        commit(&pages);
+        assert_eq!(root_blob()[4..6], BUNDLE_VERSION.to_le_bytes());
+    }
+
+    #[test]
+    fn test_delta_bundle_skips_range_reads() {
+        let network = Arc::new(MockNetworkBackend::new().with_range_support());
+        let tmp = tempfile::tempdir().unwrap();
+        let writer = CraftObjPageStore::new(tmp.path(), network.clone()).unwrap().with_delta_bundles(2);
+        let shared = writer.put(&Page { data: vec![7; 4096] }).unwrap();
         let mut pt = PageTable::new();
-        pt.set(0, cid1);
-        pt.set(1, cid2);
-        let pt_data = pt.to_bytes();
-        let pt_cid = Cid::from_bytes(&pt_data);
-        let pt_page = Page { data: pt_data };
-        store.put(&pt_page).unwrap();
+        pt.set(0, shared);
+        for byte in [1, 2] {
+            pt.set(1, writer.put(&Page { data: vec![byte; 4096] }).unwrap());
+            writer.update_root(writer.put(&Page { data: pt.to_bytes() }).unwrap()).unwrap();
+        }
 
-        // update_root should bundle and publish
-        store.update_root(pt_cid).unwrap();
+        // Reading the header is enough to tell: the bundles come whole
+        let tmp2 = tempfile::tempdir().unwrap();
+        let reader = CraftObjPageStore::new(tmp2.path(), network.clone()).unwrap();
+        assert_eq!(reader.get(&shared).unwrap().data, vec![7; 4096]);
+        assert_eq!(network.range_fetch_count.load(Ordering::Relaxed), 1);
+        assert_eq!(reader.stats.snapshot().bundles_fetched, 2);
+        assert!(reader.is_cached(pt.get(1).unwrap()));
+    }
 
-        // Exactly one network publish (the bundle)
-        assert_eq!(store.network.publish_count.load(Ordering::Relaxed), 1);
+    #[test]
+    fn test_cache_readable_as_local_store() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = make_store(tmp.path());
 
-        // Root should be set on network
-        assert!(store.network.root.lock().unwrap().is_some());
+        let page = Page { data: vec![0x5A; 4096] };
+        let cid = store.put(&page).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, cid);
+        let pt_cid = store.put(&Page { data: pt.to_bytes() }).unwrap();
+        store.update_root(pt_cid).unwrap();
+        store.set_named_root("v1", pt_cid).unwrap();
+
+        // Same directory opened as a plain local store
+        let local = LocalPageStore::new(tmp.path()).unwrap();
+        assert_eq!(local.get(&cid).unwrap().data, page.data);
+        assert_eq!(local.current_root().unwrap(), *store.network.root.lock().unwrap());
+        assert_eq!(local.get_named_root("v1").unwrap(), Some(pt_cid));
     }
 
     #[test]
@@ -0,0 +1,74 @@
+//! A cap on how many network calls run at once.
+
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::sync::{Condvar, Mutex};
+#[cfg(feature = "async")]
+use std::task::Poll;
+use std::task::Waker;
+
+/// Lets at most `max` calls run at once; the rest wait their turn.
+///
+/// Threads and async tasks share the one count: threads wait on a
+/// condition variable, tasks park their wakers.
+pub(crate) struct Limiter {
+    max: usize,
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    /// Tasks waiting for a slot, all woken when one frees up.
+    wakers: Vec<Waker>,
+}
+
+/// A slot held until dropped, released on unwind too, or a panicking call
+/// would shrink the limit.
+pub(crate) struct Permit<'a>(&'a Limiter);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut state = self.0.state.lock().unwrap();
+            state.running -= 1;
+            std::mem::take(&mut state.wakers)
+        };
+        self.0.freed.notify_one();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Limiter {
+    pub(crate) fn new(max: usize) -> Self {
+        Self { max: max.max(1), state: Mutex::new(State::default()), freed: Condvar::new() }
+    }
+
+    /// Run `call` once fewer than `max` others are running.
+    pub(crate) fn run<T>(&self, call: impl FnOnce() -> T) -> T {
+        let mut state = self.state.lock().unwrap();
+        while state.running >= self.max {
+            state = self.freed.wait(state).unwrap();
+        }
+        state.running += 1;
+        drop(state);
+
+        let _permit = Permit(self);
+        call()
+    }
+
+    /// Wait, without blocking the thread, until fewer than `max` calls run.
+    #[cfg(feature = "async")]
+    pub(crate) fn acquire(&self) -> impl Future<Output = Permit<'_>> {
+        std::future::poll_fn(move |cx| {
+            let mut state = self.state.lock().unwrap();
+            if state.running < self.max {
+                state.running += 1;
+                return Poll::Ready(Permit(self));
+            }
+            state.wakers.push(cx.waker().clone());
+            Poll::Pending
+        })
+    }
+}
@@ -0,0 +1,383 @@
+//! Primary + mirror [`NetworkBackend`] composition.
+//!
+//! [`MirroredBackend`] publishes to every configured backend (or a quorum of
+//! them) and serves reads from the first backend that answers, so a store keeps
+//! working while its primary CraftOBJ node is unreachable.
+
+use crate::{NetworkBackend, Placement, PublishOptions, TEMP_COUNTER};
+use craftsql_core::{Cid, PageStoreError, Result};
+use std::fs;
+use std::io::{BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+/// A primary backend followed by zero or more mirrors, tried in order.
+pub struct MirroredBackend {
+    backends: Vec<Box<dyn NetworkBackend>>,
+    /// Number of backends that must accept a write (None = all of them).
+    write_quorum: Option<usize>,
+    /// Where streamed transfers are spooled.
+    temp_dir: PathBuf,
+}
+
+impl MirroredBackend {
+    /// Start with a primary backend.
+    pub fn new(primary: impl NetworkBackend + 'static) -> Self {
+        Self {
+            backends: vec![Box::new(primary)],
+            write_quorum: None,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+
+    /// Add a mirror, consulted after the primary and any earlier mirrors.
+    pub fn with_mirror(mut self, mirror: impl NetworkBackend + 'static) -> Self {
+        self.backends.push(Box::new(mirror));
+        self
+    }
+
+    /// Require only `quorum` backends to accept publishes and root updates
+    /// (clamped to at least one). Defaults to all backends.
+    pub fn with_write_quorum(mut self, quorum: usize) -> Self {
+        self.write_quorum = Some(quorum.max(1));
+        self
+    }
+
+    /// Spool streamed publishes and fetches in `dir` instead of the system
+    /// temp dir.
+    ///
+    /// Spool files are uniquely named and removed as soon as each call
+    /// finishes.
+    pub fn with_temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    /// Number of configured backends, primary included.
+    pub fn len(&self) -> usize {
+        self.backends.len()
+    }
+
+    /// Always false; there is at least the primary.
+    pub fn is_empty(&self) -> bool {
+        self.backends.is_empty()
+    }
+
+    fn quorum(&self) -> usize {
+        self.write_quorum.unwrap_or(self.backends.len()).min(self.backends.len())
+    }
+
+    /// Return the first successful result, trying backends in order.
+    fn first_ok<T>(&self, op: impl Fn(&dyn NetworkBackend) -> Result<T>) -> Result<T> {
+        let mut last_err = None;
+        for backend in &self.backends {
+            match op(backend.as_ref()) {
+                Ok(v) => return Ok(v),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.expect("at least one backend"))
+    }
+
+    /// Apply a write to every backend; succeeds if at least a quorum accepted it.
+    /// Results are in backend order, failures omitted.
+    fn write_all<T>(&self, op: impl Fn(&dyn NetworkBackend) -> Result<T>) -> Result<Vec<T>> {
+        self.check_quorum(self.backends.iter().map(|b| op(b.as_ref())).collect())
+    }
+
+    /// The successes among `results`, one per backend, if there are a quorum
+    /// of them. A missed quorum is an error of the kind the last failure
+    /// was, so callers can tell an outage worth retrying from a refusal.
+    fn check_quorum<T>(&self, results: Vec<Result<T>>) -> Result<Vec<T>> {
+        let mut ok = Vec::new();
+        let mut last_err = None;
+        for result in results {
+            match result {
+                Ok(v) => ok.push(v),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        if ok.len() >= self.quorum() {
+            return Ok(ok);
+        }
+        let message = format!(
+            "write quorum not met ({} of {} required): {}",
+            ok.len(),
+            self.quorum(),
+            last_err.as_ref().map(|e| e.to_string()).unwrap_or_default(),
+        );
+        Err(match last_err.as_ref().map(PageStoreError::without_context) {
+            Some(PageStoreError::Timeout(_)) => PageStoreError::Timeout(message),
+            Some(e) if e.is_retryable() => PageStoreError::Network(message),
+            _ => PageStoreError::Storage(message),
+        })
+    }
+
+    /// Scratch file for replaying a stream to several backends.
+    fn spool_path(&self) -> PathBuf {
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        self.temp_dir.join(format!("craftsql-mirror-{}-{}.tmp", std::process::id(), n))
+    }
+}
+
+impl NetworkBackend for MirroredBackend {
+    fn publish_page(&self, data: &[u8]) -> Result<Cid> {
+        Ok(self.write_all(|b| b.publish_page(data))?[0])
+    }
+
+    fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
+        self.first_ok(|b| b.fetch_page(cid))
+    }
+
+    fn publish_many(&self, items: &[&[u8]]) -> Result<Vec<Cid>> {
+        Ok(self.write_all(|b| b.publish_many(items))?.swap_remove(0))
+    }
+
+    fn supports_range(&self) -> bool {
+        self.backends[0].supports_range()
+    }
+
+    fn fetch_range(&self, cid: &Cid, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.first_ok(|b| b.fetch_range(cid, offset, len))
+    }
+
+    fn publish_stream(&self, reader: &mut dyn Read) -> Result<Cid> {
+        self.publish_stream_with(reader, &PublishOptions::default())
+    }
+
+    fn publish_stream_with(&self, reader: &mut dyn Read, options: &PublishOptions) -> Result<Cid> {
+        if self.backends.len() == 1 {
+            return self.backends[0].publish_stream_with(reader, options);
+        }
+
+        // Spool once, then replay from disk to each backend
+        let spool = self.spool_path();
+        let result = fs::File::create(&spool)
+            .and_then(|mut file| std::io::copy(reader, &mut file))
+            .map_err(PageStoreError::from)
+            .and_then(|_| {
+                self.write_all(|b| b.publish_stream_with(&mut BufReader::new(fs::File::open(&spool)?), options))
+            });
+        let _ = fs::remove_file(&spool);
+        Ok(result?[0])
+    }
+
+    fn pin(&self, cid: &Cid) -> Result<()> {
+        self.write_all(|b| b.pin(cid)).map(|_| ())
+    }
+
+    fn unpin(&self, cid: &Cid) -> Result<()> {
+        self.write_all(|b| b.unpin(cid)).map(|_| ())
+    }
+
+    /// The placement the first backend that answers reports.
+    fn placement(&self, cid: &Cid) -> Result<Option<Placement>> {
+        self.first_ok(|b| b.placement(cid))
+    }
+
+    fn fetch_stream(&self, cid: &Cid, writer: &mut dyn Write) -> Result<()> {
+        if self.backends.len() == 1 {
+            return self.backends[0].fetch_stream(cid, writer);
+        }
+
+        // Each attempt lands in a spool file so a failed partial transfer never
+        // reaches the caller's writer.
+        let spool = self.spool_path();
+        let result = self.first_ok(|b| {
+            let mut file = fs::File::create(&spool)?;
+            b.fetch_stream(cid, &mut file)
+        })
+        .and_then(|()| {
+            std::io::copy(&mut fs::File::open(&spool)?, writer)?;
+            Ok(())
+        });
+        let _ = fs::remove_file(&spool);
+        result
+    }
+
+    fn get_root(&self) -> Result<Option<Cid>> {
+        self.first_ok(|b| b.get_root())
+    }
+
+    fn set_root(&self, cid: Cid) -> Result<()> {
+        self.write_all(|b| b.set_root(cid)).map(|_| ())
+    }
+
+    /// A conflict on any backend is reported as such, not as a missed
+    /// quorum. Backends that already took `new` are moved on to the root the
+    /// conflicting one holds (or back to `expected` if it holds none), so the
+    /// mirrors agree again on the root that won.
+    fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        let results: Vec<_> = self.backends.iter().map(|b| b.set_root_if(expected, new)).collect();
+        let conflict = results.iter().find_map(|result| match result.as_ref().map_err(PageStoreError::without_context) {
+            Err(PageStoreError::RootConflict { actual, .. }) => Some(*actual),
+            _ => None,
+        });
+        let Some(actual) = conflict else {
+            return self.check_quorum(results).map(|_| ());
+        };
+        if let Some(winner) = actual.or(expected) {
+            for (backend, _) in self.backends.iter().zip(&results).filter(|(_, result)| result.is_ok()) {
+                if let Err(e) = backend.set_root_if(Some(new), winner) {
+                    tracing::warn!(error = %e, root = %new, "failed to roll back a mirror's root");
+                }
+            }
+        }
+        Err(PageStoreError::RootConflict { expected, actual })
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.first_ok(|b| b.get_named_root(name))
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.write_all(|b| b.set_named_root(name, cid)).map(|_| ())
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        Ok(self.write_all(|b| b.remove_named_root(name))?.into_iter().any(|r| r))
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        self.first_ok(|b| b.list_named_roots())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CraftObjPageStore, MockNetworkBackend};
+    use craftsql_core::{Page, PageStore, PageTable};
+    use std::sync::Arc;
+
+    /// Backend whose node is always unreachable.
+    struct DownBackend;
+
+    impl NetworkBackend for DownBackend {
+        fn publish_page(&self, _data: &[u8]) -> Result<Cid> {
+            Err(PageStoreError::Network("node unreachable".into()))
+        }
+        fn fetch_page(&self, _cid: &Cid) -> Result<Vec<u8>> {
+            Err(PageStoreError::Network("node unreachable".into()))
+        }
+        fn get_root(&self) -> Result<Option<Cid>> {
+            Err(PageStoreError::Network("node unreachable".into()))
+        }
+        fn set_root(&self, _cid: Cid) -> Result<()> {
+            Err(PageStoreError::Network("node unreachable".into()))
+        }
+        fn get_named_root(&self, _name: &str) -> Result<Option<Cid>> {
+            Err(PageStoreError::Network("node unreachable".into()))
+        }
+        fn set_named_root(&self, _name: &str, _cid: Cid) -> Result<()> {
+            Err(PageStoreError::Network("node unreachable".into()))
+        }
+        fn remove_named_root(&self, _name: &str) -> Result<bool> {
+            Err(PageStoreError::Network("node unreachable".into()))
+        }
+        fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+            Err(PageStoreError::Network("node unreachable".into()))
+        }
+    }
+
+    #[test]
+    fn test_publish_to_all() {
+        let primary = Arc::new(MockNetworkBackend::new());
+        let mirror = Arc::new(MockNetworkBackend::new());
+        let backend = MirroredBackend::new(primary.clone()).with_mirror(mirror.clone());
+
+        let cid = backend.publish_page(b"mirrored").unwrap();
+        assert_eq!(primary.fetch_page(&cid).unwrap(), b"mirrored");
+        assert_eq!(mirror.fetch_page(&cid).unwrap(), b"mirrored");
+
+        let cid = backend.publish_stream(&mut &b"streamed"[..]).unwrap();
+        assert_eq!(mirror.fetch_page(&cid).unwrap(), b"streamed");
+    }
+
+    #[test]
+    fn test_spool_in_temp_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mirror = Arc::new(MockNetworkBackend::new());
+        let backend = MirroredBackend::new(MockNetworkBackend::new())
+            .with_mirror(mirror.clone())
+            .with_temp_dir(tmp.path().join("missing"));
+        assert!(backend.publish_stream(&mut &b"streamed"[..]).is_err());
+
+        let backend = backend.with_temp_dir(tmp.path());
+        let cid = backend.publish_stream(&mut &b"streamed"[..]).unwrap();
+        assert_eq!(mirror.fetch_page(&cid).unwrap(), b"streamed");
+        let mut out = Vec::new();
+        backend.fetch_stream(&cid, &mut out).unwrap();
+        assert_eq!(out, b"streamed");
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_fetch_falls_back_to_mirror() {
+        let mirror = Arc::new(MockNetworkBackend::new());
+        let cid = mirror.publish_page(b"only on mirror").unwrap();
+        let backend = MirroredBackend::new(DownBackend).with_mirror(mirror);
+
+        assert_eq!(backend.fetch_page(&cid).unwrap(), b"only on mirror");
+        let mut out = Vec::new();
+        backend.fetch_stream(&cid, &mut out).unwrap();
+        assert_eq!(out, b"only on mirror");
+    }
+
+    #[test]
+    fn test_write_quorum() {
+        let mirror = Arc::new(MockNetworkBackend::new());
+
+        // Default quorum is every backend
+        let strict = MirroredBackend::new(DownBackend).with_mirror(mirror.clone());
+        let err = strict.set_root(Cid([1; 32])).unwrap_err();
+        assert!(matches!(err, PageStoreError::Network(_)), "{err}");
+
+        let relaxed = MirroredBackend::new(DownBackend)
+            .with_mirror(mirror.clone())
+            .with_write_quorum(1);
+        relaxed.set_root(Cid([1; 32])).unwrap();
+        assert_eq!(relaxed.get_root().unwrap(), Some(Cid([1; 32])));
+    }
+
+    #[test]
+    fn test_root_conflict_rolls_back_mirrors() {
+        let primary = Arc::new(MockNetworkBackend::new());
+        let mirror = Arc::new(MockNetworkBackend::new());
+        let backend = MirroredBackend::new(primary.clone()).with_mirror(mirror.clone());
+
+        // Another writer got to the mirror first: the primary follows it
+        mirror.set_root(Cid([1; 32])).unwrap();
+        let err = backend.set_root_if(None, Cid([2; 32])).unwrap_err();
+        assert!(matches!(err, PageStoreError::RootConflict { expected: None, actual: Some(actual) } if actual == Cid([1; 32])));
+        assert_eq!(primary.get_root().unwrap(), Some(Cid([1; 32])));
+
+        // And a retry from the root that won goes through everywhere
+        backend.set_root_if(Some(Cid([1; 32])), Cid([3; 32])).unwrap();
+        mirror.set_root(Cid([4; 32])).unwrap();
+        assert!(backend.set_root_if(Some(Cid([3; 32])), Cid([5; 32])).is_err());
+        assert_eq!(primary.get_root().unwrap(), Some(Cid([4; 32])));
+        assert_eq!(backend.get_root().unwrap(), mirror.get_root().unwrap());
+    }
+
+    #[test]
+    fn test_store_survives_primary_outage() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mirror = Arc::new(MockNetworkBackend::new());
+        let backend = MirroredBackend::new(DownBackend)
+            .with_mirror(mirror.clone())
+            .with_write_quorum(1);
+        let store = CraftObjPageStore::new(tmp.path(), backend).unwrap();
+
+        let cid = store.put(&Page { data: vec![5u8; 4096] }).unwrap();
+        let mut pt = PageTable::new();
+        pt.set(0, cid);
+        let pt_data = pt.to_bytes();
+        let pt_cid = Cid::from_bytes(&pt_data);
+        store.put(&Page { data: pt_data }).unwrap();
+        store.update_root(pt_cid).unwrap();
+
+        assert_eq!(mirror.publish_count.load(Ordering::Relaxed), 1);
+        assert_eq!(store.current_root().unwrap(), mirror.get_root().unwrap());
+    }
+}
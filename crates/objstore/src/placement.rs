@@ -0,0 +1,73 @@
+//! Durability of published content: how CraftOBJ should encode and spread
+//! it ([`PublishOptions`]), and where it ended up ([`Placement`]).
+//!
+//! CraftOBJ erasure-codes content in segments: each segment is split into
+//! pieces, any `needed` of which rebuild it, and the pieces are spread over
+//! peers. Backends without such encoding ignore the options and report no
+//! placement.
+
+/// How CraftOBJ should encode and spread published content. Unset fields
+/// leave the network's defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PublishOptions {
+    /// Peers each piece is stored on.
+    pub replication: Option<u32>,
+    /// Bytes per erasure-coded segment. Unrelated to
+    /// [`CraftObjPageStore::with_segment_size`](crate::CraftObjPageStore::with_segment_size),
+    /// which splits bundles into separate objects.
+    pub segment_size: Option<u64>,
+}
+
+impl PublishOptions {
+    pub fn with_replication(mut self, replication: u32) -> Self {
+        self.replication = Some(replication.max(1));
+        self
+    }
+
+    pub fn with_segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = Some(bytes.max(1));
+        self
+    }
+}
+
+/// How many pieces of one erasure-coded segment are still out there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentHealth {
+    /// Pieces some peer holds.
+    pub pieces: u32,
+    /// Pieces it takes to rebuild the segment.
+    pub needed: u32,
+}
+
+impl SegmentHealth {
+    pub fn is_recoverable(&self) -> bool {
+        self.pieces >= self.needed
+    }
+}
+
+/// Where published content is stored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Placement {
+    /// Peers holding pieces of it.
+    pub providers: Vec<String>,
+    /// Its erasure-coded segments, in order.
+    pub segments: Vec<SegmentHealth>,
+}
+
+impl Placement {
+    /// Whether every segment can still be rebuilt.
+    pub fn is_recoverable(&self) -> bool {
+        self.segments.iter().all(SegmentHealth::is_recoverable)
+    }
+
+    /// Fold in the placement of another object the same content needs, as a
+    /// segmented bundle needs its segments.
+    pub fn merge(&mut self, other: Placement) {
+        for provider in other.providers {
+            if !self.providers.contains(&provider) {
+                self.providers.push(provider);
+            }
+        }
+        self.segments.extend(other.segments);
+    }
+}
@@ -0,0 +1,102 @@
+//! Progress reporting for long-running bundle transfers.
+//!
+//! Attach a [`ProgressObserver`] with [`CraftObjPageStore::with_progress`] to
+//! drive a progress bar during cold syncs and large commits.
+//!
+//! [`CraftObjPageStore::with_progress`]: crate::CraftObjPageStore::with_progress
+
+use std::io::{Read, Write};
+
+/// Phase of a bundle transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferStage {
+    /// Packing cached pages into a bundle.
+    Bundling,
+    /// Uploading a bundle to the network.
+    Publishing,
+    /// Downloading a bundle from the network.
+    Fetching,
+    /// Unpacking a bundle's pages into the cache.
+    Unbundling,
+}
+
+/// A single progress update. Totals are `None` when not known up front.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub stage: TransferStage,
+    pub bytes: u64,
+    pub total_bytes: Option<u64>,
+    pub pages: u64,
+    pub total_pages: Option<u64>,
+}
+
+impl Progress {
+    /// Byte-level update with no page counts.
+    pub(crate) fn bytes(stage: TransferStage, bytes: u64, total_bytes: Option<u64>) -> Self {
+        Self { stage, bytes, total_bytes, pages: 0, total_pages: None }
+    }
+}
+
+/// Receives progress updates. Called on the thread doing the transfer, so
+/// implementations should be cheap.
+pub trait ProgressObserver: Send + Sync {
+    fn on_progress(&self, progress: &Progress);
+}
+
+impl<F: Fn(&Progress) + Send + Sync> ProgressObserver for F {
+    fn on_progress(&self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+/// Reader that reports the running byte count after every read.
+pub(crate) struct ProgressReader<R, F: FnMut(u64)> {
+    inner: R,
+    bytes: u64,
+    report: F,
+}
+
+impl<R: Read, F: FnMut(u64)> ProgressReader<R, F> {
+    pub(crate) fn new(inner: R, report: F) -> Self {
+        Self { inner, bytes: 0, report }
+    }
+}
+
+impl<R: Read, F: FnMut(u64)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.bytes += n as u64;
+            (self.report)(self.bytes);
+        }
+        Ok(n)
+    }
+}
+
+/// Writer that reports the running byte count after every write.
+pub(crate) struct ProgressWriter<W, F: FnMut(u64)> {
+    inner: W,
+    bytes: u64,
+    report: F,
+}
+
+impl<W: Write, F: FnMut(u64)> ProgressWriter<W, F> {
+    pub(crate) fn new(inner: W, report: F) -> Self {
+        Self { inner, bytes: 0, report }
+    }
+}
+
+impl<W: Write, F: FnMut(u64)> Write for ProgressWriter<W, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.bytes += n as u64;
+            (self.report)(self.bytes);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
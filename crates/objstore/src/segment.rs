@@ -0,0 +1,140 @@
+//! Segmented bundles: a bundle larger than one publish can carry is split
+//! into consecutive segments, and the root points at a manifest listing them.
+//!
+//! Manifest format:
+//! ```text
+//! [magic: 4 bytes "CSQS"]
+//! [version: u16 LE]
+//! [segment_count: u32 LE]
+//! [segment_count × (cid: 32 bytes, len: u64 LE)]
+//! ```
+
+use crate::{NetworkBackend, RateLimit};
+use craftsql_core::{Cid, PageStoreError, Result};
+use std::fs;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Manifest magic bytes.
+const MANIFEST_MAGIC: &[u8; 4] = b"CSQS";
+/// Manifest format version.
+const MANIFEST_VERSION: u16 = 1;
+/// Bytes per manifest entry: CID + length.
+const ENTRY_LEN: usize = 40;
+
+/// The segments of a bundle, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SegmentManifest {
+    pub(crate) segments: Vec<(Cid, u64)>,
+}
+
+impl SegmentManifest {
+    /// Hash consecutive `segment_size` slices of a file.
+    pub(crate) fn from_file(path: &Path, segment_size: u64) -> Result<Self> {
+        let mut file = fs::File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut segments = Vec::new();
+        let mut offset = 0;
+        while offset < size {
+            let len = segment_size.min(size - offset);
+            segments.push((Cid::from_reader((&mut file).take(len))?, len));
+            offset += len;
+        }
+        Ok(Self { segments })
+    }
+
+    pub(crate) fn total_len(&self) -> u64 {
+        self.segments.iter().map(|(_, len)| len).sum()
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(10 + self.segments.len() * ENTRY_LEN);
+        out.extend_from_slice(MANIFEST_MAGIC);
+        out.extend_from_slice(&MANIFEST_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.segments.len() as u32).to_le_bytes());
+        for (cid, len) in &self.segments {
+            out.extend_from_slice(&cid.0);
+            out.extend_from_slice(&len.to_le_bytes());
+        }
+        out
+    }
+
+    /// Parse a manifest. Returns `None` if `data` is not one at all.
+    pub(crate) fn parse(data: &[u8]) -> Result<Option<Self>> {
+        if data.len() < 4 || &data[0..4] != MANIFEST_MAGIC {
+            return Ok(None);
+        }
+        if data.len() < 10 {
+            return Err(PageStoreError::Storage("segment manifest truncated".into()));
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != MANIFEST_VERSION {
+            return Err(PageStoreError::Storage(format!("unsupported manifest version {}", version)));
+        }
+        let count = u32::from_le_bytes([data[6], data[7], data[8], data[9]]) as usize;
+        let entries = &data[10..];
+        if entries.len() != count * ENTRY_LEN {
+            return Err(PageStoreError::Storage("segment manifest truncated".into()));
+        }
+
+        let segments = entries.chunks_exact(ENTRY_LEN)
+            .map(|entry| {
+                let mut cid = [0u8; 32];
+                cid.copy_from_slice(&entry[..32]);
+                let mut len = [0u8; 8];
+                len.copy_from_slice(&entry[32..]);
+                (Cid(cid), u64::from_le_bytes(len))
+            })
+            .collect();
+        Ok(Some(Self { segments }))
+    }
+}
+
+/// Fetch one segment into `path` at `offset`, then verify it against its CID.
+///
+/// `path` must already be sized to hold it; other segments may be written
+/// concurrently through their own handles.
+pub(crate) fn fetch_segment<N: NetworkBackend + ?Sized>(
+    network: &N,
+    download: &RateLimit,
+    cid: &Cid,
+    path: &Path,
+    offset: u64,
+    len: u64,
+) -> Result<()> {
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut writer = BufWriter::new(file);
+    network.fetch_stream(cid, &mut download.wrap(&mut writer))?;
+    writer.flush()?;
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+    if file.stream_position()? != offset + len {
+        return Err(PageStoreError::Storage(format!("segment {} has the wrong length", cid)));
+    }
+
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let actual = Cid::from_reader(file.take(len))?;
+    if actual != *cid {
+        return Err(PageStoreError::Corruption { expected: *cid, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let manifest = SegmentManifest {
+            segments: vec![(Cid::from_bytes(b"a"), 8192), (Cid::from_bytes(b"b"), 100)],
+        };
+        let bytes = manifest.to_bytes();
+        assert_eq!(SegmentManifest::parse(&bytes).unwrap(), Some(manifest.clone()));
+        assert_eq!(manifest.total_len(), 8292);
+
+        assert_eq!(SegmentManifest::parse(b"CSQL not a manifest").unwrap(), None);
+        assert!(SegmentManifest::parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! Bandwidth limits for background transfers.
+//!
+//! A [`RateLimit`] is a token bucket of bytes: transfers draw from it and
+//! sleep when it runs dry, so a long sync trickles along at the configured
+//! rate instead of saturating a home connection. Clones share one bucket,
+//! so a single limit can cover several stores, and [`RateLimit::set_rate`]
+//! can loosen or tighten it mid-transfer (e.g. when the user goes idle).
+
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Caps transfers at a number of bytes per second. `0` means unlimited.
+///
+/// Up to one second's worth of bytes may go at once after a pause.
+#[derive(Clone, Default)]
+pub struct RateLimit {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Default)]
+struct Bucket {
+    rate: u64,
+    /// Bytes that may go now; negative while callers sleep off a debt.
+    tokens: f64,
+    refilled: Option<Instant>,
+}
+
+impl RateLimit {
+    /// Limit to `bytes_per_sec`, or nothing if it is `0`.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let limit = Self::default();
+        limit.set_rate(bytes_per_sec);
+        limit
+    }
+
+    /// No limit.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// The current limit in bytes per second, `0` if unlimited.
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().unwrap().rate
+    }
+
+    /// Change the limit, for this and every clone; `0` lifts it.
+    pub fn set_rate(&self, bytes_per_sec: u64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.rate = bytes_per_sec;
+        bucket.tokens = bucket.tokens.min(bytes_per_sec as f64);
+    }
+
+    /// Account for `bytes` transferred, sleeping until the limit allows them.
+    ///
+    /// Callers queue fairly: each takes its bytes up front and sleeps off
+    /// whatever debt that leaves, so a transfer larger than the bucket
+    /// still goes through, just slowly.
+    pub fn take(&self, bytes: u64) {
+        if let Some(wait) = self.charge(bytes) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Take `bytes` from the bucket, returning how long to sleep off the
+    /// debt, for callers that can't sleep the thread.
+    pub(crate) fn charge(&self, bytes: u64) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        if bucket.rate == 0 || bytes == 0 {
+            return None;
+        }
+        let rate = bucket.rate as f64;
+        let now = Instant::now();
+        let elapsed = bucket.refilled.map_or(Duration::ZERO, |then| now - then);
+        bucket.tokens = match bucket.refilled {
+            Some(_) => (bucket.tokens + elapsed.as_secs_f64() * rate).min(rate),
+            None => rate,
+        };
+        bucket.refilled = Some(now);
+        bucket.tokens -= bytes as f64;
+        (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / rate))
+    }
+
+    /// Wrap a reader or writer so every byte through it is charged to this limit.
+    pub fn wrap<T>(&self, inner: T) -> Throttled<'_, T> {
+        Throttled { inner, limit: self }
+    }
+}
+
+impl std::fmt::Debug for RateLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimit").field("rate", &self.rate()).finish()
+    }
+}
+
+/// A reader or writer paced by a [`RateLimit`] (see [`RateLimit::wrap`]).
+pub struct Throttled<'a, T> {
+    inner: T,
+    limit: &'a RateLimit,
+}
+
+impl<T: Read> Read for Throttled<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.limit.take(n as u64);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Throttled<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.limit.take(n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let limit = RateLimit::new(100_000);
+        let started = Instant::now();
+        // The first second's worth goes at once, the next 50 KB take half a second
+        limit.take(100_000);
+        assert!(started.elapsed() < Duration::from_millis(100));
+        let mut out = Vec::new();
+        std::io::copy(&mut limit.wrap(&[7u8; 50_000][..]), &mut out).unwrap();
+        assert_eq!(out.len(), 50_000);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(450) && elapsed < Duration::from_secs(2), "{:?}", elapsed);
+
+        // Lifting the limit (through a clone) takes effect at once
+        limit.clone().set_rate(0);
+        let started = Instant::now();
+        limit.take(10_000_000);
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert_eq!(RateLimit::unlimited().rate(), 0);
+    }
+}
@@ -0,0 +1,154 @@
+//! Root updates made of several steps, published as one: see
+//! [`CraftObjPageStore::begin_root_update`].
+
+use std::fmt;
+use std::sync::MutexGuard;
+
+use craftsql_core::{Cid, PageStore, PageStoreError, Result};
+
+use crate::{CraftObjPageStore, NetworkBackend};
+
+/// A root update in progress. While it lives, no other update of the same
+/// store publishes or moves the root, and the staged root and named roots
+/// are applied together when it's [committed](Self::commit), or dropped.
+///
+/// The root moves by compare-and-swap, the named roots after it: if the
+/// swap fails nothing changes, but once it succeeds the root stays moved
+/// even if a named root then can't be set. [`RootUpdateError::NamedRoots`]
+/// reports which ones were left behind.
+pub struct RootUpdate<'a, N: NetworkBackend> {
+    store: &'a CraftObjPageStore<N>,
+    last_published: MutexGuard<'a, Option<Cid>>,
+    base: Option<Cid>,
+    root: Option<Cid>,
+    /// Named roots to set, with `None` for the root this update publishes.
+    named: Vec<(String, Option<Cid>)>,
+    done: bool,
+}
+
+impl<'a, N: NetworkBackend> RootUpdate<'a, N> {
+    pub(crate) fn new(store: &'a CraftObjPageStore<N>) -> Result<Self> {
+        let last_published = store.last_published.lock().unwrap();
+        let base = store.local.current_root()?;
+        Ok(Self { store, last_published, base, root: None, named: Vec::new(), done: false })
+    }
+
+    /// The root when the update began: the bundle (or page table) the new
+    /// root must still replace on the network for the update to commit.
+    pub fn base(&self) -> Option<Cid> {
+        self.base
+    }
+
+    /// Publish the page table `page_table` as the new root on commit.
+    pub fn set_root(&mut self, page_table: Cid) {
+        self.root = Some(page_table);
+    }
+
+    /// Point `name` at `cid` on commit, once the root has moved.
+    pub fn set_named_root(&mut self, name: &str, cid: Cid) {
+        self.named.push((name.to_string(), Some(cid)));
+    }
+
+    /// Point `name` at the root this update publishes, or at the base if
+    /// it publishes none.
+    pub fn name_root(&mut self, name: &str) {
+        self.named.push((name.to_string(), None));
+    }
+
+    /// Publish the new root and move the network root to it if it's still
+    /// the base, failing with
+    /// [`RootConflict`](PageStoreError::RootConflict) and setting no named
+    /// roots if not; then set the named roots, trying every one even if some
+    /// fail. Returns the root.
+    pub fn commit(mut self) -> std::result::Result<Option<Cid>, RootUpdateError> {
+        self.apply()
+    }
+
+    /// Drop the update without applying anything. Pages already put stay
+    /// in the cache for garbage collection.
+    pub fn abort(mut self) {
+        self.done = true;
+    }
+
+    fn apply(&mut self) -> std::result::Result<Option<Cid>, RootUpdateError> {
+        self.done = true;
+        let root = match self.root {
+            Some(page_table) => self.store.commit_root(page_table, self.base, &mut self.last_published)
+                .map_err(RootUpdateError::Root)?,
+            None => self.base,
+        };
+        let mut unset = Vec::new();
+        let mut first_error = None;
+        for (name, cid) in std::mem::take(&mut self.named) {
+            let Some(cid) = cid.or(root) else { continue };
+            if let Err(e) = self.store.set_named_root(&name, cid) {
+                first_error.get_or_insert(e);
+                unset.push((name, cid));
+            }
+        }
+        match first_error {
+            Some(source) => Err(RootUpdateError::NamedRoots { root, unset, source: Box::new(source) }),
+            None => Ok(root),
+        }
+    }
+}
+
+/// Commits an update that was neither committed nor aborted, logging
+/// failures: call [`commit`](RootUpdate::commit) to see them. Unwinding
+/// from a panic, the update is aborted instead.
+impl<N: NetworkBackend> Drop for RootUpdate<'_, N> {
+    fn drop(&mut self) {
+        if !self.done && !std::thread::panicking() {
+            if let Err(e) = self.apply() {
+                tracing::warn!(error = %e, "failed to commit root update");
+            }
+        }
+    }
+}
+
+/// Why a [`RootUpdate`] didn't commit, or only partly did.
+#[derive(Debug)]
+pub enum RootUpdateError {
+    /// The root didn't move, and no named roots were set.
+    Root(PageStoreError),
+    /// The root moved to `root`, but the named roots in `unset` weren't
+    /// pointed at their CIDs; `source` is the first failure. The other named
+    /// roots were set.
+    NamedRoots {
+        root: Option<Cid>,
+        unset: Vec<(String, Cid)>,
+        source: Box<PageStoreError>,
+    },
+}
+
+impl fmt::Display for RootUpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Root(e) => e.fmt(f),
+            Self::NamedRoots { unset, source, .. } => {
+                let names: Vec<&str> = unset.iter().map(|(name, _)| name.as_str()).collect();
+                write!(f, "root moved but named roots not set ({}): {}", names.join(", "), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RootUpdateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Root(e) => Some(e),
+            Self::NamedRoots { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+/// The underlying store error, for callers that don't need to know which
+/// named roots were left behind.
+impl From<RootUpdateError> for PageStoreError {
+    fn from(e: RootUpdateError) -> Self {
+        match e {
+            RootUpdateError::Root(e) => e,
+            RootUpdateError::NamedRoots { source, .. } => *source,
+        }
+    }
+}
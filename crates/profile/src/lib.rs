@@ -0,0 +1,393 @@
+//! CraftSQL Profile — which pages a query read, and what they cost.
+//!
+//! Over a network-backed store, a query is slow when it reads pages the
+//! cache doesn't hold, and nothing SQLite reports shows which. A
+//! [`Profiler`] attached to a connection records, per statement, the pages
+//! read from the store the VFS is registered over, how many of those reads
+//! missed the cache and went to the backend, and the time spent in each.
+//!
+//! Register the VFS over a [`ProfiledStore::new`] and, to count misses, put
+//! a [`ProfiledStore::backend`] under the cache, between it and the remote
+//! store. Then [`Profiler::attach`] the connection and, after running the
+//! queries, print its [`Profiler::report`].
+//!
+//! SQLite's trace hook carries no state of its own, so store calls are
+//! tallied per thread and charged to the statement that finishes next on
+//! it. Attach on the thread that runs the connection's statements. Calls
+//! made on other threads, such as background prefetches, aren't counted,
+//! and statements stepped side by side share their reads with whichever
+//! finishes first. Calls made while no statement runs, such as loading the
+//! page table when a connection opens, are reported as
+//! [`ProfileReport::outside`].
+
+mod store;
+
+pub use store::ProfiledStore;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use craftsql_core::Cid;
+use rusqlite::trace::{TraceEvent, TraceEventCodes};
+use rusqlite::Connection;
+
+/// The reads of one page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageReads {
+    pub cid: Cid,
+    pub reads: u64,
+    /// Reads that went to the backend.
+    pub misses: u64,
+    /// Time spent reading it from the store.
+    pub time: Duration,
+}
+
+/// Store calls made by one statement, or outside of any.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Calls {
+    /// Calls to the store the VFS is registered over, page reads included.
+    pub store_calls: u64,
+    pub store_time: Duration,
+    /// Calls that reached the backend under the cache.
+    pub backend_calls: u64,
+    pub backend_time: Duration,
+    /// The pages read, most time first.
+    pub pages: Vec<PageReads>,
+}
+
+impl Calls {
+    /// Page reads from the store.
+    pub fn reads(&self) -> u64 {
+        self.pages.iter().map(|page| page.reads).sum()
+    }
+
+    /// Page reads that missed the cache.
+    pub fn misses(&self) -> u64 {
+        self.pages.iter().map(|page| page.misses).sum()
+    }
+}
+
+/// What one statement cost, over all its runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementProfile {
+    pub sql: String,
+    pub runs: u64,
+    /// Time SQLite spent running it, store calls included.
+    pub elapsed: Duration,
+    pub calls: Calls,
+}
+
+/// What a [`Profiler`] has recorded so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileReport {
+    /// Statements by time spent in the store, most first.
+    pub statements: Vec<StatementProfile>,
+    /// Calls made while no statement ran.
+    pub outside: Calls,
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn ms(time: Duration) -> String {
+            format!("{:.1}ms", time.as_secs_f64() * 1000.0)
+        }
+        let row = |f: &mut fmt::Formatter<'_>, elapsed: String, runs: String, calls: &Calls, sql: &str| {
+            writeln!(
+                f,
+                "{:>10} {:>10} {:>10} {:>7} {:>7} {:>6}  {}",
+                elapsed,
+                ms(calls.store_time),
+                ms(calls.backend_time),
+                calls.reads(),
+                calls.misses(),
+                runs,
+                sql
+            )
+        };
+        writeln!(f, "{:>10} {:>10} {:>10} {:>7} {:>7} {:>6}  statement", "time", "store", "backend", "reads", "misses", "runs")?;
+        for statement in &self.statements {
+            let sql = statement.sql.split_whitespace().collect::<Vec<_>>().join(" ");
+            row(f, ms(statement.elapsed), statement.runs.to_string(), &statement.calls, &sql)?;
+        }
+        if self.outside.store_calls + self.outside.backend_calls > 0 {
+            row(f, "-".into(), "-".into(), &self.outside, "(outside statements)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Store calls as they are tallied.
+#[derive(Default)]
+struct Tally {
+    store_calls: u64,
+    store_time: Duration,
+    backend_calls: u64,
+    backend_time: Duration,
+    pages: HashMap<Cid, PageReads>,
+}
+
+impl Tally {
+    fn add(&mut self, other: Tally) {
+        self.store_calls += other.store_calls;
+        self.store_time += other.store_time;
+        self.backend_calls += other.backend_calls;
+        self.backend_time += other.backend_time;
+        for (cid, page) in other.pages {
+            let entry = self.pages.entry(cid).or_insert(PageReads { cid, reads: 0, misses: 0, time: Duration::ZERO });
+            entry.reads += page.reads;
+            entry.misses += page.misses;
+            entry.time += page.time;
+        }
+    }
+
+    fn to_calls(&self) -> Calls {
+        let mut pages: Vec<PageReads> = self.pages.values().cloned().collect();
+        pages.sort_by_key(|page| std::cmp::Reverse(page.time));
+        Calls {
+            store_calls: self.store_calls,
+            store_time: self.store_time,
+            backend_calls: self.backend_calls,
+            backend_time: self.backend_time,
+            pages,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// By SQL text: runs, time, and calls.
+    statements: HashMap<String, (u64, Duration, Tally)>,
+    outside: Tally,
+}
+
+/// The profiler attached on this thread, and what it's tallied since the
+/// last statement finished.
+struct Thread {
+    state: Weak<Mutex<State>>,
+    /// Statements started and not yet finished.
+    running: usize,
+    tally: Tally,
+    /// Inside a page read, and whether it has reached the backend.
+    reading: Option<bool>,
+}
+
+impl Thread {
+    /// Charge the tally to `sql`, or to no statement.
+    fn flush(&mut self, statement: Option<(&str, Duration)>) {
+        let tally = std::mem::take(&mut self.tally);
+        let Some(state) = self.state.upgrade() else { return };
+        let mut state = state.lock().unwrap();
+        match statement {
+            Some((sql, elapsed)) => {
+                let (runs, time, total) = state.statements.entry(sql.to_string()).or_default();
+                *runs += 1;
+                *time += elapsed;
+                total.add(tally);
+            }
+            None => state.outside.add(tally),
+        }
+    }
+}
+
+thread_local! {
+    static THREAD: RefCell<Option<Thread>> = const { RefCell::new(None) };
+}
+
+/// Which layer a [`ProfiledStore`] wraps.
+#[derive(Clone, Copy)]
+pub(crate) enum Layer {
+    Store,
+    Backend,
+}
+
+/// Tally a call through `layer` that took `time`, if this thread is being
+/// profiled.
+pub(crate) fn record_call(layer: Layer, time: Duration) {
+    THREAD.with(|thread| {
+        let mut thread = thread.borrow_mut();
+        let Some(thread) = thread.as_mut() else { return };
+        match layer {
+            Layer::Store => {
+                thread.tally.store_calls += 1;
+                thread.tally.store_time += time;
+            }
+            Layer::Backend => {
+                thread.tally.backend_calls += 1;
+                thread.tally.backend_time += time;
+                if let Some(missed) = thread.reading.as_mut() {
+                    *missed = true;
+                }
+            }
+        }
+    });
+}
+
+/// Mark the start of a page read from the store.
+pub(crate) fn start_read() {
+    THREAD.with(|thread| {
+        if let Some(thread) = thread.borrow_mut().as_mut() {
+            thread.reading = Some(false);
+        }
+    });
+}
+
+/// Tally the page read since [`start_read`] of page `cid`.
+pub(crate) fn finish_read(cid: Cid, time: Duration) {
+    THREAD.with(|thread| {
+        let mut thread = thread.borrow_mut();
+        let Some(thread) = thread.as_mut() else { return };
+        let missed = thread.reading.take().unwrap_or(false);
+        let page = thread.tally.pages.entry(cid).or_insert(PageReads { cid, reads: 0, misses: 0, time: Duration::ZERO });
+        page.reads += 1;
+        page.misses += u64::from(missed);
+        page.time += time;
+    });
+}
+
+fn on_trace(event: TraceEvent<'_>) {
+    THREAD.with(|thread| {
+        let mut thread = thread.borrow_mut();
+        let Some(thread) = thread.as_mut() else { return };
+        match event {
+            // Trigger subprograms start with a comment naming the trigger,
+            // and finish with the statement that fired them
+            TraceEvent::Stmt(_, sql) if sql.starts_with("--") => {}
+            TraceEvent::Stmt(..) => {
+                if thread.running == 0 {
+                    thread.flush(None);
+                }
+                thread.running += 1;
+            }
+            TraceEvent::Profile(stmt, elapsed) => {
+                thread.running = thread.running.saturating_sub(1);
+                thread.flush(Some((&stmt.sql(), elapsed)));
+            }
+            _ => {}
+        }
+    });
+}
+
+/// Records where the statements of attached connections spend their time
+/// (see the [crate docs](crate)).
+#[derive(Clone, Default)]
+pub struct Profiler {
+    state: Arc<Mutex<State>>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Profile `db`'s statements run on this thread, replacing its trace
+    /// hook and any profiler attached on this thread before.
+    pub fn attach(&self, db: &Connection) {
+        let thread = Thread { state: Arc::downgrade(&self.state), running: 0, tally: Tally::default(), reading: None };
+        THREAD.with(|current| {
+            if let Some(mut previous) = current.borrow_mut().replace(thread) {
+                previous.flush(None);
+            }
+        });
+        db.trace_v2(TraceEventCodes::SQLITE_TRACE_STMT | TraceEventCodes::SQLITE_TRACE_PROFILE, Some(on_trace));
+    }
+
+    /// Stop profiling `db` and this thread.
+    pub fn detach(&self, db: &Connection) {
+        db.trace_v2(TraceEventCodes::empty(), None);
+        THREAD.with(|current| {
+            let mut current = current.borrow_mut();
+            if current.as_ref().is_some_and(|thread| thread.state.ptr_eq(&Arc::downgrade(&self.state))) {
+                if let Some(mut thread) = current.take() {
+                    thread.flush(None);
+                }
+            }
+        });
+    }
+
+    /// What's been recorded so far, including this thread's calls since
+    /// its last statement.
+    pub fn report(&self) -> ProfileReport {
+        THREAD.with(|current| {
+            if let Some(thread) = current.borrow_mut().as_mut() {
+                if thread.running == 0 && thread.state.ptr_eq(&Arc::downgrade(&self.state)) {
+                    thread.flush(None);
+                }
+            }
+        });
+        let state = self.state.lock().unwrap();
+        let mut statements: Vec<StatementProfile> = state.statements.iter()
+            .map(|(sql, (runs, elapsed, tally))| StatementProfile {
+                sql: sql.clone(),
+                runs: *runs,
+                elapsed: *elapsed,
+                calls: tally.to_calls(),
+            })
+            .collect();
+        statements.sort_by(|a, b| b.calls.store_time.cmp(&a.calls.store_time).then_with(|| b.elapsed.cmp(&a.elapsed)));
+        ProfileReport { statements, outside: state.outside.to_calls() }
+    }
+
+    /// Forget everything recorded so far.
+    pub fn reset(&self) {
+        *self.state.lock().unwrap() = State::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::{Page, PageStore};
+    use craftsql_store_cached::{CacheConfig, CachingPageStore};
+    use craftsql_store_mem::MemPageStore;
+    use rusqlite::functions::FunctionFlags;
+
+    #[test]
+    fn test_profile_statements() {
+        let tmp = tempfile::tempdir().unwrap();
+        let remote = MemPageStore::new();
+        let cids: Vec<Cid> = (0..3u8).map(|i| remote.put(&Page { data: vec![i; 4096] }).unwrap()).collect();
+        let config = CacheConfig { root_ttl: None, ..CacheConfig::default() };
+        let cached = CachingPageStore::new(tmp.path(), ProfiledStore::backend(remote), config).unwrap();
+        let store = Arc::new(ProfiledStore::new(cached));
+
+        // Stands in for the VFS: reads pages from the store while a
+        // statement runs
+        let db = Connection::open_in_memory().unwrap();
+        let (reader, pages) = (Arc::clone(&store), cids.clone());
+        db.create_scalar_function("page", 1, FunctionFlags::SQLITE_UTF8, move |ctx| {
+            let i: usize = ctx.get(0)?;
+            let page = reader.get(&pages[i]).map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+            Ok(page.data[0] as i64)
+        }).unwrap();
+
+        let profiler = Profiler::new();
+        profiler.attach(&db);
+        let sum = |db: &Connection| -> i64 { db.query_row("SELECT page(1) + page(2)", [], |r| r.get(0)).unwrap() };
+        assert_eq!(sum(&db), 3);
+        assert_eq!(sum(&db), 3);
+        db.query_row("SELECT page(0)", [], |r| r.get::<_, i64>(0)).unwrap();
+        store.get(&cids[0]).unwrap();
+
+        let report = profiler.report();
+        assert_eq!(report.statements.len(), 2);
+        let statement = report.statements.iter().find(|s| s.sql == "SELECT page(1) + page(2)").unwrap();
+        assert_eq!(statement.runs, 2);
+        // Missed the cache the first time only
+        assert_eq!((statement.calls.reads(), statement.calls.misses()), (4, 2));
+        assert_eq!(statement.calls.pages.len(), 2);
+        assert!(statement.calls.pages.iter().all(|page| page.reads == 2 && page.misses == 1));
+        assert!(statement.calls.backend_calls >= 2);
+        assert!(statement.calls.store_time >= statement.calls.pages.iter().map(|page| page.time).sum());
+        assert_eq!((report.outside.reads(), report.outside.misses()), (1, 0));
+        assert!(report.to_string().contains("(outside statements)"));
+
+        // Nothing after detaching
+        profiler.detach(&db);
+        profiler.reset();
+        sum(&db);
+        assert_eq!(profiler.report(), ProfileReport::default());
+    }
+}
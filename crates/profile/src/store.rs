@@ -0,0 +1,114 @@
+//! The store wrapper that times every call.
+
+use std::time::Instant;
+
+use craftsql_core::{Cid, CommitMeta, Page, PageStore, Result, RootSignal};
+
+use crate::{finish_read, record_call, start_read, Layer};
+
+/// A store whose calls are charged to the statement running on the calling
+/// thread, when a [`Profiler`](crate::Profiler) is attached there.
+pub struct ProfiledStore<S> {
+    inner: S,
+    layer: Layer,
+}
+
+impl<S: PageStore> ProfiledStore<S> {
+    /// Wrap the store the VFS is registered over: its page reads are the
+    /// pages a statement read.
+    pub fn new(inner: S) -> Self {
+        Self { inner, layer: Layer::Store }
+    }
+
+    /// Wrap the backend under a cache: a page read that calls it missed
+    /// the cache.
+    pub fn backend(inner: S) -> Self {
+        Self { inner, layer: Layer::Backend }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn timed<T>(&self, call: impl FnOnce(&S) -> T) -> T {
+        let start = Instant::now();
+        let result = call(&self.inner);
+        record_call(self.layer, start.elapsed());
+        result
+    }
+}
+
+impl<S: PageStore> PageStore for ProfiledStore<S> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        if let Layer::Backend = self.layer {
+            return self.timed(|inner| inner.get(cid));
+        }
+        start_read();
+        let start = Instant::now();
+        let page = self.inner.get(cid);
+        let time = start.elapsed();
+        record_call(self.layer, time);
+        finish_read(*cid, time);
+        page
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        self.timed(|inner| inner.put(page))
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        self.timed(|inner| inner.put_many(pages))
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        self.timed(|inner| inner.has(cid))
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        self.timed(|inner| inner.update_root(new_root))
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        self.timed(|inner| inner.update_root_with_meta(new_root, meta))
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        self.timed(|inner| inner.current_root())
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.timed(|inner| inner.set_named_root(name, cid))
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        self.timed(|inner| inner.set_named_root_with_meta(name, cid, meta))
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.timed(|inner| inner.get_named_root(name))
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.timed(|inner| inner.remove_named_root(name))
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        self.timed(|inner| inner.list_named_roots())
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        self.timed(|inner| inner.list_pages())
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.timed(|inner| inner.delete_page(cid))
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        self.timed(|inner| inner.delete_many(cids))
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        self.inner.root_signal()
+    }
+}
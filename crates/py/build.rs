@@ -0,0 +1,5 @@
+fn main() {
+    // Register the VFS with the system SQLite, the one Python's sqlite3
+    // module loads, so connections opened from Python can find it.
+    println!("cargo:rustc-link-lib=dylib=sqlite3");
+}
@@ -0,0 +1,245 @@
+//! Python bindings: `import craftsql`.
+//!
+//! A `craftsql.Store` opens the same locations as the `craftsql` command
+//! (a local directory, `unix:<path>`, `tcp://<host:port>`, or `http://...`)
+//! and offers its commands as methods, with CIDs as hex strings:
+//!
+//! ```python
+//! import sqlite3, craftsql
+//!
+//! store = craftsql.Store("./db")
+//! db = sqlite3.connect(store.register(), uri=True)
+//! db.execute("PRAGMA journal_mode=DELETE")
+//! ...
+//! store.commit("main", "load data")
+//! store.push("unix:/run/craftobj.sock", "main")
+//! ```
+//!
+//! [`Store.register`](Store::register) installs the CraftSQL VFS for the
+//! store and returns a URI for `sqlite3.connect(..., uri=True)`; for
+//! SQLAlchemy, use `create_engine("sqlite:///" + uri + "&uri=true")`.
+//! The VFS registers with the SQLite library this module is linked
+//! against, the system `libsqlite3`, which is only the one Python's
+//! `sqlite3` module uses if that links the same shared library (true of
+//! most Linux distributions' Pythons, not of builds with SQLite compiled
+//! in). [`Store.diff`](Store::diff) reads the databases through that
+//! same library: nothing in this module compiles a SQLite of its own.
+//!
+//! Build with `maturin build --release` in this directory.
+
+use std::collections::BTreeMap;
+use std::io::sink;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use craftsql_cli::refs::{self, resolve};
+use craftsql_cli::store::StoreSpec;
+use craftsql_cli::{commands, Error};
+use craftsql_core::{page_table_root, Cid, PageStore};
+use craftsql_sync::Remote;
+use craftsql_vfs::{RootControl, DEFAULT_EXCLUSIVE_WAIT};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+
+create_exception!(craftsql, CraftsqlError, PyException, "A CraftSQL store or command failed.");
+
+fn to_py(e: impl Into<Error>) -> PyErr {
+    CraftsqlError::new_err(e.into().to_string())
+}
+
+/// A CraftSQL store.
+#[pyclass(module = "craftsql", frozen)]
+struct Store {
+    spec: StoreSpec,
+    store: Arc<dyn PageStore>,
+    /// One per VFS registered for the store.
+    controls: Mutex<Vec<RootControl<dyn PageStore>>>,
+}
+
+impl Store {
+    fn store(&self) -> &dyn PageStore {
+        self.store.as_ref()
+    }
+
+    fn named_root(&self, name: &str) -> PyResult<String> {
+        let cid = self.store().get_named_root(name).map_err(to_py)?;
+        Ok(cid.ok_or_else(|| to_py(Error::UnknownRef(name.to_string())))?.to_hex())
+    }
+
+    fn ref_map(refs: Vec<(String, Cid)>) -> BTreeMap<String, String> {
+        refs.into_iter().map(|(name, cid)| (name, cid.to_hex())).collect()
+    }
+
+    /// Open `remote` as the other side of a push or pull.
+    fn remote(&self, remote: &str, cache_dir: Option<PathBuf>) -> PyResult<(StoreSpec, Box<dyn PageStore>)> {
+        let spec = StoreSpec::parse(remote);
+        let store = spec.open(cache_dir.as_deref()).map_err(to_py)?;
+        Ok((spec, store))
+    }
+}
+
+#[pymethods]
+impl Store {
+    /// Open the store at `spec`. Daemon-backed stores cache pages in
+    /// `cache_dir`, or a per-location temp directory.
+    #[new]
+    #[pyo3(signature = (spec, cache_dir=None))]
+    fn new(spec: &str, cache_dir: Option<PathBuf>) -> PyResult<Self> {
+        let spec = StoreSpec::parse(spec);
+        let store = spec.open(cache_dir.as_deref()).map_err(to_py)?;
+        Ok(Self { spec, store: store.into(), controls: Mutex::default() })
+    }
+
+    /// The current root, or None for an empty store.
+    #[getter]
+    fn root(&self) -> PyResult<Option<String>> {
+        Ok(self.store().current_root().map_err(to_py)?.map(|cid| cid.to_hex()))
+    }
+
+    /// The CID a branch, snapshot, or CID prefix names.
+    fn resolve(&self, rev: &str) -> PyResult<String> {
+        Ok(resolve(self.store(), rev).map_err(to_py)?.to_hex())
+    }
+
+    /// Snapshot `from_` (default: the current root) as `name`; returns its CID.
+    #[pyo3(signature = (name, from_=None))]
+    fn snapshot(&self, name: &str, from_: Option<&str>) -> PyResult<String> {
+        commands::snapshot_create(self.store(), name, from_, &mut sink()).map_err(to_py)?;
+        self.named_root(&refs::snapshot_ref(name))
+    }
+
+    /// Snapshot names and CIDs.
+    fn snapshots(&self) -> PyResult<BTreeMap<String, String>> {
+        Ok(Self::ref_map(refs::snapshots(self.store()).map_err(to_py)?))
+    }
+
+    fn delete_snapshot(&self, name: &str) -> PyResult<()> {
+        commands::snapshot_delete(self.store(), name, &mut sink()).map_err(to_py)
+    }
+
+    /// Create branch `name` at `start` (default: the current root), or move
+    /// it if `force`; returns its CID.
+    #[pyo3(signature = (name, start=None, force=false))]
+    fn branch(&self, name: &str, start: Option<&str>, force: bool) -> PyResult<String> {
+        commands::branch_create(self.store(), name, start, force, &mut sink()).map_err(to_py)?;
+        self.named_root(name)
+    }
+
+    /// Branch names and CIDs.
+    fn branches(&self) -> PyResult<BTreeMap<String, String>> {
+        Ok(Self::ref_map(refs::branches(self.store()).map_err(to_py)?))
+    }
+
+    fn delete_branch(&self, name: &str) -> PyResult<()> {
+        commands::branch_delete(self.store(), name, &mut sink()).map_err(to_py)
+    }
+
+    /// Point the current root at a branch, snapshot, or CID; returns the
+    /// new root. Waits for connections opened through the store's VFSes to
+    /// finish their transactions, and fails if they don't within seconds;
+    /// they read the new root from their next one.
+    fn checkout(&self, py: Python<'_>, rev: &str) -> PyResult<String> {
+        py.allow_threads(|| {
+            let controls = self.controls.lock().unwrap();
+            exclusive(&controls, || commands::checkout(self.store(), rev, &mut sink()))
+                .ok_or_else(|| CraftsqlError::new_err("a connection kept its transaction open"))?
+                .map_err(to_py)
+        })?;
+        Ok(self.root()?.unwrap_or_default())
+    }
+
+    /// Commit the current root onto `branch`; returns the commit's CID.
+    #[pyo3(signature = (branch, message="", author=""))]
+    fn commit(&self, branch: &str, message: &str, author: &str) -> PyResult<String> {
+        commands::commit(self.store(), branch, message, author, &mut sink()).map_err(to_py)?;
+        self.named_root(branch)
+    }
+
+    /// The row changes from `old` to `new`, in the same form as
+    /// `craftsql diff --json`.
+    fn diff(&self, py: Python<'_>, old: &str, new: &str) -> PyResult<PyObject> {
+        let json = py.allow_threads(|| -> PyResult<String> {
+            let roots = || -> craftsql_cli::Result<_> {
+                let (old, new) = (resolve(self.store(), old)?, resolve(self.store(), new)?);
+                Ok((page_table_root(self.store(), &old)?, page_table_root(self.store(), &new)?))
+            };
+            let (old, new) = roots().map_err(to_py)?;
+            // The CLI is built without `sql`, so its error has no diff variant
+            let diff = craftsql_diff::diff_roots(Arc::clone(&self.store), old, new)
+                .map_err(|e| CraftsqlError::new_err(e.to_string()))?;
+            Ok(diff.to_json())
+        })?;
+        Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+    }
+
+    /// Push `branch` to the store at `remote`; returns the remote branch's
+    /// old and new CIDs.
+    #[pyo3(signature = (remote, branch, force=false, cache_dir=None))]
+    fn push(
+        &self,
+        py: Python<'_>,
+        remote: &str,
+        branch: &str,
+        force: bool,
+        cache_dir: Option<PathBuf>,
+    ) -> PyResult<(Option<String>, String)> {
+        let (spec, remote) = self.remote(remote, cache_dir)?;
+        let update = py.allow_threads(|| {
+            let remote = Remote { name: &spec.id(), store: remote.as_ref() };
+            craftsql_sync::push(self.store(), remote, branch, force, &mut |_| {})
+        }).map_err(to_py)?;
+        Ok((update.old.map(|cid| cid.to_hex()), update.new.to_hex()))
+    }
+
+    /// Pull `branch` from the store at `remote`; returns the local branch's
+    /// old and new CIDs.
+    #[pyo3(signature = (remote, branch, force=false, cache_dir=None))]
+    fn pull(
+        &self,
+        py: Python<'_>,
+        remote: &str,
+        branch: &str,
+        force: bool,
+        cache_dir: Option<PathBuf>,
+    ) -> PyResult<(Option<String>, String)> {
+        let (spec, remote) = self.remote(remote, cache_dir)?;
+        let update = py.allow_threads(|| {
+            let remote = Remote { name: &spec.id(), store: remote.as_ref() };
+            craftsql_sync::pull(self.store(), remote, branch, force, &mut |_| {})
+        }).map_err(to_py)?;
+        Ok((update.old.map(|cid| cid.to_hex()), update.new.to_hex()))
+    }
+
+    /// Register the CraftSQL VFS for this store under `name` (default: one
+    /// derived from the store's location) and return the URI to open the
+    /// database at its current root with. Committed transactions move the
+    /// store's root. Registrations last for the life of the process.
+    #[pyo3(signature = (name=None))]
+    fn register(&self, name: Option<String>) -> PyResult<String> {
+        let name = name.unwrap_or_else(|| format!("craftsql_{}", self.spec.id()));
+        let control = craftsql_vfs::register_with_control(&name, Arc::clone(&self.store))
+            .map_err(|e| CraftsqlError::new_err(format!("register VFS {}: {:?}", name, e)))?;
+        self.controls.lock().unwrap().push(control);
+        Ok(format!("file:/craftsql/{}/db?vfs={}", name, name))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("craftsql.Store({:?})", self.spec)
+    }
+}
+
+/// Run `f` holding every one of `controls`' exclusive locks.
+fn exclusive<T>(controls: &[RootControl<dyn PageStore>], f: impl FnOnce() -> T) -> Option<T> {
+    match controls.split_first() {
+        Some((first, rest)) => first.exclusive(DEFAULT_EXCLUSIVE_WAIT, |_| exclusive(rest, f)).flatten(),
+        None => Some(f()),
+    }
+}
+
+#[pymodule]
+fn craftsql(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Store>()?;
+    m.add("CraftsqlError", m.py().get_type::<CraftsqlError>())?;
+    Ok(())
+}
@@ -0,0 +1,274 @@
+//! CraftSQL Replicator — keeping one store a live copy of another.
+//!
+//! A [`Replicator`] watches a source store's current root and named roots
+//! by polling them. Whenever they change it copies the pages the destination
+//! lacks (see [`copy_roots`]), then moves the destination's roots to match.
+//! Names starting with `.` are bookkeeping and are not replicated. Names
+//! the destination has of its own are left alone unless the source sets
+//! them too.
+//!
+//! What was last applied is stored in the destination under [`state_ref`],
+//! so a restarted replicator only copies what changed since, and a round cut
+//! short is simply redone: pages already copied are skipped.
+//!
+//! Lag is measured from the source's side: after a round, the destination
+//! matches the source as it was when the round started, and
+//! [`Replicator::lag`] is how long ago that was.
+
+mod state;
+
+pub use state::{state_ref, ReplicaState};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use craftsql_core::{Cid, PageStore};
+use craftsql_sync::{copy_roots, Remote, Result, TransferStats};
+
+/// What one round of replication changed in the destination.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Round {
+    /// Names moved to a new value.
+    pub refs_set: Vec<String>,
+    /// Names the source no longer has.
+    pub refs_removed: Vec<String>,
+    pub root_moved: bool,
+    pub stats: TransferStats,
+    /// How long the round took, copying included.
+    pub elapsed: Duration,
+}
+
+impl Round {
+    /// Whether the destination was already up to date.
+    pub fn is_empty(&self) -> bool {
+        self.refs_set.is_empty() && self.refs_removed.is_empty() && !self.root_moved
+    }
+}
+
+/// Running totals since the replicator was created.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub rounds: u64,
+    pub failed_rounds: u64,
+    /// Rounds that changed the destination.
+    pub updates: u64,
+    pub pages_copied: u64,
+    pub bytes_copied: u64,
+    /// How long the last round that changed the destination took.
+    pub last_update_time: Duration,
+    /// The longest any such round took.
+    pub max_update_time: Duration,
+}
+
+/// Replicates one store into another.
+pub struct Replicator<'a> {
+    source: Remote<'a>,
+    dst: &'a dyn PageStore,
+    state_ref: String,
+    state: ReplicaState,
+    metrics: Metrics,
+}
+
+impl<'a> Replicator<'a> {
+    /// Replicate `source` into `dst`, carrying on from the state stored in
+    /// `dst` for a source of that name.
+    pub fn new(source: Remote<'a>, dst: &'a dyn PageStore) -> Result<Self> {
+        let state_ref = state_ref(source.name);
+        let state = ReplicaState::load(dst, &state_ref)?;
+        Ok(Self { source, dst, state_ref, state, metrics: Metrics::default() })
+    }
+
+    pub fn state(&self) -> &ReplicaState {
+        &self.state
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// How far the destination is behind: the time since the source was in
+    /// the state the destination now matches. `None` before the first round
+    /// that ever completed.
+    pub fn lag(&self) -> Option<Duration> {
+        self.state.synced_at.map(|time| time.elapsed().unwrap_or_default())
+    }
+
+    /// Bring the destination up to date with the source once.
+    pub fn sync_once(&mut self, on_progress: &mut dyn FnMut(&TransferStats)) -> Result<Round> {
+        self.metrics.rounds += 1;
+        let round = self.apply(on_progress);
+        match &round {
+            Ok(round) if !round.is_empty() => {
+                self.metrics.updates += 1;
+                self.metrics.pages_copied += round.stats.pages_copied;
+                self.metrics.bytes_copied += round.stats.bytes_copied;
+                self.metrics.last_update_time = round.elapsed;
+                self.metrics.max_update_time = self.metrics.max_update_time.max(round.elapsed);
+            }
+            Ok(_) => {}
+            Err(_) => self.metrics.failed_rounds += 1,
+        }
+        round
+    }
+
+    fn apply(&mut self, on_progress: &mut dyn FnMut(&TransferStats)) -> Result<Round> {
+        let (started_at, started) = (SystemTime::now(), Instant::now());
+        let src = self.source.store;
+        let root = src.current_root()?;
+        let refs: Vec<(String, Cid)> = src.list_named_roots()?
+            .into_iter()
+            .filter(|(name, _)| !name.starts_with('.'))
+            .collect();
+
+        let changed: Vec<&(String, Cid)> = refs.iter()
+            .filter(|(name, cid)| self.state.refs.get(name) != Some(cid))
+            .collect();
+        let removed: Vec<String> = self.state.refs.keys()
+            .filter(|name| !refs.iter().any(|(source_name, _)| source_name == *name))
+            .cloned()
+            .collect();
+        // A store's root can't be unset, so a source without one leaves ours
+        let new_root = root.filter(|root| self.state.root != Some(*root));
+
+        let mut round = Round::default();
+        if !changed.is_empty() || new_root.is_some() {
+            let roots: Vec<Cid> = new_root.into_iter().chain(changed.iter().map(|(_, cid)| *cid)).collect();
+            round.stats = copy_roots(src, self.dst, &roots, on_progress)?;
+        }
+        for (name, cid) in &changed {
+            self.dst.set_named_root(name, *cid)?;
+            round.refs_set.push(name.clone());
+        }
+        for name in &removed {
+            self.dst.remove_named_root(name)?;
+        }
+        round.refs_removed = removed;
+        if let Some(root) = new_root {
+            self.dst.update_root(root)?;
+            round.root_moved = true;
+        }
+
+        let previous = self.state.synced_at;
+        self.state.synced_at = Some(started_at);
+        if !round.is_empty() {
+            self.state.root = root.or(self.state.root);
+            self.state.refs = refs.into_iter().collect();
+            // Only rounds that changed something are stored: an idle
+            // replicator would otherwise add a page every poll
+            if let Err(e) = self.state.save(self.dst, &self.state_ref) {
+                self.state.synced_at = previous;
+                return Err(e.into());
+            }
+        }
+        round.elapsed = started.elapsed();
+        Ok(round)
+    }
+
+    /// Poll the source every `interval` and replicate what changed, until
+    /// `stop` is set. Every round, failed ones included, is reported to
+    /// `on_round`; a failed round is retried at the next poll.
+    pub fn run(
+        &mut self,
+        interval: Duration,
+        stop: &AtomicBool,
+        on_round: &mut dyn FnMut(&Result<Round>, &Metrics),
+    ) {
+        while !stop.load(Ordering::Relaxed) {
+            let round = self.sync_once(&mut |_| {});
+            on_round(&round, &self.metrics);
+            // Sleep in short steps so a stop is noticed promptly
+            let next = Instant::now() + interval;
+            while !stop.load(Ordering::Relaxed) {
+                let left = next.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    break;
+                }
+                std::thread::sleep(left.min(Duration::from_millis(100)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+    use craftsql_testing::put_table;
+
+    #[test]
+    fn test_replicates_changes() {
+        let (src, dst) = (MemPageStore::new(), MemPageStore::new());
+        let source = Remote { name: "primary", store: &src };
+        let v1 = put_table(&src, &[b"a", b"b"]);
+        src.update_root(v1).unwrap();
+        src.set_named_root("main", v1).unwrap();
+        src.set_named_root("old", v1).unwrap();
+        src.set_named_root(".head", v1).unwrap();
+        dst.set_named_root("local-only", v1).unwrap();
+
+        let mut replicator = Replicator::new(source, &dst).unwrap();
+        assert_eq!(replicator.lag(), None);
+        let round = replicator.sync_once(&mut |_| {}).unwrap();
+        assert_eq!(round.refs_set, vec!["main", "old"]);
+        assert!(round.root_moved);
+        assert_eq!(round.stats.pages_copied, 2);
+        assert_eq!(dst.current_root().unwrap(), Some(v1));
+        assert_eq!(dst.get_named_root(".head").unwrap(), None);
+        assert!(replicator.lag().is_some());
+
+        // Nothing new
+        assert!(replicator.sync_once(&mut |_| {}).unwrap().is_empty());
+
+        let v2 = put_table(&src, &[b"a", b"c"]);
+        src.set_named_root("main", v2).unwrap();
+        src.remove_named_root("old").unwrap();
+        let round = replicator.sync_once(&mut |_| {}).unwrap();
+        assert_eq!((round.refs_set, round.refs_removed), (vec!["main".to_string()], vec!["old".to_string()]));
+        assert!(!round.root_moved);
+        assert_eq!((round.stats.pages_copied, round.stats.pages_skipped), (1, 1));
+        assert_eq!(dst.get_named_root("main").unwrap(), Some(v2));
+        assert_eq!(dst.get_named_root("old").unwrap(), None);
+        assert_eq!(dst.get_named_root("local-only").unwrap(), Some(v1));
+
+        let metrics = replicator.metrics();
+        assert_eq!((metrics.rounds, metrics.updates, metrics.failed_rounds), (3, 2, 0));
+        assert_eq!(metrics.pages_copied, 3);
+    }
+
+    #[test]
+    fn test_resumes_from_stored_state() {
+        let (src, dst) = (MemPageStore::new(), MemPageStore::new());
+        let v1 = put_table(&src, &[b"a"]);
+        src.set_named_root("main", v1).unwrap();
+        Replicator::new(Remote { name: "primary", store: &src }, &dst).unwrap().sync_once(&mut |_| {}).unwrap();
+
+        // A new replicator picks up where the last left off
+        let mut replicator = Replicator::new(Remote { name: "primary", store: &src }, &dst).unwrap();
+        assert_eq!(replicator.state().refs.get("main"), Some(&v1));
+        assert!(replicator.state().synced_at.is_some());
+        assert!(replicator.sync_once(&mut |_| {}).unwrap().is_empty());
+
+        // Another source's state is separate
+        let other = Replicator::new(Remote { name: "other", store: &src }, &dst).unwrap();
+        assert!(other.state().refs.is_empty());
+    }
+
+    #[test]
+    fn test_run_until_stopped() {
+        let (src, dst) = (MemPageStore::new(), MemPageStore::new());
+        let v1 = put_table(&src, &[b"a"]);
+        src.update_root(v1).unwrap();
+        let stop = AtomicBool::new(false);
+        let mut rounds = Vec::new();
+        Replicator::new(Remote { name: "primary", store: &src }, &dst)
+            .unwrap()
+            .run(Duration::from_millis(1), &stop, &mut |round, metrics| {
+                rounds.push(round.as_ref().unwrap().is_empty());
+                if metrics.rounds == 2 {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            });
+        assert_eq!(rounds, vec![false, true]);
+        assert_eq!(dst.current_root().unwrap(), Some(v1));
+    }
+}
@@ -0,0 +1,66 @@
+//! What a replicator has applied to its destination, kept in the destination
+//! itself so a restarted replicator carries on where the last one stopped.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use craftsql_core::{Cid, Page, PageStore, PageStoreError, Result};
+use serde::{Deserialize, Serialize};
+
+/// Named root, in the destination, of the state of replication from `source`.
+pub fn state_ref(source: &str) -> String {
+    format!(".replica.{}", source)
+}
+
+/// The source's roots as last applied to the destination.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplicaState {
+    pub root: Option<Cid>,
+    /// Named roots, bookkeeping names (starting with `.`) excluded.
+    pub refs: BTreeMap<String, Cid>,
+    /// When the destination last matched the source.
+    pub synced_at: Option<SystemTime>,
+}
+
+/// On-disk form of the state; CIDs as hex, time in Unix seconds.
+#[derive(Serialize, Deserialize)]
+struct StoredState {
+    root: Option<String>,
+    refs: BTreeMap<String, String>,
+    synced_at: Option<u64>,
+}
+
+fn parse_cid(hex: &str) -> Option<Cid> {
+    Some(Cid(hex::decode(hex).ok()?.try_into().ok()?))
+}
+
+impl ReplicaState {
+    /// The state stored under `name` in `store`, or an empty one if there is
+    /// none.
+    pub fn load(store: &dyn PageStore, name: &str) -> Result<Self> {
+        let Some(cid) = store.get_named_root(name)? else {
+            return Ok(Self::default());
+        };
+        let invalid = || PageStoreError::Storage(format!("invalid replica state {} in {}", cid, name));
+        let stored: StoredState = serde_json::from_slice(&store.get(&cid)?.data).map_err(|_| invalid())?;
+        let root = stored.root.map(|hex| parse_cid(&hex).ok_or_else(invalid)).transpose()?;
+        let refs = stored.refs
+            .into_iter()
+            .map(|(name, hex)| Ok((name, parse_cid(&hex).ok_or_else(invalid)?)))
+            .collect::<Result<_>>()?;
+        let synced_at = stored.synced_at.map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        Ok(Self { root, refs, synced_at })
+    }
+
+    /// Store the state and point `name` in `store` at it.
+    pub fn save(&self, store: &dyn PageStore, name: &str) -> Result<()> {
+        let stored = StoredState {
+            root: self.root.map(|cid| cid.to_hex()),
+            refs: self.refs.iter().map(|(name, cid)| (name.clone(), cid.to_hex())).collect(),
+            synced_at: self.synced_at.and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+        };
+        let data = serde_json::to_vec(&stored).expect("replica state serialization");
+        let cid = store.put(&Page { data })?;
+        store.set_named_root(name, cid)
+    }
+}
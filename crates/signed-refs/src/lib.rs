@@ -0,0 +1,328 @@
+//! CraftSQL Signed Refs — root pointers that only allowed signers can move.
+//!
+//! Anyone who can write to a shared store, such as a network-backed one,
+//! can point its roots anywhere; page CIDs protect the data, not the
+//! pointers. [`SignedRefStore`] wraps a store so that every root pointer
+//! names a signed record instead of the root itself: which ref, the CID it
+//! points at, when, and an Ed25519 signature over all three by the writer.
+//!
+//! Writes through the wrapper are signed with its signing key, and refused
+//! if it has none or the key may not sign that ref. Reads verify the record
+//! against the [`AllowedSigners`] policy and fail with
+//! [`PageStoreError::Unauthorized`] for anything unsigned, signed by an
+//! unknown key, or signed for another ref, so a reader never follows a
+//! pointer an unauthorized writer set. Pages pass through unchanged.
+//!
+//! Signatures don't stop a writer from putting back an older record that
+//! was validly signed; compare [`SignedRef::time`] with what you last saw
+//! where that matters.
+//!
+//! Everything that reads the roots must go through the wrapper: the inner
+//! store's roots point at records, not at page tables or commits.
+
+mod policy;
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+pub use policy::{AllowedSigners, PolicyError};
+
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use craftsql_core::{Cid, CommitMeta, Page, PageStore, PageStoreError, Result, RootSignal};
+use ed25519_dalek::{Signature, Signer};
+use serde::{Deserialize, Serialize};
+
+/// The ref name the current root is signed under.
+pub const ROOT_REF: &str = "@root";
+
+/// Leading bytes of a signed ref record, also prefixed to the signed message.
+const MAGIC: &[u8; 8] = b"csqlsig1";
+
+/// A new random signing key.
+pub fn generate_signing_key() -> Result<SigningKey> {
+    let mut secret = [0u8; 32];
+    getrandom::fill(&mut secret).map_err(|e| PageStoreError::Storage(format!("random key: {}", e)))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// A verified root pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedRef {
+    pub name: String,
+    pub cid: Cid,
+    /// When it was signed, in seconds since the Unix epoch.
+    pub time: u64,
+    pub key: VerifyingKey,
+    /// The signer's name in the policy.
+    pub signer: String,
+}
+
+/// A signed ref record as stored.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    name: String,
+    cid: Cid,
+    time: u64,
+    key: [u8; 32],
+    signature: Vec<u8>,
+}
+
+fn message(name: &str, cid: &Cid, time: u64) -> Vec<u8> {
+    let mut message = MAGIC.to_vec();
+    message.extend(bincode::serialize(&(name, cid, time)).expect("ref message serialization"));
+    message
+}
+
+fn unauthorized(message: String) -> PageStoreError {
+    PageStoreError::Unauthorized(message)
+}
+
+/// A store whose root pointers are signed, and checked on every read.
+pub struct SignedRefStore<S> {
+    inner: S,
+    policy: AllowedSigners,
+    signing_key: Option<SigningKey>,
+}
+
+impl<S: PageStore> SignedRefStore<S> {
+    /// Read `inner`'s roots, accepting signatures allowed by `policy`.
+    /// Writes need [`with_signing_key`](Self::with_signing_key).
+    pub fn new(inner: S, policy: AllowedSigners) -> Self {
+        Self { inner, policy, signing_key: None }
+    }
+
+    /// Sign root updates with `key`.
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.signing_key = Some(key);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn policy(&self) -> &AllowedSigners {
+        &self.policy
+    }
+
+    /// The verified record behind ref `name` ([`ROOT_REF`] for the current
+    /// root), or `None` if it isn't set.
+    pub fn signed_ref(&self, name: &str) -> Result<Option<SignedRef>> {
+        let record = if name == ROOT_REF { self.inner.current_root()? } else { self.inner.get_named_root(name)? };
+        record.map(|record| self.verify(name, &record)).transpose()
+    }
+
+    fn verify(&self, name: &str, record_cid: &Cid) -> Result<SignedRef> {
+        let page = self.inner.get(record_cid)?;
+        let record: Record = page.data.strip_prefix(MAGIC)
+            .and_then(|data| bincode::deserialize(data).ok())
+            .ok_or_else(|| unauthorized(format!("{} is not signed", name)))?;
+        if record.name != name {
+            return Err(unauthorized(format!("{} points at the signed record for {}", name, record.name)));
+        }
+        let key = VerifyingKey::from_bytes(&record.key)
+            .map_err(|_| unauthorized(format!("{} is signed with an invalid key", name)))?;
+        let signature = <[u8; 64]>::try_from(record.signature.as_slice())
+            .map(|bytes| Signature::from_bytes(&bytes))
+            .map_err(|_| unauthorized(format!("{} has a malformed signature", name)))?;
+        key.verify_strict(&message(&record.name, &record.cid, record.time), &signature)
+            .map_err(|_| unauthorized(format!("{} has a bad signature", name)))?;
+        let signer = self.policy.signer_for(&key, name).ok_or_else(|| {
+            unauthorized(format!("{} is signed by {}, which may not sign it", name, hex::encode(key.as_bytes())))
+        })?;
+        Ok(SignedRef { name: record.name, cid: record.cid, time: record.time, key, signer: signer.to_string() })
+    }
+
+    /// The signing key, if it may sign `name`.
+    fn authorized_key(&self, name: &str) -> Result<&SigningKey> {
+        let key = self.signing_key.as_ref()
+            .ok_or_else(|| unauthorized(format!("unsigned update of {}: no signing key", name)))?;
+        if self.policy.signer_for(&key.verifying_key(), name).is_none() {
+            return Err(unauthorized(format!(
+                "key {} may not sign {}", hex::encode(key.verifying_key().as_bytes()), name
+            )));
+        }
+        Ok(key)
+    }
+
+    /// Store a signed record pointing `name` at `cid`, returning its CID.
+    fn sign(&self, name: &str, cid: Cid) -> Result<Cid> {
+        let key = self.authorized_key(name)?;
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let signature = key.sign(&message(name, &cid, time));
+        let record = Record {
+            name: name.to_string(),
+            cid,
+            time,
+            key: key.verifying_key().to_bytes(),
+            signature: signature.to_bytes().to_vec(),
+        };
+        let mut data = MAGIC.to_vec();
+        data.extend(bincode::serialize(&record).expect("signed ref serialization"));
+        self.inner.put(&Page { data })
+    }
+}
+
+impl<S: PageStore> PageStore for SignedRefStore<S> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        self.inner.get(cid)
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        self.inner.put(page)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        self.inner.put_many(pages)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        self.inner.has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        let record = self.sign(ROOT_REF, new_root)?;
+        self.inner.update_root(record)
+    }
+
+    /// The inner store records `meta` against the signed record.
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        let record = self.sign(ROOT_REF, new_root)?;
+        self.inner.update_root_with_meta(record, meta)
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        Ok(self.signed_ref(ROOT_REF)?.map(|signed| signed.cid))
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        let record = self.sign(name, cid)?;
+        self.inner.set_named_root(name, record)
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        let record = self.sign(name, cid)?;
+        self.inner.set_named_root_with_meta(name, record, meta)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        Ok(self.signed_ref(name)?.map(|signed| signed.cid))
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.authorized_key(name)?;
+        self.inner.remove_named_root(name)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        self.inner.list_named_roots()?
+            .into_iter()
+            .map(|(name, record)| Ok((name.clone(), self.verify(&name, &record)?.cid)))
+            .collect()
+    }
+
+    /// The inner store's pages, less the records its roots point at: no root
+    /// seen through the wrapper reaches those, but they must be kept.
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        let records: HashSet<Cid> = self.inner.current_root()?
+            .into_iter()
+            .chain(self.inner.list_named_roots()?.into_iter().map(|(_, record)| record))
+            .collect();
+        let mut pages = self.inner.list_pages()?;
+        pages.retain(|(cid, _)| !records.contains(cid));
+        Ok(pages)
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.inner.delete_page(cid)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        self.inner.delete_many(cids)
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        self.inner.root_signal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn store(policy: &AllowedSigners, signer: Option<SigningKey>) -> (MemPageStore, SignedRefStore<MemPageStore>) {
+        let inner = MemPageStore::new();
+        let mut store = SignedRefStore::new(inner.clone(), policy.clone());
+        if let Some(signer) = signer {
+            store = store.with_signing_key(signer);
+        }
+        (inner, store)
+    }
+
+    fn is_unauthorized<T: std::fmt::Debug>(result: Result<T>) -> bool {
+        matches!(result, Err(PageStoreError::Unauthorized(_)))
+    }
+
+    #[test]
+    fn test_signed_roots() {
+        let policy = AllowedSigners::new().with_signer("alice", key(1).verifying_key());
+        let (inner, store) = store(&policy, Some(key(1)));
+        let cid = store.put(&Page { data: b"page table".to_vec() }).unwrap();
+        store.update_root(cid).unwrap();
+        store.set_named_root("main", cid).unwrap();
+
+        assert_eq!(store.current_root().unwrap(), Some(cid));
+        assert_eq!(store.get_named_root("main").unwrap(), Some(cid));
+        assert_eq!(store.list_named_roots().unwrap(), vec![("main".to_string(), cid)]);
+        let signed = store.signed_ref("main").unwrap().unwrap();
+        assert_eq!((signed.signer.as_str(), signed.key), ("alice", key(1).verifying_key()));
+
+        // The inner store holds records, which readers verify
+        let record = inner.get_named_root("main").unwrap().unwrap();
+        assert_ne!(record, cid);
+        let reader = SignedRefStore::new(inner.clone(), policy.clone());
+        assert_eq!(reader.get_named_root("main").unwrap(), Some(cid));
+        assert!(is_unauthorized(reader.set_named_root("main", cid)));
+        assert!(is_unauthorized(reader.remove_named_root("main")));
+        assert!(store.remove_named_root("main").unwrap());
+    }
+
+    #[test]
+    fn test_unauthorized_updates() {
+        let policy = AllowedSigners::new()
+            .with_signer("alice", key(1).verifying_key())
+            .with_signer_for("ci", key(2).verifying_key(), &["release-*"]);
+        let (inner, alice) = store(&policy, Some(key(1)));
+        let cid = alice.put(&Page { data: b"page table".to_vec() }).unwrap();
+
+        // Writers without a key, with an unknown key, or outside their refs
+        let (_, none) = store(&policy, None);
+        assert!(is_unauthorized(none.update_root(cid)));
+        let (_, mallory) = store(&policy, Some(key(3)));
+        assert!(is_unauthorized(mallory.set_named_root("main", cid)));
+        let ci = SignedRefStore::new(inner.clone(), policy.clone()).with_signing_key(key(2));
+        assert!(is_unauthorized(ci.set_named_root("main", cid)));
+        ci.set_named_root("release-1", cid).unwrap();
+
+        // Pointers set behind the wrapper's back are rejected on read
+        inner.set_named_root("raw", cid).unwrap();
+        assert!(is_unauthorized(alice.get_named_root("raw")));
+        assert!(is_unauthorized(alice.list_named_roots()));
+        inner.remove_named_root("raw").unwrap();
+
+        // A record signed for one ref can't be replayed under another
+        let record = inner.get_named_root("release-1").unwrap().unwrap();
+        inner.set_named_root("main", record).unwrap();
+        assert!(is_unauthorized(alice.get_named_root("main")));
+
+        // A signer trusted by one policy isn't by another
+        let lax = AllowedSigners::new().with_signer("mallory", key(3).verifying_key());
+        SignedRefStore::new(inner.clone(), lax).with_signing_key(key(3)).set_named_root("main", cid).unwrap();
+        assert!(is_unauthorized(alice.get_named_root("main")));
+    }
+}
@@ -0,0 +1,136 @@
+//! Who may sign which root pointers.
+
+use std::path::Path;
+
+use ed25519_dalek::VerifyingKey;
+
+/// Errors reading an allowed-signers list.
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("line {line}: {reason}")]
+    Parse { line: usize, reason: String },
+    #[error("allowed signers file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A key allowed to sign, and the refs it may sign.
+#[derive(Debug, Clone)]
+struct Signer {
+    name: String,
+    key: VerifyingKey,
+    /// Ref patterns: a name, or a prefix ending in `*`. `None` means all.
+    refs: Option<Vec<String>>,
+}
+
+impl Signer {
+    fn may_sign(&self, ref_name: &str) -> bool {
+        self.refs.as_ref().is_none_or(|patterns| {
+            patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => ref_name.starts_with(prefix),
+                None => pattern == ref_name,
+            })
+        })
+    }
+}
+
+/// The public keys whose signatures a [`SignedRefStore`](crate::SignedRefStore)
+/// accepts, each for all refs or only some.
+///
+/// As text, one signer per line: a name, the public key in hex, and
+/// optionally a comma-separated list of the refs it may sign, each a name
+/// or a prefix ending in `*` (the current root is [`ROOT_REF`](crate::ROOT_REF)).
+/// Blank lines and lines starting with `#` are ignored.
+///
+/// ```text
+/// alice  3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c
+/// ci     fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025  main,release-*
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AllowedSigners {
+    signers: Vec<Signer>,
+}
+
+impl AllowedSigners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow `key`, called `name`, to sign any ref.
+    pub fn with_signer(mut self, name: &str, key: VerifyingKey) -> Self {
+        self.signers.push(Signer { name: name.to_string(), key, refs: None });
+        self
+    }
+
+    /// Allow `key`, called `name`, to sign only refs matching `refs`.
+    pub fn with_signer_for(mut self, name: &str, key: VerifyingKey, refs: &[&str]) -> Self {
+        let refs = Some(refs.iter().map(|pattern| pattern.to_string()).collect());
+        self.signers.push(Signer { name: name.to_string(), key, refs });
+        self
+    }
+
+    pub fn parse(text: &str) -> Result<Self, PolicyError> {
+        let mut policy = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |reason: &str| PolicyError::Parse { line: i + 1, reason: reason.to_string() };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (name, key, refs) = match fields[..] {
+                [name, key] => (name, key, None),
+                [name, key, refs] => (name, key, Some(refs)),
+                _ => return Err(error("expected: name, public key, and optionally refs")),
+            };
+            let key = hex::decode(key).ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+                .ok_or_else(|| error("invalid public key: expected 64 hex digits"))?;
+            let refs = refs.map(|refs| refs.split(',').filter(|r| !r.is_empty()).map(String::from).collect());
+            policy.signers.push(Signer { name: name.to_string(), key, refs });
+        }
+        Ok(policy)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, PolicyError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The name of the signer `key` belongs to, if it may sign `ref_name`.
+    pub fn signer_for(&self, key: &VerifyingKey, ref_name: &str) -> Option<&str> {
+        self.signers.iter()
+            .find(|signer| signer.key == *key && signer.may_sign(ref_name))
+            .map(|signer| signer.name.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_parse_allowed_signers() {
+        let alice = SigningKey::from_bytes(&[1; 32]).verifying_key();
+        let ci = SigningKey::from_bytes(&[2; 32]).verifying_key();
+        let text = format!(
+            "# signers\n\nalice {}\n  ci {} main,release-*\n",
+            hex::encode(alice.as_bytes()),
+            hex::encode(ci.as_bytes())
+        );
+        let policy = AllowedSigners::parse(&text).unwrap();
+        assert_eq!(policy.signer_for(&alice, "anything"), Some("alice"));
+        assert_eq!(policy.signer_for(&ci, "main"), Some("ci"));
+        assert_eq!(policy.signer_for(&ci, "release-1.2"), Some("ci"));
+        assert_eq!(policy.signer_for(&ci, "mainline"), None);
+        let stranger = SigningKey::from_bytes(&[3; 32]).verifying_key();
+        assert_eq!(policy.signer_for(&stranger, "main"), None);
+
+        assert!(matches!(AllowedSigners::parse("alice"), Err(PolicyError::Parse { line: 1, .. })));
+        assert!(matches!(AllowedSigners::parse("\nalice abcd"), Err(PolicyError::Parse { line: 2, .. })));
+    }
+}
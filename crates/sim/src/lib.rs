@@ -0,0 +1,352 @@
+//! CraftSQL Sim — predicting how a workload behaves over a slow link.
+//!
+//! A [`Simulation`] models one network [`Link`]: a latency distribution per
+//! request, upload and download bandwidth caps, and requests lost outright.
+//! Wrap a [`NetworkBackend`](craftsql_objstore::NetworkBackend) with
+//! [`Simulation::backend`] (or any [`PageStore`](craftsql_core::PageStore)
+//! with [`Simulation::page_store`]) and every call is charged what it would
+//! cost over that link. Time is simulated, not slept: a workload that would
+//! take an hour over a satellite link runs in moments, and with the same
+//! seed and the same calls in the same order, every run reports the same
+//! times and loses the same requests.
+//!
+//! Calls are charged one after another, as over a single connection, so
+//! parallel fetches are predicted as if made in turn, and their order (and
+//! with it the random draws) follows thread scheduling.
+//!
+//! [`Simulation::step`] runs part of a workload and records what it cost:
+//!
+//! ```
+//! use craftsql_core::{Page, PageStore};
+//! use craftsql_sim::{Link, Simulation};
+//! use craftsql_store_mem::MemPageStore;
+//!
+//! let sim = Simulation::new(Link::mobile()).with_seed(7);
+//! let store = sim.page_store(MemPageStore::new());
+//! let cid = sim.step("upload", || store.put(&Page { data: vec![0; 4096] })).unwrap();
+//! sim.step("download", || store.get(&cid)).unwrap();
+//! for step in sim.steps() {
+//!     println!("{}", step);
+//! }
+//! ```
+
+mod store;
+
+pub use store::{SimulatedBackend, SimulatedPageStore};
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use craftsql_core::PageStoreError;
+
+/// Message of the errors of lost requests.
+pub const LOST_REQUEST: &str = "simulated request loss";
+
+/// Whether `error` is a request the simulated link lost.
+pub fn is_lost(error: &PageStoreError) -> bool {
+    matches!(error, PageStoreError::Timeout(message) if message == LOST_REQUEST)
+}
+
+/// How long a request waits for its first byte.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Latency {
+    Fixed(Duration),
+    Uniform { min: Duration, max: Duration },
+    /// Never less than zero.
+    Normal { mean: Duration, std_dev: Duration },
+    /// `min` plus an exponential tail: mostly fast, now and then much slower.
+    Exponential { min: Duration, mean: Duration },
+}
+
+impl Latency {
+    fn sample(&self, rng: &mut Rng) -> Duration {
+        match *self {
+            Latency::Fixed(latency) => latency,
+            Latency::Uniform { min, max } => min + max.saturating_sub(min).mul_f64(rng.next()),
+            Latency::Normal { mean, std_dev } => {
+                // Box-Muller
+                let (u, v) = (1.0 - rng.next(), rng.next());
+                let z = (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos();
+                Duration::from_secs_f64((mean.as_secs_f64() + z * std_dev.as_secs_f64()).max(0.0))
+            }
+            Latency::Exponential { min, mean } => {
+                min + mean.saturating_sub(min).mul_f64(-(1.0 - rng.next()).ln())
+            }
+        }
+    }
+}
+
+/// A network link: what each request costs, and how often one is lost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub latency: Latency,
+    /// Bytes per second from the network; `None` for no limit.
+    pub download: Option<u64>,
+    /// Bytes per second to the network; `None` for no limit.
+    pub upload: Option<u64>,
+    /// Chance, from 0 to 1, that a request is lost.
+    pub loss: f64,
+    /// How long a lost request is waited for before it fails.
+    pub timeout: Duration,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Link {
+    /// A perfect link: no latency, no limits, nothing lost.
+    pub fn new() -> Self {
+        Self {
+            latency: Latency::Fixed(Duration::ZERO),
+            download: None,
+            upload: None,
+            loss: 0.0,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// A local network: a millisecond away, 100 MB/s each way.
+    pub fn lan() -> Self {
+        Self::new().with_latency(Latency::Fixed(Duration::from_millis(1))).with_bandwidth(100 << 20, 100 << 20)
+    }
+
+    /// Home broadband: 20-40 ms, 10 MB/s down and 2 MB/s up.
+    pub fn broadband() -> Self {
+        Self::new()
+            .with_latency(Latency::Uniform { min: Duration::from_millis(20), max: Duration::from_millis(40) })
+            .with_bandwidth(10 << 20, 2 << 20)
+    }
+
+    /// A mobile connection: 60 ms and up, 1 MB/s down, 256 KB/s up, and
+    /// one request in a hundred lost.
+    pub fn mobile() -> Self {
+        Self::new()
+            .with_latency(Latency::Exponential { min: Duration::from_millis(60), mean: Duration::from_millis(150) })
+            .with_bandwidth(1 << 20, 256 << 10)
+            .with_loss(0.01)
+            .with_timeout(Duration::from_secs(10))
+    }
+
+    pub fn with_latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Cap bandwidth, in bytes per second.
+    pub fn with_bandwidth(mut self, download: u64, upload: u64) -> Self {
+        self.download = Some(download);
+        self.upload = Some(upload);
+        self
+    }
+
+    pub fn with_loss(mut self, rate: f64) -> Self {
+        self.loss = rate;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Traffic over a simulated link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub requests: u64,
+    /// Requests lost, included in `requests`.
+    pub lost: u64,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+}
+
+impl SimStats {
+    fn since(&self, earlier: &SimStats) -> SimStats {
+        SimStats {
+            requests: self.requests - earlier.requests,
+            lost: self.lost - earlier.lost,
+            bytes_up: self.bytes_up - earlier.bytes_up,
+            bytes_down: self.bytes_down - earlier.bytes_down,
+        }
+    }
+}
+
+/// What one [`Simulation::step`] cost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub name: String,
+    /// Simulated time the step took.
+    pub elapsed: Duration,
+    pub stats: SimStats,
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {:.3}s, {} requests ({} lost), {} bytes up, {} bytes down",
+            self.name,
+            self.elapsed.as_secs_f64(),
+            self.stats.requests,
+            self.stats.lost,
+            self.stats.bytes_up,
+            self.stats.bytes_down
+        )
+    }
+}
+
+/// SplitMix64, as a float in [0, 1).
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as f64 / (u64::MAX as f64 + 1.0)
+    }
+}
+
+struct State {
+    link: Link,
+    now: Duration,
+    rng: Rng,
+    stats: SimStats,
+    steps: Vec<Step>,
+}
+
+/// The link shared by a simulation's wrappers.
+pub(crate) struct Net {
+    state: Mutex<State>,
+}
+
+impl Net {
+    /// Charge a request sending `up` bytes, made by `call`, whose answer is
+    /// `down(answer)` bytes. A lost request costs the timeout and never
+    /// reaches `call`.
+    pub(crate) fn request<T>(
+        &self,
+        up: u64,
+        call: impl FnOnce() -> craftsql_core::Result<T>,
+        down: impl FnOnce(&T) -> u64,
+    ) -> craftsql_core::Result<T> {
+        let latency = {
+            let mut guard = self.state.lock().unwrap();
+            let state = &mut *guard;
+            state.stats.requests += 1;
+            // Draw for every request, so a change in the loss rate doesn't shift latencies
+            let lost = state.rng.next() < state.link.loss;
+            let latency = state.link.latency.clone().sample(&mut state.rng);
+            if lost {
+                state.stats.lost += 1;
+                state.now += state.link.timeout;
+                return Err(PageStoreError::Timeout(LOST_REQUEST.into()));
+            }
+            latency
+        };
+        let result = call();
+        let down = result.as_ref().map_or(0, down);
+
+        let mut state = self.state.lock().unwrap();
+        let transfer = |bytes: u64, rate: Option<u64>| match rate {
+            Some(rate) if rate > 0 => Duration::from_secs_f64(bytes as f64 / rate as f64),
+            _ => Duration::ZERO,
+        };
+        let cost = latency + transfer(up, state.link.upload) + transfer(down, state.link.download);
+        state.now += cost;
+        state.stats.bytes_up += up;
+        state.stats.bytes_down += down;
+        result
+    }
+}
+
+/// A simulated network link and its clock. Wrappers made from one
+/// simulation share them, and its clones are the same simulation.
+#[derive(Clone)]
+pub struct Simulation {
+    net: Arc<Net>,
+}
+
+impl Simulation {
+    pub fn new(link: Link) -> Self {
+        let state = State { link, now: Duration::ZERO, rng: Rng(0), stats: SimStats::default(), steps: Vec::new() };
+        Self { net: Arc::new(Net { state: Mutex::new(state) }) }
+    }
+
+    /// Seed the generator behind latencies and losses (0 by default).
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.net.state.lock().unwrap().rng = Rng(seed);
+        self
+    }
+
+    /// `inner` behind this simulation's link.
+    pub fn backend<B>(&self, inner: B) -> SimulatedBackend<B> {
+        SimulatedBackend::new(inner, Arc::clone(&self.net))
+    }
+
+    /// `inner` behind this simulation's link, e.g. a remote store.
+    pub fn page_store<S>(&self, inner: S) -> SimulatedPageStore<S> {
+        SimulatedPageStore::new(inner, Arc::clone(&self.net))
+    }
+
+    /// Change the link from now on, e.g. to model an outage.
+    pub fn set_link(&self, link: Link) {
+        self.net.state.lock().unwrap().link = link;
+    }
+
+    /// Simulated time since the start.
+    pub fn now(&self) -> Duration {
+        self.net.state.lock().unwrap().now
+    }
+
+    /// Let `duration` of simulated time pass, e.g. for think time between
+    /// a workload's operations.
+    pub fn advance(&self, duration: Duration) {
+        self.net.state.lock().unwrap().now += duration;
+    }
+
+    /// Traffic since the start.
+    pub fn stats(&self) -> SimStats {
+        self.net.state.lock().unwrap().stats
+    }
+
+    /// Run `workload` and record what it cost as a step named `name`.
+    pub fn step<T>(&self, name: &str, workload: impl FnOnce() -> T) -> T {
+        let (started, before) = (self.now(), self.stats());
+        let result = workload();
+        let mut state = self.net.state.lock().unwrap();
+        let step = Step { name: name.to_string(), elapsed: state.now - started, stats: state.stats.since(&before) };
+        state.steps.push(step);
+        result
+    }
+
+    /// Steps recorded so far, in order.
+    pub fn steps(&self) -> Vec<Step> {
+        self.net.state.lock().unwrap().steps.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_distributions() {
+        let mut rng = Rng(1);
+        let ms = Duration::from_millis;
+        let samples = |latency: Latency, rng: &mut Rng| (0..1000).map(|_| latency.sample(rng)).collect::<Vec<_>>();
+
+        let uniform = samples(Latency::Uniform { min: ms(10), max: ms(20) }, &mut rng);
+        assert!(uniform.iter().all(|d| (ms(10)..ms(20)).contains(d)));
+        let normal = samples(Latency::Normal { mean: ms(50), std_dev: ms(10) }, &mut rng);
+        let mean = normal.iter().sum::<Duration>() / 1000;
+        assert!((ms(48)..ms(52)).contains(&mean), "{:?}", mean);
+        let tail = samples(Latency::Exponential { min: ms(10), mean: ms(30) }, &mut rng);
+        assert!(tail.iter().all(|d| *d >= ms(10)));
+        assert!(tail.iter().any(|d| *d > ms(100)));
+    }
+}
@@ -0,0 +1,253 @@
+//! Wrappers charging every call to a simulated link.
+
+use std::sync::Arc;
+
+use craftsql_core::{Cid, CommitMeta, Page, PageStore, Result};
+use craftsql_objstore::NetworkBackend;
+
+use crate::Net;
+
+/// Bytes of a CID or root pointer on the wire.
+const CID_BYTES: u64 = 32;
+
+/// Bytes of commit metadata on the wire.
+fn meta_bytes(meta: &CommitMeta) -> u64 {
+    (meta.author.len() + meta.message.len()) as u64 + meta.parent.map_or(0, |_| CID_BYTES)
+}
+
+/// A [`NetworkBackend`] behind a simulated link (see [`Simulation::backend`](crate::Simulation::backend)).
+pub struct SimulatedBackend<B> {
+    inner: B,
+    net: Arc<Net>,
+}
+
+impl<B> SimulatedBackend<B> {
+    pub(crate) fn new(inner: B, net: Arc<Net>) -> Self {
+        Self { inner, net }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+}
+
+impl<B: NetworkBackend> NetworkBackend for SimulatedBackend<B> {
+    fn publish_page(&self, data: &[u8]) -> Result<Cid> {
+        self.net.request(data.len() as u64, || self.inner.publish_page(data), |_| CID_BYTES)
+    }
+
+    fn fetch_page(&self, cid: &Cid) -> Result<Vec<u8>> {
+        self.net.request(CID_BYTES, || self.inner.fetch_page(cid), |data| data.len() as u64)
+    }
+
+    fn get_root(&self) -> Result<Option<Cid>> {
+        self.net.request(0, || self.inner.get_root(), |_| CID_BYTES)
+    }
+
+    fn set_root(&self, cid: Cid) -> Result<()> {
+        self.net.request(CID_BYTES, || self.inner.set_root(cid), |_| 0)
+    }
+
+    fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        self.net.request(2 * CID_BYTES, || self.inner.set_root_if(expected, new), |_| 0)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.net.request(name.len() as u64, || self.inner.get_named_root(name), |_| CID_BYTES)
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.net.request(name.len() as u64 + CID_BYTES, || self.inner.set_named_root(name, cid), |_| 0)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.net.request(name.len() as u64, || self.inner.remove_named_root(name), |_| 0)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        let size = |roots: &Vec<(String, Cid)>| roots.iter().map(|(name, _)| name.len() as u64 + CID_BYTES).sum();
+        self.net.request(0, || self.inner.list_named_roots(), size)
+    }
+
+    fn supports_range(&self) -> bool {
+        self.inner.supports_range()
+    }
+
+    fn fetch_range(&self, cid: &Cid, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.net.request(CID_BYTES + 16, || self.inner.fetch_range(cid, offset, len), |data| data.len() as u64)
+    }
+
+    fn publish_many(&self, items: &[&[u8]]) -> Result<Vec<Cid>> {
+        let up = items.iter().map(|data| data.len() as u64).sum();
+        self.net.request(up, || self.inner.publish_many(items), |cids| cids.len() as u64 * CID_BYTES)
+    }
+}
+
+/// A [`PageStore`] behind a simulated link (see [`Simulation::page_store`](crate::Simulation::page_store)).
+pub struct SimulatedPageStore<S> {
+    inner: S,
+    net: Arc<Net>,
+}
+
+impl<S> SimulatedPageStore<S> {
+    pub(crate) fn new(inner: S, net: Arc<Net>) -> Self {
+        Self { inner, net }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: PageStore> PageStore for SimulatedPageStore<S> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        self.net.request(CID_BYTES, || self.inner.get(cid), |page| page.data.len() as u64)
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        self.net.request(page.data.len() as u64, || self.inner.put(page), |_| CID_BYTES)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        let up = pages.iter().map(|page| page.data.len() as u64).sum();
+        self.net.request(up, || self.inner.put_many(pages), |cids| cids.len() as u64 * CID_BYTES)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        self.net.request(CID_BYTES, || self.inner.has(cid), |_| 1)
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        self.net.request(CID_BYTES, || self.inner.update_root(new_root), |_| 0)
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        let up = CID_BYTES + meta_bytes(meta);
+        self.net.request(up, || self.inner.update_root_with_meta(new_root, meta), |_| 0)
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        self.net.request(0, || self.inner.current_root(), |_| CID_BYTES)
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.net.request(name.len() as u64 + CID_BYTES, || self.inner.set_named_root(name, cid), |_| 0)
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        let up = name.len() as u64 + CID_BYTES + meta_bytes(meta);
+        self.net.request(up, || self.inner.set_named_root_with_meta(name, cid, meta), |_| 0)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.net.request(name.len() as u64, || self.inner.get_named_root(name), |_| CID_BYTES)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.net.request(name.len() as u64, || self.inner.remove_named_root(name), |_| 0)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        let size = |roots: &Vec<(String, Cid)>| roots.iter().map(|(name, _)| name.len() as u64 + CID_BYTES).sum();
+        self.net.request(0, || self.inner.list_named_roots(), size)
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        self.net.request(0, || self.inner.list_pages(), |pages| pages.len() as u64 * (CID_BYTES + 8))
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.net.request(CID_BYTES, || self.inner.delete_page(cid), |_| 0)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        self.net.request(cids.len() as u64 * CID_BYTES, || self.inner.delete_many(cids), |deleted| deleted.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use craftsql_core::PageTable;
+    use craftsql_objstore::{CraftObjPageStore, MockNetworkBackend};
+    use craftsql_store_mem::MemPageStore;
+
+    use crate::{is_lost, Latency, Link, Simulation};
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_costs() {
+        let link = Link::new().with_latency(Latency::Fixed(ms(50))).with_bandwidth(1 << 20, 1 << 10);
+        let sim = Simulation::new(link);
+        let store = sim.page_store(MemPageStore::new());
+
+        // Latency, then 1 KB up at 1 KB/s and a CID down
+        let cid = store.put(&Page { data: vec![0; 1 << 10] }).unwrap();
+        let put = ms(1050) + Duration::from_secs_f64(32.0 / (1 << 20) as f64);
+        assert_eq!(sim.now(), put);
+        sim.step("read", || store.get(&cid)).unwrap();
+        let step = &sim.steps()[0];
+        let get = ms(50) + Duration::from_secs_f64(32.0 / 1024.0 + 1024.0 / (1 << 20) as f64);
+        assert_eq!((step.elapsed, step.stats.requests, step.stats.bytes_down), (get, 1, 1024));
+        sim.advance(ms(100));
+        assert_eq!(sim.now(), put + get + ms(100));
+    }
+
+    #[test]
+    fn test_loss_is_deterministic() {
+        let run = |seed| {
+            let link = Link::new().with_latency(Latency::Normal { mean: ms(80), std_dev: ms(20) }).with_loss(0.2);
+            let sim = Simulation::new(link.with_timeout(Duration::from_secs(1))).with_seed(seed);
+            let store = sim.page_store(MemPageStore::new());
+            let lost: Vec<bool> = (0..100u32)
+                .map(|n| store.put(&Page { data: n.to_le_bytes().to_vec() }).map_err(|e| is_lost(&e)).err().unwrap_or(false))
+                .collect();
+            (lost, sim.now(), sim.stats())
+        };
+        let (lost, now, stats) = run(3);
+        assert_eq!(run(3), (lost.clone(), now, stats));
+        assert_ne!(run(4).0, lost);
+        let count = lost.iter().filter(|lost| **lost).count() as u64;
+        assert!((10..30).contains(&count), "{} lost", count);
+        assert_eq!((stats.requests, stats.lost), (100, count));
+        // Lost requests never arrive
+        assert!(now >= Duration::from_secs(count));
+    }
+
+    #[test]
+    fn test_scenario_over_craftobj() {
+        let sim = Simulation::new(Link::new()).with_seed(1);
+        let network = Arc::new(MockNetworkBackend::new());
+        let (writer_dir, reader_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+
+        // Publish a 100-page database over a free link
+        let writer = CraftObjPageStore::new(writer_dir.path(), sim.backend(Arc::clone(&network))).unwrap();
+        let mut table = PageTable::new();
+        for i in 0..100u32 {
+            let mut data = vec![0; 4096];
+            data[..4].copy_from_slice(&i.to_le_bytes());
+            table.set(i as usize, writer.put(&Page { data }).unwrap());
+        }
+        let root = writer.put(&Page { data: table.to_bytes() }).unwrap();
+        writer.update_root(root).unwrap();
+
+        // Then read one page of it cold over broadband
+        sim.set_link(Link::broadband());
+        let reader = CraftObjPageStore::new(reader_dir.path(), sim.backend(Arc::clone(&network))).unwrap();
+        let page = sim.step("cold read", || reader.get(table.get(5).unwrap())).unwrap();
+        assert_eq!(page.data[..4], 5u32.to_le_bytes());
+        sim.step("warm read", || reader.get(table.get(6).unwrap())).unwrap();
+
+        let steps = sim.steps();
+        // The whole bundle comes down at 10 MB/s on the first miss, and nothing after
+        assert!(steps[0].stats.bytes_down > 100 * 4096);
+        assert!(steps[0].elapsed > ms(20) + Duration::from_secs_f64(100.0 * 4096.0 / (10 << 20) as f64));
+        assert_eq!((steps[1].stats.requests, steps[1].elapsed), (0, Duration::ZERO));
+        assert!(steps[0].to_string().starts_with("cold read: "));
+    }
+}
@@ -0,0 +1,52 @@
+//! Hedged reads: racing a second remote against the first for pages the
+//! cache lacks (see [`CachingPageStore::with_secondary`](crate::CachingPageStore::with_secondary)).
+
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use craftsql_core::{Cid, Page, PageStore, Result};
+
+/// Two remotes holding the same pages.
+pub(crate) struct Hedge {
+    pub(crate) primary: Arc<dyn PageStore>,
+    pub(crate) secondary: Arc<dyn PageStore>,
+}
+
+impl Hedge {
+    /// Fetch `cid` from the primary, also asking the secondary if the
+    /// primary fails or hasn't answered within `delay`, and take whichever
+    /// page comes first. Returns whether the secondary was asked, and the
+    /// first error if both fail.
+    ///
+    /// Each fetch runs on a thread of its own, which the loser finishes on
+    /// after this returns.
+    pub(crate) fn get(&self, cid: &Cid, delay: Duration) -> (Result<Page>, bool) {
+        let (sender, results) = mpsc::channel();
+        let spawn = |store: &Arc<dyn PageStore>| {
+            let (store, sender, cid) = (store.clone(), sender.clone(), *cid);
+            std::thread::spawn(move || {
+                let _ = sender.send(store.get(&cid));
+            });
+        };
+
+        spawn(&self.primary);
+        let mut error = match results.recv_timeout(delay) {
+            Ok(Ok(page)) => return (Ok(page), false),
+            Ok(Err(e)) => Some(e),
+            // Slow: hedge
+            Err(_) => None,
+        };
+        spawn(&self.secondary);
+        drop(sender);
+
+        for result in results {
+            match result {
+                Ok(page) => return (Ok(page), true),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        (Err(error.expect("a fetch failed")), true)
+    }
+}
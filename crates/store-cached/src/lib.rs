@@ -1,10 +1,22 @@
 //! Caching PageStore — bridges local disk cache with remote backends
 //! Provides TTL-based root refresh, prefetching, and cache statistics.
+//!
+//! Given a second remote holding the same pages
+//! ([`CachingPageStore::with_secondary`]), pages the cache lacks are fetched
+//! with hedged requests: if the remote hasn't answered within
+//! [`CacheConfig::hedge_delay`], or fails, the secondary is asked too, and
+//! whichever answers first wins. That cuts the tail latency of flaky
+//! networks for one extra request per slow read.
 
-use craftsql_core::{Cid, Page, PageStore, PageStoreError, PageTable, Result};
+mod hedge;
+
+use hedge::Hedge;
+
+use craftsql_core::{Cid, CommitMeta, Page, PageStore, PageStoreError, PageTable, Result};
 use craftsql_store_local::LocalPageStore;
+pub use craftsql_store_local::Durability;
 use std::path::Path;
-use std::sync::{atomic::AtomicU64, atomic::Ordering, Mutex};
+use std::sync::{atomic::AtomicU64, atomic::Ordering, Arc, Mutex};
 use std::time::{Duration, Instant};
 
 /// Configuration for caching behavior
@@ -16,6 +28,11 @@ pub struct CacheConfig {
     pub prefetch_on_open: bool,
     /// Max pages to prefetch (0 = all)
     pub max_prefetch_pages: usize,
+    /// How the local cache flushes its writes (see [`Durability`])
+    pub durability: Durability,
+    /// How long a remote fetch may take before the secondary remote is
+    /// asked too (see [`CachingPageStore::with_secondary`])
+    pub hedge_delay: Duration,
 }
 
 impl Default for CacheConfig {
@@ -24,6 +41,8 @@ impl Default for CacheConfig {
             root_ttl: Some(Duration::from_secs(300)), // 5 minutes
             prefetch_on_open: false,
             max_prefetch_pages: 0, // no limit
+            durability: Durability::None,
+            hedge_delay: Duration::from_millis(100),
         }
     }
 }
@@ -41,6 +60,8 @@ pub struct CacheStats {
     pub cached_pages: usize,
     pub cache_hits: AtomicU64,
     pub cache_misses: AtomicU64,
+    /// Misses the secondary remote was asked about too
+    pub hedged_reads: AtomicU64,
 }
 
 impl CacheStats {
@@ -49,6 +70,7 @@ impl CacheStats {
             cached_pages: 0,
             cache_hits: AtomicU64::new(0),
             cache_misses: AtomicU64::new(0),
+            hedged_reads: AtomicU64::new(0),
         }
     }
 
@@ -65,12 +87,18 @@ impl CacheStats {
     }
 }
 
-/// Caching PageStore that wraps a remote backend with local disk cache
+/// Caching PageStore that wraps a remote backend with local disk cache.
+///
+/// The cache keeps a [`CidFilter`](craftsql_store_local::CidFilter) of its
+/// pages, so checking for pages it lacks doesn't stat the filesystem; the
+/// cache directory should be written only through this store.
 pub struct CachingPageStore<R: PageStore> {
     /// Local disk cache (persistent across restarts)
     local: LocalPageStore,
     /// Remote backend (CraftOBJ, S3, etc.)
-    remote: R,
+    remote: Arc<R>,
+    /// The remote and a second one, raced on cache misses
+    hedge: Option<Hedge>,
     /// Root pointer cache
     root_cache: Mutex<RootCache>,
     /// Configuration
@@ -82,12 +110,13 @@ pub struct CachingPageStore<R: PageStore> {
 impl<R: PageStore> CachingPageStore<R> {
     /// Create new caching store
     pub fn new(cache_dir: &Path, remote: R, config: CacheConfig) -> Result<Self> {
-        let local = LocalPageStore::new(cache_dir)?;
+        let local = LocalPageStore::new(cache_dir)?.with_durability(config.durability).with_cid_filter()?;
         let stats = CacheStats::new();
         
         let store = Self {
             local,
-            remote,
+            remote: Arc::new(remote),
+            hedge: None,
             root_cache: Mutex::new(RootCache::default()),
             config,
             stats,
@@ -101,6 +130,13 @@ impl<R: PageStore> CachingPageStore<R> {
         Ok(store)
     }
 
+    /// Remember `root` as the current root, as of now.
+    fn cache_root(&self, root: Cid) {
+        let mut cache = self.root_cache.lock().unwrap();
+        cache.root = Some(root);
+        cache.fetched_at = Some(Instant::now());
+    }
+
     /// Force refresh root pointer from remote
     pub fn refresh_root(&self) -> Result<Option<Cid>> {
         let remote_root = self.remote.current_root()?;
@@ -161,7 +197,7 @@ impl<R: PageStore> CachingPageStore<R> {
         let mut fetched = 0;
         for &cid in cids {
             if !self.is_cached(&cid) {
-                match self.remote.get(&cid) {
+                match self.fetch(&cid) {
                     Ok(page) => {
                         self.local.put(&page)?;
                         fetched += 1;
@@ -178,13 +214,39 @@ impl<R: PageStore> CachingPageStore<R> {
 
     /// Check if a page is locally cached
     pub fn is_cached(&self, cid: &Cid) -> bool {
-        self.local.get(cid).is_ok()
+        self.local.contains(cid)
     }
 
     /// Cache stats
     pub fn stats(&self) -> &CacheStats {
         &self.stats
     }
+
+    /// Fetch a page the cache lacks from the remote, or the faster of the
+    /// two remotes when there's a secondary
+    fn fetch(&self, cid: &Cid) -> Result<Page> {
+        let Some(hedge) = &self.hedge else {
+            return self.remote.get(cid);
+        };
+        let (page, hedged) = hedge.get(cid, self.config.hedge_delay);
+        if hedged {
+            self.stats.hedged_reads.fetch_add(1, Ordering::Relaxed);
+        }
+        page
+    }
+}
+
+impl<R: PageStore + 'static> CachingPageStore<R> {
+    /// Race `secondary`, a remote holding the same pages as the first, for
+    /// pages the cache lacks: it's asked too when the first remote fails or
+    /// takes longer than [`CacheConfig::hedge_delay`], and the first page
+    /// back wins. Writes, roots, and names still go to the first remote
+    /// only.
+    pub fn with_secondary(mut self, secondary: impl PageStore + 'static) -> Self {
+        let primary: Arc<dyn PageStore> = self.remote.clone();
+        self.hedge = Some(Hedge { primary, secondary: Arc::new(secondary) });
+        self
+    }
 }
 
 impl<R: PageStore> PageStore for CachingPageStore<R> {
@@ -203,7 +265,7 @@ impl<R: PageStore> PageStore for CachingPageStore<R> {
         }
 
         // 2. Miss → fetch from remote
-        let page = self.remote.get(cid)?;
+        let page = self.fetch(cid)?;
 
         // 3. Store in local cache
         let _ = self.local.put(&page); // Ignore local cache errors
@@ -222,6 +284,14 @@ impl<R: PageStore> PageStore for CachingPageStore<R> {
         Ok(cid)
     }
 
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        // Writes go through to the remote, so it holds everything
+        if self.local.contains(cid) {
+            return Ok(true);
+        }
+        self.remote.has(cid)
+    }
+
     fn current_root(&self) -> Result<Option<Cid>> {
         let now = Instant::now();
         
@@ -249,12 +319,14 @@ impl<R: PageStore> PageStore for CachingPageStore<R> {
         // Update both local and remote
         self.local.update_root(new_root)?;
         self.remote.update_root(new_root)?;
+        self.cache_root(new_root);
+        Ok(())
+    }
 
-        // Update root cache
-        let mut cache = self.root_cache.lock().unwrap();
-        cache.root = Some(new_root);
-        cache.fetched_at = Some(Instant::now());
-
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        self.local.update_root_with_meta(new_root, meta)?;
+        self.remote.update_root_with_meta(new_root, meta)?;
+        self.cache_root(new_root);
         Ok(())
     }
 
@@ -265,6 +337,12 @@ impl<R: PageStore> PageStore for CachingPageStore<R> {
         Ok(())
     }
 
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        self.local.set_named_root_with_meta(name, cid, meta)?;
+        self.remote.set_named_root_with_meta(name, cid, meta)?;
+        Ok(())
+    }
+
     fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
         // Try local first, fall back to remote
         match self.local.get_named_root(name) {
@@ -300,90 +378,34 @@ impl<R: PageStore> PageStore for CachingPageStore<R> {
         
         Ok(all_roots.into_iter().collect())
     }
+
+    /// Lists the cached pages: those [`delete_page`](Self::delete_page)
+    /// can remove.
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        self.local.list_pages()
+    }
+
+    /// Evicts the page from the cache; the remote keeps it.
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.local.remove(cid)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
-    use std::sync::{Arc, Mutex as StdMutex};
+    use craftsql_store_mem::MemPageStore;
     use tempfile::TempDir;
 
-    /// Mock in-memory PageStore for testing
-    #[derive(Debug, Clone)]
-    struct MockPageStore {
-        pages: Arc<StdMutex<HashMap<Cid, Page>>>,
-        root: Arc<StdMutex<Option<Cid>>>,
-        named_roots: Arc<StdMutex<HashMap<String, Cid>>>,
+    fn populate_with_pages(store: &MemPageStore, page_count: usize) -> Vec<Cid> {
+        (0..page_count)
+            .map(|i| store.put(&Page { data: format!("page {} data", i).into_bytes() }).unwrap())
+            .collect()
     }
 
-    impl MockPageStore {
-        fn new() -> Self {
-            Self {
-                pages: Arc::new(StdMutex::new(HashMap::new())),
-                root: Arc::new(StdMutex::new(None)),
-                named_roots: Arc::new(StdMutex::new(HashMap::new())),
-            }
-        }
-
-        fn populate_with_pages(&self, page_count: usize) -> Vec<Cid> {
-            let mut cids = Vec::new();
-            for i in 0..page_count {
-                let data = format!("page {} data", i);
-                let page = Page { data: data.into_bytes() };
-                let cid = self.put(&page).unwrap();
-                cids.push(cid);
-            }
-            cids
-        }
-    }
-
-    impl PageStore for MockPageStore {
-        fn get(&self, cid: &Cid) -> Result<Page> {
-            let pages = self.pages.lock().unwrap();
-            pages.get(cid).cloned().ok_or(PageStoreError::NotFound(*cid))
-        }
-
-        fn put(&self, page: &Page) -> Result<Cid> {
-            let cid = Cid::from_bytes(&page.data);
-            let mut pages = self.pages.lock().unwrap();
-            pages.insert(cid, page.clone());
-            Ok(cid)
-        }
-
-        fn current_root(&self) -> Result<Option<Cid>> {
-            Ok(*self.root.lock().unwrap())
-        }
-
-        fn update_root(&self, new_root: Cid) -> Result<()> {
-            *self.root.lock().unwrap() = Some(new_root);
-            Ok(())
-        }
-
-        fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
-            self.named_roots.lock().unwrap().insert(name.to_string(), cid);
-            Ok(())
-        }
-
-        fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
-            Ok(self.named_roots.lock().unwrap().get(name).copied())
-        }
-
-        fn remove_named_root(&self, name: &str) -> Result<bool> {
-            Ok(self.named_roots.lock().unwrap().remove(name).is_some())
-        }
-
-        fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
-            let roots = self.named_roots.lock().unwrap();
-            let mut result: Vec<_> = roots.iter().map(|(k, &v)| (k.clone(), v)).collect();
-            result.sort_by(|a, b| a.0.cmp(&b.0));
-            Ok(result)
-        }
-    }
-
-    fn create_test_store() -> (TempDir, CachingPageStore<MockPageStore>) {
+    fn create_test_store() -> (TempDir, CachingPageStore<MemPageStore>) {
         let temp_dir = TempDir::new().unwrap();
-        let mock_remote = MockPageStore::new();
+        let mock_remote = MemPageStore::new();
         let config = CacheConfig::default();
         let store = CachingPageStore::new(temp_dir.path(), mock_remote, config).unwrap();
         (temp_dir, store)
@@ -422,6 +444,11 @@ mod tests {
         
         // Should now be cached locally
         assert!(store.is_cached(&cid));
+
+        // Deleting evicts it from the cache only
+        assert!(store.delete_page(&cid).unwrap());
+        assert!(!store.is_cached(&cid));
+        assert!(store.remote.has(&cid).unwrap());
     }
 
     #[test]
@@ -493,7 +520,7 @@ mod tests {
         let (_temp_dir, store) = create_test_store();
 
         // Create a page table with some pages
-        let page_cids = store.remote.populate_with_pages(3);
+        let page_cids = populate_with_pages(&store.remote, 3);
         
         let mut page_table = PageTable::new();
         for (i, &cid) in page_cids.iter().enumerate() {
@@ -539,6 +566,19 @@ mod tests {
         assert_eq!(store.remote.current_root().unwrap(), Some(cid));
     }
 
+    #[test]
+    fn test_durability() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = CacheConfig { durability: Durability::Commit, ..CacheConfig::default() };
+        let store = CachingPageStore::new(temp_dir.path(), MemPageStore::new(), config).unwrap();
+        assert_eq!(store.local.durability(), Durability::Commit);
+
+        let cid = store.put(&Page { data: b"page".to_vec() }).unwrap();
+        store.update_root(cid).unwrap();
+        assert_eq!(store.local.current_root().unwrap(), Some(cid));
+        assert!(store.local.contains(&cid));
+    }
+
     #[test]
     fn test_cache_stats() {
         let (_temp_dir, store) = create_test_store();
@@ -572,7 +612,7 @@ mod tests {
 
         // Create first store and put a page
         {
-            let mock_remote = MockPageStore::new();
+            let mock_remote = MemPageStore::new();
             let config = CacheConfig::default();
             let store = CachingPageStore::new(&cache_path, mock_remote, config).unwrap();
             cid = store.put(&page).unwrap();
@@ -580,7 +620,7 @@ mod tests {
 
         // Create new store with same cache_dir
         {
-            let mock_remote = MockPageStore::new();
+            let mock_remote = MemPageStore::new();
             let config = CacheConfig::default();
             let store = CachingPageStore::new(&cache_path, mock_remote, config).unwrap();
             
@@ -618,6 +658,37 @@ mod tests {
         assert_eq!(store.list_named_roots().unwrap().len(), 1);
     }
 
+    #[test]
+    fn test_hedged_reads() {
+        use craftsql_testing::{FaultyPageStore, Op};
+
+        let temp_dir = TempDir::new().unwrap();
+        let (primary, secondary) = (MemPageStore::new(), MemPageStore::new());
+        let cids = populate_with_pages(&primary, 3);
+        populate_with_pages(&secondary, 3);
+        let primary = FaultyPageStore::new(primary).with_latency(&[Op::Get], Duration::from_secs(1));
+        let config = CacheConfig { hedge_delay: Duration::from_millis(10), ..CacheConfig::default() };
+        let store = CachingPageStore::new(temp_dir.path(), primary, config).unwrap().with_secondary(secondary);
+
+        // The slow remote loses to the secondary
+        let start = Instant::now();
+        assert_eq!(store.get(&cids[0]).unwrap().data, b"page 0 data");
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(store.is_cached(&cids[0]));
+        assert_eq!(store.stats.hedged_reads.load(Ordering::Relaxed), 1);
+
+        // A failing one too, and a fast one isn't hedged
+        store.remote.clear();
+        store.remote.fail_next(&[Op::Get], 1);
+        assert_eq!(store.get(&cids[1]).unwrap().data, b"page 1 data");
+        assert_eq!(store.get(&cids[2]).unwrap().data, b"page 2 data");
+        assert_eq!(store.stats.hedged_reads.load(Ordering::Relaxed), 2);
+
+        // Missing from both
+        let missing = Cid::from_bytes(b"nowhere");
+        assert!(matches!(store.get(&missing), Err(PageStoreError::NotFound(_))));
+    }
+
     #[test]
     fn test_max_prefetch_pages() {
         let (_temp_dir, mut store) = create_test_store();
@@ -626,7 +697,7 @@ mod tests {
         store.config.max_prefetch_pages = 2;
 
         // Create more pages than the limit
-        let page_cids = store.remote.populate_with_pages(5);
+        let page_cids = populate_with_pages(&store.remote, 5);
         
         let mut page_table = PageTable::new();
         for (i, &cid) in page_cids.iter().enumerate() {
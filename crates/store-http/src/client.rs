@@ -0,0 +1,201 @@
+//! Minimal HTTP/1.1 client with a pool of keep-alive connections.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use craftsql_core::{PageStoreError, Result};
+
+/// A parsed response.
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Connections to one `host:port`, reused across requests.
+pub struct Client {
+    /// `host:port` to connect to.
+    addr: String,
+    /// `Host` header value.
+    host: String,
+    idle: Mutex<Vec<TcpStream>>,
+    pub max_idle: usize,
+    pub timeout: Duration,
+    connections_opened: AtomicU64,
+}
+
+impl Client {
+    pub fn new(host: &str) -> Self {
+        let addr = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+        Self {
+            addr,
+            host: host.to_string(),
+            idle: Mutex::new(Vec::new()),
+            max_idle: 8,
+            timeout: Duration::from_secs(30),
+            connections_opened: Default::default(),
+        }
+    }
+
+    /// Connections opened so far, pooled or not.
+    pub fn connections_opened(&self) -> u64 {
+        self.connections_opened.load(Ordering::Relaxed)
+    }
+
+    /// Send a request and read the whole response.
+    ///
+    /// A pooled connection the server has since closed fails on first use;
+    /// the request is then retried once on a fresh connection.
+    pub fn send(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<Response> {
+        let pooled = self.idle.lock().unwrap().pop();
+        if let Some(stream) = pooled {
+            if let Ok(response) = self.exchange(stream, method, path, headers, body) {
+                return Ok(response);
+            }
+        }
+        let stream = TcpStream::connect(&self.addr)
+            .map_err(|e| PageStoreError::network(format_args!("connect to {}", self.addr), e))?;
+        self.connections_opened.fetch_add(1, Ordering::Relaxed);
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        self.exchange(stream, method, path, headers, body)
+            .map_err(|e| match e.kind() {
+                // A garbled response won't read better the second time
+                std::io::ErrorKind::InvalidData => {
+                    PageStoreError::Storage(format!("{} {} on {}: {}", method, path, self.addr, e))
+                }
+                _ => PageStoreError::network(format_args!("{} {} on {}", method, path, self.addr), e),
+            })
+    }
+
+    fn exchange(
+        &self,
+        stream: TcpStream,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> std::io::Result<Response> {
+        let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, self.host);
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !body.is_empty() || matches!(method, "PUT" | "POST") {
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        request.push_str("\r\n");
+        (&stream).write_all(request.as_bytes())?;
+        (&stream).write_all(body)?;
+
+        let mut reader = BufReader::new(&stream);
+        let (response, reusable) = read_response(&mut reader, method == "HEAD")?;
+        drop(reader);
+        if reusable {
+            let mut idle = self.idle.lock().unwrap();
+            if idle.len() < self.max_idle {
+                idle.push(stream);
+            }
+        }
+        Ok(response)
+    }
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Read one response; also returns whether the connection can be reused.
+fn read_response(reader: &mut impl BufRead, head_only: bool) -> std::io::Result<(Response, bool)> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    let status = line.split_whitespace().nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("malformed status line"))?;
+    let mut keep_alive = line.starts_with("HTTP/1.1");
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':').ok_or_else(|| invalid("malformed header"))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    let header = |name: &str| {
+        headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    };
+    if let Some(connection) = header("connection") {
+        keep_alive = !connection.eq_ignore_ascii_case("close");
+    }
+
+    let mut body = Vec::new();
+    if head_only || status == 204 || status == 304 {
+        // No body
+    } else if header("transfer-encoding").is_some_and(|te| te.eq_ignore_ascii_case("chunked")) {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size = usize::from_str_radix(line.trim().split(';').next().unwrap_or(""), 16)
+                .map_err(|_| invalid("malformed chunk size"))?;
+            if size == 0 {
+                // Trailers, then the blank line
+                loop {
+                    line.clear();
+                    if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                        break;
+                    }
+                }
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            line.clear();
+            reader.read_line(&mut line)?;
+        }
+    } else if let Some(length) = header("content-length") {
+        let length: usize = length.parse().map_err(|_| invalid("malformed content length"))?;
+        body.resize(length, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        reader.read_to_end(&mut body)?;
+        keep_alive = false;
+    }
+    Ok((Response { status, headers, body }, keep_alive))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2;x=y\r\nde\r\n0\r\n\r\n";
+        let (response, reusable) = read_response(&mut &raw[..], false).unwrap();
+        assert_eq!((response.status, response.body.as_slice(), reusable), (200, &b"abcde"[..], true));
+
+        let raw = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        let (response, reusable) = read_response(&mut &raw[..], false).unwrap();
+        assert_eq!((response.status, reusable), (404, false));
+
+        let raw = b"HTTP/1.0 200 OK\r\n\r\nuntil close";
+        let (response, reusable) = read_response(&mut &raw[..], false).unwrap();
+        assert_eq!((response.body.as_slice(), reusable), (&b"until close"[..], false));
+    }
+}
@@ -0,0 +1,544 @@
+//! HTTP PageStore — pages and root pointers on a remote HTTP server.
+//!
+//! Speaks a small REST protocol under a base URL (`http://host[:port][/path]`):
+//!
+//! ```text
+//! GET    /pages/<cid hex>   page bytes, or 404; honours `Range: bytes=a-b`
+//! HEAD   /pages/<cid hex>   200 if stored, 404 if not
+//! PUT    /pages/<cid hex>   store a page; sent with `If-None-Match: *`,
+//!                           so 412 means it was already there
+//! DELETE /pages/<cid hex>   delete a page (404 if there was none)
+//! GET    /pages             one `<cid hex> <size>` line per page
+//! GET    /root              root CID as hex, or 404; ETag is the quoted hex
+//! PUT    /root              set the root; `If-Match` / `If-None-Match: *`
+//!                           make it a compare-and-set (412 on mismatch)
+//! GET    /refs              one `<name> <cid hex>` line per named root
+//! GET    /refs/<name>       named root as hex, or 404
+//! PUT    /refs/<name>       set a named root
+//! DELETE /refs/<name>       remove a named root (404 if there was none)
+//! ```
+//!
+//! Requests go over HTTP/1.1 keep-alive connections from a small pool. The
+//! root is re-validated with `If-None-Match`, so an unchanged root costs a
+//! 304. Wrap the store in a `CachingPageStore` to keep pages locally.
+
+mod client;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use craftsql_core::{Cid, Page, PageStore, PageStoreError, Result};
+
+use client::{Client, Response};
+
+/// PageStore backed by an HTTP server.
+pub struct HttpPageStore {
+    url: String,
+    client: Client,
+    /// Path prefix of every request, without a trailing `/`.
+    base_path: String,
+    /// Bearer token sent with every request.
+    token: Option<String>,
+    /// Last root seen and its ETag, for conditional fetches.
+    root: Mutex<Option<(String, Cid)>>,
+}
+
+impl HttpPageStore {
+    /// Connect to the store served at `url`. Only `http://` is supported;
+    /// TLS is left to a proxy.
+    pub fn new(url: &str) -> Result<Self> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            PageStoreError::Storage(format!("unsupported store URL {} (only http:// is supported)", url))
+        })?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        Ok(Self {
+            url: url.to_string(),
+            client: Client::new(host),
+            base_path: path.trim_end_matches('/').to_string(),
+            token: None,
+            root: Mutex::new(None),
+        })
+    }
+
+    /// Set the connect, read, and write timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client.timeout = timeout;
+        self
+    }
+
+    /// Keep at most `max_idle` idle connections open for reuse.
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.client.max_idle = max_idle;
+        self
+    }
+
+    /// Send `token` as a bearer token with every request.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Connections opened so far; with pooling, far fewer than requests made.
+    pub fn connections_opened(&self) -> u64 {
+        self.client.connections_opened()
+    }
+
+    /// Fetch `len` bytes of page `cid` starting at `offset`, without
+    /// transferring the rest. Shorter at the end of the page.
+    pub fn get_range(&self, cid: &Cid, offset: u64, len: u64) -> Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let range = format!("bytes={}-{}", offset, offset + len - 1);
+        let path = format!("/pages/{}", cid.to_hex());
+        let response = self.request("GET", &path, &[("Range", &range)], &[])?;
+        match response.status {
+            206 => Ok(response.body),
+            // The server ignored the range
+            200 => Ok(response.body.into_iter().skip(offset as usize).take(len as usize).collect()),
+            416 => Ok(Vec::new()),
+            404 => Err(PageStoreError::NotFound(*cid)),
+            _ => Err(status_error("GET", &path, &response)),
+        }
+    }
+
+    /// Set the root only if it is currently `expected`, failing with
+    /// [`PageStoreError::RootConflict`] otherwise.
+    pub fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        let etag = expected.map(|cid| format!("\"{}\"", cid.to_hex()));
+        let condition = match &etag {
+            Some(etag) => ("If-Match", etag.as_str()),
+            None => ("If-None-Match", "*"),
+        };
+        let response = self.request("PUT", "/root", &[condition], new.to_hex().as_bytes())?;
+        match response.status {
+            200 | 201 | 204 => {
+                *self.root.lock().unwrap() = Some((format!("\"{}\"", new.to_hex()), new));
+                Ok(())
+            }
+            412 => Err(PageStoreError::RootConflict { expected, actual: self.current_root()? }),
+            _ => Err(status_error("PUT", "/root", &response)),
+        }
+    }
+
+    fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<Response> {
+        let auth = self.token.as_ref().map(|token| format!("Bearer {}", token));
+        let mut all_headers = headers.to_vec();
+        if let Some(auth) = &auth {
+            all_headers.push(("Authorization", auth));
+        }
+        let response = self.client.send(method, &format!("{}{}", self.base_path, path), &all_headers, body)?;
+        if matches!(response.status, 401 | 403) {
+            return Err(PageStoreError::Unauthorized(format!("{} {}: HTTP {}", method, path, response.status)));
+        }
+        Ok(response)
+    }
+
+    fn ref_path(name: &str) -> String {
+        format!("/refs/{}", encode_name(name))
+    }
+}
+
+fn status_error(method: &str, path: &str, response: &Response) -> PageStoreError {
+    let message = format!("{} {}: HTTP {}", method, path, response.status);
+    match response.status {
+        408 | 504 => PageStoreError::Timeout(message),
+        // The server, or one behind a proxy, is down or overloaded
+        502 | 503 => PageStoreError::Network(message),
+        _ => PageStoreError::Storage(message),
+    }
+}
+
+fn parse_hex_cid(body: &[u8]) -> Result<Cid> {
+    let text = std::str::from_utf8(body).unwrap_or("").trim();
+    let bytes: [u8; 32] = hex::decode(text).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| PageStoreError::Storage(format!("invalid CID from server: {:?}", text)))?;
+    Ok(Cid(bytes))
+}
+
+/// Percent-encode everything but unreserved characters.
+fn encode_name(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl PageStore for HttpPageStore {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        let path = format!("/pages/{}", cid.to_hex());
+        let response = self.request("GET", &path, &[], &[])?;
+        match response.status {
+            200 => {
+                let actual = Cid::from_bytes(&response.body);
+                if actual != *cid {
+                    return Err(PageStoreError::Corruption { expected: *cid, actual });
+                }
+                Ok(Page { data: response.body })
+            }
+            404 => Err(PageStoreError::NotFound(*cid)),
+            _ => Err(status_error("GET", &path, &response)),
+        }
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        let cid = Cid::from_bytes(&page.data);
+        let path = format!("/pages/{}", cid.to_hex());
+        let response = self.request("PUT", &path, &[("If-None-Match", "*")], &page.data)?;
+        match response.status {
+            200 | 201 | 204 | 412 => Ok(cid),
+            _ => Err(status_error("PUT", &path, &response)),
+        }
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        let path = format!("/pages/{}", cid.to_hex());
+        let response = self.request("HEAD", &path, &[], &[])?;
+        match response.status {
+            200 => Ok(true),
+            404 => Ok(false),
+            _ => Err(status_error("HEAD", &path, &response)),
+        }
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        let response = self.request("PUT", "/root", &[], new_root.to_hex().as_bytes())?;
+        match response.status {
+            200 | 201 | 204 => {
+                *self.root.lock().unwrap() = Some((format!("\"{}\"", new_root.to_hex()), new_root));
+                Ok(())
+            }
+            _ => Err(status_error("PUT", "/root", &response)),
+        }
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        let cached = self.root.lock().unwrap().clone();
+        let condition = cached.as_ref().map(|(etag, _)| ("If-None-Match", etag.as_str()));
+        let response = self.request("GET", "/root", condition.as_slice(), &[])?;
+        let root = match response.status {
+            304 => return Ok(cached.map(|(_, cid)| cid)),
+            200 => parse_hex_cid(&response.body)?,
+            404 => {
+                *self.root.lock().unwrap() = None;
+                return Ok(None);
+            }
+            _ => return Err(status_error("GET", "/root", &response)),
+        };
+        *self.root.lock().unwrap() = response.header("etag").map(|etag| (etag.to_string(), root));
+        Ok(Some(root))
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        let path = Self::ref_path(name);
+        let response = self.request("PUT", &path, &[], cid.to_hex().as_bytes())?;
+        match response.status {
+            200 | 201 | 204 => Ok(()),
+            _ => Err(status_error("PUT", &path, &response)),
+        }
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        let path = Self::ref_path(name);
+        let response = self.request("GET", &path, &[], &[])?;
+        match response.status {
+            200 => Ok(Some(parse_hex_cid(&response.body)?)),
+            404 => Ok(None),
+            _ => Err(status_error("GET", &path, &response)),
+        }
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        let path = Self::ref_path(name);
+        let response = self.request("DELETE", &path, &[], &[])?;
+        match response.status {
+            200 | 204 => Ok(true),
+            404 => Ok(false),
+            _ => Err(status_error("DELETE", &path, &response)),
+        }
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        let response = self.request("GET", "/refs", &[], &[])?;
+        if response.status != 200 {
+            return Err(status_error("GET", "/refs", &response));
+        }
+        let mut roots = String::from_utf8_lossy(&response.body)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (name, cid) = line.rsplit_once(' ')
+                    .ok_or_else(|| PageStoreError::Storage(format!("invalid ref line from server: {:?}", line)))?;
+                Ok((name.to_string(), parse_hex_cid(cid.as_bytes())?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        roots.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(roots)
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        let response = self.request("GET", "/pages", &[], &[])?;
+        if response.status != 200 {
+            return Err(status_error("GET", "/pages", &response));
+        }
+        String::from_utf8_lossy(&response.body)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let invalid = || PageStoreError::Storage(format!("invalid page line from server: {:?}", line));
+                let (cid, size) = line.split_once(' ').ok_or_else(invalid)?;
+                Ok((parse_hex_cid(cid.as_bytes())?, size.trim().parse().map_err(|_| invalid())?))
+            })
+            .collect()
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        let path = format!("/pages/{}", cid.to_hex());
+        let response = self.request("DELETE", &path, &[], &[])?;
+        match response.status {
+            200 | 204 => Ok(true),
+            404 => Ok(false),
+            _ => Err(status_error("DELETE", &path, &response)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct Server {
+        pages: Mutex<HashMap<String, Vec<u8>>>,
+        root: Mutex<Option<String>>,
+        refs: Mutex<BTreeMap<String, String>>,
+        not_modified: AtomicUsize,
+    }
+
+    /// Serve the protocol under `/db` on a local port, keeping connections alive.
+    fn serve() -> (String, Arc<Server>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/db", listener.local_addr().unwrap());
+        let server = Arc::new(Server::default());
+        let state = Arc::clone(&server);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = Arc::clone(&state);
+                std::thread::spawn(move || while handle(&stream, &state).is_some() {});
+            }
+        });
+        (url, server)
+    }
+
+    fn handle(stream: &TcpStream, server: &Server) -> Option<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).ok().filter(|n| *n > 0)?;
+        let mut parts = line.split_whitespace();
+        let (method, path) = (parts.next()?.to_string(), parts.next()?.strip_prefix("/db")?.to_string());
+        let mut headers = HashMap::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).ok()?;
+            let Some((name, value)) = header.trim_end().split_once(':') else { break };
+            headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+        }
+        let mut body = vec![0; headers.get("content-length").map_or(0, |n| n.parse().unwrap())];
+        reader.read_exact(&mut body).ok()?;
+        let body = String::from_utf8_lossy(&body).into_owned();
+        let header = |name: &str| headers.get(name).map(String::as_str);
+
+        let (status, etag, content): (u16, Option<String>, Vec<u8>) = match (method.as_str(), path.as_str()) {
+            ("GET", "/root") => match server.root.lock().unwrap().clone() {
+                Some(root) if header("if-none-match") == Some(&format!("\"{}\"", root)) => {
+                    server.not_modified.fetch_add(1, Ordering::SeqCst);
+                    (304, None, vec![])
+                }
+                Some(root) => (200, Some(format!("\"{}\"", root)), root.into_bytes()),
+                None => (404, None, vec![]),
+            },
+            ("PUT", "/root") => {
+                let mut root = server.root.lock().unwrap();
+                let current = root.as_ref().map(|root| format!("\"{}\"", root));
+                let ok = match (header("if-match"), header("if-none-match")) {
+                    (Some(etag), _) => current.as_deref() == Some(etag),
+                    (_, Some("*")) => current.is_none(),
+                    _ => true,
+                };
+                if ok {
+                    *root = Some(body);
+                }
+                (if ok { 204 } else { 412 }, None, vec![])
+            }
+            ("GET", "/refs") => {
+                let list: String = server.refs.lock().unwrap().iter().map(|(k, v)| format!("{} {}\n", k, v)).collect();
+                (200, None, list.into_bytes())
+            }
+            (method, path) if path.starts_with("/refs/") => {
+                let name = path.trim_start_matches("/refs/").replace("%20", " ");
+                let mut refs = server.refs.lock().unwrap();
+                match method {
+                    "GET" => refs.get(&name).map_or((404, None, vec![]), |cid| (200, None, cid.clone().into_bytes())),
+                    "PUT" => {
+                        refs.insert(name, body);
+                        (204, None, vec![])
+                    }
+                    _ => (if refs.remove(&name).is_some() { 204 } else { 404 }, None, vec![]),
+                }
+            }
+            ("GET", "/pages") => {
+                let pages = server.pages.lock().unwrap();
+                let list: String = pages.iter().map(|(cid, data)| format!("{} {}\n", cid, data.len())).collect();
+                (200, None, list.into_bytes())
+            }
+            ("DELETE", path) => {
+                let removed = server.pages.lock().unwrap().remove(path.trim_start_matches("/pages/")).is_some();
+                (if removed { 204 } else { 404 }, None, vec![])
+            }
+            (method, path) => {
+                let cid = path.trim_start_matches("/pages/").to_string();
+                let mut pages = server.pages.lock().unwrap();
+                match (method, pages.get(&cid)) {
+                    ("PUT", Some(_)) => (412, None, vec![]),
+                    ("PUT", None) => {
+                        pages.insert(cid, body.into_bytes());
+                        (201, None, vec![])
+                    }
+                    (_, None) => (404, None, vec![]),
+                    ("GET", Some(data)) => match header("range").and_then(|r| r.strip_prefix("bytes=")) {
+                        Some(range) => {
+                            let (a, b) = range.split_once('-').unwrap();
+                            let (a, b): (usize, usize) = (a.parse().unwrap(), b.parse().unwrap());
+                            (206, None, data[a.min(data.len())..(b + 1).min(data.len())].to_vec())
+                        }
+                        None => (200, None, data.clone()),
+                    },
+                    (_, Some(data)) => (200, None, data.clone()),
+                }
+            }
+        };
+
+        let etag = etag.map(|etag| format!("ETag: {}\r\n", etag)).unwrap_or_default();
+        let length = if method == "HEAD" { 0 } else { content.len() };
+        let head = format!("HTTP/1.1 {} X\r\n{}Content-Length: {}\r\n\r\n", status, etag, length);
+        let mut writer = stream;
+        writer.write_all(head.as_bytes()).ok()?;
+        if method != "HEAD" {
+            writer.write_all(&content).ok()?;
+        }
+        Some(())
+    }
+
+    #[test]
+    fn test_pages_and_roots() {
+        let (url, _server) = serve();
+        let store = HttpPageStore::new(&url).unwrap();
+
+        let page = Page { data: b"page over http".to_vec() };
+        let cid = store.put(&page).unwrap();
+        assert_eq!(store.put(&page).unwrap(), cid);
+        assert_eq!(store.get(&cid).unwrap().data, page.data);
+        assert!(store.has(&cid).unwrap());
+        let missing = Cid::from_bytes(b"missing");
+        assert!(!store.has(&missing).unwrap());
+        assert!(matches!(store.get(&missing), Err(PageStoreError::NotFound(_))));
+        assert_eq!(store.get_range(&cid, 5, 4).unwrap(), b"over");
+        assert_eq!(store.list_pages().unwrap(), vec![(cid, 14)]);
+
+        assert_eq!(store.current_root().unwrap(), None);
+        store.update_root(cid).unwrap();
+        assert_eq!(store.current_root().unwrap(), Some(cid));
+
+        store.set_named_root("main", cid).unwrap();
+        store.set_named_root("a b", missing).unwrap();
+        assert_eq!(store.get_named_root("main").unwrap(), Some(cid));
+        assert_eq!(store.get_named_root("a b").unwrap(), Some(missing));
+        assert_eq!(store.list_named_roots().unwrap(), vec![("a b".into(), missing), ("main".into(), cid)]);
+        assert!(store.remove_named_root("main").unwrap());
+        assert!(!store.remove_named_root("main").unwrap());
+        assert!(store.delete_page(&cid).unwrap());
+        assert!(!store.delete_page(&cid).unwrap());
+
+        // Every request above went over one pooled connection
+        assert_eq!(store.connections_opened(), 1);
+    }
+
+    #[test]
+    fn test_conditional_root() {
+        let (url, server) = serve();
+        let store = HttpPageStore::new(&url).unwrap();
+        let (a, b) = (Cid::from_bytes(b"a"), Cid::from_bytes(b"b"));
+
+        store.set_root_if(None, a).unwrap();
+        assert!(matches!(
+            store.set_root_if(None, b),
+            Err(PageStoreError::RootConflict { expected: None, actual: Some(actual) }) if actual == a
+        ));
+        store.set_root_if(Some(a), b).unwrap();
+
+        // Another client's view revalidates to 304 until the root moves
+        let other = HttpPageStore::new(&url).unwrap();
+        let before = server.not_modified.load(Ordering::SeqCst);
+        assert_eq!(other.current_root().unwrap(), Some(b));
+        assert_eq!(other.current_root().unwrap(), Some(b));
+        assert_eq!(server.not_modified.load(Ordering::SeqCst), before + 1);
+        store.update_root(a).unwrap();
+        assert_eq!(other.current_root().unwrap(), Some(a));
+    }
+
+    #[test]
+    fn test_wrapped_in_cache() {
+        use craftsql_store_cached::{CacheConfig, CachingPageStore};
+
+        let (url, server) = serve();
+        let tmp = tempfile::tempdir().unwrap();
+        let remote = HttpPageStore::new(&url).unwrap();
+        let cid = remote.put(&Page { data: b"shared".to_vec() }).unwrap();
+
+        let cached = CachingPageStore::new(tmp.path(), remote, CacheConfig::default()).unwrap();
+        assert_eq!(cached.get(&cid).unwrap().data, b"shared");
+        server.pages.lock().unwrap().clear();
+        assert_eq!(cached.get(&cid).unwrap().data, b"shared");
+    }
+
+    #[test]
+    fn test_reconnects_after_server_closes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            // Answer one request per connection, then hang up
+            for mut stream in listener.incoming().flatten() {
+                let mut line = String::new();
+                let mut reader = BufReader::new(&stream);
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+        let store = HttpPageStore::new(&url).unwrap();
+        assert_eq!(store.get_named_root("main").unwrap(), None);
+        assert_eq!(store.get_named_root("main").unwrap(), None);
+        assert_eq!(store.connections_opened(), 2);
+    }
+
+    #[test]
+    fn test_rejects_other_schemes() {
+        assert!(HttpPageStore::new("https://example.com/db").is_err());
+    }
+}
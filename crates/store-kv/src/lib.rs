@@ -0,0 +1,286 @@
+//! Embedded KV PageStore — pages, root, and named roots in one redb file.
+//!
+//! Same contents as a `LocalPageStore`, but a single file instead of a
+//! directory of thousands of small ones, which suits filesystems and backup
+//! tools that handle many small files badly. Every write is its own ACID
+//! transaction, so a crash never leaves a page half-written.
+
+use std::path::{Path, PathBuf};
+
+use craftsql_core::{Cid, Page, PageStore, PageStoreError, Result};
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition};
+
+/// Page data by CID.
+const PAGES: TableDefinition<&[u8; 32], &[u8]> = TableDefinition::new("pages");
+
+/// Named roots by name; the default root is kept in [`META`].
+const REFS: TableDefinition<&str, &[u8; 32]> = TableDefinition::new("refs");
+
+/// Store-wide pointers, currently only [`ROOT_KEY`].
+const META: TableDefinition<&str, &[u8; 32]> = TableDefinition::new("meta");
+
+const ROOT_KEY: &str = "root";
+
+fn storage<E: std::fmt::Display>(e: E) -> PageStoreError {
+    PageStoreError::Storage(e.to_string())
+}
+
+/// Pages, root, and named roots in a single embedded database file.
+pub struct KvPageStore {
+    db: Database,
+    path: PathBuf,
+}
+
+impl KvPageStore {
+    /// Open the store at `path`, creating the file if it doesn't exist.
+    pub fn new(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let db = Database::create(path).map_err(storage)?;
+
+        // Create the tables up front so reads never see them missing
+        let txn = db.begin_write().map_err(storage)?;
+        txn.open_table(PAGES).map_err(storage)?;
+        txn.open_table(REFS).map_err(storage)?;
+        txn.open_table(META).map_err(storage)?;
+        txn.commit().map_err(storage)?;
+
+        Ok(Self { db, path: path.to_path_buf() })
+    }
+
+    /// The database file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of stored pages.
+    pub fn page_count(&self) -> Result<u64> {
+        let txn = self.db.begin_read().map_err(storage)?;
+        txn.open_table(PAGES).map_err(storage)?.len().map_err(storage)
+    }
+
+    /// Delete a page. Returns whether it was stored.
+    pub fn remove(&self, cid: &Cid) -> Result<bool> {
+        let txn = self.db.begin_write().map_err(storage)?;
+        let removed = txn.open_table(PAGES).map_err(storage)?.remove(&cid.0).map_err(storage)?.is_some();
+        txn.commit().map_err(storage)?;
+        Ok(removed)
+    }
+
+    /// Delete several pages in one transaction, returning for each whether
+    /// it was stored.
+    pub fn remove_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        let txn = self.db.begin_write().map_err(storage)?;
+        let mut removed = Vec::with_capacity(cids.len());
+        {
+            let mut table = txn.open_table(PAGES).map_err(storage)?;
+            for cid in cids {
+                removed.push(table.remove(&cid.0).map_err(storage)?.is_some());
+            }
+        }
+        txn.commit().map_err(storage)?;
+        Ok(removed)
+    }
+
+    /// Store several pages in one transaction, returning their CIDs in order.
+    pub fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        let txn = self.db.begin_write().map_err(storage)?;
+        let mut cids = Vec::with_capacity(pages.len());
+        {
+            let mut table = txn.open_table(PAGES).map_err(storage)?;
+            for page in pages {
+                let cid = Cid::from_bytes(&page.data);
+                if table.get(&cid.0).map_err(storage)?.is_none() {
+                    table.insert(&cid.0, page.data.as_slice()).map_err(storage)?;
+                }
+                cids.push(cid);
+            }
+        }
+        txn.commit().map_err(storage)?;
+        Ok(cids)
+    }
+
+    /// Set the root only if it is currently `expected`, failing with
+    /// [`PageStoreError::RootConflict`] otherwise. Atomic with respect to
+    /// other writers of this file.
+    pub fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        let txn = self.db.begin_write().map_err(storage)?;
+        {
+            let mut table = txn.open_table(META).map_err(storage)?;
+            let actual = table.get(ROOT_KEY).map_err(storage)?.map(|v| Cid(*v.value()));
+            if actual != expected {
+                return Err(PageStoreError::RootConflict { expected, actual });
+            }
+            table.insert(ROOT_KEY, &new.0).map_err(storage)?;
+        }
+        txn.commit().map_err(storage)
+    }
+
+    fn read_pointer(&self, table: TableDefinition<&str, &[u8; 32]>, key: &str) -> Result<Option<Cid>> {
+        let txn = self.db.begin_read().map_err(storage)?;
+        let table = txn.open_table(table).map_err(storage)?;
+        Ok(table.get(key).map_err(storage)?.map(|v| Cid(*v.value())))
+    }
+
+    fn write_pointer(&self, table: TableDefinition<&str, &[u8; 32]>, key: &str, cid: Cid) -> Result<()> {
+        let txn = self.db.begin_write().map_err(storage)?;
+        txn.open_table(table).map_err(storage)?.insert(key, &cid.0).map_err(storage)?;
+        txn.commit().map_err(storage)
+    }
+}
+
+impl PageStore for KvPageStore {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        let txn = self.db.begin_read().map_err(storage)?;
+        let table = txn.open_table(PAGES).map_err(storage)?;
+        let data = table.get(&cid.0).map_err(storage)?.ok_or(PageStoreError::NotFound(*cid))?;
+        Ok(Page { data: data.value().to_vec() })
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        let cid = Cid::from_bytes(&page.data);
+        if !self.has(&cid)? {
+            self.put_many(std::slice::from_ref(page))?;
+        }
+        Ok(cid)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        KvPageStore::put_many(self, pages)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        let txn = self.db.begin_read().map_err(storage)?;
+        let table = txn.open_table(PAGES).map_err(storage)?;
+        Ok(table.get(&cid.0).map_err(storage)?.is_some())
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        self.write_pointer(META, ROOT_KEY, new_root)
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        self.read_pointer(META, ROOT_KEY)
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.write_pointer(REFS, name, cid)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.read_pointer(REFS, name)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        let txn = self.db.begin_write().map_err(storage)?;
+        let removed = txn.open_table(REFS).map_err(storage)?.remove(name).map_err(storage)?.is_some();
+        txn.commit().map_err(storage)?;
+        Ok(removed)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        let txn = self.db.begin_read().map_err(storage)?;
+        let table = txn.open_table(REFS).map_err(storage)?;
+        // Keys iterate in order, so the list comes out sorted
+        table.iter().map_err(storage)?
+            .map(|entry| {
+                let (name, cid) = entry.map_err(storage)?;
+                Ok((name.value().to_string(), Cid(*cid.value())))
+            })
+            .collect()
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        let txn = self.db.begin_read().map_err(storage)?;
+        let table = txn.open_table(PAGES).map_err(storage)?;
+        table.iter().map_err(storage)?
+            .map(|entry| {
+                let (cid, data) = entry.map_err(storage)?;
+                Ok((Cid(*cid.value()), data.value().len() as u64))
+            })
+            .collect()
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.remove(cid)
+    }
+
+    fn delete_many(&self, cids: &[Cid]) -> Result<Vec<bool>> {
+        self.remove_many(cids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages_and_roots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = KvPageStore::new(&tmp.path().join("db.redb")).unwrap();
+
+        let page = Page { data: vec![1; 4096] };
+        let cid = store.put(&page).unwrap();
+        assert_eq!(store.put(&page).unwrap(), cid);
+        assert_eq!(store.get(&cid).unwrap().data, page.data);
+        assert!(store.has(&cid).unwrap());
+        assert_eq!(store.page_count().unwrap(), 1);
+        let missing = Cid::from_bytes(b"missing");
+        assert!(matches!(store.get(&missing), Err(PageStoreError::NotFound(_))));
+
+        assert_eq!(store.current_root().unwrap(), None);
+        store.update_root(cid).unwrap();
+        assert_eq!(store.current_root().unwrap(), Some(cid));
+
+        store.set_named_root("main", cid).unwrap();
+        store.set_named_root("dev/feature x", missing).unwrap();
+        assert_eq!(store.get_named_root("main").unwrap(), Some(cid));
+        assert_eq!(
+            store.list_named_roots().unwrap(),
+            vec![("dev/feature x".to_string(), missing), ("main".to_string(), cid)]
+        );
+        assert!(store.remove_named_root("main").unwrap());
+        assert!(!store.remove_named_root("main").unwrap());
+
+        assert!(store.remove(&cid).unwrap());
+        assert!(!store.has(&cid).unwrap());
+        let other = store.put(&Page { data: b"other".to_vec() }).unwrap();
+        assert_eq!(store.delete_many(&[cid, other]).unwrap(), vec![false, true]);
+        assert!(!store.has(&other).unwrap());
+    }
+
+    #[test]
+    fn test_persists_in_one_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("nested").join("db.redb");
+        let pages: Vec<Page> = (0..100u8).map(|i| Page { data: vec![i; 4096] }).collect();
+        let cids = {
+            let store = KvPageStore::new(&path).unwrap();
+            let cids = store.put_many(&pages).unwrap();
+            store.update_root(cids[0]).unwrap();
+            cids
+        };
+
+        assert_eq!(std::fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+        let store = KvPageStore::new(&path).unwrap();
+        assert_eq!(store.page_count().unwrap(), 100);
+        assert_eq!(store.get(&cids[42]).unwrap().data, pages[42].data);
+        assert_eq!(store.current_root().unwrap(), Some(cids[0]));
+    }
+
+    #[test]
+    fn test_set_root_if() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = KvPageStore::new(&tmp.path().join("db.redb")).unwrap();
+        let (a, b) = (Cid::from_bytes(b"a"), Cid::from_bytes(b"b"));
+
+        store.set_root_if(None, a).unwrap();
+        assert!(matches!(
+            store.set_root_if(None, b),
+            Err(PageStoreError::RootConflict { expected: None, actual: Some(actual) }) if actual == a
+        ));
+        store.set_root_if(Some(a), b).unwrap();
+        assert_eq!(store.current_root().unwrap(), Some(b));
+    }
+}
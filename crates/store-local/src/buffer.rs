@@ -0,0 +1,141 @@
+//! Background page writes: pages are buffered in memory and written to disk
+//! in batches by a thread of their own.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+use craftsql_core::{Cid, PageStoreError, Result};
+
+use crate::LocalPageStore;
+
+/// Most pages the writer thread takes in one batch.
+const BATCH_PAGES: usize = 1024;
+
+#[derive(Default)]
+struct State {
+    /// Pages put but not yet on disk, including the batch being written.
+    pages: HashMap<Cid, Arc<[u8]>>,
+    /// Pages waiting for the writer, oldest first.
+    queue: VecDeque<Cid>,
+    /// Bytes held in `pages`.
+    bytes: usize,
+    /// Whether the writer has a batch in hand.
+    writing: bool,
+    /// The first write that failed since the last flush.
+    error: Option<String>,
+    closed: bool,
+}
+
+/// Pages waiting for a [`LocalPageStore`]'s writer thread.
+pub(crate) struct WriteBuffer {
+    state: Mutex<State>,
+    changed: Condvar,
+    /// Puts wait while more than this many bytes are buffered.
+    max_bytes: usize,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WriteBuffer {
+    /// Start a writer thread storing pages in `writer`.
+    pub(crate) fn start(writer: LocalPageStore, max_bytes: usize) -> Arc<Self> {
+        let buffer = Arc::new(Self {
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+            max_bytes,
+            writer: Mutex::new(None),
+        });
+        let thread_buffer = Arc::clone(&buffer);
+        let handle = std::thread::Builder::new()
+            .name("craftsql-page-writer".into())
+            .spawn(move || thread_buffer.run(&writer))
+            .expect("spawn page writer thread");
+        *buffer.writer.lock().unwrap() = Some(handle);
+        buffer
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.changed.wait(state).unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn run(&self, writer: &LocalPageStore) {
+        let mut state = self.lock();
+        loop {
+            if state.queue.is_empty() {
+                if state.closed {
+                    return;
+                }
+                state = self.wait(state);
+                continue;
+            }
+            let state_ref = &mut *state;
+            let take = state_ref.queue.len().min(BATCH_PAGES);
+            let batch: Vec<(Cid, Arc<[u8]>)> = state_ref.queue
+                .drain(..take)
+                .map(|cid| (cid, Arc::clone(&state_ref.pages[&cid])))
+                .collect();
+            state.writing = true;
+            drop(state);
+
+            let pages: Vec<&[u8]> = batch.iter().map(|(_, data)| &data[..]).collect();
+            let result = writer.write_pages(&pages);
+
+            state = self.lock();
+            for (cid, data) in &batch {
+                state.pages.remove(cid);
+                state.bytes -= data.len();
+            }
+            state.writing = false;
+            if let Err(e) = result {
+                state.error.get_or_insert(e.to_string());
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    /// Buffer page `cid`, once there is room for it.
+    pub(crate) fn put(&self, cid: Cid, data: &[u8]) {
+        let mut state = self.lock();
+        while state.bytes > self.max_bytes {
+            state = self.wait(state);
+        }
+        if state.pages.contains_key(&cid) {
+            return;
+        }
+        state.pages.insert(cid, Arc::from(data));
+        state.queue.push_back(cid);
+        state.bytes += data.len();
+        self.changed.notify_all();
+    }
+
+    /// The buffered page `cid`, if it hasn't reached the disk yet.
+    pub(crate) fn get(&self, cid: &Cid) -> Option<Arc<[u8]>> {
+        self.lock().pages.get(cid).cloned()
+    }
+
+    /// Wait until every page buffered so far is on disk, then report the
+    /// first write that failed since the last flush.
+    pub(crate) fn flush(&self) -> Result<()> {
+        let mut state = self.lock();
+        while !state.queue.is_empty() || state.writing {
+            state = self.wait(state);
+        }
+        match state.error.take() {
+            Some(e) => Err(PageStoreError::Storage(format!("background page write: {}", e))),
+            None => Ok(()),
+        }
+    }
+
+    /// Write what is buffered and stop the writer thread.
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+        if let Some(handle) = self.writer.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = handle.join();
+        }
+    }
+}
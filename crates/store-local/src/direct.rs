@@ -0,0 +1,98 @@
+//! Page file I/O around the OS page cache, for
+//! [`LocalPageStore::with_direct_io`](crate::LocalPageStore::with_direct_io)
+//! (`direct-io` feature).
+//!
+//! On Linux files are opened with `O_DIRECT`, which moves data straight
+//! between the disk and buffers aligned to [`ALIGN`], in multiples of it.
+//! Filesystems that refuse the flag, such as tmpfs, get ordinary I/O, as do
+//! other platforms for now.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// Alignment of direct I/O buffers, offsets, and lengths: a multiple of
+/// the block size of any disk in use.
+#[cfg(target_os = "linux")]
+const ALIGN: usize = 4096;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::fs::{FileExt, OpenOptionsExt};
+    use std::os::unix::io::AsRawFd;
+
+    /// A zeroed buffer of `len` bytes, rounded up to a multiple of
+    /// [`ALIGN`], and where the aligned part starts in it.
+    fn aligned(len: usize) -> (Vec<u8>, usize, usize) {
+        let len = len.div_ceil(ALIGN).max(1) * ALIGN;
+        let buf = vec![0u8; len + ALIGN];
+        let start = buf.as_ptr().align_offset(ALIGN);
+        (buf, start, len)
+    }
+
+    pub(crate) fn open(options: &mut OpenOptions, path: &Path) -> io::Result<File> {
+        match options.clone().custom_flags(libc::O_DIRECT).open(path) {
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => options.open(path),
+            result => result,
+        }
+    }
+
+    fn is_direct(file: &File) -> bool {
+        // SAFETY: F_GETFL only reads the flags of a descriptor we own.
+        let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+        flags != -1 && flags & libc::O_DIRECT != 0
+    }
+
+    pub(crate) fn read(path: &Path) -> io::Result<Vec<u8>> {
+        let file = open(OpenOptions::new().read(true), path)?;
+        if !is_direct(&file) {
+            return std::fs::read(path);
+        }
+        let len = file.metadata()?.len() as usize;
+        let (mut buf, start, aligned_len) = aligned(len);
+        let mut read = 0;
+        while read < len {
+            match file.read_at(&mut buf[start + read..start + aligned_len], read as u64)? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        buf.drain(..start);
+        buf.truncate(read.min(len));
+        Ok(buf)
+    }
+
+    /// Write `data` to the start of the new, empty `file`.
+    pub(crate) fn write(file: &mut File, data: &[u8]) -> io::Result<()> {
+        if !is_direct(file) || data.is_empty() {
+            return file.write_all(data);
+        }
+        // Written padded to a whole block, then cut back to size
+        let (mut buf, start, aligned_len) = aligned(data.len());
+        buf[start..start + data.len()].copy_from_slice(data);
+        file.write_all_at(&buf[start..start + aligned_len], 0)?;
+        file.set_len(data.len() as u64)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::*;
+    use std::io::Write;
+
+    pub(crate) fn open(options: &mut OpenOptions, path: &Path) -> io::Result<File> {
+        options.open(path)
+    }
+
+    pub(crate) fn read(path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    pub(crate) fn write(file: &mut File, data: &[u8]) -> io::Result<()> {
+        file.write_all(data)
+    }
+}
+
+pub(crate) use imp::{open, read, write};
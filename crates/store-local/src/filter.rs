@@ -0,0 +1,122 @@
+//! A bloom filter of the pages in a directory, so lookups of pages that
+//! aren't there skip the filesystem.
+
+use craftsql_core::Cid;
+
+/// Leading bytes of a saved filter.
+const MAGIC: &[u8; 8] = b"csqlcf01";
+
+/// Bits per page the filter is sized for: about a 1% false positive rate.
+const BITS_PER_PAGE: usize = 10;
+const HASHES: u64 = 7;
+
+/// The smallest number of pages a filter is sized for.
+const MIN_CAPACITY: usize = 1024;
+
+/// A bloom filter of CIDs. It can say a CID was never inserted, but not
+/// that one was: a hit may be a false positive, or a page removed since.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CidFilter {
+    words: Vec<u64>,
+    /// CIDs inserted, duplicates included.
+    inserted: usize,
+    /// CIDs removed since the filter was built; their bits stay set.
+    removed: usize,
+}
+
+impl CidFilter {
+    /// An empty filter sized for `capacity` CIDs.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let words = (capacity.max(MIN_CAPACITY) * BITS_PER_PAGE).div_ceil(64);
+        Self { words: vec![0; words], inserted: 0, removed: 0 }
+    }
+
+    /// The bits `cid` sets. CIDs are already uniform hashes, so two words
+    /// of one are enough to derive them all (double hashing).
+    fn bits(&self, cid: &Cid) -> impl Iterator<Item = usize> {
+        let word = |i: usize| u64::from_le_bytes(cid.0[i..i + 8].try_into().expect("8 bytes"));
+        let (h1, h2) = (word(0), word(8) | 1);
+        let len = (self.words.len() * 64) as u64;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    pub fn insert(&mut self, cid: &Cid) {
+        let bits: Vec<usize> = self.bits(cid).collect();
+        for bit in bits {
+            self.words[bit / 64] |= 1 << (bit % 64);
+        }
+        self.inserted += 1;
+    }
+
+    /// Count a removal. The removed CID's bits stay set, since other CIDs
+    /// may share them; see [`is_stale`](Self::is_stale).
+    pub fn count_removal(&mut self) {
+        self.removed += 1;
+    }
+
+    /// Whether `cid` may have been inserted; `false` means it surely wasn't.
+    pub fn may_contain(&self, cid: &Cid) -> bool {
+        self.bits(cid).all(|bit| self.words[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Whether the filter holds enough more than it was sized for, or
+    /// enough removed CIDs, that rebuilding it would pay.
+    pub fn is_stale(&self) -> bool {
+        let capacity = self.words.len() * 64 / BITS_PER_PAGE;
+        self.inserted > capacity || self.removed > self.inserted / 2 + MIN_CAPACITY
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(24 + self.words.len() * 8);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(self.inserted as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.removed as u64).to_le_bytes());
+        for word in &self.words {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Parse [`to_bytes`](Self::to_bytes) output; `None` if it isn't a filter.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let rest = bytes.strip_prefix(MAGIC)?;
+        if rest.len() < 24 || rest.len() % 8 != 0 {
+            return None;
+        }
+        let mut words = rest.chunks_exact(8).map(|chunk| u64::from_le_bytes(chunk.try_into().expect("8 bytes")));
+        let inserted = words.next()? as usize;
+        let removed = words.next()? as usize;
+        Some(Self { words: words.collect(), inserted, removed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cid_filter() {
+        let cids: Vec<Cid> = (0..2000u32).map(|i| Cid::from_bytes(&i.to_le_bytes())).collect();
+        let mut filter = CidFilter::with_capacity(2000);
+        for cid in &cids[..1000] {
+            filter.insert(cid);
+        }
+        assert!(cids[..1000].iter().all(|cid| filter.may_contain(cid)));
+        let false_positives = cids[1000..].iter().filter(|cid| filter.may_contain(cid)).count();
+        assert!(false_positives < 30, "{} false positives", false_positives);
+        assert!(!filter.is_stale());
+
+        let copy = CidFilter::from_bytes(&filter.to_bytes()).unwrap();
+        assert_eq!(copy, filter);
+        assert_eq!(CidFilter::from_bytes(b"csqlcf01"), None);
+        assert_eq!(CidFilter::from_bytes(b"not a filter"), None);
+
+        for cid in &cids[1000..] {
+            filter.insert(cid);
+        }
+        for cid in &cids {
+            filter.insert(cid);
+        }
+        assert!(filter.is_stale());
+    }
+}
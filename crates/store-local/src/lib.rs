@@ -1,24 +1,657 @@
 //! Local disk PageStore — pages as files, root in metadata file.
 //! For development, testing, and offline single-machine use.
 
-use craftsql_core::{Cid, Page, PageStore, PageStoreError, Result};
-use std::fs;
+use buffer::WriteBuffer;
+use craftsql_core::{replace_file, Cid, Commit, CommitMeta, Page, PageStore, PageStoreError, Result, RootSignal};
+use std::fs::{self, File};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
+mod buffer;
+#[cfg(feature = "direct-io")]
+mod direct;
+mod filter;
+mod watch;
+
+pub use filter::CidFilter;
+
+/// Map a ref name to a safe file name (no path traversal). Names Windows
+/// won't create files under — devices like `con` or `lpt1.txt`, and names
+/// ending in a dot — are escaped on every platform, so a store directory
+/// means the same refs wherever it is copied.
+pub fn sanitize_ref_name(name: &str) -> String {
+    let mut safe: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect();
+    if safe.ends_with('.') {
+        safe.pop();
+        safe.push('_');
+    }
+    if is_reserved_on_windows(safe.split('.').next().unwrap_or_default()) {
+        safe.insert(0, '_');
+    }
+    safe
+}
+
+/// Whether Windows reserves `stem` for a device, with any extension.
+fn is_reserved_on_windows(stem: &str) -> bool {
+    let stem = stem.to_ascii_uppercase();
+    match stem.as_bytes() {
+        b"CON" | b"PRN" | b"AUX" | b"NUL" => true,
+        [b'C', b'O', b'M', digit] | [b'L', b'P', b'T', digit] => (b'1'..=b'9').contains(digit),
+        _ => false,
+    }
+}
+
+/// How much a [`LocalPageStore`] does to make a write survive a crash or
+/// power loss before returning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Leave flushing to the OS: fastest, but a crash can lose recent
+    /// writes or leave partly written pages.
+    #[default]
+    None,
+    /// Write pages without flushing them, then flush every page written
+    /// since the last commit before a root or named root moves. A crash
+    /// can lose pages no root points at yet, but never ones a root does.
+    Commit,
+    /// Write a batch's pages, flush them all, then flush the directory
+    /// once. [`put_many`](PageStore::put_many) costs one directory flush
+    /// however many pages it writes.
+    Batch,
+    /// Flush each page and the directory after every page.
+    PerPage,
+}
+
+/// How a [`LocalPageStore`] compresses page files on disk.
+///
+/// In a compressed store every page file starts with a one-byte header
+/// saying how the rest is stored: as is, for pages compression doesn't
+/// shrink, or as a zstd frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Page files hold the pages as they are, with no header.
+    #[default]
+    None,
+    /// zstd at the given level, 1 (fastest) to 22; zstd's default is 3.
+    Zstd(i32),
+}
+
+impl Compression {
+    /// As recorded in the store's `meta` file.
+    fn to_meta(self) -> String {
+        match self {
+            Compression::None => "none".to_string(),
+            Compression::Zstd(level) => format!("zstd:{}", level),
+        }
+    }
+
+    fn from_meta(value: &str) -> Option<Self> {
+        match value.split_once(':') {
+            None if value == "none" => Some(Compression::None),
+            Some(("zstd", level)) => level.parse().ok().map(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Header of a page file in a compressed store holding the page as is.
+const HEADER_STORED: u8 = 0;
+/// Header of a page file in a compressed store holding a zstd frame.
+const HEADER_ZSTD: u8 = 1;
+
+/// A stored page's bytes, from [`LocalPageStore::read_page`]: mapped from
+/// its file with the `mmap` feature, read into memory otherwise.
+pub struct PageData(PageBytes);
+
+enum PageBytes {
+    /// The file, and where the page starts in it.
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap, usize),
+    Read(Vec<u8>),
+}
+
+impl PageData {
+    /// The bytes as a [`Page`], copied out of the file if mapped.
+    pub fn into_page(self) -> Page {
+        match self.0 {
+            #[cfg(feature = "mmap")]
+            PageBytes::Mapped(map, start) => Page { data: map[start..].to_vec() },
+            PageBytes::Read(data) => Page { data },
+        }
+    }
+}
+
+impl std::ops::Deref for PageData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            #[cfg(feature = "mmap")]
+            PageBytes::Mapped(map, start) => &map[*start..],
+            PageBytes::Read(data) => data,
+        }
+    }
+}
+
+impl AsRef<[u8]> for PageData {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Page files a [`Durability::Batch`] write keeps open before flushing them.
+const BATCH_OPEN_FILES: usize = 256;
+
+/// Pages, root, and named roots on local disk.
+///
+/// Layout under `dir`: `pages/<cid hex>`, `root`, and `refs/<name>`, each
+/// pointer file holding a hex CID. `meta` records settings the store's
+/// files depend on, if any are set, and `commits/<root hex>` the [`Commit`]
+/// recorded with each root moved to with metadata. Other stores that keep
+/// a local cache (e.g. the CraftOBJ store) embed one of these so they share
+/// the layout.
 pub struct LocalPageStore {
     dir: PathBuf,
+    /// Pages this store has, if it keeps a filter; see
+    /// [`with_cid_filter`](Self::with_cid_filter).
+    filter: Option<Mutex<CidFilter>>,
+    durability: Durability,
+    /// How page files are compressed; see
+    /// [`with_compression`](Self::with_compression).
+    compression: Compression,
+    /// Read and write page files around the OS page cache; see
+    /// `with_direct_io` (`direct-io` feature).
+    direct_io: bool,
+    /// Refuse every write; see [`open_read_only`](Self::open_read_only).
+    read_only: bool,
+    /// Pages waiting to be written in the background; see
+    /// [`with_background_writes`](Self::with_background_writes).
+    buffer: Option<Arc<WriteBuffer>>,
+    /// Pages written but not yet flushed, with [`Durability::Commit`].
+    /// Shared with the background writer, if any.
+    unsynced: Arc<Mutex<Vec<Cid>>>,
 }
 
 impl LocalPageStore {
     pub fn new(dir: &Path) -> Result<Self> {
         fs::create_dir_all(dir.join("pages"))?;
-        Ok(Self { dir: dir.to_path_buf() })
+        let compression = Self::read_compression(dir)?;
+        Ok(Self { dir: dir.to_path_buf(), filter: None, durability: Durability::None, compression, direct_io: false, read_only: false, buffer: None, unsynced: Arc::default() })
+    }
+
+    /// Open the existing store in `dir` for reading only. Every write,
+    /// pages and roots alike, fails with [`PageStoreError::ReadOnly`], and
+    /// nothing in `dir` is created or changed, so tools inspecting a live
+    /// store can't touch it by accident.
+    pub fn open_read_only(dir: &Path) -> Result<Self> {
+        if !dir.join("pages").is_dir() {
+            return Err(PageStoreError::Storage(format!("no store at {}", dir.display())));
+        }
+        let compression = Self::read_compression(dir)?;
+        Ok(Self { dir: dir.to_path_buf(), filter: None, durability: Durability::None, compression, direct_io: false, read_only: true, buffer: None, unsynced: Arc::default() })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fail with [`PageStoreError::ReadOnly`] if the store is read-only.
+    fn check_writable(&self, what: &str) -> Result<()> {
+        if self.read_only {
+            return Err(PageStoreError::ReadOnly(format!("can't {} in {}", what, self.dir.display())));
+        }
+        Ok(())
+    }
+
+    /// Flush writes to disk as `durability` says. Roots and named roots are
+    /// then flushed too, and replaced atomically.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Compress page files on disk as `compression` says, decompressing
+    /// them on read. Pages and their CIDs are unchanged: only the files
+    /// shrink, and [`list_pages`](PageStore::list_pages) reports their size
+    /// on disk.
+    ///
+    /// Whether a store compresses is fixed when it's created: this is
+    /// recorded in its `meta` file, which later opens go by without being
+    /// told, and fails on a store that already holds pages stored the other
+    /// way. Changing the level of a compressed store is fine, as it only
+    /// applies to pages written from then on. Call this before
+    /// [`with_background_writes`](Self::with_background_writes).
+    pub fn with_compression(mut self, compression: Compression) -> Result<Self> {
+        if compression == self.compression {
+            return Ok(self);
+        }
+        let compressed = |c: Compression| c != Compression::None;
+        if compressed(compression) != compressed(self.compression) && fs::read_dir(self.dir.join("pages"))?.next().is_some() {
+            return Err(PageStoreError::Storage(format!(
+                "can't store pages in {} with compression {}: it already holds pages stored with {}",
+                self.dir.display(), compression.to_meta(), self.compression.to_meta(),
+            )));
+        }
+        self.check_writable("change compression")?;
+        fs::write(self.meta_path(), format!("compression={}\n", compression.to_meta()))?;
+        self.compression = compression;
+        Ok(self)
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Read and write page files without going through the OS page cache,
+    /// so that scanning or loading a large database doesn't evict what the
+    /// rest of the program has cached. Each read then goes to the disk, and
+    /// [`read_page`](Self::read_page) reads rather than maps.
+    ///
+    /// Uses `O_DIRECT` on Linux, falling back to ordinary I/O on
+    /// filesystems that don't support it; elsewhere this has no effect yet.
+    /// [`open_page`](Self::open_page) and [`insert_file`](Self::insert_file)
+    /// still go through the cache. Call this before
+    /// [`with_background_writes`](Self::with_background_writes).
+    #[cfg(feature = "direct-io")]
+    pub fn with_direct_io(mut self) -> Self {
+        self.direct_io = true;
+        self
+    }
+
+    pub fn direct_io(&self) -> bool {
+        self.direct_io
+    }
+
+    fn meta_path(&self) -> PathBuf {
+        self.dir.join("meta")
+    }
+
+    /// The compression recorded in the `meta` file in `dir`: none without one.
+    fn read_compression(dir: &Path) -> Result<Compression> {
+        let path = dir.join("meta");
+        if !path.exists() {
+            return Ok(Compression::None);
+        }
+        for line in fs::read_to_string(&path)?.lines() {
+            if let Some(value) = line.strip_prefix("compression=") {
+                return Compression::from_meta(value.trim()).ok_or_else(|| {
+                    PageStoreError::Storage(format!("unknown compression {:?} in {}", value, path.display()))
+                });
+            }
+        }
+        Ok(Compression::None)
+    }
+
+    /// Buffer puts in memory and write them to disk in batches on a
+    /// background thread, holding at most about `max_buffered_bytes` before
+    /// puts wait for the writer. Bulk loads get much faster, at the cost of
+    /// a window in which a crash loses pages already put.
+    ///
+    /// Buffered pages are readable at once. [`flush`](Self::flush) waits for
+    /// them to reach the disk and reports failed writes; root updates flush
+    /// first, so a root never points at pages that aren't on disk. Pages
+    /// are written with the store's [`Durability`] as set before this call.
+    pub fn with_background_writes(mut self, max_buffered_bytes: usize) -> Self {
+        let writer = Self {
+            dir: self.dir.clone(),
+            filter: None,
+            durability: self.durability,
+            compression: self.compression,
+            direct_io: self.direct_io,
+            read_only: false,
+            buffer: None,
+            unsynced: Arc::clone(&self.unsynced),
+        };
+        self.buffer = Some(WriteBuffer::start(writer, max_buffered_bytes));
+        self
+    }
+
+    /// Wait until every page put so far is on disk. With background writes,
+    /// reports the first one that failed since the last flush; otherwise
+    /// there is nothing to wait for.
+    pub fn flush(&self) -> Result<()> {
+        match &self.buffer {
+            Some(buffer) => buffer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep a [`CidFilter`] of the stored pages, so that
+    /// [`contains`](Self::contains) answers for most missing pages without
+    /// touching the filesystem; pages it may hold are still checked on disk.
+    ///
+    /// The filter is saved to `cid-filter` when the store is dropped and
+    /// loaded (and the file removed) when one is opened, so a store that
+    /// didn't shut down cleanly leaves none behind and the next one lists
+    /// its pages afresh. It only sees pages written through this store: use
+    /// it for a directory one store owns, such as a cache, or call
+    /// [`rebuild_cid_filter`](Self::rebuild_cid_filter) after writing behind
+    /// its back. A read-only store lists its pages and leaves the file alone.
+    pub fn with_cid_filter(mut self) -> Result<Self> {
+        let saved = if self.read_only {
+            None
+        } else {
+            fs::read(self.filter_path()).ok().and_then(|bytes| CidFilter::from_bytes(&bytes))
+        };
+        let filter = match saved {
+            Some(filter) => {
+                fs::remove_file(self.filter_path())?;
+                filter
+            }
+            None => self.build_cid_filter()?,
+        };
+        self.filter = Some(Mutex::new(filter));
+        Ok(self)
+    }
+
+    fn filter_path(&self) -> PathBuf {
+        self.dir.join("cid-filter")
+    }
+
+    /// A filter of the pages on disk, sized for twice as many.
+    fn build_cid_filter(&self) -> Result<CidFilter> {
+        let pages = self.list_pages()?;
+        let mut filter = CidFilter::with_capacity(pages.len() * 2);
+        for (cid, _) in &pages {
+            filter.insert(cid);
+        }
+        Ok(filter)
     }
 
-    fn page_path(&self, cid: &Cid) -> PathBuf {
+    /// List the pages on disk again for the filter, if the store keeps one.
+    pub fn rebuild_cid_filter(&self) -> Result<()> {
+        if let Some(filter) = &self.filter {
+            let mut filter = filter.lock().unwrap();
+            *filter = self.build_cid_filter()?;
+        }
+        Ok(())
+    }
+
+    /// Record a page in the filter, rebuilding it once it has outgrown its size.
+    fn filter_insert(&self, cid: &Cid) -> Result<()> {
+        if let Some(filter) = &self.filter {
+            let mut filter = filter.lock().unwrap();
+            filter.insert(cid);
+            if filter.is_stale() {
+                *filter = self.build_cid_filter()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The directory this store lives in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// File holding the page `cid`. Anything written here directly must hash to `cid`.
+    pub fn page_path(&self, cid: &Cid) -> PathBuf {
         self.dir.join("pages").join(hex::encode(cid.0))
     }
 
+    /// Check if a page is stored.
+    pub fn contains(&self, cid: &Cid) -> bool {
+        if self.buffer.as_ref().is_some_and(|buffer| buffer.get(cid).is_some()) {
+            return true;
+        }
+        if let Some(filter) = &self.filter {
+            if !filter.lock().unwrap().may_contain(cid) {
+                return false;
+            }
+        }
+        self.page_path(cid).exists()
+    }
+
+    /// Read page `cid` without copying it onto the heap where the platform
+    /// allows: with the `mmap` feature the file is mapped into memory, and
+    /// read into a buffer if it can't be (or without the feature).
+    ///
+    /// Suits long scans over many pages; [`get`](PageStore::get) still
+    /// returns an owned copy. Pages compressed on disk are decompressed
+    /// into a buffer.
+    pub fn read_page(&self, cid: &Cid) -> Result<PageData> {
+        if let Some(data) = self.buffer.as_ref().and_then(|buffer| buffer.get(cid)) {
+            return Ok(PageData(PageBytes::Read(data.to_vec())));
+        }
+        let path = self.page_path(cid);
+        #[cfg(feature = "mmap")]
+        if !self.direct_io {
+            let file = File::open(&path).map_err(|_| PageStoreError::NotFound(*cid))?;
+            // Empty files can't be mapped everywhere
+            if file.metadata()?.len() > 0 {
+                // SAFETY: page files are never changed in place: they are
+                // created whole (moved into place, or created exclusively)
+                // and only ever removed, which leaves a mapping intact.
+                if let Ok(map) = unsafe { memmap2::Mmap::map(&file) } {
+                    match (self.compression, map[0]) {
+                        (Compression::None, _) => return Ok(PageData(PageBytes::Mapped(map, 0))),
+                        (_, HEADER_STORED) => return Ok(PageData(PageBytes::Mapped(map, 1))),
+                        _ => return Ok(PageData(PageBytes::Read(self.decode(cid, &map)?))),
+                    }
+                }
+            }
+        }
+        let data = self.read_page_file(&path).map_err(|_| PageStoreError::NotFound(*cid))?;
+        Ok(PageData(PageBytes::Read(self.decode_owned(cid, data)?)))
+    }
+
+    /// Stream page `cid`, decompressing it as it's read: for looking into
+    /// large objects without reading them whole.
+    pub fn open_page(&self, cid: &Cid) -> Result<Box<dyn Read + Send>> {
+        if let Some(data) = self.buffer.as_ref().and_then(|buffer| buffer.get(cid)) {
+            return Ok(Box::new(std::io::Cursor::new(data.to_vec())));
+        }
+        let mut file = File::open(self.page_path(cid)).map_err(|_| PageStoreError::NotFound(*cid))?;
+        if self.compression == Compression::None {
+            return Ok(Box::new(file));
+        }
+        let mut header = [0u8; 1];
+        file.read_exact(&mut header).map_err(|_| Self::corrupt(cid))?;
+        match header[0] {
+            HEADER_STORED => Ok(Box::new(file)),
+            HEADER_ZSTD => Ok(Box::new(zstd::Decoder::new(file)?)),
+            _ => Err(Self::corrupt(cid)),
+        }
+    }
+
+    fn corrupt(cid: &Cid) -> PageStoreError {
+        PageStoreError::Storage(format!("page file {} has no valid compression header", cid))
+    }
+
+    /// The page in the file contents `data`.
+    fn decode(&self, cid: &Cid, data: &[u8]) -> Result<Vec<u8>> {
+        if self.compression == Compression::None {
+            return Ok(data.to_vec());
+        }
+        match data.split_first() {
+            Some((&HEADER_STORED, page)) => Ok(page.to_vec()),
+            Some((&HEADER_ZSTD, frame)) => Ok(zstd::decode_all(frame)?),
+            _ => Err(Self::corrupt(cid)),
+        }
+    }
+
+    /// [`decode`](Self::decode), without copying pages stored as they are.
+    fn decode_owned(&self, cid: &Cid, mut data: Vec<u8>) -> Result<Vec<u8>> {
+        if self.compression == Compression::None {
+            return Ok(data);
+        }
+        if data.first() == Some(&HEADER_STORED) {
+            data.remove(0);
+            return Ok(data);
+        }
+        self.decode(cid, &data)
+    }
+
+    /// Read the page file at `path`, around the page cache with direct I/O.
+    fn read_page_file(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        #[cfg(feature = "direct-io")]
+        if self.direct_io {
+            return direct::read(path);
+        }
+        fs::read(path)
+    }
+
+    /// Open the page file at `path` to write, around the page cache with
+    /// direct I/O.
+    fn open_page_file(&self, options: &mut fs::OpenOptions, path: &Path) -> std::io::Result<File> {
+        #[cfg(feature = "direct-io")]
+        if self.direct_io {
+            return direct::open(options, path);
+        }
+        options.open(path)
+    }
+
+    /// Write page `data` to the new page file `file`.
+    fn write_page_file(&self, file: &mut File, data: &[u8]) -> Result<()> {
+        #[cfg(feature = "direct-io")]
+        if self.direct_io {
+            // Direct writes go in one piece, from an aligned buffer
+            let mut encoded = Vec::with_capacity(data.len() + 1);
+            self.write_encoded(&mut encoded, data)?;
+            return Ok(direct::write(file, &encoded)?);
+        }
+        self.write_encoded(file, data)
+    }
+
+    /// Write page `data` to `file` as the store's compression says.
+    fn write_encoded(&self, file: &mut impl Write, data: &[u8]) -> Result<()> {
+        let Compression::Zstd(level) = self.compression else {
+            file.write_all(data)?;
+            return Ok(());
+        };
+        let frame = zstd::bulk::compress(data, level)?;
+        if frame.len() < data.len() {
+            file.write_all(&[HEADER_ZSTD])?;
+            file.write_all(&frame)?;
+        } else {
+            file.write_all(&[HEADER_STORED])?;
+            file.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    /// Move `src`, whose contents hash to `cid`, into the store.
+    ///
+    /// Lets large objects be streamed to a scratch file on the same
+    /// filesystem and then stored without reading them back into memory.
+    /// A compressed store streams `src` through the compressor instead, and
+    /// removes it.
+    pub fn insert_file(&self, cid: &Cid, src: &Path) -> Result<()> {
+        self.check_writable("store pages")?;
+        self.flush()?;
+        let Compression::Zstd(level) = self.compression else {
+            replace_file(src, &self.page_path(cid))?;
+            return self.filter_insert(cid);
+        };
+        let tmp = Self::scratch_path(&self.dir.join("pages"));
+        let written = File::create(&tmp).and_then(|mut file| {
+            file.write_all(&[HEADER_ZSTD])?;
+            zstd::stream::copy_encode(File::open(src)?, &mut file, level)
+        });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        replace_file(&tmp, &self.page_path(cid))?;
+        fs::remove_file(src)?;
+        self.filter_insert(cid)
+    }
+
+    /// Delete a page. Returns whether it was stored.
+    pub fn remove(&self, cid: &Cid) -> Result<bool> {
+        self.check_writable("delete pages")?;
+        self.flush()?;
+        match fs::remove_file(self.page_path(cid)) {
+            Ok(()) => {
+                if let Some(filter) = &self.filter {
+                    let mut filter = filter.lock().unwrap();
+                    filter.count_removal();
+                    if filter.is_stale() {
+                        *filter = self.build_cid_filter()?;
+                    }
+                }
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// A file name in `dir` no other write uses, for writing a file before
+    /// moving it into place.
+    fn scratch_path(dir: &Path) -> PathBuf {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        dir.join(format!("{}-{}.tmp", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    /// Write page `cid` to a scratch file, to be moved into place by
+    /// [`flush_pages`](Self::flush_pages).
+    fn start_page(&self, cid: &Cid, data: &[u8]) -> Result<(Cid, File, PathBuf)> {
+        let tmp = Self::scratch_path(&self.dir.join("pages"));
+        let mut file = self.open_page_file(File::options().write(true).create(true).truncate(true), &tmp)?;
+        self.write_page_file(&mut file, data)?;
+        Ok((*cid, file, tmp))
+    }
+
+    /// Flush started pages and move them into place. The directory still
+    /// needs flushing.
+    fn flush_pages(&self, started: &mut Vec<(Cid, File, PathBuf)>) -> Result<()> {
+        for (_, file, _) in started.iter() {
+            file.sync_data()?;
+        }
+        for (cid, _, tmp) in started.drain(..) {
+            replace_file(&tmp, &self.page_path(&cid))?;
+            self.filter_insert(&cid)?;
+        }
+        Ok(())
+    }
+
+    /// Write the pointer file at `path`: atomically and flushed, unless the
+    /// store's durability is [`Durability::None`].
+    fn write_pointer(&self, path: &Path, cid: &Cid) -> Result<()> {
+        self.flush()?;
+        if self.durability == Durability::None {
+            fs::write(path, hex::encode(cid.0))?;
+            return Ok(());
+        }
+        self.sync_unsynced()?;
+        let tmp = Self::scratch_path(&self.dir);
+        let mut file = File::create(&tmp)?;
+        file.write_all(hex::encode(cid.0).as_bytes())?;
+        file.sync_data()?;
+        replace_file(&tmp, path)?;
+        sync_dir(path.parent().unwrap_or(&self.dir))
+    }
+
+    /// Flush the pages written since the last commit, with
+    /// [`Durability::Commit`].
+    fn sync_unsynced(&self) -> Result<()> {
+        let mut unsynced = self.unsynced.lock().unwrap();
+        if unsynced.is_empty() {
+            return Ok(());
+        }
+        for cid in unsynced.iter() {
+            // Opened for writing, as Windows needs to flush a file
+            match fs::OpenOptions::new().write(true).open(self.page_path(cid)) {
+                Ok(file) => file.sync_data()?,
+                // Deleted since: nothing to flush
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        sync_dir(&self.dir.join("pages"))?;
+        unsynced.clear();
+        Ok(())
+    }
+
     fn root_path(&self) -> PathBuf {
         self.dir.join("root")
     }
@@ -28,11 +661,89 @@ impl LocalPageStore {
     }
 
     fn ref_path(&self, name: &str) -> PathBuf {
-        // Sanitize name to avoid path traversal
-        let safe_name: String = name.chars()
-            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
-            .collect();
-        self.refs_dir().join(safe_name)
+        self.refs_dir().join(sanitize_ref_name(name))
+    }
+
+    fn commit_path(&self, root: &Cid) -> PathBuf {
+        self.dir.join("commits").join(hex::encode(root.0))
+    }
+
+    /// Record `meta` as a [`Commit`] of `root`, before anything points at it.
+    fn record_commit(&self, root: Cid, meta: &CommitMeta) -> Result<()> {
+        fs::create_dir_all(self.dir.join("commits"))?;
+        fs::write(self.commit_path(&root), meta.to_commit(root).to_bytes())?;
+        Ok(())
+    }
+
+    /// Who moved the root to `root` and why, as recorded by
+    /// [`update_root_with_meta`](PageStore::update_root_with_meta) the
+    /// last time it did; `None` if it never recorded any.
+    pub fn root_commit(&self, root: &Cid) -> Result<Option<Commit>> {
+        match fs::read(self.commit_path(root)) {
+            Ok(data) => Commit::from_bytes(&data)
+                .map(Some)
+                .ok_or_else(|| PageStoreError::Storage(format!("invalid commit record for root {}", root))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write a page to disk.
+    fn write_page(&self, data: &[u8]) -> Result<Cid> {
+        let cid = Cid::from_bytes(data);
+        if self.contains(&cid) {
+            return Ok(cid);
+        }
+        if self.durability == Durability::None {
+            // Created exclusively, so a page file is never truncated under a
+            // reader: a concurrent writer of the same page just finds it
+            match self.open_page_file(fs::OpenOptions::new().write(true).create_new(true), &self.page_path(&cid)) {
+                Ok(mut file) => self.write_page_file(&mut file, data)?,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(cid),
+                Err(e) => return Err(e.into()),
+            }
+            self.filter_insert(&cid)?;
+        } else if self.durability == Durability::Commit {
+            // Moved into place whole, so even unflushed a page is never seen
+            // half written by another process
+            let (_, _, tmp) = self.start_page(&cid, data)?;
+            replace_file(&tmp, &self.page_path(&cid))?;
+            self.filter_insert(&cid)?;
+            self.unsynced.lock().unwrap().push(cid);
+        } else {
+            self.flush_pages(&mut vec![self.start_page(&cid, data)?])?;
+            sync_dir(&self.dir.join("pages"))?;
+        }
+        Ok(cid)
+    }
+
+    /// Write pages to disk, flushing them as a batch if the durability
+    /// says so.
+    pub(crate) fn write_pages(&self, pages: &[&[u8]]) -> Result<Vec<Cid>> {
+        if self.durability != Durability::Batch {
+            return pages.iter().map(|data| self.write_page(data)).collect();
+        }
+        let mut cids = Vec::with_capacity(pages.len());
+        let mut started = Vec::new();
+        let mut written = HashSet::new();
+        for data in pages {
+            let cid = Cid::from_bytes(data);
+            cids.push(cid);
+            if written.contains(&cid) || self.contains(&cid) {
+                continue;
+            }
+            started.push(self.start_page(&cid, data)?);
+            written.insert(cid);
+            // Flushed in groups, so a big batch doesn't run out of file handles
+            if started.len() == BATCH_OPEN_FILES {
+                self.flush_pages(&mut started)?;
+            }
+        }
+        if !written.is_empty() {
+            self.flush_pages(&mut started)?;
+            sync_dir(&self.dir.join("pages"))?;
+        }
+        Ok(cids)
     }
 
     fn read_cid_file(path: &Path) -> Result<Option<Cid>> {
@@ -42,31 +753,89 @@ impl LocalPageStore {
         let hex_str = fs::read_to_string(path)?;
         let bytes = hex::decode(hex_str.trim())
             .map_err(|e| PageStoreError::Storage(e.to_string()))?;
+        if bytes.len() != 32 {
+            return Err(PageStoreError::Storage("invalid CID length".into()));
+        }
         let mut cid = [0u8; 32];
         cid.copy_from_slice(&bytes);
         Ok(Some(Cid(cid)))
     }
 }
 
+/// Flush a directory's entries, so files moved into it stay there.
+fn sync_dir(dir: &Path) -> Result<()> {
+    // Directories can only be opened, and need to be flushed, on Unix
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+impl Drop for LocalPageStore {
+    fn drop(&mut self) {
+        if let Some(buffer) = &self.buffer {
+            buffer.close();
+        }
+        if self.read_only {
+            return;
+        }
+        if let Some(filter) = &self.filter {
+            let bytes = filter.lock().unwrap_or_else(|e| e.into_inner()).to_bytes();
+            let _ = fs::write(self.filter_path(), bytes);
+        }
+    }
+}
+
 impl PageStore for LocalPageStore {
     fn get(&self, cid: &Cid) -> Result<Page> {
+        if let Some(data) = self.buffer.as_ref().and_then(|buffer| buffer.get(cid)) {
+            return Ok(Page { data: data.to_vec() });
+        }
         let path = self.page_path(cid);
-        let data = fs::read(&path).map_err(|_| PageStoreError::NotFound(*cid))?;
-        Ok(Page { data })
+        let data = self.read_page_file(&path).map_err(|_| PageStoreError::NotFound(*cid))?;
+        Ok(Page { data: self.decode_owned(cid, data)? })
     }
 
     fn put(&self, page: &Page) -> Result<Cid> {
-        let cid = Cid::from_bytes(&page.data);
-        let path = self.page_path(&cid);
-        if !path.exists() {
-            fs::write(&path, &page.data)?;
+        self.check_writable("store pages")?;
+        match &self.buffer {
+            Some(buffer) => {
+                let cid = Cid::from_bytes(&page.data);
+                if !self.contains(&cid) {
+                    buffer.put(cid, &page.data);
+                    self.filter_insert(&cid)?;
+                }
+                Ok(cid)
+            }
+            None => self.write_page(&page.data),
         }
-        Ok(cid)
+    }
+
+    fn put_many(&self, pages: &[Page]) -> Result<Vec<Cid>> {
+        self.check_writable("store pages")?;
+        if self.buffer.is_some() {
+            return pages.iter().map(|page| self.put(page)).collect();
+        }
+        self.write_pages(&pages.iter().map(|page| &page.data[..]).collect::<Vec<_>>())
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        Ok(self.contains(cid))
     }
 
     fn update_root(&self, new_root: Cid) -> Result<()> {
-        fs::write(self.root_path(), hex::encode(new_root.0))?;
-        Ok(())
+        self.check_writable("update the root")?;
+        self.write_pointer(&self.root_path(), &new_root)
+    }
+
+    /// Records `meta` as a [`Commit`] of `new_root` in `commits/` before
+    /// moving the root, so whoever sees the root can find it; see
+    /// [`root_commit`](Self::root_commit).
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        self.check_writable("update the root")?;
+        self.record_commit(new_root, meta)?;
+        self.update_root(new_root)
     }
 
     fn current_root(&self) -> Result<Option<Cid>> {
@@ -74,9 +843,17 @@ impl PageStore for LocalPageStore {
     }
 
     fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.check_writable("set named roots")?;
         fs::create_dir_all(self.refs_dir())?;
-        fs::write(self.ref_path(name), hex::encode(cid.0))?;
-        Ok(())
+        self.write_pointer(&self.ref_path(name), &cid)
+    }
+
+    /// Records `meta` in `commits/` as
+    /// [`update_root_with_meta`](PageStore::update_root_with_meta) does.
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        self.check_writable("set named roots")?;
+        self.record_commit(cid, meta)?;
+        self.set_named_root(name, cid)
     }
 
     fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
@@ -84,6 +861,7 @@ impl PageStore for LocalPageStore {
     }
 
     fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.check_writable("remove named roots")?;
         let path = self.ref_path(name);
         if path.exists() {
             fs::remove_file(&path)?;
@@ -109,12 +887,50 @@ impl PageStore for LocalPageStore {
         roots.sort_by(|a, b| a.0.cmp(&b.0));
         Ok(roots)
     }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        self.flush()?;
+        let mut pages = Vec::new();
+        for entry in fs::read_dir(self.dir.join("pages"))? {
+            let entry = entry?;
+            // Skip anything that isn't named for a CID, such as scratch files
+            let name = entry.file_name();
+            let Some(bytes) = name.to_str().and_then(|name| hex::decode(name).ok()) else {
+                continue;
+            };
+            if let Ok(cid) = <[u8; 32]>::try_from(bytes) {
+                pages.push((Cid(cid), entry.metadata()?.len()));
+            }
+        }
+        Ok(pages)
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.remove(cid)
+    }
+
+    /// Watches `root` and `refs/` for changes, which sees writes from other
+    /// processes too: inotify on Linux, the platform's file watcher
+    /// elsewhere.
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        // A read-only store can't create the directory to watch
+        if self.read_only && !self.refs_dir().is_dir() {
+            return Ok(None);
+        }
+        fs::create_dir_all(self.refs_dir())?;
+        #[cfg(target_os = "linux")]
+        let signal = watch::InotifySignal::new(&self.dir, &self.refs_dir())?;
+        #[cfg(not(target_os = "linux"))]
+        let signal = watch::NotifySignal::new(&self.dir, &self.refs_dir())?;
+        Ok(Some(Box::new(signal)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use craftsql_core::PageTable;
+    use std::collections::HashMap;
 
     fn temp_dir() -> PathBuf {
         std::env::temp_dir().join(format!("craftsql-test-{}", std::process::id()))
@@ -134,6 +950,35 @@ mod tests {
         fs::remove_dir_all(&dir).ok();
     }
 
+    #[test]
+    fn test_sanitize_ref_name() {
+        assert_eq!(sanitize_ref_name("feature/x.v1"), "feature_x.v1");
+        assert_eq!(sanitize_ref_name(".."), "._");
+        assert_eq!(sanitize_ref_name("con"), "_con");
+        assert_eq!(sanitize_ref_name("Lpt3.log"), "_Lpt3.log");
+        assert_eq!(sanitize_ref_name("com0"), "com0");
+        assert_eq!(sanitize_ref_name("console"), "console");
+    }
+
+    #[test]
+    fn test_read_page() {
+        let dir = temp_dir().join("read_page");
+        let store = LocalPageStore::new(&dir).unwrap();
+
+        let cid = store.put(&Page { data: vec![7; 4096] }).unwrap();
+        let page = store.read_page(&cid).unwrap();
+        assert_eq!(&page[..], &store.get(&cid).unwrap().data[..]);
+        // Still readable once removed from the store
+        store.remove(&cid).unwrap();
+        assert_eq!(page.into_page().data, vec![7; 4096]);
+
+        let empty = store.put(&Page { data: Vec::new() }).unwrap();
+        assert!(store.read_page(&empty).unwrap().is_empty());
+        assert!(matches!(store.read_page(&cid), Err(PageStoreError::NotFound(_))));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
     #[test]
     fn test_get_not_found() {
         let dir = temp_dir().join("not_found");
@@ -158,6 +1003,236 @@ mod tests {
         fs::remove_dir_all(&dir).ok();
     }
 
+    #[test]
+    fn test_insert_and_remove() {
+        let dir = temp_dir().join("insert_remove");
+        let store = LocalPageStore::new(&dir).unwrap();
+
+        let src = dir.join("blob.tmp");
+        fs::write(&src, b"streamed blob").unwrap();
+        let cid = Cid::from_bytes(b"streamed blob");
+        assert!(!store.contains(&cid));
+        store.insert_file(&cid, &src).unwrap();
+        assert!(store.contains(&cid));
+        assert!(store.has(&cid).unwrap());
+        assert!(!src.exists());
+        assert_eq!(store.get(&cid).unwrap().data, b"streamed blob");
+        fs::write(dir.join("pages").join("scratch.tmp"), b"partial").unwrap();
+        assert_eq!(store.list_pages().unwrap(), vec![(cid, 13)]);
+
+        assert!(store.remove(&cid).unwrap());
+        assert!(!store.remove(&cid).unwrap());
+        assert!(store.get(&cid).is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_compression() {
+        let dir = temp_dir().join("compression");
+        let store = LocalPageStore::new(&dir).unwrap().with_compression(Compression::Zstd(3)).unwrap();
+        let text = Page { data: b"the same words again and again ".repeat(128) };
+        let noise = Page { data: (0..128u32).flat_map(|i| Cid::from_bytes(&i.to_le_bytes()).0).collect() };
+        let cids = store.put_many(&[text.clone(), noise.clone()]).unwrap();
+        assert_eq!(store.get(&cids[0]).unwrap().data, text.data);
+        assert_eq!(&store.read_page(&cids[1]).unwrap()[..], &noise.data[..]);
+        let sizes: HashMap<Cid, u64> = store.list_pages().unwrap().into_iter().collect();
+        assert!(sizes[&cids[0]] < text.data.len() as u64 / 10);
+        assert_eq!(sizes[&cids[1]], noise.data.len() as u64 + 1);
+
+        // Streamed in compressed, and out decompressed
+        let src = dir.join("blob.tmp");
+        fs::write(&src, &text.data[..1000]).unwrap();
+        let blob = Cid::from_bytes(&text.data[..1000]);
+        store.insert_file(&blob, &src).unwrap();
+        assert!(!src.exists());
+        let mut streamed = Vec::new();
+        store.open_page(&blob).unwrap().read_to_end(&mut streamed).unwrap();
+        assert_eq!(streamed, &text.data[..1000]);
+
+        // Recorded for the next open, which can't undo it but can change the level
+        drop(store);
+        let store = LocalPageStore::new(&dir).unwrap();
+        assert_eq!(store.compression(), Compression::Zstd(3));
+        assert_eq!(store.get(&cids[1]).unwrap().data, noise.data);
+        assert!(LocalPageStore::new(&dir).unwrap().with_compression(Compression::None).is_err());
+        let store = store.with_compression(Compression::Zstd(19)).unwrap();
+        assert_eq!(LocalPageStore::open_read_only(&dir).unwrap().compression(), Compression::Zstd(19));
+        assert_eq!(store.get(&blob).unwrap().data, &text.data[..1000]);
+
+        // Pages already stored plainly keep a store plain
+        let plain = temp_dir().join("compression_plain");
+        LocalPageStore::new(&plain).unwrap().put(&text).unwrap();
+        assert!(LocalPageStore::new(&plain).unwrap().with_compression(Compression::Zstd(3)).is_err());
+
+        fs::remove_dir_all(&dir).ok();
+        fs::remove_dir_all(&plain).ok();
+    }
+
+    #[cfg(feature = "direct-io")]
+    #[test]
+    fn test_direct_io() {
+        for compression in [Compression::None, Compression::Zstd(3)] {
+            let dir = temp_dir().join(format!("direct_io_{:?}", compression));
+            let store = LocalPageStore::new(&dir).unwrap().with_compression(compression).unwrap().with_direct_io();
+            let pages: Vec<Page> = [0, 1, 4096, 5000].iter().map(|&len| Page { data: vec![len as u8; len] }).collect();
+            let cids = store.put_many(&pages).unwrap();
+            for (page, cid) in pages.iter().zip(&cids) {
+                assert_eq!(store.get(cid).unwrap().data, page.data);
+                assert_eq!(&store.read_page(cid).unwrap()[..], &page.data[..]);
+            }
+            // Files are cut back to size, and readable without direct I/O
+            let plain = LocalPageStore::new(&dir).unwrap();
+            assert_eq!(plain.get(&cids[3]).unwrap().data, pages[3].data);
+            if compression == Compression::None {
+                assert_eq!(fs::metadata(store.page_path(&cids[3])).unwrap().len(), 5000);
+            }
+
+            let store = store.with_durability(Durability::Batch);
+            let cid = store.put(&Page { data: b"batched".to_vec() }).unwrap();
+            assert_eq!(plain.get(&cid).unwrap().data, b"batched");
+
+            fs::remove_dir_all(&dir).ok();
+        }
+    }
+
+    #[test]
+    fn test_root_commit() {
+        let dir = temp_dir().join("root_commit");
+        let store = LocalPageStore::new(&dir).unwrap();
+        let first = store.put(&Page { data: b"v1".to_vec() }).unwrap();
+        let second = store.put(&Page { data: b"v2".to_vec() }).unwrap();
+        store.update_root(first).unwrap();
+        assert_eq!(store.root_commit(&first).unwrap(), None);
+
+        let meta = CommitMeta::new("alice", "add users").with_parent(Some(first));
+        store.update_root_with_meta(second, &meta).unwrap();
+        assert_eq!(store.current_root().unwrap(), Some(second));
+        let commit = store.root_commit(&second).unwrap().unwrap();
+        assert_eq!(commit.parents, vec![first]);
+        assert_eq!((commit.author.as_str(), commit.message.as_str()), ("alice", "add users"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_cid_filter() {
+        let dir = temp_dir().join("cid_filter");
+        let kept = Cid::from_bytes(b"kept");
+        let store = LocalPageStore::new(&dir).unwrap().with_cid_filter().unwrap();
+        let cid = store.put(&Page { data: b"kept".to_vec() }).unwrap();
+        assert_eq!(cid, kept);
+        let removed = store.put(&Page { data: b"removed".to_vec() }).unwrap();
+        assert!(store.contains(&kept) && store.contains(&removed));
+        assert!(store.remove(&removed).unwrap());
+        assert!(!store.contains(&removed));
+
+        // Saved on drop, taken on open
+        drop(store);
+        let store = LocalPageStore::new(&dir).unwrap().with_cid_filter().unwrap();
+        assert!(!dir.join("cid-filter").exists());
+        assert!(store.contains(&kept));
+
+        // Pages written behind its back show up once the filter is rebuilt
+        let behind = Cid::from_bytes(b"behind");
+        LocalPageStore::new(&dir).unwrap().put(&Page { data: b"behind".to_vec() }).unwrap();
+        assert!(!store.contains(&behind));
+        store.rebuild_cid_filter().unwrap();
+        assert!(store.contains(&behind));
+
+        // A store that didn't shut down cleanly leaves no filter to trust
+        std::mem::forget(store);
+        let store = LocalPageStore::new(&dir).unwrap().with_cid_filter().unwrap();
+        assert!(store.contains(&kept) && store.contains(&behind));
+
+        drop(store);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_durability() {
+        for durability in [Durability::Commit, Durability::Batch, Durability::PerPage] {
+            let dir = temp_dir().join(format!("durability_{:?}", durability));
+            let store = LocalPageStore::new(&dir).unwrap().with_durability(durability);
+            let pages: Vec<Page> = [b"one", b"two", b"one"].iter().map(|data| Page { data: data.to_vec() }).collect();
+            let cids = store.put_many(&pages).unwrap();
+            assert_eq!(cids, pages.iter().map(|page| Cid::from_bytes(&page.data)).collect::<Vec<_>>());
+            assert_eq!(store.get(&cids[1]).unwrap().data, b"two");
+            assert_eq!(store.put(&Page { data: b"three".to_vec() }).unwrap(), Cid::from_bytes(b"three"));
+            assert_eq!(store.list_pages().unwrap().len(), 3);
+            assert_eq!(store.unsynced.lock().unwrap().len(), if durability == Durability::Commit { 3 } else { 0 });
+
+            store.update_root(cids[0]).unwrap();
+            assert!(store.unsynced.lock().unwrap().is_empty());
+            store.set_named_root("snapshot.v1", cids[1]).unwrap();
+            assert_eq!(store.current_root().unwrap(), Some(cids[0]));
+            assert_eq!(store.list_named_roots().unwrap(), vec![("snapshot.v1".to_string(), cids[1])]);
+
+            // No scratch files left behind
+            let scratch = |dir: &Path| fs::read_dir(dir).unwrap().filter(|e| e.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "tmp")).count();
+            assert_eq!(scratch(&dir) + scratch(&dir.join("pages")), 0);
+
+            fs::remove_dir_all(&dir).ok();
+        }
+    }
+
+    #[test]
+    fn test_background_writes() {
+        let dir = temp_dir().join("background_writes");
+        let store = LocalPageStore::new(&dir).unwrap().with_durability(Durability::Batch).with_background_writes(64);
+        let pages: Vec<Page> = (0..100u32).map(|i| Page { data: format!("page {}", i).into_bytes() }).collect();
+        let cids = store.put_many(&pages).unwrap();
+        assert_eq!(store.put(&pages[7]).unwrap(), cids[7]);
+        assert_eq!(store.get(&cids[99]).unwrap().data, b"page 99");
+        assert!(store.contains(&cids[50]));
+
+        store.flush().unwrap();
+        assert!(cids.iter().all(|cid| store.page_path(cid).exists()));
+        assert_eq!(store.list_pages().unwrap().len(), 100);
+
+        // A root is only written once its pages are
+        let last = store.put(&Page { data: b"last".to_vec() }).unwrap();
+        store.update_root(last).unwrap();
+        assert!(store.page_path(&last).exists());
+
+        // Dropping the store writes what is still buffered
+        let extra = store.put(&Page { data: b"extra".to_vec() }).unwrap();
+        drop(store);
+        assert_eq!(LocalPageStore::new(&dir).unwrap().get(&extra).unwrap().data, b"extra");
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_read_only() {
+        let dir = temp_dir().join("read_only");
+        assert!(LocalPageStore::open_read_only(&dir).is_err());
+        assert!(!dir.exists());
+
+        let store = LocalPageStore::new(&dir).unwrap();
+        let cid = store.put(&Page { data: b"page".to_vec() }).unwrap();
+        store.update_root(cid).unwrap();
+        let reader = LocalPageStore::open_read_only(&dir).unwrap().with_cid_filter().unwrap();
+        assert!(reader.is_read_only());
+        assert_eq!(reader.get(&cid).unwrap().data, b"page");
+        assert_eq!(reader.current_root().unwrap(), Some(cid));
+        assert!(reader.contains(&cid));
+
+        let read_only = |result: Result<()>| matches!(result, Err(PageStoreError::ReadOnly(_)));
+        assert!(read_only(reader.put(&Page { data: b"new".to_vec() }).map(drop)));
+        assert!(read_only(reader.put_many(&[Page { data: b"new".to_vec() }]).map(drop)));
+        assert!(read_only(reader.update_root(cid)));
+        assert!(read_only(reader.set_named_root("main", cid)));
+        assert!(read_only(reader.remove_named_root("main").map(drop)));
+        assert!(read_only(reader.delete_page(&cid).map(drop)));
+        assert!(store.contains(&cid));
+        assert!(!dir.join("refs").exists());
+        drop(reader);
+        assert!(!dir.join("cid-filter").exists());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
     #[test]
     fn test_root_pointer() {
         let dir = temp_dir().join("root");
@@ -228,4 +1303,25 @@ mod tests {
 
         fs::remove_dir_all(&dir).ok();
     }
+
+    #[test]
+    fn test_watch() {
+        let dir = temp_dir().join("watch");
+        let store = LocalPageStore::new(&dir).unwrap();
+        let mut watch = craftsql_core::watch(&store).unwrap().with_poll_interval(std::time::Duration::from_secs(60));
+        assert!(watch.is_signaled());
+
+        // Another handle, as another process would write
+        let other = LocalPageStore::new(&dir).unwrap();
+        let cid = Cid::from_bytes(b"root");
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            other.set_named_root("main", cid).unwrap();
+        });
+        let event = watch.next_event(Some(std::time::Duration::from_secs(10))).unwrap();
+        assert_eq!(event, Some(craftsql_core::RootEvent::Named { name: "main".into(), cid: Some(cid) }));
+        writer.join().unwrap();
+
+        fs::remove_dir_all(&dir).ok();
+    }
 }
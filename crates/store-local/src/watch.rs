@@ -0,0 +1,115 @@
+//! Root change notification: inotify on Linux, and elsewhere whatever
+//! the `notify` crate uses there (FSEvents, kqueue, ReadDirectoryChangesW).
+
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::path::Path;
+#[cfg(not(target_os = "linux"))]
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+#[cfg(not(target_os = "linux"))]
+use craftsql_core::PageStoreError;
+use craftsql_core::{Result, RootSignal};
+#[cfg(target_os = "linux")]
+use inotify::{Inotify, WatchMask};
+#[cfg(not(target_os = "linux"))]
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Wakes on any file in the store directory or `refs/` being written,
+/// renamed into place, or deleted. Page writes land in `pages/`, which
+/// isn't watched.
+#[cfg(target_os = "linux")]
+pub(crate) struct InotifySignal {
+    inotify: Inotify,
+}
+
+#[cfg(target_os = "linux")]
+impl InotifySignal {
+    pub(crate) fn new(dir: &Path, refs_dir: &Path) -> Result<Self> {
+        let inotify = Inotify::init()?;
+        let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::DELETE;
+        inotify.watches().add(dir, mask)?;
+        inotify.watches().add(refs_dir, mask)?;
+        Ok(Self { inotify })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl RootSignal for InotifySignal {
+    fn wait(&mut self, timeout: Duration) -> Result<()> {
+        let mut fd = libc::pollfd { fd: self.inotify.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: `fd` is one valid pollfd for the duration of the call
+        if unsafe { libc::poll(&mut fd, 1, timeout_ms) } < 0 {
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::Interrupted => Ok(()),
+                _ => Err(e.into()),
+            };
+        }
+        // Drain what arrived; which files changed doesn't matter
+        let mut buffer = [0u8; 4096];
+        loop {
+            match self.inotify.read_events(&mut buffer) {
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Wakes on any change the platform reports to the store directory or
+/// `refs/`, which includes other processes' writes. Neither is watched
+/// recursively, so page writes in `pages/` don't wake it.
+#[cfg(not(target_os = "linux"))]
+pub(crate) struct NotifySignal {
+    // Dropping the watcher stops the events
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl NotifySignal {
+    pub(crate) fn new(dir: &Path, refs_dir: &Path) -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The watch may be gone; then nobody is waiting
+            let _ = sender.send(event);
+        }).map_err(watch_error)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(watch_error)?;
+        watcher.watch(refs_dir, RecursiveMode::NonRecursive).map_err(watch_error)?;
+        Ok(Self { _watcher: watcher, events })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl RootSignal for NotifySignal {
+    fn wait(&mut self, timeout: Duration) -> Result<()> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => {
+                event.map_err(watch_error)?;
+            }
+            Err(RecvTimeoutError::Timeout) => return Ok(()),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(PageStoreError::Storage("store directory watcher stopped".into()));
+            }
+        }
+        // Drain what arrived; which files changed doesn't matter
+        while let Ok(event) = self.events.try_recv() {
+            event.map_err(watch_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_error(e: notify::Error) -> PageStoreError {
+    match e.kind {
+        notify::ErrorKind::Io(e) => e.into(),
+        _ => PageStoreError::Storage(format!("watching the store directory: {}", e)),
+    }
+}
@@ -0,0 +1,332 @@
+//! In-memory PageStore — pages, root, and named roots in process memory.
+//!
+//! For tests, scratch databases, and embedding. Clones share one store, so
+//! a handle can be given to the VFS and another kept to inspect what it
+//! wrote. Limits on page count or total bytes make it usable as a bounded
+//! scratch space, and [`MemPageStore::snapshot`] / [`MemPageStore::export_to`]
+//! capture or persist the contents.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use craftsql_core::{Cid, Page, PageStore, PageStoreError, Result, RootSignal};
+
+/// A copy of a store's contents at one moment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemSnapshot {
+    pub pages: HashMap<Cid, Vec<u8>>,
+    pub root: Option<Cid>,
+    pub named_roots: BTreeMap<String, Cid>,
+}
+
+impl MemSnapshot {
+    /// Total bytes of page data.
+    pub fn total_bytes(&self) -> u64 {
+        self.pages.values().map(|data| data.len() as u64).sum()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    contents: MemSnapshot,
+    bytes: u64,
+    max_pages: Option<usize>,
+    max_bytes: Option<u64>,
+    /// Bumped whenever a root changes, for watchers.
+    root_changes: u64,
+}
+
+/// Thread-safe in-memory PageStore; clones share the same contents.
+#[derive(Clone, Default)]
+pub struct MemPageStore {
+    state: Arc<Mutex<State>>,
+    roots_changed: Arc<Condvar>,
+}
+
+impl MemPageStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store holding `snapshot`'s contents.
+    pub fn from_snapshot(snapshot: MemSnapshot) -> Self {
+        let store = Self::new();
+        store.restore(snapshot);
+        store
+    }
+
+    /// Refuse puts that would store more than `max` pages.
+    pub fn with_max_pages(self, max: usize) -> Self {
+        self.state.lock().unwrap().max_pages = Some(max);
+        self
+    }
+
+    /// Refuse puts that would hold more than `max` bytes of page data.
+    pub fn with_max_bytes(self, max: u64) -> Self {
+        self.state.lock().unwrap().max_bytes = Some(max);
+        self
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.state.lock().unwrap().contents.pages.len()
+    }
+
+    /// Total bytes of page data stored.
+    pub fn total_bytes(&self) -> u64 {
+        self.state.lock().unwrap().bytes
+    }
+
+    pub fn contains(&self, cid: &Cid) -> bool {
+        self.state.lock().unwrap().contents.pages.contains_key(cid)
+    }
+
+    /// CIDs of all stored pages, in no particular order.
+    pub fn cids(&self) -> Vec<Cid> {
+        self.state.lock().unwrap().contents.pages.keys().copied().collect()
+    }
+
+    /// Delete a page. Returns whether it was stored.
+    pub fn remove(&self, cid: &Cid) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.contents.pages.remove(cid) {
+            Some(data) => {
+                state.bytes -= data.len() as u64;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the root only if it is currently `expected`, failing with
+    /// [`PageStoreError::RootConflict`] otherwise.
+    pub fn set_root_if(&self, expected: Option<Cid>, new: Cid) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let actual = state.contents.root;
+        if actual != expected {
+            return Err(PageStoreError::RootConflict { expected, actual });
+        }
+        state.contents.root = Some(new);
+        self.notify(&mut state);
+        Ok(())
+    }
+
+    /// A copy of everything stored.
+    pub fn snapshot(&self) -> MemSnapshot {
+        self.state.lock().unwrap().contents.clone()
+    }
+
+    /// Replace everything stored with `snapshot`'s contents. Limits are kept
+    /// but not enforced against the restored contents.
+    pub fn restore(&self, snapshot: MemSnapshot) {
+        let mut state = self.state.lock().unwrap();
+        state.bytes = snapshot.total_bytes();
+        state.contents = snapshot;
+        self.notify(&mut state);
+    }
+
+    /// Copy every page and pointer into `dst`, returning the number of
+    /// pages copied. Pages go first, so `dst`'s roots never name a page it
+    /// lacks.
+    pub fn export_to(&self, dst: &dyn PageStore) -> Result<usize> {
+        let snapshot = self.snapshot();
+        for data in snapshot.pages.values() {
+            dst.put(&Page { data: data.clone() })?;
+        }
+        for (name, cid) in &snapshot.named_roots {
+            dst.set_named_root(name, *cid)?;
+        }
+        if let Some(root) = snapshot.root {
+            dst.update_root(root)?;
+        }
+        Ok(snapshot.pages.len())
+    }
+
+    fn notify(&self, state: &mut State) {
+        state.root_changes += 1;
+        self.roots_changed.notify_all();
+    }
+}
+
+/// Wakes when any handle on the store changes a root.
+struct MemSignal {
+    state: Arc<Mutex<State>>,
+    roots_changed: Arc<Condvar>,
+    seen: u64,
+}
+
+impl RootSignal for MemSignal {
+    fn wait(&mut self, timeout: Duration) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self.roots_changed
+            .wait_timeout_while(state, timeout, |state| state.root_changes == self.seen)
+            .unwrap();
+        self.seen = state.root_changes;
+        Ok(())
+    }
+}
+
+impl PageStore for MemPageStore {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        let state = self.state.lock().unwrap();
+        let data = state.contents.pages.get(cid).ok_or(PageStoreError::NotFound(*cid))?;
+        Ok(Page { data: data.clone() })
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        let cid = Cid::from_bytes(&page.data);
+        let mut state = self.state.lock().unwrap();
+        if state.contents.pages.contains_key(&cid) {
+            return Ok(cid);
+        }
+        let (pages, bytes) = (state.contents.pages.len() + 1, state.bytes + page.data.len() as u64);
+        if state.max_pages.is_some_and(|max| pages > max) || state.max_bytes.is_some_and(|max| bytes > max) {
+            return Err(PageStoreError::Storage(format!(
+                "in-memory store full: {} pages, {} bytes", state.contents.pages.len(), state.bytes
+            )));
+        }
+        state.contents.pages.insert(cid, page.data.clone());
+        state.bytes = bytes;
+        Ok(cid)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        Ok(self.contains(cid))
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.contents.root = Some(new_root);
+        self.notify(&mut state);
+        Ok(())
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        Ok(self.state.lock().unwrap().contents.root)
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.contents.named_roots.insert(name.to_string(), cid);
+        self.notify(&mut state);
+        Ok(())
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        Ok(self.state.lock().unwrap().contents.named_roots.get(name).copied())
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let removed = state.contents.named_roots.remove(name).is_some();
+        if removed {
+            self.notify(&mut state);
+        }
+        Ok(removed)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.contents.named_roots.iter().map(|(name, cid)| (name.clone(), *cid)).collect())
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.contents.pages.iter().map(|(cid, data)| (*cid, data.len() as u64)).collect())
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        Ok(self.remove(cid))
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        Ok(Some(Box::new(MemSignal {
+            state: self.state.clone(),
+            roots_changed: self.roots_changed.clone(),
+            seen: self.state.lock().unwrap().root_changes,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::RootEvent;
+
+    #[test]
+    fn test_clones_share_contents() {
+        let store = MemPageStore::new();
+        let handle = store.clone();
+        let cid = store.put(&Page { data: b"page".to_vec() }).unwrap();
+        store.update_root(cid).unwrap();
+        store.set_named_root("b", cid).unwrap();
+        store.set_named_root("a", cid).unwrap();
+
+        assert_eq!(handle.get(&cid).unwrap().data, b"page");
+        assert_eq!(handle.current_root().unwrap(), Some(cid));
+        assert_eq!(handle.list_named_roots().unwrap(), vec![("a".into(), cid), ("b".into(), cid)]);
+        assert_eq!((handle.page_count(), handle.total_bytes()), (1, 4));
+
+        assert!(handle.remove(&cid));
+        assert!(!store.contains(&cid));
+        assert_eq!(store.total_bytes(), 0);
+        assert!(matches!(store.get(&cid), Err(PageStoreError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_capacity_limits() {
+        let store = MemPageStore::new().with_max_pages(2).with_max_bytes(10);
+        store.put(&Page { data: vec![1; 4] }).unwrap();
+        assert!(store.put(&Page { data: vec![2; 7] }).is_err());
+        store.put(&Page { data: vec![3; 6] }).unwrap();
+        // Already stored pages are always accepted
+        store.put(&Page { data: vec![3; 6] }).unwrap();
+        assert!(store.put(&Page { data: vec![] }).is_err());
+        assert_eq!(store.page_count(), 2);
+    }
+
+    #[test]
+    fn test_snapshot_and_export() {
+        let store = MemPageStore::new();
+        let a = store.put(&Page { data: b"a".to_vec() }).unwrap();
+        store.update_root(a).unwrap();
+        let snapshot = store.snapshot();
+
+        let b = store.put(&Page { data: b"b".to_vec() }).unwrap();
+        store.set_root_if(Some(a), b).unwrap();
+        assert!(store.set_root_if(Some(a), b).is_err());
+        store.restore(snapshot.clone());
+        assert_eq!(store.current_root().unwrap(), Some(a));
+        assert!(!store.contains(&b));
+
+        let copy = MemPageStore::new();
+        store.set_named_root("main", a).unwrap();
+        assert_eq!(store.export_to(&copy).unwrap(), 1);
+        assert_eq!(copy.snapshot(), store.snapshot());
+        assert_eq!(MemPageStore::from_snapshot(snapshot).total_bytes(), 1);
+    }
+
+    #[test]
+    fn test_watch() {
+        let store = MemPageStore::new();
+        let writer = store.clone();
+        let mut watch = craftsql_core::watch(&store).unwrap().with_poll_interval(Duration::from_secs(60));
+        assert!(watch.is_signaled());
+        assert_eq!(watch.next_event(Some(Duration::from_millis(10))).unwrap(), None);
+
+        let cid = Cid::from_bytes(b"root");
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            writer.update_root(cid).unwrap();
+        });
+        // Woken by the signal long before the poll interval
+        assert_eq!(watch.next_event(Some(Duration::from_secs(10))).unwrap(), Some(RootEvent::Root(cid)));
+        thread.join().unwrap();
+        store.set_named_root("main", cid).unwrap();
+        store.remove_named_root("main").unwrap();
+        store.set_named_root("dev", cid).unwrap();
+        // Changes between two reads collapse
+        let event = watch.next_event(Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(event, Some(RootEvent::Named { name: "dev".into(), cid: Some(cid) }));
+    }
+}
@@ -0,0 +1,117 @@
+//! JavaScript API, exported with wasm-bindgen.
+
+use std::sync::{Arc, Mutex};
+
+use craftsql_core::{Cid, Page, PageStore, PageStoreError};
+use wasm_bindgen::prelude::*;
+
+use crate::{OpfsFile, OpfsPageStore, PagedFile};
+
+fn to_js(e: PageStoreError) -> JsValue {
+    JsError::new(&e.to_string()).into()
+}
+
+fn parse_cid(hex_str: &str) -> Result<Cid, JsValue> {
+    hex::decode(hex_str).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(Cid)
+        .ok_or_else(|| JsError::new(&format!("invalid CID {:?}", hex_str)).into())
+}
+
+/// A content-addressed store in the origin's private file system.
+/// CIDs are hex strings.
+#[wasm_bindgen(js_name = CraftStore)]
+pub struct JsStore {
+    store: Arc<OpfsPageStore>,
+}
+
+#[wasm_bindgen(js_class = CraftStore)]
+impl JsStore {
+    /// Open the store named `name`, creating it if needed.
+    pub async fn open(name: String) -> Result<JsStore, JsValue> {
+        let file = OpfsFile::open("craftsql", &format!("{}.log", name)).await.map_err(to_js)?;
+        let store = OpfsPageStore::open(file).map_err(to_js)?;
+        Ok(JsStore { store: Arc::new(store) })
+    }
+
+    pub fn get(&self, cid: &str) -> Result<Vec<u8>, JsValue> {
+        Ok(self.store.get(&parse_cid(cid)?).map_err(to_js)?.data)
+    }
+
+    pub fn put(&self, data: Vec<u8>) -> Result<String, JsValue> {
+        Ok(self.store.put(&Page { data }).map_err(to_js)?.to_hex())
+    }
+
+    #[wasm_bindgen(js_name = currentRoot)]
+    pub fn current_root(&self) -> Result<Option<String>, JsValue> {
+        Ok(self.store.current_root().map_err(to_js)?.map(|cid| cid.to_hex()))
+    }
+
+    #[wasm_bindgen(js_name = updateRoot)]
+    pub fn update_root(&self, cid: &str) -> Result<(), JsValue> {
+        self.store.update_root(parse_cid(cid)?).map_err(to_js)
+    }
+
+    #[wasm_bindgen(js_name = getNamedRoot)]
+    pub fn get_named_root(&self, name: &str) -> Result<Option<String>, JsValue> {
+        Ok(self.store.get_named_root(name).map_err(to_js)?.map(|cid| cid.to_hex()))
+    }
+
+    #[wasm_bindgen(js_name = setNamedRoot)]
+    pub fn set_named_root(&self, name: &str, cid: &str) -> Result<(), JsValue> {
+        self.store.set_named_root(name, parse_cid(cid)?).map_err(to_js)
+    }
+
+    #[wasm_bindgen(js_name = removeNamedRoot)]
+    pub fn remove_named_root(&self, name: &str) -> Result<bool, JsValue> {
+        self.store.remove_named_root(name).map_err(to_js)
+    }
+
+    /// Names of all named roots, sorted.
+    #[wasm_bindgen(js_name = namedRoots)]
+    pub fn named_roots(&self) -> Result<Vec<String>, JsValue> {
+        Ok(self.store.list_named_roots().map_err(to_js)?.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// The database at the current root, as a file for a SQLite VFS.
+    #[wasm_bindgen(js_name = openFile)]
+    pub fn open_file(&self) -> Result<JsFile, JsValue> {
+        let file = PagedFile::open(Arc::clone(&self.store)).map_err(to_js)?;
+        Ok(JsFile { file: Mutex::new(file) })
+    }
+}
+
+/// A database file for a JavaScript SQLite VFS: `xRead`, `xWrite`,
+/// `xTruncate`, `xFileSize`, and `xSync` map onto `read`, `write`,
+/// `truncate`, `size`, and `sync`.
+#[wasm_bindgen(js_name = CraftFile)]
+pub struct JsFile {
+    file: Mutex<PagedFile<OpfsPageStore>>,
+}
+
+#[wasm_bindgen(js_class = CraftFile)]
+impl JsFile {
+    /// Fill `buf` from `offset`, zeroing anything past the end. Returns the
+    /// bytes read from the file; fewer than asked is SQLite's short read.
+    pub fn read(&self, buf: &mut [u8], offset: f64) -> Result<u32, JsValue> {
+        Ok(self.file.lock().unwrap().read(buf, offset as u64).map_err(to_js)? as u32)
+    }
+
+    pub fn write(&self, data: &[u8], offset: f64) -> Result<(), JsValue> {
+        self.file.lock().unwrap().write(data, offset as u64).map_err(to_js)
+    }
+
+    pub fn truncate(&self, size: f64) {
+        self.file.lock().unwrap().truncate(size as u64)
+    }
+
+    pub fn size(&self) -> f64 {
+        self.file.lock().unwrap().size() as f64
+    }
+
+    /// Commit buffered writes as a new root; returns it, or nothing if
+    /// nothing changed.
+    pub fn sync(&self) -> Result<Option<String>, JsValue> {
+        Ok(self.file.lock().unwrap().sync().map_err(to_js)?.map(|cid| cid.to_hex()))
+    }
+}
@@ -0,0 +1,183 @@
+//! A database file over a PageStore, for SQLite VFSes that can't use
+//! `craftsql-vfs`, such as one written in JavaScript for SQLite's WASM build.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use craftsql_core::{Cid, Page, PageStore, PageStoreError, PageTable, Result};
+
+/// Page size until the database header says otherwise.
+const DEFAULT_PAGE_SIZE: usize = 4096;
+
+/// The database at a store's current root as a byte-addressed file.
+///
+/// Same semantics as the `craftsql-vfs` file handle: writes are buffered
+/// until [`sync`](Self::sync), which stores the changed pages and a new page
+/// table and moves the store's root to it.
+pub struct PagedFile<S: PageStore + ?Sized> {
+    store: Arc<S>,
+    table: PageTable,
+    page_size: usize,
+    size: u64,
+    /// Pages written since the last sync.
+    dirty: BTreeMap<usize, Vec<u8>>,
+    /// Whether anything changed since the last sync, truncation included.
+    changed: bool,
+}
+
+impl<S: PageStore + ?Sized> PagedFile<S> {
+    /// Open the database at `store`'s current root, or an empty one.
+    pub fn open(store: Arc<S>) -> Result<Self> {
+        let table = match store.current_root()? {
+            Some(root) => PageTable::from_bytes(&store.get(&root)?.data)
+                .map_err(|e| PageStoreError::Storage(format!("invalid page table: {}", e)))?,
+            None => PageTable::created_now(),
+        };
+        let page_size = table.page_size_in(&*store)?.unwrap_or(DEFAULT_PAGE_SIZE);
+        let size = (table.len() * page_size) as u64;
+        Ok(Self { store, table, page_size, size, dirty: BTreeMap::new(), changed: false })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn page(&self, page_num: usize) -> Result<Vec<u8>> {
+        if let Some(data) = self.dirty.get(&page_num) {
+            return Ok(data.clone());
+        }
+        match self.table.get(page_num) {
+            Some(cid) => Ok(self.store.get(cid)?.data),
+            None => Ok(vec![0; self.page_size]),
+        }
+    }
+
+    /// Fill `buf` from `offset`, with zeros past the end of the file.
+    /// Returns how many bytes came from the file.
+    pub fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let available = self.size.saturating_sub(offset).min(buf.len() as u64) as usize;
+        buf[available..].fill(0);
+        let mut done = 0;
+        while done < available {
+            let position = offset as usize + done;
+            let (page_num, in_page) = (position / self.page_size, position % self.page_size);
+            let page = self.page(page_num)?;
+            let len = (available - done).min(self.page_size - in_page);
+            let end = (in_page + len).min(page.len());
+            let copied = end.saturating_sub(in_page);
+            buf[done..done + copied].copy_from_slice(&page[in_page..end]);
+            buf[done + copied..done + len].fill(0);
+            done += len;
+        }
+        Ok(available)
+    }
+
+    /// Write `data` at `offset`, growing the file as needed.
+    pub fn write(&mut self, data: &[u8], offset: u64) -> Result<()> {
+        // SQLite writes page 1 first; its header gives the page size
+        if offset == 0 && self.table.is_empty() && self.dirty.is_empty() && data.len() >= 100 {
+            let page_size = u16::from_be_bytes([data[16], data[17]]) as usize;
+            // 1 means 65536
+            let page_size = if page_size == 1 { 65536 } else { page_size };
+            if (512..=65536).contains(&page_size) && page_size.is_power_of_two() {
+                self.page_size = page_size;
+            }
+        }
+
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset as usize + done;
+            let (page_num, in_page) = (position / self.page_size, position % self.page_size);
+            let len = (data.len() - done).min(self.page_size - in_page);
+            let mut page = self.page(page_num)?;
+            page.resize(self.page_size, 0);
+            page[in_page..in_page + len].copy_from_slice(&data[done..done + len]);
+            self.dirty.insert(page_num, page);
+            done += len;
+        }
+        self.size = self.size.max(offset + data.len() as u64);
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Cut or extend the file to `size` bytes.
+    pub fn truncate(&mut self, size: u64) {
+        let pages = (size as usize).div_ceil(self.page_size);
+        self.table.entries.truncate(pages);
+        self.dirty.retain(|&page_num, _| page_num < pages);
+        self.size = size;
+        self.changed = true;
+    }
+
+    /// Store what changed and move the store's root to the new page table,
+    /// returning it. Does nothing if nothing changed.
+    pub fn sync(&mut self) -> Result<Option<Cid>> {
+        if !self.changed {
+            return Ok(None);
+        }
+        for (&page_num, data) in &self.dirty {
+            let cid = self.store.put(&Page { data: data.clone() })?;
+            self.table.set(page_num, cid);
+        }
+        self.table.page_size = self.page_size as u32;
+        if let Some(header) = self.dirty.get(&0) {
+            self.table.record_header(header);
+        }
+        let root = self.store.put(&Page { data: self.table.to_bytes() })?;
+        self.store.update_root(root)?;
+        self.dirty.clear();
+        self.changed = false;
+        Ok(Some(root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+
+    #[test]
+    fn test_write_sync_reopen() {
+        let store = Arc::new(MemPageStore::new());
+        let mut file = PagedFile::open(Arc::clone(&store)).unwrap();
+
+        // A header declaring 1024-byte pages, then a write spanning pages 1 and 2
+        let mut header = vec![0u8; 1024];
+        header[16..18].copy_from_slice(&1024u16.to_be_bytes());
+        file.write(&header, 0).unwrap();
+        file.write(&[7; 100], 2000).unwrap();
+        assert_eq!((file.page_size(), file.size()), (1024, 2100));
+        assert_eq!(store.current_root().unwrap(), None);
+
+        let root = file.sync().unwrap().unwrap();
+        assert_eq!(file.sync().unwrap(), None);
+        assert_eq!(store.current_root().unwrap(), Some(root));
+
+        let reopened = PagedFile::open(Arc::clone(&store)).unwrap();
+        assert_eq!((reopened.page_size(), reopened.size()), (1024, 3072));
+        let mut buf = [1u8; 120];
+        assert_eq!(reopened.read(&mut buf, 1990).unwrap(), 120);
+        assert_eq!(&buf[..10], &[0; 10]);
+        assert_eq!(&buf[10..110], &[7; 100]);
+
+        // Reads past the end come back zeroed
+        let mut buf = [1u8; 8];
+        assert_eq!(reopened.read(&mut buf, 3068).unwrap(), 4);
+        assert_eq!(buf, [0; 8]);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let store = Arc::new(MemPageStore::new());
+        let mut file = PagedFile::open(Arc::clone(&store)).unwrap();
+        file.write(&vec![1; 3 * DEFAULT_PAGE_SIZE], 0).unwrap();
+        file.sync().unwrap();
+        file.truncate(DEFAULT_PAGE_SIZE as u64);
+        file.sync().unwrap();
+        assert_eq!(PagedFile::open(store).unwrap().size(), DEFAULT_PAGE_SIZE as u64);
+    }
+}
@@ -0,0 +1,52 @@
+//! Browser PageStore — content-addressed databases in the Origin Private
+//! File System, for `wasm32-unknown-unknown`.
+//!
+//! Each store is one OPFS file holding an append-only log of pages and root
+//! updates ([`LogPageStore`]), written through a synchronous access handle,
+//! so the synchronous [`PageStore`] API works unchanged. Sync access
+//! handles exist only in dedicated workers; run the database in one.
+//!
+//! # Using it with SQLite's WASM build
+//!
+//! Build with `wasm-pack build crates/store-opfs --target web`, which
+//! exports two classes (see `bindings.rs`):
+//!
+//! - `CraftStore.open(name)` opens a store, with `get`/`put` for pages and
+//!   `currentRoot`/`updateRoot` and the named-root methods for branches;
+//! - `store.openFile()` returns a `CraftFile` for the database at the
+//!   current root: byte-level `read`/`write`/`truncate`/`size` and `sync`,
+//!   which commits buffered writes as a new root.
+//!
+//! Register a VFS with the `@sqlite.org/sqlite-wasm` build (via its
+//! `sqlite3.vfs.installVfs` helper) whose main-database `xRead`, `xWrite`,
+//! `xTruncate`, `xFileSize`, and `xSync` call the `CraftFile` methods of
+//! the same names, and open databases with `PRAGMA journal_mode=MEMORY` so
+//! no journal file reaches the VFS. As with `craftsql-vfs`, never use WAL.
+//!
+//! The pages are ordinary CraftSQL pages and roots are page tables, so a
+//! browser database can be copied to or from any other store through the
+//! [`PageStore`] API, as `craftsql-sync` does.
+//!
+//! On other targets only the portable parts, [`LogPageStore`] and
+//! [`PagedFile`], are built.
+
+mod file;
+mod log;
+
+pub use file::PagedFile;
+pub use log::{BlockFile, LogPageStore};
+
+#[cfg(target_arch = "wasm32")]
+mod bindings;
+#[cfg(target_arch = "wasm32")]
+mod opfs;
+
+#[cfg(target_arch = "wasm32")]
+pub use opfs::OpfsFile;
+
+/// A PageStore kept in an OPFS file.
+#[cfg(target_arch = "wasm32")]
+pub type OpfsPageStore = LogPageStore<OpfsFile>;
+
+#[cfg(doc)]
+use craftsql_core::PageStore;
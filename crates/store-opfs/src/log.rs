@@ -0,0 +1,271 @@
+//! A PageStore kept as one append-only log in a single random-access file.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use craftsql_core::{Cid, Page, PageStore, PageStoreError, Result};
+use serde::{Deserialize, Serialize};
+
+/// A random-access file, such as an OPFS sync access handle.
+pub trait BlockFile: Send + Sync {
+    fn size(&self) -> Result<u64>;
+
+    /// Fill `buf` from `offset`; reading past the end is an error.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()>;
+
+    fn write_at(&self, data: &[u8], offset: u64) -> Result<()>;
+
+    fn truncate(&self, len: u64) -> Result<()>;
+
+    /// Persist everything written so far.
+    fn flush(&self) -> Result<()>;
+}
+
+/// Record kinds.
+const PAGE: u8 = 0;
+const ROOTS: u8 = 1;
+
+/// Record header: kind, payload length (u32 LE), and the payload's CID.
+const HEADER_LEN: usize = 1 + 4 + 32;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Roots {
+    root: Option<Cid>,
+    named: BTreeMap<String, Cid>,
+}
+
+struct State {
+    /// Payload offset and length of each page.
+    index: HashMap<Cid, (u64, u32)>,
+    /// Where the next record goes.
+    end: u64,
+    roots: Roots,
+}
+
+/// Pages and root pointers in a single file.
+///
+/// Every change is a record appended to the file: a page, or the whole set
+/// of root pointers after a root changed, the last of which is current.
+/// Records carry the CID of their payload, so a record torn by a crash fails
+/// to verify on open and is cut off along with anything after it. Opening
+/// reads and hashes the whole file to rebuild the page index.
+pub struct LogPageStore<F: BlockFile> {
+    file: F,
+    state: Mutex<State>,
+}
+
+impl<F: BlockFile> LogPageStore<F> {
+    /// Open the store kept in `file`, which may be empty.
+    pub fn open(file: F) -> Result<Self> {
+        let size = file.size()?;
+        let mut state = State { index: HashMap::new(), end: 0, roots: Roots::default() };
+        let mut header = [0u8; HEADER_LEN];
+        while state.end + HEADER_LEN as u64 <= size {
+            file.read_at(&mut header, state.end)?;
+            let len = u32::from_le_bytes(header[1..5].try_into().unwrap());
+            let cid = Cid(header[5..].try_into().unwrap());
+            let start = state.end + HEADER_LEN as u64;
+            if start + len as u64 > size {
+                break;
+            }
+            let mut payload = vec![0; len as usize];
+            file.read_at(&mut payload, start)?;
+            if Cid::from_bytes(&payload) != cid {
+                break;
+            }
+            match header[0] {
+                PAGE => {
+                    state.index.insert(cid, (start, len));
+                }
+                ROOTS => {
+                    state.roots = bincode::deserialize(&payload)
+                        .map_err(|e| PageStoreError::Storage(format!("invalid roots record: {}", e)))?;
+                }
+                _ => break,
+            }
+            state.end = start + len as u64;
+        }
+        if state.end < size {
+            file.truncate(state.end)?;
+        }
+        Ok(Self { file, state: Mutex::new(state) })
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.state.lock().unwrap().index.len()
+    }
+
+    /// Bytes of file in use, including superseded root records.
+    pub fn file_size(&self) -> u64 {
+        self.state.lock().unwrap().end
+    }
+
+    fn append(&self, state: &mut State, kind: u8, payload: &[u8]) -> Result<(Cid, u64)> {
+        let cid = Cid::from_bytes(payload);
+        let len = u32::try_from(payload.len())
+            .map_err(|_| PageStoreError::Storage(format!("{} byte record is too large", payload.len())))?;
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.push(kind);
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&cid.0);
+        record.extend_from_slice(payload);
+        self.file.write_at(&record, state.end)?;
+        let start = state.end + HEADER_LEN as u64;
+        state.end = start + len as u64;
+        Ok((cid, start))
+    }
+
+    /// Apply `change` to the roots and append them, flushing the pages
+    /// written before so the roots never name a page that isn't durable.
+    fn update_roots<T>(&self, change: impl FnOnce(&mut Roots) -> T) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        let mut roots = state.roots.clone();
+        let result = change(&mut roots);
+        let payload = bincode::serialize(&roots).map_err(|e| PageStoreError::Storage(e.to_string()))?;
+        self.append(&mut state, ROOTS, &payload)?;
+        self.file.flush()?;
+        state.roots = roots;
+        Ok(result)
+    }
+}
+
+impl<F: BlockFile> PageStore for LogPageStore<F> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        let (offset, len) = *self.state.lock().unwrap().index.get(cid).ok_or(PageStoreError::NotFound(*cid))?;
+        let mut data = vec![0; len as usize];
+        self.file.read_at(&mut data, offset)?;
+        Ok(Page { data })
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        let mut state = self.state.lock().unwrap();
+        let cid = Cid::from_bytes(&page.data);
+        if !state.index.contains_key(&cid) {
+            let (_, offset) = self.append(&mut state, PAGE, &page.data)?;
+            state.index.insert(cid, (offset, page.data.len() as u32));
+        }
+        Ok(cid)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        Ok(self.state.lock().unwrap().index.contains_key(cid))
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        self.update_roots(|roots| roots.root = Some(new_root))
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        Ok(self.state.lock().unwrap().roots.root)
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.update_roots(|roots| {
+            roots.named.insert(name.to_string(), cid);
+        })
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        Ok(self.state.lock().unwrap().roots.named.get(name).copied())
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        if !self.state.lock().unwrap().roots.named.contains_key(name) {
+            return Ok(false);
+        }
+        self.update_roots(|roots| roots.named.remove(name).is_some())
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.roots.named.iter().map(|(name, cid)| (name.clone(), *cid)).collect())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// A file in memory; clones share the same bytes.
+    #[derive(Clone, Default)]
+    pub(crate) struct MemFile(pub Arc<Mutex<Vec<u8>>>);
+
+    impl BlockFile for MemFile {
+        fn size(&self) -> Result<u64> {
+            Ok(self.0.lock().unwrap().len() as u64)
+        }
+
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+            let data = self.0.lock().unwrap();
+            let range = offset as usize..offset as usize + buf.len();
+            buf.copy_from_slice(data.get(range).ok_or_else(|| PageStoreError::Storage("short read".into()))?);
+            Ok(())
+        }
+
+        fn write_at(&self, data: &[u8], offset: u64) -> Result<()> {
+            let mut file = self.0.lock().unwrap();
+            let end = offset as usize + data.len();
+            if file.len() < end {
+                file.resize(end, 0);
+            }
+            file[offset as usize..end].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn truncate(&self, len: u64) -> Result<()> {
+            self.0.lock().unwrap().truncate(len as usize);
+            Ok(())
+        }
+
+        fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_reopen_replays_log() {
+        let file = MemFile::default();
+        let store = LogPageStore::open(file.clone()).unwrap();
+        let a = store.put(&Page { data: b"page a".to_vec() }).unwrap();
+        let b = store.put(&Page { data: b"page b".to_vec() }).unwrap();
+        store.put(&Page { data: b"page a".to_vec() }).unwrap();
+        store.update_root(a).unwrap();
+        store.update_root(b).unwrap();
+        store.set_named_root("main", a).unwrap();
+        assert!(store.remove_named_root("main").unwrap());
+        store.set_named_root("dev", b).unwrap();
+
+        let reopened = LogPageStore::open(file).unwrap();
+        assert_eq!(reopened.page_count(), 2);
+        assert_eq!(reopened.get(&a).unwrap().data, b"page a");
+        assert_eq!(reopened.current_root().unwrap(), Some(b));
+        assert_eq!(reopened.list_named_roots().unwrap(), vec![("dev".into(), b)]);
+        assert_eq!(reopened.file_size(), store.file_size());
+    }
+
+    #[test]
+    fn test_torn_tail_is_dropped() {
+        let file = MemFile::default();
+        let store = LogPageStore::open(file.clone()).unwrap();
+        let a = store.put(&Page { data: vec![1; 100] }).unwrap();
+        store.update_root(a).unwrap();
+        let durable = store.file_size();
+        let b = store.put(&Page { data: vec![2; 100] }).unwrap();
+        store.update_root(b).unwrap();
+
+        // A crash that lost the end of the second root update
+        let torn = store.file_size() - 3;
+        file.truncate(torn).unwrap();
+        let reopened = LogPageStore::open(file.clone()).unwrap();
+        assert_eq!(reopened.current_root().unwrap(), Some(a));
+        assert!(reopened.has(&b).unwrap());
+        assert!(reopened.file_size() > durable);
+        assert_eq!(file.size().unwrap(), reopened.file_size());
+
+        // Corruption inside a record cuts the log there
+        file.0.lock().unwrap()[HEADER_LEN + 10] ^= 1;
+        let reopened = LogPageStore::open(file).unwrap();
+        assert_eq!((reopened.page_count(), reopened.current_root().unwrap()), (0, None));
+    }
+}
@@ -0,0 +1,106 @@
+//! OPFS files through synchronous access handles (dedicated workers only).
+
+use craftsql_core::{PageStoreError, Result};
+use js_sys::Promise;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemGetDirectoryOptions, FileSystemGetFileOptions,
+    FileSystemReadWriteOptions, FileSystemSyncAccessHandle, WorkerGlobalScope,
+};
+
+use crate::log::BlockFile;
+
+fn js_error(context: &str, e: JsValue) -> PageStoreError {
+    PageStoreError::Storage(format!("{}: {:?}", context, e))
+}
+
+async fn resolve<T: JsCast>(promise: Promise, context: &str) -> Result<T> {
+    let value = JsFuture::from(promise).await.map_err(|e| js_error(context, e))?;
+    value.dyn_into().map_err(|e| js_error(context, e))
+}
+
+/// An OPFS file open for synchronous reads and writes.
+///
+/// The handle locks the file until dropped, so only one store per file can
+/// be open at a time, across all tabs of the origin.
+pub struct OpfsFile {
+    handle: FileSystemSyncAccessHandle,
+}
+
+// SAFETY: without the `atomics` target feature a wasm32 program has a single
+// thread, so the JS handle can never be used from two threads.
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Send for OpfsFile {}
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Sync for OpfsFile {}
+
+impl OpfsFile {
+    /// Open or create `file` in directory `dir` at the root of the origin's
+    /// private file system. Must run in a dedicated worker.
+    pub async fn open(dir: &str, file: &str) -> Result<Self> {
+        let scope: WorkerGlobalScope = js_sys::global()
+            .dyn_into()
+            .map_err(|_| PageStoreError::Storage("OPFS sync access needs a dedicated worker".into()))?;
+        let root: FileSystemDirectoryHandle =
+            resolve(scope.navigator().storage().get_directory(), "open OPFS root").await?;
+
+        let dir_options = FileSystemGetDirectoryOptions::new();
+        dir_options.set_create(true);
+        let dir: FileSystemDirectoryHandle =
+            resolve(root.get_directory_handle_with_options(dir, &dir_options), "open OPFS directory").await?;
+
+        let file_options = FileSystemGetFileOptions::new();
+        file_options.set_create(true);
+        let file: FileSystemFileHandle =
+            resolve(dir.get_file_handle_with_options(file, &file_options), "open OPFS file").await?;
+        let handle = resolve(file.create_sync_access_handle(), "lock OPFS file").await?;
+        Ok(Self { handle })
+    }
+}
+
+impl BlockFile for OpfsFile {
+    fn size(&self) -> Result<u64> {
+        Ok(self.handle.get_size().map_err(|e| js_error("OPFS size", e))? as u64)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let options = FileSystemReadWriteOptions::new();
+        options.set_at(offset as f64);
+        let read = self.handle.read_with_u8_array_and_options(buf, &options)
+            .map_err(|e| js_error("OPFS read", e))?;
+        if (read as usize) < buf.len() {
+            return Err(PageStoreError::Storage(format!(
+                "OPFS read at {}: {} of {} bytes", offset, read, buf.len()
+            )));
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, data: &[u8], offset: u64) -> Result<()> {
+        let options = FileSystemReadWriteOptions::new();
+        options.set_at(offset as f64);
+        let written = self.handle.write_with_u8_array_and_options(data, &options)
+            .map_err(|e| js_error("OPFS write", e))?;
+        if (written as usize) < data.len() {
+            return Err(PageStoreError::Storage(format!(
+                "OPFS write at {}: {} of {} bytes (quota?)", offset, written, data.len()
+            )));
+        }
+        Ok(())
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        self.handle.truncate_with_f64(len as f64).map_err(|e| js_error("OPFS truncate", e))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.handle.flush().map_err(|e| js_error("OPFS flush", e))
+    }
+}
+
+impl Drop for OpfsFile {
+    fn drop(&mut self) {
+        self.handle.close();
+    }
+}
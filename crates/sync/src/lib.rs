@@ -0,0 +1,471 @@
+//! CraftSQL Sync — moving databases between PageStores.
+//!
+//! [`clone_store`] copies a whole store; [`push`] and [`pull`] move one
+//! branch, sending only the pages the other side lacks, and [`fetch`] brings
+//! a remote branch over without moving ours. [`clone_tables`]
+//! and [`pull_tables`] copy only some tables of each database (see
+//! [`partial`]); [`clone_shallow`] and [`pull_shallow`] copy only the latest
+//! version, without its history (see [`shallow`]). [`fetch_over`] and
+//! [`push_over`] do the same over any byte stream to a store that
+//! [`serve`]s it (see [`protocol`]). [`estimate_sync_cost`] tells what a
+//! sync would bring over before starting it.
+//!
+//! An update is a fast-forward if the branch being overwritten is an
+//! ancestor of the new value in the commit DAG (see [`Commit`](craftsql_core::Commit)), or failing
+//! that, if it is still where it was after the last sync: for each remote,
+//! the local store remembers where every branch was after the last push or
+//! pull (its tracking ref, see [`tracking_ref`]). The second rule covers
+//! branches that point at bare page tables, which carry no history. Forcing
+//! an update is refused if the branch is protected on the side it would
+//! move (see [`protected_branches`]).
+//!
+//! [`remote_branch`] is where the local store last saw a remote's branch:
+//! its tracking ref, or what a later [`fetch`] found there. Fetching leaves
+//! the tracking ref alone, so that it still records what both sides agreed
+//! on when our branch catches up.
+
+pub mod partial;
+pub mod protocol;
+pub mod shallow;
+mod transfer;
+
+pub use partial::{clone_tables, pull_tables};
+pub use protocol::{fetch_over, push_over, serve};
+pub use shallow::{clone_shallow, pull_shallow, unshallow};
+pub use transfer::{clone_store, copy_roots, estimate_sync_cost, TransferStats};
+
+use transfer::CopyRoots;
+
+use craftsql_core::{is_ancestor, protected_branches, Cid, PageStore, PageStoreError};
+
+/// Sync errors.
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error(transparent)]
+    Store(#[from] PageStoreError),
+    #[error("no branch {0:?}")]
+    NoSuchBranch(String),
+    #[error("no table {0:?}")]
+    NoSuchTable(String),
+    #[error("non-fast-forward update of {branch}: it moved to {theirs} since the last sync (ours is {ours})")]
+    NonFastForward {
+        branch: String,
+        ours: Cid,
+        theirs: Cid,
+    },
+    /// The other end of a [`protocol`] session sent something it shouldn't.
+    #[error("sync protocol error: {0}")]
+    Protocol(String),
+}
+
+pub type Result<T> = std::result::Result<T, SyncError>;
+
+/// A store to push to or pull from, and the name its tracking refs go under.
+#[derive(Clone, Copy)]
+pub struct Remote<'a> {
+    pub name: &'a str,
+    pub store: &'a dyn PageStore,
+}
+
+/// Named root recording where `branch` on `remote` was after the last sync.
+pub fn tracking_ref(remote: &str, branch: &str) -> String {
+    format!("{}{}.{}", TRACKING_PREFIX, remote, branch)
+}
+
+const TRACKING_PREFIX: &str = ".remote.";
+
+/// Named root recording where a [`fetch`] found `branch` on `remote`, if it
+/// moved since the last push or pull.
+fn fetched_ref(remote: &str, branch: &str) -> String {
+    format!("{}{}.{}", FETCHED_PREFIX, remote, branch)
+}
+
+const FETCHED_PREFIX: &str = ".fetched.";
+
+/// Where `branch` on `remote` was when we last pushed, pulled, or fetched it.
+pub fn remote_branch(local: &dyn PageStore, remote: &str, branch: &str) -> Result<Option<Cid>> {
+    if let Some(cid) = local.get_named_root(&fetched_ref(remote, branch))? {
+        return Ok(Some(cid));
+    }
+    Ok(local.get_named_root(&tracking_ref(remote, branch))?)
+}
+
+/// Every remote branch we know of, as `(remote, branch, root)` sorted by
+/// remote and branch (see [`remote_branch`]).
+///
+/// Remote names are told apart from branch names at the first `.`, so
+/// remotes should be named without one.
+pub fn remote_branches(local: &dyn PageStore) -> Result<Vec<(String, String, Cid)>> {
+    let key = |name: &str, prefix| {
+        let (remote, branch) = name.strip_prefix(prefix)?.split_once('.')?;
+        Some((remote.to_string(), branch.to_string()))
+    };
+    let mut found = std::collections::BTreeMap::new();
+    let mut fetched = Vec::new();
+    for (name, cid) in local.list_named_roots()? {
+        if let Some(key) = key(&name, TRACKING_PREFIX) {
+            found.insert(key, cid);
+        } else if let Some(key) = key(&name, FETCHED_PREFIX) {
+            fetched.push((key, cid));
+        }
+    }
+    // What a fetch found is newer than the last push or pull
+    found.extend(fetched);
+    Ok(found.into_iter().map(|((remote, branch), cid)| (remote, branch, cid)).collect())
+}
+
+/// Forget every branch of `remote`, e.g. once it's no longer used.
+pub fn remove_remote_branches(local: &dyn PageStore, remote: &str) -> Result<()> {
+    for (_, branch, _) in remote_branches(local)?.into_iter().filter(|(name, ..)| name == remote) {
+        local.remove_named_root(&tracking_ref(remote, &branch))?;
+        local.remove_named_root(&fetched_ref(remote, &branch))?;
+    }
+    Ok(())
+}
+
+/// Record that `branch` is at `root` on both sides after a push or pull.
+fn set_synced(local: &dyn PageStore, remote: &str, branch: &str, root: Cid) -> Result<()> {
+    local.set_named_root(&tracking_ref(remote, branch), root)?;
+    local.remove_named_root(&fetched_ref(remote, branch))?;
+    Ok(())
+}
+
+/// Result of a push or pull.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchUpdate {
+    pub branch: String,
+    /// The updated side's branch before the sync.
+    pub old: Option<Cid>,
+    /// The updated side's branch after the sync.
+    pub new: Cid,
+    /// Whether a non-fast-forward update was forced.
+    pub forced: bool,
+    pub stats: TransferStats,
+}
+
+impl BranchUpdate {
+    pub fn up_to_date(&self) -> bool {
+        self.old == Some(self.new)
+    }
+}
+
+/// Send `branch` and any pages the remote lacks, then move the remote's
+/// branch to match ours.
+pub fn push(
+    local: &dyn PageStore,
+    remote: Remote<'_>,
+    branch: &str,
+    force: bool,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<BranchUpdate> {
+    let ours = local.get_named_root(branch)?
+        .ok_or_else(|| SyncError::NoSuchBranch(branch.to_string()))?;
+    let synced = local.get_named_root(&tracking_ref(remote.name, branch))?;
+
+    let mut copy = |roots: &[Cid]| copy_roots(local, remote.store, roots, on_progress);
+    let update = update_branch(local, remote.store, Direction::Push, branch, ours, synced, force, &mut copy)?;
+    // If the remote was ahead it kept its branch, which we haven't pulled
+    if update.new == ours {
+        set_synced(local, remote.name, branch, ours)?;
+    }
+    Ok(update)
+}
+
+/// Fetch the remote's `branch` and any pages we lack, then move our branch
+/// to match it.
+pub fn pull(
+    local: &dyn PageStore,
+    remote: Remote<'_>,
+    branch: &str,
+    force: bool,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<BranchUpdate> {
+    pull_with(local, remote, branch, force, copy_roots, on_progress)
+}
+
+/// [`pull`], bringing the remote's branch over with `copy`.
+pub(crate) fn pull_with(
+    local: &dyn PageStore,
+    remote: Remote<'_>,
+    branch: &str,
+    force: bool,
+    copy: CopyRoots,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<BranchUpdate> {
+    let theirs = remote.store.get_named_root(branch)?
+        .ok_or_else(|| SyncError::NoSuchBranch(branch.to_string()))?;
+    let synced = local.get_named_root(&tracking_ref(remote.name, branch))?;
+
+    let mut copy = |roots: &[Cid]| copy(remote.store, local, roots, on_progress);
+    let update = update_branch(remote.store, local, Direction::Pull, branch, theirs, synced, force, &mut copy)?;
+    set_synced(local, remote.name, branch, theirs)?;
+    Ok(update)
+}
+
+/// Fetch the remote's `branch` and any pages we lack, leaving our own
+/// branch where it is; [`remote_branch`] then finds it. The update is of
+/// where we last saw the remote's branch, which always follows the remote.
+pub fn fetch(
+    local: &dyn PageStore,
+    remote: Remote<'_>,
+    branch: &str,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<BranchUpdate> {
+    let theirs = remote.store.get_named_root(branch)?
+        .ok_or_else(|| SyncError::NoSuchBranch(branch.to_string()))?;
+    let mut update = BranchUpdate {
+        branch: branch.to_string(),
+        old: remote_branch(local, remote.name, branch)?,
+        new: theirs,
+        forced: false,
+        stats: TransferStats::default(),
+    };
+    if update.up_to_date() {
+        return Ok(update);
+    }
+
+    update.stats = copy_roots(remote.store, local, &[theirs], on_progress)?;
+    let fetched = fetched_ref(remote.name, branch);
+    if local.get_named_root(&tracking_ref(remote.name, branch))? == Some(theirs) {
+        local.remove_named_root(&fetched)?;
+    } else {
+        local.set_named_root(&fetched, theirs)?;
+    }
+    Ok(update)
+}
+
+/// Which way a branch update goes: our store is `src` for a push and `dst`
+/// for a pull.
+#[derive(Clone, Copy)]
+enum Direction {
+    Push,
+    Pull,
+}
+
+/// Move `branch` in `dst` to `source_root` from `src`, given where the
+/// branch was at the last sync; `copy` brings roots over from `src`.
+#[allow(clippy::too_many_arguments)]
+fn update_branch(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    direction: Direction,
+    branch: &str,
+    source_root: Cid,
+    synced: Option<Cid>,
+    force: bool,
+    copy: &mut dyn FnMut(&[Cid]) -> Result<TransferStats>,
+) -> Result<BranchUpdate> {
+    let old = dst.get_named_root(branch)?;
+    let mut update = BranchUpdate {
+        branch: branch.to_string(),
+        old,
+        new: source_root,
+        forced: false,
+        stats: TransferStats::default(),
+    };
+
+    if let Some(old) = old {
+        if old == source_root {
+            return Ok(update);
+        }
+        // Nothing new on our side since the last sync, or the other side
+        // already built on ours: the other side is ahead
+        if !force && (synced == Some(source_root) || is_ancestor(dst, source_root, old)?) {
+            update.new = old;
+            return Ok(update);
+        }
+        if synced != Some(old) && !is_ancestor(src, old, source_root)? {
+            if !force {
+                let (ours, theirs) = match direction {
+                    Direction::Push => (source_root, old),
+                    Direction::Pull => (old, source_root),
+                };
+                return Err(SyncError::NonFastForward { branch: branch.to_string(), ours, theirs });
+            }
+            if protected_branches(dst)?.contains(branch) {
+                return Err(PageStoreError::Protected(branch.to_string()).into());
+            }
+            update.forced = true;
+        }
+    }
+
+    update.stats = copy(&[source_root])?;
+    dst.set_named_root(branch, source_root)?;
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::Commit;
+    use craftsql_store_local::LocalPageStore;
+    use craftsql_testing::put_table;
+
+    fn stores() -> (tempfile::TempDir, LocalPageStore, LocalPageStore) {
+        let tmp = tempfile::tempdir().unwrap();
+        let local = LocalPageStore::new(&tmp.path().join("local")).unwrap();
+        let remote = LocalPageStore::new(&tmp.path().join("remote")).unwrap();
+        (tmp, local, remote)
+    }
+
+    #[test]
+    fn test_push_sends_only_missing_pages() {
+        let (_tmp, local, remote_store) = stores();
+        let remote = Remote { name: "origin", store: &remote_store };
+
+        let v1 = put_table(&local, &[b"a", b"b"]);
+        local.set_named_root("main", v1).unwrap();
+        let update = push(&local, remote, "main", false, &mut |_| {}).unwrap();
+        assert_eq!((update.old, update.new, update.forced), (None, v1, false));
+        assert_eq!(update.stats.pages_copied, 2);
+        assert_eq!(remote_store.get_named_root("main").unwrap(), Some(v1));
+        assert_eq!(local.get_named_root(&tracking_ref("origin", "main")).unwrap(), Some(v1));
+
+        let v2 = put_table(&local, &[b"a", b"b", b"c"]);
+        local.set_named_root("main", v2).unwrap();
+        let update = push(&local, remote, "main", false, &mut |_| {}).unwrap();
+        assert_eq!((update.stats.pages_copied, update.stats.pages_skipped), (1, 2));
+        assert!(push(&local, remote, "main", false, &mut |_| {}).unwrap().up_to_date());
+
+        assert!(matches!(
+            push(&local, remote, "nope", false, &mut |_| {}),
+            Err(SyncError::NoSuchBranch(_))
+        ));
+    }
+
+    #[test]
+    fn test_push_refuses_non_fast_forward() {
+        let (_tmp, local, remote_store) = stores();
+        let remote = Remote { name: "origin", store: &remote_store };
+        local.set_named_root("main", put_table(&local, &[b"base"])).unwrap();
+        push(&local, remote, "main", false, &mut |_| {}).unwrap();
+
+        // Both sides move on
+        let theirs = put_table(&remote_store, &[b"theirs"]);
+        remote_store.set_named_root("main", theirs).unwrap();
+        let ours = put_table(&local, &[b"ours"]);
+        local.set_named_root("main", ours).unwrap();
+
+        let err = push(&local, remote, "main", false, &mut |_| {}).unwrap_err();
+        assert!(matches!(err, SyncError::NonFastForward { theirs: t, ours: o, .. } if t == theirs && o == ours));
+        assert_eq!(remote_store.get_named_root("main").unwrap(), Some(theirs));
+
+        // Not even forced onto a protected branch
+        craftsql_core::set_protected(&remote_store, "main", true).unwrap();
+        let err = push(&local, remote, "main", true, &mut |_| {}).unwrap_err();
+        assert!(matches!(err, SyncError::Store(PageStoreError::Protected(_))));
+        craftsql_core::set_protected(&remote_store, "main", false).unwrap();
+
+        let update = push(&local, remote, "main", true, &mut |_| {}).unwrap();
+        assert!(update.forced);
+        assert_eq!(remote_store.get_named_root("main").unwrap(), Some(ours));
+
+        // Remote moves ahead of an unchanged local branch: nothing to push,
+        // and it must not be overwritten later either
+        let ahead = put_table(&remote_store, &[b"ahead"]);
+        remote_store.set_named_root("main", ahead).unwrap();
+        assert_eq!(push(&local, remote, "main", false, &mut |_| {}).unwrap().new, ahead);
+        assert_eq!(push(&local, remote, "main", false, &mut |_| {}).unwrap().new, ahead);
+        assert_eq!(remote_store.get_named_root("main").unwrap(), Some(ahead));
+    }
+
+    #[test]
+    fn test_pull() {
+        let (_tmp, local, remote_store) = stores();
+        let remote = Remote { name: "origin", store: &remote_store };
+        let v1 = put_table(&remote_store, &[b"x", b"y"]);
+        remote_store.set_named_root("main", v1).unwrap();
+
+        let update = pull(&local, remote, "main", false, &mut |_| {}).unwrap();
+        assert_eq!(update.stats.pages_copied, 2);
+        assert_eq!(local.get_named_root("main").unwrap(), Some(v1));
+        assert_eq!(local.get(&v1).unwrap().data, remote_store.get(&v1).unwrap().data);
+
+        // Remote moves ahead: fast-forward
+        let v2 = put_table(&remote_store, &[b"x", b"z"]);
+        remote_store.set_named_root("main", v2).unwrap();
+        assert_eq!(pull(&local, remote, "main", false, &mut |_| {}).unwrap().new, v2);
+
+        // We move ahead: pulling leaves our branch alone
+        let ours = put_table(&local, &[b"local"]);
+        local.set_named_root("main", ours).unwrap();
+        let update = pull(&local, remote, "main", false, &mut |_| {}).unwrap();
+        assert!(update.up_to_date());
+        assert_eq!(local.get_named_root("main").unwrap(), Some(ours));
+
+        // Both move: refused unless forced
+        remote_store.set_named_root("main", v1).unwrap();
+        assert!(matches!(
+            pull(&local, remote, "main", false, &mut |_| {}),
+            Err(SyncError::NonFastForward { ours: o, theirs: t, .. }) if o == ours && t == v1
+        ));
+        assert!(pull(&local, remote, "main", true, &mut |_| {}).unwrap().forced);
+        assert_eq!(local.get_named_root("main").unwrap(), Some(v1));
+    }
+
+    #[test]
+    fn test_fetch_moves_only_the_remote_branch() {
+        let (_tmp, local, remote_store) = stores();
+        let remote = Remote { name: "origin", store: &remote_store };
+        let base = put_table(&local, &[b"base"]);
+        local.set_named_root("main", base).unwrap();
+        push(&local, remote, "main", false, &mut |_| {}).unwrap();
+
+        let theirs = put_table(&remote_store, &[b"base", b"theirs"]);
+        remote_store.set_named_root("main", theirs).unwrap();
+        let update = fetch(&local, remote, "main", &mut |_| {}).unwrap();
+        assert_eq!((update.old, update.new, update.stats.pages_copied), (Some(base), theirs, 1));
+        assert_eq!(local.get_named_root("main").unwrap(), Some(base));
+        assert_eq!(remote_branch(&local, "origin", "main").unwrap(), Some(theirs));
+        assert!(fetch(&local, remote, "main", &mut |_| {}).unwrap().up_to_date());
+        assert!(matches!(fetch(&local, remote, "nope", &mut |_| {}), Err(SyncError::NoSuchBranch(_))));
+
+        // Pulling after a fetch still fast-forwards, copying nothing more
+        let update = pull(&local, remote, "main", false, &mut |_| {}).unwrap();
+        assert_eq!((update.new, update.stats.pages_copied), (theirs, 0));
+        assert_eq!(local.get_named_root("main").unwrap(), Some(theirs));
+
+        remote_store.set_named_root("dev", base).unwrap();
+        fetch(&local, remote, "dev", &mut |_| {}).unwrap();
+        let expected = vec![
+            ("origin".to_string(), "dev".to_string(), base),
+            ("origin".to_string(), "main".to_string(), theirs),
+        ];
+        assert_eq!(remote_branches(&local).unwrap(), expected);
+        remove_remote_branches(&local, "origin").unwrap();
+        assert!(remote_branches(&local).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_commit_history_decides_fast_forward() {
+        let (_tmp, local, remote_store) = stores();
+        let remote = Remote { name: "origin", store: &remote_store };
+        let base = Commit::new(put_table(&local, &[b"base"]), vec![], "base").put(&local).unwrap();
+        local.set_named_root("main", base).unwrap();
+        push(&local, remote, "main", false, &mut |_| {}).unwrap();
+
+        // The remote branch moves on from base without us having synced it
+        let theirs = Commit::new(put_table(&remote_store, &[b"theirs"]), vec![base], "theirs")
+            .put(&remote_store)
+            .unwrap();
+        remote_store.set_named_root("main", theirs).unwrap();
+        local.remove_named_root(&tracking_ref("origin", "main")).unwrap();
+
+        // Pulling fast-forwards, and then pushing a descendant does too
+        assert!(!pull(&local, remote, "main", false, &mut |_| {}).unwrap().forced);
+        local.remove_named_root(&tracking_ref("origin", "main")).unwrap();
+        let ours = Commit::new(put_table(&local, &[b"ours"]), vec![theirs], "ours").put(&local).unwrap();
+        local.set_named_root("main", ours).unwrap();
+        let update = push(&local, remote, "main", false, &mut |_| {}).unwrap();
+        assert_eq!((update.new, update.forced), (ours, false));
+
+        // A commit that doesn't descend from the remote's is refused
+        let other = Commit::new(put_table(&local, &[b"other"]), vec![base], "other").put(&local).unwrap();
+        local.set_named_root("main", other).unwrap();
+        local.remove_named_root(&tracking_ref("origin", "main")).unwrap();
+        assert!(matches!(
+            push(&local, remote, "main", false, &mut |_| {}),
+            Err(SyncError::NonFastForward { .. })
+        ));
+    }
+}
@@ -0,0 +1,447 @@
+//! Partial copies: some tables of a database, not all of it.
+//!
+//! A partial root is a page table over the schema, the chosen tables and
+//! their indexes, and SQLite's own bookkeeping pages (see
+//! [`page_map`](craftsql_core::page_map)). Every other table and index is
+//! emptied: its root page is replaced with an empty b-tree page and the
+//! rest of its pages are left out, so the result opens as an ordinary
+//! database in which those tables have no rows. Only the pages a partial
+//! root keeps are read from the source, and only those the destination
+//! lacks are copied.
+//!
+//! Partial roots are bare page tables: the history of the commits they were
+//! cut from stays behind.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use craftsql_core::{
+    load_page_table, page_map_of, Cid, Page, PageOwner, PageStore, PageStoreError, PageTable, SchemaObject,
+};
+
+use crate::transfer::put_verified;
+use crate::{set_synced, tracking_ref, update_branch, BranchUpdate, Direction, Remote, Result, SyncError, TransferStats};
+
+/// `src` as seen while cutting a partial root: pages of the database are
+/// read from `dst` when it has them, and otherwise fetched from `src` and
+/// copied into `dst` on the way.
+struct Fetch<'a> {
+    src: &'a dyn PageStore,
+    dst: &'a dyn PageStore,
+    /// Pages of the database being cut; anything else is only read.
+    pages: HashSet<Cid>,
+    /// Pages counted in `stats` so far.
+    counted: Mutex<(HashSet<Cid>, TransferStats)>,
+}
+
+impl Fetch<'_> {
+    fn read_only<T>(&self) -> craftsql_core::Result<T> {
+        Err(PageStoreError::Storage("partial copies only read their source".into()))
+    }
+
+    /// Make sure `dst` has the page `cid`, copying it if not.
+    fn fetch(&self, cid: &Cid) -> Result<Option<Page>> {
+        if self.dst.has(cid)? {
+            self.count(cid, None);
+            return Ok(None);
+        }
+        let page = self.src.get(cid)?;
+        put_verified(self.dst, cid, &page)?;
+        self.count(cid, Some(&page));
+        Ok(Some(page))
+    }
+
+    fn count(&self, cid: &Cid, copied: Option<&Page>) {
+        let mut counted = self.counted.lock().unwrap();
+        let (seen, stats) = &mut *counted;
+        if seen.insert(*cid) {
+            match copied {
+                Some(page) => {
+                    stats.pages_copied += 1;
+                    stats.bytes_copied += page.data.len() as u64;
+                }
+                None => stats.pages_skipped += 1,
+            }
+        }
+    }
+
+    fn stats(&self) -> TransferStats {
+        self.counted.lock().unwrap().1.clone()
+    }
+}
+
+impl PageStore for Fetch<'_> {
+    fn get(&self, cid: &Cid) -> craftsql_core::Result<Page> {
+        if !self.pages.contains(cid) {
+            return self.src.get(cid);
+        }
+        let fetched = self.fetch(cid).map_err(|e| match e {
+            SyncError::Store(e) => e,
+            e => PageStoreError::Storage(e.to_string()),
+        })?;
+        match fetched {
+            Some(page) => Ok(page),
+            None => self.dst.get(cid),
+        }
+    }
+
+    fn put(&self, _page: &Page) -> craftsql_core::Result<Cid> {
+        self.read_only()
+    }
+
+    fn has(&self, cid: &Cid) -> craftsql_core::Result<bool> {
+        self.src.has(cid)
+    }
+
+    fn update_root(&self, _new_root: Cid) -> craftsql_core::Result<()> {
+        self.read_only()
+    }
+
+    fn current_root(&self) -> craftsql_core::Result<Option<Cid>> {
+        self.src.current_root()
+    }
+
+    fn set_named_root(&self, _name: &str, _cid: Cid) -> craftsql_core::Result<()> {
+        self.read_only()
+    }
+
+    fn get_named_root(&self, name: &str) -> craftsql_core::Result<Option<Cid>> {
+        self.src.get_named_root(name)
+    }
+
+    fn remove_named_root(&self, _name: &str) -> craftsql_core::Result<bool> {
+        self.read_only()
+    }
+
+    fn list_named_roots(&self) -> craftsql_core::Result<Vec<(String, Cid)>> {
+        self.src.list_named_roots()
+    }
+}
+
+/// An empty b-tree page of the same kind (table or index) as `root`.
+fn empty_btree(root: &[u8], usable: usize) -> Page {
+    let mut data = vec![0; root.len()];
+    // Interior pages become leaves: 2 and 10 are index pages, 5 and 13 table
+    data[0] = if matches!(root.first(), Some(2 | 10)) { 10 } else { 13 };
+    // Cell content starts at the end of the usable space; 65536 is stored as 0
+    data[5..7].copy_from_slice(&(usable as u16).to_be_bytes());
+    Page { data }
+}
+
+/// Put `pages` on the freelist of the database `partial` with first page
+/// `header`. Returns the pages to rewrite, `header` among them: the new
+/// freelist trunks and, under auto-vacuum, the pointer map pages.
+fn free_pages(
+    partial: &mut PageTable,
+    header: &mut [u8],
+    pages: &[u32],
+    usable: usize,
+    fetch: &Fetch<'_>,
+) -> Result<Vec<(u32, Vec<u8>)>> {
+    let be32 = |data: &[u8], at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    let mut rewrite = Vec::new();
+
+    // Each trunk lists as many leaves as every SQLite version accepts
+    let chunks: Vec<&[u32]> = pages.chunks(usable / 4 - 8 + 1).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let next = chunks.get(i + 1).map_or_else(|| be32(header, 32), |next| next[0]);
+        let mut trunk = vec![0; header.len()];
+        trunk[0..4].copy_from_slice(&next.to_be_bytes());
+        trunk[4..8].copy_from_slice(&(chunk.len() as u32 - 1).to_be_bytes());
+        for (j, leaf) in chunk[1..].iter().enumerate() {
+            trunk[8 + 4 * j..12 + 4 * j].copy_from_slice(&leaf.to_be_bytes());
+            partial.entries[*leaf as usize - 1] = None;
+        }
+        rewrite.push((chunk[0], trunk));
+    }
+    let free = be32(header, 36) + pages.len() as u32;
+    header[32..36].copy_from_slice(&pages[0].to_be_bytes());
+    header[36..40].copy_from_slice(&free.to_be_bytes());
+
+    if be32(header, 52) != 0 {
+        // Auto-vacuum: each freed page's pointer map entry becomes "free"
+        let mut maps: HashMap<u32, Vec<u8>> = HashMap::new();
+        for page in pages {
+            let span = usable as u32 / 5 + 1;
+            let map_page = (page - 2) / span * span + 2;
+            let map = match maps.get_mut(&map_page) {
+                Some(map) => map,
+                None => {
+                    let cid = partial.get(map_page as usize - 1).copied();
+                    let data = match cid {
+                        Some(cid) => fetch.get(&cid)?.data,
+                        None => vec![0; header.len()],
+                    };
+                    maps.entry(map_page).or_insert(data)
+                }
+            };
+            let at = 5 * (page - map_page - 1) as usize;
+            map[at..at + 5].copy_from_slice(&[2, 0, 0, 0, 0]);
+        }
+        rewrite.extend(maps);
+    }
+    rewrite.push((1, header.to_vec()));
+    Ok(rewrite)
+}
+
+/// A partial root cut from `root` in `src` and stored in `dst`, with the
+/// tables of `tables` it has.
+fn cut(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    root: &Cid,
+    tables: &[String],
+    stats: &mut TransferStats,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<(Cid, Vec<String>)> {
+    let full = load_page_table(src, root)?;
+    let fetch = Fetch {
+        src,
+        dst,
+        pages: full.entries.iter().flatten().copied().collect(),
+        counted: Mutex::new((HashSet::new(), stats.clone())),
+    };
+    let keep = |object: &SchemaObject| object.table.starts_with("sqlite_") || tables.contains(&object.table);
+    let map = page_map_of(&fetch, root, &keep)?;
+    on_progress(&fetch.stats());
+
+    let mut partial = PageTable { entries: vec![None; full.len()], page_size: full.page_size, meta: full.meta };
+    for (page, owner) in map.iter() {
+        let i = page as usize - 1;
+        let kept = match owner {
+            PageOwner::Object(object) => keep(&map.objects[object]),
+            PageOwner::Freelist | PageOwner::PointerMap => true,
+            PageOwner::LockByte | PageOwner::Unused => false,
+        };
+        if let (true, Some(cid)) = (kept, full.get(i)) {
+            fetch.fetch(cid)?;
+            on_progress(&fetch.stats());
+            partial.entries[i] = Some(*cid);
+        }
+    }
+
+    let Some(first) = full.get(0) else {
+        *stats = fetch.stats();
+        return Ok((dst.put(&Page { data: partial.to_bytes() })?, Vec::new()));
+    };
+    let mut header = fetch.get(first)?.data;
+    let usable = map.page_size - header[20] as usize;
+    let mut emptied = HashSet::new();
+    for object in map.objects.iter().filter(|object| !keep(object)) {
+        let i = object.root_page as usize - 1;
+        if let Some(cid) = full.get(i) {
+            let empty = empty_btree(&src.get(cid)?.data, usable);
+            partial.entries[i] = Some(dst.put(&empty)?);
+            emptied.insert(object.root_page);
+        }
+    }
+
+    // Free every page left out, so the database stays whole
+    let dropped: Vec<u32> = map
+        .iter()
+        .filter(|(page, owner)| *owner == PageOwner::Unused && !emptied.contains(page))
+        .chain(map.iter().filter(|(_, owner)| matches!(owner, PageOwner::Object(object) if !keep(&map.objects[*object]))))
+        .map(|(page, _)| page)
+        .collect();
+    if !dropped.is_empty() {
+        let freed = free_pages(&mut partial, &mut header, &dropped, usable, &fetch)?;
+        for (page, data) in freed {
+            partial.entries[page as usize - 1] = Some(dst.put(&Page { data })?);
+        }
+    }
+
+    *stats = fetch.stats();
+    let cid = dst.put(&Page { data: partial.to_bytes() })?;
+    let present = map
+        .objects
+        .iter()
+        .filter(|object| object.kind == "table" && tables.contains(&object.name))
+        .map(|object| object.name.clone())
+        .collect();
+    Ok((cid, present))
+}
+
+fn check_tables(tables: &[String], present: &HashSet<String>) -> Result<()> {
+    match tables.iter().find(|table| !present.contains(*table)) {
+        Some(table) => Err(SyncError::NoSuchTable(table.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Like [`clone_store`](crate::clone_store), but copying only `tables` of
+/// each database (see the [module docs](self)). Every root is replaced by
+/// its partial root; a table may be missing from some, but not from all.
+pub fn clone_tables(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    tables: &[String],
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<TransferStats> {
+    let current = src.current_root()?;
+    let named: Vec<(String, Cid)> = src.list_named_roots()?
+        .into_iter()
+        .filter(|(name, _)| !name.starts_with('.'))
+        .collect();
+
+    let roots: Vec<Cid> = current.into_iter().chain(named.iter().map(|(_, cid)| *cid)).collect();
+    let mut stats = TransferStats { roots_total: roots.iter().collect::<HashSet<_>>().len(), ..Default::default() };
+    let mut cut_roots: HashMap<Cid, Cid> = HashMap::new();
+    let mut present = HashSet::new();
+    for root in roots {
+        if cut_roots.contains_key(&root) {
+            continue;
+        }
+        let (partial, found) = cut(src, dst, &root, tables, &mut stats, on_progress)?;
+        present.extend(found);
+        cut_roots.insert(root, partial);
+        stats.roots_done += 1;
+        on_progress(&stats);
+    }
+    check_tables(tables, &present)?;
+
+    for (name, cid) in &named {
+        dst.set_named_root(name, cut_roots[cid])?;
+    }
+    if let Some(root) = current {
+        dst.update_root(cut_roots[&root])?;
+    }
+    Ok(stats)
+}
+
+/// Like [`pull`](crate::pull), but fetching only `tables` of the remote's
+/// `branch` (see the [module docs](self)). Our branch is set to the partial
+/// root, and so is its tracking ref, so that later pulls, partial or not,
+/// can tell whether it moved since.
+pub fn pull_tables(
+    local: &dyn PageStore,
+    remote: Remote<'_>,
+    branch: &str,
+    tables: &[String],
+    force: bool,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<BranchUpdate> {
+    let theirs = remote.store.get_named_root(branch)?
+        .ok_or_else(|| SyncError::NoSuchBranch(branch.to_string()))?;
+    let synced = local.get_named_root(&tracking_ref(remote.name, branch))?;
+
+    let mut stats = TransferStats { roots_total: 1, ..Default::default() };
+    let (partial, present) = cut(remote.store, local, &theirs, tables, &mut stats, on_progress)?;
+    check_tables(tables, &present.into_iter().collect())?;
+    stats.roots_done = 1;
+    on_progress(&stats);
+
+    // Everything is in place, so this only decides whether to move the branch
+    let mut nothing_to_copy = |_: &[Cid]| Ok(TransferStats::default());
+    let mut update = update_branch(local, local, Direction::Pull, branch, partial, synced, force, &mut nothing_to_copy)?;
+    update.stats = stats;
+    set_synced(local, remote.name, branch, partial)?;
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_core::import_sqlite;
+    use craftsql_store_local::LocalPageStore;
+    use rusqlite::Connection;
+
+    fn integrity_check(path: &std::path::Path) -> String {
+        let db = Connection::open(path).unwrap();
+        db.query_row("PRAGMA integrity_check", [], |r| r.get(0)).unwrap()
+    }
+
+    fn count(path: &std::path::Path, table: &str) -> i64 {
+        let db = Connection::open(path).unwrap();
+        db.query_row(&format!("SELECT count(*) FROM {}", table), [], |r| r.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_pull_tables() {
+        let tmp = tempfile::tempdir().unwrap();
+        let local = LocalPageStore::new(&tmp.path().join("local")).unwrap();
+        let remote_store = LocalPageStore::new(&tmp.path().join("remote")).unwrap();
+        let remote = Remote { name: "origin", store: &remote_store };
+
+        let file = tmp.path().join("shared.db");
+        let db = Connection::open(&file).unwrap();
+        db.execute_batch("
+            CREATE TABLE small (id INTEGER PRIMARY KEY, name TEXT);
+            CREATE INDEX small_name ON small (name);
+            CREATE TABLE big (body BLOB);
+            INSERT INTO small VALUES (1, 'one'), (2, 'two');
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 500)
+                INSERT INTO big SELECT randomblob(2000) FROM n;
+        ").unwrap();
+        let v1 = import_sqlite(&remote_store, &file, "v1").unwrap();
+        remote_store.set_named_root("main", v1.commit).unwrap();
+
+        let tables = ["small".to_string()];
+        let update = pull_tables(&local, remote, "main", &tables, false, &mut |_| {}).unwrap();
+        assert!(update.stats.pages_copied < 10, "copied {} pages", update.stats.pages_copied);
+        let partial = local.get_named_root("main").unwrap().unwrap();
+        assert_eq!(update.new, partial);
+        assert_eq!(local.get_named_root(&tracking_ref("origin", "main")).unwrap(), Some(partial));
+
+        // The partial database opens as usual, with `big` empty
+        let copy = tmp.path().join("copy.db");
+        craftsql_core::export_sqlite(&local, &partial, &copy).unwrap();
+        assert_eq!((count(&copy, "small"), count(&copy, "big")), (2, 0));
+        assert_eq!(integrity_check(&copy), "ok");
+        let name: i64 = Connection::open(&copy)
+            .unwrap()
+            .query_row("SELECT id FROM small INDEXED BY small_name WHERE name = 'two'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(name, 2);
+
+        // A later pull only fetches what changed
+        db.execute("INSERT INTO small VALUES (3, 'three')", []).unwrap();
+        let v2 = import_sqlite(&remote_store, &file, "v2").unwrap();
+        remote_store.set_named_root("main", v2.commit).unwrap();
+        let update = pull_tables(&local, remote, "main", &tables, false, &mut |_| {}).unwrap();
+        assert_eq!(update.old, Some(partial));
+        assert!(update.stats.pages_copied <= 3, "copied {} pages", update.stats.pages_copied);
+        craftsql_core::export_sqlite(&local, &update.new, &copy).unwrap();
+        assert_eq!(count(&copy, "small"), 3);
+
+        assert!(matches!(
+            pull_tables(&local, remote, "main", &["nope".to_string()], false, &mut |_| {}),
+            Err(SyncError::NoSuchTable(_))
+        ));
+    }
+
+    #[test]
+    fn test_clone_tables() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = LocalPageStore::new(&tmp.path().join("src")).unwrap();
+        let dst = LocalPageStore::new(&tmp.path().join("dst")).unwrap();
+        let file = tmp.path().join("shared.db");
+        let db = Connection::open(&file).unwrap();
+        db.execute_batch("
+            PRAGMA auto_vacuum = FULL;
+            CREATE TABLE a (x);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300)
+                INSERT INTO a SELECT randomblob(1000) FROM n;
+        ").unwrap();
+        let v1 = import_sqlite(&src, &file, "v1").unwrap();
+        db.execute_batch("CREATE TABLE b (x); INSERT INTO b VALUES (2);").unwrap();
+        let v2 = import_sqlite(&src, &file, "v2").unwrap();
+        src.set_named_root("old", v1.commit).unwrap();
+        src.update_root(v2.page_table).unwrap();
+
+        // `b` is only in one of the roots, which is enough
+        let stats = clone_tables(&src, &dst, &["b".to_string()], &mut |_| {}).unwrap();
+        assert_eq!((stats.roots_done, stats.roots_total), (2, 2));
+        let copy = tmp.path().join("copy.db");
+        craftsql_core::export_sqlite(&dst, &dst.current_root().unwrap().unwrap(), &copy).unwrap();
+        assert_eq!((count(&copy, "a"), count(&copy, "b")), (0, 1));
+        // `a`'s pages are free, and the pointer maps say so
+        assert_eq!(integrity_check(&copy), "ok");
+        craftsql_core::export_sqlite(&dst, &dst.get_named_root("old").unwrap().unwrap(), &copy).unwrap();
+        assert_eq!(count(&copy, "a"), 0);
+
+        assert!(matches!(
+            clone_tables(&src, &dst, &["c".to_string()], &mut |_| {}),
+            Err(SyncError::NoSuchTable(_))
+        ));
+    }
+}
@@ -0,0 +1,715 @@
+//! Push and pull over any byte stream, for stores that can't reach each
+//! other's [`PageStore`] directly.
+//!
+//! A session is between a server, serving one store with [`serve`], and a
+//! client. The server opens by advertising its branches. Then, to fetch
+//! ([`fetch_over`]), the client sends the roots it wants and the roots it
+//! already has; to push ([`push_over`]), the client takes the server's
+//! branches as what it has. Either way the sending side offers every page
+//! the wanted roots reach that the other's roots don't, the receiving side
+//! wants back those it lacks, and the sender packs them up. A push ends
+//! with the client moving the server's branch, which the server refuses
+//! to force if the branch is protected there.
+//!
+//! That's two round trips after the advertisement, however long the
+//! history. Pages are offered and packed children first, so a store never
+//! holds a page table or commit without the pages it points at.
+//!
+//! On the wire each [`Message`] is a frame: its length as a little-endian
+//! `u32`, a tag byte, then its fields.
+
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+use craftsql_core::{force_update, is_ancestor, Cid, Commit, Page, PageStore, PageStoreError, PageTable};
+
+use crate::transfer::put_verified;
+use crate::{BranchUpdate, Result, SyncError, TransferStats};
+
+/// Protocol version, sent with the server's advertisement.
+pub const VERSION: u8 = 1;
+
+/// Largest frame either side accepts.
+const MAX_FRAME: usize = 64 << 20;
+
+/// Page bytes the sender puts in one [`Message::Pack`].
+const PACK_BYTES: usize = 1 << 20;
+
+const TAG_REFS: u8 = 1;
+const TAG_WANT: u8 = 2;
+const TAG_HAVE: u8 = 3;
+const TAG_OFFER: u8 = 4;
+const TAG_PACK: u8 = 5;
+const TAG_UPDATE: u8 = 6;
+const TAG_DONE: u8 = 7;
+const TAG_ERROR: u8 = 8;
+
+/// A protocol message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// The server's protocol version and branches, opening a session and
+    /// answering a push's updates.
+    Refs { version: u8, refs: Vec<(String, Cid)> },
+    /// Roots to fetch, or after an offer, the offered pages to send.
+    Want(Vec<Cid>),
+    /// Roots the fetching side already has, with everything they reach.
+    Have(Vec<Cid>),
+    /// Pages the sender would send, children first.
+    Offer(Vec<Cid>),
+    /// Wanted pages, in the order they were offered.
+    Pack(Vec<Vec<u8>>),
+    /// Move the server's branch `name` to `new` if it's still at `old`.
+    Update { name: String, old: Option<Cid>, new: Cid },
+    /// The end of a session, of a pack, or of a push's updates.
+    Done,
+    /// Why the other side gave up.
+    Error(String),
+}
+
+impl Message {
+    /// Write this message as one frame and flush it.
+    pub fn write_to(&self, w: &mut dyn Write) -> Result<()> {
+        let body = self.encode();
+        let len = u32::try_from(body.len()).ok().filter(|&len| len as usize <= MAX_FRAME)
+            .ok_or_else(|| protocol(format!("{} byte message is too large to send", body.len())))?;
+        w.write_all(&len.to_le_bytes())
+            .and_then(|()| w.write_all(&body))
+            .and_then(|()| w.flush())
+            .map_err(|e| PageStoreError::network("sync connection", e))?;
+        Ok(())
+    }
+
+    /// Read one frame.
+    pub fn read_from(r: &mut dyn Read) -> Result<Self> {
+        let mut len = [0u8; 4];
+        r.read_exact(&mut len).map_err(|e| PageStoreError::network("sync connection", e))?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_FRAME {
+            return Err(protocol(format!("{} byte message is too large", len)));
+        }
+        let mut body = vec![0u8; len];
+        r.read_exact(&mut body).map_err(|e| PageStoreError::network("sync connection", e))?;
+        Self::decode(&body)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Message::Refs { version, refs } => {
+                out.extend([TAG_REFS, *version]);
+                put_len(&mut out, refs.len());
+                for (name, cid) in refs {
+                    put_str(&mut out, name);
+                    out.extend(cid.0);
+                }
+            }
+            Message::Want(cids) => put_cids(&mut out, TAG_WANT, cids),
+            Message::Have(cids) => put_cids(&mut out, TAG_HAVE, cids),
+            Message::Offer(cids) => put_cids(&mut out, TAG_OFFER, cids),
+            Message::Pack(pages) => {
+                out.push(TAG_PACK);
+                put_len(&mut out, pages.len());
+                for data in pages {
+                    put_len(&mut out, data.len());
+                    out.extend(data);
+                }
+            }
+            Message::Update { name, old, new } => {
+                out.push(TAG_UPDATE);
+                put_str(&mut out, name);
+                match old {
+                    Some(old) => {
+                        out.push(1);
+                        out.extend(old.0);
+                    }
+                    None => out.push(0),
+                }
+                out.extend(new.0);
+            }
+            Message::Done => out.push(TAG_DONE),
+            Message::Error(message) => {
+                out.push(TAG_ERROR);
+                put_str(&mut out, message);
+            }
+        }
+        out
+    }
+
+    fn decode(body: &[u8]) -> Result<Self> {
+        let mut body = Body(body);
+        let message = match body.u8()? {
+            TAG_REFS => {
+                let version = body.u8()?;
+                let count = body.len(36)?;
+                let mut refs = Vec::with_capacity(count);
+                for _ in 0..count {
+                    refs.push((body.str()?, body.cid()?));
+                }
+                Message::Refs { version, refs }
+            }
+            TAG_WANT => Message::Want(body.cids()?),
+            TAG_HAVE => Message::Have(body.cids()?),
+            TAG_OFFER => Message::Offer(body.cids()?),
+            TAG_PACK => {
+                let count = body.len(4)?;
+                let mut pages = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let len = body.len(1)?;
+                    pages.push(body.take(len)?.to_vec());
+                }
+                Message::Pack(pages)
+            }
+            TAG_UPDATE => {
+                let name = body.str()?;
+                let old = match body.u8()? {
+                    0 => None,
+                    1 => Some(body.cid()?),
+                    flag => return Err(protocol(format!("bad update flag {}", flag))),
+                };
+                Message::Update { name, old, new: body.cid()? }
+            }
+            TAG_DONE => Message::Done,
+            TAG_ERROR => Message::Error(body.str()?),
+            tag => return Err(protocol(format!("unknown message tag {}", tag))),
+        };
+        if !body.0.is_empty() {
+            return Err(protocol(format!("{} stray bytes after message", body.0.len())));
+        }
+        Ok(message)
+    }
+}
+
+fn put_len(out: &mut Vec<u8>, len: usize) {
+    out.extend((len as u32).to_le_bytes());
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_len(out, s.len());
+    out.extend(s.as_bytes());
+}
+
+fn put_cids(out: &mut Vec<u8>, tag: u8, cids: &[Cid]) {
+    out.push(tag);
+    put_len(out, cids.len());
+    for cid in cids {
+        out.extend(cid.0);
+    }
+}
+
+/// The unread rest of a frame.
+struct Body<'a>(&'a [u8]);
+
+impl<'a> Body<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(protocol("truncated message".to_string()));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// A length or count, checked against what's left of the frame when
+    /// each item takes at least `min_size` bytes.
+    fn len(&mut self, min_size: usize) -> Result<usize> {
+        let len = u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize;
+        if len.saturating_mul(min_size) > self.0.len() {
+            return Err(protocol("truncated message".to_string()));
+        }
+        Ok(len)
+    }
+
+    fn cid(&mut self) -> Result<Cid> {
+        Ok(Cid(self.take(32)?.try_into().unwrap()))
+    }
+
+    fn cids(&mut self) -> Result<Vec<Cid>> {
+        let count = self.len(32)?;
+        (0..count).map(|_| self.cid()).collect()
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.len(1)?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| protocol("name is not UTF-8".to_string()))
+    }
+}
+
+fn protocol(message: String) -> SyncError {
+    SyncError::Protocol(message)
+}
+
+fn unexpected(message: &Message) -> SyncError {
+    match message {
+        Message::Error(message) => protocol(format!("the other side gave up: {}", message)),
+        message => protocol(format!("unexpected {:?}", message)),
+    }
+}
+
+/// Serve `store` for one session on `conn`: a fetch, a push, or nothing.
+///
+/// Fetches get only pages reachable from the advertised branches, and
+/// pushes move a branch only if it's still where the client saw it and
+/// its new root arrived.
+pub fn serve<C: Read + Write>(store: &dyn PageStore, conn: &mut C) -> Result<()> {
+    let refs = advertised(store)?;
+    Message::Refs { version: VERSION, refs: refs.clone() }.write_to(conn)?;
+    match Message::read_from(conn)? {
+        Message::Done => Ok(()),
+        Message::Want(roots) => {
+            if let Some(root) = roots.iter().find(|root| !refs.iter().any(|(_, cid)| cid == *root)) {
+                let message = format!("{} is not an advertised branch", root);
+                Message::Error(message.clone()).write_to(conn)?;
+                return Err(protocol(message));
+            }
+            let haves = match Message::read_from(conn)? {
+                Message::Have(haves) => haves,
+                message => return Err(unexpected(&message)),
+            };
+            let offer = missing_pages(store, &roots, &haves)?;
+            send_pages(store, conn, offer, &mut TransferStats::default())
+        }
+        Message::Offer(offer) => {
+            receive_pages(store, conn, offer, &mut TransferStats::default(), &mut |_| {})?;
+            loop {
+                match Message::read_from(conn)? {
+                    Message::Update { name, old, new } => {
+                        if !name.starts_with('.') && store.get_named_root(&name)? == old && store.has(&new)? {
+                            // A protected branch stays put; the client sees so in the refs
+                            match force_update(store, &name, new) {
+                                Err(PageStoreError::Protected(_)) => {}
+                                result => {
+                                    result?;
+                                }
+                            }
+                        }
+                    }
+                    Message::Done => break,
+                    message => return Err(unexpected(&message)),
+                }
+            }
+            Message::Refs { version: VERSION, refs: advertised(store)? }.write_to(conn)
+        }
+        message => Err(unexpected(&message)),
+    }
+}
+
+/// Bring the server's `branch` and the pages we lack over `conn`, leaving
+/// our refs alone. Returns the branch's root, whose pages are all here.
+pub fn fetch_over<C: Read + Write>(
+    local: &dyn PageStore,
+    conn: &mut C,
+    branch: &str,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<(Cid, TransferStats)> {
+    let refs = read_refs(conn)?;
+    let Some(theirs) = find(&refs, branch) else {
+        Message::Done.write_to(conn)?;
+        return Err(SyncError::NoSuchBranch(branch.to_string()));
+    };
+    let mut stats = TransferStats { roots_total: 1, ..TransferStats::default() };
+    // Roots are stored after their pages, so having one means having them all
+    if !local.has(&theirs)? {
+        let mut haves: HashSet<Cid> = local.list_named_roots()?.into_iter().map(|(_, cid)| cid).collect();
+        haves.extend(local.current_root()?);
+        Message::Want(vec![theirs]).write_to(conn)?;
+        Message::Have(haves.into_iter().collect()).write_to(conn)?;
+        let offer = match Message::read_from(conn)? {
+            Message::Offer(offer) => offer,
+            message => return Err(unexpected(&message)),
+        };
+        receive_pages(local, conn, offer, &mut stats, on_progress)?;
+    } else {
+        Message::Done.write_to(conn)?;
+    }
+    stats.roots_done = 1;
+    on_progress(&stats);
+    Ok((theirs, stats))
+}
+
+/// Send our `branch` and the pages the server lacks over `conn`, then move
+/// the server's branch to match ours. Unless `force`, the server's branch
+/// must be an ancestor of ours.
+pub fn push_over<C: Read + Write>(
+    local: &dyn PageStore,
+    conn: &mut C,
+    branch: &str,
+    force: bool,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<BranchUpdate> {
+    let ours = match local.get_named_root(branch)? {
+        Some(ours) => ours,
+        None => {
+            // Still end the session the server opened
+            read_refs(conn)?;
+            Message::Done.write_to(conn)?;
+            return Err(SyncError::NoSuchBranch(branch.to_string()));
+        }
+    };
+    let refs = read_refs(conn)?;
+    let old = find(&refs, branch);
+    let mut update = BranchUpdate {
+        branch: branch.to_string(),
+        old,
+        new: ours,
+        forced: false,
+        stats: TransferStats { roots_total: 1, ..TransferStats::default() },
+    };
+    if let Some(old) = old.filter(|&old| old != ours) {
+        if !(local.has(&old)? && is_ancestor(local, old, ours)?) {
+            if !force {
+                Message::Done.write_to(conn)?;
+                return Err(SyncError::NonFastForward { branch: branch.to_string(), ours, theirs: old });
+            }
+            update.forced = true;
+        }
+    }
+    if update.up_to_date() {
+        Message::Done.write_to(conn)?;
+        return Ok(update);
+    }
+
+    let haves: Vec<Cid> = refs.iter().map(|(_, cid)| *cid).collect();
+    let offer = missing_pages(local, &[ours], &haves)?;
+    let offered = offer.len() as u64;
+    send_pages(local, conn, offer, &mut update.stats)?;
+    update.stats.pages_skipped = offered - update.stats.pages_copied;
+    Message::Update { name: branch.to_string(), old, new: ours }.write_to(conn)?;
+    Message::Done.write_to(conn)?;
+
+    match find(&read_refs(conn)?, branch) {
+        Some(now) if now == ours => {}
+        // Forcing it is all the server refuses
+        Some(now) if update.forced && Some(now) == old => {
+            return Err(PageStoreError::Protected(branch.to_string()).into());
+        }
+        Some(now) => return Err(SyncError::NonFastForward { branch: branch.to_string(), ours, theirs: now }),
+        None => return Err(protocol(format!("the server didn't take {}", branch))),
+    }
+    update.stats.roots_done = 1;
+    on_progress(&update.stats);
+    Ok(update)
+}
+
+/// Branches a server advertises: its named roots but for bookkeeping ones.
+fn advertised(store: &dyn PageStore) -> Result<Vec<(String, Cid)>> {
+    let mut refs = store.list_named_roots()?;
+    refs.retain(|(name, _)| !name.starts_with('.'));
+    Ok(refs)
+}
+
+fn read_refs(conn: &mut dyn Read) -> Result<Vec<(String, Cid)>> {
+    match Message::read_from(conn)? {
+        Message::Refs { version: VERSION, refs } => Ok(refs),
+        Message::Refs { version, .. } => Err(protocol(format!("unsupported protocol version {}", version))),
+        message => Err(unexpected(&message)),
+    }
+}
+
+fn find(refs: &[(String, Cid)], branch: &str) -> Option<Cid> {
+    refs.iter().find(|(name, _)| name == branch).map(|(_, cid)| *cid)
+}
+
+/// Pages reachable from `wants` in `store` but not from the `haves` it
+/// also has, children first.
+///
+/// A have rules out itself, the history behind it, and its own page
+/// table's pages, but not older tables' pages: the receiver drops any of
+/// those it has from the offer.
+fn missing_pages(store: &dyn PageStore, wants: &[Cid], haves: &[Cid]) -> Result<Vec<Cid>> {
+    let mut tips = HashSet::new();
+    for have in haves {
+        if store.has(have)? {
+            tips.insert(*have);
+        }
+    }
+    // The haves' history, and their tables' pages
+    let mut known = HashSet::new();
+    let mut queue: Vec<Cid> = tips.iter().copied().collect();
+    let mut tables = Vec::new();
+    while let Some(cid) = queue.pop() {
+        if !known.insert(cid) {
+            continue;
+        }
+        let page = store.get(&cid)?;
+        if let Some(commit) = Commit::from_bytes(&page.data) {
+            if tips.contains(&cid) {
+                tables.push(commit.root);
+            }
+            for parent in commit.parents {
+                if store.has(&parent)? {
+                    queue.push(parent);
+                }
+            }
+        } else {
+            tables.push(cid);
+        }
+    }
+    for table in tables {
+        if !store.has(&table)? {
+            continue;
+        }
+        known.insert(table);
+        if let Ok(table) = PageTable::from_bytes(&store.get(&table)?.data) {
+            known.extend(table.entries.into_iter().flatten());
+        }
+    }
+
+    enum Step {
+        Visit(Cid),
+        Emit(Cid),
+    }
+    let mut offer = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack: Vec<Step> = wants.iter().rev().map(|&cid| Step::Visit(cid)).collect();
+    while let Some(step) = stack.pop() {
+        let cid = match step {
+            Step::Emit(cid) => {
+                offer.push(cid);
+                continue;
+            }
+            Step::Visit(cid) => cid,
+        };
+        if known.contains(&cid) || !seen.insert(cid) {
+            continue;
+        }
+        let page = store.get(&cid)?;
+        stack.push(Step::Emit(cid));
+        if let Some(commit) = Commit::from_bytes(&page.data) {
+            stack.extend(commit.parents.into_iter().rev().map(Step::Visit));
+            stack.push(Step::Visit(commit.root));
+        } else if let Ok(table) = PageTable::from_bytes(&page.data) {
+            // Data pages are leaves: offer them straight away
+            for data in table.entries.into_iter().flatten() {
+                if !known.contains(&data) && seen.insert(data) {
+                    offer.push(data);
+                }
+            }
+        }
+    }
+    Ok(offer)
+}
+
+/// The sender's side once `offer` is sent: read which pages the receiver
+/// wants and pack them up.
+fn send_pages(
+    store: &dyn PageStore,
+    conn: &mut (impl Read + Write),
+    offer: Vec<Cid>,
+    stats: &mut TransferStats,
+) -> Result<()> {
+    Message::Offer(offer.clone()).write_to(conn)?;
+    let wanted = match Message::read_from(conn)? {
+        Message::Want(wanted) => wanted,
+        message => return Err(unexpected(&message)),
+    };
+    let offered: HashSet<Cid> = offer.into_iter().collect();
+    if let Some(cid) = wanted.iter().find(|cid| !offered.contains(cid)) {
+        let message = format!("{} was not offered", cid);
+        Message::Error(message.clone()).write_to(conn)?;
+        return Err(protocol(message));
+    }
+
+    let (mut pack, mut bytes) = (Vec::new(), 0);
+    for cid in &wanted {
+        let page = store.get(cid)?;
+        stats.pages_copied += 1;
+        stats.bytes_copied += page.data.len() as u64;
+        bytes += page.data.len();
+        pack.push(page.data);
+        if bytes >= PACK_BYTES {
+            Message::Pack(std::mem::take(&mut pack)).write_to(conn)?;
+            bytes = 0;
+        }
+    }
+    if !pack.is_empty() {
+        Message::Pack(pack).write_to(conn)?;
+    }
+    Message::Done.write_to(conn)
+}
+
+/// The receiver's side once `offer` arrives: want the pages we lack and
+/// store them as they come.
+fn receive_pages(
+    store: &dyn PageStore,
+    conn: &mut (impl Read + Write),
+    offer: Vec<Cid>,
+    stats: &mut TransferStats,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<()> {
+    let mut wanted = Vec::new();
+    for cid in offer {
+        if store.has(&cid)? {
+            stats.pages_skipped += 1;
+        } else {
+            wanted.push(cid);
+        }
+    }
+    Message::Want(wanted.clone()).write_to(conn)?;
+    let mut wanted = wanted.into_iter();
+    loop {
+        match Message::read_from(conn)? {
+            Message::Pack(pages) => {
+                for data in pages {
+                    let cid = wanted.next().ok_or_else(|| protocol("unwanted page in pack".to_string()))?;
+                    stats.bytes_copied += data.len() as u64;
+                    put_verified(store, &cid, &Page { data })?;
+                    stats.pages_copied += 1;
+                    on_progress(stats);
+                }
+            }
+            Message::Done => break,
+            message => return Err(unexpected(&message)),
+        }
+    }
+    if wanted.next().is_some() {
+        return Err(protocol("pack ended before every wanted page".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_testing::put_table;
+    use craftsql_store_local::LocalPageStore;
+    use std::net::{TcpListener, TcpStream};
+
+    /// Run `client` against [`serve`] for `store` over a loopback socket.
+    fn session<T>(store: &dyn PageStore, client: impl FnOnce(&mut TcpStream) -> T) -> (T, Result<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::scope(|s| {
+            let server = s.spawn(move || serve(store, &mut listener.accept().unwrap().0));
+            let result = client(&mut TcpStream::connect(addr).unwrap());
+            (result, server.join().unwrap())
+        })
+    }
+
+    fn stores() -> (tempfile::TempDir, LocalPageStore, LocalPageStore) {
+        let tmp = tempfile::tempdir().unwrap();
+        let local = LocalPageStore::new(&tmp.path().join("local")).unwrap();
+        let remote = LocalPageStore::new(&tmp.path().join("remote")).unwrap();
+        (tmp, local, remote)
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        let cid = Cid::from_bytes(b"page");
+        for message in [
+            Message::Refs { version: VERSION, refs: vec![("main".into(), cid), ("dev".into(), cid)] },
+            Message::Want(vec![cid, cid]),
+            Message::Have(vec![]),
+            Message::Offer(vec![cid]),
+            Message::Pack(vec![b"page".to_vec(), vec![]]),
+            Message::Update { name: "main".into(), old: None, new: cid },
+            Message::Update { name: "main".into(), old: Some(cid), new: cid },
+            Message::Done,
+            Message::Error("no".into()),
+        ] {
+            let mut wire = Vec::new();
+            message.write_to(&mut wire).unwrap();
+            assert_eq!(Message::read_from(&mut wire.as_slice()).unwrap(), message);
+
+            // Cut short, or with a count past the end of the frame
+            let mut short = wire[..wire.len() - 1].to_vec();
+            short[..4].copy_from_slice(&(wire.len() as u32 - 5).to_le_bytes());
+            assert!(Message::read_from(&mut short.as_slice()).is_err());
+        }
+        let mut wire = Vec::new();
+        Message::Want(vec![cid]).write_to(&mut wire).unwrap();
+        wire[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(Message::read_from(&mut wire.as_slice()), Err(SyncError::Protocol(_))));
+        assert!(matches!(Message::read_from(&mut &[9, 0, 0, 0][..]), Err(SyncError::Store(_))));
+    }
+
+    #[test]
+    fn test_fetch_over_sends_only_missing_pages() {
+        let (_tmp, local, remote) = stores();
+        let first = Commit::new(put_table(&remote, &[b"a", b"b"]), vec![], "first").put(&remote).unwrap();
+        remote.set_named_root("main", first).unwrap();
+        remote.set_named_root(".private", put_table(&remote, &[b"secret"])).unwrap();
+
+        let ((root, stats), served) = session(&remote, |conn| fetch_over(&local, conn, "main", &mut |_| {}).unwrap());
+        served.unwrap();
+        assert_eq!(root, first);
+        assert_eq!((stats.pages_copied, stats.roots_done), (4, 1));
+        local.set_named_root("origin.main", first).unwrap();
+
+        // Only the new commit, its table, and the changed page come over
+        let second = Commit::new(put_table(&remote, &[b"a", b"c"]), vec![first], "second").put(&remote).unwrap();
+        remote.set_named_root("main", second).unwrap();
+        let ((root, stats), served) = session(&remote, |conn| fetch_over(&local, conn, "main", &mut |_| {}).unwrap());
+        served.unwrap();
+        assert_eq!((root, stats.pages_copied, stats.pages_skipped), (second, 3, 0));
+        assert_eq!(Commit::load(&local, &second).unwrap().unwrap().parents, vec![first]);
+
+        // Up to date: nothing offered
+        let ((_, stats), served) = session(&remote, |conn| fetch_over(&local, conn, "main", &mut |_| {}).unwrap());
+        served.unwrap();
+        assert_eq!(stats.pages_copied, 0);
+
+        let (fetched, served) = session(&remote, |conn| fetch_over(&local, conn, ".private", &mut |_| {}));
+        assert!(matches!(fetched, Err(SyncError::NoSuchBranch(_))));
+        served.unwrap();
+    }
+
+    #[test]
+    fn test_serve_refuses_unadvertised_roots() {
+        let (_tmp, _, remote) = stores();
+        let secret = put_table(&remote, &[b"secret"]);
+        remote.set_named_root(".private", secret).unwrap();
+        let (reply, served) = session(&remote, |conn| {
+            Message::read_from(conn).unwrap();
+            Message::Want(vec![secret]).write_to(conn).unwrap();
+            Message::read_from(conn).unwrap()
+        });
+        assert!(matches!(reply, Message::Error(_)));
+        assert!(matches!(served, Err(SyncError::Protocol(_))));
+    }
+
+    #[test]
+    fn test_push_over() {
+        let (_tmp, local, remote) = stores();
+        let first = Commit::new(put_table(&local, &[b"a", b"b"]), vec![], "first").put(&local).unwrap();
+        local.set_named_root("main", first).unwrap();
+        let (update, served) = session(&remote, |conn| push_over(&local, conn, "main", false, &mut |_| {}).unwrap());
+        served.unwrap();
+        assert_eq!((update.old, update.new, update.stats.pages_copied), (None, first, 4));
+        assert_eq!(remote.get_named_root("main").unwrap(), Some(first));
+
+        let second = Commit::new(put_table(&local, &[b"a", b"c"]), vec![first], "second").put(&local).unwrap();
+        local.set_named_root("main", second).unwrap();
+        let (update, served) = session(&remote, |conn| push_over(&local, conn, "main", false, &mut |_| {}).unwrap());
+        served.unwrap();
+        assert_eq!((update.old, update.stats.pages_copied), (Some(first), 3));
+        assert_eq!(remote.get_named_root("main").unwrap(), Some(second));
+        let (update, _) = session(&remote, |conn| push_over(&local, conn, "main", false, &mut |_| {}).unwrap());
+        assert!(update.up_to_date());
+
+        // The server moved on: refused unless forced
+        let theirs = Commit::new(put_table(&remote, &[b"theirs"]), vec![second], "theirs").put(&remote).unwrap();
+        remote.set_named_root("main", theirs).unwrap();
+        let (pushed, served) = session(&remote, |conn| push_over(&local, conn, "main", false, &mut |_| {}));
+        served.unwrap();
+        assert!(matches!(pushed, Err(SyncError::NonFastForward { theirs: t, .. }) if t == theirs));
+        assert_eq!(remote.get_named_root("main").unwrap(), Some(theirs));
+        craftsql_core::set_protected(&remote, "main", true).unwrap();
+        let (pushed, served) = session(&remote, |conn| push_over(&local, conn, "main", true, &mut |_| {}));
+        served.unwrap();
+        assert!(matches!(pushed, Err(SyncError::Store(PageStoreError::Protected(_)))));
+        assert_eq!(remote.get_named_root("main").unwrap(), Some(theirs));
+        craftsql_core::set_protected(&remote, "main", false).unwrap();
+        let (update, served) = session(&remote, |conn| push_over(&local, conn, "main", true, &mut |_| {}).unwrap());
+        served.unwrap();
+        assert!(update.forced);
+        assert_eq!(remote.get_named_root("main").unwrap(), Some(second));
+
+        let (pushed, served) = session(&remote, |conn| push_over(&local, conn, "nope", false, &mut |_| {}));
+        assert!(matches!(pushed, Err(SyncError::NoSuchBranch(_))));
+        served.unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+//! Shallow copies: the latest version of each branch, without its history.
+//!
+//! A shallow copy of a commit brings over the commit and its page table but
+//! not its parents, so copying a long-lived database costs what one version
+//! of it takes. The destination marks each commit whose parents it lacks
+//! with a [`shallow_ref`] named root; walks through its history stop there
+//! (as in [`is_ancestor`](craftsql_core::is_ancestor)) until [`unshallow`]
+//! fetches the rest.
+//!
+//! Later copies into a shallow store stay shallow below the marked commits:
+//! a commit the destination already has is never copied again, parents
+//! included. Roots that aren't commits have no history to leave behind and
+//! are copied as usual.
+
+use std::collections::HashSet;
+
+use craftsql_core::{Cid, Commit, PageStore};
+
+use crate::transfer::{clone_with, copy_root, copy_roots, put_verified};
+use crate::{pull_with, BranchUpdate, Remote, Result, TransferStats};
+
+/// Prefix of the named roots marking commits whose parents weren't copied.
+pub const SHALLOW_PREFIX: &str = ".shallow.";
+
+/// Named root marking `commit` as shallow; it points at the commit itself.
+pub fn shallow_ref(commit: &Cid) -> String {
+    format!("{}{}", SHALLOW_PREFIX, commit.to_hex())
+}
+
+/// The commits in `store` whose parents were left out by a shallow copy.
+pub fn shallow_commits(store: &dyn PageStore) -> Result<Vec<Cid>> {
+    Ok(store.list_named_roots()?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(SHALLOW_PREFIX))
+        .map(|(_, cid)| cid)
+        .collect())
+}
+
+/// Like [`copy_roots`], but copying only the commits in `roots`, not their
+/// parents.
+pub(crate) fn copy_shallow(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    roots: &[Cid],
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<TransferStats> {
+    let mut seen = HashSet::new();
+    let roots: Vec<&Cid> = roots.iter().filter(|cid| seen.insert(**cid)).collect();
+
+    let mut stats = TransferStats { roots_total: roots.len(), ..Default::default() };
+    for root in roots {
+        let page = if dst.has(root)? { None } else { Some(src.get(root)?) };
+        match page.as_ref().and_then(|page| Commit::from_bytes(&page.data).map(|commit| (page, commit))) {
+            Some((page, commit)) => {
+                copy_root(src, dst, &commit.root, &mut stats, on_progress)?;
+                let mut complete = true;
+                for parent in &commit.parents {
+                    complete &= dst.has(parent)?;
+                }
+                // Marked before the commit lands, so that a copy interrupted
+                // in between can't leave an unmarked hole in the history
+                if !complete {
+                    dst.set_named_root(&shallow_ref(root), *root)?;
+                }
+                put_verified(dst, root, page)?;
+            }
+            None => copy_root(src, dst, root, &mut stats, on_progress)?,
+        }
+        stats.roots_done += 1;
+        on_progress(&stats);
+    }
+    Ok(stats)
+}
+
+/// Like [`clone_store`](crate::clone_store), but leaving out the history of
+/// every commit copied.
+pub fn clone_shallow(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<TransferStats> {
+    clone_with(src, dst, copy_shallow, on_progress)
+}
+
+/// Like [`pull`](crate::pull), but leaving out the history of the remote's
+/// branch.
+pub fn pull_shallow(
+    local: &dyn PageStore,
+    remote: Remote<'_>,
+    branch: &str,
+    force: bool,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<BranchUpdate> {
+    pull_with(local, remote, branch, force, copy_shallow, on_progress)
+}
+
+/// Fetch the history shallow copies left out of `local` from `src`, which
+/// must have it, and clear the shallow markers.
+pub fn unshallow(
+    local: &dyn PageStore,
+    src: &dyn PageStore,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<TransferStats> {
+    let shallow = shallow_commits(local)?;
+    let mut parents = Vec::new();
+    for commit in &shallow {
+        parents.extend(Commit::load(src, commit)?.map(|commit| commit.parents).unwrap_or_default());
+    }
+    let stats = copy_roots(src, local, &parents, on_progress)?;
+    for commit in &shallow {
+        local.remove_named_root(&shallow_ref(commit))?;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_testing::put_table;
+    use craftsql_core::is_ancestor;
+    use craftsql_store_local::LocalPageStore;
+
+    #[test]
+    fn test_shallow_clone_and_unshallow() {
+        let (tmp_src, tmp_dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let src = LocalPageStore::new(tmp_src.path()).unwrap();
+        let dst = LocalPageStore::new(tmp_dst.path()).unwrap();
+        let first = Commit::new(put_table(&src, &[b"one"]), vec![], "first").put(&src).unwrap();
+        let second = Commit::new(put_table(&src, &[b"two"]), vec![first], "second").put(&src).unwrap();
+        src.set_named_root("main", second).unwrap();
+        let bare = put_table(&src, &[b"bare"]);
+        src.set_named_root("snapshot.bare", bare).unwrap();
+
+        let stats = clone_shallow(&src, &dst, &mut |_| {}).unwrap();
+        assert_eq!(stats.pages_copied, 2);
+        assert_eq!(dst.get_named_root("main").unwrap(), Some(second));
+        assert!(!dst.has(&first).unwrap());
+        assert_eq!(shallow_commits(&dst).unwrap(), vec![second]);
+        assert!(is_ancestor(&dst, second, second).unwrap());
+
+        // Pulling a descendant copies just it; the history stays cut at second
+        let remote = Remote { name: "origin", store: &src };
+        let third = Commit::new(put_table(&src, &[b"two", b"three"]), vec![second], "third").put(&src).unwrap();
+        src.set_named_root("main", third).unwrap();
+        let update = pull_shallow(&dst, remote, "main", false, &mut |_| {}).unwrap();
+        assert_eq!((update.new, update.forced, update.stats.pages_copied), (third, false, 1));
+        assert_eq!(shallow_commits(&dst).unwrap(), vec![second]);
+
+        let stats = unshallow(&dst, &src, &mut |_| {}).unwrap();
+        assert_eq!(stats.pages_copied, 1);
+        assert!(shallow_commits(&dst).unwrap().is_empty());
+        assert!(is_ancestor(&dst, first, third).unwrap());
+        assert!(dst.has(&Commit::load(&dst, &first).unwrap().unwrap().root).unwrap());
+    }
+}
@@ -0,0 +1,291 @@
+//! Copying roots and the pages they reference between stores.
+//!
+//! Pages are copied before anything that points at them: a page table's
+//! pages, then the page table, then any commit naming it (after that
+//! commit's parents), then whatever names that. Only pages the destination
+//! reports missing via [`PageStore::has`] are sent, and a page table or
+//! commit the destination already has is skipped outright (everything it
+//! references was copied first), so an interrupted transfer can simply be
+//! run again.
+
+use std::collections::HashSet;
+
+use craftsql_core::{load_page_table, Cid, Commit, DiffStats, Page, PageStore, PageStoreError, PageTable};
+
+use crate::Result;
+
+/// Running totals, reported after every copied or skipped page.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub pages_copied: u64,
+    /// Pages the destination already had.
+    pub pages_skipped: u64,
+    pub bytes_copied: u64,
+    /// Roots fully present in the destination.
+    pub roots_done: usize,
+    pub roots_total: usize,
+}
+
+/// Copy the current root, every named root, and all pages they reference
+/// from `src` to `dst`, then point `dst`'s names and current root at them.
+///
+/// Names starting with `.` are store bookkeeping and are not copied.
+pub fn clone_store(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<TransferStats> {
+    clone_with(src, dst, copy_roots, on_progress)
+}
+
+/// How a clone, pull, or push brings roots over: [`copy_roots`], or
+/// [`copy_shallow`](crate::shallow::copy_shallow).
+pub(crate) type CopyRoots = fn(
+    &dyn PageStore,
+    &dyn PageStore,
+    &[Cid],
+    &mut dyn FnMut(&TransferStats),
+) -> Result<TransferStats>;
+
+/// [`clone_store`], bringing the roots over with `copy`.
+pub(crate) fn clone_with(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    copy: CopyRoots,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<TransferStats> {
+    let current = src.current_root()?;
+    let named: Vec<(String, Cid)> = src.list_named_roots()?
+        .into_iter()
+        .filter(|(name, _)| !name.starts_with('.'))
+        .collect();
+
+    let roots: Vec<Cid> = current.into_iter().chain(named.iter().map(|(_, cid)| *cid)).collect();
+    let stats = copy(src, dst, &roots, on_progress)?;
+
+    for (name, cid) in &named {
+        dst.set_named_root(name, *cid)?;
+    }
+    if let Some(root) = current {
+        dst.update_root(root)?;
+    }
+    Ok(stats)
+}
+
+/// Copy `roots` and the pages they reference from `src` to `dst`.
+pub fn copy_roots(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    roots: &[Cid],
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<TransferStats> {
+    let mut seen = HashSet::new();
+    let roots: Vec<&Cid> = roots.iter().filter(|cid| seen.insert(**cid)).collect();
+
+    let mut stats = TransferStats { roots_total: roots.len(), ..Default::default() };
+    for root in roots {
+        copy_root(src, dst, root, &mut stats, on_progress)?;
+        stats.roots_done += 1;
+        on_progress(&stats);
+    }
+    Ok(stats)
+}
+
+/// Copy one root object and everything it references: a page table's
+/// pages, or a commit's page table and parent commits.
+pub(crate) fn copy_root(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    root: &Cid,
+    stats: &mut TransferStats,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<()> {
+    enum Step {
+        Visit(Cid),
+        /// Everything the page references is in place
+        Put(Cid, Page),
+    }
+
+    let mut visited = HashSet::new();
+    let mut steps = vec![Step::Visit(*root)];
+    while let Some(step) = steps.pop() {
+        let cid = match step {
+            Step::Put(cid, page) => {
+                put_verified(dst, &cid, &page)?;
+                continue;
+            }
+            Step::Visit(cid) => cid,
+        };
+        if !visited.insert(cid) || dst.has(&cid)? {
+            continue;
+        }
+        let page = src.get(&cid)?;
+        if let Some(commit) = Commit::from_bytes(&page.data) {
+            steps.push(Step::Put(cid, page));
+            steps.push(Step::Visit(commit.root));
+            steps.extend(commit.parents.into_iter().map(Step::Visit));
+        } else {
+            if let Ok(page_table) = PageTable::from_bytes(&page.data) {
+                copy_pages(src, dst, &page_table, stats, on_progress)?;
+            }
+            put_verified(dst, &cid, &page)?;
+        }
+    }
+    Ok(())
+}
+
+/// What bringing `dst` from `dst_root` (nothing if `None`) to `src_root`
+/// in `src` would change, and the pages `dst` lacks for it: see
+/// [`PageTableDiff::stats`](craftsql_core::PageTableDiff::stats). Both
+/// roots may be page tables or commits.
+///
+/// Only the latest version is counted, not its page table or the history
+/// behind it, so a pull of a branch with new commits in between, or a
+/// partial pull, transfers somewhat more or less.
+pub fn estimate_sync_cost(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    src_root: Cid,
+    dst_root: Option<Cid>,
+) -> Result<DiffStats> {
+    let new = load_page_table(src, &src_root)?;
+    let old = match dst_root {
+        Some(root) => load_page_table(dst, &root)?,
+        None => PageTable::new(),
+    };
+    let diff = new.diff(&old);
+    let page_size = new.page_size_in(src)?.unwrap_or(0);
+    let mut stats = diff.stats(page_size);
+
+    let mut seen = HashSet::new();
+    stats.pages_to_transfer = 0;
+    for cid in diff.changed.iter().filter_map(|&(_, _, cid)| cid) {
+        if seen.insert(cid) && !dst.has(&cid)? {
+            stats.pages_to_transfer += 1;
+        }
+    }
+    stats.bytes = stats.pages_to_transfer * page_size as u64;
+    Ok(stats)
+}
+
+fn copy_pages(
+    src: &dyn PageStore,
+    dst: &dyn PageStore,
+    page_table: &PageTable,
+    stats: &mut TransferStats,
+    on_progress: &mut dyn FnMut(&TransferStats),
+) -> Result<()> {
+    let mut seen = HashSet::new();
+    for cid in page_table.entries.iter().flatten() {
+        if !seen.insert(*cid) {
+            continue;
+        }
+        if dst.has(cid)? {
+            stats.pages_skipped += 1;
+        } else {
+            let page = src.get(cid)?;
+            put_verified(dst, cid, &page)?;
+            stats.pages_copied += 1;
+            stats.bytes_copied += page.data.len() as u64;
+        }
+        on_progress(stats);
+    }
+    Ok(())
+}
+
+pub(crate) fn put_verified(dst: &dyn PageStore, cid: &Cid, page: &Page) -> Result<()> {
+    let stored = dst.put(page)?;
+    if stored != *cid {
+        return Err(PageStoreError::Corruption { expected: *cid, actual: stored }.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_testing::put_table;
+    use craftsql_store_local::LocalPageStore;
+
+    #[test]
+    fn test_clone_copies_pages_and_refs() {
+        let (tmp_src, tmp_dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let src = LocalPageStore::new(tmp_src.path()).unwrap();
+        let dst = LocalPageStore::new(tmp_dst.path()).unwrap();
+
+        let v1 = put_table(&src, &[b"a", b"b", b"a"]);
+        let v2 = put_table(&src, &[b"a", b"c"]);
+        src.set_named_root("snapshot.v1", v1).unwrap();
+        src.set_named_root(".history", Cid::from_bytes(b"bookkeeping")).unwrap();
+        src.update_root(v2).unwrap();
+
+        let mut reports = 0;
+        let stats = clone_store(&src, &dst, &mut |_| reports += 1).unwrap();
+        assert_eq!(stats.pages_copied, 3);
+        assert_eq!(stats.pages_skipped, 1); // "a" again under v1
+        assert_eq!((stats.roots_done, stats.roots_total), (2, 2));
+        assert!(reports >= 4);
+
+        assert_eq!(dst.current_root().unwrap(), Some(v2));
+        assert_eq!(dst.list_named_roots().unwrap(), vec![("snapshot.v1".to_string(), v1)]);
+        for cid in [v1, v2, Cid::from_bytes(b"b"), Cid::from_bytes(b"c")] {
+            assert!(dst.has(&cid).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_estimate_sync_cost() {
+        let (tmp_src, tmp_dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let src = LocalPageStore::new(tmp_src.path()).unwrap();
+        let dst = LocalPageStore::new(tmp_dst.path()).unwrap();
+        let v1 = put_table(&src, &[b"aa", b"bb", b"aa"]);
+        let v2 = put_table(&src, &[b"aa", b"cc"]);
+
+        let stats = estimate_sync_cost(&src, &dst, v1, None).unwrap();
+        assert_eq!((stats.pages_added, stats.pages_to_transfer, stats.bytes), (3, 2, 4));
+        let copied = copy_roots(&src, &dst, &[v1], &mut |_| {}).unwrap();
+        assert_eq!(copied.bytes_copied, stats.bytes);
+
+        let stats = estimate_sync_cost(&src, &dst, v2, Some(v1)).unwrap();
+        assert_eq!(stats, DiffStats { pages_changed: 1, pages_removed: 1, pages_to_transfer: 1, bytes: 2, ..DiffStats::default() });
+        // Pages the destination has anyway aren't counted
+        dst.put(&Page { data: b"cc".to_vec() }).unwrap();
+        let stats = estimate_sync_cost(&src, &dst, v2, Some(v1)).unwrap();
+        assert_eq!((stats.pages_changed, stats.pages_to_transfer, stats.bytes), (1, 0, 0));
+    }
+
+    #[test]
+    fn test_clone_resumes() {
+        let (tmp_src, tmp_dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let src = LocalPageStore::new(tmp_src.path()).unwrap();
+        let dst = LocalPageStore::new(tmp_dst.path()).unwrap();
+        let root = put_table(&src, &[b"one", b"two", b"three"]);
+        src.update_root(root).unwrap();
+
+        // An earlier run got as far as one page
+        dst.put(&Page { data: b"two".to_vec() }).unwrap();
+        let stats = clone_store(&src, &dst, &mut |_| {}).unwrap();
+        assert_eq!((stats.pages_copied, stats.pages_skipped), (2, 1));
+
+        // Nothing left to do
+        let stats = clone_store(&src, &dst, &mut |_| {}).unwrap();
+        assert_eq!((stats.pages_copied, stats.pages_skipped), (0, 0));
+        assert_eq!(dst.current_root().unwrap(), Some(root));
+    }
+
+    #[test]
+    fn test_copy_commit_history() {
+        let (tmp_src, tmp_dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let src = LocalPageStore::new(tmp_src.path()).unwrap();
+        let dst = LocalPageStore::new(tmp_dst.path()).unwrap();
+        let first = Commit::new(put_table(&src, &[b"one"]), vec![], "first").put(&src).unwrap();
+        let second = Commit::new(put_table(&src, &[b"one", b"two"]), vec![first], "second").put(&src).unwrap();
+
+        let stats = copy_roots(&src, &dst, &[second], &mut |_| {}).unwrap();
+        assert_eq!(stats.pages_copied, 2);
+        let copied = Commit::load(&dst, &second).unwrap().unwrap();
+        assert_eq!(copied.parents, vec![first]);
+        assert!(dst.has(&copied.root).unwrap());
+        assert!(dst.has(&Commit::load(&dst, &first).unwrap().unwrap().root).unwrap());
+    }
+}
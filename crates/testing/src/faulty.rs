@@ -0,0 +1,330 @@
+//! A PageStore wrapper that injects failures and latency.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use craftsql_core::{Cid, CommitMeta, Page, PageStore, PageStoreError, Result, RootSignal};
+
+use crate::INJECTED_FAILURE;
+
+/// A [`PageStore`] method, for targeting faults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Get,
+    Put,
+    Has,
+    UpdateRoot,
+    CurrentRoot,
+    SetNamedRoot,
+    GetNamedRoot,
+    RemoveNamedRoot,
+    ListNamedRoots,
+    ListPages,
+    DeletePage,
+}
+
+impl Op {
+    pub const ALL: [Op; 11] = [
+        Op::Get,
+        Op::Put,
+        Op::Has,
+        Op::UpdateRoot,
+        Op::CurrentRoot,
+        Op::SetNamedRoot,
+        Op::GetNamedRoot,
+        Op::RemoveNamedRoot,
+        Op::ListNamedRoots,
+        Op::ListPages,
+        Op::DeletePage,
+    ];
+
+    /// Pages in and out: get, put, has.
+    pub const PAGES: [Op; 3] = [Op::Get, Op::Put, Op::Has];
+
+    /// Every method that changes the store.
+    pub const WRITES: [Op; 5] = [Op::Put, Op::UpdateRoot, Op::SetNamedRoot, Op::RemoveNamedRoot, Op::DeletePage];
+}
+
+/// Faults injected into one method.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    /// Chance, from 0 to 1, that a call fails.
+    pub error_rate: f64,
+    /// Delay before every call, failed ones included.
+    pub latency: Duration,
+    /// Fail every call once this many have been made.
+    pub fail_after: Option<u64>,
+    /// Fail this many of the next calls.
+    pub fail_next: u64,
+}
+
+#[derive(Default)]
+struct State {
+    faults: HashMap<Op, Faults>,
+    /// Calls made per method, failed ones included.
+    calls: HashMap<Op, u64>,
+    injected: u64,
+    rng: u64,
+}
+
+impl State {
+    /// Next value of a SplitMix64 sequence, as a float in [0, 1).
+    fn random(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as f64 / (u64::MAX as f64 + 1.0)
+    }
+}
+
+/// Whether `error` was injected by a [`FaultyPageStore`].
+pub fn is_injected(error: &PageStoreError) -> bool {
+    matches!(error, PageStoreError::Storage(message) if message.starts_with(INJECTED_FAILURE))
+}
+
+/// Wraps a store and makes its calls fail or slow down, per method.
+///
+/// Random failures come from a seeded generator, so a test that makes the
+/// same calls in the same order sees the same failures every run. Failed
+/// calls never reach the inner store. Faults can be changed while the
+/// store is in use, e.g. to make a database go read-only halfway through.
+///
+/// ```
+/// use craftsql_core::{Page, PageStore};
+/// use craftsql_store_mem::MemPageStore;
+/// use craftsql_testing::{FaultyPageStore, Op};
+///
+/// let store = FaultyPageStore::new(MemPageStore::new()).with_fail_after(&[Op::Put], 1);
+/// store.put(&Page { data: b"first".to_vec() }).unwrap();
+/// assert!(store.put(&Page { data: b"second".to_vec() }).is_err());
+/// ```
+pub struct FaultyPageStore<S> {
+    inner: S,
+    state: Mutex<State>,
+}
+
+impl<S: PageStore> FaultyPageStore<S> {
+    /// Pass every call through until faults are configured.
+    pub fn new(inner: S) -> Self {
+        Self { inner, state: Mutex::default() }
+    }
+
+    /// Seed the generator behind error rates (0 by default).
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.state.lock().unwrap().rng = seed;
+        self
+    }
+
+    /// Fail calls of `ops` with probability `rate`.
+    pub fn with_error_rate(self, ops: &[Op], rate: f64) -> Self {
+        self.update(ops, |faults| faults.error_rate = rate);
+        self
+    }
+
+    /// Delay calls of `ops` by `latency`.
+    pub fn with_latency(self, ops: &[Op], latency: Duration) -> Self {
+        self.update(ops, |faults| faults.latency = latency);
+        self
+    }
+
+    /// Fail every call of each of `ops` after its first `count`.
+    pub fn with_fail_after(self, ops: &[Op], count: u64) -> Self {
+        self.update(ops, |faults| faults.fail_after = Some(count));
+        self
+    }
+
+    /// Fail the next `count` calls of each of `ops`.
+    pub fn fail_next(&self, ops: &[Op], count: u64) {
+        self.update(ops, |faults| faults.fail_next += count);
+    }
+
+    /// Replace the faults of `op`; "fail after" counts from calls made so far.
+    pub fn set_faults(&self, op: Op, faults: Faults) {
+        self.state.lock().unwrap().faults.insert(op, faults);
+    }
+
+    /// Stop injecting faults.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().faults.clear();
+    }
+
+    /// Calls of `op` made so far, failed ones included.
+    pub fn calls(&self, op: Op) -> u64 {
+        self.state.lock().unwrap().calls.get(&op).copied().unwrap_or(0)
+    }
+
+    /// Failures injected so far.
+    pub fn injected(&self) -> u64 {
+        self.state.lock().unwrap().injected
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn update(&self, ops: &[Op], f: impl Fn(&mut Faults)) {
+        let mut state = self.state.lock().unwrap();
+        for op in ops {
+            f(state.faults.entry(*op).or_default());
+        }
+    }
+
+    /// Count a call of `op`, then sleep and fail it as configured.
+    fn enter(&self, op: Op) -> Result<()> {
+        let (latency, fail) = {
+            let mut state = self.state.lock().unwrap();
+            let calls = *state.calls.entry(op).and_modify(|calls| *calls += 1).or_insert(1);
+            let Some(faults) = state.faults.get(&op).cloned() else {
+                return Ok(());
+            };
+            let mut fail = faults.fail_after.is_some_and(|after| calls > after);
+            if faults.fail_next > 0 {
+                state.faults.get_mut(&op).unwrap().fail_next -= 1;
+                fail = true;
+            }
+            // Draw for every call, so one method's faults don't shift another's
+            if faults.error_rate > 0.0 && state.random() < faults.error_rate {
+                fail = true;
+            }
+            if fail {
+                state.injected += 1;
+            }
+            (faults.latency, fail)
+        };
+        if !latency.is_zero() {
+            std::thread::sleep(latency);
+        }
+        if fail {
+            return Err(PageStoreError::Storage(format!("{} ({:?})", INJECTED_FAILURE, op)));
+        }
+        Ok(())
+    }
+}
+
+impl<S: PageStore> PageStore for FaultyPageStore<S> {
+    fn get(&self, cid: &Cid) -> Result<Page> {
+        self.enter(Op::Get)?;
+        self.inner.get(cid)
+    }
+
+    fn put(&self, page: &Page) -> Result<Cid> {
+        self.enter(Op::Put)?;
+        self.inner.put(page)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        self.enter(Op::Has)?;
+        self.inner.has(cid)
+    }
+
+    fn update_root(&self, new_root: Cid) -> Result<()> {
+        self.enter(Op::UpdateRoot)?;
+        self.inner.update_root(new_root)
+    }
+
+    fn update_root_with_meta(&self, new_root: Cid, meta: &CommitMeta) -> Result<()> {
+        self.enter(Op::UpdateRoot)?;
+        self.inner.update_root_with_meta(new_root, meta)
+    }
+
+    fn current_root(&self) -> Result<Option<Cid>> {
+        self.enter(Op::CurrentRoot)?;
+        self.inner.current_root()
+    }
+
+    fn set_named_root(&self, name: &str, cid: Cid) -> Result<()> {
+        self.enter(Op::SetNamedRoot)?;
+        self.inner.set_named_root(name, cid)
+    }
+
+    fn set_named_root_with_meta(&self, name: &str, cid: Cid, meta: &CommitMeta) -> Result<()> {
+        self.enter(Op::SetNamedRoot)?;
+        self.inner.set_named_root_with_meta(name, cid, meta)
+    }
+
+    fn get_named_root(&self, name: &str) -> Result<Option<Cid>> {
+        self.enter(Op::GetNamedRoot)?;
+        self.inner.get_named_root(name)
+    }
+
+    fn remove_named_root(&self, name: &str) -> Result<bool> {
+        self.enter(Op::RemoveNamedRoot)?;
+        self.inner.remove_named_root(name)
+    }
+
+    fn list_named_roots(&self) -> Result<Vec<(String, Cid)>> {
+        self.enter(Op::ListNamedRoots)?;
+        self.inner.list_named_roots()
+    }
+
+    fn list_pages(&self) -> Result<Vec<(Cid, u64)>> {
+        self.enter(Op::ListPages)?;
+        self.inner.list_pages()
+    }
+
+    fn delete_page(&self, cid: &Cid) -> Result<bool> {
+        self.enter(Op::DeletePage)?;
+        self.inner.delete_page(cid)
+    }
+
+    fn root_signal(&self) -> Result<Option<Box<dyn RootSignal>>> {
+        self.inner.root_signal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+    use std::time::Instant;
+
+    fn page(n: u32) -> Page {
+        Page { data: n.to_le_bytes().to_vec() }
+    }
+
+    #[test]
+    fn test_fail_after_and_next() {
+        let store = FaultyPageStore::new(MemPageStore::new()).with_fail_after(&Op::WRITES, 2);
+        let cid = store.put(&page(0)).unwrap();
+        store.update_root(cid).unwrap();
+        store.put(&page(1)).unwrap();
+        let err = store.put(&page(2)).unwrap_err();
+        assert!(is_injected(&err));
+        assert!(!store.inner().contains(&Cid::from_bytes(&page(2).data)));
+        // Reads are unaffected
+        assert_eq!(store.get(&cid).unwrap().data, page(0).data);
+        assert_eq!((store.calls(Op::Put), store.injected()), (3, 1));
+
+        store.clear();
+        store.put(&page(2)).unwrap();
+        store.fail_next(&[Op::Get], 2);
+        assert!(store.get(&cid).is_err() && store.get(&cid).is_err());
+        store.get(&cid).unwrap();
+        assert!(!is_injected(&store.get(&Cid::from_bytes(b"missing")).unwrap_err()));
+    }
+
+    #[test]
+    fn test_error_rate_is_deterministic() {
+        let failures = |seed| {
+            let store = FaultyPageStore::new(MemPageStore::new()).with_seed(seed).with_error_rate(&Op::PAGES, 0.3);
+            (0..200).map(|n| store.put(&page(n)).is_err()).collect::<Vec<_>>()
+        };
+        let run = failures(7);
+        assert_eq!(run, failures(7));
+        assert_ne!(run, failures(8));
+        let count = run.iter().filter(|failed| **failed).count();
+        assert!((40..80).contains(&count), "{} failures", count);
+    }
+
+    #[test]
+    fn test_latency() {
+        let store = FaultyPageStore::new(MemPageStore::new()).with_latency(&[Op::CurrentRoot], Duration::from_millis(20));
+        let started = Instant::now();
+        store.put(&page(0)).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(20));
+        store.current_root().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+}
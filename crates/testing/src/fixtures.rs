@@ -0,0 +1,13 @@
+//! Building store contents for tests.
+
+use craftsql_core::{Cid, Page, PageStore, PageTable};
+
+/// Put `pages` into `store` as pages 0, 1, ... of a new page table, and
+/// return the table's CID.
+pub fn put_table(store: &dyn PageStore, pages: &[&[u8]]) -> Cid {
+    let mut table = PageTable::new();
+    for (i, data) in pages.iter().enumerate() {
+        table.set(i, store.put(&Page { data: data.to_vec() }).unwrap());
+    }
+    store.put(&Page { data: table.to_bytes() }).unwrap()
+}
@@ -0,0 +1,600 @@
+//! Test doubles: a mock CraftOBJ daemon and a fault-injecting PageStore.
+//!
+//! [`MockDaemon`] speaks the same JSON-RPC as the real daemon — `publish`,
+//! `fetch`, `publish_data`, `fetch_data`, `placement`, `pin`, `unpin` and the `kv.*` methods, single or
+//! batched — over a Unix socket, TCP, or HTTP, so code built on
+//! `craftsql-objbridge` can be tested without running CraftOBJ.
+//!
+//! Beyond serving requests it can simulate a slow network
+//! ([`MockDaemon::with_latency`]), inject failures ([`MockDaemon::fail_next`],
+//! [`MockDaemon::fail_method`], [`MockDaemon::drop_next`]), and expose what
+//! clients stored ([`MockDaemon::get`], [`MockDaemon::kv_get`],
+//! [`MockDaemon::requests`]).
+//!
+//! ```no_run
+//! use craftsql_testing::MockDaemon;
+//!
+//! let daemon = MockDaemon::new("/tmp/my-test.sock");
+//! daemon.start();
+//! // point a DaemonBackend at "/tmp/my-test.sock" ...
+//! assert_eq!(daemon.requests("publish"), 0);
+//! ```
+//!
+//! [`FaultyPageStore`] wraps any store to fail or delay its calls, by
+//! method, at a seeded random rate, after a number of calls, or on demand.
+//!
+//! [`put_table`] builds a page table from page contents, for tests that need
+//! history in a store.
+
+mod faulty;
+mod fixtures;
+
+pub use faulty::{is_injected, Faults, FaultyPageStore, Op};
+pub use fixtures::put_table;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use craftsql_core::Cid;
+use serde_json::{json, Value};
+
+/// Error code returned when a request carries the wrong auth token. Matches
+/// `craftsql_objbridge::UNAUTHORIZED_CODE`.
+pub const UNAUTHORIZED_CODE: i32 = -32001;
+
+/// Error code for every other failure, injected ones included.
+pub const SERVER_ERROR_CODE: i32 = -32000;
+
+/// Message of injected error responses.
+pub const INJECTED_FAILURE: &str = "injected failure";
+
+/// Segment size the mock assumes when a publish doesn't give one.
+const DEFAULT_SEGMENT_SIZE: u64 = 10 << 20;
+
+/// Durability options an object was published with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PublishedWith {
+    pub replication: Option<u64>,
+    pub segment_size: Option<u64>,
+}
+
+/// Failures queued by the test.
+#[derive(Default)]
+struct QueuedFailures {
+    /// Connections to close without replying.
+    drop_connections: usize,
+    /// Calls (of any method) to answer with an error.
+    fail_calls: usize,
+    /// Calls of a specific method to answer with an error.
+    fail_methods: HashMap<String, usize>,
+}
+
+/// Daemon state shared with the listener threads.
+#[derive(Default)]
+struct State {
+    /// Stored content by CID hex.
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+    /// Options each object was last published with, by CID hex.
+    published_with: Mutex<HashMap<String, PublishedWith>>,
+    /// Pinned objects by CID hex.
+    pinned: Mutex<HashSet<String>>,
+    kv: Mutex<BTreeMap<String, String>>,
+    /// Token every request must carry, if set.
+    token: Mutex<Option<String>>,
+    /// Delay before answering each message.
+    latency: Mutex<Duration>,
+    /// Where `fetch` writes content instead of the requested output.
+    fetch_dir: Mutex<Option<PathBuf>>,
+    faults: Mutex<QueuedFailures>,
+    /// Calls received per method, including failed ones.
+    requests: Mutex<HashMap<String, u64>>,
+}
+
+/// Mock CraftOBJ daemon that handles publish/fetch over a Unix socket,
+/// TCP, or HTTP.
+///
+/// All listeners started from one daemon share its content, so a page
+/// published over TCP can be fetched over HTTP.
+pub struct MockDaemon {
+    socket_path: String,
+    state: Arc<State>,
+}
+
+impl MockDaemon {
+    /// Create a daemon that will listen on `socket_path` once [`start`](Self::start)ed.
+    pub fn new(socket_path: &str) -> Self {
+        Self {
+            socket_path: socket_path.to_string(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Reject requests that don't carry `token`.
+    pub fn with_token(self, token: &str) -> Self {
+        *self.state.token.lock().unwrap() = Some(token.to_string());
+        self
+    }
+
+    /// Wait `latency` before answering each message.
+    pub fn with_latency(self, latency: Duration) -> Self {
+        self.set_latency(latency);
+        self
+    }
+
+    /// Change the response delay of a running daemon.
+    pub fn set_latency(&self, latency: Duration) {
+        *self.state.latency.lock().unwrap() = latency;
+    }
+
+    /// Have `fetch` write content into `dir` and report that path instead
+    /// of the output asked for, as a daemon with its own staging area
+    /// would; `None` goes back to the requested output.
+    pub fn set_fetch_dir(&self, dir: Option<&Path>) {
+        *self.state.fetch_dir.lock().unwrap() = dir.map(Path::to_path_buf);
+    }
+
+    pub fn socket_path(&self) -> &str {
+        &self.socket_path
+    }
+
+    /// Answer the next `count` calls with an error response.
+    pub fn fail_next(&self, count: usize) {
+        self.state.faults.lock().unwrap().fail_calls += count;
+    }
+
+    /// Answer the next `count` calls of `method` with an error response.
+    pub fn fail_method(&self, method: &str, count: usize) {
+        *self.state.faults.lock().unwrap().fail_methods.entry(method.to_string()).or_default() += count;
+    }
+
+    /// Close the next `count` connections without replying, as a crashing
+    /// daemon would.
+    pub fn drop_next(&self, count: usize) {
+        self.state.faults.lock().unwrap().drop_connections += count;
+    }
+
+    /// Store `data` as if a client had published it.
+    pub fn insert(&self, data: &[u8]) -> Cid {
+        let cid = Cid::from_bytes(data);
+        self.state.objects.lock().unwrap().insert(hex::encode(cid.0), data.to_vec());
+        cid
+    }
+
+    /// Forget an object, as if it had been lost from the network.
+    pub fn remove(&self, cid: &Cid) -> bool {
+        self.state.objects.lock().unwrap().remove(&hex::encode(cid.0)).is_some()
+    }
+
+    pub fn contains(&self, cid: &Cid) -> bool {
+        self.state.objects.lock().unwrap().contains_key(&hex::encode(cid.0))
+    }
+
+    pub fn get(&self, cid: &Cid) -> Option<Vec<u8>> {
+        self.state.objects.lock().unwrap().get(&hex::encode(cid.0)).cloned()
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.state.objects.lock().unwrap().len()
+    }
+
+    /// CIDs of all stored objects, in no particular order.
+    pub fn objects(&self) -> Vec<Cid> {
+        self.state.objects.lock().unwrap().keys()
+            .filter_map(|key| hex::decode(key).ok()?.try_into().ok().map(Cid))
+            .collect()
+    }
+
+    /// The options `cid` was last published with, if a client published it.
+    pub fn published_with(&self, cid: &Cid) -> Option<PublishedWith> {
+        self.state.published_with.lock().unwrap().get(&hex::encode(cid.0)).copied()
+    }
+
+    pub fn is_pinned(&self, cid: &Cid) -> bool {
+        self.state.pinned.lock().unwrap().contains(&hex::encode(cid.0))
+    }
+
+    pub fn kv_get(&self, key: &str) -> Option<String> {
+        self.state.kv.lock().unwrap().get(key).cloned()
+    }
+
+    /// All kv entries, sorted by key.
+    pub fn kv_entries(&self) -> Vec<(String, String)> {
+        self.state.kv.lock().unwrap().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// Number of `method` calls received so far. Each call in a batch counts.
+    pub fn requests(&self, method: &str) -> u64 {
+        self.state.requests.lock().unwrap().get(method).copied().unwrap_or(0)
+    }
+
+    /// Number of calls received so far, across all methods.
+    pub fn total_requests(&self) -> u64 {
+        self.state.requests.lock().unwrap().values().sum()
+    }
+
+    /// Start listening on the Unix socket in a background thread. Unix
+    /// only; elsewhere use [`start_tcp`](Self::start_tcp).
+    #[cfg(unix)]
+    pub fn start(&self) -> std::thread::JoinHandle<()> {
+        // Remove stale socket
+        let _ = std::fs::remove_file(&self.socket_path);
+
+        let listener = UnixListener::bind(&self.socket_path).expect("bind mock daemon socket");
+        let state = self.state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => break,
+                };
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    let reader = BufReader::new(stream.try_clone().unwrap());
+                    serve_line(reader, stream, &state);
+                });
+            }
+        })
+    }
+
+    /// Serve newline-framed JSON-RPC on an ephemeral TCP port. Returns `host:port`.
+    pub fn start_tcp(&self) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock daemon tcp");
+        let addr = listener.local_addr().unwrap().to_string();
+        let state = self.state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    let reader = BufReader::new(stream.try_clone().unwrap());
+                    serve_line(reader, stream, &state);
+                });
+            }
+        });
+        addr
+    }
+
+    /// Serve JSON-RPC over HTTP POST on an ephemeral port. Returns the URL.
+    pub fn start_http(&self) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock daemon http");
+        let url = format!("http://{}/rpc", listener.local_addr().unwrap());
+        let state = self.state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    let reader = BufReader::new(stream.try_clone().unwrap());
+                    serve_http(reader, stream, &state);
+                });
+            }
+        });
+        url
+    }
+}
+
+impl Drop for MockDaemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+/// Serve one newline-framed message.
+fn serve_line(mut reader: impl BufRead, mut writer: impl Write, state: &State) {
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() || state.take_drop() {
+        return;
+    }
+    state.delay();
+    if let Some(response) = handle_request(&line, state) {
+        let _ = writer.write_all(format!("{}\n", response).as_bytes());
+    }
+}
+
+/// Serve one HTTP/1.0 POST.
+fn serve_http(mut reader: impl BufRead, mut writer: impl Write, state: &State) {
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).is_err() {
+            return;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    if reader.read_exact(&mut body).is_err() || state.take_drop() {
+        return;
+    }
+    state.delay();
+    let Some(response) = handle_request(&String::from_utf8_lossy(&body), state) else {
+        let _ = writer.write_all(b"HTTP/1.0 400 Bad Request\r\n\r\n");
+        return;
+    };
+    let _ = writer.write_all(format!(
+        "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        response.len(), response
+    ).as_bytes());
+}
+
+impl State {
+    fn delay(&self) {
+        let latency = *self.latency.lock().unwrap();
+        if !latency.is_zero() {
+            std::thread::sleep(latency);
+        }
+    }
+
+    /// Consume one queued connection drop, if any.
+    fn take_drop(&self) -> bool {
+        let mut faults = self.faults.lock().unwrap();
+        if faults.drop_connections == 0 {
+            return false;
+        }
+        faults.drop_connections -= 1;
+        true
+    }
+
+    /// Consume one queued failure for a `method` call, if any.
+    fn take_failure(&self, method: &str) -> bool {
+        let mut faults = self.faults.lock().unwrap();
+        if let Some(remaining) = faults.fail_methods.get_mut(method).filter(|n| **n > 0) {
+            *remaining -= 1;
+            return true;
+        }
+        if faults.fail_calls > 0 {
+            faults.fail_calls -= 1;
+            return true;
+        }
+        false
+    }
+}
+
+/// Handle one JSON-RPC message (a request or a batch of them), returning the
+/// serialized response.
+fn handle_request(line: &str, state: &State) -> Option<String> {
+    let message: Value = serde_json::from_str(line.trim()).ok()?;
+    let response = match message {
+        Value::Array(requests) => Value::Array(
+            requests.iter().map(|request| handle_call(request, state)).collect(),
+        ),
+        request => handle_call(&request, state),
+    };
+    Some(serde_json::to_string(&response).unwrap())
+}
+
+fn handle_call(request: &Value, state: &State) -> Value {
+    let method = request["method"].as_str().unwrap_or("");
+    let params = &request["params"];
+    let id = request["id"].as_u64().unwrap_or(0);
+    *state.requests.lock().unwrap().entry(method.to_string()).or_default() += 1;
+
+    let token = state.token.lock().unwrap().clone();
+    if token.is_some() && request["auth"].as_str() != token.as_deref() {
+        return error_response(id, UNAUTHORIZED_CODE, "invalid auth token");
+    }
+    if state.take_failure(method) {
+        return error_response(id, SERVER_ERROR_CODE, INJECTED_FAILURE);
+    }
+
+    let param = |name: &str| params[name].as_str().unwrap_or("").to_string();
+    let published_with = PublishedWith {
+        replication: params["replication"].as_u64(),
+        segment_size: params["segment_size"].as_u64(),
+    };
+    let result = match method {
+        "publish" => match std::fs::read(param("path")) {
+            Ok(data) => {
+                let cid_hex = hex::encode(Cid::from_bytes(&data).0);
+                let size = data.len() as u64;
+                let segments = size.div_ceil(published_with.segment_size.unwrap_or(DEFAULT_SEGMENT_SIZE)).max(1);
+                state.objects.lock().unwrap().insert(cid_hex.clone(), data);
+                state.published_with.lock().unwrap().insert(cid_hex.clone(), published_with);
+                Ok(json!({ "cid": cid_hex, "size": size, "segments": segments }))
+            }
+            Err(e) => Err(format!("read file: {}", e)),
+        },
+        "fetch" => {
+            let cid_hex = param("cid");
+            let output = match &*state.fetch_dir.lock().unwrap() {
+                Some(dir) => dir.join(&cid_hex),
+                None => PathBuf::from(params["output"].as_str().unwrap_or("/tmp/mock-fetch-out")),
+            };
+            match state.objects.lock().unwrap().get(&cid_hex) {
+                Some(data) => match std::fs::write(&output, data) {
+                    Ok(()) => Ok(json!({ "path": output })),
+                    Err(e) => Err(format!("write file: {}", e)),
+                },
+                None => Err(format!("content not found: {}", cid_hex)),
+            }
+        }
+        "publish_data" => match BASE64.decode(param("data")) {
+            Ok(data) => {
+                let cid_hex = hex::encode(Cid::from_bytes(&data).0);
+                state.objects.lock().unwrap().insert(cid_hex.clone(), data);
+                state.published_with.lock().unwrap().insert(cid_hex.clone(), published_with);
+                Ok(json!({ "cid": cid_hex }))
+            }
+            Err(e) => Err(format!("decode data: {}", e)),
+        },
+        "fetch_data" => {
+            let cid_hex = param("cid");
+            match state.objects.lock().unwrap().get(&cid_hex) {
+                Some(data) => Ok(json!({ "data": BASE64.encode(data) })),
+                None => Err(format!("content not found: {}", cid_hex)),
+            }
+        }
+        // One provider per replica, each holding every piece; any one piece
+        // rebuilds a segment
+        "placement" => {
+            let cid_hex = param("cid");
+            match state.objects.lock().unwrap().get(&cid_hex) {
+                Some(data) => {
+                    let with = state.published_with.lock().unwrap().get(&cid_hex).copied().unwrap_or_default();
+                    let replication = with.replication.unwrap_or(1);
+                    let segments = (data.len() as u64).div_ceil(with.segment_size.unwrap_or(DEFAULT_SEGMENT_SIZE)).max(1);
+                    let providers: Vec<String> = (0..replication).map(|i| format!("peer-{}", i)).collect();
+                    let segments = vec![json!({ "pieces": replication, "needed": 1 }); segments as usize];
+                    Ok(json!({ "providers": providers, "segments": segments }))
+                }
+                None => Err(format!("content not found: {}", cid_hex)),
+            }
+        }
+        "pin" => {
+            let cid_hex = param("cid");
+            if state.objects.lock().unwrap().contains_key(&cid_hex) {
+                state.pinned.lock().unwrap().insert(cid_hex);
+                Ok(json!({ "pinned": true }))
+            } else {
+                Err(format!("content not found: {}", cid_hex))
+            }
+        }
+        "unpin" => {
+            let was_pinned = state.pinned.lock().unwrap().remove(&param("cid"));
+            Ok(json!({ "unpinned": was_pinned }))
+        }
+        "kv.put" => {
+            state.kv.lock().unwrap().insert(param("key"), param("value"));
+            Ok(json!({ "ok": true }))
+        }
+        // Swap only if the key still holds `expected` (null: absent)
+        "kv.cas" => {
+            let key = param("key");
+            let mut kv = state.kv.lock().unwrap();
+            let current = kv.get(&key).cloned();
+            if current.as_deref() == params["expected"].as_str() {
+                kv.insert(key, param("value"));
+                Ok(json!({ "swapped": true, "value": param("value") }))
+            } else {
+                Ok(json!({ "swapped": false, "value": current }))
+            }
+        }
+        "kv.get" => {
+            let key = param("key");
+            let value = state.kv.lock().unwrap().get(&key).cloned();
+            Ok(json!({ "key": key, "value": value }))
+        }
+        "kv.delete" => {
+            let existed = state.kv.lock().unwrap().remove(&param("key")).is_some();
+            Ok(json!({ "deleted": existed }))
+        }
+        "kv.list" => {
+            let prefix = param("prefix");
+            let keys: Vec<String> = state.kv.lock().unwrap().keys()
+                .filter(|k| k.starts_with(&prefix))
+                .cloned()
+                .collect();
+            Ok(json!({ "keys": keys }))
+        }
+        _ => Err(format!("unknown method: {}", method)),
+    };
+
+    match result {
+        Ok(val) => json!({ "jsonrpc": "2.0", "result": val, "id": id }),
+        Err(msg) => error_response(id, SERVER_ERROR_CODE, &msg),
+    }
+}
+
+fn error_response(id: u64, code: i32, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message },
+        "id": id,
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    /// Send one raw message; `None` if the daemon hung up without replying.
+    fn call(path: &str, message: Value) -> Option<Value> {
+        let mut stream = UnixStream::connect(path).unwrap();
+        stream.write_all(format!("{}\n", message).as_bytes()).unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        (!line.is_empty()).then(|| serde_json::from_str(&line).unwrap())
+    }
+
+    fn kv_put(key: &str, value: &str) -> Value {
+        json!({ "jsonrpc": "2.0", "method": "kv.put", "params": { "key": key, "value": value }, "id": 1 })
+    }
+
+    #[test]
+    fn test_inspect_content() {
+        let path = format!("/tmp/craftsql-testing-inspect-{}.sock", std::process::id());
+        let daemon = MockDaemon::new(&path);
+        daemon.start();
+
+        let cid = daemon.insert(b"seeded");
+        let response = call(&path, json!({
+            "jsonrpc": "2.0", "method": "fetch_data", "params": { "cid": cid.to_hex() }, "id": 7,
+        })).unwrap();
+        assert_eq!(response["result"]["data"], BASE64.encode(b"seeded"));
+        assert_eq!(response["id"], 7);
+
+        call(&path, json!([kv_put("a", "1"), kv_put("b", "2")])).unwrap();
+        assert_eq!(daemon.kv_get("a").as_deref(), Some("1"));
+        assert_eq!(daemon.kv_entries().len(), 2);
+        assert_eq!(daemon.requests("kv.put"), 2);
+        assert_eq!(daemon.total_requests(), 3);
+
+        assert_eq!(daemon.objects(), vec![cid]);
+        assert!(daemon.remove(&cid));
+        assert!(!daemon.contains(&cid));
+        assert_eq!(daemon.object_count(), 0);
+    }
+
+    #[test]
+    fn test_injected_failures() {
+        let path = format!("/tmp/craftsql-testing-faults-{}.sock", std::process::id());
+        let daemon = MockDaemon::new(&path);
+        daemon.start();
+
+        daemon.drop_next(1);
+        assert_eq!(call(&path, kv_put("k", "v")), None);
+        assert_eq!(daemon.kv_get("k"), None);
+
+        daemon.fail_method("kv.get", 1);
+        daemon.fail_next(1);
+        let response = call(&path, kv_put("k", "v")).unwrap();
+        assert_eq!(response["error"]["message"], INJECTED_FAILURE);
+        assert_eq!(call(&path, kv_put("k", "v")).unwrap()["result"]["ok"], true);
+
+        let get = json!({ "jsonrpc": "2.0", "method": "kv.get", "params": { "key": "k" }, "id": 1 });
+        assert_eq!(call(&path, get.clone()).unwrap()["error"]["code"], SERVER_ERROR_CODE);
+        assert_eq!(call(&path, get).unwrap()["result"]["value"], "v");
+    }
+
+    #[test]
+    fn test_token_and_latency() {
+        let path = format!("/tmp/craftsql-testing-token-{}.sock", std::process::id());
+        let daemon = MockDaemon::new(&path)
+            .with_token("s3cret")
+            .with_latency(Duration::from_millis(50));
+        daemon.start();
+
+        let started = std::time::Instant::now();
+        let response = call(&path, kv_put("k", "v")).unwrap();
+        assert_eq!(response["error"]["code"], UNAUTHORIZED_CODE);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let mut authorized = kv_put("k", "v");
+        authorized["auth"] = json!("s3cret");
+        assert_eq!(call(&path, authorized).unwrap()["result"]["ok"], true);
+    }
+}
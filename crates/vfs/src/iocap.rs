@@ -0,0 +1,146 @@
+//! Reporting the store's write guarantees to SQLite: the sector size and
+//! device characteristics of the main database file.
+//!
+//! Pages only reach the store whole, when SQLite syncs, and nothing is
+//! visible until the root moves. A write can't tear a neighbouring sector
+//! or leave garbage past the end of a file, so the file is
+//! `POWERSAFE_OVERWRITE` and `SAFE_APPEND`, and its sector size can be the
+//! smallest SQLite takes. SQLite then skips journal padding and the sync
+//! between writing a journal header and its records.
+//!
+//! `sqlite-vfs` answers `xSectorSize` and `xDeviceCharacteristics` itself,
+//! without asking our file handles. Once it has registered a VFS,
+//! [`install`] wraps that VFS's `xOpen` so each main database file it opens
+//! gets a copy of its io methods answering both from here.
+
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::sync::Mutex;
+use std::{mem, ptr};
+
+/// The sector size reported: SQLite's minimum.
+pub const SECTOR_SIZE: i32 = 512;
+
+/// `SQLITE_IOCAP_SAFE_APPEND | SQLITE_IOCAP_POWERSAFE_OVERWRITE`.
+pub const DEVICE_CHARACTERISTICS: i32 = 0x200 | 0x1000;
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_CANTOPEN: c_int = 14;
+const SQLITE_OPEN_MAIN_DB: c_int = 0x100;
+
+type FileFn = unsafe extern "C" fn(*mut File) -> c_int;
+type OpenFn = unsafe extern "C" fn(*mut Vfs, *const c_char, *mut File, c_int, *mut c_int) -> c_int;
+
+/// `sqlite3_file`.
+#[repr(C)]
+struct File {
+    methods: *const IoMethods,
+}
+
+/// `sqlite3_io_methods`, version 3. Only the two methods replaced are typed.
+#[repr(C)]
+struct IoMethods {
+    version: c_int,
+    /// `xClose` through `xFileControl`
+    before: [*const c_void; 10],
+    sector_size: Option<FileFn>,
+    device_characteristics: Option<FileFn>,
+    /// `xShmMap` through `xUnfetch`
+    after: [*const c_void; 6],
+}
+
+/// The start of `sqlite3_vfs`, up to `xOpen`.
+#[repr(C)]
+struct Vfs {
+    version: c_int,
+    os_file_size: c_int,
+    max_pathname: c_int,
+    next: *mut Vfs,
+    name: *const c_char,
+    app_data: *mut c_void,
+    open: Option<OpenFn>,
+}
+
+extern "C" {
+    fn sqlite3_vfs_find(name: *const c_char) -> *mut Vfs;
+}
+
+/// The `xOpen` of each wrapped VFS, by the VFS's address.
+static OPENS: Mutex<Vec<(usize, OpenFn)>> = Mutex::new(Vec::new());
+
+/// Patched copies of io methods, by the address of the original.
+static METHODS: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+/// Have the main database files of the registered VFS `name` report
+/// [`SECTOR_SIZE`] and [`DEVICE_CHARACTERISTICS`].
+pub(crate) fn install(name: &str) {
+    let Ok(name) = CString::new(name) else {
+        return;
+    };
+    // SAFETY: the VFS was just registered and nothing has opened a file
+    // through it yet; SQLite never frees a registered VFS.
+    unsafe {
+        let vfs = sqlite3_vfs_find(name.as_ptr());
+        let Some(open) = vfs.as_ref().and_then(|vfs| vfs.open) else {
+            return;
+        };
+        if open as usize == open_main as OpenFn as usize {
+            return;
+        }
+        OPENS.lock().unwrap().push((vfs as usize, open));
+        (*vfs).open = Some(open_main);
+    }
+}
+
+unsafe extern "C" fn open_main(
+    vfs: *mut Vfs,
+    name: *const c_char,
+    file: *mut File,
+    flags: c_int,
+    out_flags: *mut c_int,
+) -> c_int {
+    let open = OPENS.lock().unwrap().iter().find(|&&(wrapped, _)| wrapped == vfs as usize).map(|&(_, open)| open);
+    let Some(open) = open else {
+        return SQLITE_CANTOPEN;
+    };
+    let rc = open(vfs, name, file, flags, out_flags);
+    if rc == SQLITE_OK && flags & SQLITE_OPEN_MAIN_DB != 0 && !(*file).methods.is_null() {
+        (*file).methods = patched((*file).methods);
+    }
+    rc
+}
+
+/// `original` with the sector size and device characteristics replaced,
+/// made once and kept for good, as SQLite expects of io methods.
+unsafe fn patched(original: *const IoMethods) -> *const IoMethods {
+    let mut methods = METHODS.lock().unwrap();
+    if let Some(&(_, copy)) = methods.iter().find(|&&(of, _)| of == original as usize) {
+        return copy as *const IoMethods;
+    }
+    // Older versions end sooner; the methods they lack stay null
+    let len = match (*original).version {
+        1 => mem::offset_of!(IoMethods, after),
+        2 => mem::offset_of!(IoMethods, after) + 4 * mem::size_of::<*const c_void>(),
+        _ => mem::size_of::<IoMethods>(),
+    };
+    let mut copy = IoMethods {
+        version: 0,
+        before: [ptr::null(); 10],
+        sector_size: None,
+        device_characteristics: None,
+        after: [ptr::null(); 6],
+    };
+    ptr::copy_nonoverlapping(original.cast::<u8>(), ptr::addr_of_mut!(copy).cast::<u8>(), len);
+    copy.sector_size = Some(sector_size);
+    copy.device_characteristics = Some(device_characteristics);
+    let copy: *const IoMethods = Box::leak(Box::new(copy));
+    methods.push((original as usize, copy as usize));
+    copy
+}
+
+unsafe extern "C" fn sector_size(_file: *mut File) -> c_int {
+    SECTOR_SIZE
+}
+
+unsafe extern "C" fn device_characteristics(_file: *mut File) -> c_int {
+    DEVICE_CHARACTERISTICS
+}
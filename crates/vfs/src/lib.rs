@@ -2,11 +2,50 @@
 //!
 //! Translates SQLite's page-level reads/writes into PageStore operations.
 //! WAL mode is disabled — rollback journal only (single-owner writes).
+//!
+//! Connections through one registered VFS share a generation counter,
+//! bumped whenever one of them moves the root. A connection that finds it
+//! moved when it next takes a lock reloads the page table, so it reads
+//! what the others committed instead of its own stale copy. A
+//! [`RootControl`] moves the root from outside them the same way. The
+//! connections also take SQLite's locks from one another (see [`locks`]):
+//! a connection that can't get one within a moment gets `SQLITE_BUSY`, for
+//! its busy handler to retry.
+//!
+//! Every other file SQLite opens — the journal, TEMP databases, sorter
+//! spills — is a [`TempFile`](temp::TempFile): memory first, spilled to
+//! the store as unreferenced scratch pages when it grows large.
+//!
+//! The main database file reports the store's write guarantees to SQLite
+//! (see [`iocap`]): a [`SECTOR_SIZE`] of 512 and the
+//! [`DEVICE_CHARACTERISTICS`] `SAFE_APPEND` and `POWERSAFE_OVERWRITE`.
+//!
+//! A [`ConnectionMeta`] has the root updates one connection commits carry
+//! an author and message, through [`PageStore::update_root_with_meta`]:
+//! open the connection at [`RootControl::connection_path`].
+//!
+//! With the `libsql` feature, [`libsql`] opens the database with libsql.
 
-use craftsql_core::{Cid, Page, PageStore, PageTable};
+use craftsql_core::{Cid, CommitMeta, Page, PageStore, PageTable};
 use sqlite_vfs::{DatabaseHandle, LockKind, OpenAccess, OpenKind, OpenOptions, Vfs, WalDisabled};
+use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+mod iocap;
+#[cfg(feature = "libsql")]
+pub mod libsql;
+mod locks;
+mod temp;
+
+pub use iocap::{DEVICE_CHARACTERISTICS, SECTOR_SIZE};
+use locks::{Locks, LOCK_WAIT};
+use temp::TempFile;
+
+/// Prefix of [`Vfs::temporary_name`]s, which never name the database.
+const TEMP_PREFIX: &str = "craftsql-tmp-";
 
 /// Register the CraftSQL VFS with SQLite.
 ///
@@ -15,23 +54,129 @@ use std::sync::{Arc, Mutex};
 /// .open file:mydb?vfs=craftsql
 /// ```
 pub fn register<S: PageStore + 'static>(name: &str, store: S) -> Result<(), sqlite_vfs::RegisterError> {
+    register_shared(name, Arc::new(store))
+}
+
+/// [`register`] a store that is shared with other code, such as an
+/// `Arc<dyn PageStore>` whose backend was picked at runtime. The VFS uses
+/// it as it is, without wrapping it again.
+pub fn register_shared<S: PageStore + ?Sized + 'static>(name: &str, store: Arc<S>) -> Result<(), sqlite_vfs::RegisterError> {
+    register_with_control(name, store).map(drop)
+}
+
+/// [`register_shared`], also returning a [`RootControl`] to move the root
+/// by while the VFS's connections are open.
+pub fn register_with_control<S: PageStore + ?Sized + 'static>(
+    name: &str,
+    store: Arc<S>,
+) -> Result<RootControl<S>, sqlite_vfs::RegisterError> {
+    let control = RootControl { store: Arc::clone(&store), generation: Arc::default(), locks: Arc::default(), commit_meta: Arc::default() };
     let vfs = CraftVfs {
-        store: Arc::new(store),
+        store,
+        generation: Arc::clone(&control.generation),
+        locks: Arc::clone(&control.locks),
+        commit_meta: Arc::clone(&control.commit_meta),
     };
-    sqlite_vfs::register(name, vfs, false)
+    sqlite_vfs::register(name, vfs, false)?;
+    iocap::install(name);
+    Ok(control)
+}
+
+/// How long [`RootControl::exclusive`] callers usually wait for open
+/// transactions to finish.
+pub const DEFAULT_EXCLUSIVE_WAIT: Duration = Duration::from_secs(5);
+
+/// Moves the root of a registered VFS from outside its connections, such
+/// as for a checkout, the way one of them would. Moving it with
+/// [`PageStore::update_root`] alone leaves an open connection on its old
+/// page table, and a writer's commit puts the old version back.
+pub struct RootControl<S: PageStore + ?Sized> {
+    store: Arc<S>,
+    generation: Arc<AtomicU64>,
+    locks: Arc<Locks>,
+    commit_meta: Arc<Mutex<HashMap<String, ConnectionMeta>>>,
+}
+
+impl<S: PageStore + ?Sized> RootControl<S> {
+    /// Run `f` holding the database's EXCLUSIVE lock, then have every
+    /// connection reload the page table as it starts its next transaction.
+    ///
+    /// Waits up to `wait` for connections to finish their transactions,
+    /// keeping new ones from starting meanwhile; returns `None` without
+    /// running `f` if one is still open then.
+    pub fn exclusive<T>(&self, wait: Duration, f: impl FnOnce(&S) -> T) -> Option<T> {
+        let id = self.locks.new_id();
+        let deadline = Instant::now() + wait;
+        let mut held = self.locks.change(id, LockKind::None, LockKind::Shared, wait);
+        if locks::rank(held) == locks::rank(LockKind::Shared) {
+            held = self.locks.change(id, held, LockKind::Exclusive, deadline.saturating_duration_since(Instant::now()));
+        }
+        let result = (locks::rank(held) == locks::rank(LockKind::Exclusive)).then(|| {
+            let result = f(&self.store);
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            result
+        });
+        self.locks.change(id, held, LockKind::None, Duration::ZERO);
+        result
+    }
+
+    /// A new path to open a connection at, whose root updates then record
+    /// `meta`. Every path opens the VFS's one database; the first file
+    /// handle opened at this one takes `meta` for good, so it never ends up
+    /// on another connection's commits.
+    pub fn connection_path(&self, meta: &ConnectionMeta) -> String {
+        let path = format!("/craftsql/{}/db", uuid::Uuid::new_v4());
+        self.commit_meta.lock().unwrap().insert(path.clone(), meta.clone());
+        path
+    }
+}
+
+/// The author and message one connection records with the root updates it
+/// commits; see [`RootControl::connection_path`].
+#[derive(Clone, Default)]
+pub struct ConnectionMeta(Arc<Mutex<Option<CommitMeta>>>);
+
+impl ConnectionMeta {
+    /// Record `meta` with every root update the connection commits from now
+    /// on, until replaced; `None` stops recording any. Each update's parent
+    /// is filled in with the root its transaction started from.
+    pub fn set(&self, meta: Option<CommitMeta>) {
+        *self.0.lock().unwrap() = meta;
+    }
 }
 
 /// The CraftSQL virtual file system.
-struct CraftVfs<S: PageStore> {
+struct CraftVfs<S: PageStore + ?Sized> {
     store: Arc<S>,
+    /// Bumped each time a handle moves the root.
+    generation: Arc<AtomicU64>,
+    /// Locks on the main database.
+    locks: Arc<Locks>,
+    /// Commit meta not yet taken, by the path its connection will open;
+    /// see [`RootControl::connection_path`].
+    commit_meta: Arc<Mutex<HashMap<String, ConnectionMeta>>>,
 }
 
 /// Handle to an open database file.
-struct CraftDbHandle<S: PageStore> {
+struct CraftDbHandle<S: PageStore + ?Sized> {
     store: Arc<S>,
     /// In-memory page buffer: page_num → data. Flushed on sync.
     pages: Mutex<PageBuffer>,
     lock: Mutex<LockKind>,
+    /// The VFS's generation counter.
+    generation: Arc<AtomicU64>,
+    locks: Arc<Locks>,
+    /// This handle's id in `locks`.
+    id: u64,
+    /// Recorded with each root update, if the connection was opened at a
+    /// [`RootControl::connection_path`].
+    commit_meta: Option<ConnectionMeta>,
+}
+
+/// Any file SQLite opens through the VFS.
+enum CraftFile<S: PageStore + ?Sized> {
+    Main(CraftDbHandle<S>),
+    Temp(TempFile<S>),
 }
 
 /// Buffered pages for a database connection.
@@ -46,6 +191,10 @@ struct PageBuffer {
     page_table: PageTable,
     /// Whether any writes have occurred since last sync.
     dirty: bool,
+    /// VFS generation the page table was loaded or written at.
+    generation: u64,
+    /// The root the page table was loaded from or written as.
+    root: Option<Cid>,
 }
 
 impl PageBuffer {
@@ -58,9 +207,33 @@ impl PageBuffer {
             file_size,
             page_table,
             dirty: false,
+            generation: 0,
+            root: None,
         }
     }
 
+    /// The current root's pages, or a new database's if there is no root
+    /// and `create`.
+    fn load<S: PageStore + ?Sized>(store: &S, create: bool) -> Result<Self, Error> {
+        let root = store.current_root()
+            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        let page_table = match root {
+            Some(root) => {
+                let pt_page = store.get(&root)
+                    .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+                PageTable::from_bytes(&pt_page.data)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?
+            }
+            None if create => PageTable::created_now(),
+            None => return Err(Error::new(ErrorKind::NotFound, "database not found")),
+        };
+
+        let page_size = page_table.page_size_in(store)
+            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?
+            .unwrap_or(4096);
+        Ok(Self { root, ..Self::new(page_table, page_size) })
+    }
+
     fn ensure_page(&mut self, page_num: usize) {
         if page_num >= self.pages.len() {
             self.pages.resize(page_num + 1, None);
@@ -68,70 +241,37 @@ impl PageBuffer {
     }
 }
 
-impl<S: PageStore + 'static> Vfs for CraftVfs<S> {
-    type Handle = CraftDbHandle<S>;
+impl<S: PageStore + ?Sized + 'static> Vfs for CraftVfs<S> {
+    type Handle = CraftFile<S>;
 
-    fn open(&self, _db: &str, opts: OpenOptions) -> Result<Self::Handle, Error> {
-        // For journal/temp files, return an empty handle
+    fn open(&self, db: &str, opts: OpenOptions) -> Result<Self::Handle, Error> {
+        // Journals and temporary files are never committed
         if opts.kind != OpenKind::MainDb {
-            return Ok(CraftDbHandle {
-                store: Arc::clone(&self.store),
-                pages: Mutex::new(PageBuffer::new(PageTable::new(), 4096)),
-                lock: Mutex::new(LockKind::None),
-            });
-        }
-
-        // Load existing page table or create new
-        let page_table = match opts.access {
-            OpenAccess::Read => {
-                // Must exist
-                let root = self.store.current_root()
-                    .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?
-                    .ok_or_else(|| Error::new(ErrorKind::NotFound, "database not found"))?;
-                let pt_page = self.store.get(&root)
-                    .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
-                PageTable::from_bytes(&pt_page.data)
-                    .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?
-            }
-            _ => {
-                // Try to load existing, or create new
-                match self.store.current_root()
-                    .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))? {
-                    Some(root) => {
-                        let pt_page = self.store.get(&root)
-                            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
-                        PageTable::from_bytes(&pt_page.data)
-                            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?
-                    }
-                    None => PageTable::new(),
-                }
-            }
-        };
+            return Ok(CraftFile::Temp(TempFile::new(Arc::clone(&self.store), temp::SPILL_BYTES)));
+        }
 
-        let page_size = if page_table.len() > 0 {
-            // Detect from first page
-            if let Some(cid) = page_table.get(0) {
-                let page = self.store.get(cid)
-                    .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
-                page.data.len()
-            } else {
-                4096
-            }
-        } else {
-            4096
-        };
+        // Load existing page table, or create new unless read-only. Read
+        // the generation first: a root moved in between only means an
+        // extra reload later.
+        let generation = self.generation.load(Ordering::SeqCst);
+        let mut buf = PageBuffer::load(&*self.store, !matches!(opts.access, OpenAccess::Read))?;
+        buf.generation = generation;
 
-        Ok(CraftDbHandle {
+        Ok(CraftFile::Main(CraftDbHandle {
             store: Arc::clone(&self.store),
-            pages: Mutex::new(PageBuffer::new(page_table, page_size)),
+            pages: Mutex::new(buf),
             lock: Mutex::new(LockKind::None),
-        })
+            generation: Arc::clone(&self.generation),
+            locks: Arc::clone(&self.locks),
+            id: self.locks.new_id(),
+            commit_meta: self.commit_meta.lock().unwrap().remove(db),
+        }))
     }
 
     fn delete(&self, db: &str) -> Result<(), Error> {
-        // Only delete the main database, not journal/wal files
-        if db.ends_with("-journal") || db.ends_with("-wal") || db.ends_with("-shm") {
-            return Ok(()); // Journal cleanup is a no-op for us
+        // Only delete the main database, not journal/wal/temporary files
+        if is_side_file(db) {
+            return Ok(()); // Their contents go with their handles
         }
         // Delete = reset root pointer. Pages are garbage collected separately.
         self.store.update_root(Cid([0u8; 32]))
@@ -139,8 +279,8 @@ impl<S: PageStore + 'static> Vfs for CraftVfs<S> {
     }
 
     fn exists(&self, db: &str) -> Result<bool, Error> {
-        // Journal/WAL files never "exist" in our VFS
-        if db.ends_with("-journal") || db.ends_with("-wal") || db.ends_with("-shm") {
+        // Journal/WAL/temporary files never "exist" in our VFS
+        if is_side_file(db) {
             return Ok(false);
         }
         let root = self.store.current_root()
@@ -149,7 +289,7 @@ impl<S: PageStore + 'static> Vfs for CraftVfs<S> {
     }
 
     fn temporary_name(&self) -> String {
-        format!("craftsql-tmp-{}", uuid::Uuid::new_v4())
+        format!("{}{}", TEMP_PREFIX, uuid::Uuid::new_v4())
     }
 
     fn random(&self, buffer: &mut [i8]) {
@@ -169,7 +309,7 @@ impl<S: PageStore + 'static> Vfs for CraftVfs<S> {
     }
 }
 
-impl<S: PageStore + 'static> DatabaseHandle for CraftDbHandle<S> {
+impl<S: PageStore + ?Sized + 'static> DatabaseHandle for CraftDbHandle<S> {
     type WalIndex = WalDisabled;
 
     fn size(&self) -> Result<u64, Error> {
@@ -219,11 +359,17 @@ impl<S: PageStore + 'static> DatabaseHandle for CraftDbHandle<S> {
         let mut buf = self.pages.lock().unwrap();
 
         // Detect page size from first write (SQLite writes page 1 header first)
-        if offset == 0 && buf.page_table.is_empty() && data.len() >= 100 {
+        if offset == 0 && data.len() >= 100 {
             // SQLite stores page size at offset 16 (2 bytes, big-endian)
             let ps = u16::from_be_bytes([data[16], data[17]]) as usize;
-            if ps >= 512 && ps <= 65536 && ps.is_power_of_two() {
-                buf.page_size = ps;
+            if ps >= 512 && ps <= 65536 && ps.is_power_of_two() && ps != buf.page_size {
+                if buf.page_table.is_empty() && !buf.dirty {
+                    buf.page_size = ps;
+                } else {
+                    // A VACUUM or backup changing the page size of a
+                    // database that has pages
+                    self.repage(&mut buf, ps)?;
+                }
             }
         }
 
@@ -243,17 +389,9 @@ impl<S: PageStore + 'static> DatabaseHandle for CraftDbHandle<S> {
             let available_in_page = page_size - current_offset;
             let to_write = remaining.min(available_in_page);
 
-            // Get or create page data
-            let page_data = if let Some(ref existing) = buf.pages[current_page] {
-                existing.clone()
-            } else if let Some(cid) = buf.page_table.get(current_page) {
-                // Load from store
-                self.store.get(cid)
-                    .map(|p| p.data)
-                    .unwrap_or_else(|_| vec![0u8; page_size])
-            } else {
-                vec![0u8; page_size]
-            };
+            // Get or create page data. A page that can't be loaded fails
+            // the write rather than being rewritten as zeros.
+            let page_data = self.read_page(&buf, current_page)?;
 
             let mut page_data = if page_data.len() < page_size {
                 let mut d = page_data;
@@ -289,19 +427,21 @@ impl<S: PageStore + 'static> DatabaseHandle for CraftDbHandle<S> {
             return Ok(());
         }
 
-        // Collect dirty pages and their CIDs
-        let mut updates: Vec<(usize, Cid)> = Vec::new();
-        for (i, page_data) in buf.pages.iter().enumerate() {
-            if let Some(data) = page_data {
-                let page = Page { data: data.clone() };
-                let cid = self.store.put(&page)
-                    .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
-                updates.push((i, cid));
-            }
-        }
-        for (i, cid) in updates {
+        // Store the dirty pages as one batch
+        let (numbers, pages): (Vec<usize>, Vec<Page>) = buf.pages.iter()
+            .enumerate()
+            .filter_map(|(i, data)| Some((i, Page { data: data.clone()? })))
+            .unzip();
+        let cids = self.store.put_many(&pages)
+            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        for (i, cid) in numbers.into_iter().zip(cids) {
             buf.page_table.set(i, cid);
         }
+        buf.page_table.page_size = buf.page_size as u32;
+        let PageBuffer { pages, page_table, .. } = &mut *buf;
+        if let Some(Some(header)) = pages.first() {
+            page_table.record_header(header);
+        }
 
         // Persist page table itself as a page
         let pt_data = buf.page_table.to_bytes();
@@ -309,9 +449,15 @@ impl<S: PageStore + 'static> DatabaseHandle for CraftDbHandle<S> {
         let pt_cid = self.store.put(&pt_page)
             .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
 
-        // Update root pointer
-        self.store.update_root(pt_cid)
-            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        // Update root pointer, with who made the change if that's known
+        let meta = self.commit_meta.as_ref().and_then(|meta| meta.0.lock().unwrap().clone());
+        match meta {
+            Some(meta) => self.store.update_root_with_meta(pt_cid, &meta.with_parent(buf.root)),
+            None => self.store.update_root(pt_cid),
+        }
+        .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        buf.root = Some(pt_cid);
+        buf.generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
 
         // Clear dirty pages (keep table)
         for p in buf.pages.iter_mut() {
@@ -333,12 +479,21 @@ impl<S: PageStore + 'static> DatabaseHandle for CraftDbHandle<S> {
     }
 
     fn lock(&mut self, lock: LockKind) -> Result<bool, Error> {
-        *self.lock.lock().unwrap() = lock;
-        Ok(true)
+        let mut current = self.lock.lock().unwrap();
+        let held = self.locks.change(self.id, *current, lock, LOCK_WAIT);
+        let started = matches!(*current, LockKind::None) && !matches!(held, LockKind::None);
+        *current = held;
+        // Starting a transaction: pick up what other connections committed.
+        // SQLite then sees the header's change counter move and drops its
+        // own cache.
+        if started {
+            self.refresh()?;
+        }
+        Ok(locks::rank(held) == locks::rank(lock))
     }
 
     fn reserved(&mut self) -> Result<bool, Error> {
-        Ok(false)
+        Ok(self.locks.reserved())
     }
 
     fn current_lock(&self) -> Result<LockKind, Error> {
@@ -351,105 +506,132 @@ impl<S: PageStore + 'static> DatabaseHandle for CraftDbHandle<S> {
     }
 }
 
-impl<S: PageStore> CraftDbHandle<S> {
-    fn read_page(&self, buf: &PageBuffer, page_num: usize) -> Result<Vec<u8>, Error> {
-        // Check buffer first
-        if page_num < buf.pages.len() {
-            if let Some(ref data) = buf.pages[page_num] {
-                return Ok(data.clone());
-            }
-        }
+impl<S: PageStore + ?Sized> Drop for CraftDbHandle<S> {
+    fn drop(&mut self) {
+        // SQLite unlocks before closing, but a lock must never outlive its
+        // handle
+        let current = *self.lock.get_mut().unwrap();
+        self.locks.change(self.id, current, LockKind::None, std::time::Duration::ZERO);
+    }
+}
 
-        // Check page table → store
-        if let Some(cid) = buf.page_table.get(page_num) {
-            let page = self.store.get(cid)
-                .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
-            return Ok(page.data);
+/// Forward every method to the file's handle.
+macro_rules! forward {
+    ($self:ident, $file:ident => $call:expr) => {
+        match $self {
+            CraftFile::Main($file) => $call,
+            CraftFile::Temp($file) => $call,
         }
+    };
+}
 
-        // Page doesn't exist yet — return zeros
-        Ok(vec![0u8; buf.page_size])
+impl<S: PageStore + ?Sized + 'static> DatabaseHandle for CraftFile<S> {
+    type WalIndex = WalDisabled;
+
+    fn size(&self) -> Result<u64, Error> {
+        forward!(self, file => file.size())
     }
-}
 
+    fn read_exact_at(&mut self, out: &mut [u8], offset: u64) -> Result<(), Error> {
+        forward!(self, file => file.read_exact_at(out, offset))
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use craftsql_core::{PageStoreError, Result as CsResult};
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    fn write_all_at(&mut self, data: &[u8], offset: u64) -> Result<(), Error> {
+        forward!(self, file => file.write_all_at(data, offset))
+    }
 
-    /// In-memory PageStore for testing. Clone-friendly (Arc inner).
-    #[derive(Clone)]
-    struct MemStore {
-        inner: Arc<MemStoreInner>,
+    fn sync(&mut self, data_only: bool) -> Result<(), Error> {
+        forward!(self, file => file.sync(data_only))
     }
 
-    struct MemStoreInner {
-        pages: Mutex<std::collections::HashMap<Cid, Vec<u8>>>,
-        root: Mutex<Option<Cid>>,
-        named_roots: Mutex<std::collections::HashMap<String, Cid>>,
+    fn set_len(&mut self, size: u64) -> Result<(), Error> {
+        forward!(self, file => file.set_len(size))
     }
 
-    impl MemStore {
-        fn new() -> Self {
-            Self {
-                inner: Arc::new(MemStoreInner {
-                    pages: Mutex::new(std::collections::HashMap::new()),
-                    root: Mutex::new(None),
-                    named_roots: Mutex::new(std::collections::HashMap::new()),
-                }),
-            }
-        }
+    fn lock(&mut self, lock: LockKind) -> Result<bool, Error> {
+        forward!(self, file => file.lock(lock))
+    }
 
-        fn page_count(&self) -> usize {
-            self.inner.pages.lock().unwrap().len()
-        }
+    fn reserved(&mut self) -> Result<bool, Error> {
+        forward!(self, file => file.reserved())
     }
 
-    impl PageStore for MemStore {
-        fn get(&self, cid: &Cid) -> CsResult<Page> {
-            self.inner.pages.lock().unwrap()
-                .get(cid)
-                .map(|d| Page { data: d.clone() })
-                .ok_or(PageStoreError::NotFound(*cid))
-        }
+    fn current_lock(&self) -> Result<LockKind, Error> {
+        forward!(self, file => file.current_lock())
+    }
 
-        fn put(&self, page: &Page) -> CsResult<Cid> {
-            let cid = Cid::from_bytes(&page.data);
-            self.inner.pages.lock().unwrap().insert(cid, page.data.clone());
-            Ok(cid)
-        }
+    fn wal_index(&self, readonly: bool) -> Result<WalDisabled, Error> {
+        forward!(self, file => file.wal_index(readonly))
+    }
+}
 
-        fn update_root(&self, new_root: Cid) -> CsResult<()> {
-            *self.inner.root.lock().unwrap() = Some(new_root);
-            Ok(())
-        }
+/// Whether `db` names a journal, WAL, or temporary file rather than the
+/// database.
+fn is_side_file(db: &str) -> bool {
+    db.ends_with("-journal") || db.ends_with("-wal") || db.ends_with("-shm") || db.starts_with(TEMP_PREFIX)
+}
 
-        fn current_root(&self) -> CsResult<Option<Cid>> {
-            Ok(*self.inner.root.lock().unwrap())
+impl<S: PageStore + ?Sized> CraftDbHandle<S> {
+    /// Reload the page table if another handle moved the root since this
+    /// one loaded or wrote it. Unsynced writes are never thrown away.
+    fn refresh(&self) -> Result<(), Error> {
+        let mut buf = self.pages.lock().unwrap();
+        let now = self.generation.load(Ordering::SeqCst);
+        if buf.generation == now || buf.dirty {
+            return Ok(());
         }
+        *buf = PageBuffer::load(&*self.store, true)?;
+        buf.generation = now;
+        Ok(())
+    }
 
-        fn set_named_root(&self, name: &str, cid: Cid) -> CsResult<()> {
-            self.inner.named_roots.lock().unwrap().insert(name.to_string(), cid);
-            Ok(())
+    /// Split the file into pages of `page_size` instead, all of them dirty.
+    /// Holds the whole file in memory while it does.
+    fn repage(&self, buf: &mut PageBuffer, page_size: usize) -> Result<(), Error> {
+        let mut bytes = Vec::with_capacity(buf.file_size as usize);
+        for page_num in 0..(buf.file_size as usize).div_ceil(buf.page_size) {
+            let mut data = self.read_page(buf, page_num)?;
+            data.resize(buf.page_size, 0);
+            bytes.extend_from_slice(&data);
         }
+        bytes.truncate(buf.file_size as usize);
+        let page_count = bytes.len().div_ceil(page_size);
+        bytes.resize(page_count * page_size, 0);
 
-        fn get_named_root(&self, name: &str) -> CsResult<Option<Cid>> {
-            Ok(self.inner.named_roots.lock().unwrap().get(name).copied())
-        }
+        buf.pages = bytes.chunks(page_size).map(|data| Some(data.to_vec())).collect();
+        buf.page_table.entries.truncate(page_count);
+        buf.page_size = page_size;
+        buf.dirty = true;
+        Ok(())
+    }
 
-        fn remove_named_root(&self, name: &str) -> CsResult<bool> {
-            Ok(self.inner.named_roots.lock().unwrap().remove(name).is_some())
+    fn read_page(&self, buf: &PageBuffer, page_num: usize) -> Result<Vec<u8>, Error> {
+        // Check buffer first
+        if page_num < buf.pages.len() {
+            if let Some(ref data) = buf.pages[page_num] {
+                return Ok(data.clone());
+            }
         }
 
-        fn list_named_roots(&self) -> CsResult<Vec<(String, Cid)>> {
-            let roots = self.inner.named_roots.lock().unwrap();
-            let mut v: Vec<_> = roots.iter().map(|(k, v)| (k.clone(), *v)).collect();
-            v.sort_by(|a, b| a.0.cmp(&b.0));
-            Ok(v)
+        // Check page table → store
+        if let Some(cid) = buf.page_table.get(page_num) {
+            let page = self.store.get(cid)
+                .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+            return Ok(page.data);
         }
+
+        // Page doesn't exist yet — return zeros
+        Ok(vec![0u8; buf.page_size])
     }
+}
+
+
+// rusqlite's bundled SQLite can't link alongside libsql's
+#[cfg(all(test, not(feature = "libsql")))]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     static VFS_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
@@ -472,7 +654,7 @@ mod tests {
     #[test]
     fn test_create_insert_query() {
         let name = unique_vfs_name();
-        register(&name, MemStore::new()).unwrap();
+        register(&name, MemPageStore::new()).unwrap();
         let db = open_db(&name);
 
         db.execute_batch("
@@ -489,10 +671,73 @@ mod tests {
         assert_eq!(names, vec!["alice", "bob"]);
     }
 
+    #[test]
+    fn test_io_capabilities() {
+        use rusqlite::ffi;
+
+        let name = unique_vfs_name();
+        register(&name, MemPageStore::new()).unwrap();
+        let db = open_db(&name);
+        // SAFETY: the file pointer SQLite hands out lives as long as `db`
+        let (sector_size, characteristics) = unsafe {
+            let mut file: *mut ffi::sqlite3_file = std::ptr::null_mut();
+            let rc = ffi::sqlite3_file_control(
+                db.handle(),
+                c"main".as_ptr(),
+                ffi::SQLITE_FCNTL_FILE_POINTER,
+                (&mut file as *mut *mut ffi::sqlite3_file).cast(),
+            );
+            assert_eq!(rc, ffi::SQLITE_OK);
+            let methods = &*(*file).pMethods;
+            (methods.xSectorSize.unwrap()(file), methods.xDeviceCharacteristics.unwrap()(file))
+        };
+        assert_eq!(sector_size, SECTOR_SIZE);
+        assert_eq!(characteristics, ffi::SQLITE_IOCAP_SAFE_APPEND | ffi::SQLITE_IOCAP_POWERSAFE_OVERWRITE);
+        assert_eq!(characteristics, DEVICE_CHARACTERISTICS);
+
+        // Still a working database
+        db.execute_batch("CREATE TABLE t (x INTEGER); BEGIN; INSERT INTO t VALUES (1); ROLLBACK;").unwrap();
+        let rows: i64 = db.query_row("SELECT count(*) FROM t", [], |r: &rusqlite::Row| r.get(0)).unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[test]
+    fn test_register_shared() {
+        let name = unique_vfs_name();
+        let store: Arc<dyn PageStore> = Arc::new(MemPageStore::new());
+        register_shared(&name, Arc::clone(&store)).unwrap();
+        let db = open_db(&name);
+        db.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1);").unwrap();
+        drop(db);
+        assert!(store.current_root().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_page_table_records_database() {
+        let name = unique_vfs_name();
+        let store = MemPageStore::new();
+        register(&name, store.clone()).unwrap();
+        {
+            let db = open_db(&name);
+            db.execute_batch("PRAGMA page_size=1024; PRAGMA user_version=5; CREATE TABLE t (x INTEGER);").unwrap();
+        }
+        let root = store.current_root().unwrap().unwrap();
+        let table = PageTable::from_bytes(&store.get(&root).unwrap().data).unwrap();
+        assert_eq!((table.page_size, table.meta.schema_version), (1024, 5));
+        assert!(table.meta.created > 0);
+
+        // Kept as the database changes
+        open_db(&name).execute_batch("PRAGMA user_version=6; INSERT INTO t VALUES (1);").unwrap();
+        let later = store.current_root().unwrap().unwrap();
+        let later = PageTable::from_bytes(&store.get(&later).unwrap().data).unwrap();
+        assert_eq!((later.page_size, later.meta.schema_version), (1024, 6));
+        assert_eq!(later.meta.created, table.meta.created);
+    }
+
     #[test]
     fn test_many_rows() {
         let name = unique_vfs_name();
-        register(&name, MemStore::new()).unwrap();
+        register(&name, MemPageStore::new()).unwrap();
         let db = open_db(&name);
 
         db.execute_batch("CREATE TABLE nums (i INTEGER);").unwrap();
@@ -507,7 +752,7 @@ mod tests {
     #[test]
     fn test_transaction_rollback() {
         let name = unique_vfs_name();
-        register(&name, MemStore::new()).unwrap();
+        register(&name, MemPageStore::new()).unwrap();
         let db = open_db(&name);
 
         db.execute_batch("
@@ -521,12 +766,156 @@ mod tests {
         assert_eq!(count, 1);
     }
 
+    #[test]
+    fn test_connections_see_each_others_commits() {
+        let name = unique_vfs_name();
+        register(&name, MemPageStore::new()).unwrap();
+        let first = open_db(&name);
+        first.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1);").unwrap();
+        let second = open_db(&name);
+        let count = |db: &rusqlite::Connection| -> i64 {
+            db.query_row("SELECT COUNT(*) FROM t", [], |r: &rusqlite::Row| r.get(0)).unwrap()
+        };
+        assert_eq!(count(&second), 1);
+
+        // Each sees the other's later commits without reopening
+        first.execute("INSERT INTO t VALUES (2)", []).unwrap();
+        assert_eq!(count(&second), 2);
+        second.execute("INSERT INTO t VALUES (3)", []).unwrap();
+        assert_eq!(count(&first), 3);
+        first.execute("INSERT INTO t VALUES (4)", []).unwrap();
+        let sum: i64 = second.query_row("SELECT SUM(x) FROM t", [], |r: &rusqlite::Row| r.get(0)).unwrap();
+        assert_eq!(sum, 10);
+    }
+
+    #[test]
+    fn test_one_writer_at_a_time() {
+        let name = unique_vfs_name();
+        register(&name, MemPageStore::new()).unwrap();
+        let first = open_db(&name);
+        first.execute_batch("CREATE TABLE t (x INTEGER);").unwrap();
+        let second = open_db(&name);
+        let count = |db: &rusqlite::Connection| -> i64 {
+            db.query_row("SELECT COUNT(*) FROM t", [], |r: &rusqlite::Row| r.get(0)).unwrap()
+        };
+
+        // A second writer is told the database is busy; readers carry on
+        first.execute_batch("BEGIN IMMEDIATE; INSERT INTO t VALUES (1);").unwrap();
+        let err = second.execute_batch("BEGIN IMMEDIATE").unwrap_err();
+        assert_eq!(err.sqlite_error_code(), Some(rusqlite::ErrorCode::DatabaseBusy));
+        assert_eq!(count(&second), 0);
+
+        first.execute_batch("COMMIT").unwrap();
+        second.execute_batch("BEGIN IMMEDIATE; INSERT INTO t VALUES (2); COMMIT;").unwrap();
+        assert_eq!(count(&first), 2);
+    }
+
+    #[test]
+    fn test_root_control() {
+        let name = unique_vfs_name();
+        let control = register_with_control(&name, Arc::new(MemPageStore::new())).unwrap();
+        let first = open_db(&name);
+        first.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1);").unwrap();
+        let old = control.store.current_root().unwrap().unwrap();
+        first.execute("INSERT INTO t VALUES (2)", []).unwrap();
+        let second = open_db(&name);
+        let count = |db: &rusqlite::Connection| -> i64 {
+            db.query_row("SELECT COUNT(*) FROM t", [], |r: &rusqlite::Row| r.get(0)).unwrap()
+        };
+        assert_eq!(count(&second), 2);
+
+        // Not while a connection has a transaction open
+        second.execute_batch("BEGIN; SELECT * FROM t;").unwrap();
+        assert!(control.exclusive(Duration::ZERO, |store| store.update_root(old)).is_none());
+        second.execute_batch("COMMIT").unwrap();
+
+        // Afterwards both connections read the old version, and write on
+        // top of it
+        control.exclusive(Duration::ZERO, |store| store.update_root(old)).unwrap().unwrap();
+        assert_eq!(count(&first), 1);
+        assert_eq!(count(&second), 1);
+        second.execute("INSERT INTO t VALUES (3)", []).unwrap();
+        let sum: i64 = first.query_row("SELECT SUM(x) FROM t", [], |r: &rusqlite::Row| r.get(0)).unwrap();
+        assert_eq!(sum, 4);
+    }
+
+    #[test]
+    fn test_temp_tables() {
+        let name = unique_vfs_name();
+        register(&name, MemPageStore::new()).unwrap();
+        let db = open_db(&name);
+        db.execute_batch("
+            PRAGMA temp_store = FILE;
+            CREATE TABLE t (x INTEGER);
+            INSERT INTO t VALUES (1);
+            CREATE TEMP TABLE scratch (x INTEGER);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000)
+            INSERT INTO scratch SELECT i FROM n;
+        ").unwrap();
+
+        let sum: i64 = db.query_row("SELECT SUM(x) FROM scratch", [], |r: &rusqlite::Row| r.get(0)).unwrap();
+        assert_eq!(sum, 5000 * 5001 / 2);
+        // The TEMP database is separate from the one committed to the store
+        let count: i64 = db.query_row("SELECT COUNT(*) FROM t", [], |r: &rusqlite::Row| r.get(0)).unwrap();
+        assert_eq!(count, 1);
+        drop(db);
+        let db = open_db(&name);
+        let count: i64 = db.query_row("SELECT COUNT(*) FROM t", [], |r: &rusqlite::Row| r.get(0)).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_backup_in_and_out() {
+        let rows = |db: &rusqlite::Connection| -> Vec<String> {
+            db.prepare("SELECT v FROM t ORDER BY id").unwrap()
+                .query_map([], |r: &rusqlite::Row| r.get(0)).unwrap()
+                .map(|r| r.unwrap())
+                .collect()
+        };
+        let page_size = |db: &rusqlite::Connection| -> i64 {
+            db.query_row("PRAGMA page_size", [], |r: &rusqlite::Row| r.get(0)).unwrap()
+        };
+        let source = rusqlite::Connection::open_in_memory().unwrap();
+        source.execute_batch("
+            PRAGMA page_size = 1024;
+            CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 500)
+            INSERT INTO t SELECT i, printf('row %d', i) FROM n;
+        ").unwrap();
+
+        // Into a database that already has pages, of another size
+        let name = unique_vfs_name();
+        let store = MemPageStore::new();
+        register(&name, store.clone()).unwrap();
+        let mut db = open_db(&name);
+        db.execute_batch("CREATE TABLE old (x); INSERT INTO old VALUES (randomblob(20000));").unwrap();
+        assert_eq!(page_size(&db), 4096);
+        rusqlite::backup::Backup::new(&source, &mut db).unwrap()
+            .run_to_completion(7, std::time::Duration::ZERO, None).unwrap();
+        assert_eq!(page_size(&db), 1024);
+        assert_eq!(rows(&db), rows(&source));
+        drop(db);
+        let db = open_db(&name);
+        let check: String = db.query_row("PRAGMA integrity_check", [], |r: &rusqlite::Row| r.get(0)).unwrap();
+        assert_eq!(check, "ok");
+        assert_eq!(rows(&db), rows(&source));
+
+        // And back out, while another connection holds unsynced writes
+        let writer = open_db(&name);
+        writer.execute_batch("BEGIN; INSERT INTO t VALUES (1000, 'uncommitted');").unwrap();
+        let mut copy = rusqlite::Connection::open_in_memory().unwrap();
+        rusqlite::backup::Backup::new(&db, &mut copy).unwrap()
+            .run_to_completion(5, std::time::Duration::ZERO, None).unwrap();
+        writer.execute_batch("ROLLBACK").unwrap();
+        assert_eq!(rows(&copy), rows(&source));
+    }
+
     // --- Persistence tests ---
 
     #[test]
     fn test_persist_close_reopen() {
         let name = unique_vfs_name();
-        let store = MemStore::new();
+        let store = MemPageStore::new();
         register(&name, store.clone()).unwrap();
 
         // Write and close
@@ -557,7 +946,7 @@ mod tests {
     #[test]
     fn test_persist_schema_and_index() {
         let name = unique_vfs_name();
-        let store = MemStore::new();
+        let store = MemPageStore::new();
         register(&name, store.clone()).unwrap();
 
         // Create schema with index + data
@@ -596,7 +985,7 @@ mod tests {
     #[test]
     fn test_persist_multiple_cycles() {
         let name = unique_vfs_name();
-        let store = MemStore::new();
+        let store = MemPageStore::new();
         register(&name, store.clone()).unwrap();
 
         // Cycle 1: create table
@@ -653,10 +1042,44 @@ mod tests {
         std::fs::remove_dir_all(&dir).ok();
     }
 
+    #[test]
+    fn test_commit_meta() {
+        use craftsql_store_local::LocalPageStore;
+
+        let dir = std::env::temp_dir().join(format!("craftsql-commit-meta-{}", std::process::id()));
+        let store = Arc::new(LocalPageStore::new(&dir).unwrap());
+        let name = unique_vfs_name();
+        let control = register_with_control(&name, Arc::clone(&store)).unwrap();
+        let meta = ConnectionMeta::default();
+        let path = control.connection_path(&meta);
+        let db = rusqlite::Connection::open_with_flags_and_vfs(&path, OPEN_RW, &name).unwrap();
+        db.execute_batch("PRAGMA journal_mode=DELETE; CREATE TABLE t (x INTEGER);").unwrap();
+        let first = store.current_root().unwrap().unwrap();
+        assert_eq!(store.root_commit(&first).unwrap(), None);
+
+        meta.set(Some(CommitMeta::new("alice", "add a row")));
+        db.execute("INSERT INTO t VALUES (1)", []).unwrap();
+        let second = store.current_root().unwrap().unwrap();
+        let commit = store.root_commit(&second).unwrap().unwrap();
+        assert_eq!((commit.author.as_str(), commit.message.as_str()), ("alice", "add a row"));
+        assert_eq!(commit.parents, vec![first]);
+
+        // Another connection records nothing, even opened at the same path
+        let other = rusqlite::Connection::open_with_flags_and_vfs(&path, OPEN_RW, &name).unwrap();
+        other.execute_batch("PRAGMA journal_mode=DELETE;").unwrap();
+        other.execute("INSERT INTO t VALUES (2)", []).unwrap();
+        let third = store.current_root().unwrap().unwrap();
+        assert_ne!(third, second);
+        assert_eq!(store.root_commit(&third).unwrap(), None);
+
+        drop((db, other));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
     #[test]
     fn test_content_dedup() {
         let name = unique_vfs_name();
-        let store = MemStore::new();
+        let store = MemPageStore::new();
         register(&name, store.clone()).unwrap();
 
         let db = open_db(&name);
@@ -679,7 +1102,7 @@ mod tests {
     #[test]
     fn test_snapshot_and_restore() {
         let name = unique_vfs_name();
-        let store = MemStore::new();
+        let store = MemPageStore::new();
         register(&name, store.clone()).unwrap();
 
         // Create table, insert initial data
@@ -722,7 +1145,7 @@ mod tests {
     #[test]
     fn test_branch_diverge() {
         let name = unique_vfs_name();
-        let store = MemStore::new();
+        let store = MemPageStore::new();
         register(&name, store.clone()).unwrap();
 
         // Create base state
@@ -773,7 +1196,7 @@ mod tests {
     #[test]
     fn test_snapshot_list_and_remove() {
         let name = unique_vfs_name();
-        let store = MemStore::new();
+        let store = MemPageStore::new();
         register(&name, store.clone()).unwrap();
 
         {
@@ -803,7 +1226,7 @@ mod tests {
         use craftsql_core::PageTable;
 
         let name = unique_vfs_name();
-        let store = MemStore::new();
+        let store = MemPageStore::new();
         register(&name, store.clone()).unwrap();
 
         // Create initial state
@@ -0,0 +1,100 @@
+//! Opening databases through the VFS with [libsql](::libsql) instead of
+//! rusqlite (`libsql` feature).
+//!
+//! libsql sets SQLite's threading mode the first time it opens a database,
+//! which SQLite only allows before it's initialized, and registering a VFS
+//! initializes it. So register through [`register`] here, which has libsql
+//! set up SQLite first, rather than with [`crate::register`] directly:
+//!
+//! ```no_run
+//! # async fn example(store: std::sync::Arc<dyn craftsql_core::PageStore>) -> Result<(), craftsql_vfs::libsql::Error> {
+//! let db = craftsql_vfs::libsql::register("craftsql", store).await?;
+//! let conn = craftsql_vfs::libsql::connect(&db).await?;
+//! conn.execute("CREATE TABLE t (x)", ()).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! libsql's bundled SQLite must be the only one in the program: don't
+//! enable this alongside rusqlite's `bundled` feature. C programs linked
+//! against libsql can load the `craftsql-ext` extension as they would into
+//! SQLite.
+
+use std::fmt;
+use std::sync::Arc;
+
+use craftsql_core::PageStore;
+
+use crate::RootControl;
+
+/// Why a database couldn't be opened through the VFS.
+#[derive(Debug)]
+pub enum Error {
+    Register(sqlite_vfs::RegisterError),
+    Libsql(::libsql::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Register(e) => write!(f, "registering the VFS: {}", e),
+            Error::Libsql(e) => write!(f, "libsql: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<sqlite_vfs::RegisterError> for Error {
+    fn from(e: sqlite_vfs::RegisterError) -> Self {
+        Error::Register(e)
+    }
+}
+
+impl From<::libsql::Error> for Error {
+    fn from(e: ::libsql::Error) -> Self {
+        Error::Libsql(e)
+    }
+}
+
+/// The URI that opens the database of the VFS registered as `vfs`.
+pub fn uri(vfs: &str) -> String {
+    format!("file:/craftsql/{}/db?vfs={}", vfs, vfs)
+}
+
+/// Have libsql set up SQLite, if it hasn't yet.
+async fn init() -> Result<(), Error> {
+    // Opening any database does; an in-memory one touches nothing
+    ::libsql::Builder::new_local(":memory:").build().await?;
+    Ok(())
+}
+
+/// Register the VFS for `store` as `vfs` and open its database with libsql.
+pub async fn register<S: PageStore + ?Sized + 'static>(vfs: &str, store: Arc<S>) -> Result<::libsql::Database, Error> {
+    init().await?;
+    crate::register_shared(vfs, store)?;
+    open(vfs).await
+}
+
+/// [`register`], also returning the VFS's [`RootControl`].
+pub async fn register_with_control<S: PageStore + ?Sized + 'static>(
+    vfs: &str,
+    store: Arc<S>,
+) -> Result<(::libsql::Database, RootControl<S>), Error> {
+    init().await?;
+    let control = crate::register_with_control(vfs, store)?;
+    Ok((open(vfs).await?, control))
+}
+
+/// Open the database of the VFS already registered as `vfs`.
+pub async fn open(vfs: &str) -> Result<::libsql::Database, Error> {
+    Ok(::libsql::Builder::new_local(uri(vfs)).build().await?)
+}
+
+/// Connect to `db`, in the rollback journal mode the VFS requires.
+pub async fn connect(db: &::libsql::Database) -> Result<::libsql::Connection, Error> {
+    let conn = db.connect()?;
+    // Returns the new mode as a row, so it's a query
+    conn.query("PRAGMA journal_mode=DELETE", ()).await?;
+    Ok(conn)
+}
@@ -0,0 +1,165 @@
+//! SQLite's file locks between the connections of one registered VFS.
+//!
+//! Stores have no lease to hold a lock across processes, so these only
+//! keep the connections of one process from writing over each other.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use sqlite_vfs::LockKind;
+
+/// How long a lock request waits for other connections before SQLite is
+/// told the database is busy. SQLite's busy handler, if the connection
+/// has one, retries from there.
+pub(crate) const LOCK_WAIT: Duration = Duration::from_millis(100);
+
+/// Lock levels in SQLite's order.
+pub(crate) fn rank(lock: LockKind) -> u8 {
+    match lock {
+        LockKind::None => 0,
+        LockKind::Shared => 1,
+        LockKind::Reserved => 2,
+        LockKind::Pending => 3,
+        LockKind::Exclusive => 4,
+    }
+}
+
+/// The locks held on the main database, by handle id.
+#[derive(Default)]
+pub(crate) struct Locks {
+    state: Mutex<State>,
+    changed: Condvar,
+    next_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct State {
+    /// Handles holding SHARED or more.
+    shared: usize,
+    /// The one handle holding RESERVED, PENDING, or EXCLUSIVE, and which.
+    writer: Option<(u64, LockKind)>,
+}
+
+impl Locks {
+    /// An id for a new handle.
+    pub(crate) fn new_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Whether any handle holds RESERVED or more.
+    pub(crate) fn reserved(&self) -> bool {
+        self.state.lock().unwrap().writer.is_some()
+    }
+
+    /// Move handle `id` from lock `from` to `to`, waiting up to `wait` for
+    /// other handles to get out of the way. Returns the lock it then holds:
+    /// `to`, or if others are still in the way, `from` — or PENDING for an
+    /// EXCLUSIVE still waiting for readers to finish, which keeps new
+    /// readers out meanwhile.
+    pub(crate) fn change(&self, id: u64, mut from: LockKind, to: LockKind, wait: Duration) -> LockKind {
+        let deadline = Instant::now() + wait;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.change(id, from, to) {
+                Ok(()) => {
+                    self.changed.notify_all();
+                    return to;
+                }
+                Err(held) => from = held,
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return from;
+            }
+            state = self.changed.wait_timeout(state, left).unwrap().0;
+        }
+    }
+}
+
+impl State {
+    /// Move `id` from `from` to `to` if no other handle is in the way;
+    /// otherwise return the lock it holds.
+    fn change(&mut self, id: u64, from: LockKind, to: LockKind) -> Result<(), LockKind> {
+        let (from_rank, to_rank) = (rank(from), rank(to));
+        if to_rank <= from_rank {
+            if from_rank >= 1 && to_rank == 0 {
+                self.shared -= 1;
+            }
+            if to_rank < 2 {
+                self.writer = self.writer.filter(|&(holder, _)| holder != id);
+            } else {
+                self.writer = Some((id, to));
+            }
+            return Ok(());
+        }
+
+        // SQLite only ever asks for SHARED from no lock, and more from SHARED
+        let other = self.writer.filter(|&(holder, _)| holder != id).map(|(_, lock)| rank(lock));
+        match to {
+            LockKind::None => unreachable!("no lock is below another"),
+            LockKind::Shared => {
+                if other.is_some_and(|rank| rank >= 3) {
+                    return Err(from);
+                }
+                self.shared += 1;
+            }
+            LockKind::Reserved | LockKind::Pending => {
+                if other.is_some() {
+                    return Err(from);
+                }
+                self.writer = Some((id, to));
+            }
+            LockKind::Exclusive => {
+                if other.is_some() {
+                    return Err(from);
+                }
+                if self.shared > 1 {
+                    self.writer = Some((id, LockKind::Pending));
+                    return Err(LockKind::Pending);
+                }
+                self.writer = Some((id, LockKind::Exclusive));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LockKind::*;
+
+    #[test]
+    fn test_locks() {
+        let locks = Locks::default();
+        let (a, b, c) = (locks.new_id(), locks.new_id(), locks.new_id());
+        let now = Duration::ZERO;
+        assert_eq!(rank(locks.change(a, None, Shared, now)), rank(Shared));
+        assert_eq!(rank(locks.change(b, None, Shared, now)), rank(Shared));
+
+        // One writer at a time; readers carry on
+        assert_eq!(rank(locks.change(a, Shared, Reserved, now)), rank(Reserved));
+        assert!(locks.reserved());
+        assert_eq!(rank(locks.change(b, Shared, Reserved, now)), rank(Shared));
+        // Exclusive waits for b to finish reading, keeping new readers out
+        assert_eq!(rank(locks.change(a, Reserved, Exclusive, now)), rank(Pending));
+        assert_eq!(rank(locks.change(c, None, Shared, now)), rank(None));
+
+        // b finishes while a waits
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                locks.change(b, Shared, None, now);
+            });
+            assert_eq!(rank(locks.change(a, Pending, Exclusive, Duration::from_secs(10))), rank(Exclusive));
+        });
+        assert_eq!(rank(locks.change(c, None, Shared, now)), rank(None));
+
+        assert_eq!(rank(locks.change(a, Exclusive, Shared, now)), rank(Shared));
+        assert!(!locks.reserved());
+        assert_eq!(rank(locks.change(c, None, Shared, now)), rank(Shared));
+        assert_eq!(rank(locks.change(a, Shared, None, now)), rank(None));
+        assert_eq!(rank(locks.change(c, Shared, Exclusive, now)), rank(Exclusive));
+    }
+}
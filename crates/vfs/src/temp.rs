@@ -0,0 +1,197 @@
+//! Files SQLite opens besides the main database: rollback journals, TEMP
+//! databases, sorter and materialization spills.
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+use craftsql_core::{Cid, Page, PageStore};
+use sqlite_vfs::{DatabaseHandle, LockKind, WalDisabled};
+
+/// Bytes of a temporary file kept together, in memory or spilled.
+const BLOCK: usize = 4096;
+
+/// Bytes a temporary file keeps in memory before spilling to the store.
+pub(crate) const SPILL_BYTES: usize = 32 << 20;
+
+enum Block {
+    Memory(Vec<u8>),
+    /// Stored as a page no root points at, which garbage collection
+    /// removes once it's unused.
+    Spilled(Cid),
+}
+
+/// A file that lives only as long as its handle, held in memory in blocks
+/// until it grows past a limit, then spilled to the store as scratch pages.
+/// Nothing about it is ever committed, so syncing it does nothing.
+pub(crate) struct TempFile<S: PageStore + ?Sized> {
+    store: Arc<S>,
+    blocks: BTreeMap<u64, Block>,
+    size: u64,
+    /// Bytes of blocks in memory.
+    in_memory: usize,
+    spill_bytes: usize,
+    lock: LockKind,
+}
+
+impl<S: PageStore + ?Sized> TempFile<S> {
+    pub(crate) fn new(store: Arc<S>, spill_bytes: usize) -> Self {
+        Self { store, blocks: BTreeMap::new(), size: 0, in_memory: 0, spill_bytes, lock: LockKind::None }
+    }
+
+    /// Block `index` in memory, loading it back or creating it zeroed.
+    fn block_mut(&mut self, index: u64) -> Result<&mut Vec<u8>, Error> {
+        let data = match self.blocks.remove(&index) {
+            Some(Block::Memory(data)) => data,
+            Some(Block::Spilled(cid)) => {
+                self.in_memory += BLOCK;
+                self.store.get(&cid).map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?.data
+            }
+            None => {
+                self.in_memory += BLOCK;
+                vec![0; BLOCK]
+            }
+        };
+        match self.blocks.entry(index).or_insert(Block::Memory(data)) {
+            Block::Memory(data) => Ok(data),
+            Block::Spilled(_) => unreachable!("block was just put in memory"),
+        }
+    }
+
+    /// Move every block in memory to the store.
+    fn spill(&mut self) -> Result<(), Error> {
+        let (indexes, pages): (Vec<u64>, Vec<Page>) = self.blocks.iter()
+            .filter_map(|(&index, block)| match block {
+                Block::Memory(data) => Some((index, Page { data: data.clone() })),
+                Block::Spilled(_) => None,
+            })
+            .unzip();
+        let cids = self.store.put_many(&pages).map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        for (index, cid) in indexes.into_iter().zip(cids) {
+            self.blocks.insert(index, Block::Spilled(cid));
+        }
+        self.in_memory = 0;
+        Ok(())
+    }
+}
+
+impl<S: PageStore + ?Sized> DatabaseHandle for TempFile<S> {
+    type WalIndex = WalDisabled;
+
+    fn size(&self) -> Result<u64, Error> {
+        Ok(self.size)
+    }
+
+    fn read_exact_at(&mut self, out: &mut [u8], offset: u64) -> Result<(), Error> {
+        let mut done = 0;
+        while done < out.len() {
+            let pos = offset + done as u64;
+            let (index, start) = (pos / BLOCK as u64, pos as usize % BLOCK);
+            let len = (out.len() - done).min(BLOCK - start);
+            let chunk = &mut out[done..done + len];
+            match self.blocks.get(&index) {
+                Some(Block::Memory(data)) => chunk.copy_from_slice(&data[start..start + len]),
+                Some(Block::Spilled(cid)) => {
+                    let page = self.store.get(cid).map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+                    chunk.copy_from_slice(&page.data[start..start + len]);
+                }
+                // Never written, or past the end: zeros, as SQLite expects
+                None => chunk.fill(0),
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write_all_at(&mut self, data: &[u8], offset: u64) -> Result<(), Error> {
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let (index, start) = (pos / BLOCK as u64, pos as usize % BLOCK);
+            let len = (data.len() - done).min(BLOCK - start);
+            self.block_mut(index)?[start..start + len].copy_from_slice(&data[done..done + len]);
+            done += len;
+        }
+        self.size = self.size.max(offset + data.len() as u64);
+        if self.in_memory > self.spill_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self, _data_only: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_len(&mut self, size: u64) -> Result<(), Error> {
+        let first_gone = size.div_ceil(BLOCK as u64);
+        for (_, block) in self.blocks.split_off(&first_gone) {
+            if let Block::Memory(_) = block {
+                self.in_memory -= BLOCK;
+            }
+        }
+        // Zero the tail of a partial last block, so growing again reads zeros
+        let end = size as usize % BLOCK;
+        if end != 0 && self.blocks.contains_key(&(size / BLOCK as u64)) {
+            self.block_mut(size / BLOCK as u64)?[end..].fill(0);
+        }
+        self.size = size;
+        Ok(())
+    }
+
+    fn lock(&mut self, lock: LockKind) -> Result<bool, Error> {
+        self.lock = lock;
+        Ok(true)
+    }
+
+    fn reserved(&mut self) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn current_lock(&self) -> Result<LockKind, Error> {
+        Ok(self.lock)
+    }
+
+    fn wal_index(&self, _readonly: bool) -> Result<WalDisabled, Error> {
+        Ok(WalDisabled::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftsql_store_mem::MemPageStore;
+
+    #[test]
+    fn test_temp_file_spills() {
+        let store = Arc::new(MemPageStore::new());
+        let mut file = TempFile::new(Arc::clone(&store), 2 * BLOCK);
+        let data: Vec<u8> = (0..5 * BLOCK).map(|i| (i % 251) as u8).collect();
+        file.write_all_at(&data, 100).unwrap();
+        assert_eq!(file.size().unwrap(), 100 + data.len() as u64);
+        assert!(file.in_memory <= 2 * BLOCK);
+        assert!(store.list_pages().unwrap().len() >= 3);
+
+        let mut read = vec![1u8; data.len() + 200];
+        file.read_exact_at(&mut read, 0).unwrap();
+        assert!(read[..100].iter().all(|&b| b == 0));
+        assert_eq!(&read[100..100 + data.len()], &data[..]);
+        assert!(read[100 + data.len()..].iter().all(|&b| b == 0));
+
+        // Rewriting a spilled block brings it back
+        file.write_all_at(b"rewritten", 2 * BLOCK as u64).unwrap();
+        let mut read = [0u8; 9];
+        file.read_exact_at(&mut read, 2 * BLOCK as u64).unwrap();
+        assert_eq!(&read, b"rewritten");
+
+        // Truncating drops the tail, which then reads as zeros
+        file.set_len(BLOCK as u64 + 10).unwrap();
+        assert_eq!(file.size().unwrap(), BLOCK as u64 + 10);
+        file.set_len(3 * BLOCK as u64).unwrap();
+        let mut read = vec![1u8; 2 * BLOCK];
+        file.read_exact_at(&mut read, BLOCK as u64).unwrap();
+        assert_eq!(&read[..10], &data[BLOCK - 100..BLOCK - 90]);
+        assert!(read[10..].iter().all(|&b| b == 0));
+        assert!(store.current_root().unwrap().is_none());
+    }
+}
@@ -0,0 +1,38 @@
+//! Integration test for the libsql adapter: libsql → CraftVFS → PageStore.
+//!
+//! Its own test binary, so that libsql's SQLite is the only one in it.
+
+use std::sync::Arc;
+
+use craftsql_core::PageStore;
+use craftsql_store_mem::MemPageStore;
+use craftsql_vfs::libsql;
+
+async fn count(conn: &::libsql::Connection) -> i64 {
+    let mut rows = conn.query("SELECT COUNT(*) FROM t", ()).await.unwrap();
+    rows.next().await.unwrap().unwrap().get(0).unwrap()
+}
+
+#[tokio::test]
+async fn test_libsql_roundtrip() {
+    let store = Arc::new(MemPageStore::new());
+    let (db, control) = libsql::register_with_control("craftsql-libsql-test", store.clone()).await.unwrap();
+    let conn = libsql::connect(&db).await.unwrap();
+    conn.execute_batch("
+        CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT);
+        WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 100)
+        INSERT INTO t SELECT i, printf('row %d', i) FROM n;
+    ").await.unwrap();
+    let first = store.current_root().unwrap().unwrap();
+
+    // Another connection sees the commit
+    let other = libsql::connect(&db).await.unwrap();
+    assert_eq!(count(&other).await, 100);
+
+    // A root moved from outside is what both read next
+    conn.execute("DELETE FROM t WHERE id > 10", ()).await.unwrap();
+    assert_eq!(count(&other).await, 10);
+    control.exclusive(craftsql_vfs::DEFAULT_EXCLUSIVE_WAIT, |store| store.update_root(first)).unwrap().unwrap();
+    assert_eq!(count(&conn).await, 100);
+    assert_eq!(count(&other).await, 100);
+}